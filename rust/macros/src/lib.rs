@@ -0,0 +1,337 @@
+//! Attribute and derive macros for the Diagnyx SDK.
+//!
+//! Use them through the `macros` feature of the `diagnyx` crate, which
+//! re-exports them.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Expr, Fields, ItemFn, LitStr, ReturnType};
+
+/// Track each call of an async function as an LLM call.
+///
+/// The call is timed, and its token usage and error are read from the return
+/// value through `diagnyx::TrackOutput`. It is tracked with the client given
+/// by `client`, or with the process-wide client set up by `diagnyx::init`;
+/// without either, nothing is tracked.
+///
+/// Arguments:
+///
+/// - `provider`: `"openai"`, `"anthropic"`, `"google"`, `"azure"`, `"aws"`
+///   or `"custom"` (required)
+/// - `model`: the model name, or `model_from`: an expression evaluating to
+///   it, run before the function body (one is required)
+/// - `client`: an expression evaluating to a `&DiagnyxClient`, run after
+///   the function body
+///
+/// ```rust,ignore
+/// #[diagnyx::track(provider = "openai", model_from = "req.model")]
+/// async fn complete(req: ChatRequest) -> Result<ChatResponse, Error> {
+///     // ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn track(args: TokenStream, item: TokenStream) -> TokenStream {
+    let mut provider = None;
+    let mut model = None;
+    let mut client = None;
+    let parser = syn::meta::parser(|meta| {
+        let value = || -> syn::Result<LitStr> { meta.value()?.parse() };
+        if meta.path.is_ident("provider") {
+            provider = Some(provider_variant(&value()?)?);
+        } else if meta.path.is_ident("model") {
+            let name = value()?;
+            model = Some(quote!(::std::string::String::from(#name)));
+        } else if meta.path.is_ident("model_from") {
+            let expr: Expr = value()?.parse()?;
+            model = Some(quote_spanned!(expr.span()=> ::std::string::ToString::to_string(&#expr)));
+        } else if meta.path.is_ident("client") {
+            client = Some(value()?.parse::<Expr>()?);
+        } else {
+            return Err(meta.error("expected `provider`, `model`, `model_from` or `client`"));
+        }
+        Ok(())
+    });
+    parse_macro_input!(args with parser);
+    let function = parse_macro_input!(item as ItemFn);
+
+    match expand(function, provider, model, client) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Implement `diagnyx::prompts::PromptVars` for a struct with named fields,
+/// checking its template against the fields at compile time.
+///
+/// Arguments of the `#[prompt(...)]` attribute:
+///
+/// - `template`: the template the struct's fields are substituted into
+///   (required)
+/// - `name`: the prompt name rendered prompts are linked to, by default the
+///   struct's name
+///
+/// Every variable, `#if` and `#each` the template uses outside of `#each`
+/// blocks must name a field, as renamed by `#[serde(rename = "...")]`;
+/// otherwise the derive fails with an error on the template. Variables
+/// inside `#each` blocks may be fields of the elements, and partials are
+/// only known when rendering, so neither is checked.
+///
+/// ```rust,ignore
+/// #[derive(Serialize, PromptVars)]
+/// #[prompt(template = "Hi {{customer}}, your cart: {{#each items}}{{this}} {{/each}}")]
+/// struct Checkout {
+///     customer: String,
+///     items: Vec<String>,
+/// }
+/// ```
+#[proc_macro_derive(PromptVars, attributes(prompt))]
+pub fn derive_prompt_vars(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as DeriveInput);
+    match expand_prompt_vars(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand_prompt_vars(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut template: Option<LitStr> = None;
+    let mut name: Option<LitStr> = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("prompt")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("template") {
+                template = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("expected `template` or `name`"));
+            }
+            Ok(())
+        })?;
+    }
+    let template = template
+        .ok_or_else(|| Error::new(Span::call_site(), "missing `#[prompt(template = \"...\")]`"))?;
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "#[derive(PromptVars)] only applies to structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "#[derive(PromptVars)] only applies to structs",
+            ))
+        }
+    };
+    let mut names = Vec::new();
+    for field in fields {
+        if let Some(name) = serialized_name(field)? {
+            names.push(name);
+        }
+    }
+    let variables = template_variables(&template.value())
+        .map_err(|message| Error::new(template.span(), message))?;
+    if let Some(missing) = variables.iter().find(|v| !names.contains(v)) {
+        return Err(Error::new(
+            template.span(),
+            format!("template uses `{}`, which is not a field", missing),
+        ));
+    }
+
+    let ident = &input.ident;
+    let name = name.map_or_else(|| ident.to_string(), |name| name.value());
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::diagnyx::prompts::PromptVars for #ident #type_generics #where_clause {
+            const NAME: &'static str = #name;
+            const TEMPLATE: &'static str = #template;
+        }
+    })
+}
+
+/// The name `field` is serialized under, or `None` if serde skips it.
+fn serialized_name(field: &syn::Field) -> syn::Result<Option<String>> {
+    let mut name = field.ident.as_ref().map(|ident| {
+        let name = ident.to_string();
+        name.strip_prefix("r#").map(str::to_string).unwrap_or(name)
+    });
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") && meta.input.peek(syn::Token![=]) {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_serializing") {
+                name = None;
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<Expr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                let _nested: proc_macro2::Group = meta.input.parse()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(name)
+}
+
+/// The variables `template` reads from the prompt's own variables: the first
+/// segment of each variable, `#if` and `#each` path outside of `#each`
+/// blocks, in which variables may be read from the elements instead.
+fn template_variables(template: &str) -> Result<Vec<String>, String> {
+    let mut variables = Vec::new();
+    let mut blocks: Vec<bool> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or("unclosed variable in template")?;
+        let tag = after[..end].trim();
+        rest = &after[end + 2..];
+
+        let path = if let Some(open) = tag.strip_prefix('#') {
+            let (kind, path) = open.split_once(char::is_whitespace).unwrap_or((open, ""));
+            if !matches!(kind, "if" | "each") || path.trim().is_empty() {
+                return Err(format!("invalid block {{{{{}}}}} in template", tag));
+            }
+            let in_each = blocks.contains(&true);
+            blocks.push(kind == "each");
+            if in_each {
+                continue;
+            }
+            path.trim()
+        } else if let Some(close) = tag.strip_prefix('/') {
+            let each = blocks
+                .pop()
+                .ok_or_else(|| format!("unopened block {{{{{}}}}} in template", tag))?;
+            if close.trim() != if each { "each" } else { "if" } {
+                return Err(format!("mismatched block {{{{{}}}}} in template", tag));
+            }
+            continue;
+        } else if tag == "else" || tag.starts_with('>') || blocks.contains(&true) {
+            continue;
+        } else if tag == "@index" {
+            return Err("{{@index}} outside of an each block in template".to_string());
+        } else {
+            tag
+        };
+        let first = path.split('.').next().unwrap_or(path);
+        if first != "this" && !variables.iter().any(|v| v == first) {
+            variables.push(first.to_string());
+        }
+    }
+    if !blocks.is_empty() {
+        return Err("unclosed block in template".to_string());
+    }
+    Ok(variables)
+}
+
+/// The `diagnyx::Provider` variant named by `name`.
+fn provider_variant(name: &LitStr) -> syn::Result<proc_macro2::TokenStream> {
+    let variant =
+        match name.value().as_str() {
+            "openai" => quote!(OpenAI),
+            "anthropic" => quote!(Anthropic),
+            "google" => quote!(Google),
+            "azure" => quote!(Azure),
+            "aws" => quote!(Aws),
+            "custom" => quote!(Custom),
+            _ => return Err(Error::new(
+                name.span(),
+                "unknown provider; expected one of openai, anthropic, google, azure, aws, custom",
+            )),
+        };
+    Ok(quote!(::diagnyx::Provider::#variant))
+}
+
+fn expand(
+    function: ItemFn,
+    provider: Option<proc_macro2::TokenStream>,
+    model: Option<proc_macro2::TokenStream>,
+    client: Option<Expr>,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = function;
+    if sig.asyncness.is_none() {
+        return Err(Error::new(
+            sig.fn_token.span(),
+            "#[diagnyx::track] only applies to async functions",
+        ));
+    }
+    let provider =
+        provider.ok_or_else(|| Error::new(Span::call_site(), "missing `provider = \"...\"`"))?;
+    let model = model.ok_or_else(|| {
+        Error::new(
+            Span::call_site(),
+            "missing `model = \"...\"` or `model_from = \"...\"`",
+        )
+    })?;
+    let output_type = match &sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => quote!(#ty),
+    };
+    let record = |client: proc_macro2::TokenStream| {
+        quote! {
+            ::diagnyx::track_output(
+                #client,
+                #provider,
+                __diagnyx_model,
+                __diagnyx_start.elapsed(),
+                &__diagnyx_output,
+            )
+            .await;
+        }
+    };
+    let track = match client {
+        Some(client) => record(quote!(&(#client))),
+        None => {
+            let record = record(quote!(&__diagnyx_client));
+            quote! {
+                if let ::std::option::Option::Some(__diagnyx_client) = ::diagnyx::global() {
+                    #record
+                }
+            }
+        }
+    };
+
+    Ok(quote! {
+        #(#attrs)*
+        #vis #sig {
+            let __diagnyx_model: ::std::string::String = #model;
+            let __diagnyx_start = ::std::time::Instant::now();
+            let __diagnyx_output: #output_type = async {
+                let __diagnyx_body: #output_type = #block;
+                __diagnyx_body
+            }
+            .await;
+            #track
+            __diagnyx_output
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_variables() {
+        let variables = template_variables(
+            "Hi {{ customer.name }}{{#if vip}}!{{else}}.{{/if}} \
+             {{#each items}}{{this}} {{@index}} {{price}} {{/each}}{{> footer}}{{customer}}",
+        )
+        .unwrap();
+        assert_eq!(variables, ["customer", "vip", "items"]);
+
+        assert!(template_variables("Hi {{customer").is_err());
+        assert!(template_variables("{{#each items}}{{/if}}").is_err());
+        assert!(template_variables("{{#if vip}}").is_err());
+        assert!(template_variables("{{@index}}").is_err());
+    }
+}
@@ -0,0 +1,475 @@
+//! Alerts Module for Diagnyx Rust SDK
+//!
+//! Provides methods for managing alert rules from code, such as a rule
+//! notifying a Slack channel when cost spikes, so alerting can be set up
+//! alongside the rest of the infrastructure instead of in the dashboard.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::alerts::{AlertClient, AlertCondition, AlertRuleSpec, NotificationChannel};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let client = AlertClient::new("dx_api_key", "org-123");
+//!
+//!     let rule = client
+//!         .create_rule(
+//!             AlertRuleSpec::new(
+//!                 "checkout latency",
+//!                 AlertCondition::LatencyP95 {
+//!                     threshold_ms: 2000,
+//!                     window_minutes: 15,
+//!                 },
+//!             )
+//!             .project_id("checkout")
+//!             .channel(NotificationChannel::Slack {
+//!                 webhook_url: "https://hooks.slack.com/services/...".to_string(),
+//!                 channel: Some("#oncall".to_string()),
+//!             }),
+//!         )
+//!         .await?;
+//!     println!("Created alert rule {}", rule.id);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::error::DiagnyxError;
+use crate::http::{HttpConfig, REQUEST_TIMEOUT};
+use crate::retry::{send_with_retry, RetryPolicy};
+use crate::types::{default_base_url, Masked};
+
+/// When an alert fires.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "snake_case",
+    rename_all_fields = "camelCase"
+)]
+pub enum AlertCondition {
+    /// Cost over the window exceeds the cost of the previous window by
+    /// more than `threshold_pct` percent.
+    CostSpike {
+        threshold_pct: f64,
+        window_minutes: u32,
+    },
+    /// The share of failed calls over the window exceeds `threshold`,
+    /// from 0 to 1.
+    ErrorRate { threshold: f64, window_minutes: u32 },
+    /// The 95th percentile latency over the window exceeds `threshold_ms`.
+    LatencyP95 {
+        threshold_ms: u64,
+        window_minutes: u32,
+    },
+}
+
+/// Where an alert is sent.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "snake_case",
+    rename_all_fields = "camelCase"
+)]
+pub enum NotificationChannel {
+    /// A JSON POST to `url`, signed with `secret` if given.
+    Webhook {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        secret: Option<String>,
+    },
+    Email {
+        addresses: Vec<String>,
+    },
+    /// A Slack incoming webhook, optionally overriding its channel.
+    Slack {
+        webhook_url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        channel: Option<String>,
+    },
+}
+
+impl fmt::Debug for NotificationChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotificationChannel::Webhook { url, secret } => f
+                .debug_struct("Webhook")
+                .field("url", url)
+                .field("secret", &secret.as_deref().map(Masked))
+                .finish(),
+            NotificationChannel::Email { addresses } => f
+                .debug_struct("Email")
+                .field("addresses", addresses)
+                .finish(),
+            NotificationChannel::Slack {
+                webhook_url,
+                channel,
+            } => f
+                .debug_struct("Slack")
+                .field("webhook_url", webhook_url)
+                .field("channel", channel)
+                .finish(),
+        }
+    }
+}
+
+/// Definition of an alert rule, to create or replace one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRuleSpec {
+    pub name: String,
+    pub condition: AlertCondition,
+    pub channels: Vec<NotificationChannel>,
+    /// Project the rule watches; all projects if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<String>,
+    /// Model the rule watches; all models if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub enabled: bool,
+}
+
+impl AlertRuleSpec {
+    pub fn new(name: impl Into<String>, condition: AlertCondition) -> Self {
+        Self {
+            name: name.into(),
+            condition,
+            channels: Vec::new(),
+            project_id: None,
+            model: None,
+            enabled: true,
+        }
+    }
+
+    /// Add a channel, after those already added.
+    pub fn channel(mut self, channel: NotificationChannel) -> Self {
+        self.channels.push(channel);
+        self
+    }
+
+    pub fn project_id(mut self, project_id: impl Into<String>) -> Self {
+        self.project_id = Some(project_id.into());
+        self
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+}
+
+/// An alert rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRule {
+    pub id: String,
+    #[serde(flatten)]
+    pub spec: AlertRuleSpec,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_triggered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AlertRuleList {
+    rules: Vec<AlertRule>,
+}
+
+/// Configuration for AlertClient.
+#[derive(Clone)]
+pub struct AlertClientConfig {
+    pub api_key: String,
+    pub organization_id: String,
+    pub base_url: String,
+    pub retry_policy: RetryPolicy,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
+    pub debug: bool,
+}
+
+impl fmt::Debug for AlertClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlertClientConfig")
+            .field("api_key", &Masked(&self.api_key))
+            .field("organization_id", &self.organization_id)
+            .field("base_url", &self.base_url)
+            .field("retry_policy", &self.retry_policy)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .finish()
+    }
+}
+
+impl AlertClientConfig {
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        let api_key = api_key.into();
+        Self {
+            base_url: default_base_url(&api_key),
+            api_key,
+            organization_id: organization_id.into(),
+            retry_policy: RetryPolicy::new(3),
+            http: HttpConfig::default(),
+            debug: false,
+        }
+    }
+
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into();
+        self
+    }
+
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.retry_policy.max_attempts = retries as u32;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+}
+
+/// Client for managing alert rules.
+pub struct AlertClient {
+    config: AlertClientConfig,
+    http_client: Client,
+}
+
+impl AlertClient {
+    /// Create a new AlertClient with default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        Self::with_config(AlertClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new AlertClient with custom configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_with_config`](Self::try_with_config) to handle the error instead.
+    pub fn with_config(config: AlertClientConfig) -> Self {
+        Self::try_with_config(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new AlertClient with default settings, returning an error if
+    /// the HTTP client cannot be created.
+    pub fn try_new(
+        api_key: impl Into<String>,
+        organization_id: impl Into<String>,
+    ) -> Result<Self, DiagnyxError> {
+        Self::try_with_config(AlertClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new AlertClient with custom configuration, returning an error
+    /// if the HTTP client cannot be created.
+    pub fn try_with_config(config: AlertClientConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config.http.client(REQUEST_TIMEOUT)?;
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Create a new AlertClient sending requests with `http_client`.
+    ///
+    /// The HTTP settings of `config` are not applied to it.
+    pub fn with_http_client(config: AlertClientConfig, http_client: Client) -> Self {
+        Self {
+            config,
+            http_client,
+        }
+    }
+
+    /// Create an alert rule.
+    pub async fn create_rule(&self, spec: AlertRuleSpec) -> Result<AlertRule, DiagnyxError> {
+        let payload = serde_json::to_value(spec)?;
+        self.request(Method::POST, &self.rules_path(None), Some(payload))
+            .await
+    }
+
+    /// List all alert rules.
+    pub async fn list_rules(&self) -> Result<Vec<AlertRule>, DiagnyxError> {
+        let list: AlertRuleList = self
+            .request(Method::GET, &self.rules_path(None), None)
+            .await?;
+        Ok(list.rules)
+    }
+
+    /// Get an alert rule by ID.
+    pub async fn get_rule(&self, rule_id: &str) -> Result<AlertRule, DiagnyxError> {
+        self.request(Method::GET, &self.rules_path(Some(rule_id)), None)
+            .await
+    }
+
+    /// Replace the definition of an alert rule.
+    pub async fn update_rule(
+        &self,
+        rule_id: &str,
+        spec: AlertRuleSpec,
+    ) -> Result<AlertRule, DiagnyxError> {
+        let payload = serde_json::to_value(spec)?;
+        self.request(Method::PUT, &self.rules_path(Some(rule_id)), Some(payload))
+            .await
+    }
+
+    /// Delete an alert rule.
+    pub async fn delete_rule(&self, rule_id: &str) -> Result<(), DiagnyxError> {
+        let url = format!("{}{}", self.config.base_url, self.rules_path(Some(rule_id)));
+        send_with_retry(&self.config.retry_policy, None, Method::DELETE, |method| {
+            self.http_client
+                .request(method, &url)
+                .header("Authorization", format!("Bearer {}", self.config.api_key))
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Send a test notification to the channels of an alert rule.
+    pub async fn test_rule(&self, rule_id: &str) -> Result<(), DiagnyxError> {
+        let path = format!("{}/test", self.rules_path(Some(rule_id)));
+        let _: serde_json::Value = self.request(Method::POST, &path, None).await?;
+        Ok(())
+    }
+
+    fn rules_path(&self, rule_id: Option<&str>) -> String {
+        let mut path = format!(
+            "/api/v1/organizations/{}/alerts/rules",
+            self.config.organization_id
+        );
+        if let Some(id) = rule_id {
+            path.push('/');
+            path.push_str(id);
+        }
+        path
+    }
+
+    async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, DiagnyxError> {
+        let url = format!("{}{}", self.config.base_url, path);
+        let response = send_with_retry(&self.config.retry_policy, None, method, |method| {
+            let mut request = self
+                .http_client
+                .request(method, &url)
+                .header("Content-Type", "application/json")
+                .header("Authorization", format!("Bearer {}", self.config.api_key));
+            if let Some(ref b) = body {
+                request = request.json(b);
+            }
+            request
+        })
+        .await?;
+
+        response
+            .json()
+            .await
+            .map_err(|e| DiagnyxError::ConfigError(format!("Failed to parse response: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_create_and_delete_rule() {
+        let server = MockServer::start().await;
+        let spec = serde_json::json!({
+            "name": "error rate",
+            "condition": {"type": "error_rate", "threshold": 0.05, "windowMinutes": 10},
+            "channels": [
+                {"type": "email", "addresses": ["oncall@example.com"]},
+                {"type": "webhook", "url": "https://example.com/hook"}
+            ],
+            "model": "gpt-4o",
+            "enabled": true
+        });
+        let mut rule = spec.clone();
+        rule["id"] = "rule-1".into();
+        rule["createdAt"] = "2024-03-01T00:00:00Z".into();
+        Mock::given(method("POST"))
+            .and(path("/api/v1/organizations/org-1/alerts/rules"))
+            .and(body_json(spec))
+            .respond_with(ResponseTemplate::new(201).set_body_json(rule))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/v1/organizations/org-1/alerts/rules/rule-1"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = AlertClient::with_config(
+            AlertClientConfig::new("test-api-key", "org-1").base_url(server.uri()),
+        );
+        let rule = client
+            .create_rule(
+                AlertRuleSpec::new(
+                    "error rate",
+                    AlertCondition::ErrorRate {
+                        threshold: 0.05,
+                        window_minutes: 10,
+                    },
+                )
+                .model("gpt-4o")
+                .channel(NotificationChannel::Email {
+                    addresses: vec!["oncall@example.com".to_string()],
+                })
+                .channel(NotificationChannel::Webhook {
+                    url: "https://example.com/hook".to_string(),
+                    secret: None,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(rule.id, "rule-1");
+        assert_eq!(rule.spec.channels.len(), 2);
+
+        client.delete_rule(&rule.id).await.unwrap();
+    }
+
+    #[test]
+    fn test_debug_masks_secrets() {
+        let config = AlertClientConfig::new("dx_live_abc123", "org-1");
+        let debug = format!("{:?}", config);
+        assert!(debug.contains("api_key: \"dx_live_****\""));
+        assert!(!debug.contains("abc123"));
+
+        let channel = NotificationChannel::Webhook {
+            url: "https://example.com/hook".to_string(),
+            secret: Some("whsec_abc123".to_string()),
+        };
+        let debug = format!("{:?}", channel);
+        assert!(debug.contains("secret: Some(\"****\")"));
+        assert!(!debug.contains("abc123"));
+    }
+}
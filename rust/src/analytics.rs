@@ -0,0 +1,954 @@
+//! Analytics Module for Diagnyx Rust SDK
+//!
+//! Provides read-side queries over tracked LLM usage, such as the
+//! authoritative historical spend of a single end user, cost and token
+//! usage aggregates for dashboards, and latency percentiles of a model.
+//! Aggregates with many rows are returned a [`Page`] at a time.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::analytics::{AnalyticsClient, SpendPeriod};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let client = AnalyticsClient::new("dx_api_key", "org-123");
+//!
+//!     let spend = client.user_spend("user_123", SpendPeriod::MonthToDate).await?;
+//!     println!("{} spent ${:.4}", spend.user_identifier, spend.total_cost);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, TimeZone, Utc};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::error::DiagnyxError;
+use crate::http::{HttpConfig, REQUEST_TIMEOUT};
+use crate::retry::{send_with_retry, RetryPolicy};
+use crate::types::{default_base_url, Masked};
+
+/// Time period for analytics queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpendPeriod {
+    /// Since midnight UTC today.
+    Today,
+    /// The last 7 days up to now.
+    Last7Days,
+    /// The last 30 days up to now.
+    Last30Days,
+    /// Since the first day of the current month (UTC).
+    MonthToDate,
+    /// An explicit time range.
+    Custom {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+}
+
+impl SpendPeriod {
+    /// Resolve the period to a concrete `(start, end)` range relative to `now`.
+    pub fn resolve(&self, now: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+        match *self {
+            SpendPeriod::Today => (start_of_day(now), now),
+            SpendPeriod::Last7Days => (now - ChronoDuration::days(7), now),
+            SpendPeriod::Last30Days => (now - ChronoDuration::days(30), now),
+            SpendPeriod::MonthToDate => (start_of_month(now), now),
+            SpendPeriod::Custom { start, end } => (start, end),
+        }
+    }
+}
+
+fn start_of_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(now.year(), now.month(), now.day(), 0, 0, 0)
+        .single()
+        .unwrap_or(now)
+}
+
+fn start_of_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+        .single()
+        .unwrap_or(now)
+}
+
+/// Historical spend of a single end user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSpend {
+    pub user_identifier: String,
+    pub total_cost: f64,
+    #[serde(default)]
+    pub total_tokens: i64,
+    #[serde(default)]
+    pub input_tokens: i64,
+    #[serde(default)]
+    pub output_tokens: i64,
+    #[serde(default)]
+    pub call_count: i64,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+}
+
+/// Spend attributed to calls carrying a given metadata key/value pair.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataSpend {
+    pub key: String,
+    pub value: String,
+    pub total_cost: f64,
+    #[serde(default)]
+    pub total_tokens: i64,
+    #[serde(default)]
+    pub call_count: i64,
+}
+
+/// Dimension to group leaderboard entries by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardDimension {
+    Model,
+    Project,
+    User,
+}
+
+impl LeaderboardDimension {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LeaderboardDimension::Model => "model",
+            LeaderboardDimension::Project => "project",
+            LeaderboardDimension::User => "user",
+        }
+    }
+}
+
+/// Metric used to rank leaderboard entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RankBy {
+    Cost,
+    Errors,
+}
+
+impl RankBy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RankBy::Cost => "cost",
+            RankBy::Errors => "errors",
+        }
+    }
+}
+
+/// A single ranked row of a leaderboard.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+    pub rank: i32,
+    /// Model name, project ID or user identifier, depending on the dimension.
+    pub key: String,
+    pub total_cost: f64,
+    #[serde(default)]
+    pub total_tokens: i64,
+    #[serde(default)]
+    pub call_count: i64,
+    #[serde(default)]
+    pub error_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct LeaderboardResponse {
+    entries: Vec<LeaderboardEntry>,
+}
+
+/// Dimension to group cost breakdowns by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    Model,
+    Project,
+    User,
+    Environment,
+}
+
+impl GroupBy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GroupBy::Model => "model",
+            GroupBy::Project => "project",
+            GroupBy::User => "user",
+            GroupBy::Environment => "environment",
+        }
+    }
+}
+
+/// Aggregated cost of a single group within a time window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CostGroup {
+    pub key: String,
+    pub total_cost: f64,
+    #[serde(default)]
+    pub total_tokens: i64,
+    #[serde(default)]
+    pub call_count: i64,
+    #[serde(default)]
+    pub error_count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct CostBreakdownResponse {
+    groups: Vec<CostGroup>,
+}
+
+/// Change in cost of a single group between two windows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CostDelta {
+    pub key: String,
+    pub cost_a: f64,
+    pub cost_b: f64,
+    /// `cost_b - cost_a`; positive when the group got more expensive.
+    pub cost_delta: f64,
+    /// Relative change in percent, `None` when the group had no cost in window A.
+    pub cost_change_pct: Option<f64>,
+    pub calls_a: i64,
+    pub calls_b: i64,
+}
+
+/// Result of comparing cost between two time windows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CostComparison {
+    pub group_by: GroupBy,
+    pub total_cost_a: f64,
+    pub total_cost_b: f64,
+    /// Per-group deltas, largest cost increase first.
+    pub deltas: Vec<CostDelta>,
+}
+
+impl CostComparison {
+    /// Build a comparison from the groups of window A and window B.
+    pub fn from_groups(group_by: GroupBy, a: &[CostGroup], b: &[CostGroup]) -> Self {
+        let mut keys: Vec<&str> = a.iter().chain(b.iter()).map(|g| g.key.as_str()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let find = |groups: &[CostGroup], key: &str| -> (f64, i64) {
+            groups
+                .iter()
+                .find(|g| g.key == key)
+                .map(|g| (g.total_cost, g.call_count))
+                .unwrap_or((0.0, 0))
+        };
+
+        let mut deltas: Vec<CostDelta> = keys
+            .into_iter()
+            .map(|key| {
+                let (cost_a, calls_a) = find(a, key);
+                let (cost_b, calls_b) = find(b, key);
+                CostDelta {
+                    key: key.to_string(),
+                    cost_a,
+                    cost_b,
+                    cost_delta: cost_b - cost_a,
+                    cost_change_pct: if cost_a > 0.0 {
+                        Some((cost_b - cost_a) / cost_a * 100.0)
+                    } else {
+                        None
+                    },
+                    calls_a,
+                    calls_b,
+                }
+            })
+            .collect();
+        deltas.sort_by(|x, y| y.cost_delta.total_cmp(&x.cost_delta));
+
+        Self {
+            group_by,
+            total_cost_a: a.iter().map(|g| g.total_cost).sum(),
+            total_cost_b: b.iter().map(|g| g.total_cost).sum(),
+            deltas,
+        }
+    }
+
+    /// Groups whose cost increased by more than `threshold_pct` percent,
+    /// including groups that are new in window B.
+    pub fn regressions(&self, threshold_pct: f64) -> Vec<&CostDelta> {
+        self.deltas
+            .iter()
+            .filter(|d| match d.cost_change_pct {
+                Some(pct) => pct > threshold_pct,
+                None => d.cost_b > 0.0,
+            })
+            .collect()
+    }
+}
+
+/// Options for fetching a page of rows.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageOptions {
+    pub limit: Option<i32>,
+    pub offset: Option<i32>,
+}
+
+/// A page of rows of an aggregate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub total: i32,
+    pub limit: i32,
+    pub offset: i32,
+}
+
+impl<T> Page<T> {
+    /// Options fetching the page after this one, or `None` if this is the
+    /// last page.
+    pub fn next_page(&self) -> Option<PageOptions> {
+        let offset = self.offset + self.data.len() as i32;
+        (!self.data.is_empty() && offset < self.total).then_some(PageOptions {
+            limit: Some(self.limit),
+            offset: Some(offset),
+        })
+    }
+}
+
+/// Cost and token usage of a single model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelCost {
+    pub model: String,
+    #[serde(default)]
+    pub provider: Option<String>,
+    pub total_cost: f64,
+    #[serde(default)]
+    pub input_tokens: i64,
+    #[serde(default)]
+    pub output_tokens: i64,
+    #[serde(default)]
+    pub call_count: i64,
+}
+
+/// Token usage of a project, or of one group within a project.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectUsage {
+    pub project_id: String,
+    /// Key of the group within the project, such as a model name, when
+    /// grouped by a dimension other than the project.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub input_tokens: i64,
+    #[serde(default)]
+    pub output_tokens: i64,
+    #[serde(default)]
+    pub total_tokens: i64,
+    #[serde(default)]
+    pub call_count: i64,
+    pub total_cost: f64,
+}
+
+/// Latency percentiles of a model's calls, in milliseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyPercentiles {
+    pub model: String,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    #[serde(default)]
+    pub sample_count: i64,
+}
+
+/// Configuration for AnalyticsClient.
+#[derive(Clone)]
+pub struct AnalyticsClientConfig {
+    pub api_key: String,
+    pub organization_id: String,
+    pub base_url: String,
+    pub retry_policy: RetryPolicy,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
+    pub debug: bool,
+}
+
+impl fmt::Debug for AnalyticsClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnalyticsClientConfig")
+            .field("api_key", &Masked(&self.api_key))
+            .field("organization_id", &self.organization_id)
+            .field("base_url", &self.base_url)
+            .field("retry_policy", &self.retry_policy)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .finish()
+    }
+}
+
+impl AnalyticsClientConfig {
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        let api_key = api_key.into();
+        Self {
+            base_url: default_base_url(&api_key),
+            api_key,
+            organization_id: organization_id.into(),
+            retry_policy: RetryPolicy::new(3),
+            http: HttpConfig::default(),
+            debug: false,
+        }
+    }
+
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into();
+        self
+    }
+
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.retry_policy.max_attempts = retries as u32;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+}
+
+/// Client for querying cost and usage analytics.
+pub struct AnalyticsClient {
+    config: AnalyticsClientConfig,
+    http_client: Client,
+}
+
+impl AnalyticsClient {
+    /// Create a new AnalyticsClient with default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        Self::with_config(AnalyticsClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new AnalyticsClient with custom configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_with_config`](Self::try_with_config) to handle the error instead.
+    pub fn with_config(config: AnalyticsClientConfig) -> Self {
+        Self::try_with_config(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new AnalyticsClient with default settings, returning an error if
+    /// the HTTP client cannot be created.
+    pub fn try_new(
+        api_key: impl Into<String>,
+        organization_id: impl Into<String>,
+    ) -> Result<Self, DiagnyxError> {
+        Self::try_with_config(AnalyticsClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new AnalyticsClient with custom configuration, returning an error
+    /// if the HTTP client cannot be created.
+    pub fn try_with_config(config: AnalyticsClientConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config.http.client(REQUEST_TIMEOUT)?;
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Create a new AnalyticsClient sending requests with `http_client`.
+    ///
+    /// The HTTP settings of `config` are not applied to it.
+    pub fn with_http_client(config: AnalyticsClientConfig, http_client: Client) -> Self {
+        Self {
+            config,
+            http_client,
+        }
+    }
+
+    /// Get the historical spend of an end user over a period.
+    ///
+    /// The user identifier is the same value passed to
+    /// `LLMCallBuilder::user_identifier` when tracking calls.
+    pub async fn user_spend(
+        &self,
+        user_identifier: &str,
+        period: SpendPeriod,
+    ) -> Result<UserSpend, DiagnyxError> {
+        let (start, end) = period.resolve(Utc::now());
+        let path = format!(
+            "/api/v1/organizations/{}/analytics/users/spend",
+            self.config.organization_id
+        );
+        let query = vec![
+            ("userIdentifier", user_identifier.to_string()),
+            ("startDate", start.to_rfc3339()),
+            ("endDate", end.to_rfc3339()),
+        ];
+
+        self.get(&path, &query).await
+    }
+
+    /// Get the spend of calls tagged with `metadata[key] == value`.
+    pub async fn spend_by_metadata(
+        &self,
+        key: &str,
+        value: &str,
+        period: SpendPeriod,
+    ) -> Result<MetadataSpend, DiagnyxError> {
+        let (start, end) = period.resolve(Utc::now());
+        let path = format!(
+            "/api/v1/organizations/{}/analytics/metadata/spend",
+            self.config.organization_id
+        );
+        let query = vec![
+            ("key", key.to_string()),
+            ("value", value.to_string()),
+            ("startDate", start.to_rfc3339()),
+            ("endDate", end.to_rfc3339()),
+        ];
+
+        self.get(&path, &query).await
+    }
+
+    /// Get the top `limit` entries of a dimension ranked by cost or errors.
+    pub async fn top(
+        &self,
+        dimension: LeaderboardDimension,
+        rank_by: RankBy,
+        period: SpendPeriod,
+        limit: usize,
+    ) -> Result<Vec<LeaderboardEntry>, DiagnyxError> {
+        let (start, end) = period.resolve(Utc::now());
+        let path = format!(
+            "/api/v1/organizations/{}/analytics/leaderboard",
+            self.config.organization_id
+        );
+        let query = vec![
+            ("dimension", dimension.as_str().to_string()),
+            ("rankBy", rank_by.as_str().to_string()),
+            ("limit", limit.to_string()),
+            ("startDate", start.to_rfc3339()),
+            ("endDate", end.to_rfc3339()),
+        ];
+
+        let response: LeaderboardResponse = self.get(&path, &query).await?;
+        Ok(response.entries)
+    }
+
+    /// Get the top models ranked by cost or errors.
+    pub async fn top_models(
+        &self,
+        rank_by: RankBy,
+        period: SpendPeriod,
+        limit: usize,
+    ) -> Result<Vec<LeaderboardEntry>, DiagnyxError> {
+        self.top(LeaderboardDimension::Model, rank_by, period, limit)
+            .await
+    }
+
+    /// Get the top projects ranked by cost or errors.
+    pub async fn top_projects(
+        &self,
+        rank_by: RankBy,
+        period: SpendPeriod,
+        limit: usize,
+    ) -> Result<Vec<LeaderboardEntry>, DiagnyxError> {
+        self.top(LeaderboardDimension::Project, rank_by, period, limit)
+            .await
+    }
+
+    /// Get the top end users ranked by cost or errors.
+    pub async fn top_users(
+        &self,
+        rank_by: RankBy,
+        period: SpendPeriod,
+        limit: usize,
+    ) -> Result<Vec<LeaderboardEntry>, DiagnyxError> {
+        self.top(LeaderboardDimension::User, rank_by, period, limit)
+            .await
+    }
+
+    /// Get the cost of a period broken down by a dimension.
+    pub async fn cost_breakdown(
+        &self,
+        period: SpendPeriod,
+        group_by: GroupBy,
+    ) -> Result<Vec<CostGroup>, DiagnyxError> {
+        let (start, end) = period.resolve(Utc::now());
+        let path = format!(
+            "/api/v1/organizations/{}/analytics/cost",
+            self.config.organization_id
+        );
+        let query = vec![
+            ("groupBy", group_by.as_str().to_string()),
+            ("startDate", start.to_rfc3339()),
+            ("endDate", end.to_rfc3339()),
+        ];
+
+        let response: CostBreakdownResponse = self.get(&path, &query).await?;
+        Ok(response.groups)
+    }
+
+    /// Get a page of the cost of each model, most expensive first.
+    pub async fn cost_by_model(
+        &self,
+        period: SpendPeriod,
+        page: Option<PageOptions>,
+    ) -> Result<Page<ModelCost>, DiagnyxError> {
+        let path = format!(
+            "/api/v1/organizations/{}/analytics/cost/models",
+            self.config.organization_id
+        );
+        let query = range_query(period, page);
+
+        self.get(&path, &query).await
+    }
+
+    /// Get a page of the token usage of each project, further broken down
+    /// by `group_by` unless it is [`GroupBy::Project`].
+    pub async fn usage_by_project(
+        &self,
+        period: SpendPeriod,
+        group_by: GroupBy,
+        page: Option<PageOptions>,
+    ) -> Result<Page<ProjectUsage>, DiagnyxError> {
+        let path = format!(
+            "/api/v1/organizations/{}/analytics/usage/projects",
+            self.config.organization_id
+        );
+        let mut query = range_query(period, page);
+        query.push(("groupBy", group_by.as_str().to_string()));
+
+        self.get(&path, &query).await
+    }
+
+    /// Get the latency percentiles of a model's calls.
+    pub async fn latency_percentiles(
+        &self,
+        model: &str,
+        period: SpendPeriod,
+    ) -> Result<LatencyPercentiles, DiagnyxError> {
+        let path = format!(
+            "/api/v1/organizations/{}/analytics/latency",
+            self.config.organization_id
+        );
+        let mut query = range_query(period, None);
+        query.push(("model", model.to_string()));
+
+        self.get(&path, &query).await
+    }
+
+    /// Compare cost between two windows, e.g. before and after a deploy.
+    pub async fn compare(
+        &self,
+        window_a: SpendPeriod,
+        window_b: SpendPeriod,
+        group_by: GroupBy,
+    ) -> Result<CostComparison, DiagnyxError> {
+        let (a, b) = futures::future::try_join(
+            self.cost_breakdown(window_a, group_by),
+            self.cost_breakdown(window_b, group_by),
+        )
+        .await?;
+
+        Ok(CostComparison::from_groups(group_by, &a, &b))
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, DiagnyxError> {
+        let url = format!("{}{}", self.config.base_url, path);
+
+        let response = send_with_retry(&self.config.retry_policy, None, Method::GET, |method| {
+            self.http_client
+                .request(method, &url)
+                .query(query)
+                .header("Authorization", format!("Bearer {}", self.config.api_key))
+        })
+        .await?;
+
+        response
+            .json()
+            .await
+            .map_err(|e| DiagnyxError::ConfigError(format!("Failed to parse response: {}", e)))
+    }
+}
+
+/// Query parameters of a period and page.
+fn range_query(period: SpendPeriod, page: Option<PageOptions>) -> Vec<(&'static str, String)> {
+    let (start, end) = period.resolve(Utc::now());
+    let mut query = vec![
+        ("startDate", start.to_rfc3339()),
+        ("endDate", end.to_rfc3339()),
+    ];
+    let page = page.unwrap_or_default();
+    if let Some(limit) = page.limit {
+        query.push(("limit", limit.to_string()));
+    }
+    if let Some(offset) = page.offset {
+        query.push(("offset", offset.to_string()));
+    }
+    query
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn create_mock_client(server: &MockServer) -> AnalyticsClient {
+        AnalyticsClient::with_config(
+            AnalyticsClientConfig::new("test-api-key", "org-1")
+                .base_url(server.uri())
+                .max_retries(1),
+        )
+    }
+
+    #[test]
+    fn test_spend_period_resolve() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 15, 13, 45, 0).unwrap();
+
+        let (start, end) = SpendPeriod::MonthToDate.resolve(now);
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap());
+        assert_eq!(end, now);
+
+        let (start, _) = SpendPeriod::Today.resolve(now);
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 3, 15, 0, 0, 0).unwrap());
+
+        let (start, _) = SpendPeriod::Last7Days.resolve(now);
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 3, 8, 13, 45, 0).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_user_spend() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/organizations/org-1/analytics/users/spend"))
+            .and(query_param("userIdentifier", "user@example.com"))
+            .and(header("Authorization", "Bearer test-api-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "userIdentifier": "user@example.com",
+                "totalCost": 1.25,
+                "totalTokens": 15000,
+                "callCount": 42,
+                "periodStart": "2024-03-01T00:00:00Z",
+                "periodEnd": "2024-03-15T13:45:00Z"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server);
+        let spend = client
+            .user_spend("user@example.com", SpendPeriod::MonthToDate)
+            .await
+            .unwrap();
+
+        assert_eq!(spend.user_identifier, "user@example.com");
+        assert_eq!(spend.total_cost, 1.25);
+        assert_eq!(spend.total_tokens, 15000);
+        assert_eq!(spend.call_count, 42);
+        assert_eq!(spend.input_tokens, 0);
+    }
+
+    #[tokio::test]
+    async fn test_top_models_by_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/organizations/org-1/analytics/leaderboard"))
+            .and(query_param("dimension", "model"))
+            .and(query_param("rankBy", "errors"))
+            .and(query_param("limit", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "entries": [
+                    {"rank": 1, "key": "gpt-4", "totalCost": 12.5, "errorCount": 30},
+                    {"rank": 2, "key": "claude-3-haiku", "totalCost": 0.8, "errorCount": 4}
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server);
+        let entries = client
+            .top_models(RankBy::Errors, SpendPeriod::Last7Days, 2)
+            .await
+            .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].key, "gpt-4");
+        assert_eq!(entries[0].error_count, 30);
+        assert_eq!(entries[1].rank, 2);
+    }
+
+    fn group(key: &str, cost: f64) -> CostGroup {
+        CostGroup {
+            key: key.to_string(),
+            total_cost: cost,
+            total_tokens: 0,
+            call_count: 1,
+            error_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_cost_comparison_from_groups() {
+        let a = vec![group("gpt-4", 10.0), group("gpt-3.5-turbo", 2.0)];
+        let b = vec![group("gpt-4", 15.0), group("claude-3-haiku", 1.0)];
+
+        let comparison = CostComparison::from_groups(GroupBy::Model, &a, &b);
+
+        assert_eq!(comparison.total_cost_a, 12.0);
+        assert_eq!(comparison.total_cost_b, 16.0);
+        assert_eq!(comparison.deltas.len(), 3);
+        assert_eq!(comparison.deltas[0].key, "gpt-4");
+        assert_eq!(comparison.deltas[0].cost_delta, 5.0);
+        assert_eq!(comparison.deltas[0].cost_change_pct, Some(50.0));
+        assert_eq!(comparison.deltas[2].key, "gpt-3.5-turbo");
+        assert_eq!(comparison.deltas[2].cost_change_pct, Some(-100.0));
+
+        let regressions: Vec<&str> = comparison
+            .regressions(20.0)
+            .iter()
+            .map(|d| d.key.as_str())
+            .collect();
+        assert_eq!(regressions, vec!["gpt-4", "claude-3-haiku"]);
+    }
+
+    #[tokio::test]
+    async fn test_compare_fetches_both_windows() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/organizations/org-1/analytics/cost"))
+            .and(query_param("groupBy", "project"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "groups": [{"key": "proj-1", "totalCost": 3.0, "callCount": 10}]
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server);
+        let now = Utc::now();
+        let comparison = client
+            .compare(
+                SpendPeriod::Custom {
+                    start: now - ChronoDuration::days(2),
+                    end: now - ChronoDuration::days(1),
+                },
+                SpendPeriod::Today,
+                GroupBy::Project,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(comparison.deltas.len(), 1);
+        assert_eq!(comparison.deltas[0].cost_delta, 0.0);
+        assert!(comparison.regressions(0.0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_user_spend_api_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("not found"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server);
+        let result = client.user_spend("unknown", SpendPeriod::Today).await;
+
+        match result {
+            Err(DiagnyxError::ApiError { status_code, .. }) => assert_eq!(status_code, 404),
+            other => panic!("Expected ApiError, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_usage_by_project_pages() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/organizations/org-1/analytics/usage/projects"))
+            .and(query_param("groupBy", "model"))
+            .and(query_param("limit", "1"))
+            .and(query_param("offset", "1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": [{
+                    "projectId": "proj-1",
+                    "group": "gpt-4o",
+                    "inputTokens": 1000,
+                    "outputTokens": 500,
+                    "totalTokens": 1500,
+                    "callCount": 3,
+                    "totalCost": 0.0075
+                }],
+                "total": 3,
+                "limit": 1,
+                "offset": 1
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/organizations/org-1/analytics/latency"))
+            .and(query_param("model", "gpt-4o"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "model": "gpt-4o",
+                "p50Ms": 420.0,
+                "p90Ms": 910.0,
+                "p95Ms": 1200.0,
+                "p99Ms": 2300.0,
+                "sampleCount": 812
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server);
+        let page = client
+            .usage_by_project(
+                SpendPeriod::Last7Days,
+                GroupBy::Model,
+                Some(PageOptions {
+                    limit: Some(1),
+                    offset: Some(1),
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(page.data[0].group.as_deref(), Some("gpt-4o"));
+        let next = page.next_page().unwrap();
+        assert_eq!((next.limit, next.offset), (Some(1), Some(2)));
+        let last = Page {
+            offset: 2,
+            ..page.clone()
+        };
+        assert!(last.next_page().is_none());
+
+        let latency = client
+            .latency_percentiles("gpt-4o", SpendPeriod::Last7Days)
+            .await
+            .unwrap();
+        assert_eq!(latency.p95_ms, 1200.0);
+        assert_eq!(latency.sample_count, 812);
+    }
+}
@@ -0,0 +1,135 @@
+//! Audit log of runtime configuration changes.
+//!
+//! Settings that affect what data leaves the application, such as full
+//! content capture and the sample rate, can be changed on a running
+//! [`DiagnyxClient`](crate::DiagnyxClient). Every change is recorded as an
+//! [`AuditEvent`] naming the actor responsible and sent to the API's audit
+//! endpoint, so security teams can see who enabled content capture in
+//! production and when. Events that cannot be delivered are kept and
+//! retried on the next flush.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::DiagnyxClient;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), diagnyx::DiagnyxError> {
+//!     let client = DiagnyxClient::new("dx_live_your_api_key");
+//!
+//!     // Recorded with the actor, previous and new value
+//!     client
+//!         .set_capture_full_content(true, "oncall:alice@example.com")
+//!         .await?;
+//!     Ok(())
+//! }
+//! ```
+
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::error::DiagnyxError;
+use crate::retry::send_with_retry;
+use crate::types::DiagnyxConfig;
+
+/// A client setting that can be changed at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSetting {
+    /// Whether full prompts and responses are captured.
+    CaptureFullContent,
+    /// Fraction of calls that are tracked.
+    SampleRate,
+}
+
+/// A change of a runtime setting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub setting: ConfigSetting,
+    pub old_value: serde_json::Value,
+    pub new_value: serde_json::Value,
+    /// Who or what made the change, e.g. a user or a deploy job.
+    pub actor: String,
+    pub timestamp: DateTime<Utc>,
+    pub sdk_version: String,
+}
+
+impl AuditEvent {
+    pub(crate) fn new(
+        setting: ConfigSetting,
+        old_value: impl Into<serde_json::Value>,
+        new_value: impl Into<serde_json::Value>,
+        actor: impl Into<String>,
+    ) -> Self {
+        Self {
+            setting,
+            old_value: old_value.into(),
+            new_value: new_value.into(),
+            actor: actor.into(),
+            timestamp: Utc::now(),
+            sdk_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuditBatch<'a> {
+    events: &'a [AuditEvent],
+}
+
+/// Audit events waiting to be delivered.
+#[derive(Debug, Default)]
+pub(crate) struct AuditLog {
+    pending: Mutex<Vec<AuditEvent>>,
+}
+
+impl AuditLog {
+    /// Queue `event` and send every pending event.
+    pub(crate) async fn record(
+        &self,
+        event: AuditEvent,
+        http_client: &Client,
+        config: &DiagnyxConfig,
+    ) -> Result<(), DiagnyxError> {
+        self.pending.lock().await.push(event);
+        self.send_pending(http_client, config).await
+    }
+
+    /// Send pending events, keeping them for the next attempt on failure.
+    pub(crate) async fn send_pending(
+        &self,
+        http_client: &Client,
+        config: &DiagnyxConfig,
+    ) -> Result<(), DiagnyxError> {
+        let events = std::mem::take(&mut *self.pending.lock().await);
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let url = format!("{}/api/v1/audit/config-changes", config.base_url);
+        let payload = AuditBatch { events: &events };
+        let result = send_with_retry(
+            &config.retry_policy,
+            config.rate_limiter.as_ref(),
+            Method::POST,
+            |method| {
+                http_client
+                    .request(method, &url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", config.api_key))
+                    .json(&payload)
+            },
+        )
+        .await;
+
+        if let Err(e) = result {
+            let mut pending = self.pending.lock().await;
+            let newer = std::mem::replace(&mut *pending, events);
+            pending.extend(newer);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
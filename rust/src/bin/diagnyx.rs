@@ -0,0 +1,372 @@
+//! Command-line tool for Diagnyx operations.
+//!
+//! Built with the `cli` feature:
+//!
+//! ```text
+//! cargo install diagnyx --features cli
+//! diagnyx validate-key
+//! diagnyx usage --org org-1 --period week
+//! diagnyx tail calls.ndjson --follow
+//! diagnyx replay calls.ndjson
+//! diagnyx guardrail-test --org org-1 --project proj-1 "Some model output"
+//! diagnyx export --org org-1 ds-1 --output examples.jsonl
+//! ```
+//!
+//! The API key is read from `--api-key` or `DIAGNYX_API_KEY`.
+
+use clap::{Parser, Subcommand, ValueEnum};
+use diagnyx::analytics::{AnalyticsClient, AnalyticsClientConfig, ModelCost, SpendPeriod};
+use diagnyx::datasets::{DatasetClient, DatasetClientConfig};
+use diagnyx::guardrails::{StreamingEvent, StreamingGuardrails, StreamingGuardrailsConfig};
+use diagnyx::{DiagnyxClient, DiagnyxConfig, DiagnyxError, LLMCall, UploadProgress};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// How often `tail --follow` checks the file for new calls.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often `replay` checks whether the upload has finished.
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "diagnyx",
+    version,
+    about = "Diagnyx operations from the command line"
+)]
+struct Cli {
+    /// API key
+    #[arg(long, env = "DIAGNYX_API_KEY", hide_env_values = true, global = true)]
+    api_key: Option<String>,
+
+    /// API base URL, defaulting to the one for the key
+    #[arg(long, env = "DIAGNYX_BASE_URL", global = true)]
+    base_url: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Check that the API key is accepted
+    ValidateKey,
+    /// Print the cost of each model over a period, as reported by the API
+    Usage {
+        #[arg(long)]
+        org: String,
+        #[arg(long, value_enum, default_value_t = Period::MonthToDate)]
+        period: Period,
+    },
+    /// Print the last calls of a local NDJSON file written by a file sink
+    Tail {
+        file: PathBuf,
+        /// Number of calls to print
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+        /// Keep printing calls as they are appended
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Upload the calls of an NDJSON file
+    Replay {
+        file: PathBuf,
+        /// Calls per request
+        #[arg(long, default_value_t = 100)]
+        batch_size: usize,
+    },
+    /// Run a guardrail session over some text, one token per word
+    GuardrailTest {
+        #[arg(long)]
+        org: String,
+        #[arg(long)]
+        project: String,
+        /// Prompt the text answers
+        #[arg(long)]
+        input: Option<String>,
+        text: String,
+    },
+    /// Export a dataset as JSON Lines
+    Export {
+        #[arg(long)]
+        org: String,
+        dataset: String,
+        /// File to write, instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Period of the `usage` command.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Period {
+    Today,
+    Week,
+    Month,
+    MonthToDate,
+}
+
+impl From<Period> for SpendPeriod {
+    fn from(period: Period) -> Self {
+        match period {
+            Period::Today => SpendPeriod::Today,
+            Period::Week => SpendPeriod::Last7Days,
+            Period::Month => SpendPeriod::Last30Days,
+            Period::MonthToDate => SpendPeriod::MonthToDate,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<ExitCode, DiagnyxError> {
+    let api_key = || {
+        cli.api_key.clone().ok_or_else(|| {
+            DiagnyxError::ConfigError("no API key; set --api-key or DIAGNYX_API_KEY".to_string())
+        })
+    };
+    let base_url = cli.base_url.clone();
+
+    match cli.command {
+        Command::ValidateKey => {
+            let mut config = DiagnyxConfig::new(api_key()?);
+            if let Some(url) = base_url {
+                config = config.base_url(url);
+            }
+            let client = DiagnyxClient::try_with_config(config)?;
+            client.verify_api_key().await?;
+            println!("API key accepted");
+        }
+        Command::Usage { org, period } => {
+            let mut config = AnalyticsClientConfig::new(api_key()?, org);
+            if let Some(url) = base_url {
+                config = config.base_url(url);
+            }
+            let client = AnalyticsClient::try_with_config(config)?;
+            let mut options = None;
+            loop {
+                let page = client.cost_by_model(period.into(), options).await?;
+                for cost in &page.data {
+                    println!("{}", model_summary(cost));
+                }
+                options = page.next_page();
+                if options.is_none() {
+                    break;
+                }
+            }
+        }
+        Command::Tail {
+            file,
+            lines,
+            follow,
+        } => tail(&file, lines, follow).await?,
+        Command::Replay { file, batch_size } => {
+            let calls = read_calls(BufReader::new(File::open(&file)?))?;
+            let mut config = DiagnyxConfig::new(api_key()?).batch_size(batch_size);
+            if let Some(url) = base_url {
+                config = config.base_url(url);
+            }
+            let client = DiagnyxClient::try_with_config(config)?;
+            let upload = client.upload(calls, |progress| eprint!("\r{}", describe(progress)));
+            let ctrl_c = tokio::signal::ctrl_c();
+            tokio::pin!(ctrl_c);
+            while !upload.is_finished() {
+                tokio::select! {
+                    _ = &mut ctrl_c => upload.cancel(),
+                    _ = tokio::time::sleep(WAIT_INTERVAL) => {}
+                }
+            }
+            let progress = upload.wait().await?;
+            eprintln!();
+            if !progress.is_complete() {
+                eprintln!("cancelled");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::GuardrailTest {
+            org,
+            project,
+            input,
+            text,
+        } => {
+            let mut config = StreamingGuardrailsConfig::new(api_key()?, org, project);
+            if let Some(url) = base_url {
+                config = config.base_url(url);
+            }
+            let guardrails = StreamingGuardrails::try_new(config)?;
+            let session = guardrails.start_session(input.as_deref()).await?;
+            println!("session {}", session.session_id);
+            for token in text.split_inclusive(' ') {
+                match guardrails.evaluate_token(token).await? {
+                    StreamingEvent::ViolationDetected(data) => {
+                        println!("violation: {}", data.violation.message)
+                    }
+                    StreamingEvent::EarlyTermination(data) => {
+                        println!("terminated: {}", data.reason);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let session = guardrails.complete_session().await?;
+            println!(
+                "{} tokens, {} violations, {}",
+                session.tokens_processed,
+                session.violations.len(),
+                if session.allowed {
+                    "allowed"
+                } else {
+                    "blocked"
+                }
+            );
+            if !session.allowed {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Export {
+            org,
+            dataset,
+            output,
+        } => {
+            let mut config = DatasetClientConfig::new(api_key()?, org);
+            if let Some(url) = base_url {
+                config = config.base_url(url);
+            }
+            let client = DatasetClient::try_with_config(config)?;
+            let exported = match output {
+                Some(path) => {
+                    client
+                        .export_jsonl(&dataset, BufWriter::new(File::create(path)?))
+                        .await?
+                }
+                None => client.export_jsonl(&dataset, io::stdout().lock()).await?,
+            };
+            eprintln!("exported {} examples", exported);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Print the last `lines` calls of `path`, then its new calls if `follow`.
+async fn tail(path: &Path, lines: usize, follow: bool) -> Result<(), DiagnyxError> {
+    let mut file = File::open(path)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let calls = read_calls(contents.as_bytes())?;
+    for call in &calls[calls.len().saturating_sub(lines)..] {
+        println!("{}", summary(call));
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    let mut offset = contents.len() as u64;
+    loop {
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut appended = String::new();
+        file.read_to_string(&mut appended)?;
+        // A line still being written is read on the next check
+        let complete = appended.rfind('\n').map_or(0, |end| end + 1);
+        for call in read_calls(&appended.as_bytes()[..complete])? {
+            println!("{}", summary(&call));
+        }
+        offset += complete as u64;
+    }
+}
+
+/// Parse one call per non-empty line.
+fn read_calls(reader: impl BufRead) -> Result<Vec<LLMCall>, DiagnyxError> {
+    let mut calls = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let call = serde_json::from_str(&line).map_err(|e| {
+            DiagnyxError::ConfigError(format!("line {}: invalid call: {}", index + 1, e))
+        })?;
+        calls.push(call);
+    }
+    Ok(calls)
+}
+
+/// One line describing a call.
+fn summary(call: &LLMCall) -> String {
+    let mut line = format!(
+        "{} {:?} {} {}+{} tokens {}ms {:?}",
+        call.timestamp.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+        call.provider,
+        call.model,
+        call.input_tokens,
+        call.output_tokens,
+        call.latency_ms,
+        call.status,
+    );
+    if let Some(cost) = call.estimated_cost_usd {
+        line.push_str(&format!(" ${:.6}", cost));
+    }
+    line
+}
+
+/// One line describing the cost of a model.
+fn model_summary(cost: &ModelCost) -> String {
+    format!(
+        "{} {} calls {}+{} tokens ${:.6}",
+        cost.model, cost.call_count, cost.input_tokens, cost.output_tokens, cost.total_cost
+    )
+}
+
+fn describe(progress: &UploadProgress) -> String {
+    let mut line = format!(
+        "{}/{} batches, {} calls, {} bytes",
+        progress.batches_sent, progress.total_batches, progress.calls_sent, progress.bytes_sent
+    );
+    if let Some(eta) = progress.eta {
+        line.push_str(&format!(", eta {}s", eta.as_secs()));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use diagnyx::Provider;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_read_calls_reports_bad_lines() {
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .input_tokens(5)
+            .output_tokens(2)
+            .build();
+        let line = serde_json::to_string(&call).unwrap();
+        let input = format!("{}\n\nnot json\n", line);
+        let err = read_calls(input.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 3"));
+
+        let calls = read_calls(line.as_bytes()).unwrap();
+        assert_eq!(calls[0].model, "gpt-4");
+        assert!(summary(&calls[0]).contains("OpenAI gpt-4 5+2 tokens"));
+    }
+}
@@ -0,0 +1,655 @@
+//! A blocking client for applications without an async runtime.
+//!
+//! [`DiagnyxClient`] has the same `track`/`flush`/`shutdown` API as the
+//! async [`crate::DiagnyxClient`], built on `reqwest::blocking`. Buffered
+//! calls are flushed by a background thread, so CLI tools and synchronous
+//! services can track calls without running Tokio themselves.
+//!
+//! Filters, sampling, redaction, the cost calculator, spend and budget
+//! tracking and auth failure parking of the [`DiagnyxConfig`] apply as
+//! usual. Enrichers are async and are not run by this client.
+//!
+//! The client may be used, and dropped, on a thread running a Tokio
+//! runtime: requests are then sent from a separate thread.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::blocking::DiagnyxClient;
+//! use diagnyx::{LLMCall, Provider};
+//!
+//! let client = DiagnyxClient::new("dx_live_your_api_key");
+//!
+//! client.track(
+//!     LLMCall::builder()
+//!         .provider(Provider::OpenAI)
+//!         .model("gpt-4o-mini")
+//!         .input_tokens(120)
+//!         .output_tokens(40)
+//!         .build(),
+//! );
+//!
+//! client.shutdown().unwrap();
+//! ```
+
+use chrono::{DateTime, Utc};
+use reqwest::blocking::Client;
+use reqwest::Method;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::budgets::BudgetTracker;
+use crate::buffer::CallBuffer;
+use crate::client::{
+    batch_body, finish_prepare, negotiated_mode, park_buffer, rejects_format, sampled_out,
+    AuthState, FlushReport,
+};
+use crate::error::DiagnyxError;
+use crate::events::{EventBus, SdkEvent};
+use crate::http::REQUEST_TIMEOUT;
+use crate::ids::ProjectId;
+use crate::parked::ParkedQueue;
+use crate::retry::send_with_retry_blocking;
+use crate::sampling::AdaptiveSampler;
+use crate::schedule::{phase_offset, FlushSchedule};
+use crate::spend::SpendCache;
+use crate::types::{DiagnyxConfig, EmbeddingCall, FlushMode, LLMCall};
+use crate::watermark::WatermarkState;
+
+/// State shared with the flusher thread.
+struct Shared {
+    config: DiagnyxConfig,
+    http_client: HttpClient,
+    buffer: Mutex<CallBuffer>,
+    shutdown: Mutex<bool>,
+    wake: Condvar,
+    events: EventBus,
+    sampler: Option<AdaptiveSampler>,
+    /// Set once the server rejects compact batches.
+    compact_rejected: AtomicBool,
+    watermarks: WatermarkState,
+    schedule: FlushSchedule,
+    last_report: Mutex<Option<FlushReport>>,
+    spend: SpendCache,
+    budgets: BudgetTracker,
+    auth: AuthState,
+    parked: Option<ParkedQueue>,
+}
+
+/// The blocking Diagnyx client for tracking LLM calls.
+pub struct DiagnyxClient {
+    shared: Arc<Shared>,
+    flusher: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl DiagnyxClient {
+    /// Create a new client with the given API key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self::with_config(DiagnyxConfig::new(api_key))
+    }
+
+    /// Create a new client with custom configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_with_config`](Self::try_with_config) to handle the error instead.
+    pub fn with_config(config: DiagnyxConfig) -> Self {
+        Self::try_with_config(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new client with the given API key, returning an error if
+    /// the HTTP client cannot be created.
+    pub fn try_new(api_key: impl Into<String>) -> Result<Self, DiagnyxError> {
+        Self::try_with_config(DiagnyxConfig::new(api_key))
+    }
+
+    /// Create a new client with custom configuration, returning an error if
+    /// the HTTP client cannot be created.
+    pub fn try_with_config(config: DiagnyxConfig) -> Result<Self, DiagnyxError> {
+        let parked = config
+            .parked_buffer_path
+            .clone()
+            .filter(|_| !config.disabled)
+            .map(ParkedQueue::new);
+        let restored = parked.as_ref().map(ParkedQueue::take).unwrap_or_default();
+        let shared = Arc::new(Shared {
+            http_client: HttpClient(Some(outside_runtime(|| {
+                config.http.blocking_client(REQUEST_TIMEOUT)
+            })?)),
+            buffer: Mutex::new(CallBuffer::new(restored)),
+            shutdown: Mutex::new(false),
+            wake: Condvar::new(),
+            events: EventBus::default(),
+            sampler: config.sampling.volume_cap.map(AdaptiveSampler::new),
+            compact_rejected: AtomicBool::new(false),
+            watermarks: WatermarkState::default(),
+            schedule: FlushSchedule::default(),
+            last_report: Mutex::new(None),
+            spend: SpendCache::open(config.spend_cache_path.clone()),
+            // Test traffic must not count against budgets or warn about them
+            budgets: BudgetTracker::new(if config.is_test_mode() {
+                &[]
+            } else {
+                &config.budgets
+            }),
+            auth: AuthState::default(),
+            parked,
+            config,
+        });
+
+        let flusher = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("diagnyx-flush".to_string())
+                .spawn(move || flush_loop(&shared))?
+        };
+
+        Ok(Self {
+            shared,
+            flusher: Mutex::new(Some(flusher)),
+        })
+    }
+
+    /// Subscribe to flush and buffer events emitted by this client.
+    ///
+    /// Use [`blocking_recv`](tokio::sync::broadcast::Receiver::blocking_recv)
+    /// to wait for events outside an async runtime.
+    pub fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<SdkEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// Track a single LLM call.
+    pub fn track(&self, call: LLMCall) {
+        self.track_all(vec![call]);
+    }
+
+    /// Track an embedding call.
+    pub fn track_embedding(&self, call: EmbeddingCall) {
+        self.track(call.into());
+    }
+
+    /// Track multiple LLM calls.
+    pub fn track_all(&self, calls: Vec<LLMCall>) {
+        let now = Utc::now();
+        let calls: Vec<_> = calls
+            .into_iter()
+            .filter_map(|mut call| {
+                if call.timestamp == DateTime::<Utc>::default() {
+                    call.timestamp = now;
+                }
+                let call = self.prepare(call)?;
+                self.shared.spend.record(&call);
+                self.shared.budgets.record(&call);
+                Some(call)
+            })
+            .collect();
+
+        let should_flush = {
+            let mut buffer = self.shared.buffer.lock().unwrap();
+            buffer.extend(calls);
+            buffer.trim(&self.shared.config, &self.shared.events);
+            self.shared.update_watermarks(buffer.len());
+            buffer.len() >= self.shared.config.batch_size
+        };
+
+        if should_flush && self.shared.schedule.ready() {
+            let _ = self.flush();
+        }
+    }
+
+    /// Sample the call, run filters, estimate the cost and export it as a
+    /// span, returning `None` if the call is dropped.
+    fn prepare(&self, call: LLMCall) -> Option<LLMCall> {
+        let config = &self.shared.config;
+        if config.disabled {
+            return None;
+        }
+        let rate = config.sampling.rate;
+        if config.sampling.is_uniform() && sampled_out(rate) {
+            return None;
+        }
+        let call = config.filters.run(call, &self.shared.events)?;
+        let sampler = self.shared.sampler.as_ref();
+        finish_prepare(call, rate, config, sampler, &self.shared.events)
+    }
+
+    /// Whether flushing stopped because the API key was rejected
+    /// [`auth_failure_threshold`](DiagnyxConfig::auth_failure_threshold)
+    /// times in a row.
+    pub fn is_parked(&self) -> bool {
+        self.shared.auth.parked().is_some()
+    }
+
+    /// Month-to-date spend of `project` in USD, from the estimated cost of
+    /// tracked calls.
+    pub fn month_to_date(&self, project: &ProjectId) -> f64 {
+        self.shared.spend.month_to_date(project.as_str())
+    }
+
+    /// Check the configured budgets before making an LLM call for `project`
+    /// and `user`.
+    ///
+    /// Returns [`DiagnyxError::BudgetExceeded`] if an exhausted budget
+    /// rejects calls.
+    pub fn check_budget(
+        &self,
+        project: Option<&ProjectId>,
+        user: Option<&str>,
+    ) -> Result<(), DiagnyxError> {
+        self.shared
+            .budgets
+            .check(project, user, &self.shared.events)
+    }
+
+    /// Flush all buffered calls to the API, returning its totals for the
+    /// batch.
+    pub fn flush(&self) -> Result<FlushReport, DiagnyxError> {
+        self.shared.flush()
+    }
+
+    /// The report of the last flush that sent calls, whether it was
+    /// requested or ran on the flusher thread.
+    ///
+    /// `None` until a flush has succeeded.
+    pub fn last_flush_stats(&self) -> Option<FlushReport> {
+        self.shared.last_report.lock().unwrap().clone()
+    }
+
+    /// Get the current buffer size.
+    pub fn buffer_size(&self) -> usize {
+        self.shared.buffer.lock().unwrap().len()
+    }
+
+    /// Stop the flusher thread and flush any remaining calls.
+    pub fn shutdown(&self) -> Result<(), DiagnyxError> {
+        *self.shared.shutdown.lock().unwrap() = true;
+        self.shared.wake.notify_all();
+        if let Some(flusher) = self.flusher.lock().unwrap().take() {
+            let _ = flusher.join();
+        }
+        self.shared.flush().map(drop)
+    }
+}
+
+impl Drop for DiagnyxClient {
+    fn drop(&mut self) {
+        if self.flusher.lock().unwrap().is_some() {
+            let _ = self.shutdown();
+        }
+    }
+}
+
+/// Flush every `flush_interval_ms`, or less often if the API asks for it,
+/// until shutdown.
+fn flush_loop(shared: &Shared) {
+    let interval = Duration::from_millis(shared.config.flush_interval_ms);
+    let mut wait = if shared.config.flush_jitter {
+        phase_offset(interval)
+    } else {
+        Duration::ZERO
+    };
+    let mut shutdown = shared.shutdown.lock().unwrap();
+
+    while !*shutdown {
+        wait += shared.schedule.interval(interval);
+        shutdown = shared.wake.wait_timeout(shutdown, wait).unwrap().0;
+        wait = Duration::ZERO;
+        if *shutdown {
+            break;
+        }
+
+        drop(shutdown);
+        if let Err(e) = shared.flush() {
+            shared
+                .config
+                .logger()
+                .warn(&e.with_request_id(format!("Background flush error: {}", e)));
+        }
+        shutdown = shared.shutdown.lock().unwrap();
+    }
+}
+
+impl Shared {
+    fn update_watermarks(&self, depth: usize) {
+        self.watermarks.update(depth, &self.config, &self.events);
+    }
+
+    fn flush(&self) -> Result<FlushReport, DiagnyxError> {
+        if let Some(status_code) = self.auth.parked() {
+            return Err(DiagnyxError::AuthFailed { status_code });
+        }
+
+        // Pick up calls parked by other processes sharing the file
+        let restored = self
+            .parked
+            .as_ref()
+            .map(ParkedQueue::take)
+            .unwrap_or_default();
+        let calls = {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.extend(restored);
+            if buffer.is_empty() {
+                return Ok(FlushReport::default());
+            }
+            buffer.take()
+        };
+        if let Err(e) = self.spend.save() {
+            self.config
+                .logger()
+                .log(&format!("Failed to save spend cache: {}", e));
+        }
+
+        self.events
+            .emit(SdkEvent::FlushStarted { count: calls.len() });
+
+        let result = outside_runtime(|| self.send_batch(&calls));
+        let parked = self
+            .auth
+            .record(&result, self.config.auth_failure_threshold);
+        match result {
+            Ok(report) => {
+                self.config
+                    .logger()
+                    .log(&format!("Flushed {} calls", calls.len()));
+                self.events
+                    .emit(SdkEvent::FlushSucceeded { count: calls.len() });
+                self.update_watermarks(self.buffer.lock().unwrap().len());
+                *self.last_report.lock().unwrap() = Some(report.clone());
+                Ok(report)
+            }
+            Err(e) => {
+                self.events.emit(SdkEvent::FlushFailed {
+                    error: e.to_string(),
+                });
+                let mut buffer = self.buffer.lock().unwrap();
+                buffer.restore(calls, &self.config, &self.events);
+                if let Some(status_code) = parked {
+                    park_buffer(
+                        &self.config,
+                        self.parked.as_ref(),
+                        &mut buffer,
+                        &self.events,
+                        &e,
+                        status_code,
+                    );
+                }
+                self.update_watermarks(buffer.len());
+                Err(e)
+            }
+        }
+    }
+
+    fn send_batch(&self, calls: &[LLMCall]) -> Result<FlushReport, DiagnyxError> {
+        if let Some(sink) = &self.config.sink {
+            // Sinks are async; this client runs without a runtime of its own
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            return runtime.block_on(sink.write(calls));
+        }
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::debug_span!(target: "diagnyx", "flush", batch_size = calls.len()).entered();
+        let mode = negotiated_mode(&self.config, &self.compact_rejected);
+        match self.send_encoded(mode, calls) {
+            Err(e) if mode == FlushMode::Compact && rejects_format(&e) => {
+                self.compact_rejected.store(true, Ordering::Relaxed);
+                self.config
+                    .logger()
+                    .log("Compact batches not accepted; sending plain batches");
+                self.send_encoded(FlushMode::Batch, calls)
+            }
+            result => result,
+        }
+    }
+
+    fn send_encoded(
+        &self,
+        mode: FlushMode,
+        calls: &[LLMCall],
+    ) -> Result<FlushReport, DiagnyxError> {
+        let (body, encoding) = batch_body(&self.config, mode, calls)?;
+        let url = format!("{}{}", self.config.base_url, mode.path());
+
+        let response = send_with_retry_blocking(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            Method::POST,
+            |method| {
+                let request = self
+                    .http_client
+                    .request(method, &url)
+                    .header("Content-Type", mode.content_type())
+                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+                    .body(body.clone());
+                match encoding {
+                    Some(encoding) => request.header("Content-Encoding", encoding),
+                    None => request,
+                }
+            },
+        )?;
+        self.schedule.sent(response.headers());
+        Ok(FlushReport::from_response(
+            calls.len(),
+            &response.bytes()?,
+            &self.config,
+        ))
+    }
+}
+
+/// The blocking HTTP client, which starts a runtime of its own when built
+/// and shuts it down when dropped. Both panic on a thread running a Tokio
+/// runtime, so the client is then built and dropped on another thread.
+struct HttpClient(Option<Client>);
+
+impl std::ops::Deref for HttpClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.0
+            .as_ref()
+            .expect("the HTTP client is only taken on drop")
+    }
+}
+
+impl Drop for HttpClient {
+    fn drop(&mut self) {
+        if let Some(client) = self.0.take() {
+            outside_runtime(move || drop(client));
+        }
+    }
+}
+
+/// Run `f` on a new thread if the current thread runs a Tokio runtime, in
+/// which blocking requests, and blocking on a runtime of our own, panic.
+fn outside_runtime<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    if tokio::runtime::Handle::try_current().is_err() {
+        return f();
+    }
+    std::thread::scope(|scope| scope.spawn(f).join())
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Provider;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn call() -> LLMCall {
+        LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .input_tokens(100)
+            .output_tokens(50)
+            .build()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_track_and_shutdown_flushes() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .and(header("Authorization", "Bearer test-api-key"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        tokio::task::spawn_blocking(move || {
+            let client = DiagnyxClient::with_config(
+                DiagnyxConfig::new("test-api-key")
+                    .base_url(uri)
+                    .flush_interval_ms(60000),
+            );
+            client.track_all(vec![call(), call()]);
+            assert_eq!(client.buffer_size(), 2);
+
+            client.shutdown().unwrap();
+            assert_eq!(client.buffer_size(), 0);
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failed_flush_restores_buffer() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        tokio::task::spawn_blocking(move || {
+            let client = DiagnyxClient::with_config(
+                DiagnyxConfig::new("test-api-key")
+                    .base_url(uri)
+                    .flush_interval_ms(60000),
+            );
+            client.track(call());
+
+            assert!(client.flush().is_err());
+            assert_eq!(client.buffer_size(), 1);
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_can_be_dropped_within_a_runtime() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000),
+        );
+        client.track(call());
+        drop(client);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rejected_key_parks_the_client() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        tokio::task::spawn_blocking(move || {
+            let client = DiagnyxClient::with_config(
+                DiagnyxConfig::new("test-api-key")
+                    .base_url(uri)
+                    .flush_interval_ms(60000)
+                    .max_retries(0)
+                    .auth_failure_threshold(1),
+            );
+            client.track(call());
+            assert!(client.flush().is_err());
+            assert!(client.is_parked());
+            assert!(matches!(
+                client.flush(),
+                Err(DiagnyxError::AuthFailed { status_code: 401 })
+            ));
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_oversized_calls_are_trimmed() {
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url("http://127.0.0.1:9")
+                .flush_interval_ms(60000)
+                .capture_full_content(true)
+                .max_call_bytes(1024),
+        );
+        let mut events = client.subscribe_events();
+        let mut oversized = call();
+        oversized.full_prompt = Some("x".repeat(4096));
+
+        client.track(oversized);
+
+        assert_eq!(client.buffer_size(), 1);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(SdkEvent::CallTrimmed { fields }) if fields.contains(&"full_prompt".to_string())
+        ));
+        let buffer = client.shared.buffer.lock().unwrap();
+        assert!(serde_json::to_vec(&buffer[0]).unwrap().len() <= 1024);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_background_thread_flushes() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        tokio::task::spawn_blocking(move || {
+            let client = DiagnyxClient::with_config(
+                DiagnyxConfig::new("test-api-key")
+                    .base_url(uri)
+                    .flush_interval_ms(20),
+            );
+            let mut events = client.subscribe_events();
+            client.track(call());
+
+            assert_eq!(
+                events.blocking_recv().unwrap(),
+                SdkEvent::FlushStarted { count: 1 }
+            );
+            assert_eq!(
+                events.blocking_recv().unwrap(),
+                SdkEvent::FlushSucceeded { count: 1 }
+            );
+        })
+        .await
+        .unwrap();
+    }
+}
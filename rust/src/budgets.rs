@@ -0,0 +1,438 @@
+//! Client-side budget enforcement.
+//!
+//! A [`BudgetConfig`] caps the spend of a project or a user over a window,
+//! such as a day or a calendar month. The client keeps a running total of
+//! the estimated cost of tracked calls in each budget's current window and
+//! periodically replaces it with the total reported by the API.
+//!
+//! Before making an LLM call, [`DiagnyxClient::check_budget`] rejects it
+//! with [`DiagnyxError::BudgetExceeded`] once the total reaches the limit,
+//! or only emits an [`SdkEvent::BudgetWarning`] for budgets configured with
+//! [`BudgetConfig::warn_only`]. [`BudgetGuard`] wraps the check around a
+//! call.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::budgets::{BudgetConfig, BudgetGuard, BudgetWindow};
+//! use diagnyx::{DiagnyxClient, DiagnyxConfig, ProjectId};
+//! use std::sync::Arc;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), diagnyx::DiagnyxError> {
+//!     let project = ProjectId::from_static("chatbot");
+//!     let client = Arc::new(DiagnyxClient::with_config(
+//!         DiagnyxConfig::new("dx_live_your_api_key")
+//!             .budget(BudgetConfig::project(project.clone(), 50.0, BudgetWindow::Daily))
+//!             .budget(BudgetConfig::user("user-42", 1.0, BudgetWindow::Daily).warn_only())
+//!             .budget_sync_interval_ms(60_000),
+//!     ));
+//!
+//!     let guard = BudgetGuard::new(client.clone())
+//!         .project_id(project)
+//!         .user_identifier("user-42");
+//!     let reply = guard.run(|| async { "call the model here" }).await?;
+//!     println!("{}", reply);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! [`DiagnyxClient::check_budget`]: crate::DiagnyxClient::check_budget
+
+use chrono::{DateTime, Datelike, Duration, NaiveTime, Timelike, Utc};
+use serde::Deserialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use crate::client::DiagnyxClient;
+use crate::error::DiagnyxError;
+use crate::events::{EventBus, SdkEvent};
+use crate::ids::ProjectId;
+use crate::spend;
+use crate::types::LLMCall;
+
+/// Period a budget's spend is summed over. Windows start at midnight UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetWindow {
+    Hourly,
+    Daily,
+    /// Starting on Monday.
+    Weekly,
+    /// The calendar month.
+    Monthly,
+}
+
+impl BudgetWindow {
+    /// Start of the window containing `time`.
+    pub fn start(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let midnight = time.date_naive().and_time(NaiveTime::MIN).and_utc();
+        match self {
+            BudgetWindow::Hourly => midnight + Duration::hours(i64::from(time.hour())),
+            BudgetWindow::Daily => midnight,
+            BudgetWindow::Weekly => {
+                midnight - Duration::days(i64::from(time.weekday().num_days_from_monday()))
+            }
+            BudgetWindow::Monthly => midnight - Duration::days(i64::from(time.day0())),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            BudgetWindow::Hourly => "hour",
+            BudgetWindow::Daily => "day",
+            BudgetWindow::Weekly => "week",
+            BudgetWindow::Monthly => "month",
+        }
+    }
+}
+
+/// Whose spend a budget limits.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetScope {
+    Project(ProjectId),
+    /// Calls with this `user_identifier`.
+    User(String),
+}
+
+impl BudgetScope {
+    fn matches(&self, project_id: Option<&ProjectId>, user: Option<&str>) -> bool {
+        match self {
+            BudgetScope::Project(id) => project_id == Some(id),
+            BudgetScope::User(id) => user == Some(id.as_str()),
+        }
+    }
+}
+
+/// What happens when a budget is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetAction {
+    /// Fail the budget check.
+    #[default]
+    Reject,
+    /// Emit a warning and let the call through.
+    Warn,
+}
+
+/// A spend limit for a project or user.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BudgetConfig {
+    pub scope: BudgetScope,
+    pub limit_usd: f64,
+    pub window: BudgetWindow,
+    #[serde(default)]
+    pub action: BudgetAction,
+}
+
+impl BudgetConfig {
+    /// Limit the spend of `project` to `limit_usd` per `window`.
+    pub fn project(project: ProjectId, limit_usd: f64, window: BudgetWindow) -> Self {
+        Self {
+            scope: BudgetScope::Project(project),
+            limit_usd,
+            window,
+            action: BudgetAction::Reject,
+        }
+    }
+
+    /// Limit the spend of calls by `user` to `limit_usd` per `window`.
+    pub fn user(user: impl Into<String>, limit_usd: f64, window: BudgetWindow) -> Self {
+        Self {
+            scope: BudgetScope::User(user.into()),
+            limit_usd,
+            window,
+            action: BudgetAction::Reject,
+        }
+    }
+
+    /// Warn instead of rejecting calls once the budget is exhausted.
+    pub fn warn_only(mut self) -> Self {
+        self.action = BudgetAction::Warn;
+        self
+    }
+}
+
+/// Spend of a budget's scope over a window, as reported by the API.
+#[derive(Debug, Deserialize)]
+pub(crate) struct WindowSpendResponse {
+    pub total_cost: f64,
+}
+
+#[derive(Debug)]
+struct Budget {
+    config: BudgetConfig,
+    /// Start of the window and the spend in it.
+    spent: Mutex<(DateTime<Utc>, f64)>,
+}
+
+impl Budget {
+    /// Spend in the window containing `now`.
+    fn spent_at(&self, now: DateTime<Utc>) -> f64 {
+        let spent = self.spent.lock().unwrap();
+        if spent.0 == self.config.window.start(now) {
+            spent.1
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Running totals of the configured budgets.
+#[derive(Debug, Default)]
+pub(crate) struct BudgetTracker {
+    budgets: Vec<Budget>,
+}
+
+impl BudgetTracker {
+    pub(crate) fn new(configs: &[BudgetConfig]) -> Self {
+        let now = Utc::now();
+        Self {
+            budgets: configs
+                .iter()
+                .map(|config| Budget {
+                    spent: Mutex::new((config.window.start(now), 0.0)),
+                    config: config.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.budgets.is_empty()
+    }
+
+    /// Add the estimated cost of a tracked call to the budgets it counts
+    /// against.
+    pub(crate) fn record(&self, call: &LLMCall) {
+        let cost = spend::call_cost(call);
+        for budget in self.matching(call.project_id.as_ref(), call.user_identifier.as_deref()) {
+            let start = budget.config.window.start(call.timestamp);
+            let mut spent = budget.spent.lock().unwrap();
+            if start > spent.0 {
+                *spent = (start, 0.0);
+            }
+            if start == spent.0 {
+                spent.1 += cost;
+            }
+        }
+    }
+
+    /// Check the budgets of a call about to be made.
+    ///
+    /// Returns the first exhausted budget that rejects calls, after emitting
+    /// a warning for each exhausted budget that only warns.
+    pub(crate) fn check(
+        &self,
+        project_id: Option<&ProjectId>,
+        user: Option<&str>,
+        events: &EventBus,
+    ) -> Result<(), DiagnyxError> {
+        let now = Utc::now();
+        let mut result = Ok(());
+        for budget in self.matching(project_id, user) {
+            let spent = budget.spent_at(now);
+            if spent < budget.config.limit_usd {
+                continue;
+            }
+            match budget.config.action {
+                BudgetAction::Warn => events.emit(SdkEvent::BudgetWarning {
+                    scope: budget.config.scope.clone(),
+                    spent_usd: spent,
+                    limit_usd: budget.config.limit_usd,
+                }),
+                BudgetAction::Reject if result.is_ok() => {
+                    result = Err(DiagnyxError::BudgetExceeded {
+                        spent_usd: spent,
+                        budget_usd: budget.config.limit_usd,
+                    })
+                }
+                BudgetAction::Reject => {}
+            }
+        }
+        result
+    }
+
+    /// Budget configurations, with the query identifying each budget's
+    /// scope and window for the API.
+    pub(crate) fn queries(&self) -> Vec<(usize, [(&'static str, String); 2])> {
+        self.budgets
+            .iter()
+            .enumerate()
+            .map(|(i, budget)| {
+                let scope = match &budget.config.scope {
+                    BudgetScope::Project(id) => ("project_id", id.to_string()),
+                    BudgetScope::User(user) => ("user_identifier", user.clone()),
+                };
+                (
+                    i,
+                    [scope, ("window", budget.config.window.as_str().to_string())],
+                )
+            })
+            .collect()
+    }
+
+    /// Replace the total of budget `index` with the API's total for the
+    /// current window plus the cost of `pending` calls not yet sent.
+    pub(crate) fn sync(&self, index: usize, server_total: f64, pending: &[LLMCall]) {
+        let budget = &self.budgets[index];
+        let start = budget.config.window.start(Utc::now());
+        let pending: f64 = pending
+            .iter()
+            .filter(|call| {
+                budget
+                    .config
+                    .scope
+                    .matches(call.project_id.as_ref(), call.user_identifier.as_deref())
+                    && budget.config.window.start(call.timestamp) == start
+            })
+            .map(spend::call_cost)
+            .sum();
+        *budget.spent.lock().unwrap() = (start, server_total + pending);
+    }
+
+    fn matching<'a>(
+        &'a self,
+        project_id: Option<&'a ProjectId>,
+        user: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a Budget> {
+        self.budgets
+            .iter()
+            .filter(move |budget| budget.config.scope.matches(project_id, user))
+    }
+}
+
+/// Checks the budgets of a project and user before running LLM calls.
+pub struct BudgetGuard {
+    client: Arc<DiagnyxClient>,
+    project_id: Option<ProjectId>,
+    user_identifier: Option<String>,
+}
+
+impl BudgetGuard {
+    pub fn new(client: Arc<DiagnyxClient>) -> Self {
+        Self {
+            client,
+            project_id: None,
+            user_identifier: None,
+        }
+    }
+
+    pub fn project_id(mut self, id: ProjectId) -> Self {
+        self.project_id = Some(id);
+        self
+    }
+
+    pub fn user_identifier(mut self, user: impl Into<String>) -> Self {
+        self.user_identifier = Some(user.into());
+        self
+    }
+
+    /// Check the budgets.
+    ///
+    /// Returns [`DiagnyxError::BudgetExceeded`] if a rejecting budget is
+    /// exhausted.
+    pub fn check(&self) -> Result<(), DiagnyxError> {
+        self.client
+            .check_budget(self.project_id.as_ref(), self.user_identifier.as_deref())
+    }
+
+    /// Run `call` if the budgets allow it.
+    pub async fn run<F, Fut, T>(&self, call: F) -> Result<T, DiagnyxError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        self.check()?;
+        Ok(call().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Provider;
+    use chrono::TimeZone;
+
+    fn call(project: &'static str, user: &str, cost: f64) -> LLMCall {
+        let mut call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .project_id(ProjectId::from_static(project))
+            .user_identifier(user)
+            .estimated_cost_usd(cost)
+            .build();
+        call.timestamp = Utc::now();
+        call
+    }
+
+    #[test]
+    fn test_window_start() {
+        let time = Utc.with_ymd_and_hms(2024, 5, 16, 14, 30, 0).unwrap();
+        let at = |d, h| Utc.with_ymd_and_hms(2024, 5, d, h, 0, 0).unwrap();
+        assert_eq!(BudgetWindow::Hourly.start(time), at(16, 14));
+        assert_eq!(BudgetWindow::Daily.start(time), at(16, 0));
+        assert_eq!(BudgetWindow::Weekly.start(time), at(13, 0));
+        assert_eq!(BudgetWindow::Monthly.start(time), at(1, 0));
+    }
+
+    #[test]
+    fn test_rejects_once_limit_is_reached() {
+        let project = ProjectId::from_static("proj-1");
+        let tracker = BudgetTracker::new(&[BudgetConfig::project(
+            project.clone(),
+            1.0,
+            BudgetWindow::Daily,
+        )]);
+        let events = EventBus::default();
+
+        tracker.record(&call("proj-1", "u", 0.6));
+        assert!(tracker.check(Some(&project), None, &events).is_ok());
+
+        tracker.record(&call("proj-1", "u", 0.6));
+        tracker.record(&call("proj-2", "u", 5.0));
+        match tracker.check(Some(&project), None, &events) {
+            Err(DiagnyxError::BudgetExceeded { spent_usd, .. }) => {
+                assert!((spent_usd - 1.2).abs() < 1e-9)
+            }
+            other => panic!("Expected BudgetExceeded, got {:?}", other),
+        }
+        assert!(tracker
+            .check(Some(&ProjectId::from_static("proj-3")), None, &events)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_warn_only_budgets_emit_events() {
+        let tracker =
+            BudgetTracker::new(&[
+                BudgetConfig::user("alice", 0.5, BudgetWindow::Monthly).warn_only()
+            ]);
+        let events = EventBus::default();
+        let mut receiver = events.subscribe();
+
+        tracker.record(&call("proj-1", "alice", 1.0));
+        assert!(tracker.check(None, Some("alice"), &events).is_ok());
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            SdkEvent::BudgetWarning {
+                scope: BudgetScope::User("alice".to_string()),
+                spent_usd: 1.0,
+                limit_usd: 0.5,
+            }
+        );
+    }
+
+    #[test]
+    fn test_sync_replaces_total() {
+        let tracker = BudgetTracker::new(&[BudgetConfig::user("alice", 10.0, BudgetWindow::Daily)]);
+        tracker.record(&call("proj-1", "alice", 1.0));
+
+        tracker.sync(0, 12.0, &[call("proj-1", "alice", 0.5)]);
+        assert!(matches!(
+            tracker.check(None, Some("alice"), &EventBus::default()),
+            Err(DiagnyxError::BudgetExceeded { spent_usd, .. }) if spent_usd == 12.5
+        ));
+    }
+}
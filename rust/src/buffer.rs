@@ -0,0 +1,196 @@
+//! The buffer of calls waiting to be flushed.
+//!
+//! The buffer keeps a running count of the captured content it holds, and
+//! how many of its oldest calls have had their content stripped, so that
+//! enforcing `max_content_bytes` on every tracked call does not rescan it.
+
+use std::ops::Deref;
+
+use crate::error::DiagnyxError;
+use crate::events::{EventBus, SdkEvent};
+use crate::parked::ParkedQueue;
+use crate::types::{DiagnyxConfig, LLMCall};
+
+/// Calls waiting to be flushed, oldest first.
+#[derive(Debug, Default)]
+pub(crate) struct CallBuffer {
+    calls: Vec<LLMCall>,
+    /// Bytes of `full_prompt` and `full_response` held by `calls`.
+    content_bytes: usize,
+    /// Leading calls known to hold no content.
+    stripped: usize,
+}
+
+fn content_len(call: &LLMCall) -> usize {
+    call.full_prompt.as_ref().map_or(0, String::len)
+        + call.full_response.as_ref().map_or(0, String::len)
+}
+
+impl CallBuffer {
+    pub(crate) fn new(calls: Vec<LLMCall>) -> Self {
+        Self {
+            content_bytes: calls.iter().map(content_len).sum(),
+            calls,
+            stripped: 0,
+        }
+    }
+
+    pub(crate) fn push(&mut self, call: LLMCall) {
+        self.content_bytes += content_len(&call);
+        self.calls.push(call);
+    }
+
+    pub(crate) fn extend(&mut self, calls: impl IntoIterator<Item = LLMCall>) {
+        for call in calls {
+            self.push(call);
+        }
+    }
+
+    /// Take all the calls, leaving the buffer empty.
+    pub(crate) fn take(&mut self) -> Vec<LLMCall> {
+        self.content_bytes = 0;
+        self.stripped = 0;
+        std::mem::take(&mut self.calls)
+    }
+
+    /// Put calls from a failed flush back in front of any tracked since.
+    pub(crate) fn restore(
+        &mut self,
+        calls: Vec<LLMCall>,
+        config: &DiagnyxConfig,
+        events: &EventBus,
+    ) {
+        self.content_bytes += calls.iter().map(content_len).sum::<usize>();
+        self.stripped = 0;
+        self.calls.splice(..0, calls);
+        self.trim(config, events);
+    }
+
+    /// Append the calls to `parked`, removing them once they are saved.
+    pub(crate) fn park(&mut self, parked: &ParkedQueue) -> Result<(), DiagnyxError> {
+        parked.append(&mut self.calls)?;
+        self.content_bytes = 0;
+        self.stripped = 0;
+        Ok(())
+    }
+
+    /// Drop the oldest calls so the buffer holds at most `max_buffer_size`,
+    /// then strip captured content from the oldest calls until it fits in
+    /// `max_content_bytes`.
+    pub(crate) fn trim(&mut self, config: &DiagnyxConfig, events: &EventBus) {
+        if self.calls.len() > config.max_buffer_size {
+            let count = self.calls.len() - config.max_buffer_size;
+            let dropped: usize = self.calls.drain(..count).map(|c| content_len(&c)).sum();
+            self.content_bytes -= dropped;
+            self.stripped = self.stripped.saturating_sub(count);
+            config.logger().warn(&format!(
+                "Buffer full ({} calls); dropped {} oldest calls",
+                config.max_buffer_size, count
+            ));
+            events.emit(SdkEvent::CallDropped { count });
+        }
+
+        let Some(max_bytes) = config.max_content_bytes else {
+            return;
+        };
+        let mut count = 0;
+        while self.content_bytes > max_bytes && self.stripped < self.calls.len() {
+            let call = &mut self.calls[self.stripped];
+            let len = content_len(call);
+            if len > 0 {
+                self.content_bytes -= len;
+                call.full_prompt = None;
+                call.full_response = None;
+                count += 1;
+            }
+            self.stripped += 1;
+        }
+        if count > 0 {
+            events.emit(SdkEvent::ContentStripped { count });
+        }
+    }
+}
+
+impl Deref for CallBuffer {
+    type Target = [LLMCall];
+
+    fn deref(&self) -> &[LLMCall] {
+        &self.calls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Provider;
+
+    fn call(prompt: &str) -> LLMCall {
+        LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .full_prompt(prompt)
+            .input_tokens(5)
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_content_cap_strips_oldest_calls() {
+        let config = DiagnyxConfig::new("test-api-key").max_content_bytes(20);
+        let events = EventBus::new(16);
+        let mut receiver = events.subscribe();
+        let mut buffer = CallBuffer::new((0..3).map(|_| call("0123456789")).collect());
+
+        buffer.trim(&config, &events);
+
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer[0].full_prompt, None);
+        assert_eq!(buffer[0].input_tokens, 5);
+        assert!(buffer[1].full_prompt.is_some());
+        assert!(buffer[2].full_prompt.is_some());
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            SdkEvent::ContentStripped { count: 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restoring_a_failed_flush_drops_the_oldest_calls() {
+        let config = DiagnyxConfig::new("test-api-key").max_buffer_size(2);
+        let events = EventBus::new(16);
+        let mut receiver = events.subscribe();
+        let mut buffer = CallBuffer::default();
+
+        buffer.push(call("new"));
+        buffer.restore(vec![call("a"), call("b")], &config, &events);
+
+        let prompts: Vec<_> = buffer.iter().map(|c| c.full_prompt.as_deref()).collect();
+        assert_eq!(prompts, [Some("b"), Some("new")]);
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            SdkEvent::CallDropped { count: 1 }
+        );
+    }
+
+    #[test]
+    fn test_content_count_follows_dropped_and_restored_calls() {
+        let config = DiagnyxConfig::new("test-api-key")
+            .max_buffer_size(2)
+            .max_content_bytes(12);
+        let events = EventBus::default();
+        let mut buffer = CallBuffer::default();
+
+        buffer.extend([call("0123456789"), call("0123456789")]);
+        buffer.push(call("0123456789"));
+        buffer.trim(&config, &events);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer[0].full_prompt, None);
+        assert!(buffer[1].full_prompt.is_some());
+
+        let sent = buffer.take();
+        buffer.push(call("abc"));
+        buffer.restore(sent, &config, &events);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer[0].full_prompt, None);
+        assert_eq!(buffer[1].full_prompt.as_deref(), Some("abc"));
+    }
+}
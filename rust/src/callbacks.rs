@@ -1,19 +1,22 @@
 //! Callback handlers for LLM framework integrations.
 //!
 //! This module provides callback handlers for tracking LLM calls made through
-//! various Rust LLM frameworks like langchain-rust.
+//! various Rust LLM frameworks like langchain-rust. Adapters built on the
+//! handler track rig agents, with the `rig` feature, the genai client, with
+//! the `genai` feature, and llm-chain executors, with the `llm-chain`
+//! feature.
 //!
 //! # Example
 //!
 //! ```rust,ignore
-//! use diagnyx::{DiagnyxClient, callbacks::DiagnyxCallbackHandler};
+//! use diagnyx::{DiagnyxClient, ProjectId, callbacks::DiagnyxCallbackHandler};
 //! use std::sync::Arc;
 //!
 //! #[tokio::main]
 //! async fn main() {
 //!     let client = Arc::new(DiagnyxClient::new("dx_live_xxx"));
 //!     let handler = DiagnyxCallbackHandler::new(client.clone())
-//!         .with_project_id("my-project")
+//!         .with_project_id(ProjectId::from_static("my-project"))
 //!         .with_environment("production");
 //!
 //!     // Use with langchain-rust or other frameworks
@@ -21,16 +24,23 @@
 //!     // ... LLM call happens ...
 //!     handler.on_llm_end(&run_id, "gpt-4", "Hi there!", 10, 5);
 //!
-//!     client.shutdown().await.unwrap();
+//!     client.shutdown().await.into_result().unwrap();
 //! }
 //! ```
 
-use crate::{CallStatus, DiagnyxClient, LLMCall, Provider};
-use chrono::Utc;
+use crate::client::truncate_text;
+use crate::ids::{self, IdGenerator};
+use crate::{CallStatus, DiagnyxClient, LLMCall, ProjectId, Provider, ToolCallRecord};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
-use uuid::Uuid;
+
+#[cfg(feature = "genai")]
+pub mod genai;
+#[cfg(feature = "llm-chain")]
+pub mod llm_chain;
+#[cfg(feature = "rig")]
+pub mod rig;
 
 /// Context for a single LLM call being tracked.
 #[derive(Debug, Clone)]
@@ -40,11 +50,19 @@ struct CallContext {
     prompt: Option<String>,
 }
 
+/// Tool calls not yet attributed to an LLM call.
+#[derive(Debug, Default)]
+struct ToolState {
+    /// Running tools, innermost last: name, arguments size and start time.
+    running: Vec<(String, usize, Instant)>,
+    finished: Vec<ToolCallRecord>,
+}
+
 /// Options for configuring the DiagnyxCallbackHandler.
 #[derive(Debug, Clone, Default)]
 pub struct CallbackOptions {
     /// Project ID for categorizing calls.
-    pub project_id: Option<String>,
+    pub project_id: Option<ProjectId>,
     /// Environment name (production, staging, etc.).
     pub environment: Option<String>,
     /// User identifier for tracking.
@@ -65,8 +83,8 @@ impl CallbackOptions {
     }
 
     /// Sets the project ID.
-    pub fn project_id(mut self, id: impl Into<String>) -> Self {
-        self.project_id = Some(id.into());
+    pub fn project_id(mut self, id: ProjectId) -> Self {
+        self.project_id = Some(id);
         self
     }
 
@@ -103,6 +121,8 @@ pub struct DiagnyxCallbackHandler {
     client: Arc<DiagnyxClient>,
     options: CallbackOptions,
     call_contexts: Arc<Mutex<HashMap<String, CallContext>>>,
+    tools: Mutex<ToolState>,
+    id_generator: Option<Arc<dyn IdGenerator>>,
 }
 
 impl DiagnyxCallbackHandler {
@@ -112,12 +132,14 @@ impl DiagnyxCallbackHandler {
             client,
             options: CallbackOptions::new(),
             call_contexts: Arc::new(Mutex::new(HashMap::new())),
+            tools: Mutex::new(ToolState::default()),
+            id_generator: None,
         }
     }
 
     /// Sets the project ID for categorizing calls.
-    pub fn with_project_id(mut self, id: impl Into<String>) -> Self {
-        self.options.project_id = Some(id.into());
+    pub fn with_project_id(mut self, id: ProjectId) -> Self {
+        self.options.project_id = Some(id);
         self
     }
 
@@ -145,11 +167,20 @@ impl DiagnyxCallbackHandler {
         self
     }
 
+    /// Sets the generator of run IDs returned by `on_llm_start`.
+    ///
+    /// Defaults to the process-wide generator set with
+    /// [`set_id_generator`](crate::set_id_generator), or UUID v4.
+    pub fn with_id_generator(mut self, generator: impl IdGenerator + 'static) -> Self {
+        self.id_generator = Some(Arc::new(generator));
+        self
+    }
+
     /// Called when an LLM call starts.
     ///
     /// Returns a run ID that should be passed to `on_llm_end` or `on_llm_error`.
     pub fn on_llm_start(&self, model: &str, prompt: &str) -> String {
-        let run_id = Uuid::new_v4().to_string();
+        let run_id = ids::next_id("run ID", self.id_generator.as_deref());
         self.on_llm_start_with_id(&run_id, model, prompt);
         run_id
     }
@@ -210,7 +241,7 @@ impl DiagnyxCallbackHandler {
             .status(CallStatus::Success);
 
         if let Some(ref project_id) = self.options.project_id {
-            call = call.project_id(project_id);
+            call = call.project_id(project_id.clone());
         }
         if let Some(ref environment) = self.options.environment {
             call = call.environment(environment);
@@ -224,21 +255,14 @@ impl DiagnyxCallbackHandler {
 
             if let Some(ref c) = ctx {
                 if let Some(ref prompt) = c.prompt {
-                    let truncated = if prompt.len() > max_len {
-                        format!("{}... [truncated]", &prompt[..max_len])
-                    } else {
-                        prompt.clone()
-                    };
-                    call = call.full_prompt(truncated);
+                    call = call.full_prompt(truncate_text(prompt.clone(), max_len));
                 }
             }
 
-            let response_truncated = if response.len() > max_len {
-                format!("{}... [truncated]", &response[..max_len])
-            } else {
-                response.to_string()
-            };
-            call = call.full_response(response_truncated);
+            call = call.full_response(truncate_text(response.to_string(), max_len));
+        }
+        for record in self.take_tool_calls() {
+            call = call.tool_call(record);
         }
 
         let client = Arc::clone(&self.client);
@@ -287,7 +311,7 @@ impl DiagnyxCallbackHandler {
             .error_message(error_msg);
 
         if let Some(ref project_id) = self.options.project_id {
-            call = call.project_id(project_id);
+            call = call.project_id(project_id.clone());
         }
         if let Some(ref environment) = self.options.environment {
             call = call.environment(environment);
@@ -295,6 +319,9 @@ impl DiagnyxCallbackHandler {
         if let Some(ref user_identifier) = self.options.user_identifier {
             call = call.user_identifier(user_identifier);
         }
+        for record in self.take_tool_calls() {
+            call = call.tool_call(record);
+        }
 
         let client = Arc::clone(&self.client);
         let call = call.build();
@@ -303,6 +330,14 @@ impl DiagnyxCallbackHandler {
         });
     }
 
+    /// Forget a call that will never end, without tracking it.
+    #[cfg_attr(not(feature = "rig"), allow(dead_code))]
+    pub(crate) fn discard_run(&self, run_id: &str) {
+        if let Ok(mut contexts) = self.call_contexts.lock() {
+            contexts.remove(run_id);
+        }
+    }
+
     /// Called when a chain starts. No-op for cost tracking.
     pub fn on_chain_start(&self, _chain_name: &str, _inputs: &str) {
         // No-op for cost tracking
@@ -318,19 +353,44 @@ impl DiagnyxCallbackHandler {
         // No-op for cost tracking
     }
 
-    /// Called when a tool starts. No-op for cost tracking.
-    pub fn on_tool_start(&self, _tool_name: &str, _input: &str) {
-        // No-op for cost tracking
+    /// Called when a tool starts.
+    ///
+    /// Finished tool calls are recorded on the next LLM call that ends, which
+    /// is the call consuming their results.
+    pub fn on_tool_start(&self, tool_name: &str, input: &str) {
+        if let Ok(mut tools) = self.tools.lock() {
+            tools
+                .running
+                .push((tool_name.to_string(), input.len(), Instant::now()));
+        }
     }
 
-    /// Called when a tool ends. No-op for cost tracking.
+    /// Called when the most recently started tool ends.
     pub fn on_tool_end(&self, _output: &str) {
-        // No-op for cost tracking
+        self.finish_tool(CallStatus::Success);
     }
 
-    /// Called when a tool errors. No-op for cost tracking.
+    /// Called when the most recently started tool errors.
     pub fn on_tool_error(&self, _error: &str) {
-        // No-op for cost tracking
+        self.finish_tool(CallStatus::Error);
+    }
+
+    fn finish_tool(&self, status: CallStatus) {
+        if let Ok(mut tools) = self.tools.lock() {
+            if let Some((name, arguments_bytes, start_time)) = tools.running.pop() {
+                let duration_ms = start_time.elapsed().as_millis() as i64;
+                tools
+                    .finished
+                    .push(ToolCallRecord::new(name, arguments_bytes, duration_ms).status(status));
+            }
+        }
+    }
+
+    fn take_tool_calls(&self) -> Vec<ToolCallRecord> {
+        self.tools
+            .lock()
+            .map(|mut tools| std::mem::take(&mut tools.finished))
+            .unwrap_or_default()
     }
 }
 
@@ -386,13 +446,13 @@ mod tests {
     #[test]
     fn test_callback_options_builder() {
         let opts = CallbackOptions::new()
-            .project_id("my-project")
+            .project_id(ProjectId::from_static("my-project"))
             .environment("production")
             .user_identifier("user-123")
             .capture_content(true)
             .content_max_length(5000);
 
-        assert_eq!(opts.project_id, Some("my-project".to_string()));
+        assert_eq!(opts.project_id, Some(ProjectId::from_static("my-project")));
         assert_eq!(opts.environment, Some("production".to_string()));
         assert_eq!(opts.user_identifier, Some("user-123".to_string()));
         assert!(opts.capture_content);
@@ -416,13 +476,16 @@ mod tests {
             crate::DiagnyxConfig::new("test-key").base_url("http://localhost:9999"),
         ));
         let handler = DiagnyxCallbackHandler::new(client.clone())
-            .with_project_id("test-project")
+            .with_project_id(ProjectId::from_static("test-project"))
             .with_environment("test")
             .with_user_identifier("test-user")
             .with_capture_content(true)
             .with_content_max_length(5000);
 
-        assert_eq!(handler.options.project_id, Some("test-project".to_string()));
+        assert_eq!(
+            handler.options.project_id,
+            Some(ProjectId::from_static("test-project"))
+        );
         assert_eq!(handler.options.environment, Some("test".to_string()));
         assert_eq!(
             handler.options.user_identifier,
@@ -457,10 +520,27 @@ mod tests {
 
         let run_id = handler.on_llm_start("gpt-4", "Hello");
 
-        let contexts = handler.call_contexts.lock().unwrap();
-        assert!(contexts.contains_key(&run_id));
-        assert_eq!(contexts.get(&run_id).unwrap().model, "gpt-4");
-        drop(contexts);
+        {
+            let contexts = handler.call_contexts.lock().unwrap();
+            assert!(contexts.contains_key(&run_id));
+            assert_eq!(contexts.get(&run_id).unwrap().model, "gpt-4");
+        }
+        let _ = client.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_custom_run_id_generator() {
+        let client = Arc::new(DiagnyxClient::with_config(
+            crate::DiagnyxConfig::new("test-key").base_url("http://localhost:9999"),
+        ));
+        let counter = std::sync::atomic::AtomicUsize::new(0);
+        let handler = DiagnyxCallbackHandler::new(client.clone()).with_id_generator(move || {
+            let n = counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            format!("run-{}", n)
+        });
+
+        assert_eq!(handler.on_llm_start("gpt-4", "Hello"), "run-0");
+        assert_eq!(handler.on_llm_start("gpt-4", "Hello"), "run-1");
         let _ = client.shutdown().await;
     }
 
@@ -477,9 +557,10 @@ mod tests {
         // Give the spawned task a moment to complete
         tokio::time::sleep(std::time::Duration::from_millis(10)).await;
 
-        let contexts = handler.call_contexts.lock().unwrap();
-        assert!(!contexts.contains_key(&run_id));
-        drop(contexts);
+        {
+            let contexts = handler.call_contexts.lock().unwrap();
+            assert!(!contexts.contains_key(&run_id));
+        }
         let _ = client.shutdown().await;
     }
 
@@ -496,9 +577,10 @@ mod tests {
         // Give the spawned task a moment to complete
         tokio::time::sleep(std::time::Duration::from_millis(10)).await;
 
-        let contexts = handler.call_contexts.lock().unwrap();
-        assert!(!contexts.contains_key(&run_id));
-        drop(contexts);
+        {
+            let contexts = handler.call_contexts.lock().unwrap();
+            assert!(!contexts.contains_key(&run_id));
+        }
         let _ = client.shutdown().await;
     }
 
@@ -517,17 +599,43 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_tool_callbacks_are_no_ops() {
+    async fn test_tool_calls_are_recorded_on_next_llm_call() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
         let client = Arc::new(DiagnyxClient::with_config(
-            crate::DiagnyxConfig::new("test-key").base_url("http://localhost:9999"),
+            crate::DiagnyxConfig::new("test-key").base_url(server.uri()),
         ));
         let handler = DiagnyxCallbackHandler::new(client.clone());
 
-        // These should not panic
-        handler.on_tool_start("test-tool", "input");
+        handler.on_tool_start("search", "{\"q\":\"rust\"}");
         handler.on_tool_end("output");
+        handler.on_tool_start("fetch", "url");
         handler.on_tool_error("error");
-        let _ = client.shutdown().await;
+        // Ending a tool that was not started is ignored
+        handler.on_tool_end("output");
+
+        let run_id = handler.on_llm_start("gpt-4", "Answer");
+        handler.on_llm_end(&run_id, "gpt-4", "Done", 10, 5);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        client.flush().await.unwrap();
+
+        let body: serde_json::Value = server.received_requests().await.unwrap()[0]
+            .body_json()
+            .unwrap();
+        let tool_calls = &body["calls"][0]["tool_calls"];
+        assert_eq!(tool_calls.as_array().unwrap().len(), 2);
+        assert_eq!(tool_calls[0]["name"], "search");
+        assert_eq!(tool_calls[0]["arguments_bytes"], 12);
+        assert_eq!(tool_calls[0]["status"], "success");
+        assert_eq!(tool_calls[1]["status"], "error");
+        assert!(handler.take_tool_calls().is_empty());
     }
 
     #[tokio::test]
@@ -548,9 +656,10 @@ mod tests {
         // Give the spawned tasks a moment to complete
         tokio::time::sleep(std::time::Duration::from_millis(10)).await;
 
-        let contexts = handler.call_contexts.lock().unwrap();
-        assert!(contexts.is_empty());
-        drop(contexts);
+        {
+            let contexts = handler.call_contexts.lock().unwrap();
+            assert!(contexts.is_empty());
+        }
         let _ = client.shutdown().await;
     }
 }
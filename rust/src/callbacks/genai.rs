@@ -0,0 +1,333 @@
+//! Adapter for the [genai](https://docs.rs/genai) client.
+//!
+//! genai has no callback interface, so [`InstrumentedClient`] wraps a
+//! `genai::Client` and reports each chat through a
+//! [`DiagnyxCallbackHandler`]: the model and token usage come from the
+//! response, and failed requests are tracked with an error status.
+//! Streamed chats are tracked when their stream ends, with the usage genai
+//! captures at the end of the stream.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::callbacks::genai::InstrumentedClient;
+//! use diagnyx::callbacks::DiagnyxCallbackHandler;
+//! use diagnyx::DiagnyxClient;
+//! use genai::chat::{ChatMessage, ChatRequest};
+//! use std::sync::Arc;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let diagnyx = Arc::new(DiagnyxClient::new("dx_live_your_api_key"));
+//!     let client = InstrumentedClient::new(
+//!         genai::Client::default(),
+//!         DiagnyxCallbackHandler::new(diagnyx.clone()),
+//!     );
+//!
+//!     let request = ChatRequest::new(vec![ChatMessage::user("Hello!")]);
+//!     let response = client.exec_chat("gpt-4o-mini", request, None).await?;
+//!     println!("{:?}", response.content_text_as_str());
+//!
+//!     diagnyx.flush().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use futures::Stream;
+use genai::chat::{ChatOptions, ChatRequest, ChatResponse, ChatStream, ChatStreamEvent};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::DiagnyxCallbackHandler;
+
+/// A genai client that tracks every chat with Diagnyx.
+pub struct InstrumentedClient {
+    inner: genai::Client,
+    handler: Arc<DiagnyxCallbackHandler>,
+}
+
+impl InstrumentedClient {
+    /// Wrap `inner`, tracking its chats with `handler`.
+    pub fn new(inner: genai::Client, handler: DiagnyxCallbackHandler) -> Self {
+        Self {
+            inner,
+            handler: Arc::new(handler),
+        }
+    }
+
+    /// The wrapped client, for requests that are not tracked.
+    pub fn inner(&self) -> &genai::Client {
+        &self.inner
+    }
+
+    /// Execute a chat request.
+    pub async fn exec_chat(
+        &self,
+        model: &str,
+        request: ChatRequest,
+        options: Option<&ChatOptions>,
+    ) -> genai::Result<ChatResponse> {
+        let run_id = self.handler.on_llm_start(model, &request_text(&request));
+        let result = self.inner.exec_chat(model, request, options).await;
+
+        match &result {
+            Ok(response) => self.handler.on_llm_end(
+                &run_id,
+                &response.provider_model_iden.model_name.to_string(),
+                response.content_text_as_str().unwrap_or_default(),
+                response.usage.prompt_tokens.unwrap_or(0),
+                response.usage.completion_tokens.unwrap_or(0),
+            ),
+            Err(e) => self.handler.on_llm_error(&run_id, model, &e.to_string()),
+        }
+        result
+    }
+
+    /// Execute a chat request, streaming the response.
+    ///
+    /// The chat is tracked when the stream ends. Usage is captured unless
+    /// `options` turn `capture_usage` off. A stream dropped before its end
+    /// is tracked as an error.
+    pub async fn exec_chat_stream(
+        &self,
+        model: &str,
+        request: ChatRequest,
+        options: Option<&ChatOptions>,
+    ) -> genai::Result<InstrumentedChatStream> {
+        let mut options = options.cloned().unwrap_or_default();
+        options.capture_usage.get_or_insert(true);
+
+        let run_id = self.handler.on_llm_start(model, &request_text(&request));
+        match self
+            .inner
+            .exec_chat_stream(model, request, Some(&options))
+            .await
+        {
+            Ok(response) => Ok(InstrumentedChatStream {
+                inner: response.stream,
+                handler: Arc::clone(&self.handler),
+                run: Some(run_id),
+                model: response.model_iden.model_name.to_string(),
+                text: String::new(),
+            }),
+            Err(e) => {
+                self.handler.on_llm_error(&run_id, model, &e.to_string());
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The events of a streamed chat, tracked when the stream ends.
+pub struct InstrumentedChatStream {
+    inner: ChatStream,
+    handler: Arc<DiagnyxCallbackHandler>,
+    /// Run of the chat, until it is reported.
+    run: Option<String>,
+    model: String,
+    /// Text streamed so far.
+    text: String,
+}
+
+impl Stream for InstrumentedChatStream {
+    type Item = genai::Result<ChatStreamEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let item = futures::ready!(Pin::new(&mut this.inner).poll_next(cx));
+        match &item {
+            Some(Ok(ChatStreamEvent::Chunk(chunk))) => this.text.push_str(&chunk.content),
+            Some(Ok(ChatStreamEvent::End(end))) => {
+                if let Some(run_id) = this.run.take() {
+                    let usage = end.captured_usage.clone().unwrap_or_default();
+                    this.handler.on_llm_end(
+                        &run_id,
+                        &this.model,
+                        &this.text,
+                        usage.prompt_tokens.unwrap_or(0),
+                        usage.completion_tokens.unwrap_or(0),
+                    );
+                }
+            }
+            Some(Err(e)) => {
+                if let Some(run_id) = this.run.take() {
+                    this.handler
+                        .on_llm_error(&run_id, &this.model, &e.to_string());
+                }
+            }
+            _ => {}
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl Drop for InstrumentedChatStream {
+    fn drop(&mut self) {
+        if let Some(run_id) = self.run.take() {
+            self.handler
+                .on_llm_error(&run_id, &self.model, "stream dropped before it ended");
+        }
+    }
+}
+
+/// The system prompt and text messages of a request, one per line.
+fn request_text(request: &ChatRequest) -> String {
+    request
+        .system
+        .as_deref()
+        .into_iter()
+        .chain(
+            request
+                .messages
+                .iter()
+                .filter_map(|message| message.content.text_as_str()),
+        )
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DiagnyxClient, DiagnyxConfig};
+    use genai::chat::ChatMessage;
+    use genai::resolver::{AuthData, Endpoint};
+    use genai::ServiceTarget;
+    use std::sync::Arc;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn clients(server: &MockServer) -> (InstrumentedClient, Arc<DiagnyxClient>) {
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(server)
+            .await;
+        let diagnyx = Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key").base_url(server.uri()),
+        ));
+
+        let endpoint = format!("{}/v1/", server.uri());
+        let genai = genai::Client::builder()
+            .with_service_target_resolver_fn(move |target: ServiceTarget| {
+                Ok(ServiceTarget {
+                    endpoint: Endpoint::from_owned(endpoint.clone()),
+                    auth: AuthData::from_single("sk-test"),
+                    model: target.model,
+                })
+            })
+            .build();
+        let handler = DiagnyxCallbackHandler::new(Arc::clone(&diagnyx));
+        (InstrumentedClient::new(genai, handler), diagnyx)
+    }
+
+    async fn tracked(server: &MockServer, diagnyx: &DiagnyxClient) -> serde_json::Value {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        diagnyx.flush().await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        let ingest = requests
+            .iter()
+            .find(|r| r.url.path() == "/api/v1/ingest/llm/batch")
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&ingest.body).unwrap();
+        body["calls"][0].clone()
+    }
+
+    #[tokio::test]
+    async fn test_exec_chat_tracks_usage() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1700000000,
+                "model": "gpt-4o-mini-2024-07-18",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Hi!"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 9, "completion_tokens": 3, "total_tokens": 12}
+            })))
+            .mount(&server)
+            .await;
+        let (client, diagnyx) = clients(&server).await;
+
+        let request = ChatRequest::new(vec![ChatMessage::user("Hello")]);
+        let response = client
+            .exec_chat("gpt-4o-mini", request, None)
+            .await
+            .unwrap();
+        assert_eq!(response.content_text_as_str(), Some("Hi!"));
+
+        let call = tracked(&server, &diagnyx).await;
+        assert_eq!(call["provider"], "openai");
+        assert_eq!(call["model"], "gpt-4o-mini-2024-07-18");
+        assert_eq!(call["input_tokens"], 9);
+        assert_eq!(call["output_tokens"], 3);
+        assert_eq!(call["status"], "success");
+    }
+
+    #[tokio::test]
+    async fn test_exec_chat_stream_tracks_usage_at_the_end() {
+        let server = MockServer::start().await;
+        let body = [
+            r#"{"id":"c","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{"role":"assistant","content":"Hi"},"finish_reason":null}]}"#,
+            r#"{"id":"c","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{"content":"!"},"finish_reason":null}]}"#,
+            r#"{"id":"c","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+            r#"{"id":"c","object":"chat.completion.chunk","created":1,"model":"gpt-4o-mini","choices":[],"usage":{"prompt_tokens":9,"completion_tokens":3,"total_tokens":12}}"#,
+            "[DONE]",
+        ]
+        .iter()
+        .map(|data| format!("data: {}\n\n", data))
+        .collect::<String>();
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+        let (client, diagnyx) = clients(&server).await;
+
+        let request = ChatRequest::new(vec![ChatMessage::user("Hello")]);
+        let mut stream = client
+            .exec_chat_stream("gpt-4o-mini", request, None)
+            .await
+            .unwrap();
+        let mut text = String::new();
+        while let Some(event) = futures::StreamExt::next(&mut stream).await {
+            if let ChatStreamEvent::Chunk(chunk) = event.unwrap() {
+                text.push_str(&chunk.content);
+            }
+        }
+        assert_eq!(text, "Hi!");
+
+        let call = tracked(&server, &diagnyx).await;
+        assert_eq!(call["model"], "gpt-4o-mini");
+        assert_eq!(call["input_tokens"], 9);
+        assert_eq!(call["output_tokens"], 3);
+        assert_eq!(call["status"], "success");
+    }
+
+    #[tokio::test]
+    async fn test_exec_chat_tracks_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let (client, diagnyx) = clients(&server).await;
+
+        let request = ChatRequest::new(vec![ChatMessage::user("Hello")]);
+        assert!(client
+            .exec_chat("gpt-4o-mini", request, None)
+            .await
+            .is_err());
+
+        let call = tracked(&server, &diagnyx).await;
+        assert_eq!(call["model"], "gpt-4o-mini");
+        assert_eq!(call["status"], "error");
+    }
+}
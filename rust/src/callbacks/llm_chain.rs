@@ -0,0 +1,383 @@
+//! Adapter for [llm-chain](https://docs.rs/llm-chain) executors.
+//!
+//! llm-chain has no callback interface, so [`TrackedExecutor`] wraps an
+//! executor and reports each prompt it executes through a
+//! [`DiagnyxCallbackHandler`]. It is an executor itself, so steps and chains
+//! run on it in place of the executor it wraps.
+//!
+//! llm-chain reports no token usage: tokens are counted with the wrapped
+//! executor's tokenizer, and streamed responses count one output token per
+//! streamed segment. The model is read from the options of each call, or
+//! else is the one given to the wrapper.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use diagnyx::callbacks::llm_chain::TrackedExecutor;
+//! use diagnyx::callbacks::DiagnyxCallbackHandler;
+//! use diagnyx::DiagnyxClient;
+//! use llm_chain::{parameters, prompt};
+//! use std::sync::Arc;
+//!
+//! let client = Arc::new(DiagnyxClient::new("dx_live_xxx"));
+//! let exec = TrackedExecutor::wrap(
+//!     llm_chain_openai::chatgpt::Executor::new()?,
+//!     DiagnyxCallbackHandler::new(client),
+//!     "gpt-3.5-turbo",
+//! );
+//!
+//! let res = prompt!("Say hello").run(&parameters!(), &exec).await?;
+//! ```
+
+use futures::Stream;
+use llm_chain::options::{Opt, OptDiscriminants, Options};
+use llm_chain::output::{Output, OutputStream, StreamSegment};
+use llm_chain::prompt::Prompt;
+use llm_chain::tokens::{PromptTokensError, TokenCount, Tokenizer, TokenizerError};
+use llm_chain::traits::{Executor, ExecutorCreationError, ExecutorError};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use super::DiagnyxCallbackHandler;
+
+/// An llm-chain executor that tracks every prompt it executes with Diagnyx.
+pub struct TrackedExecutor<E> {
+    inner: E,
+    handler: Arc<DiagnyxCallbackHandler>,
+    model: String,
+}
+
+impl<E: Executor> TrackedExecutor<E> {
+    /// Wrap `inner`, tracking its prompts with `handler` under `model`
+    /// unless the options of a call name another.
+    pub fn wrap(inner: E, handler: DiagnyxCallbackHandler, model: impl Into<String>) -> Self {
+        Self {
+            inner,
+            handler: Arc::new(handler),
+            model: model.into(),
+        }
+    }
+
+    /// The wrapped executor, for prompts that are not tracked.
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// The model named by `options`, or the wrapper's.
+    fn model(&self, options: &Options) -> String {
+        match options.get(OptDiscriminants::Model) {
+            Some(Opt::Model(model)) => model.to_name(),
+            _ => self.model.clone(),
+        }
+    }
+
+    /// Tokens in `text` for the model of `options`, or 0 if the executor
+    /// cannot count them.
+    fn count_tokens(&self, options: &Options, text: &str) -> i32 {
+        self.inner
+            .get_tokenizer(options)
+            .and_then(|tokenizer| tokenizer.tokenize_str(text))
+            .map_or(0, |tokens| tokens.len() as i32)
+    }
+}
+
+#[async_trait::async_trait]
+impl<E> Executor for TrackedExecutor<E>
+where
+    E: Executor + Send + Sync,
+{
+    type StepTokenizer<'a>
+        = E::StepTokenizer<'a>
+    where
+        Self: 'a;
+
+    /// Create the wrapped executor with `options`, tracking its prompts
+    /// with the process-wide client set up by [`crate::init`].
+    fn new_with_options(options: Options) -> Result<Self, ExecutorCreationError> {
+        let client = crate::global().ok_or_else(|| {
+            ExecutorCreationError::FieldRequiredError("diagnyx::init".to_string())
+        })?;
+        let model = match options.get(OptDiscriminants::Model) {
+            Some(Opt::Model(model)) => model.to_name(),
+            _ => "unknown".to_string(),
+        };
+        Ok(Self::wrap(
+            E::new_with_options(options)?,
+            DiagnyxCallbackHandler::new(client),
+            model,
+        ))
+    }
+
+    async fn execute(&self, options: &Options, prompt: &Prompt) -> Result<Output, ExecutorError> {
+        let model = self.model(options);
+        let text = prompt.to_text();
+        let run_id = self.handler.on_llm_start(&model, &text);
+        let input_tokens = self.count_tokens(options, &text);
+
+        match self.inner.execute(options, prompt).await {
+            Ok(Output::Immediate(immediate)) => {
+                let response = immediate.get_content().to_text();
+                let output_tokens = self.count_tokens(options, &response);
+                self.handler
+                    .on_llm_end(&run_id, &model, &response, input_tokens, output_tokens);
+                Ok(Output::new_immediate(immediate.as_content()))
+            }
+            Ok(Output::Stream(stream)) => Ok(Output::from_stream(TrackedStream {
+                inner: stream,
+                handler: Arc::clone(&self.handler),
+                run: Some(run_id),
+                model,
+                text: String::new(),
+                input_tokens,
+                segments: 0,
+            })),
+            Err(e) => {
+                self.handler.on_llm_error(&run_id, &model, &e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    fn tokens_used(
+        &self,
+        options: &Options,
+        prompt: &Prompt,
+    ) -> Result<TokenCount, PromptTokensError> {
+        self.inner.tokens_used(options, prompt)
+    }
+
+    fn max_tokens_allowed(&self, options: &Options) -> i32 {
+        self.inner.max_tokens_allowed(options)
+    }
+
+    fn answer_prefix(&self, prompt: &Prompt) -> Option<String> {
+        self.inner.answer_prefix(prompt)
+    }
+
+    fn get_tokenizer(&self, options: &Options) -> Result<Self::StepTokenizer<'_>, TokenizerError> {
+        self.inner.get_tokenizer(options)
+    }
+}
+
+/// The segments of a streamed response, tracked when the stream ends.
+struct TrackedStream {
+    inner: OutputStream,
+    handler: Arc<DiagnyxCallbackHandler>,
+    /// Run of the prompt, until it is reported.
+    run: Option<String>,
+    model: String,
+    /// Text streamed so far.
+    text: String,
+    input_tokens: i32,
+    /// Content segments streamed so far.
+    segments: i32,
+}
+
+impl Stream for TrackedStream {
+    type Item = StreamSegment;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let item = futures::ready!(Pin::new(&mut this.inner).poll_next(cx));
+        match &item {
+            Some(StreamSegment::Content(content)) => {
+                this.text.push_str(content);
+                this.segments += 1;
+            }
+            Some(StreamSegment::Err(e)) => {
+                if let Some(run_id) = this.run.take() {
+                    this.handler
+                        .on_llm_error(&run_id, &this.model, &e.to_string());
+                }
+            }
+            Some(StreamSegment::Role(_)) => {}
+            None => {
+                if let Some(run_id) = this.run.take() {
+                    this.handler.on_llm_end(
+                        &run_id,
+                        &this.model,
+                        &this.text,
+                        this.input_tokens,
+                        this.segments,
+                    );
+                }
+            }
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl Drop for TrackedStream {
+    fn drop(&mut self) {
+        if let Some(run_id) = self.run.take() {
+            self.handler
+                .on_llm_error(&run_id, &self.model, "stream dropped before it ended");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DiagnyxClient, DiagnyxConfig};
+    use llm_chain::options::ModelRef;
+    use llm_chain::prompt::Data;
+    use llm_chain::tokens::TokenCollection;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Counts one token per word.
+    struct Words;
+
+    impl Tokenizer for Words {
+        fn tokenize_str(&self, doc: &str) -> Result<TokenCollection, TokenizerError> {
+            Ok(vec![0; doc.split_whitespace().count()].into())
+        }
+
+        fn to_string(&self, _tokens: TokenCollection) -> Result<String, TokenizerError> {
+            Err(TokenizerError::ToStringError)
+        }
+    }
+
+    /// Answers every prompt with a fixed reply, streamed if `stream`.
+    struct Reply {
+        stream: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl Executor for Reply {
+        type StepTokenizer<'a> = Words;
+
+        fn new_with_options(_options: Options) -> Result<Self, ExecutorCreationError> {
+            Ok(Self { stream: false })
+        }
+
+        async fn execute(
+            &self,
+            _options: &Options,
+            prompt: &Prompt,
+        ) -> Result<Output, ExecutorError> {
+            if prompt.to_text() == "fail" {
+                return Err(ExecutorError::InvalidOptions);
+            }
+            if !self.stream {
+                return Ok(Output::new_immediate(Data::text(
+                    "Hi there Ada".to_string(),
+                )));
+            }
+            let (sender, output) = Output::new_stream();
+            for segment in ["Hi", " there"] {
+                sender
+                    .send(StreamSegment::Content(segment.to_string()))
+                    .unwrap();
+            }
+            Ok(output)
+        }
+
+        fn tokens_used(
+            &self,
+            _options: &Options,
+            _prompt: &Prompt,
+        ) -> Result<TokenCount, PromptTokensError> {
+            Err(PromptTokensError::NotAvailable)
+        }
+
+        fn max_tokens_allowed(&self, _options: &Options) -> i32 {
+            4096
+        }
+
+        fn answer_prefix(&self, _prompt: &Prompt) -> Option<String> {
+            None
+        }
+
+        fn get_tokenizer(&self, _options: &Options) -> Result<Words, TokenizerError> {
+            Ok(Words)
+        }
+    }
+
+    async fn executor(
+        server: &MockServer,
+        stream: bool,
+    ) -> (TrackedExecutor<Reply>, Arc<DiagnyxClient>) {
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(server)
+            .await;
+        let diagnyx = Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key").base_url(server.uri()),
+        ));
+        let handler = DiagnyxCallbackHandler::new(Arc::clone(&diagnyx));
+        (
+            TrackedExecutor::wrap(Reply { stream }, handler, "gpt-4o-mini"),
+            diagnyx,
+        )
+    }
+
+    async fn tracked(server: &MockServer, diagnyx: &DiagnyxClient) -> serde_json::Value {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        diagnyx.flush().await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        body["calls"][0].clone()
+    }
+
+    #[tokio::test]
+    async fn test_execute_tracks_counted_tokens() {
+        let server = MockServer::start().await;
+        let (exec, diagnyx) = executor(&server, false).await;
+
+        let output = exec
+            .execute(
+                Options::empty(),
+                &Data::text("Say hello to Ada".to_string()),
+            )
+            .await
+            .unwrap();
+        let immediate = output.to_immediate().await.unwrap();
+        assert_eq!(immediate.get_content().to_text(), "Hi there Ada");
+
+        let call = tracked(&server, &diagnyx).await;
+        assert_eq!(call["model"], "gpt-4o-mini");
+        assert_eq!(call["input_tokens"], 4);
+        assert_eq!(call["output_tokens"], 3);
+        assert_eq!(call["status"], "success");
+    }
+
+    #[tokio::test]
+    async fn test_execute_stream_tracks_the_model_of_the_options_at_the_end() {
+        let server = MockServer::start().await;
+        let (exec, diagnyx) = executor(&server, true).await;
+        let mut options = Options::builder();
+        options.add_option(Opt::Model(ModelRef::from_model_name("gpt-4o")));
+
+        let output = exec
+            .execute(&options.build(), &Data::text("Say hello".to_string()))
+            .await
+            .unwrap();
+        let immediate = output.to_immediate().await.unwrap();
+        assert_eq!(immediate.get_content().to_text(), "Hi there");
+
+        let call = tracked(&server, &diagnyx).await;
+        assert_eq!(call["model"], "gpt-4o");
+        assert_eq!(call["input_tokens"], 2);
+        assert_eq!(call["output_tokens"], 2);
+        assert_eq!(call["status"], "success");
+    }
+
+    #[tokio::test]
+    async fn test_execute_tracks_errors() {
+        let server = MockServer::start().await;
+        let (exec, diagnyx) = executor(&server, false).await;
+
+        assert!(exec
+            .execute(Options::empty(), &Data::text("fail".to_string()))
+            .await
+            .is_err());
+
+        let call = tracked(&server, &diagnyx).await;
+        assert_eq!(call["model"], "gpt-4o-mini");
+        assert_eq!(call["status"], "error");
+    }
+}
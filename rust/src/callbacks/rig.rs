@@ -0,0 +1,281 @@
+//! Adapter for [rig](https://docs.rs/rig-core) agents.
+//!
+//! [`DiagnyxHook`] implements rig's `PromptHook`, so an agent's completions
+//! and tool calls are tracked through a [`DiagnyxCallbackHandler`] by adding
+//! the hook to a prompt request.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use diagnyx::callbacks::rig::DiagnyxHook;
+//! use diagnyx::callbacks::DiagnyxCallbackHandler;
+//! use diagnyx::DiagnyxClient;
+//! use rig::completion::Prompt;
+//! use rig::prelude::*;
+//! use std::sync::Arc;
+//!
+//! let client = Arc::new(DiagnyxClient::new("dx_live_xxx"));
+//! let hook = DiagnyxHook::new(DiagnyxCallbackHandler::new(client), "gpt-4o");
+//!
+//! let agent = rig::providers::openai::Client::from_env().agent("gpt-4o").build();
+//! let answer = agent.prompt("Hello!").with_hook(hook).await?;
+//! ```
+
+use rig::completion::{CompletionModel, CompletionResponse, GetTokenUsage, Usage};
+use rig::message::{AssistantContent, Message, UserContent};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use super::DiagnyxCallbackHandler;
+
+/// Runs in progress, by prompt text, oldest first.
+///
+/// rig passes the prompt to both the call and the response hooks, so keying
+/// runs by prompt keeps completions apart when clones of a hook serve
+/// concurrent requests.
+struct Runs {
+    handler: Arc<DiagnyxCallbackHandler>,
+    by_prompt: Mutex<HashMap<String, VecDeque<String>>>,
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        // Completions that failed never report a response
+        let Ok(runs) = self.by_prompt.get_mut() else {
+            return;
+        };
+        for run_id in runs.drain().flat_map(|(_, runs)| runs) {
+            self.handler.discard_run(&run_id);
+        }
+    }
+}
+
+/// A rig `PromptHook` that tracks completions and tool calls.
+///
+/// Tool calls are recorded on the completion that consumes their results.
+/// Clones share their runs, which are matched to responses by prompt.
+#[derive(Clone)]
+pub struct DiagnyxHook {
+    handler: Arc<DiagnyxCallbackHandler>,
+    model: String,
+    runs: Arc<Runs>,
+}
+
+impl DiagnyxHook {
+    /// Track the completions of an agent using `model` with `handler`.
+    ///
+    /// rig responses do not name the model, so the calls are recorded with
+    /// the one given here.
+    pub fn new(handler: DiagnyxCallbackHandler, model: impl Into<String>) -> Self {
+        let handler = Arc::new(handler);
+        Self {
+            runs: Arc::new(Runs {
+                handler: Arc::clone(&handler),
+                by_prompt: Mutex::new(HashMap::new()),
+            }),
+            handler,
+            model: model.into(),
+        }
+    }
+
+    fn start(&self, prompt: &Message) {
+        let prompt = message_text(prompt);
+        let run_id = self.handler.on_llm_start(&self.model, &prompt);
+        if let Ok(mut runs) = self.runs.by_prompt.lock() {
+            runs.entry(prompt).or_default().push_back(run_id);
+        }
+    }
+
+    fn end(&self, prompt: &Message, response: &str, usage: Option<Usage>) {
+        let prompt = message_text(prompt);
+        let run_id = self.runs.by_prompt.lock().ok().and_then(|mut runs| {
+            let queued = runs.get_mut(&prompt)?;
+            let run_id = queued.pop_front();
+            if queued.is_empty() {
+                runs.remove(&prompt);
+            }
+            run_id
+        });
+        let Some(run_id) = run_id else {
+            return;
+        };
+        let usage = usage.unwrap_or_default();
+        self.handler.on_llm_end(
+            &run_id,
+            &self.model,
+            response,
+            usage.input_tokens as i32,
+            usage.output_tokens as i32,
+        );
+    }
+}
+
+impl<M: CompletionModel> rig::agent::PromptHook<M> for DiagnyxHook {
+    async fn on_completion_call(&self, prompt: &Message, _history: &[Message]) {
+        self.start(prompt);
+    }
+
+    async fn on_completion_response(
+        &self,
+        prompt: &Message,
+        response: &CompletionResponse<M::Response>,
+    ) {
+        let text = response
+            .choice
+            .iter()
+            .filter_map(|content| match content {
+                AssistantContent::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.end(prompt, &text, Some(response.usage));
+    }
+
+    async fn on_stream_completion_response_finish(
+        &self,
+        prompt: &Message,
+        response: &M::StreamingResponse,
+    ) {
+        self.end(prompt, "", response.token_usage());
+    }
+
+    async fn on_tool_call(&self, tool_name: &str, args: &str) {
+        self.handler.on_tool_start(tool_name, args);
+    }
+
+    async fn on_tool_result(&self, _tool_name: &str, _args: &str, result: &str) {
+        self.handler.on_tool_end(result);
+    }
+}
+
+/// The text parts of a message, one per line.
+fn message_text(message: &Message) -> String {
+    let parts: Vec<&str> = match message {
+        Message::User { content } => content
+            .iter()
+            .filter_map(|content| match content {
+                UserContent::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect(),
+        Message::Assistant { content, .. } => content
+            .iter()
+            .filter_map(|content| match content {
+                AssistantContent::Text(text) => Some(text.text.as_str()),
+                _ => None,
+            })
+            .collect(),
+    };
+    parts.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DiagnyxClient, DiagnyxConfig};
+    use rig::completion::{CompletionError, CompletionRequest, Prompt};
+    use rig::streaming::StreamingCompletionResponse;
+    use rig::OneOrMany;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A model that answers every prompt with "Hi!".
+    #[derive(Clone)]
+    struct Echo;
+
+    impl CompletionModel for Echo {
+        type Response = ();
+        type StreamingResponse = ();
+
+        async fn completion(
+            &self,
+            _request: CompletionRequest,
+        ) -> Result<CompletionResponse<()>, CompletionError> {
+            Ok(CompletionResponse {
+                choice: OneOrMany::one(AssistantContent::text("Hi!")),
+                usage: Usage {
+                    input_tokens: 7,
+                    output_tokens: 2,
+                    total_tokens: 9,
+                },
+                raw_response: (),
+            })
+        }
+
+        async fn stream(
+            &self,
+            _request: CompletionRequest,
+        ) -> Result<StreamingCompletionResponse<()>, CompletionError> {
+            Err(CompletionError::ProviderError("not streamed".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hook_tracks_agent_completions() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let client = Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key").base_url(server.uri()),
+        ));
+        let hook = DiagnyxHook::new(
+            DiagnyxCallbackHandler::new(Arc::clone(&client)).with_capture_content(true),
+            "gpt-4o",
+        );
+
+        let agent = rig::agent::AgentBuilder::new(Echo).build();
+        let answer = agent.prompt("Hello").with_hook(hook).await.unwrap();
+        assert_eq!(answer, "Hi!");
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        client.flush().await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let call = &body["calls"][0];
+        assert_eq!(call["model"], "gpt-4o");
+        assert_eq!(call["input_tokens"], 7);
+        assert_eq!(call["output_tokens"], 2);
+        assert_eq!(call["full_prompt"], "Hello");
+        assert_eq!(call["full_response"], "Hi!");
+    }
+
+    #[tokio::test]
+    async fn test_clones_keep_concurrent_runs_apart() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let client = Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key").base_url(server.uri()),
+        ));
+        let hook = DiagnyxHook::new(
+            DiagnyxCallbackHandler::new(Arc::clone(&client)).with_capture_content(true),
+            "gpt-4o",
+        );
+        let other = hook.clone();
+
+        hook.start(&Message::user("First"));
+        other.start(&Message::user("Second"));
+        other.end(&Message::user("Second"), "Two", None);
+        hook.end(&Message::user("First"), "One", None);
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        client.flush().await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let pairs: Vec<_> = body["calls"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|call| (call["full_prompt"].clone(), call["full_response"].clone()))
+            .collect();
+        assert!(pairs.contains(&("First".into(), "One".into())));
+        assert!(pairs.contains(&("Second".into(), "Two".into())));
+    }
+}
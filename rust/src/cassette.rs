@@ -0,0 +1,388 @@
+//! Record-and-replay of provider HTTP interactions for tests.
+//!
+//! A [`Cassette`] records the requests an instrumented call makes to an LLM
+//! provider, with credentials stripped, and replays them on later runs. The
+//! recorded latency is replayed as well, so calls tracked from replayed
+//! responses are identical between runs.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::cassette::{Cassette, CassetteMode};
+//! use diagnyx::{DiagnyxClient, LLMCall, Provider};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let cassette = Cassette::open("tests/cassettes/chat.json", CassetteMode::Auto)?;
+//!     let http = reqwest::Client::new();
+//!     let client = DiagnyxClient::new("dx_test_key");
+//!
+//!     let request = http
+//!         .post("https://api.openai.com/v1/chat/completions")
+//!         .bearer_auth(std::env::var("OPENAI_API_KEY").unwrap_or_default())
+//!         .json(&serde_json::json!({"model": "gpt-4o-mini", "messages": []}))
+//!         .build()?;
+//!     let response = cassette.execute(&http, request).await?;
+//!
+//!     client
+//!         .track(
+//!             LLMCall::builder()
+//!                 .provider(Provider::OpenAI)
+//!                 .model("gpt-4o-mini")
+//!                 .latency_ms(response.latency_ms)
+//!                 .build(),
+//!         )
+//!         .await;
+//!
+//!     cassette.save()?;
+//!     Ok(())
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::error::DiagnyxError;
+
+/// Placeholder written in place of stripped secrets.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Headers whose values are never written to a cassette.
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "x-api-key",
+    "api-key",
+    "x-goog-api-key",
+    "cookie",
+    "set-cookie",
+];
+
+/// Query parameters whose values are never written to a cassette.
+const SECRET_QUERY_PARAMS: &[&str] = &["key", "api_key", "apikey", "access_token"];
+
+/// How a cassette handles requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    /// Always perform requests and record them.
+    Record,
+    /// Never perform requests; fail if no recorded interaction matches.
+    Replay,
+    /// Replay if the cassette file exists, otherwise record.
+    Auto,
+}
+
+/// A recorded request, with secrets stripped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+impl RecordedRequest {
+    fn from_request(request: &reqwest::Request) -> Self {
+        let headers = request
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                let name = name.as_str().to_lowercase();
+                let value = if SECRET_HEADERS.contains(&name.as_str()) {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name, value)
+            })
+            .collect();
+
+        let body = request
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| String::from_utf8_lossy(b).into_owned());
+
+        Self {
+            method: request.method().to_string(),
+            url: redact_url(request.url()),
+            headers,
+            body,
+        }
+    }
+
+    fn matches(&self, other: &RecordedRequest) -> bool {
+        self.method == other.method && self.url == other.url && self.body == other.body
+    }
+}
+
+/// A recorded response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    /// Latency observed when the interaction was recorded.
+    pub latency_ms: i64,
+}
+
+impl RecordedResponse {
+    /// Deserialize the response body as JSON.
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, DiagnyxError> {
+        Ok(serde_json::from_str(&self.body)?)
+    }
+}
+
+/// A request/response pair.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Default)]
+struct CassetteState {
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+}
+
+/// A set of recorded provider interactions backed by a JSON file.
+#[derive(Debug)]
+pub struct Cassette {
+    path: PathBuf,
+    recording: bool,
+    state: Mutex<CassetteState>,
+}
+
+impl Cassette {
+    /// Open a cassette file in the given mode.
+    ///
+    /// In `Replay` mode the file must exist. In `Record` mode any existing
+    /// interactions are discarded when the cassette is saved.
+    pub fn open(path: impl AsRef<Path>, mode: CassetteMode) -> Result<Self, DiagnyxError> {
+        let path = path.as_ref().to_path_buf();
+        let recording = match mode {
+            CassetteMode::Record => true,
+            CassetteMode::Replay => false,
+            CassetteMode::Auto => !path.exists(),
+        };
+
+        let interactions = if recording {
+            Vec::new()
+        } else {
+            let contents = std::fs::read_to_string(&path)?;
+            serde_json::from_str::<CassetteFile>(&contents)?.interactions
+        };
+
+        Ok(Self {
+            path,
+            recording,
+            state: Mutex::new(CassetteState {
+                used: vec![false; interactions.len()],
+                interactions,
+            }),
+        })
+    }
+
+    /// Whether requests are performed and recorded rather than replayed.
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Execute a request, recording or replaying it.
+    ///
+    /// When replaying, interactions are matched on method, URL and body, and
+    /// each recorded interaction is used at most once, in recorded order.
+    pub async fn execute(
+        &self,
+        http_client: &reqwest::Client,
+        request: reqwest::Request,
+    ) -> Result<RecordedResponse, DiagnyxError> {
+        let recorded_request = RecordedRequest::from_request(&request);
+
+        if !self.recording {
+            return self.replay(&recorded_request);
+        }
+
+        let start = Instant::now();
+        let response = http_client.execute(request).await?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !SECRET_HEADERS.contains(&name.as_str()))
+            .map(|(name, value)| {
+                (
+                    name.as_str().to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+        let body = response.text().await?;
+
+        let recorded_response = RecordedResponse {
+            status,
+            headers,
+            body,
+            latency_ms: start.elapsed().as_millis() as i64,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state.interactions.push(Interaction {
+            request: recorded_request,
+            response: recorded_response.clone(),
+        });
+        state.used.push(true);
+
+        Ok(recorded_response)
+    }
+
+    fn replay(&self, request: &RecordedRequest) -> Result<RecordedResponse, DiagnyxError> {
+        let mut state = self.state.lock().unwrap();
+        let CassetteState { interactions, used } = &mut *state;
+
+        let index = interactions
+            .iter()
+            .zip(used.iter())
+            .position(|(interaction, used)| !used && interaction.request.matches(request))
+            .ok_or_else(|| {
+                DiagnyxError::ConfigError(format!(
+                    "No recorded interaction for {} {} in {}",
+                    request.method,
+                    request.url,
+                    self.path.display()
+                ))
+            })?;
+
+        used[index] = true;
+        Ok(interactions[index].response.clone())
+    }
+
+    /// The interactions recorded or loaded so far.
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.state.lock().unwrap().interactions.clone()
+    }
+
+    /// Write recorded interactions to the cassette file.
+    ///
+    /// Does nothing when replaying.
+    pub fn save(&self) -> Result<(), DiagnyxError> {
+        if !self.recording {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let file = CassetteFile {
+            interactions: self.interactions(),
+        };
+        std::fs::write(&self.path, serde_json::to_string_pretty(&file)?)?;
+        Ok(())
+    }
+}
+
+fn redact_url(url: &reqwest::Url) -> String {
+    if url.query().is_none() {
+        return url.to_string();
+    }
+
+    let mut redacted = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(k, v)| {
+            let value = if SECRET_QUERY_PARAMS.contains(&k.to_lowercase().as_str()) {
+                REDACTED.to_string()
+            } else {
+                v.into_owned()
+            };
+            (k.into_owned(), value)
+        })
+        .collect();
+    redacted.query_pairs_mut().clear().extend_pairs(pairs);
+    redacted.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn temp_cassette_path() -> PathBuf {
+        std::env::temp_dir().join(format!("diagnyx-cassette-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_redact_url_strips_secret_query_params() {
+        let url = reqwest::Url::parse("https://example.com/v1/models?key=secret&alt=json").unwrap();
+        assert_eq!(
+            redact_url(&url),
+            "https://example.com/v1/models?key=%5BREDACTED%5D&alt=json"
+        );
+    }
+
+    #[test]
+    fn test_replay_requires_existing_file() {
+        let result = Cassette::open(temp_cassette_path(), CassetteMode::Replay);
+        assert!(matches!(result, Err(DiagnyxError::IoError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "usage": {"prompt_tokens": 10, "completion_tokens": 5}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let http = reqwest::Client::new();
+        let cassette_path = temp_cassette_path();
+        let build_request = || {
+            http.post(format!("{}/v1/chat/completions", server.uri()))
+                .bearer_auth("sk-secret")
+                .body(r#"{"model":"gpt-4"}"#)
+                .build()
+                .unwrap()
+        };
+
+        let recorder = Cassette::open(&cassette_path, CassetteMode::Auto).unwrap();
+        assert!(recorder.is_recording());
+        let recorded = recorder.execute(&http, build_request()).await.unwrap();
+        recorder.save().unwrap();
+
+        let contents = std::fs::read_to_string(&cassette_path).unwrap();
+        assert!(!contents.contains("sk-secret"));
+        assert!(contents.contains(REDACTED));
+
+        let player = Cassette::open(&cassette_path, CassetteMode::Auto).unwrap();
+        assert!(!player.is_recording());
+        let replayed = player.execute(&http, build_request()).await.unwrap();
+        assert_eq!(replayed, recorded);
+
+        let body: serde_json::Value = replayed.json().unwrap();
+        assert_eq!(body["usage"]["prompt_tokens"], 10);
+
+        // Each interaction replays once
+        assert!(player.execute(&http, build_request()).await.is_err());
+
+        std::fs::remove_file(&cassette_path).unwrap();
+    }
+}
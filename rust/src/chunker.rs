@@ -0,0 +1,192 @@
+//! Re-chunking of streamed provider deltas.
+//!
+//! Providers stream output as raw byte deltas that may end in the middle of
+//! a multi-byte UTF-8 character or a word. Decoding each delta on its own
+//! corrupts the text, and evaluating half-words lets terms slip past
+//! guardrail policies. A [`DeltaChunker`] buffers incomplete input and
+//! releases text only at character or word boundaries, so the chunks it
+//! produces can be passed to guardrail evaluation and tracking directly.
+//!
+//! # Example
+//!
+//! ```rust
+//! use diagnyx::chunker::DeltaChunker;
+//!
+//! let mut chunker = DeltaChunker::new();
+//! let mut text = String::new();
+//!
+//! // "café au lait", with 'é' split across two deltas
+//! for delta in [&b"caf\xc3"[..], b"\xa9 au la", b"it"] {
+//!     if let Some(chunk) = chunker.push(delta) {
+//!         text.push_str(&chunk);
+//!     }
+//! }
+//! if let Some(chunk) = chunker.finish() {
+//!     text.push_str(&chunk);
+//! }
+//!
+//! assert_eq!(text, "café au lait");
+//! ```
+
+/// Where a [`DeltaChunker`] may split the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChunkBoundary {
+    /// Release text after every complete character.
+    Char,
+    /// Release text up to the last whitespace, keeping partial words.
+    #[default]
+    Word,
+}
+
+/// Splits streamed byte deltas on UTF-8 and word boundaries.
+///
+/// Invalid UTF-8 is replaced with U+FFFD rather than dropped, so the
+/// released text always has the same number of characters as the provider
+/// sent.
+#[derive(Debug, Clone)]
+pub struct DeltaChunker {
+    boundary: ChunkBoundary,
+    max_word_bytes: usize,
+    pending: Vec<u8>,
+}
+
+impl Default for DeltaChunker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeltaChunker {
+    /// Create a chunker releasing text at word boundaries.
+    pub fn new() -> Self {
+        Self {
+            boundary: ChunkBoundary::Word,
+            max_word_bytes: 64,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Set where text may be split. Default: [`ChunkBoundary::Word`].
+    pub fn boundary(mut self, boundary: ChunkBoundary) -> Self {
+        self.boundary = boundary;
+        self
+    }
+
+    /// Release a partial word once it grows past this many bytes.
+    ///
+    /// Scripts such as Chinese and Japanese do not separate words with
+    /// whitespace, so without a limit their text would be held until the
+    /// end of the stream. Default: 64.
+    pub fn max_word_bytes(mut self, max_word_bytes: usize) -> Self {
+        self.max_word_bytes = max_word_bytes.max(1);
+        self
+    }
+
+    /// Add a delta, returning the text that is ready to be released.
+    pub fn push(&mut self, delta: impl AsRef<[u8]>) -> Option<String> {
+        self.pending.extend_from_slice(delta.as_ref());
+
+        let (mut text, incomplete) = decode(&self.pending);
+        let split = match self.boundary {
+            ChunkBoundary::Char => text.len(),
+            ChunkBoundary::Word => {
+                let last_space = text
+                    .char_indices()
+                    .rev()
+                    .find(|(_, c)| c.is_whitespace())
+                    .map_or(0, |(i, c)| i + c.len_utf8());
+                if text.len() - last_space > self.max_word_bytes {
+                    text.len()
+                } else {
+                    last_space
+                }
+            }
+        };
+
+        let mut rest = text.split_off(split).into_bytes();
+        rest.extend_from_slice(&self.pending[self.pending.len() - incomplete..]);
+        self.pending = rest;
+
+        (!text.is_empty()).then_some(text)
+    }
+
+    /// Release all buffered text at the end of the stream.
+    ///
+    /// A trailing incomplete character is replaced with U+FFFD.
+    pub fn finish(&mut self) -> Option<String> {
+        let pending = std::mem::take(&mut self.pending);
+        let text = String::from_utf8_lossy(&pending).into_owned();
+        (!text.is_empty()).then_some(text)
+    }
+}
+
+/// Decode `bytes`, replacing invalid sequences. Returns the text and the
+/// length of a trailing incomplete character that may still be completed.
+fn decode(bytes: &[u8]) -> (String, usize) {
+    let mut text = String::with_capacity(bytes.len());
+    let mut rest = bytes;
+
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return (text, 0);
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                // Safe to unwrap: the prefix was just validated
+                text.push_str(std::str::from_utf8(valid).unwrap());
+                match e.error_len() {
+                    Some(len) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        rest = &after[len..];
+                    }
+                    None => return (text, after.len()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(mut chunker: DeltaChunker, deltas: &[&[u8]]) -> Vec<String> {
+        let mut chunks: Vec<String> = deltas.iter().filter_map(|d| chunker.push(d)).collect();
+        chunks.extend(chunker.finish());
+        chunks
+    }
+
+    #[test]
+    fn test_holds_split_characters() {
+        let chunker = DeltaChunker::new().boundary(ChunkBoundary::Char);
+        // '😀' is four bytes
+        let chunks = collect(chunker, &[b"hi \xf0\x9f", b"\x98", b"\x80!"]);
+        assert_eq!(chunks, vec!["hi ", "😀!"]);
+    }
+
+    #[test]
+    fn test_splits_on_word_boundaries() {
+        let chunks = collect(DeltaChunker::new(), &[b"pass", b"word is", b" secret"]);
+        assert_eq!(chunks, vec!["password ", "is ", "secret"]);
+    }
+
+    #[test]
+    fn test_releases_long_words() {
+        let chunker = DeltaChunker::new().max_word_bytes(6);
+        let chunks = collect(chunker, &["今日は".as_bytes(), "天気".as_bytes()]);
+        assert_eq!(chunks, vec!["今日は", "天気"]);
+
+        let chunker = DeltaChunker::new().max_word_bytes(9);
+        let chunks = collect(chunker, &["今日は".as_bytes(), "天気".as_bytes()]);
+        assert_eq!(chunks, vec!["今日は天気"]);
+    }
+
+    #[test]
+    fn test_replaces_invalid_bytes() {
+        let chunker = DeltaChunker::new().boundary(ChunkBoundary::Char);
+        let chunks = collect(chunker, &[b"a\xffb", b"\xe2\x82"]);
+        assert_eq!(chunks, vec!["a\u{FFFD}b", "\u{FFFD}"]);
+    }
+}
@@ -0,0 +1,568 @@
+//! Helpers for running cost and quality checks in CI pipelines.
+//!
+//! Integration suites that call real models can tag every tracked call with
+//! the current branch (or any other metadata key) and fail the pipeline when
+//! the spend attributed to that tag exceeds a budget. [`EvalRunner`] runs a
+//! dataset of prompts against a model from `cargo test`, records the scores
+//! as an evaluation run and asserts a minimum mean score.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::analytics::AnalyticsClient;
+//! use diagnyx::ci::BudgetGate;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let analytics = AnalyticsClient::new("dx_api_key", "org-123");
+//!
+//!     // Fails with DiagnyxError::BudgetExceeded above $5
+//!     let report = BudgetGate::for_branch(analytics, 5.0)?.check().await?;
+//!     println!("Spent ${:.2} of ${:.2}", report.spent_usd, report.budget_usd);
+//!
+//!     Ok(())
+//! }
+//! ```
+//!
+//! # Eval-in-CI
+//!
+//! ```rust,no_run
+//! use diagnyx::ci::{EvalCase, EvalRunner, ModelOutput};
+//! use diagnyx::evaluations::EvaluationClient;
+//! use diagnyx::Provider;
+//!
+//! #[tokio::test]
+//! async fn answers_arithmetic() {
+//!     let evaluations = EvaluationClient::new("dx_api_key", "org-123");
+//!     let cases = vec![EvalCase::new("What is 2+2?").expected("4")];
+//!
+//!     let report = EvalRunner::new(&evaluations, "arithmetic", Provider::OpenAI, "gpt-4o-mini")
+//!         .min_score(0.9)
+//!         .run(&cases, |_input| async move {
+//!             // Call the model here
+//!             Ok::<_, std::io::Error>(ModelOutput::new("4"))
+//!         })
+//!         .await
+//!         .unwrap();
+//!
+//!     report.assert_passed();
+//! }
+//! ```
+
+use crate::analytics::{AnalyticsClient, SpendPeriod};
+use crate::error::DiagnyxError;
+use crate::evaluations::{EvaluationClient, EvaluationResult, EvaluationRun};
+use crate::{CallStatus, DiagnyxClient, LLMCall, Provider, TraceId};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Metadata key used for branch attribution by [`BudgetGate::for_branch`].
+pub const BRANCH_METADATA_KEY: &str = "branch";
+
+/// Environment variables checked, in order, to detect the current branch.
+const BRANCH_ENV_VARS: &[&str] = &[
+    "DIAGNYX_CI_BRANCH",
+    "GITHUB_HEAD_REF",
+    "GITHUB_REF_NAME",
+    "CI_COMMIT_REF_NAME",
+    "BRANCH_NAME",
+];
+
+/// Detect the current CI branch from well-known environment variables.
+pub fn detect_branch() -> Option<String> {
+    BRANCH_ENV_VARS
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Outcome of a passing budget check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetReport {
+    pub spent_usd: f64,
+    pub budget_usd: f64,
+    pub remaining_usd: f64,
+    pub call_count: i64,
+}
+
+/// Fails a pipeline when tagged spend exceeds a budget.
+pub struct BudgetGate {
+    client: AnalyticsClient,
+    metadata_key: String,
+    metadata_value: String,
+    budget_usd: f64,
+    period: SpendPeriod,
+}
+
+impl BudgetGate {
+    /// Create a gate over calls tagged with `metadata[key] == value`.
+    pub fn new(
+        client: AnalyticsClient,
+        metadata_key: impl Into<String>,
+        metadata_value: impl Into<String>,
+        budget_usd: f64,
+    ) -> Self {
+        Self {
+            client,
+            metadata_key: metadata_key.into(),
+            metadata_value: metadata_value.into(),
+            budget_usd,
+            period: SpendPeriod::Last30Days,
+        }
+    }
+
+    /// Create a gate over calls tagged with the current CI branch.
+    ///
+    /// Returns a `ConfigError` if no branch can be detected.
+    pub fn for_branch(client: AnalyticsClient, budget_usd: f64) -> Result<Self, DiagnyxError> {
+        let branch = detect_branch().ok_or_else(|| {
+            DiagnyxError::ConfigError("Could not detect CI branch from environment".to_string())
+        })?;
+        Ok(Self::new(client, BRANCH_METADATA_KEY, branch, budget_usd))
+    }
+
+    /// Set the period spend is summed over. Default: last 30 days.
+    pub fn period(mut self, period: SpendPeriod) -> Self {
+        self.period = period;
+        self
+    }
+
+    /// Fetch the attributed spend and compare it against the budget.
+    ///
+    /// Returns `DiagnyxError::BudgetExceeded` if the spend is over budget.
+    pub async fn check(&self) -> Result<BudgetReport, DiagnyxError> {
+        let spend = self
+            .client
+            .spend_by_metadata(&self.metadata_key, &self.metadata_value, self.period)
+            .await?;
+
+        if spend.total_cost > self.budget_usd {
+            return Err(DiagnyxError::BudgetExceeded {
+                spent_usd: spend.total_cost,
+                budget_usd: self.budget_usd,
+            });
+        }
+
+        Ok(BudgetReport {
+            spent_usd: spend.total_cost,
+            budget_usd: self.budget_usd,
+            remaining_usd: self.budget_usd - spend.total_cost,
+            call_count: spend.call_count,
+        })
+    }
+}
+
+/// A single prompt in an evaluation dataset.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalCase {
+    pub input: String,
+    pub expected: Option<String>,
+}
+
+impl EvalCase {
+    pub fn new(input: impl Into<String>) -> Self {
+        Self {
+            input: input.into(),
+            expected: None,
+        }
+    }
+
+    pub fn expected(mut self, expected: impl Into<String>) -> Self {
+        self.expected = Some(expected.into());
+        self
+    }
+}
+
+/// Output of the model under test for a single case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelOutput {
+    pub text: String,
+    pub input_tokens: i32,
+    pub output_tokens: i32,
+}
+
+impl ModelOutput {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            input_tokens: 0,
+            output_tokens: 0,
+        }
+    }
+
+    pub fn tokens(mut self, input_tokens: i32, output_tokens: i32) -> Self {
+        self.input_tokens = input_tokens;
+        self.output_tokens = output_tokens;
+        self
+    }
+}
+
+/// Scores a model output for a case, from 0.0 (wrong) to 1.0 (correct).
+pub type Scorer = Arc<dyn Fn(&EvalCase, &str) -> f64 + Send + Sync>;
+
+/// Scores 1.0 when the trimmed output equals the expected answer.
+pub fn exact_match() -> Scorer {
+    Arc::new(|case, output| match &case.expected {
+        Some(expected) if expected.trim() == output.trim() => 1.0,
+        _ => 0.0,
+    })
+}
+
+/// Scores 1.0 when the output contains the expected answer, ignoring case.
+pub fn contains_expected() -> Scorer {
+    Arc::new(|case, output| match &case.expected {
+        Some(expected) if output.to_lowercase().contains(&expected.to_lowercase()) => 1.0,
+        _ => 0.0,
+    })
+}
+
+/// Scored outcome of a single case.
+#[derive(Debug, Clone)]
+pub struct EvalCaseResult {
+    pub case: EvalCase,
+    pub output: String,
+    pub score: f64,
+    pub error: Option<String>,
+}
+
+/// Outcome of an evaluation run executed by [`EvalRunner`].
+#[derive(Debug, Clone)]
+pub struct EvalReport {
+    pub run: EvaluationRun,
+    pub results: Vec<EvalCaseResult>,
+    pub mean_score: f64,
+    pub min_score: f64,
+}
+
+impl EvalReport {
+    /// Whether the mean score reached the configured minimum.
+    pub fn passed(&self) -> bool {
+        self.mean_score >= self.min_score
+    }
+
+    /// Panic with the failing cases if the mean score is below the minimum.
+    pub fn assert_passed(&self) {
+        if self.passed() {
+            return;
+        }
+
+        let failing: Vec<String> = self
+            .results
+            .iter()
+            .filter(|r| r.score < 1.0)
+            .map(|r| match &r.error {
+                Some(e) => format!("  {:?}: error: {}", r.case.input, e),
+                None => format!(
+                    "  {:?}: got {:?}, expected {:?} (score {:.2})",
+                    r.case.input, r.output, r.case.expected, r.score
+                ),
+            })
+            .collect();
+
+        panic!(
+            "Evaluation run {} scored {:.3}, below minimum {:.3}\n{}",
+            self.run.id,
+            self.mean_score,
+            self.min_score,
+            failing.join("\n")
+        );
+    }
+}
+
+/// Runs a dataset of prompts against a model and records an evaluation run.
+pub struct EvalRunner<'a> {
+    evaluations: &'a EvaluationClient,
+    tracker: Option<&'a DiagnyxClient>,
+    name: String,
+    provider: Provider,
+    model: String,
+    scorer: Scorer,
+    min_score: f64,
+}
+
+impl<'a> EvalRunner<'a> {
+    /// Create a runner that scores outputs with [`exact_match`].
+    pub fn new(
+        evaluations: &'a EvaluationClient,
+        name: impl Into<String>,
+        provider: Provider,
+        model: impl Into<String>,
+    ) -> Self {
+        Self {
+            evaluations,
+            tracker: None,
+            name: name.into(),
+            provider,
+            model: model.into(),
+            scorer: exact_match(),
+            min_score: 1.0,
+        }
+    }
+
+    /// Track every model call with the given client.
+    pub fn track_with(mut self, client: &'a DiagnyxClient) -> Self {
+        self.tracker = Some(client);
+        self
+    }
+
+    /// Set the scorer. Default: [`exact_match`].
+    pub fn scorer(mut self, scorer: Scorer) -> Self {
+        self.scorer = scorer;
+        self
+    }
+
+    /// Set the minimum mean score for the run to pass. Default: 1.0.
+    pub fn min_score(mut self, min_score: f64) -> Self {
+        self.min_score = min_score;
+        self
+    }
+
+    /// Execute every case with `model_fn`, submit the scores and complete the run.
+    ///
+    /// Model errors score 0.0 and do not abort the run; only failures to
+    /// talk to the evaluations API are returned as errors.
+    pub async fn run<F, Fut, E>(
+        &self,
+        cases: &[EvalCase],
+        model_fn: F,
+    ) -> Result<EvalReport, DiagnyxError>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<ModelOutput, E>>,
+        E: std::fmt::Display,
+    {
+        let run = self
+            .evaluations
+            .create_run(&self.name, &self.model, None)
+            .await?;
+
+        let mut results = Vec::with_capacity(cases.len());
+        let mut submissions = Vec::with_capacity(cases.len());
+
+        for case in cases {
+            let trace_id = TraceId::generate();
+            let start = Instant::now();
+            let outcome = model_fn(case.input.clone()).await;
+            let latency_ms = start.elapsed().as_millis() as i64;
+
+            let mut metadata = HashMap::new();
+            metadata.insert("eval_run_id".to_string(), serde_json::json!(run.id));
+
+            let mut call = LLMCall::builder()
+                .provider(self.provider.clone())
+                .model(&self.model)
+                .latency_ms(latency_ms)
+                .trace_id(trace_id.clone())
+                .metadata(metadata);
+
+            let result = match outcome {
+                Ok(output) => {
+                    call = call
+                        .input_tokens(output.input_tokens)
+                        .output_tokens(output.output_tokens)
+                        .status(CallStatus::Success);
+                    EvalCaseResult {
+                        case: case.clone(),
+                        score: (self.scorer)(case, &output.text),
+                        output: output.text,
+                        error: None,
+                    }
+                }
+                Err(e) => {
+                    call = call.status(CallStatus::Error).error_message(e.to_string());
+                    EvalCaseResult {
+                        case: case.clone(),
+                        output: String::new(),
+                        score: 0.0,
+                        error: Some(e.to_string()),
+                    }
+                }
+            };
+
+            if let Some(tracker) = self.tracker {
+                tracker.track(call.build()).await;
+            }
+
+            let mut submission = EvaluationResult::new(&case.input, &result.output, result.score)
+                .trace_id(trace_id)
+                .latency_ms(latency_ms);
+            if let Some(expected) = &case.expected {
+                submission = submission.expected_output(expected);
+            }
+            submissions.push(submission);
+            results.push(result);
+        }
+
+        self.evaluations
+            .submit_results(&run.id, submissions)
+            .await?;
+        let run = self.evaluations.complete_run(&run.id).await?;
+
+        if let Some(tracker) = self.tracker {
+            let _ = tracker.flush().await;
+        }
+
+        let mean_score = if results.is_empty() {
+            0.0
+        } else {
+            results.iter().map(|r| r.score).sum::<f64>() / results.len() as f64
+        };
+
+        Ok(EvalReport {
+            run,
+            results,
+            mean_score,
+            min_score: self.min_score,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::AnalyticsClientConfig;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn mock_spend(server: &MockServer, total_cost: f64) {
+        Mock::given(method("GET"))
+            .and(path("/api/v1/organizations/org-1/analytics/metadata/spend"))
+            .and(query_param("key", "branch"))
+            .and(query_param("value", "feature-x"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "key": "branch",
+                "value": "feature-x",
+                "totalCost": total_cost,
+                "callCount": 12
+            })))
+            .mount(server)
+            .await;
+    }
+
+    fn gate(server: &MockServer, budget_usd: f64) -> BudgetGate {
+        let client = AnalyticsClient::with_config(
+            AnalyticsClientConfig::new("test-api-key", "org-1")
+                .base_url(server.uri())
+                .max_retries(1),
+        );
+        BudgetGate::new(client, "branch", "feature-x", budget_usd)
+    }
+
+    async fn mock_evaluations(server: &MockServer) -> EvaluationClient {
+        let run = serde_json::json!({
+            "id": "run-1",
+            "name": "arithmetic",
+            "model": "gpt-4",
+            "status": "running",
+            "createdAt": "2024-03-01T00:00:00Z"
+        });
+        Mock::given(method("POST"))
+            .and(path("/api/v1/organizations/org-1/evaluations/runs"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(run.clone()))
+            .mount(server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/evaluations/runs/run-1/results",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .expect(1)
+            .mount(server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/evaluations/runs/run-1/complete",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(run))
+            .mount(server)
+            .await;
+
+        EvaluationClient::with_config(
+            crate::evaluations::EvaluationClientConfig::new("test-api-key", "org-1")
+                .base_url(server.uri())
+                .max_retries(1),
+        )
+    }
+
+    fn arithmetic_cases() -> Vec<EvalCase> {
+        vec![
+            EvalCase::new("2+2").expected("4"),
+            EvalCase::new("3+3").expected("6"),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_eval_runner_scores_cases() {
+        let server = MockServer::start().await;
+        let evaluations = mock_evaluations(&server).await;
+
+        let report = EvalRunner::new(&evaluations, "arithmetic", Provider::OpenAI, "gpt-4")
+            .min_score(0.5)
+            .run(&arithmetic_cases(), |input| async move {
+                match input.as_str() {
+                    "2+2" => Ok(ModelOutput::new(" 4 ").tokens(3, 1)),
+                    _ => Err("model unavailable"),
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.results[0].score, 1.0);
+        assert_eq!(
+            report.results[1].error,
+            Some("model unavailable".to_string())
+        );
+        assert_eq!(report.mean_score, 0.5);
+        assert!(report.passed());
+        report.assert_passed();
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "below minimum")]
+    async fn test_eval_report_assert_passed_panics_below_minimum() {
+        let server = MockServer::start().await;
+        let evaluations = mock_evaluations(&server).await;
+
+        let report = EvalRunner::new(&evaluations, "arithmetic", Provider::OpenAI, "gpt-4")
+            .scorer(contains_expected())
+            .run(&arithmetic_cases(), |_input| async move {
+                Ok::<_, String>(ModelOutput::new("The answer is 4"))
+            })
+            .await
+            .unwrap();
+
+        report.assert_passed();
+    }
+
+    #[tokio::test]
+    async fn test_budget_gate_passes_under_budget() {
+        let server = MockServer::start().await;
+        mock_spend(&server, 1.5).await;
+
+        let report = gate(&server, 5.0).check().await.unwrap();
+
+        assert_eq!(report.spent_usd, 1.5);
+        assert_eq!(report.remaining_usd, 3.5);
+        assert_eq!(report.call_count, 12);
+    }
+
+    #[tokio::test]
+    async fn test_budget_gate_fails_over_budget() {
+        let server = MockServer::start().await;
+        mock_spend(&server, 7.25).await;
+
+        match gate(&server, 5.0).check().await {
+            Err(DiagnyxError::BudgetExceeded {
+                spent_usd,
+                budget_usd,
+            }) => {
+                assert_eq!(spent_usd, 7.25);
+                assert_eq!(budget_usd, 5.0);
+            }
+            other => panic!("Expected BudgetExceeded, got {:?}", other),
+        }
+    }
+}
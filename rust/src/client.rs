@@ -1,107 +1,668 @@
+use crate::audit::{AuditEvent, AuditLog, ConfigSetting};
+use crate::budgets::{BudgetTracker, WindowSpendResponse};
+use crate::buffer::CallBuffer;
+use crate::compact;
 use crate::error::DiagnyxError;
-use crate::types::{BatchRequest, DiagnyxConfig, LLMCall};
+use crate::events::{EventBus, SdkEvent};
+use crate::heartbeat::Heartbeat;
+use crate::host::HostContext;
+use crate::http::REQUEST_TIMEOUT;
+use crate::ids::ProjectId;
+use crate::limit::limit_call_size;
+use crate::parked::ParkedQueue;
+use crate::report::FailureReporter;
+use crate::retry::{
+    new_request_id, send_with_retry, with_timeout, within_deadline, REQUEST_ID_HEADER,
+};
+use crate::sampling::AdaptiveSampler;
+use crate::schedule::{phase_offset, FlushSchedule};
+use crate::spend::{self, MonthToDateResponse, SpendCache};
+use crate::trace::Span;
+use crate::types::{
+    BatchRequest, BatchResponse, DiagnyxConfig, EmbeddingCall, FlushMode, LLMCall, Provider,
+    TEST_ENVIRONMENT,
+};
+use crate::upload::{self, UploadHandle, UploadProgress};
+use crate::watermark::WatermarkState;
+use async_trait::async_trait;
 use chrono::Utc;
-use reqwest::Client;
+use reqwest::{Body, Client, Method, Response, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::task::{JoinHandle, JoinSet};
 use tokio::time::interval;
 
 /// The Diagnyx client for tracking LLM calls.
 pub struct DiagnyxClient {
     config: DiagnyxConfig,
     http_client: Client,
-    buffer: Arc<Mutex<Vec<LLMCall>>>,
-    shutdown: Arc<Mutex<bool>>,
+    buffer: Arc<Mutex<CallBuffer>>,
+    /// Set to stop the background tasks.
+    shutdown: watch::Sender<bool>,
+    flush_task: std::sync::Mutex<Option<JoinHandle<()>>>,
+    /// The reconcile, budget sync and heartbeat tasks.
+    tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
+    events: EventBus,
+    spend: Arc<SpendCache>,
+    budgets: Arc<BudgetTracker>,
+    sampler: Option<AdaptiveSampler>,
+    settings: RuntimeSettings,
+    audit: Arc<AuditLog>,
+    auth: Arc<AuthState>,
+    failures: Arc<FailureReporter>,
+    heartbeat: Heartbeat,
+    watermarks: Arc<WatermarkState>,
+    schedule: Arc<FlushSchedule>,
+    sender: Arc<BatchSender>,
+    parked: Option<Arc<ParkedQueue>>,
+    /// Set once the panic hook is installed.
+    panic_hook: AtomicBool,
+    /// Set once the signal handler is installed.
+    #[cfg(feature = "signals")]
+    signal_handler: AtomicBool,
+    host: Option<Arc<HostContext>>,
+}
+
+/// Outcome of a flush, with the totals the API reported for the batch.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlushReport {
+    /// Calls sent.
+    pub calls: usize,
+    /// What the API reported for the calls, with the IDs it assigned in the
+    /// order they were sent.
+    ///
+    /// `None` if it reported nothing, e.g. when calls go to a
+    /// [sink](DiagnyxConfig::sink).
+    pub response: Option<BatchResponse>,
+}
+
+impl FlushReport {
+    /// The totals of this report and `other` together, without a response
+    /// unless both have one.
+    fn merge(self, other: FlushReport) -> Self {
+        let response = match (self.response, other.response) {
+            (Some(a), Some(b)) => {
+                let mut ids = a.ids;
+                ids.extend(b.ids);
+                Some(BatchResponse {
+                    tracked: a.tracked + b.tracked,
+                    total_cost: a.total_cost + b.total_cost,
+                    total_tokens: a.total_tokens + b.total_tokens,
+                    ids,
+                })
+            }
+            _ => None,
+        };
+        Self {
+            calls: self.calls + other.calls,
+            response,
+        }
+    }
+
+    /// The report for `calls` sent calls answered with `body`, which may be
+    /// empty. A body that cannot be parsed is logged and reported as no
+    /// response.
+    pub(crate) fn from_response(calls: usize, body: &[u8], config: &DiagnyxConfig) -> Self {
+        if body.is_empty() {
+            return Self {
+                calls,
+                response: None,
+            };
+        }
+        let response = serde_json::from_slice(body)
+            .map_err(|e| {
+                config
+                    .logger()
+                    .warn(&format!("Failed to parse ingest response: {}", e))
+            })
+            .ok();
+        Self { calls, response }
+    }
+}
+
+/// Outcome of [`DiagnyxClient::shutdown`].
+#[derive(Debug, Default)]
+#[must_use = "the report tells whether calls were dropped"]
+pub struct ShutdownReport {
+    /// Calls sent by the final flush.
+    pub flushed: usize,
+    /// Calls saved to `parked_buffer_path` because the API key was rejected.
+    pub parked: usize,
+    /// Calls lost because the final flush failed or timed out.
+    pub dropped: usize,
+    /// Why the final flush failed.
+    pub error: Option<DiagnyxError>,
+}
+
+impl ShutdownReport {
+    /// The error of the final flush, if it failed.
+    pub fn into_result(self) -> Result<(), DiagnyxError> {
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+/// Settings that can be changed while the client is running.
+#[derive(Debug)]
+struct RuntimeSettings {
+    capture_full_content: AtomicBool,
+    /// Bits of the `f64` sample rate.
+    sample_rate: AtomicU64,
+}
+
+/// Consecutive auth failures of flushes.
+#[derive(Debug, Default)]
+pub(crate) struct AuthState {
+    failures: AtomicU32,
+    /// Status code the client was parked with, or 0 while flushing.
+    parked_status: AtomicU16,
+}
+
+impl AuthState {
+    pub(crate) fn parked(&self) -> Option<u16> {
+        match self.parked_status.load(Ordering::Relaxed) {
+            0 => None,
+            status => Some(status),
+        }
+    }
+
+    /// Record the outcome of a flush, returning the status code if this
+    /// failure parks the client.
+    pub(crate) fn record<T>(
+        &self,
+        result: &Result<T, DiagnyxError>,
+        threshold: u32,
+    ) -> Option<u16> {
+        let Some(status) = result.as_ref().err().and_then(|e| e.auth_failure_status()) else {
+            self.failures.store(0, Ordering::Relaxed);
+            return None;
+        };
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures < threshold.max(1) {
+            return None;
+        }
+        match self.parked_status.swap(status, Ordering::Relaxed) {
+            0 => Some(status),
+            _ => None,
+        }
+    }
 }
 
 impl DiagnyxClient {
     /// Create a new DiagnyxClient with the given API key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
     pub fn new(api_key: impl Into<String>) -> Self {
         Self::with_config(DiagnyxConfig::new(api_key))
     }
 
     /// Create a new DiagnyxClient with custom configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_with_config`](Self::try_with_config) to handle the error instead.
     pub fn with_config(config: DiagnyxConfig) -> Self {
+        Self::try_with_config(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new DiagnyxClient with the given API key, returning an error
+    /// if the HTTP client cannot be created.
+    pub fn try_new(api_key: impl Into<String>) -> Result<Self, DiagnyxError> {
+        Self::try_with_config(DiagnyxConfig::new(api_key))
+    }
+
+    /// Create a new DiagnyxClient with custom configuration, returning an
+    /// error if the HTTP client cannot be created, e.g. because a
+    /// certificate file cannot be read.
+    pub fn try_with_config(config: DiagnyxConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config.http.client(REQUEST_TIMEOUT)?;
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Create a new DiagnyxClient sending requests with `http_client`, e.g.
+    /// one set up for a corporate network.
+    ///
+    /// The proxy and certificates of `config` are not applied to it.
+    pub fn with_http_client(config: DiagnyxConfig, http_client: Client) -> Self {
+        // A disabled client leaves parked calls for an enabled one to send
+        let parked = config
+            .parked_buffer_path
+            .clone()
+            .filter(|_| !config.disabled)
+            .map(|path| Arc::new(ParkedQueue::new(path)));
+        let restored = parked.as_deref().map(ParkedQueue::take).unwrap_or_default();
+
+        let schedule = Arc::new(FlushSchedule::default());
+        let sink = config.sink.clone().unwrap_or_else(|| {
+            Arc::new(HttpSink::with_schedule(
+                config.clone(),
+                http_client.clone(),
+                Arc::clone(&schedule),
+            ))
+        });
+        let sender = Arc::new(BatchSender {
+            sink,
+            config: config.clone(),
+            schedule: Arc::clone(&schedule),
+            last_report: std::sync::Mutex::new(None),
+        });
         let client = Self {
+            http_client,
+            buffer: Arc::new(Mutex::new(CallBuffer::new(restored))),
+            shutdown: watch::Sender::new(false),
+            flush_task: std::sync::Mutex::new(None),
+            tasks: std::sync::Mutex::new(Vec::new()),
+            events: EventBus::default(),
+            spend: Arc::new(SpendCache::open(config.spend_cache_path.clone())),
+            // Test traffic must not count against budgets or warn about them
+            budgets: Arc::new(BudgetTracker::new(if config.is_test_mode() {
+                &[]
+            } else {
+                &config.budgets
+            })),
+            sampler: config.sampling.volume_cap.map(AdaptiveSampler::new),
+            settings: RuntimeSettings {
+                capture_full_content: AtomicBool::new(config.capture_full_content),
+                sample_rate: AtomicU64::new(config.sampling.rate.to_bits()),
+            },
+            audit: Arc::new(AuditLog::default()),
+            auth: Arc::new(AuthState::default()),
+            failures: Arc::new(FailureReporter::default()),
+            heartbeat: Heartbeat::new(&config),
+            watermarks: Arc::new(WatermarkState::default()),
+            schedule,
+            sender,
+            parked,
+            panic_hook: AtomicBool::new(false),
+            #[cfg(feature = "signals")]
+            signal_handler: AtomicBool::new(false),
+            host: (config.capture_host_context && !config.disabled)
+                .then(|| Arc::new(HostContext::detect())),
             config,
-            http_client: Client::builder()
-                .timeout(Duration::from_secs(30))
-                .build()
-                .expect("Failed to create HTTP client"),
-            buffer: Arc::new(Mutex::new(Vec::new())),
-            shutdown: Arc::new(Mutex::new(false)),
         };
 
+        if client.config.disabled {
+            return client;
+        }
         // Start background flush task
-        client.start_flush_task();
+        *client.flush_task.lock().unwrap() = Some(client.start_flush_task());
+        let mut tasks = Vec::new();
+        if let Some(interval_ms) = client.config.spend_reconcile_interval_ms {
+            tasks.push(client.start_reconcile_task(interval_ms));
+        }
+        if let Some(interval_ms) = client.config.budget_sync_interval_ms {
+            if !client.budgets.is_empty() {
+                tasks.push(client.start_budget_sync_task(interval_ms));
+            }
+        }
+        if let Some(interval_ms) = client.config.heartbeat_interval_ms {
+            tasks.push(client.start_heartbeat_task(interval_ms));
+        }
+        *client.tasks.lock().unwrap() = tasks;
+        if let Some(host) = &client.host {
+            let host = Arc::clone(host);
+            tokio::spawn(async move { host.lookup_instance_type().await });
+        }
 
         client
     }
 
+    /// Subscribe to flush and buffer events emitted by this client.
+    pub fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<SdkEvent> {
+        self.events.subscribe()
+    }
+
+    /// Start a trace of a multi-step workflow, returning its root span.
+    pub fn start_trace(self: &Arc<Self>, name: impl Into<String>) -> Span {
+        Span::root(Arc::clone(self), name)
+    }
+
     /// Track a single LLM call.
+    ///
+    /// Configured enrichers and then filters run on the call before it is
+    /// buffered.
     pub async fn track(&self, mut call: LLMCall) {
         if call.timestamp == DateTime::<Utc>::default() {
             call.timestamp = Utc::now();
         }
+        let Some(call) = self.prepare(call).await else {
+            return;
+        };
+        self.spend.record(&call);
+        self.budgets.record(&call);
 
         let should_flush = {
             let mut buffer = self.buffer.lock().await;
             buffer.push(call);
+            buffer.trim(&self.config, &self.events);
+            self.watermarks
+                .update(buffer.len(), &self.config, &self.events);
             buffer.len() >= self.config.batch_size
         };
 
-        if should_flush {
+        if should_flush && self.schedule.ready() {
             let _ = self.flush().await;
         }
     }
 
+    /// Track an embedding call.
+    pub async fn track_embedding(&self, call: EmbeddingCall) {
+        self.track(call.into()).await;
+    }
+
     /// Track multiple LLM calls.
     pub async fn track_all(&self, calls: Vec<LLMCall>) {
         let now = Utc::now();
-        let calls: Vec<LLMCall> = calls
-            .into_iter()
-            .map(|mut c| {
-                if c.timestamp == DateTime::<Utc>::default() {
-                    c.timestamp = now;
-                }
-                c
-            })
-            .collect();
+        let mut prepared = Vec::with_capacity(calls.len());
+        for mut call in calls {
+            if call.timestamp == DateTime::<Utc>::default() {
+                call.timestamp = now;
+            }
+            if let Some(call) = self.prepare(call).await {
+                self.spend.record(&call);
+                self.budgets.record(&call);
+                prepared.push(call);
+            }
+        }
+        let calls = prepared;
 
         let should_flush = {
             let mut buffer = self.buffer.lock().await;
             buffer.extend(calls);
+            buffer.trim(&self.config, &self.events);
+            self.watermarks
+                .update(buffer.len(), &self.config, &self.events);
             buffer.len() >= self.config.batch_size
         };
 
-        if should_flush {
+        if should_flush && self.schedule.ready() {
             let _ = self.flush().await;
         }
     }
 
+    /// Sample the call, run enrichers and filters, estimate the cost and
+    /// export it as a span, returning `None` if the call is dropped.
+    ///
+    /// Calls are sampled once prepared if the sampling policy has rules
+    /// depending on the call, such as cost sampling, or a volume cap.
+    async fn prepare(&self, mut call: LLMCall) -> Option<LLMCall> {
+        if self.config.disabled {
+            return None;
+        }
+        let rate = self.sample_rate();
+        if self.config.sampling.is_uniform() && sampled_out(rate) {
+            return None;
+        }
+        if let Some(host) = &self.host {
+            host.apply(&mut call);
+        }
+        let call = self.config.enrichers.run(call, &self.events).await;
+        let call = self.config.filters.run(call, &self.events)?;
+        finish_prepare(
+            call,
+            rate,
+            &self.config,
+            self.sampler.as_ref(),
+            &self.events,
+        )
+    }
+
+    /// Current rate of the adaptive sampler, if a volume cap is configured.
+    pub fn adaptive_sample_rate(&self) -> Option<f64> {
+        self.sampler.as_ref().map(AdaptiveSampler::rate)
+    }
+
+    /// Estimate the cost in USD of a call with the client's cost
+    /// calculator. Returns `None` if the model has no known price.
+    pub fn estimate_cost(
+        &self,
+        provider: Provider,
+        model: &str,
+        input_tokens: i32,
+        output_tokens: i32,
+    ) -> Option<f64> {
+        self.config
+            .cost_calculator
+            .estimate(&provider, model, input_tokens, output_tokens)
+    }
+
+    /// Whether full prompts and responses are captured.
+    pub fn capture_full_content(&self) -> bool {
+        self.settings.capture_full_content.load(Ordering::Relaxed)
+    }
+
+    /// Enable or disable full content capture, recording the change in the
+    /// audit log.
+    ///
+    /// The change applies immediately. An error means the audit event could
+    /// not be delivered; it is kept and retried on the next flush.
+    pub async fn set_capture_full_content(
+        &self,
+        enabled: bool,
+        actor: impl Into<String>,
+    ) -> Result<(), DiagnyxError> {
+        let old = self
+            .settings
+            .capture_full_content
+            .swap(enabled, Ordering::Relaxed);
+        if old == enabled {
+            return Ok(());
+        }
+        self.log(&format!("Full content capture set to {}", enabled));
+        let event = AuditEvent::new(ConfigSetting::CaptureFullContent, old, enabled, actor);
+        self.audit
+            .record(event, &self.http_client, &self.config)
+            .await
+    }
+
+    /// Fraction of calls that are tracked.
+    pub fn sample_rate(&self) -> f64 {
+        f64::from_bits(self.settings.sample_rate.load(Ordering::Relaxed))
+    }
+
+    /// Change the fraction of calls that are tracked, recording the change
+    /// in the audit log.
+    ///
+    /// Returns a [`DiagnyxError::ConfigError`] without changing anything if
+    /// `rate` is not between 0.0 and 1.0. Otherwise the change applies
+    /// immediately, and an error means the audit event could not be
+    /// delivered; it is kept and retried on the next flush.
+    pub async fn set_sample_rate(
+        &self,
+        rate: f64,
+        actor: impl Into<String>,
+    ) -> Result<(), DiagnyxError> {
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(DiagnyxError::ConfigError(format!(
+                "sample rate must be between 0.0 and 1.0, got {}",
+                rate
+            )));
+        }
+        let old = f64::from_bits(
+            self.settings
+                .sample_rate
+                .swap(rate.to_bits(), Ordering::Relaxed),
+        );
+        if old == rate {
+            return Ok(());
+        }
+        self.log(&format!("Sample rate set to {}", rate));
+        let event = AuditEvent::new(ConfigSetting::SampleRate, old, rate, actor);
+        self.audit
+            .record(event, &self.http_client, &self.config)
+            .await
+    }
+
+    /// Whether the client stopped flushing because the API repeatedly
+    /// rejected its API key.
+    ///
+    /// A parked client keeps buffering calls but does not send them. With a
+    /// parked buffer path configured, the calls buffered when it was parked
+    /// are moved to that file, and those buffered later are added to it on
+    /// shutdown. They are loaded by the next client created with that path.
+    pub fn is_parked(&self) -> bool {
+        self.auth.parked().is_some()
+    }
+
     /// Flush all buffered calls to the API.
-    pub async fn flush(&self) -> Result<(), DiagnyxError> {
+    ///
+    /// Returns the API's totals for the batch, or
+    /// [`DiagnyxError::AuthFailed`] without sending anything if the client is
+    /// [parked](Self::is_parked).
+    pub async fn flush(&self) -> Result<FlushReport, DiagnyxError> {
+        self.flush_within(None).await
+    }
+
+    /// Flush all buffered calls, giving up after `timeout`.
+    ///
+    /// The timeout covers every retry attempt. Calls are kept in the buffer
+    /// if the flush times out.
+    pub async fn flush_with_timeout(&self, timeout: Duration) -> Result<FlushReport, DiagnyxError> {
+        self.flush_within(Some(timeout)).await
+    }
+
+    /// The report of the last flush that sent calls, whether it was
+    /// requested or ran in the background.
+    ///
+    /// `None` until a flush has succeeded.
+    pub fn last_flush_stats(&self) -> Option<FlushReport> {
+        self.sender.last_report()
+    }
+
+    /// Send a single heartbeat, registering this instance with the API.
+    ///
+    /// Use [`verify_api_key`](Self::verify_api_key) to check a key without
+    /// registering an instance.
+    pub async fn send_heartbeat(&self) -> Result<(), DiagnyxError> {
+        self.heartbeat.send(&self.http_client, &self.config).await
+    }
+
+    /// Check that the API key is accepted.
+    ///
+    /// Reads the month-to-date spend of the organization, so nothing is
+    /// recorded by the API.
+    pub async fn verify_api_key(&self) -> Result<(), DiagnyxError> {
+        let url = format!("{}/api/v1/spend/month-to-date", self.config.base_url);
+        send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            Method::GET,
+            |method| {
+                self.http_client
+                    .request(method, &url)
+                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+            },
+        )
+        .await
+        .map(drop)
+    }
+
+    /// Upload `calls` in the background in batches of
+    /// [`batch_size`](DiagnyxConfig::batch_size), e.g. to backfill calls
+    /// recorded elsewhere.
+    ///
+    /// Calls are sent as given, bypassing the buffer, sampling, enrichers and
+    /// filters, to the configured [sink](DiagnyxConfig::sink) if there is
+    /// one. `on_progress` is called after each batch. Nothing is sent if the
+    /// client is [disabled](DiagnyxConfig::disabled).
+    pub fn upload(
+        &self,
+        calls: Vec<LLMCall>,
+        on_progress: impl Fn(&UploadProgress) + Send + 'static,
+    ) -> UploadHandle {
+        let calls = if self.config.disabled {
+            Vec::new()
+        } else {
+            calls
+        };
+        let sender = Arc::clone(&self.sender);
+        let send = move |batch: Vec<LLMCall>| {
+            let sender = Arc::clone(&sender);
+            async move { sender.send(&batch).await.map(drop) }
+        };
+        upload::spawn(calls, self.config.batch_size, send, on_progress)
+    }
+
+    async fn flush_within(&self, timeout: Option<Duration>) -> Result<FlushReport, DiagnyxError> {
+        if let Err(e) = self
+            .audit
+            .send_pending(&self.http_client, &self.config)
+            .await
+        {
+            self.log(&format!("Failed to send audit events: {}", e));
+        }
+
+        if let Some(status_code) = self.auth.parked() {
+            return Err(DiagnyxError::AuthFailed { status_code });
+        }
+
+        // Pick up calls parked by other processes sharing the file, or by
+        // the panic hook
+        let restored = self
+            .parked
+            .as_deref()
+            .map(ParkedQueue::take)
+            .unwrap_or_default();
         let calls = {
             let mut buffer = self.buffer.lock().await;
+            buffer.extend(restored);
             if buffer.is_empty() {
-                return Ok(());
+                return Ok(FlushReport::default());
             }
-            std::mem::take(&mut *buffer)
+            buffer.take()
         };
-
-        match self.send_batch(&calls).await {
-            Ok(_) => {
-                self.log(&format!("Flushed {} calls", calls.len()));
-                Ok(())
+        self.save_spend();
+
+        let count = calls.len();
+        self.events.emit(SdkEvent::FlushStarted { count });
+
+        let (result, unsent) = self.sender.send_all(calls, timeout).await;
+        let parked = self
+            .auth
+            .record(&result, self.config.auth_failure_threshold);
+
+        match result {
+            Ok(report) => {
+                self.log(&format!("Flushed {} calls", count));
+                self.events.emit(SdkEvent::FlushSucceeded { count });
+                report_success(&self.failures, &self.config, &self.events);
+                let depth = self.buffer.lock().await.len();
+                self.watermarks.update(depth, &self.config, &self.events);
+                Ok(report)
             }
             Err(e) => {
-                // Put calls back in buffer on error
+                if let Some(message) = self.failures.failure(&e.to_string()) {
+                    self.log(&e.with_request_id(message));
+                }
+                self.events.emit(SdkEvent::FlushFailed {
+                    error: e.to_string(),
+                });
                 let mut buffer = self.buffer.lock().await;
-                let mut restored = calls;
-                restored.append(&mut *buffer);
-                *buffer = restored;
-                self.log(&format!("Flush failed: {}", e));
+                buffer.restore(unsent, &self.config, &self.events);
+                if let Some(status_code) = parked {
+                    park_buffer(
+                        &self.config,
+                        self.parked.as_deref(),
+                        &mut buffer,
+                        &self.events,
+                        &e,
+                        status_code,
+                    );
+                }
+                self.watermarks
+                    .update(buffer.len(), &self.config, &self.events);
                 Err(e)
             }
         }
@@ -112,127 +673,1258 @@ impl DiagnyxClient {
         self.buffer.lock().await.len()
     }
 
-    /// Shutdown the client, flushing any remaining calls.
-    pub async fn shutdown(&self) -> Result<(), DiagnyxError> {
-        *self.shutdown.lock().await = true;
-        self.flush().await
+    /// Month-to-date spend of `project` in USD.
+    ///
+    /// The total is kept locally from the estimated cost of tracked calls
+    /// and corrected on each reconciliation with the API, so reading it
+    /// makes no request.
+    pub fn month_to_date(&self, project: &ProjectId) -> f64 {
+        self.spend.month_to_date(project.as_str())
+    }
+
+    /// Fetch the month-to-date spend of `project` from the API and update
+    /// the local total.
+    ///
+    /// Calls still in the buffer are added to the API's total. Returns the
+    /// updated total in USD.
+    pub async fn reconcile_spend(&self, project: &ProjectId) -> Result<f64, DiagnyxError> {
+        reconcile_project(
+            &self.http_client,
+            &self.config,
+            &self.buffer,
+            &self.spend,
+            project.as_str(),
+        )
+        .await
+    }
+
+    /// Check the configured budgets before making an LLM call for `project`
+    /// and `user`.
+    ///
+    /// Budgets are checked against the local running total, so this makes
+    /// no request. Returns [`DiagnyxError::BudgetExceeded`] if an exhausted
+    /// budget rejects calls; exhausted budgets that only warn emit an
+    /// [`SdkEvent::BudgetWarning`].
+    pub fn check_budget(
+        &self,
+        project: Option<&ProjectId>,
+        user: Option<&str>,
+    ) -> Result<(), DiagnyxError> {
+        let result = self.budgets.check(project, user, &self.events);
+        if let Err(e) = &result {
+            self.log(&format!("Call rejected: {}", e));
+        }
+        result
+    }
+
+    /// Fetch the spend of every budget's current window from the API and
+    /// update the local totals.
+    ///
+    /// Calls still in the buffer are added to the API's totals.
+    pub async fn sync_budgets(&self) -> Result<(), DiagnyxError> {
+        sync_budgets(&self.http_client, &self.config, &self.buffer, &self.budgets).await
+    }
+
+    /// Shut down the client: stop its background tasks, waiting for a flush
+    /// or a spend reconciliation they have in progress, then flush the
+    /// remaining calls. Both together
+    /// take at most [`shutdown_timeout`](DiagnyxConfig::shutdown_timeout);
+    /// a background flush still running at the deadline is aborted.
+    ///
+    /// Calls that could not be sent are reported as dropped, unless the
+    /// client is [parked](Self::is_parked) and they are saved to
+    /// `parked_buffer_path`.
+    pub async fn shutdown(&self) -> ShutdownReport {
+        self.shutdown.send_replace(true);
+        let task = self
+            .flush_task
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        let deadline = self
+            .config
+            .shutdown_timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        if let Some(task) = task {
+            if !join_by(task, deadline).await {
+                self.log("Shutdown timed out waiting for the background flush");
+            }
+        }
+        // Stopped before the spend cache is saved for the last time, so that
+        // reconciliation cannot overwrite it afterwards
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap_or_else(|e| e.into_inner()));
+        for task in tasks {
+            join_by(task, deadline).await;
+        }
+
+        let remaining = deadline
+            .map(|deadline| deadline.saturating_duration_since(tokio::time::Instant::now()));
+        let result = self.flush_within(remaining).await;
+        let remaining = deadline
+            .map(|deadline| deadline.saturating_duration_since(tokio::time::Instant::now()));
+        if let Err(e) = with_timeout(remaining, self.sender.sink.close()).await {
+            self.log(&format!("Failed to finish sending calls: {}", e));
+        }
+        self.save_spend();
+        let mut report = ShutdownReport::default();
+        let e = match result {
+            Ok(sent) => {
+                report.flushed = sent.calls;
+                return report;
+            }
+            Err(e) => e,
+        };
+        if let DiagnyxError::PartialFlush { report: sent, .. } = &e {
+            report.flushed = sent.calls;
+        }
+
+        let mut buffer = self.buffer.lock().await;
+        let unsent = buffer.len();
+        match &self.parked {
+            Some(parked) if self.is_parked() => match buffer.park(parked) {
+                Ok(()) => report.parked = unsent,
+                Err(err) => {
+                    self.log(&format!("Failed to save parked calls: {}", err));
+                    report.dropped = unsent;
+                }
+            },
+            _ => report.dropped = unsent,
+        }
+        if report.dropped > 0 {
+            self.config.logger().warn(&format!(
+                "Shutdown dropped {} unsent calls: {}",
+                report.dropped, e
+            ));
+            self.events.emit(SdkEvent::CallDropped {
+                count: report.dropped,
+            });
+        }
+        report.error = Some(e);
+        report
+    }
+
+    /// Persist the buffered calls to `parked_buffer_path` if the process
+    /// panics, so that they are sent by the next client started with the
+    /// same path. The panic hook installed before runs afterwards.
+    ///
+    /// The calls are moved from the buffer to the file, never copied, so a
+    /// panic that is caught does not duplicate them: the client picks them
+    /// up from the file on its next flush. Installing the hook again for the
+    /// same client does nothing.
+    ///
+    /// This is best effort: calls are not saved if the buffer is locked when
+    /// the panic happens. Returns a `ConfigError` if no
+    /// `parked_buffer_path` is configured.
+    pub fn install_panic_hook(&self) -> Result<(), DiagnyxError> {
+        let parked = self.parked.as_ref().map(Arc::downgrade).ok_or_else(|| {
+            DiagnyxError::ConfigError("parked_buffer_path is required to persist calls".into())
+        })?;
+        if self.panic_hook.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        // Weak, so that the hook does not keep a dropped client's buffer, or
+        // its drain lock, alive
+        let buffer = Arc::downgrade(&self.buffer);
+
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if let (Some(buffer), Some(parked)) = (buffer.upgrade(), parked.upgrade()) {
+                if let Ok(mut buffer) = buffer.try_lock() {
+                    let _ = buffer.park(&parked);
+                }
+            }
+            previous(info);
+        }));
+        Ok(())
+    }
+
+    /// Shut the client down when the process is interrupted (SIGINT, or
+    /// Ctrl-C on Windows) or terminated (SIGTERM), then exit with status 128
+    /// plus the signal's number.
+    ///
+    /// The buffered calls are flushed as by [`shutdown`](Self::shutdown),
+    /// and those that could not be sent are saved to `parked_buffer_path`,
+    /// if configured, for the next client started with the same path.
+    /// Handling the signals replaces their default action for the whole
+    /// process, so applications shutting down gracefully on their own should
+    /// call `shutdown` from there instead. Installing the handler again for
+    /// the same client does nothing.
+    ///
+    /// Returns a `ConfigError` if called outside of a Tokio runtime.
+    #[cfg(feature = "signals")]
+    pub fn install_signal_handler(self: &Arc<Self>) -> Result<(), DiagnyxError> {
+        let runtime = tokio::runtime::Handle::try_current().map_err(|_| {
+            DiagnyxError::ConfigError("a Tokio runtime is required to handle signals".into())
+        })?;
+        if self.signal_handler.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let signal = match termination() {
+            Ok(signal) => signal,
+            Err(e) => {
+                self.signal_handler.store(false, Ordering::SeqCst);
+                return Err(e.into());
+            }
+        };
+        let client = Arc::downgrade(self);
+        runtime.spawn(async move {
+            let status = signal.await;
+            if let Some(client) = client.upgrade() {
+                let _ = client.shutdown_on_signal().await;
+            }
+            std::process::exit(status);
+        });
+        Ok(())
+    }
+
+    /// Shut down, saving the calls that could not be sent to
+    /// `parked_buffer_path` if one is configured.
+    #[cfg(feature = "signals")]
+    async fn shutdown_on_signal(&self) -> ShutdownReport {
+        let mut report = self.shutdown().await;
+        if report.dropped == 0 {
+            return report;
+        }
+        if let Some(parked) = &self.parked {
+            match self.buffer.lock().await.park(parked) {
+                Ok(()) => {
+                    report.parked += report.dropped;
+                    report.dropped = 0;
+                }
+                Err(e) => self.log(&format!("Failed to save unsent calls: {}", e)),
+            }
+        }
+        report
+    }
+
+    fn save_spend(&self) {
+        if let Err(e) = self.spend.save() {
+            self.log(&format!("Failed to save spend cache: {}", e));
+        }
     }
 
-    fn start_flush_task(&self) {
+    fn start_reconcile_task(&self, interval_ms: u64) -> JoinHandle<()> {
         let buffer = Arc::clone(&self.buffer);
-        let shutdown = Arc::clone(&self.shutdown);
+        let mut shutdown = self.shutdown.subscribe();
+        let spend = Arc::clone(&self.spend);
         let config = self.config.clone();
+        let logger = config.logger();
         let http_client = self.http_client.clone();
 
         tokio::spawn(async move {
-            let mut ticker = interval(Duration::from_millis(config.flush_interval_ms));
+            let mut ticker = interval(Duration::from_millis(interval_ms));
 
             loop {
-                ticker.tick().await;
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = stopped(&mut shutdown) => break,
+                }
 
-                if *shutdown.lock().await {
-                    break;
+                for project in spend.projects() {
+                    if let Err(e) =
+                        reconcile_project(&http_client, &config, &buffer, &spend, &project).await
+                    {
+                        logger.warn(&format!("Spend reconciliation error: {}", e));
+                    }
+                }
+                if let Err(e) = spend.save() {
+                    logger.warn(&format!("Failed to save spend cache: {}", e));
+                }
+            }
+        })
+    }
+
+    fn start_budget_sync_task(&self, interval_ms: u64) -> JoinHandle<()> {
+        let buffer = Arc::clone(&self.buffer);
+        let mut shutdown = self.shutdown.subscribe();
+        let budgets = Arc::clone(&self.budgets);
+        let config = self.config.clone();
+        let logger = config.logger();
+        let http_client = self.http_client.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_millis(interval_ms));
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = stopped(&mut shutdown) => break,
+                }
+
+                if let Err(e) = sync_budgets(&http_client, &config, &buffer, &budgets).await {
+                    logger.warn(&format!("Budget sync error: {}", e));
+                }
+            }
+        })
+    }
+
+    fn start_heartbeat_task(&self, interval_ms: u64) -> JoinHandle<()> {
+        let mut shutdown = self.shutdown.subscribe();
+        let config = self.config.clone();
+        let logger = config.logger();
+        let http_client = self.http_client.clone();
+        let heartbeat = self.heartbeat.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = interval(Duration::from_millis(interval_ms));
+
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = stopped(&mut shutdown) => break,
+                }
+
+                if let Err(e) = heartbeat.send(&http_client, &config).await {
+                    logger.warn(&format!("Heartbeat error: {}", e));
+                }
+            }
+        })
+    }
+
+    fn start_flush_task(&self) -> JoinHandle<()> {
+        let buffer = Arc::clone(&self.buffer);
+        let mut shutdown = self.shutdown.subscribe();
+        let config = self.config.clone();
+        let logger = config.logger();
+        let http_client = self.http_client.clone();
+        let events = self.events.clone();
+        let spend = Arc::clone(&self.spend);
+        let audit = Arc::clone(&self.audit);
+        let auth = Arc::clone(&self.auth);
+        let failures = Arc::clone(&self.failures);
+        let watermarks = Arc::clone(&self.watermarks);
+        let schedule = Arc::clone(&self.schedule);
+        let sender = Arc::clone(&self.sender);
+        let queue = self.parked.clone();
+
+        tokio::spawn(async move {
+            let flush_interval = Duration::from_millis(config.flush_interval_ms);
+            if config.flush_jitter {
+                tokio::select! {
+                    _ = tokio::time::sleep(phase_offset(flush_interval)) => {}
+                    _ = stopped(&mut shutdown) => return,
+                }
+            }
+
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(schedule.interval(flush_interval)) => {}
+                    _ = stopped(&mut shutdown) => break,
+                }
+
+                if let Err(e) = audit.send_pending(&http_client, &config).await {
+                    logger.warn(&format!("Failed to send audit events: {}", e));
+                }
+
+                if auth.parked().is_some() {
+                    continue;
                 }
 
+                // Pick up calls parked by other processes sharing the file
+                let restored = queue.as_deref().map(ParkedQueue::take).unwrap_or_default();
                 let calls = {
                     let mut buf = buffer.lock().await;
+                    buf.extend(restored);
                     if buf.is_empty() {
                         continue;
                     }
-                    std::mem::take(&mut *buf)
+                    buf.take()
                 };
+                if let Err(e) = spend.save() {
+                    logger.warn(&format!("Failed to save spend cache: {}", e));
+                }
+
+                let count = calls.len();
+                events.emit(SdkEvent::FlushStarted { count });
 
-                if let Err(e) = Self::send_batch_static(&http_client, &config, &calls).await {
-                    if config.debug {
-                        eprintln!("[Diagnyx] Background flush error: {}", e);
+                let (result, unsent) = sender.send_all(calls, None).await;
+                let parked = auth.record(&result, config.auth_failure_threshold);
+                if let Err(e) = result {
+                    if let Some(message) = failures.failure(&e.to_string()) {
+                        logger.warn(&e.with_request_id(message));
                     }
-                    // Put calls back
+                    events.emit(SdkEvent::FlushFailed {
+                        error: e.to_string(),
+                    });
                     let mut buf = buffer.lock().await;
-                    let mut restored = calls;
-                    restored.append(&mut *buf);
-                    *buf = restored;
-                } else if config.debug {
-                    println!("[Diagnyx] Flushed {} calls", calls.len());
+                    buf.restore(unsent, &config, &events);
+                    if let Some(status_code) = parked {
+                        park_buffer(
+                            &config,
+                            queue.as_deref(),
+                            &mut buf,
+                            &events,
+                            &e,
+                            status_code,
+                        );
+                    }
+                    watermarks.update(buf.len(), &config, &events);
+                } else {
+                    logger.log(&format!("Flushed {} calls", count));
+                    events.emit(SdkEvent::FlushSucceeded { count });
+                    report_success(&failures, &config, &events);
+                    watermarks.update(buffer.lock().await.len(), &config, &events);
                 }
             }
-        });
+        })
     }
 
-    async fn send_batch(&self, calls: &[LLMCall]) -> Result<(), DiagnyxError> {
-        Self::send_batch_static(&self.http_client, &self.config, calls).await
+    fn log(&self, message: &str) {
+        self.config.logger().log(message);
     }
+}
 
-    async fn send_batch_static(
-        http_client: &Client,
-        config: &DiagnyxConfig,
-        calls: &[LLMCall],
-    ) -> Result<(), DiagnyxError> {
-        let payload = BatchRequest {
-            calls: calls.to_vec(),
-        };
-
-        let url = format!("{}/api/v1/ingest/llm/batch", config.base_url);
+/// What sending a batch needs, shared with the tasks sending batches
+/// concurrently.
+struct BatchSender {
+    sink: Arc<dyn Sink>,
+    config: DiagnyxConfig,
+    schedule: Arc<FlushSchedule>,
+    /// Report of the last successful [`send_all`](Self::send_all).
+    last_report: std::sync::Mutex<Option<FlushReport>>,
+}
 
-        let mut last_error = None;
+impl BatchSender {
+    fn last_report(&self) -> Option<FlushReport> {
+        self.last_report
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
 
-        for attempt in 0..config.max_retries {
-            let result = http_client
-                .post(&url)
-                .header("Content-Type", "application/json")
-                .header("Authorization", format!("Bearer {}", config.api_key))
-                .json(&payload)
-                .send()
-                .await;
+    async fn send(&self, calls: &[LLMCall]) -> Result<FlushReport, DiagnyxError> {
+        self.sink.write(calls).await
+    }
 
-            match result {
-                Ok(response) => {
-                    let status = response.status();
-                    if status.is_success() {
-                        return Ok(());
-                    }
+    /// Send `calls` in batches within `timeout`, returning the combined
+    /// report and the calls of the batches that were not accepted.
+    ///
+    /// Batches still in flight when the timeout fires are abandoned and
+    /// count as not accepted.
+    async fn send_all(
+        self: &Arc<Self>,
+        calls: Vec<LLMCall>,
+        timeout: Option<Duration>,
+    ) -> (Result<FlushReport, DiagnyxError>, Vec<LLMCall>) {
+        let sent = Arc::new(std::sync::Mutex::new(ChunkedSend::default()));
+        let outcome = with_timeout(timeout, async {
+            self.send_chunked(&calls, &sent).await;
+            Ok(())
+        })
+        .await;
+        let sent = std::mem::take(&mut *sent.lock().unwrap_or_else(|e| e.into_inner()));
+        let (result, unsent) = sent.into_result(calls, self.batch_size(), outcome.err());
+        if let Ok(report) = &result {
+            *self.last_report.lock().unwrap_or_else(|e| e.into_inner()) = Some(report.clone());
+        }
+        (result, unsent)
+    }
 
-                    let message = response.text().await.unwrap_or_default();
-                    last_error = Some(DiagnyxError::ApiError {
-                        status_code: status.as_u16(),
-                        message,
-                    });
+    fn batch_size(&self) -> usize {
+        self.config.batch_size.max(1)
+    }
 
-                    if status.is_client_error() {
-                        break;
-                    }
-                }
-                Err(e) => {
-                    last_error = Some(DiagnyxError::HttpError(e));
-                }
+    /// Send `calls` in batches of `batch_size`, up to
+    /// `max_concurrent_flushes` at a time and spaced within the rate the API
+    /// requested. A failed batch does not stop the others. Each batch is
+    /// recorded in `sent` as soon as it completes.
+    async fn send_chunked(
+        self: &Arc<Self>,
+        calls: &[LLMCall],
+        sent: &Arc<std::sync::Mutex<ChunkedSend>>,
+    ) {
+        let mut batches = calls.chunks(self.batch_size()).enumerate();
+        let mut sending = JoinSet::new();
+        let mut indexes = HashMap::new();
+        loop {
+            while sending.len() < self.config.max_concurrent_flushes.max(1) {
+                let Some((index, batch)) = batches.next() else {
+                    break;
+                };
+                let sender = Arc::clone(self);
+                let sent = Arc::clone(sent);
+                let batch = batch.to_vec();
+                let task = sending.spawn(within_deadline(async move {
+                    sender.schedule.pace().await;
+                    let result = sender.send(&batch).await;
+                    sent.lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .record(index, result);
+                }));
+                indexes.insert(task.id(), index);
             }
-
-            if attempt < config.max_retries - 1 {
-                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+            let Some(joined) = sending.join_next_with_id().await else {
+                break;
+            };
+            if let Err(e) = joined {
+                let error = std::io::Error::other(e.to_string()).into();
+                sent.lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .record(indexes[&e.id()], Err(error));
             }
         }
+    }
+}
 
-        Err(last_error.unwrap_or(DiagnyxError::MaxRetriesExceeded))
+/// Cut `text` to at most `max_len` bytes, on a character boundary, marking
+/// it as truncated.
+pub(crate) fn truncate_text(text: String, max_len: usize) -> String {
+    if text.len() <= max_len {
+        return text;
     }
+    let mut end = max_len;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... [truncated]", &text[..end])
+}
 
-    fn log(&self, message: &str) {
-        if self.config.debug {
-            println!("[Diagnyx] {}", message);
+/// Outcome of sending calls in batches.
+#[derive(Default)]
+struct ChunkedSend {
+    /// Reports of the batches sent, by batch index.
+    reports: Vec<(usize, FlushReport)>,
+    /// Errors of the batches that failed, by batch index.
+    failed: Vec<(usize, DiagnyxError)>,
+}
+
+impl ChunkedSend {
+    fn record(&mut self, index: usize, result: Result<FlushReport, DiagnyxError>) {
+        match result {
+            Ok(report) => self.reports.push((index, report)),
+            Err(e) => self.failed.push((index, e)),
         }
     }
+
+    /// The combined report of the batches of `calls` that were sent, with
+    /// the calls of the others.
+    ///
+    /// Fails with the error of the first batch that failed, or else
+    /// `interrupted` if some were not sent, wrapped in
+    /// [`DiagnyxError::PartialFlush`] if other batches were sent.
+    fn into_result(
+        mut self,
+        calls: Vec<LLMCall>,
+        batch_size: usize,
+        interrupted: Option<DiagnyxError>,
+    ) -> (Result<FlushReport, DiagnyxError>, Vec<LLMCall>) {
+        self.reports.sort_by_key(|(index, _)| *index);
+        let unsent: Vec<LLMCall> = calls
+            .chunks(batch_size)
+            .enumerate()
+            .filter(|(index, _)| !self.reports.iter().any(|(sent, _)| sent == index))
+            .flat_map(|(_, batch)| batch.iter().cloned())
+            .collect();
+        let report = self
+            .reports
+            .into_iter()
+            .map(|(_, report)| report)
+            .reduce(FlushReport::merge)
+            .unwrap_or_default();
+
+        self.failed.sort_by_key(|(index, _)| *index);
+        let error = match self.failed.into_iter().next() {
+            Some((_, error)) => Some(error),
+            None if !unsent.is_empty() => interrupted,
+            None => None,
+        };
+        let result = match error {
+            None => Ok(report),
+            Some(error) if report.calls == 0 => Err(error),
+            Some(error) => Err(DiagnyxError::PartialFlush {
+                report: Box::new(report),
+                error: Box::new(error),
+            }),
+        };
+        (result, unsent)
+    }
 }
 
-use chrono::DateTime;
+/// Replace the local month-to-date spend of `project` with the API's total
+/// plus the cost of calls not yet sent.
+async fn reconcile_project(
+    http_client: &Client,
+    config: &DiagnyxConfig,
+    buffer: &Mutex<CallBuffer>,
+    spend: &SpendCache,
+    project: &str,
+) -> Result<f64, DiagnyxError> {
+    let url = format!("{}/api/v1/spend/month-to-date", config.base_url);
+
+    let response = send_with_retry(
+        &config.retry_policy,
+        config.rate_limiter.as_ref(),
+        Method::GET,
+        |method| {
+            http_client
+                .request(method, &url)
+                .header("Authorization", format!("Bearer {}", config.api_key))
+                .query(&[("project_id", project)])
+        },
+    )
+    .await?;
+    let server: MonthToDateResponse = response.json().await?;
+
+    let pending: f64 = buffer
+        .lock()
+        .await
+        .iter()
+        .filter(|call| {
+            call.project_id.as_ref().is_some_and(|id| id == project)
+                && spend::month_of(call.timestamp) == server.month
+        })
+        .map(spend::call_cost)
+        .sum();
+
+    let total = server.total_cost + pending;
+    spend.reconcile(project, &server.month, total);
+    Ok(total)
+}
 
-/// Track an LLM call with automatic timing.
-pub async fn track_call<F, T>(
-    client: &DiagnyxClient,
-    provider: crate::Provider,
-    model: impl Into<String>,
-    f: F,
-) -> Result<T, DiagnyxError>
-where
-    F: FnOnce() -> Result<(T, i32, i32), Box<dyn std::error::Error + Send + Sync>>,
-{
+/// Replace the local total of every budget with the API's total for its
+/// current window plus the cost of calls not yet sent.
+async fn sync_budgets(
+    http_client: &Client,
+    config: &DiagnyxConfig,
+    buffer: &Mutex<CallBuffer>,
+    budgets: &BudgetTracker,
+) -> Result<(), DiagnyxError> {
+    let url = format!("{}/api/v1/spend/usage", config.base_url);
+
+    for (index, query) in budgets.queries() {
+        let response = send_with_retry(
+            &config.retry_policy,
+            config.rate_limiter.as_ref(),
+            Method::GET,
+            |method| {
+                http_client
+                    .request(method, &url)
+                    .header("Authorization", format!("Bearer {}", config.api_key))
+                    .query(&query)
+            },
+        )
+        .await?;
+        let server: WindowSpendResponse = response.json().await?;
+        budgets.sync(index, server.total_cost, &buffer.lock().await);
+    }
+    Ok(())
+}
+
+/// Log and emit a recovery if the flush that just succeeded followed
+/// failed ones.
+fn report_success(failures: &FailureReporter, config: &DiagnyxConfig, events: &EventBus) {
+    if let Some(count) = failures.success() {
+        config
+            .logger()
+            .log(&format!("Flush recovered after {} failures", count));
+        events.emit(SdkEvent::FlushRecovered { failures: count });
+    }
+}
+
+/// Stop flushing after repeated auth failures: persist the buffered calls
+/// if configured, then notify subscribers and the auth failure callback.
+pub(crate) fn park_buffer(
+    config: &DiagnyxConfig,
+    parked: Option<&ParkedQueue>,
+    calls: &mut CallBuffer,
+    events: &EventBus,
+    error: &DiagnyxError,
+    status_code: u16,
+) {
+    let logger = config.logger();
+    let count = calls.len();
+    logger.warn(&format!(
+        "API key rejected (HTTP {}); parking {} calls",
+        status_code, count
+    ));
+    if let Some(parked) = parked {
+        // Parked calls are for another process, or the next client, to send
+        parked.release_drain();
+        if let Err(e) = calls.park(parked) {
+            logger.warn(&format!("Failed to save parked calls: {}", e));
+        }
+    }
+    events.emit(SdkEvent::AuthFailed {
+        status_code,
+        parked: count,
+    });
+    if let Some(callback) = &config.on_auth_failure {
+        callback.call(error);
+    }
+}
+
+/// Apply the sampling policy and adaptive sampling to a call whose cost
+/// has been estimated, after uniform sampling at `rate` if the policy is
+/// uniform.
+///
+/// Records the effective rate on the call and returns `false` if the call
+/// is dropped.
+fn sample_prepared(
+    call: &mut LLMCall,
+    rate: f64,
+    config: &DiagnyxConfig,
+    sampler: Option<&AdaptiveSampler>,
+) -> bool {
+    let mut applied = rate;
+    if !config.sampling.is_uniform() {
+        let Some(kept) = config.sampling.sample(call, rate) else {
+            return false;
+        };
+        applied = kept;
+    }
+    if let Some(sampler) = sampler {
+        let Some(adaptive) = sampler.sample(call) else {
+            return false;
+        };
+        applied *= adaptive;
+    }
+    if applied < 1.0 || sampler.is_some() {
+        call.sample_rate = Some(applied);
+    }
+    true
+}
+
+/// Wait for `task` until `deadline`, aborting it if it is still running
+/// then. Returns whether it finished in time.
+async fn join_by(mut task: JoinHandle<()>, deadline: Option<tokio::time::Instant>) -> bool {
+    let Some(deadline) = deadline else {
+        let _ = task.await;
+        return true;
+    };
+    if tokio::time::timeout_at(deadline, &mut task).await.is_ok() {
+        return true;
+    }
+    task.abort();
+    // An aborted task stops at its next await, so wait for it not to run
+    // past the shutdown
+    let _ = task.await;
+    false
+}
+
+/// Wait for SIGINT or SIGTERM, returning the exit status for it.
+#[cfg(all(feature = "signals", unix))]
+fn termination() -> std::io::Result<impl std::future::Future<Output = i32>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = interrupt.recv() => 130,
+            _ = terminate.recv() => 143,
+        }
+    })
+}
+
+/// Wait for Ctrl-C, returning the exit status for it.
+#[cfg(all(feature = "signals", not(unix)))]
+fn termination() -> std::io::Result<impl std::future::Future<Output = i32>> {
+    Ok(async {
+        let _ = tokio::signal::ctrl_c().await;
+        130
+    })
+}
+
+/// Whether a call is dropped when sampling at `rate`.
+pub(crate) fn sampled_out(rate: f64) -> bool {
+    rate < 1.0 && random_unit() >= rate
+}
+
+/// The configured flush mode, or [`FlushMode::Batch`] once the server has
+/// rejected compact batches.
+pub(crate) fn negotiated_mode(config: &DiagnyxConfig, compact_rejected: &AtomicBool) -> FlushMode {
+    match config.flush_mode {
+        FlushMode::Compact if compact_rejected.load(Ordering::Relaxed) => FlushMode::Batch,
+        mode => mode,
+    }
+}
+
+/// Whether the server rejected a batch for its format rather than its
+/// contents.
+pub(crate) fn rejects_format(error: &DiagnyxError) -> bool {
+    matches!(error, DiagnyxError::ApiError { status_code, .. }
+        if *status_code == StatusCode::UNSUPPORTED_MEDIA_TYPE.as_u16())
+}
+
+/// Serialize a batch of calls in `mode`, compressing it if configured and
+/// the body is large enough. Returns the body and its content encoding, if
+/// any.
+pub(crate) fn batch_body(
+    config: &DiagnyxConfig,
+    mode: FlushMode,
+    calls: &[LLMCall],
+) -> Result<(Vec<u8>, Option<&'static str>), DiagnyxError> {
+    let body = match mode {
+        FlushMode::Batch => serde_json::to_vec(&BatchRequest {
+            calls: calls.to_vec(),
+        })?,
+        FlushMode::Ndjson => ndjson(calls)?,
+        FlushMode::Compact => compact::encode(calls)?,
+    };
+
+    #[cfg(feature = "compression")]
+    if let Some(compression) = config.compression {
+        if body.len() >= config.compression_threshold {
+            return Ok((compression.compress(&body)?, Some(compression.encoding())));
+        }
+    }
+    #[cfg(not(feature = "compression"))]
+    let _ = config;
+
+    Ok((body, None))
+}
+
+/// `calls` as JSON, one per line.
+fn ndjson(calls: &[LLMCall]) -> Result<Vec<u8>, DiagnyxError> {
+    let mut body = Vec::new();
+    for call in calls {
+        serde_json::to_writer(&mut body, call)?;
+        body.push(b'\n');
+    }
+    Ok(body)
+}
+
+/// Destination of flushed calls.
+///
+/// By default calls are sent to the API by an [`HttpSink`]. A sink set with
+/// [`DiagnyxConfig::sink`] receives them instead, e.g. to see exactly what
+/// would be sent during development, or to ship calls out-of-band from an
+/// air-gapped network. Calls are written as they would be sent, after
+/// sampling, enrichment and truncation.
+///
+/// Sinks are called from the client's tasks, so those doing blocking I/O
+/// should move it off the runtime, e.g. with `spawn_blocking`.
+#[async_trait]
+pub trait Sink: fmt::Debug + Send + Sync {
+    /// Write a batch of calls, returning its report. An error keeps them in
+    /// the buffer for the next flush.
+    ///
+    /// Sinks without an API response report only the number of calls:
+    /// `FlushReport { calls: calls.len(), ..Default::default() }`.
+    async fn write(&self, calls: &[LLMCall]) -> Result<FlushReport, DiagnyxError>;
+
+    /// Finish sending the calls written so far, e.g. by ending an open
+    /// request. Called when the client shuts down, after its final flush.
+    async fn close(&self) -> Result<(), DiagnyxError> {
+        Ok(())
+    }
+}
+
+/// Longest an NDJSON stream stays open before its request ends.
+const STREAM_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Bytes of lines after which an NDJSON stream is ended, bounding the lines
+/// kept to resend if its request fails.
+const STREAM_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// Batches queued for an NDJSON stream before writes wait for it.
+const STREAM_QUEUE: usize = 16;
+
+/// Calls streamed to the ingest endpoint in [`FlushMode::Ndjson`].
+#[derive(Default)]
+struct NdjsonStream {
+    open: Option<OpenStream>,
+    /// Lines of a failed stream, sent first on the next one.
+    resend: Vec<u8>,
+}
+
+/// A request whose body is fed the lines of each batch as it is written.
+struct OpenStream {
+    lines: mpsc::Sender<Vec<u8>>,
+    response: JoinHandle<Result<Response, reqwest::Error>>,
+    request_id: String,
+    /// Lines sent on the stream, kept until its response.
+    sent: Vec<u8>,
+}
+
+/// Sends calls to the Diagnyx API, in the configured
+/// [`flush_mode`](DiagnyxConfig::flush_mode). This is the sink of clients
+/// without one configured.
+///
+/// In [`FlushMode::Ndjson`], batches are streamed over one long-lived
+/// request, ended after a minute, after 4 MiB of lines, or when the sink is
+/// [closed](Sink::close), and the lines of a request that fails are sent
+/// again on the next. The request is driven by a task on the runtime the
+/// sink first writes on, which must outlive it.
+pub struct HttpSink {
+    http_client: Client,
+    config: DiagnyxConfig,
+    /// Set once the server rejects compact batches.
+    compact_rejected: AtomicBool,
+    schedule: Arc<FlushSchedule>,
+    stream: Mutex<NdjsonStream>,
+}
+
+impl fmt::Debug for HttpSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpSink")
+            .field("base_url", &self.config.base_url)
+            .field("flush_mode", &self.config.flush_mode)
+            .finish()
+    }
+}
+
+impl HttpSink {
+    /// Send calls to the API of `config`, with its HTTP settings.
+    pub fn new(config: DiagnyxConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config.http.client(REQUEST_TIMEOUT)?;
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Send calls to the API of `config` with `http_client`.
+    pub fn with_http_client(config: DiagnyxConfig, http_client: Client) -> Self {
+        Self::with_schedule(config, http_client, Arc::default())
+    }
+
+    /// A sink that reports the API's flush rate to `schedule`.
+    fn with_schedule(
+        config: DiagnyxConfig,
+        http_client: Client,
+        schedule: Arc<FlushSchedule>,
+    ) -> Self {
+        Self {
+            http_client,
+            config,
+            compact_rejected: AtomicBool::new(false),
+            schedule,
+            stream: Mutex::default(),
+        }
+    }
+
+    /// Write `calls` to the open NDJSON stream, opening one if there is
+    /// none or it has ended.
+    async fn stream(&self, calls: &[LLMCall]) -> Result<FlushReport, DiagnyxError> {
+        let lines = ndjson(calls)?;
+        let report = FlushReport {
+            calls: calls.len(),
+            ..Default::default()
+        };
+        let mut state = self.stream.lock().await;
+
+        if let Some(mut open) = state.open.take() {
+            let full = !open.sent.is_empty() && open.sent.len() + lines.len() > STREAM_MAX_BYTES;
+            if !full && open.lines.send(lines.clone()).await.is_ok() {
+                open.sent.extend_from_slice(&lines);
+                state.open = Some(open);
+                return Ok(report);
+            }
+            self.finish(&mut state, open).await?;
+        }
+
+        let mut open = self.open_stream();
+        open.sent = std::mem::take(&mut state.resend);
+        let mut body = open.sent.clone();
+        body.extend_from_slice(&lines);
+        if open.lines.send(body).await.is_err() {
+            self.finish(&mut state, open).await?;
+            return Err(DiagnyxError::IoError(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "NDJSON stream ended before it was written",
+            )));
+        }
+        open.sent.extend_from_slice(&lines);
+        state.open = Some(open);
+        Ok(report)
+    }
+
+    /// Start a request streaming the lines sent to the returned stream, which
+    /// ends once its sender is dropped or after [`STREAM_MAX_AGE`].
+    fn open_stream(&self) -> OpenStream {
+        let (lines, receiver) = mpsc::channel::<Vec<u8>>(STREAM_QUEUE);
+        let deadline = Box::pin(tokio::time::sleep(STREAM_MAX_AGE));
+        let body = futures::stream::unfold(
+            (receiver, deadline),
+            |(mut receiver, mut deadline)| async move {
+                let line = tokio::select! {
+                    biased;
+                    line = receiver.recv() => line,
+                    _ = &mut deadline => {
+                        // Refuse new lines, but send those already queued
+                        receiver.close();
+                        receiver.recv().await
+                    }
+                };
+                line.map(|line| (Ok::<_, Infallible>(line), (receiver, deadline)))
+            },
+        );
+
+        let config = &self.config;
+        let mode = FlushMode::Ndjson;
+        let request_id = new_request_id();
+        let request = self
+            .http_client
+            .post(format!("{}{}", config.base_url, mode.path()))
+            .header("Content-Type", mode.content_type())
+            .header("Authorization", format!("Bearer {}", config.api_key))
+            .header(REQUEST_ID_HEADER, &request_id)
+            .timeout(STREAM_MAX_AGE + REQUEST_TIMEOUT)
+            .body(Body::wrap_stream(body));
+        let limiter = config.rate_limiter.clone();
+        let response = tokio::spawn(async move {
+            if let Some(limiter) = limiter {
+                limiter.acquire().await;
+            }
+            request.send().await
+        });
+
+        OpenStream {
+            lines,
+            response,
+            request_id,
+            sent: Vec::new(),
+        }
+    }
+
+    /// End `open`'s request and wait for its response, keeping its lines to
+    /// send again if it failed.
+    async fn finish(&self, state: &mut NdjsonStream, open: OpenStream) -> Result<(), DiagnyxError> {
+        drop(open.lines);
+        let result = match open.response.await {
+            Ok(Ok(response)) if response.status().is_success() => {
+                self.schedule.sent(response.headers());
+                Ok(())
+            }
+            Ok(Ok(response)) => Err(DiagnyxError::ApiError {
+                status_code: response.status().as_u16(),
+                message: response.text().await.unwrap_or_default(),
+                request_id: Some(open.request_id),
+            }),
+            Ok(Err(e)) => Err(DiagnyxError::HttpError(e)),
+            Err(e) => Err(DiagnyxError::IoError(e.into())),
+        };
+        if result.is_err() {
+            state.resend = open.sent;
+        }
+        result
+    }
+
+    async fn send_encoded(
+        &self,
+        mode: FlushMode,
+        calls: &[LLMCall],
+    ) -> Result<FlushReport, DiagnyxError> {
+        let config = &self.config;
+        let (body, encoding) = batch_body(config, mode, calls)?;
+        let url = format!("{}{}", config.base_url, mode.path());
+
+        let send = send_with_retry(
+            &config.retry_policy,
+            config.rate_limiter.as_ref(),
+            Method::POST,
+            |method| {
+                let request = self
+                    .http_client
+                    .request(method, &url)
+                    .header("Content-Type", mode.content_type())
+                    .header("Authorization", format!("Bearer {}", config.api_key))
+                    .body(body.clone());
+                match encoding {
+                    Some(encoding) => request.header("Content-Encoding", encoding),
+                    None => request,
+                }
+            },
+        );
+        #[cfg(feature = "tracing")]
+        let send = tracing::Instrument::instrument(
+            send,
+            tracing::debug_span!(target: "diagnyx", "flush", batch_size = calls.len()),
+        );
+        let response = send.await?;
+        self.schedule.sent(response.headers());
+
+        Ok(FlushReport::from_response(
+            calls.len(),
+            &response.bytes().await?,
+            config,
+        ))
+    }
+}
+
+#[async_trait]
+impl Sink for HttpSink {
+    async fn write(&self, calls: &[LLMCall]) -> Result<FlushReport, DiagnyxError> {
+        let mode = negotiated_mode(&self.config, &self.compact_rejected);
+        if mode == FlushMode::Ndjson {
+            return self.stream(calls).await;
+        }
+        match self.send_encoded(mode, calls).await {
+            Err(e) if mode == FlushMode::Compact && rejects_format(&e) => {
+                self.compact_rejected.store(true, Ordering::Relaxed);
+                self.config
+                    .logger()
+                    .log("Compact batches not accepted; sending plain batches");
+                self.send_encoded(FlushMode::Batch, calls).await
+            }
+            result => result,
+        }
+    }
+
+    async fn close(&self) -> Result<(), DiagnyxError> {
+        let mut state = self.stream.lock().await;
+        let open = match state.open.take() {
+            Some(open) => open,
+            None if state.resend.is_empty() => return Ok(()),
+            None => {
+                let mut open = self.open_stream();
+                open.sent = std::mem::take(&mut state.resend);
+                let _ = open.lines.send(open.sent.clone()).await;
+                open
+            }
+        };
+        self.finish(&mut state, open).await
+    }
+}
+
+/// Appends calls to a file, one JSON object per line.
+#[derive(Debug)]
+pub struct NdjsonFileSink {
+    path: PathBuf,
+    /// Keeps concurrent flushes from interleaving their lines.
+    lock: Mutex<()>,
+}
+
+impl NdjsonFileSink {
+    /// Append to the file at `path`, created on the first write.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for NdjsonFileSink {
+    async fn write(&self, calls: &[LLMCall]) -> Result<FlushReport, DiagnyxError> {
+        let lines = ndjson(calls)?;
+        let path = self.path.clone();
+        let _guard = self.lock.lock().await;
+        write_blocking(move || {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(&lines)
+        })
+        .await?;
+        Ok(FlushReport {
+            calls: calls.len(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Prints calls to standard output, one JSON object per line.
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+#[async_trait]
+impl Sink for StdoutSink {
+    async fn write(&self, calls: &[LLMCall]) -> Result<FlushReport, DiagnyxError> {
+        let lines = ndjson(calls)?;
+        write_blocking(move || {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&lines)?;
+            stdout.flush()
+        })
+        .await?;
+        Ok(FlushReport {
+            calls: calls.len(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Run `write` on the blocking thread pool.
+async fn write_blocking(
+    write: impl FnOnce() -> std::io::Result<()> + Send + 'static,
+) -> Result<(), DiagnyxError> {
+    tokio::task::spawn_blocking(write)
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))??;
+    Ok(())
+}
+
+pub(crate) fn random_bits() -> u64 {
+    // Each RandomState is seeded with fresh random keys
+    RandomState::new().build_hasher().finish()
+}
+
+/// A random number in `[0, 1)`.
+pub(crate) fn random_unit() -> f64 {
+    (random_bits() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Resolve once the client is shut down. Never resolves for a client dropped
+/// without a shutdown, whose tasks keep running.
+async fn stopped(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|stopped| *stopped).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Mask personal data in the captured content of `call` with the
+/// configured redactor, then truncate it to `content_max_length`.
+fn redact_call(call: &mut LLMCall, config: &DiagnyxConfig) {
+    let max_len = if config.content_max_length > 0 {
+        config.content_max_length
+    } else {
+        10000
+    };
+    for content in [&mut call.full_prompt, &mut call.full_response]
+        .into_iter()
+        .flatten()
+    {
+        *content = truncate_text(config.content_redactor.redact(content), max_len);
+    }
+}
+
+/// Prepare a call that passed the filters: tag test traffic, redact its
+/// content, estimate the cost, apply the remaining sampling, export it as
+/// a span and bound its size. Returns `None` if the call is sampled out.
+pub(crate) fn finish_prepare(
+    mut call: LLMCall,
+    rate: f64,
+    config: &DiagnyxConfig,
+    sampler: Option<&AdaptiveSampler>,
+    events: &EventBus,
+) -> Option<LLMCall> {
+    if config.is_test_mode() {
+        call.environment = Some(TEST_ENVIRONMENT.to_string());
+    }
+    redact_call(&mut call, config);
+    config.cost_calculator.apply(&mut call);
+    if !sample_prepared(&mut call, rate, config, sampler) {
+        return None;
+    }
+    #[cfg(feature = "otel")]
+    if let Some(otel) = &config.otel {
+        otel.export(&mut call);
+    }
+    if let Some(max_bytes) = config.max_call_bytes {
+        let fields = limit_call_size(&mut call, max_bytes);
+        if !fields.is_empty() {
+            config
+                .logger()
+                .log(&format!("Trimmed oversized call: {}", fields.join(", ")));
+            events.emit(SdkEvent::CallTrimmed { fields });
+        }
+    }
+    Some(call)
+}
+
+use chrono::DateTime;
+
+/// Track an LLM call with automatic timing.
+pub async fn track_call<F, T>(
+    client: &DiagnyxClient,
+    provider: crate::Provider,
+    model: impl Into<String>,
+    f: F,
+) -> Result<T, DiagnyxError>
+where
+    F: FnOnce() -> Result<(T, i32, i32), Box<dyn std::error::Error + Send + Sync>>,
+{
     let model = model.into();
     let start = std::time::Instant::now();
 
@@ -273,6 +1965,10 @@ where
 
 /// Track an LLM call with full content capture.
 /// Use this for providers without dedicated wrappers (like Anthropic).
+///
+/// With the `tokenizers` feature, token counts given as zero are counted
+/// locally from the prompt and response.
+#[allow(clippy::too_many_arguments)]
 pub async fn track_call_with_content(
     client: &DiagnyxClient,
     provider: crate::Provider,
@@ -283,11 +1979,25 @@ pub async fn track_call_with_content(
     output_tokens: i32,
     latency_ms: i64,
 ) {
-    let config = client.config.clone();
+    let capture_full_content = client.capture_full_content();
     let model = model.into();
     let prompt = prompt.into();
     let response = response.into();
 
+    #[cfg(feature = "tokenizers")]
+    let (input_tokens, output_tokens) = (
+        if input_tokens == 0 {
+            crate::tokens::count(&model, &prompt)
+        } else {
+            input_tokens
+        },
+        if output_tokens == 0 {
+            crate::tokens::count(&model, &response)
+        } else {
+            output_tokens
+        },
+    );
+
     let mut builder = LLMCall::builder()
         .provider(provider)
         .model(&model)
@@ -296,28 +2006,8 @@ pub async fn track_call_with_content(
         .latency_ms(latency_ms)
         .status(crate::CallStatus::Success);
 
-    if config.capture_full_content {
-        let max_len = if config.content_max_length > 0 {
-            config.content_max_length
-        } else {
-            10000
-        };
-
-        let truncated_prompt = if prompt.len() > max_len {
-            format!("{}... [truncated]", &prompt[..max_len])
-        } else {
-            prompt
-        };
-
-        let truncated_response = if response.len() > max_len {
-            format!("{}... [truncated]", &response[..max_len])
-        } else {
-            response
-        };
-
-        builder = builder
-            .full_prompt(truncated_prompt)
-            .full_response(truncated_response);
+    if capture_full_content {
+        builder = builder.full_prompt(prompt).full_response(response);
     }
 
     client.track(builder.build()).await;
@@ -326,146 +2016,1284 @@ pub async fn track_call_with_content(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{CallStatus, DiagnyxConfig, LLMCall, Provider};
-    use wiremock::matchers::{header, method, path};
+    use crate::{CallStatus, CostSampling, DiagnyxConfig, LLMCall, Provider};
+    use wiremock::matchers::{
+        body_partial_json, body_string_contains, header, method, path, query_param,
+    };
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     async fn create_mock_client(server: &MockServer) -> DiagnyxClient {
         DiagnyxClient::with_config(
             DiagnyxConfig::new("test-api-key")
-                .base_url(&server.uri())
+                .base_url(server.uri())
                 .flush_interval_ms(60000) // Disable auto-flush
                 .max_retries(1),
         )
     }
 
     #[tokio::test]
-    async fn test_create_client_with_api_key() {
-        let client = DiagnyxClient::new("test-api-key");
-        assert_eq!(client.buffer_size().await, 0);
+    async fn test_create_client_with_api_key() {
+        let client = DiagnyxClient::new("test-api-key");
+        assert_eq!(client.buffer_size().await, 0);
+        let _ = client.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_try_new_returns_client() {
+        let client = DiagnyxClient::try_new("test-api-key").unwrap();
+        assert_eq!(client.buffer_size().await, 0);
+        let _ = client.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_track_adds_to_buffer() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 1,
+                "total_cost": 0.001,
+                "total_tokens": 150,
+                "ids": ["id-1"]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server).await;
+
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .input_tokens(100)
+            .output_tokens(50)
+            .status(CallStatus::Success)
+            .build();
+
+        client.track(call).await;
+
+        assert_eq!(client.buffer_size().await, 1);
+        let _ = client.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_track_all_adds_multiple() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 3,
+                "total_cost": 0.003,
+                "total_tokens": 450,
+                "ids": ["id-1", "id-2", "id-3"]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server).await;
+
+        let calls = vec![
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model("gpt-4")
+                .build(),
+            LLMCall::builder()
+                .provider(Provider::Anthropic)
+                .model("claude-3")
+                .build(),
+            LLMCall::builder()
+                .provider(Provider::Google)
+                .model("gemini")
+                .build(),
+        ];
+
+        client.track_all(calls).await;
+
+        assert_eq!(client.buffer_size().await, 3);
+        let _ = client.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_flush_sends_to_api() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .and(header("Authorization", "Bearer test-api-key"))
+            .and(header("Content-Type", "application/json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 1
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server).await;
+
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .input_tokens(100)
+            .output_tokens(50)
+            .build();
+
+        client.track(call).await;
+        assert_eq!(client.buffer_size().await, 1);
+
+        let result = client.flush().await;
+        assert!(result.is_ok());
+        assert_eq!(client.buffer_size().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_flush_reports_api_totals() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 2,
+                "total_cost": 0.003,
+                "total_tokens": 450,
+                "ids": ["id-1", "id-2"]
+            })))
+            .mount(&server)
+            .await;
+        let client = create_mock_client(&server).await;
+        let call = || {
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model("gpt-4")
+                .build()
+        };
+
+        client.track_all(vec![call(), call()]).await;
+        let report = client.flush().await.unwrap();
+        let expected = FlushReport {
+            calls: 2,
+            response: Some(BatchResponse {
+                tracked: 2,
+                total_cost: 0.003,
+                total_tokens: 450,
+                ids: vec!["id-1".to_string(), "id-2".to_string()],
+            }),
+        };
+        assert_eq!(report, expected);
+        assert_eq!(client.flush().await.unwrap(), FlushReport::default());
+        assert_eq!(client.last_flush_stats(), Some(expected));
+    }
+
+    #[tokio::test]
+    async fn test_flush_sends_batches_concurrently_and_keeps_failed_ones() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .and(body_string_contains("rejected-model"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"tracked": 2})),
+            )
+            .mount(&server)
+            .await;
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+                .batch_size(2)
+                .max_concurrent_flushes(2)
+                .max_retries(0),
+        );
+        let call = |model: &str| {
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model(model)
+                .build()
+        };
+
+        client
+            .track_all(vec![
+                call("gpt-4"),
+                call("gpt-4"),
+                call("gpt-4"),
+                call("rejected-model"),
+                call("gpt-4o"),
+            ])
+            .await;
+        // Reaching the batch size flushed the buffer
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+        assert_eq!(client.buffer_size().await, 2);
+        assert!(client.flush().await.is_err());
+        assert_eq!(client.buffer_size().await, 2);
+
+        server.reset().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 2,
+                "total_cost": 0.002,
+                "total_tokens": 300,
+                "ids": ["id-1", "id-2"]
+            })))
+            .mount(&server)
+            .await;
+        let report = client.flush().await.unwrap();
+        assert_eq!(report.calls, 2);
+        assert_eq!(report.response.map(|r| r.tracked), Some(2));
+        assert_eq!(client.buffer_size().await, 0);
+    }
+
+    #[test]
+    fn test_interrupted_send_keeps_only_unsent_batches() {
+        let calls: Vec<LLMCall> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|model| {
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model(*model)
+                    .build()
+            })
+            .collect();
+        let mut sent = ChunkedSend::default();
+        sent.record(
+            0,
+            Ok(FlushReport {
+                calls: 2,
+                ..Default::default()
+            }),
+        );
+
+        let timeout = DiagnyxError::Timeout(Duration::from_secs(1));
+        let (result, unsent) = sent.into_result(calls, 2, Some(timeout));
+
+        let Err(DiagnyxError::PartialFlush { report, error }) = result else {
+            panic!("expected a partial flush, got {:?}", result);
+        };
+        assert_eq!(report.calls, 2);
+        assert!(matches!(*error, DiagnyxError::Timeout(_)));
+        let models: Vec<_> = unsent.iter().map(|call| call.model.as_str()).collect();
+        assert_eq!(models, ["c", "d"]);
+    }
+
+    #[test]
+    fn test_merged_report_needs_the_response_of_every_batch() {
+        let config = DiagnyxConfig::new("test-api-key");
+        let answered = || {
+            FlushReport::from_response(
+                2,
+                br#"{"tracked": 2, "total_cost": 0.5, "total_tokens": 40, "ids": ["a", "b"]}"#,
+                &config,
+            )
+        };
+        assert_eq!(answered().response.unwrap().tracked, 2);
+
+        let merged = answered().merge(answered());
+        assert_eq!(merged.calls, 4);
+        assert_eq!(merged.response.unwrap().ids, ["a", "b", "a", "b"]);
+
+        let unparsable = FlushReport::from_response(2, b"<html>", &config);
+        assert_eq!(unparsable.response, None);
+        let merged = answered().merge(unparsable);
+        assert_eq!(merged.calls, 4);
+        assert_eq!(merged.response, None);
+    }
+
+    #[tokio::test]
+    async fn test_with_http_client_sends_with_given_client() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .and(header("X-Egress-Tag", "team-ml"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let headers = reqwest::header::HeaderMap::from_iter([(
+            reqwest::header::HeaderName::from_static("x-egress-tag"),
+            reqwest::header::HeaderValue::from_static("team-ml"),
+        )]);
+        let http_client = Client::builder().default_headers(headers).build().unwrap();
+        let client = DiagnyxClient::with_http_client(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000),
+            http_client,
+        );
+
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build(),
+            )
+            .await;
+        client.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_flush_empty_buffer_succeeds() {
+        let server = MockServer::start().await;
+        let client = create_mock_client(&server).await;
+
+        // Flush with empty buffer should succeed
+        let result = client.flush().await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_flush_restores_buffer_on_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(500).set_body_json(serde_json::json!({
+                "error": "Server error"
+            })))
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server).await;
+
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .build();
+
+        client.track(call).await;
+        assert_eq!(client.buffer_size().await, 1);
+
+        let result = client.flush().await;
+        assert!(result.is_err());
+
+        // Buffer should be restored
+        assert_eq!(client.buffer_size().await, 1);
+        let _ = client.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_disabled_client_drops_calls() {
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("")
+                .base_url("http://127.0.0.1:9")
+                .heartbeat_interval_ms(10)
+                .disabled(true),
+        );
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .build();
+        client.track(call.clone()).await;
+        client.track_all(vec![call]).await;
+
+        assert_eq!(client.buffer_size().await, 0);
+        client.flush().await.unwrap();
+        let _ = client.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_disabled_client_leaves_parked_calls() {
+        let parked_path =
+            std::env::temp_dir().join(format!("diagnyx-parked-{}.json", uuid::Uuid::new_v4()));
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .build();
+        ParkedQueue::new(parked_path.clone())
+            .append(&mut vec![call])
+            .unwrap();
+
+        let client = DiagnyxClient::with_http_client(
+            DiagnyxConfig::new("test-api-key")
+                .parked_buffer_path(&parked_path)
+                .disabled(true),
+            Client::new(),
+        );
+
+        assert_eq!(client.buffer_size().await, 0);
+        assert!(client.flush_task.lock().unwrap().is_none());
+        assert!(parked_path.exists());
+        std::fs::remove_file(parked_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_receives_flushed_calls() {
+        let sink_path =
+            std::env::temp_dir().join(format!("diagnyx-sink-{}.ndjson", uuid::Uuid::new_v4()));
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key")
+                .base_url("http://127.0.0.1:9")
+                .flush_interval_ms(60000)
+                .sink(NdjsonFileSink::new(&sink_path)),
+        );
+
+        for model in ["gpt-4", "gpt-4o"] {
+            let call = LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model(model)
+                .build();
+            client.track(call).await;
+            client.flush().await.unwrap();
+        }
+
+        let contents = std::fs::read_to_string(&sink_path).unwrap();
+        let models: Vec<String> = contents
+            .lines()
+            .map(|line| serde_json::from_str::<LLMCall>(line).unwrap().model)
+            .collect();
+        assert_eq!(models, ["gpt-4", "gpt-4o"]);
+        let _ = client.shutdown().await;
+        let _ = std::fs::remove_file(&sink_path);
+    }
+
+    #[tokio::test]
+    async fn test_ingest_rate_hint_defers_full_batch_flushes() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).insert_header("X-Ingest-Rate", "0.01"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+                .batch_size(1),
+        );
+
+        for _ in 0..2 {
+            let call = LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model("gpt-4")
+                .build();
+            client.track(call).await;
+        }
+        assert_eq!(client.buffer_size().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_sends_heartbeats() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/sdk/heartbeat"))
+            .and(body_partial_json(serde_json::json!({
+                "service_name": "checkout",
+                "sdk": "rust"
+            })))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server)
+            .await;
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .service_name("checkout")
+                .heartbeat_interval_ms(20),
+        );
+        tokio::time::sleep(Duration::from_millis(70)).await;
+        let _ = client.shutdown().await;
+
+        let heartbeats = server.received_requests().await.unwrap();
+        assert!(heartbeats.len() >= 2);
+        let first: serde_json::Value = heartbeats[0].body_json().unwrap();
+        let second: serde_json::Value = heartbeats[1].body_json().unwrap();
+        assert_eq!(first["instance_id"], second["instance_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_verify_api_key_reads_without_registering() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/spend/month-to-date"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "month": "2026-10",
+                "totalCost": 1.5
+            })))
+            .mount(&server)
+            .await;
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .max_retries(0),
+        );
+        client.verify_api_key().await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, wiremock::http::Method::Get);
+    }
+
+    #[tokio::test]
+    async fn test_month_to_date_spend_is_reconciled() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 1
+            })))
+            .mount(&server)
+            .await;
+        let month = crate::spend::month_of(Utc::now());
+        Mock::given(method("GET"))
+            .and(path("/api/v1/spend/month-to-date"))
+            .and(query_param("project_id", "proj-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "month": month,
+                "total_cost": 10.0
+            })))
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server).await;
+        let project = ProjectId::from_static("proj-1");
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .input_tokens(1000)
+                    .output_tokens(500)
+                    .project_id(project.clone())
+                    .build(),
+            )
+            .await;
+        assert!((client.month_to_date(&project) - 0.06).abs() < 1e-9);
+
+        client.flush().await.unwrap();
+
+        assert_eq!(client.reconcile_spend(&project).await.unwrap(), 10.0);
+        assert_eq!(client.month_to_date(&project), 10.0);
+        let _ = client.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_budgets_are_checked_and_synced() {
+        use crate::budgets::{BudgetConfig, BudgetWindow};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/spend/usage"))
+            .and(query_param("project_id", "proj-1"))
+            .and(query_param("window", "day"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "total_cost": 4.5
+            })))
+            .mount(&server)
+            .await;
+
+        let project = ProjectId::from_static("proj-1");
+        let config = DiagnyxConfig::new("test-api-key")
+            .base_url(server.uri())
+            .budget(BudgetConfig::project(
+                project.clone(),
+                5.0,
+                BudgetWindow::Daily,
+            ));
+        let client = DiagnyxClient::with_config(config);
+        assert!(client.check_budget(Some(&project), None).is_ok());
+
+        client.sync_budgets().await.unwrap();
+        assert!(client.check_budget(Some(&project), None).is_ok());
+
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .project_id(project.clone())
+                    .estimated_cost_usd(0.5)
+                    .build(),
+            )
+            .await;
+        match client.check_budget(Some(&project), Some("user-1")) {
+            Err(DiagnyxError::BudgetExceeded {
+                spent_usd,
+                budget_usd,
+            }) => {
+                assert_eq!(spent_usd, 5.0);
+                assert_eq!(budget_usd, 5.0);
+            }
+            other => panic!("Expected BudgetExceeded, got {:?}", other),
+        }
+        assert!(client.check_budget(None, Some("user-1")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_test_mode_marks_calls_and_skips_budgets() {
+        use crate::budgets::{BudgetConfig, BudgetWindow};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let project = ProjectId::from_static("proj-1");
+        let config = DiagnyxConfig::new("dx_test_key")
+            .base_url(server.uri())
+            .budget(BudgetConfig::project(
+                project.clone(),
+                1.0,
+                BudgetWindow::Daily,
+            ));
+        let client = DiagnyxClient::with_config(config);
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .project_id(project.clone())
+                    .environment("production")
+                    .estimated_cost_usd(2.0)
+                    .build(),
+            )
+            .await;
+        assert!(client.check_budget(Some(&project), None).is_ok());
+
+        client.flush().await.unwrap();
+        let request = &server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["calls"][0]["environment"], "test");
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn test_compresses_large_batches() {
+        use crate::compression::Compression;
+        use std::io::Read;
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .and(header("Content-Encoding", "gzip"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let config = DiagnyxConfig::new("test-api-key")
+            .base_url(server.uri())
+            .compression(Compression::Gzip)
+            .compression_threshold(0);
+        let client = DiagnyxClient::with_config(config);
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build(),
+            )
+            .await;
+        client.flush().await.unwrap();
+
+        let request = &server.received_requests().await.unwrap()[0];
+        let mut body = String::new();
+        flate2::read::GzDecoder::new(request.body.as_slice())
+            .read_to_string(&mut body)
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["calls"][0]["model"], "gpt-4");
+
+        // Small batches are sent as is
+        let (body, encoding) = batch_body(
+            &client.config.clone().compression_threshold(1 << 20),
+            FlushMode::Batch,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(encoding, None);
+        assert_eq!(body, br#"{"calls":[]}"#);
+    }
+
+    #[tokio::test]
+    async fn test_compact_flush_mode_falls_back_when_rejected() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .and(header("Content-Type", compact::CONTENT_TYPE))
+            .respond_with(ResponseTemplate::new(415))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .and(header("Content-Type", "application/json"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_mode(FlushMode::Compact),
+        );
+        for _ in 0..2 {
+            client
+                .track(
+                    LLMCall::builder()
+                        .provider(Provider::OpenAI)
+                        .model("gpt-4")
+                        .build(),
+                )
+                .await;
+            client.flush().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_flush_mode() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/stream"))
+            .and(header("Content-Type", "application/x-ndjson"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_mode(FlushMode::Ndjson),
+        );
+        let calls = ["gpt-4", "gpt-4o"]
+            .into_iter()
+            .map(|model| {
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model(model)
+                    .build()
+            })
+            .collect();
+        client.track_all(calls).await;
+        client.flush().await.unwrap();
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4o-mini")
+                    .build(),
+            )
+            .await;
+        client.flush().await.unwrap();
+        // Both flushes go to the same request, which ends at shutdown
+        assert!(server.received_requests().await.unwrap().is_empty());
+        client.shutdown().await.into_result().unwrap();
+
+        let request = &server.received_requests().await.unwrap()[0];
+        let body = String::from_utf8(request.body.clone()).unwrap();
+        let lines: Vec<serde_json::Value> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["model"], "gpt-4");
+        assert_eq!(lines[1]["model"], "gpt-4o");
+        assert_eq!(lines[2]["model"], "gpt-4o-mini");
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_stream_resends_lines_of_a_failed_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/stream"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/stream"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let sink = HttpSink::new(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_mode(FlushMode::Ndjson),
+        )
+        .unwrap();
+        let call = |model: &str| {
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model(model)
+                .build()
+        };
+        sink.write(&[call("gpt-4")]).await.unwrap();
+        assert!(sink.close().await.is_err());
+        sink.write(&[call("gpt-4o")]).await.unwrap();
+        sink.close().await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let models: Vec<String> = String::from_utf8(requests[1].body.clone())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<LLMCall>(line).unwrap().model)
+            .collect();
+        assert_eq!(models, ["gpt-4", "gpt-4o"]);
+    }
+
+    #[tokio::test]
+    async fn test_track_runs_enrichers() {
+        struct Region;
+
+        #[async_trait::async_trait]
+        impl crate::Enricher for Region {
+            fn name(&self) -> &str {
+                "region"
+            }
+
+            async fn enrich(&self, call: &mut LLMCall) -> Result<(), DiagnyxError> {
+                call.environment = Some("eu-west".to_string());
+                Ok(())
+            }
+        }
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .flush_interval_ms(60000)
+                .enricher(Region),
+        );
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build(),
+            )
+            .await;
+
+        let buffer = client.buffer.lock().await;
+        assert_eq!(buffer[0].environment.as_deref(), Some("eu-west"));
+    }
+
+    #[tokio::test]
+    async fn test_track_estimates_cost() {
+        let client =
+            DiagnyxClient::with_config(DiagnyxConfig::new("test-api-key").flush_interval_ms(60000));
+        assert_eq!(
+            client.estimate_cost(Provider::Anthropic, "claude-3-haiku-20240307", 1_000_000, 0),
+            Some(0.25)
+        );
+
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4o")
+                    .input_tokens(1_000_000)
+                    .output_tokens(100_000)
+                    .build(),
+            )
+            .await;
+
+        let buffer = client.buffer.lock().await;
+        assert_eq!(buffer[0].estimated_cost_usd, Some(3.5));
+    }
+
+    #[tokio::test]
+    async fn test_track_all_applies_filters() {
+        use crate::filter::FilterAction;
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .flush_interval_ms(60000)
+                .filter(|call: &mut LLMCall| {
+                    if call.model == "health-check" {
+                        FilterAction::Drop
+                    } else {
+                        FilterAction::Keep
+                    }
+                }),
+        );
+        let call = |model: &str| {
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model(model)
+                .build()
+        };
+        client
+            .track_all(vec![call("health-check"), call("gpt-4")])
+            .await;
+
+        let buffer = client.buffer.lock().await;
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer[0].model, "gpt-4");
+    }
+
+    #[tokio::test]
+    async fn test_auto_flush_when_batch_size_reached() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 5
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .batch_size(5)
+                .flush_interval_ms(60000),
+        );
+
+        for _ in 0..5 {
+            let call = LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model("gpt-4")
+                .build();
+            client.track(call).await;
+        }
+
+        // Wait a bit for async flush
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        assert_eq!(client.buffer_size().await, 0);
+        let _ = client.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_reports_calls_parked_by_other_processes_as_flushed() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let parked_path =
+            std::env::temp_dir().join(format!("diagnyx-shutdown-{}.json", uuid::Uuid::new_v4()));
+        let call = || {
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model("gpt-4")
+                .build()
+        };
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+                .parked_buffer_path(&parked_path),
+        );
+        client.track(call()).await;
+        ParkedQueue::new(parked_path.clone())
+            .append(&mut vec![call(), call()])
+            .unwrap();
+
+        let report = client.shutdown().await;
+        assert_eq!((report.flushed, report.dropped), (3, 0));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_reconciliation_before_saving_spend() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/spend/month-to-date"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({
+                        "month": crate::spend::month_of(Utc::now()),
+                        "total_cost": 10.0
+                    }))
+                    .set_delay(Duration::from_millis(300)),
+            )
+            .mount(&server)
+            .await;
+        let spend_path =
+            std::env::temp_dir().join(format!("diagnyx-spend-{}.json", uuid::Uuid::new_v4()));
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+                .spend_cache_path(&spend_path)
+                .spend_reconcile_interval_ms(20)
+                .shutdown_timeout(Duration::from_millis(100)),
+        );
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .project_id(ProjectId::from_static("proj-1"))
+                    .build(),
+            )
+            .await;
+        // Let reconciliation start waiting on the slow response
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let _ = client.shutdown().await;
+        std::fs::remove_file(&spend_path).unwrap();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(!spend_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_flushes_buffer() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 1
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server).await;
+
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .build();
+
+        client.track(call).await;
+        assert_eq!(client.buffer_size().await, 1);
+
+        let report = client.shutdown().await;
+        assert!(report.error.is_none());
+        assert_eq!(report.flushed, 1);
+        assert_eq!(client.buffer_size().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_flush_task_and_reports_dropped_calls() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(10)
+                .batch_size(100),
+        );
+        let mut events = client.subscribe_events();
+        let call = || {
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model("gpt-4")
+                .build()
+        };
+
+        client.track_all(vec![call(), call()]).await;
+        let report = client.shutdown().await;
+        assert_eq!(report.flushed, 0);
+        assert_eq!(report.dropped, 2);
+        assert!(report.into_result().is_err());
+        let mut dropped = None;
+        while let Ok(event) = events.try_recv() {
+            if let SdkEvent::CallDropped { count } = event {
+                dropped = Some(count);
+            }
+        }
+        assert_eq!(dropped, Some(2));
+
+        let requests = server.received_requests().await.unwrap().len();
+        client.track(call()).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(server.received_requests().await.unwrap().len(), requests);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_aborts_background_flush_at_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(10)))
+            .mount(&server)
+            .await;
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(10)
+                .shutdown_timeout(Duration::from_millis(200)),
+        );
+
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build(),
+            )
+            .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let started = std::time::Instant::now();
         let _ = client.shutdown().await;
+        assert!(started.elapsed() < Duration::from_secs(2));
     }
 
     #[tokio::test]
-    async fn test_track_adds_to_buffer() {
+    async fn test_flush_emits_events() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path("/api/v1/ingest/llm/batch"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "tracked": 1,
-                "total_cost": 0.001,
-                "total_tokens": 150,
-                "ids": ["id-1"]
-            })))
+            .respond_with(ResponseTemplate::new(200))
             .mount(&server)
             .await;
 
         let client = create_mock_client(&server).await;
-
+        let mut events = client.subscribe_events();
         let call = LLMCall::builder()
             .provider(Provider::OpenAI)
             .model("gpt-4")
-            .input_tokens(100)
-            .output_tokens(50)
-            .status(CallStatus::Success)
             .build();
 
         client.track(call).await;
+        client.flush().await.unwrap();
 
-        assert_eq!(client.buffer_size().await, 1);
-        let _ = client.shutdown().await;
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SdkEvent::FlushStarted { count: 1 }
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SdkEvent::FlushSucceeded { count: 1 }
+        );
     }
 
     #[tokio::test]
-    async fn test_track_all_adds_multiple() {
+    async fn test_flush_after_failures_emits_recovery() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path("/api/v1/ingest/llm/batch"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "tracked": 3,
-                "total_cost": 0.003,
-                "total_tokens": 450,
-                "ids": ["id-1", "id-2", "id-3"]
-            })))
+            .respond_with(ResponseTemplate::new(400))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
             .mount(&server)
             .await;
 
         let client = create_mock_client(&server).await;
+        let mut events = client.subscribe_events();
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .build();
 
-        let calls = vec![
-            LLMCall::builder()
-                .provider(Provider::OpenAI)
-                .model("gpt-4")
-                .build(),
-            LLMCall::builder()
-                .provider(Provider::Anthropic)
-                .model("claude-3")
-                .build(),
-            LLMCall::builder()
-                .provider(Provider::Google)
-                .model("gemini")
-                .build(),
-        ];
+        client.track(call).await;
+        assert!(client.flush().await.is_err());
+        assert!(client.flush().await.is_err());
+        client.flush().await.unwrap();
+
+        loop {
+            if let SdkEvent::FlushRecovered { failures } = events.recv().await.unwrap() {
+                assert_eq!(failures, 2);
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_flush_drops_calls_over_buffer_limit() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+                .max_buffer_size(2),
+        );
+        let mut events = client.subscribe_events();
+        let calls = (0..3)
+            .map(|_| {
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build()
+            })
+            .collect();
 
         client.track_all(calls).await;
 
-        assert_eq!(client.buffer_size().await, 3);
-        let _ = client.shutdown().await;
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SdkEvent::CallDropped { count: 1 }
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SdkEvent::BufferHigh {
+                depth: 2,
+                capacity: 2
+            }
+        );
+        assert_eq!(client.buffer_size().await, 2);
+
+        assert!(client.flush().await.is_err());
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SdkEvent::FlushStarted { count: 2 }
+        );
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            SdkEvent::FlushFailed { .. }
+        ));
+        assert_eq!(client.buffer_size().await, 2);
     }
 
     #[tokio::test]
-    async fn test_flush_sends_to_api() {
+    async fn test_flush_with_timeout_keeps_buffer() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path("/api/v1/ingest/llm/batch"))
-            .and(header("Authorization", "Bearer test-api-key"))
-            .and(header("Content-Type", "application/json"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "tracked": 1
-            })))
-            .expect(1)
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
             .mount(&server)
             .await;
 
         let client = create_mock_client(&server).await;
-
         let call = LLMCall::builder()
             .provider(Provider::OpenAI)
             .model("gpt-4")
-            .input_tokens(100)
-            .output_tokens(50)
             .build();
 
         client.track(call).await;
-        assert_eq!(client.buffer_size().await, 1);
-
-        let result = client.flush().await;
-        assert!(result.is_ok());
-        assert_eq!(client.buffer_size().await, 0);
-    }
-
-    #[tokio::test]
-    async fn test_flush_empty_buffer_succeeds() {
-        let server = MockServer::start().await;
-        let client = create_mock_client(&server).await;
+        let result = client.flush_with_timeout(Duration::from_millis(50)).await;
 
-        // Flush with empty buffer should succeed
-        let result = client.flush().await;
-        assert!(result.is_ok());
+        assert!(matches!(result, Err(DiagnyxError::Timeout(_))));
+        assert_eq!(client.buffer_size().await, 1);
     }
 
     #[tokio::test]
-    async fn test_flush_restores_buffer_on_error() {
+    async fn test_no_retry_on_client_error() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path("/api/v1/ingest/llm/batch"))
-            .respond_with(ResponseTemplate::new(500).set_body_json(serde_json::json!({
-                "error": "Server error"
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": "Bad request"
             })))
+            .expect(1) // Should only be called once (no retries on 4xx)
             .mount(&server)
             .await;
 
-        let client = create_mock_client(&server).await;
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+                .max_retries(3), // Set retries to 3 but it should still only call once
+        );
 
         let call = LLMCall::builder()
             .provider(Provider::OpenAI)
@@ -473,106 +3301,388 @@ mod tests {
             .build();
 
         client.track(call).await;
-        assert_eq!(client.buffer_size().await, 1);
-
         let result = client.flush().await;
-        assert!(result.is_err());
 
+        assert!(result.is_err());
         // Buffer should be restored
         assert_eq!(client.buffer_size().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_runtime_setting_changes_are_audited() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/audit/config-changes"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server).await;
+        assert!(!client.capture_full_content());
+
+        client
+            .set_capture_full_content(true, "alice@example.com")
+            .await
+            .unwrap();
+        // Unchanged values are not audited
+        client
+            .set_capture_full_content(true, "alice@example.com")
+            .await
+            .unwrap();
+        client.set_sample_rate(0.5, "deploy-bot").await.unwrap();
+        assert!(client.capture_full_content());
+        assert_eq!(client.sample_rate(), 0.5);
+
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let event = &body["events"][0];
+        assert_eq!(event["setting"], "capture_full_content");
+        assert_eq!(event["old_value"], false);
+        assert_eq!(event["new_value"], true);
+        assert_eq!(event["actor"], "alice@example.com");
+
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(body["events"][0]["setting"], "sample_rate");
+        assert_eq!(body["events"][0]["old_value"], 1.0);
+        assert_eq!(body["events"][0]["new_value"], 0.5);
         let _ = client.shutdown().await;
     }
 
     #[tokio::test]
-    async fn test_auto_flush_when_batch_size_reached() {
+    async fn test_undelivered_audit_events_are_retried_on_flush() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
-            .and(path("/api/v1/ingest/llm/batch"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "tracked": 5
-            })))
-            .expect(1)
+            .and(path("/api/v1/audit/config-changes"))
+            .respond_with(ResponseTemplate::new(400))
+            .up_to_n_times(1)
             .mount(&server)
             .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/audit/config-changes"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server).await;
+        assert!(client
+            .set_capture_full_content(true, "alice@example.com")
+            .await
+            .is_err());
+        // The change applies even if the event was not delivered
+        assert!(client.capture_full_content());
+
+        client.flush().await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let body: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(body["events"][0]["actor"], "alice@example.com");
+        let _ = client.shutdown().await;
+    }
 
+    #[tokio::test]
+    async fn test_sample_rate_drops_calls() {
         let client = DiagnyxClient::with_config(
             DiagnyxConfig::new("test-api-key")
-                .base_url(&server.uri())
-                .batch_size(5)
-                .flush_interval_ms(60000),
+                .flush_interval_ms(60000)
+                .sample_rate(0.0),
         );
+        assert!(client.set_sample_rate(1.5, "alice").await.is_err());
+        assert!(client.set_sample_rate(f64::NAN, "alice").await.is_err());
+        assert_eq!(client.sample_rate(), 0.0);
 
-        for _ in 0..5 {
-            let call = LLMCall::builder()
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .build();
+        client.track(call).await;
+        assert_eq!(client.buffer_size().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cost_sampling_keeps_expensive_calls() {
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .flush_interval_ms(60000)
+                .sample_rate(0.0)
+                .cost_sampling(CostSampling::new(0.01)),
+        );
+        let call = |input_tokens| {
+            LLMCall::builder()
                 .provider(Provider::OpenAI)
                 .model("gpt-4")
-                .build();
-            client.track(call).await;
-        }
+                .input_tokens(input_tokens)
+        };
 
-        // Wait a bit for async flush
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        client
+            .track_all(vec![
+                call(10).build(),
+                call(1000).build(),
+                call(10).status(CallStatus::Error).build(),
+            ])
+            .await;
+        // The cheap successful call is dropped
+        let buffer = client.buffer.lock().await;
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer[0].input_tokens, 1000);
+        assert_eq!(buffer[1].status, CallStatus::Error);
+    }
 
-        assert_eq!(client.buffer_size().await, 0);
-        let _ = client.shutdown().await;
+    #[tokio::test]
+    async fn test_volume_cap_limits_calls_and_records_rate() {
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .flush_interval_ms(60000)
+                .volume_cap(crate::sampling::VolumeCap::CallsPerMinute(3)),
+        );
+        let calls = (0..5)
+            .map(|_| {
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build()
+            })
+            .collect();
+
+        client.track_all(calls).await;
+        let buffer = client.buffer.lock().await;
+        assert_eq!(buffer.len(), 3);
+        assert!(buffer.iter().all(|call| call.sample_rate == Some(1.0)));
+        assert_eq!(client.adaptive_sample_rate(), Some(1.0));
     }
 
     #[tokio::test]
-    async fn test_shutdown_flushes_buffer() {
+    async fn test_repeated_auth_failures_park_buffer() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path("/api/v1/ingest/llm/batch"))
-            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
-                "tracked": 1
-            })))
-            .expect(1)
+            .respond_with(ResponseTemplate::new(401).set_body_string("invalid api key"))
+            .expect(2)
             .mount(&server)
             .await;
 
-        let client = create_mock_client(&server).await;
+        let parked_path =
+            std::env::temp_dir().join(format!("diagnyx-parked-{}.json", uuid::Uuid::new_v4()));
+        let failures = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = Arc::clone(&failures);
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("revoked-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+                .auth_failure_threshold(2)
+                .parked_buffer_path(&parked_path)
+                .on_auth_failure(move |e| {
+                    assert_eq!(e.auth_failure_status(), Some(401));
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }),
+        );
+        let mut events = client.subscribe_events();
 
         let call = LLMCall::builder()
             .provider(Provider::OpenAI)
             .model("gpt-4")
             .build();
-
         client.track(call).await;
+
+        assert!(client.flush().await.is_err());
+        assert!(!client.is_parked());
+        assert!(client.flush().await.is_err());
+        assert!(client.is_parked());
+        assert_eq!(failures.load(Ordering::SeqCst), 1);
+
+        // Parked calls moved to the file, and parked clients no longer send
+        assert_eq!(client.buffer_size().await, 0);
+        assert!(parked_path.exists());
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4o")
+                    .build(),
+            )
+            .await;
+        assert!(matches!(
+            client.flush().await,
+            Err(DiagnyxError::AuthFailed { status_code: 401 })
+        ));
         assert_eq!(client.buffer_size().await, 1);
+        loop {
+            if let SdkEvent::AuthFailed {
+                status_code,
+                parked,
+            } = events.recv().await.unwrap()
+            {
+                assert_eq!((status_code, parked), (401, 1));
+                break;
+            }
+        }
 
-        let result = client.shutdown().await;
-        assert!(result.is_ok());
+        let _ = client.shutdown().await;
+        let restarted = DiagnyxClient::with_config(
+            DiagnyxConfig::new("rotated-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+                .parked_buffer_path(&parked_path),
+        );
+        assert_eq!(restarted.buffer_size().await, 2);
+        assert!(!parked_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_panic_hook_persists_buffer() {
+        let parked_path =
+            std::env::temp_dir().join(format!("diagnyx-panic-{}.json", uuid::Uuid::new_v4()));
+        let config = DiagnyxConfig::new("test-api-key").flush_interval_ms(60000);
+        assert!(DiagnyxClient::with_config(config.clone())
+            .install_panic_hook()
+            .is_err());
+
+        let previous = std::panic::take_hook();
+        let client = DiagnyxClient::with_config(config.clone().parked_buffer_path(&parked_path));
+        client.install_panic_hook().unwrap();
+        client.install_panic_hook().unwrap();
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build(),
+            )
+            .await;
+
+        assert!(std::panic::catch_unwind(|| panic!("crash")).is_err());
+        std::panic::set_hook(previous);
+        // Moved to the file, not copied
         assert_eq!(client.buffer_size().await, 0);
+
+        let restarted = DiagnyxClient::with_config(config.parked_buffer_path(&parked_path));
+        assert_eq!(restarted.buffer_size().await, 1);
     }
 
+    #[cfg(feature = "signals")]
+    #[test]
+    fn test_signal_handler_requires_a_runtime() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client = Arc::new(runtime.block_on(async {
+            DiagnyxClient::with_config(DiagnyxConfig::new("test-api-key").flush_interval_ms(60000))
+        }));
+
+        assert!(matches!(
+            client.install_signal_handler(),
+            Err(DiagnyxError::ConfigError(_))
+        ));
+    }
+
+    #[cfg(feature = "signals")]
     #[tokio::test]
-    async fn test_no_retry_on_client_error() {
+    async fn test_shutdown_on_signal_saves_unsent_calls() {
         let server = MockServer::start().await;
         Mock::given(method("POST"))
             .and(path("/api/v1/ingest/llm/batch"))
-            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
-                "error": "Bad request"
-            })))
-            .expect(1) // Should only be called once (no retries on 4xx)
+            .respond_with(ResponseTemplate::new(503))
             .mount(&server)
             .await;
+        let parked_path =
+            std::env::temp_dir().join(format!("diagnyx-signal-{}.json", uuid::Uuid::new_v4()));
+        let config = DiagnyxConfig::new("test-api-key")
+            .base_url(server.uri())
+            .flush_interval_ms(60000)
+            .max_retries(0)
+            .parked_buffer_path(&parked_path);
+
+        let client = DiagnyxClient::with_config(config.clone());
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build(),
+            )
+            .await;
+        let report = client.shutdown_on_signal().await;
+        assert_eq!((report.flushed, report.parked, report.dropped), (0, 1, 0));
+
+        let restarted = DiagnyxClient::with_config(config);
+        assert_eq!(restarted.buffer_size().await, 1);
+        let _ = std::fs::remove_file(&parked_path);
+    }
+
+    #[cfg(feature = "tokenizers")]
+    #[tokio::test]
+    async fn test_track_call_with_content_counts_missing_tokens() {
+        let client =
+            DiagnyxClient::with_config(DiagnyxConfig::new("test-api-key").flush_interval_ms(60000));
+
+        track_call_with_content(
+            &client,
+            Provider::Anthropic,
+            "claude-3-5-sonnet",
+            "Summarize this text",
+            "Done",
+            0,
+            7,
+            100,
+        )
+        .await;
+
+        let buffer = client.buffer.lock().await;
+        assert!(buffer[0].input_tokens > 0);
+        assert_eq!(buffer[0].output_tokens, 7);
+    }
 
+    #[tokio::test]
+    async fn test_track_call_with_content_redacts_before_truncating() {
         let client = DiagnyxClient::with_config(
             DiagnyxConfig::new("test-api-key")
-                .base_url(&server.uri())
                 .flush_interval_ms(60000)
-                .max_retries(3), // Set retries to 3 but it should still only call once
+                .capture_full_content(true)
+                .content_max_length(20)
+                .content_redactor(crate::ContentRedactor::pii()),
+        );
+
+        track_call_with_content(
+            &client,
+            Provider::OpenAI,
+            "gpt-4o",
+            "Email a.very.long.address@example.com",
+            "SSN is 123-45-6789",
+            10,
+            5,
+            100,
+        )
+        .await;
+
+        let buffer = client.buffer.lock().await;
+        assert_eq!(buffer[0].full_prompt.as_deref(), Some("Email [EMAIL]"));
+        assert_eq!(buffer[0].full_response.as_deref(), Some("SSN is [SSN]"));
+    }
+
+    #[tokio::test]
+    async fn test_track_redacts_content_of_manually_built_calls() {
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .flush_interval_ms(60000)
+                .content_redactor(crate::ContentRedactor::pii()),
         );
 
         let call = LLMCall::builder()
             .provider(Provider::OpenAI)
             .model("gpt-4")
+            .full_prompt("Mail jane@example.com")
+            .full_response("SSN 123-45-6789")
+            .status(CallStatus::Success)
             .build();
-
         client.track(call).await;
-        let result = client.flush().await;
 
-        assert!(result.is_err());
-        // Buffer should be restored
-        assert_eq!(client.buffer_size().await, 1);
+        let buffer = client.buffer.lock().await;
+        assert_eq!(buffer[0].full_prompt.as_deref(), Some("Mail [EMAIL]"));
+        assert_eq!(buffer[0].full_response.as_deref(), Some("SSN [SSN]"));
+    }
+
+    #[test]
+    fn test_truncate_text_cuts_on_char_boundary() {
+        assert_eq!(truncate_text("héllo".to_string(), 2), "h... [truncated]");
+        assert_eq!(truncate_text("héllo".to_string(), 3), "hé... [truncated]");
+        assert_eq!(truncate_text("héllo".to_string(), 6), "héllo");
     }
 }
@@ -0,0 +1,113 @@
+//! Dictionary-encoded batch bodies.
+//!
+//! Calls in a batch usually share their provider, model, project and
+//! environment. In the compact format each distinct value of these fields is
+//! sent once in a `dictionary`, and calls refer to it by index:
+//!
+//! ```json
+//! {
+//!   "dictionary": {"model": ["gpt-4", "gpt-4o"], "provider": ["openai"]},
+//!   "calls": [{"model": 0, "provider": 0, ...}, {"model": 1, "provider": 0, ...}]
+//! }
+//! ```
+//!
+//! Fields a call does not set are left out as in the plain format.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use crate::types::LLMCall;
+
+/// Content type the compact format is sent with.
+pub(crate) const CONTENT_TYPE: &str = "application/vnd.diagnyx.compact+json";
+
+/// Fields replaced by an index into the dictionary.
+const DICTIONARY_FIELDS: &[&str] = &[
+    "provider",
+    "model",
+    "endpoint",
+    "project_id",
+    "environment",
+    "user_identifier",
+    "prompt_id",
+];
+
+#[derive(Debug, Serialize)]
+struct CompactBatch {
+    dictionary: BTreeMap<&'static str, Vec<Value>>,
+    calls: Vec<Value>,
+}
+
+pub(crate) fn encode(calls: &[LLMCall]) -> serde_json::Result<Vec<u8>> {
+    let mut dictionary: BTreeMap<&'static str, Vec<Value>> = BTreeMap::new();
+    let mut encoded = Vec::with_capacity(calls.len());
+
+    for call in calls {
+        let mut call: Map<String, Value> = match serde_json::to_value(call)? {
+            Value::Object(call) => call,
+            _ => unreachable!("calls serialize to objects"),
+        };
+        for &field in DICTIONARY_FIELDS {
+            let Some(value) = call.get_mut(field) else {
+                continue;
+            };
+            let values = dictionary.entry(field).or_default();
+            let index = match values.iter().position(|known| known == value) {
+                Some(index) => index,
+                None => {
+                    values.push(value.take());
+                    values.len() - 1
+                }
+            };
+            *value = Value::from(index);
+        }
+        encoded.push(Value::Object(call));
+    }
+
+    serde_json::to_vec(&CompactBatch {
+        dictionary,
+        calls: encoded,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Provider;
+
+    #[test]
+    fn test_encode_shares_repeated_values() {
+        let calls: Vec<LLMCall> = ["gpt-4", "gpt-4o", "gpt-4"]
+            .into_iter()
+            .map(|model| {
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model(model)
+                    .input_tokens(10)
+                    .build()
+            })
+            .collect();
+
+        let body: Value = serde_json::from_slice(&encode(&calls).unwrap()).unwrap();
+
+        assert_eq!(
+            body["dictionary"]["model"],
+            serde_json::json!(["gpt-4", "gpt-4o"])
+        );
+        assert_eq!(
+            body["dictionary"]["provider"],
+            serde_json::json!(["openai"])
+        );
+        assert!(body["dictionary"].get("environment").is_none());
+        let models: Vec<&Value> = body["calls"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|call| &call["model"])
+            .collect();
+        assert_eq!(models, [&Value::from(0), &Value::from(1), &Value::from(0)]);
+        assert_eq!(body["calls"][2]["provider"], 0);
+        assert_eq!(body["calls"][2]["input_tokens"], 10);
+    }
+}
@@ -0,0 +1,76 @@
+//! Compression of batch ingest payloads.
+//!
+//! Batches with full prompts and responses captured can be megabytes. With
+//! a [`Compression`] configured, batch bodies of at least
+//! [`compression_threshold`](crate::DiagnyxConfig::compression_threshold)
+//! bytes are compressed and sent with a matching `Content-Encoding` header.
+//! Smaller bodies are sent as is, since compressing them saves little.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::compression::Compression;
+//! use diagnyx::{DiagnyxClient, DiagnyxConfig};
+//!
+//! let client = DiagnyxClient::with_config(
+//!     DiagnyxConfig::new("dx_live_your_api_key")
+//!         .capture_full_content(true)
+//!         .compression(Compression::Zstd),
+//! );
+//! ```
+
+use serde::Deserialize;
+use std::io::{self, Write};
+
+/// Algorithm used to compress batch bodies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Value of the `Content-Encoding` header for the algorithm.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    pub(crate) fn compress(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::encode_all(body, 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_round_trip() {
+        let body = br#"{"calls":[]}"#.repeat(100);
+
+        let gzip = Compression::Gzip.compress(&body).unwrap();
+        assert!(gzip.len() < body.len());
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(gzip.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let zstd = Compression::Zstd.compress(&body).unwrap();
+        assert!(zstd.len() < body.len());
+        assert_eq!(zstd::decode_all(zstd.as_slice()).unwrap(), body);
+    }
+}
@@ -0,0 +1,438 @@
+//! Datasets Module for Diagnyx Rust SDK
+//!
+//! Provides methods for building datasets of examples, each an input with
+//! an optional expected output, for evaluations and fine-tuning. Examples
+//! can be made from feedback corrections, so responses users corrected
+//! become training data, and a dataset can be exported as JSON Lines.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::datasets::{DatasetClient, Example};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let client = DatasetClient::new("dx_api_key", "org-123");
+//!
+//!     let dataset = client.create_dataset("support-corrections", None).await?;
+//!     client
+//!         .append_examples(
+//!             &dataset.id,
+//!             vec![Example::new("How do I reset my password?")
+//!                 .expected_output("Open Settings > Security and choose Reset.")],
+//!         )
+//!         .await?;
+//!
+//!     let file = std::fs::File::create("support-corrections.jsonl")?;
+//!     let exported = client.export_jsonl(&dataset.id, file).await?;
+//!     println!("Exported {} examples", exported);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+
+use crate::error::DiagnyxError;
+#[cfg(feature = "feedback")]
+use crate::feedback::Feedback;
+use crate::http::{HttpConfig, REQUEST_TIMEOUT};
+use crate::ids::TraceId;
+use crate::retry::{send_with_retry, RetryPolicy};
+use crate::types::{default_base_url, Masked};
+
+/// Number of examples fetched per page by [`DatasetClient::export_jsonl`].
+const EXPORT_PAGE_SIZE: i32 = 100;
+
+/// A dataset of examples.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Dataset {
+    pub id: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub example_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An example in a dataset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Example {
+    /// Assigned by the API when the example is appended.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub input: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_output: Option<String>,
+    /// Trace the example was taken from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<TraceId>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+impl Example {
+    pub fn new(input: impl Into<String>) -> Self {
+        Self {
+            id: None,
+            input: input.into(),
+            expected_output: None,
+            trace_id: None,
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// An example of `input` expecting the correction given as `feedback`,
+    /// or `None` if the feedback is not a correction.
+    #[cfg(feature = "feedback")]
+    pub fn from_correction(input: impl Into<String>, feedback: &Feedback) -> Option<Self> {
+        let correction = feedback.correction.as_ref()?;
+        Some(
+            Self::new(input)
+                .expected_output(correction)
+                .trace_id(feedback.trace_id.clone()),
+        )
+    }
+
+    pub fn expected_output(mut self, expected: impl Into<String>) -> Self {
+        self.expected_output = Some(expected.into());
+        self
+    }
+
+    pub fn trace_id(mut self, trace_id: TraceId) -> Self {
+        self.trace_id = Some(trace_id);
+        self
+    }
+
+    pub fn metadata(mut self, metadata: HashMap<String, serde_json::Value>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// Options for listing the examples of a dataset.
+#[derive(Debug, Clone, Default)]
+pub struct ListExamplesOptions {
+    pub limit: Option<i32>,
+    pub offset: Option<i32>,
+}
+
+/// A page of the examples of a dataset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExampleListResult {
+    pub data: Vec<Example>,
+    pub total: i32,
+    pub limit: i32,
+    pub offset: i32,
+}
+
+/// Configuration for DatasetClient.
+#[derive(Clone)]
+pub struct DatasetClientConfig {
+    pub api_key: String,
+    pub organization_id: String,
+    pub base_url: String,
+    pub retry_policy: RetryPolicy,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
+    pub debug: bool,
+}
+
+impl fmt::Debug for DatasetClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatasetClientConfig")
+            .field("api_key", &Masked(&self.api_key))
+            .field("organization_id", &self.organization_id)
+            .field("base_url", &self.base_url)
+            .field("retry_policy", &self.retry_policy)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .finish()
+    }
+}
+
+impl DatasetClientConfig {
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        let api_key = api_key.into();
+        Self {
+            base_url: default_base_url(&api_key),
+            api_key,
+            organization_id: organization_id.into(),
+            retry_policy: RetryPolicy::new(3),
+            http: HttpConfig::default(),
+            debug: false,
+        }
+    }
+
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into();
+        self
+    }
+
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.retry_policy.max_attempts = retries as u32;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+}
+
+/// Client for managing datasets.
+pub struct DatasetClient {
+    config: DatasetClientConfig,
+    http_client: Client,
+}
+
+impl DatasetClient {
+    /// Create a new DatasetClient with default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        Self::with_config(DatasetClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new DatasetClient with custom configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_with_config`](Self::try_with_config) to handle the error instead.
+    pub fn with_config(config: DatasetClientConfig) -> Self {
+        Self::try_with_config(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new DatasetClient with default settings, returning an error if
+    /// the HTTP client cannot be created.
+    pub fn try_new(
+        api_key: impl Into<String>,
+        organization_id: impl Into<String>,
+    ) -> Result<Self, DiagnyxError> {
+        Self::try_with_config(DatasetClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new DatasetClient with custom configuration, returning an error
+    /// if the HTTP client cannot be created.
+    pub fn try_with_config(config: DatasetClientConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config.http.client(REQUEST_TIMEOUT)?;
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Create a new DatasetClient sending requests with `http_client`.
+    ///
+    /// The HTTP settings of `config` are not applied to it.
+    pub fn with_http_client(config: DatasetClientConfig, http_client: Client) -> Self {
+        Self {
+            config,
+            http_client,
+        }
+    }
+
+    /// Create a new dataset.
+    pub async fn create_dataset(
+        &self,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<Dataset, DiagnyxError> {
+        let payload = serde_json::json!({
+            "name": name,
+            "description": description,
+        });
+        self.request("POST", &self.datasets_path(None, ""), Some(payload))
+            .await
+    }
+
+    /// Get a dataset by ID.
+    pub async fn get_dataset(&self, dataset_id: &str) -> Result<Dataset, DiagnyxError> {
+        self.request("GET", &self.datasets_path(Some(dataset_id), ""), None)
+            .await
+    }
+
+    /// Append examples to a dataset.
+    pub async fn append_examples(
+        &self,
+        dataset_id: &str,
+        examples: Vec<Example>,
+    ) -> Result<(), DiagnyxError> {
+        let payload = serde_json::json!({ "examples": examples });
+        let _: serde_json::Value = self
+            .request(
+                "POST",
+                &self.datasets_path(Some(dataset_id), "/examples"),
+                Some(payload),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// List a page of the examples of a dataset.
+    pub async fn list_examples(
+        &self,
+        dataset_id: &str,
+        options: Option<ListExamplesOptions>,
+    ) -> Result<ExampleListResult, DiagnyxError> {
+        let options = options.unwrap_or_default();
+
+        let mut query_params = Vec::new();
+        if let Some(limit) = options.limit {
+            query_params.push(format!("limit={}", limit));
+        }
+        if let Some(offset) = options.offset {
+            query_params.push(format!("offset={}", offset));
+        }
+
+        let mut path = self.datasets_path(Some(dataset_id), "/examples");
+        if !query_params.is_empty() {
+            path.push('?');
+            path.push_str(&query_params.join("&"));
+        }
+
+        self.request("GET", &path, None).await
+    }
+
+    /// Write every example of a dataset to `writer` as JSON Lines, one
+    /// example per line, and return the number written.
+    pub async fn export_jsonl<W: Write>(
+        &self,
+        dataset_id: &str,
+        mut writer: W,
+    ) -> Result<usize, DiagnyxError> {
+        let mut written = 0;
+        loop {
+            let page = self
+                .list_examples(
+                    dataset_id,
+                    Some(ListExamplesOptions {
+                        limit: Some(EXPORT_PAGE_SIZE),
+                        offset: Some(written as i32),
+                    }),
+                )
+                .await?;
+            for example in &page.data {
+                serde_json::to_writer(&mut writer, example)?;
+                writer.write_all(b"\n")?;
+            }
+            written += page.data.len();
+            if page.data.is_empty() || written >= page.total as usize {
+                break;
+            }
+        }
+        writer.flush()?;
+        Ok(written)
+    }
+
+    fn datasets_path(&self, dataset_id: Option<&str>, suffix: &str) -> String {
+        let mut path = format!(
+            "/api/v1/organizations/{}/datasets",
+            self.config.organization_id
+        );
+        if let Some(id) = dataset_id {
+            path.push('/');
+            path.push_str(id);
+        }
+        path.push_str(suffix);
+        path
+    }
+
+    async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, DiagnyxError> {
+        let url = format!("{}{}", self.config.base_url, path);
+        let method = match method {
+            "POST" => Method::POST,
+            "GET" => Method::GET,
+            _ => {
+                return Err(DiagnyxError::ConfigError(format!(
+                    "Unknown method: {}",
+                    method
+                )))
+            }
+        };
+
+        let response = send_with_retry(&self.config.retry_policy, None, method, |method| {
+            let mut request = self
+                .http_client
+                .request(method, &url)
+                .header("Content-Type", "application/json")
+                .header("Authorization", format!("Bearer {}", self.config.api_key));
+            if let Some(ref b) = body {
+                request = request.json(b);
+            }
+            request
+        })
+        .await?;
+
+        response
+            .json()
+            .await
+            .map_err(|e| DiagnyxError::ConfigError(format!("Failed to parse response: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_export_jsonl_pages_through_examples() {
+        let server = MockServer::start().await;
+        let examples: Vec<Example> = (0..150)
+            .map(|i| Example::new(format!("question {}", i)).expected_output("answer"))
+            .collect();
+        for (offset, page) in [(0, &examples[..100]), (100, &examples[100..])] {
+            Mock::given(method("GET"))
+                .and(path("/api/v1/organizations/org-1/datasets/ds-1/examples"))
+                .and(query_param("offset", offset.to_string()))
+                .and(query_param("limit", "100"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "data": page,
+                    "total": 150,
+                    "limit": 100,
+                    "offset": offset
+                })))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let client = DatasetClient::with_config(
+            DatasetClientConfig::new("test-api-key", "org-1").base_url(server.uri()),
+        );
+        let mut out = Vec::new();
+        let written = client.export_jsonl("ds-1", &mut out).await.unwrap();
+
+        assert_eq!(written, 150);
+        let lines: Vec<&str> = std::str::from_utf8(&out).unwrap().lines().collect();
+        assert_eq!(lines.len(), 150);
+        let last: Example = serde_json::from_str(lines[149]).unwrap();
+        assert_eq!(last, examples[149]);
+    }
+}
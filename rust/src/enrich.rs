@@ -0,0 +1,325 @@
+//! Metadata enrichment of tracked calls.
+//!
+//! An [`Enricher`] runs on every call before it is buffered and can add
+//! metadata that the call site does not know, such as a customer's tier
+//! looked up from a local cache. Enrichers run in the order they were
+//! added to the [`DiagnyxConfig`](crate::DiagnyxConfig). Each one is bounded
+//! by a timeout and isolated from the others: if it fails, panics or times
+//! out, its changes are discarded, an [`SdkEvent::EnrichmentFailed`] is
+//! emitted and the call is tracked without them. An enricher that fails
+//! several times in a row is disabled and an [`SdkEvent::HookDisabled`] is
+//! emitted.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use async_trait::async_trait;
+//! use diagnyx::enrich::Enricher;
+//! use diagnyx::{DiagnyxClient, DiagnyxConfig, DiagnyxError, LLMCall};
+//!
+//! struct CustomerTier;
+//!
+//! #[async_trait]
+//! impl Enricher for CustomerTier {
+//!     fn name(&self) -> &str {
+//!         "customer_tier"
+//!     }
+//!
+//!     async fn enrich(&self, call: &mut LLMCall) -> Result<(), DiagnyxError> {
+//!         let tier = match call.user_identifier.as_deref() {
+//!             Some(user) if user.starts_with("ent_") => "enterprise",
+//!             _ => "free",
+//!         };
+//!         call.metadata
+//!             .get_or_insert_with(Default::default)
+//!             .insert("customer_tier".to_string(), tier.into());
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let client = DiagnyxClient::with_config(
+//!     DiagnyxConfig::new("dx_live_your_api_key").enricher(CustomerTier),
+//! );
+//! ```
+
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::DiagnyxError;
+use crate::events::{EventBus, SdkEvent};
+use crate::health::{panic_message, HookHealth, DEFAULT_MAX_FAILURES};
+use crate::types::LLMCall;
+
+/// Adds information to calls before they are buffered.
+#[async_trait]
+pub trait Enricher: Send + Sync {
+    /// Name reported when the enricher fails.
+    fn name(&self) -> &str;
+
+    /// Modify `call` in place.
+    ///
+    /// Returning an error discards every change made to the call.
+    async fn enrich(&self, call: &mut LLMCall) -> Result<(), DiagnyxError>;
+}
+
+/// The enrichers of a client, run in order.
+#[derive(Clone)]
+pub struct EnricherChain {
+    enrichers: Vec<Arc<Slot>>,
+    timeout: Duration,
+    max_failures: u32,
+}
+
+/// An enricher and its failure count.
+struct Slot {
+    enricher: Box<dyn Enricher>,
+    health: HookHealth,
+}
+
+impl Default for EnricherChain {
+    fn default() -> Self {
+        Self {
+            enrichers: Vec::new(),
+            timeout: Duration::from_millis(100),
+            max_failures: DEFAULT_MAX_FAILURES,
+        }
+    }
+}
+
+impl fmt::Debug for EnricherChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnricherChain")
+            .field(
+                "enrichers",
+                &self
+                    .enrichers
+                    .iter()
+                    .map(|slot| slot.enricher.name())
+                    .collect::<Vec<_>>(),
+            )
+            .field("timeout", &self.timeout)
+            .field("max_failures", &self.max_failures)
+            .finish()
+    }
+}
+
+impl EnricherChain {
+    /// Append an enricher, which runs after those already added.
+    pub fn push(&mut self, enricher: impl Enricher + 'static) {
+        self.enrichers.push(Arc::new(Slot {
+            enricher: Box::new(enricher),
+            health: HookHealth::default(),
+        }));
+    }
+
+    /// Set how long each enricher may take. Default: 100ms.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Set how many consecutive failures disable an enricher. 0 never
+    /// disables it. Default: 5.
+    pub fn set_max_failures(&mut self, max_failures: u32) {
+        self.max_failures = max_failures;
+    }
+
+    /// Number of enrichers in the chain.
+    pub fn len(&self) -> usize {
+        self.enrichers.len()
+    }
+
+    /// Whether the chain has no enrichers.
+    pub fn is_empty(&self) -> bool {
+        self.enrichers.is_empty()
+    }
+
+    /// Run every enabled enricher on `call`, keeping the changes of those
+    /// that succeed within the timeout.
+    ///
+    /// Each enricher runs in its own task so that a panic is caught as an
+    /// error instead of unwinding through the caller.
+    pub(crate) async fn run(&self, mut call: LLMCall, events: &EventBus) -> LLMCall {
+        for slot in &self.enrichers {
+            if slot.health.is_disabled() {
+                continue;
+            }
+            let task = tokio::spawn({
+                let slot = Arc::clone(slot);
+                let mut enriched = call.clone();
+                async move {
+                    slot.enricher.enrich(&mut enriched).await?;
+                    Ok::<_, DiagnyxError>(enriched)
+                }
+            });
+            let abort = task.abort_handle();
+            let result = match tokio::time::timeout(self.timeout, task).await {
+                Ok(Ok(result)) => result.map_err(|e| e.to_string()),
+                Ok(Err(e)) => Err(match e.try_into_panic() {
+                    Ok(payload) => panic_message(payload.as_ref()),
+                    Err(e) => e.to_string(),
+                }),
+                Err(_) => {
+                    abort.abort();
+                    Err(DiagnyxError::Timeout(self.timeout).to_string())
+                }
+            };
+            match result {
+                Ok(enriched) => {
+                    slot.health.succeeded();
+                    call = enriched;
+                }
+                Err(error) => {
+                    let name = slot.enricher.name().to_string();
+                    events.emit(SdkEvent::EnrichmentFailed {
+                        enricher: name.clone(),
+                        error,
+                    });
+                    if let Some(failures) = slot.health.failed(self.max_failures) {
+                        events.emit(SdkEvent::HookDisabled {
+                            hook: name,
+                            failures,
+                        });
+                    }
+                }
+            }
+        }
+        call
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Provider;
+
+    struct Tag(&'static str);
+
+    #[async_trait]
+    impl Enricher for Tag {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        async fn enrich(&self, call: &mut LLMCall) -> Result<(), DiagnyxError> {
+            let metadata = call.metadata.get_or_insert_with(Default::default);
+            let order = metadata.len();
+            metadata.insert(self.0.to_string(), order.into());
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    #[async_trait]
+    impl Enricher for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        async fn enrich(&self, call: &mut LLMCall) -> Result<(), DiagnyxError> {
+            call.model = "changed".to_string();
+            Err(DiagnyxError::ConfigError("lookup failed".to_string()))
+        }
+    }
+
+    struct Slow;
+
+    #[async_trait]
+    impl Enricher for Slow {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        async fn enrich(&self, call: &mut LLMCall) -> Result<(), DiagnyxError> {
+            call.model = "changed".to_string();
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        }
+    }
+
+    struct Panicking;
+
+    #[async_trait]
+    impl Enricher for Panicking {
+        fn name(&self) -> &str {
+            "panicking"
+        }
+
+        async fn enrich(&self, _call: &mut LLMCall) -> Result<(), DiagnyxError> {
+            panic!("cache poisoned")
+        }
+    }
+
+    fn call() -> LLMCall {
+        LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_enrichers_run_in_order() {
+        let mut chain = EnricherChain::default();
+        chain.push(Tag("first"));
+        chain.push(Tag("second"));
+
+        let call = chain.run(call(), &EventBus::default()).await;
+        let metadata = call.metadata.unwrap();
+        assert_eq!(metadata["first"], 0);
+        assert_eq!(metadata["second"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_enrichers_are_isolated() {
+        let mut chain = EnricherChain::default();
+        chain.set_timeout(Duration::from_millis(10));
+        chain.push(Failing);
+        chain.push(Slow);
+        chain.push(Tag("after"));
+
+        let events = EventBus::default();
+        let mut receiver = events.subscribe();
+        let call = chain.run(call(), &events).await;
+
+        assert_eq!(call.model, "gpt-4");
+        assert!(call.metadata.unwrap().contains_key("after"));
+        for name in ["failing", "slow"] {
+            match receiver.recv().await.unwrap() {
+                SdkEvent::EnrichmentFailed { enricher, .. } => assert_eq!(enricher, name),
+                other => panic!("unexpected event: {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_enricher_is_disabled() {
+        let mut chain = EnricherChain::default();
+        chain.set_max_failures(2);
+        chain.push(Panicking);
+        chain.push(Tag("after"));
+
+        let events = EventBus::default();
+        let mut receiver = events.subscribe();
+        for _ in 0..3 {
+            let call = chain.run(call(), &events).await;
+            assert!(call.metadata.unwrap().contains_key("after"));
+        }
+
+        let mut failed = 0;
+        while let Ok(event) = receiver.try_recv() {
+            match event {
+                SdkEvent::EnrichmentFailed { error, .. } => {
+                    assert!(error.contains("cache poisoned"));
+                    failed += 1;
+                }
+                SdkEvent::HookDisabled { hook, failures } => {
+                    assert_eq!((hook.as_str(), failures), ("panicking", 2));
+                }
+                other => panic!("unexpected event: {:?}", other),
+            }
+        }
+        assert_eq!(failed, 2);
+    }
+}
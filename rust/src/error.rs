@@ -10,14 +10,78 @@ pub enum DiagnyxError {
     SerializationError(#[from] serde_json::Error),
 
     #[error("API error: HTTP {status_code} - {message}")]
-    ApiError { status_code: u16, message: String },
+    ApiError {
+        status_code: u16,
+        message: String,
+        /// `X-Request-Id` sent with the request, to quote in support
+        /// tickets.
+        request_id: Option<String>,
+    },
+
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
 
     #[error("Configuration error: {0}")]
     ConfigError(String),
 
+    #[error("Operation timed out after {0:?}")]
+    Timeout(std::time::Duration),
+
+    /// Some batches of a flush were sent and others were not; `report`
+    /// covers those sent.
+    #[error("Flush partly failed after sending {} calls: {error}", .report.calls)]
+    PartialFlush {
+        report: Box<crate::client::FlushReport>,
+        error: Box<DiagnyxError>,
+    },
+
+    /// A client-side limit refused the request without sending it.
+    #[error("Too many requests; retry after {retry_after:?}")]
+    TooManyRequests { retry_after: std::time::Duration },
+
+    #[error("API key rejected (HTTP {status_code}); tracked calls are parked")]
+    AuthFailed { status_code: u16 },
+
     #[error("Max retries exceeded")]
     MaxRetriesExceeded,
 
+    #[error("Budget exceeded: spent ${spent_usd:.4} of ${budget_usd:.4}")]
+    BudgetExceeded { spent_usd: f64, budget_usd: f64 },
+
+    #[error("Template error: {0}")]
+    TemplateError(String),
+
     #[error("Guardrail violation: {0}")]
     ViolationError(Box<dyn std::error::Error + Send + Sync>),
 }
+
+impl DiagnyxError {
+    /// ID of the request the API rejected, sent as `X-Request-Id`.
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            Self::ApiError { request_id, .. } => request_id.as_deref(),
+            Self::PartialFlush { error, .. } => error.request_id(),
+            _ => None,
+        }
+    }
+
+    /// The error message followed by the request ID, if any, for logs.
+    pub(crate) fn with_request_id(&self, message: String) -> String {
+        match self.request_id() {
+            Some(request_id) => format!("{} (request ID {})", message, request_id),
+            None => message,
+        }
+    }
+
+    /// HTTP status code if the API rejected the request's credentials.
+    pub fn auth_failure_status(&self) -> Option<u16> {
+        match self {
+            Self::ApiError { status_code, .. } if matches!(status_code, 401 | 403) => {
+                Some(*status_code)
+            }
+            Self::AuthFailed { status_code } => Some(*status_code),
+            Self::PartialFlush { error, .. } => error.auth_failure_status(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,628 @@
+//! Evaluations Module for Diagnyx Rust SDK
+//!
+//! Provides methods for recording evaluation runs: a named set of model
+//! outputs, each scored against an expected answer. Results can carry the
+//! scores of several scorers, such as exact match, semantic similarity or an
+//! LLM judge, which the run summary aggregates per scorer.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::evaluations::{EvaluationClient, EvaluationResult, ScorerResult};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let client = EvaluationClient::new("dx_api_key", "org-123");
+//!
+//!     let run = client.create_run("nightly-qa", "gpt-4o-mini", None).await?;
+//!     client
+//!         .submit_results(
+//!             &run.id,
+//!             vec![EvaluationResult::new("2+2?", "4", 1.0)
+//!                 .expected_output("4")
+//!                 .scorer_result(ScorerResult::exact_match("4", "4"))],
+//!         )
+//!         .await?;
+//!     let run = client.complete_run(&run.id).await?;
+//!     println!("Mean score: {:?}", run.mean_score);
+//!
+//!     let summary = client.get_summary(&run.id).await?;
+//!     println!("Pass rate: {:?}", summary.pass_rate);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::DiagnyxError;
+use crate::http::{HttpConfig, REQUEST_TIMEOUT};
+use crate::ids::TraceId;
+use crate::ratelimit::RateLimiter;
+use crate::retry::{send_with_retry, RetryPolicy};
+use crate::types::{default_base_url, Masked};
+
+/// Lifecycle status of an evaluation run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvaluationRunStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+/// An evaluation run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationRun {
+    pub id: String,
+    pub name: String,
+    pub model: String,
+    pub status: EvaluationRunStatus,
+    #[serde(default)]
+    pub result_count: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_score: Option<f64>,
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// How a result was scored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "snake_case",
+    rename_all_fields = "camelCase"
+)]
+pub enum Scorer {
+    /// The output equals the expected output.
+    ExactMatch { case_sensitive: bool },
+    /// Cosine similarity of the embeddings of the output and the expected
+    /// output, passing at or above `threshold`.
+    SemanticSimilarity {
+        embedding_model: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        threshold: Option<f64>,
+    },
+    /// A model grading the output against a rubric.
+    LlmJudge { judge_model: String, rubric: String },
+    /// Any other scorer, by name.
+    Custom { name: String },
+}
+
+impl Scorer {
+    /// Name the run summary groups the scorer's results under.
+    pub fn name(&self) -> &str {
+        match self {
+            Scorer::ExactMatch { .. } => "exact_match",
+            Scorer::SemanticSimilarity { .. } => "semantic_similarity",
+            Scorer::LlmJudge { .. } => "llm_judge",
+            Scorer::Custom { name } => name,
+        }
+    }
+}
+
+/// The score a scorer gave a result.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScorerResult {
+    pub scorer: Scorer,
+    pub score: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passed: Option<bool>,
+    /// Explanation of the score, such as an LLM judge's reasoning.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+}
+
+impl ScorerResult {
+    pub fn new(scorer: Scorer, score: f64) -> Self {
+        Self {
+            scorer,
+            score,
+            passed: None,
+            reasoning: None,
+        }
+    }
+
+    /// Score `output` by exact, case-sensitive match with `expected`,
+    /// ignoring surrounding whitespace.
+    pub fn exact_match(output: &str, expected: &str) -> Self {
+        let passed = output.trim() == expected.trim();
+        Self::new(
+            Scorer::ExactMatch {
+                case_sensitive: true,
+            },
+            if passed { 1.0 } else { 0.0 },
+        )
+        .passed(passed)
+    }
+
+    pub fn passed(mut self, passed: bool) -> Self {
+        self.passed = Some(passed);
+        self
+    }
+
+    pub fn reasoning(mut self, reasoning: impl Into<String>) -> Self {
+        self.reasoning = Some(reasoning.into());
+        self
+    }
+}
+
+/// Aggregate scores of a run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationSummary {
+    pub run_id: String,
+    pub result_count: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_score: Option<f64>,
+    /// Share of results that passed every scorer reporting a verdict.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pass_rate: Option<f64>,
+    /// Summaries by scorer name.
+    #[serde(default)]
+    pub scorers: HashMap<String, ScorerSummary>,
+}
+
+/// Aggregate scores of one scorer within a run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScorerSummary {
+    pub count: i64,
+    pub mean_score: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pass_rate: Option<f64>,
+}
+
+/// A single scored model output within a run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationResult {
+    pub input: String,
+    pub output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_output: Option<String>,
+    /// Overall score, if the result has been scored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<TraceId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scorer_results: Vec<ScorerResult>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+impl EvaluationResult {
+    pub fn new(input: impl Into<String>, output: impl Into<String>, score: f64) -> Self {
+        Self {
+            score: Some(score),
+            ..Self::unscored(input, output)
+        }
+    }
+
+    /// A result without a score yet, such as one awaiting human review.
+    pub fn unscored(input: impl Into<String>, output: impl Into<String>) -> Self {
+        Self {
+            input: input.into(),
+            output: output.into(),
+            expected_output: None,
+            score: None,
+            trace_id: None,
+            latency_ms: None,
+            scorer_results: Vec::new(),
+            metadata: HashMap::new(),
+        }
+    }
+
+    pub fn expected_output(mut self, expected: impl Into<String>) -> Self {
+        self.expected_output = Some(expected.into());
+        self
+    }
+
+    pub fn trace_id(mut self, trace_id: TraceId) -> Self {
+        self.trace_id = Some(trace_id);
+        self
+    }
+
+    pub fn latency_ms(mut self, latency_ms: i64) -> Self {
+        self.latency_ms = Some(latency_ms);
+        self
+    }
+
+    /// Add the score of a scorer, after those already added.
+    pub fn scorer_result(mut self, result: ScorerResult) -> Self {
+        self.scorer_results.push(result);
+        self
+    }
+
+    pub fn metadata(mut self, metadata: HashMap<String, serde_json::Value>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// Configuration for EvaluationClient.
+#[derive(Clone)]
+pub struct EvaluationClientConfig {
+    pub api_key: String,
+    pub organization_id: String,
+    pub base_url: String,
+    pub retry_policy: RetryPolicy,
+    /// Spaces out requests to the API, retries included. Default: None (no
+    /// limit)
+    pub rate_limiter: Option<RateLimiter>,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
+    pub debug: bool,
+}
+
+impl fmt::Debug for EvaluationClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EvaluationClientConfig")
+            .field("api_key", &Masked(&self.api_key))
+            .field("organization_id", &self.organization_id)
+            .field("base_url", &self.base_url)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .finish()
+    }
+}
+
+impl EvaluationClientConfig {
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        let api_key = api_key.into();
+        Self {
+            base_url: default_base_url(&api_key),
+            api_key,
+            organization_id: organization_id.into(),
+            retry_policy: RetryPolicy::new(3),
+            rate_limiter: None,
+            http: HttpConfig::default(),
+            debug: false,
+        }
+    }
+
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into();
+        self
+    }
+
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.retry_policy.max_attempts = retries as u32;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Space out requests with `limiter`, e.g. the one shared by the other
+    /// clients, so that they stay within one rate together.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+}
+
+/// Client for recording evaluation runs.
+pub struct EvaluationClient {
+    config: EvaluationClientConfig,
+    http_client: Client,
+}
+
+impl EvaluationClient {
+    /// Create a new EvaluationClient with default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        Self::with_config(EvaluationClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new EvaluationClient with custom configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_with_config`](Self::try_with_config) to handle the error instead.
+    pub fn with_config(config: EvaluationClientConfig) -> Self {
+        Self::try_with_config(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new EvaluationClient with default settings, returning an error if
+    /// the HTTP client cannot be created.
+    pub fn try_new(
+        api_key: impl Into<String>,
+        organization_id: impl Into<String>,
+    ) -> Result<Self, DiagnyxError> {
+        Self::try_with_config(EvaluationClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new EvaluationClient with custom configuration, returning an error
+    /// if the HTTP client cannot be created.
+    pub fn try_with_config(config: EvaluationClientConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config.http.client(REQUEST_TIMEOUT)?;
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Create a new EvaluationClient sending requests with `http_client`.
+    ///
+    /// The HTTP settings of `config` are not applied to it.
+    pub fn with_http_client(config: EvaluationClientConfig, http_client: Client) -> Self {
+        Self {
+            config,
+            http_client,
+        }
+    }
+
+    /// Create a new evaluation run.
+    pub async fn create_run(
+        &self,
+        name: &str,
+        model: &str,
+        metadata: Option<HashMap<String, serde_json::Value>>,
+    ) -> Result<EvaluationRun, DiagnyxError> {
+        let payload = serde_json::json!({
+            "name": name,
+            "model": model,
+            "metadata": metadata.unwrap_or_default(),
+        });
+
+        self.request("POST", &self.runs_path(None, ""), Some(payload))
+            .await
+    }
+
+    /// Submit scored results to a run.
+    pub async fn submit_results(
+        &self,
+        run_id: &str,
+        results: Vec<EvaluationResult>,
+    ) -> Result<(), DiagnyxError> {
+        let payload = serde_json::json!({ "results": results });
+        let _: serde_json::Value = self
+            .request(
+                "POST",
+                &self.runs_path(Some(run_id), "/results"),
+                Some(payload),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Mark a run as completed and return its final state.
+    pub async fn complete_run(&self, run_id: &str) -> Result<EvaluationRun, DiagnyxError> {
+        self.request("POST", &self.runs_path(Some(run_id), "/complete"), None)
+            .await
+    }
+
+    /// Get a run by ID.
+    pub async fn get_run(&self, run_id: &str) -> Result<EvaluationRun, DiagnyxError> {
+        self.request("GET", &self.runs_path(Some(run_id), ""), None)
+            .await
+    }
+
+    /// Get the aggregate scores of a run, overall and by scorer.
+    pub async fn get_summary(&self, run_id: &str) -> Result<EvaluationSummary, DiagnyxError> {
+        self.request("GET", &self.runs_path(Some(run_id), "/summary"), None)
+            .await
+    }
+
+    fn runs_path(&self, run_id: Option<&str>, suffix: &str) -> String {
+        let mut path = format!(
+            "/api/v1/organizations/{}/evaluations/runs",
+            self.config.organization_id
+        );
+        if let Some(id) = run_id {
+            path.push('/');
+            path.push_str(id);
+        }
+        path.push_str(suffix);
+        path
+    }
+
+    async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, DiagnyxError> {
+        let url = format!("{}{}", self.config.base_url, path);
+        let method = match method {
+            "POST" => Method::POST,
+            "GET" => Method::GET,
+            _ => {
+                return Err(DiagnyxError::ConfigError(format!(
+                    "Unknown method: {}",
+                    method
+                )))
+            }
+        };
+
+        let response = send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            method,
+            |method| {
+                let mut request = self
+                    .http_client
+                    .request(method, &url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", self.config.api_key));
+                if let Some(ref b) = body {
+                    request = request.json(b);
+                }
+                request
+            },
+        )
+        .await?;
+
+        response
+            .json()
+            .await
+            .map_err(|e| DiagnyxError::ConfigError(format!("Failed to parse response: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn create_mock_client(server: &MockServer) -> EvaluationClient {
+        EvaluationClient::with_config(
+            EvaluationClientConfig::new("test-api-key", "org-1")
+                .base_url(server.uri())
+                .max_retries(1),
+        )
+    }
+
+    #[test]
+    fn test_evaluation_result_serialization() {
+        let result = EvaluationResult::new("2+2?", "4", 1.0)
+            .expected_output("4")
+            .latency_ms(120);
+        let json = serde_json::to_value(&result).unwrap();
+
+        assert_eq!(json["expectedOutput"], "4");
+        assert_eq!(json["latencyMs"], 120);
+        assert!(json.get("traceId").is_none());
+        assert!(json.get("metadata").is_none());
+        assert!(json.get("scorerResults").is_none());
+    }
+
+    #[test]
+    fn test_scorer_result_serialization() {
+        let result = EvaluationResult::new("2+2?", "4", 1.0)
+            .scorer_result(ScorerResult::exact_match(" 4\n", "4"))
+            .scorer_result(
+                ScorerResult::new(
+                    Scorer::LlmJudge {
+                        judge_model: "gpt-4o".to_string(),
+                        rubric: "Is the answer correct?".to_string(),
+                    },
+                    0.9,
+                )
+                .reasoning("Correct"),
+            );
+        let json = serde_json::to_value(&result).unwrap();
+
+        assert_eq!(
+            json["scorerResults"][0],
+            serde_json::json!({
+                "scorer": {"type": "exact_match", "caseSensitive": true},
+                "score": 1.0,
+                "passed": true
+            })
+        );
+        assert_eq!(json["scorerResults"][1]["scorer"]["type"], "llm_judge");
+        assert_eq!(json["scorerResults"][1]["scorer"]["judgeModel"], "gpt-4o");
+        assert_eq!(json["scorerResults"][1]["reasoning"], "Correct");
+        assert_eq!(ScorerResult::exact_match("5", "4").score, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_get_summary() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/api/v1/organizations/org-1/evaluations/runs/run-1/summary",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "runId": "run-1",
+                "resultCount": 4,
+                "meanScore": 0.75,
+                "passRate": 0.5,
+                "scorers": {
+                    "exact_match": {"count": 4, "meanScore": 0.5, "passRate": 0.5}
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let summary = create_mock_client(&server)
+            .get_summary("run-1")
+            .await
+            .unwrap();
+        assert_eq!(summary.pass_rate, Some(0.5));
+        assert_eq!(summary.scorers["exact_match"].count, 4);
+    }
+
+    #[tokio::test]
+    async fn test_run_lifecycle() {
+        let server = MockServer::start().await;
+        let run = serde_json::json!({
+            "id": "run-1",
+            "name": "nightly",
+            "model": "gpt-4",
+            "status": "running",
+            "createdAt": "2024-03-01T00:00:00Z"
+        });
+        Mock::given(method("POST"))
+            .and(path("/api/v1/organizations/org-1/evaluations/runs"))
+            .and(body_partial_json(serde_json::json!({"name": "nightly"})))
+            .respond_with(ResponseTemplate::new(201).set_body_json(run.clone()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/evaluations/runs/run-1/results",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "accepted": 1
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let mut completed = run.clone();
+        completed["status"] = "completed".into();
+        completed["meanScore"] = 0.5.into();
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/evaluations/runs/run-1/complete",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(completed))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = create_mock_client(&server);
+        let run = client.create_run("nightly", "gpt-4", None).await.unwrap();
+        assert_eq!(run.status, EvaluationRunStatus::Running);
+
+        client
+            .submit_results(&run.id, vec![EvaluationResult::new("q", "a", 0.5)])
+            .await
+            .unwrap();
+
+        let run = client.complete_run(&run.id).await.unwrap();
+        assert_eq!(run.status, EvaluationRunStatus::Completed);
+        assert_eq!(run.mean_score, Some(0.5));
+    }
+}
@@ -0,0 +1,145 @@
+//! Structured SDK events.
+//!
+//! Clients publish what they are doing (flushes, dropped calls, terminated
+//! guardrail sessions) on an event bus so that applications can feed the
+//! SDK's own behaviour into their observability stack.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::{DiagnyxClient, SdkEvent};
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let client = DiagnyxClient::new("dx_live_your_api_key");
+//!     let mut events = client.subscribe_events();
+//!
+//!     tokio::spawn(async move {
+//!         while let Ok(event) = events.recv().await {
+//!             if let SdkEvent::FlushFailed { error } = event {
+//!                 eprintln!("diagnyx flush failed: {}", error);
+//!             }
+//!         }
+//!     });
+//! }
+//! ```
+
+use tokio::sync::broadcast;
+
+use crate::budgets::BudgetScope;
+use crate::ids::SessionId;
+
+/// Number of events buffered per subscriber before the oldest are skipped.
+const DEFAULT_CAPACITY: usize = 256;
+
+/// An event emitted by the SDK.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SdkEvent {
+    /// A batch of buffered calls is about to be sent.
+    FlushStarted { count: usize },
+    /// A batch of calls was accepted by the API.
+    FlushSucceeded { count: usize },
+    /// A batch failed to send; its calls are kept for the next flush.
+    FlushFailed { error: String },
+    /// Calls were discarded because the buffer was full.
+    CallDropped { count: usize },
+    /// The buffer filled past its high watermark; calls will be dropped if
+    /// it reaches `capacity`.
+    BufferHigh { depth: usize, capacity: usize },
+    /// The buffer drained to its low watermark after a [`BufferHigh`].
+    ///
+    /// [`BufferHigh`]: SdkEvent::BufferHigh
+    BufferDrained { depth: usize, capacity: usize },
+    /// The captured content of `count` buffered calls was dropped to keep
+    /// the buffer under `max_content_bytes`; their metrics were kept.
+    ContentStripped { count: usize },
+    /// A call larger than `max_call_bytes` was trimmed when tracked; the
+    /// trimmed fields are listed, as in its `diagnyx.trimmed` metadata.
+    CallTrimmed { fields: Vec<String> },
+    /// An enricher failed or timed out; the call was tracked without its
+    /// changes.
+    EnrichmentFailed { enricher: String, error: String },
+    /// A filter panicked; the call was tracked as it was before the filter.
+    FilterFailed { filter: usize, error: String },
+    /// An enricher or filter failed `failures` times in a row and will no
+    /// longer run. Filters are named by their position, as in `filter #0`.
+    HookDisabled { hook: String, failures: u32 },
+    /// A guardrail session was terminated early by a blocking violation.
+    SessionTerminated {
+        session_id: SessionId,
+        reason: String,
+    },
+    /// The API repeatedly rejected the API key. Flushing stopped and the
+    /// buffered calls were parked.
+    AuthFailed { status_code: u16, parked: usize },
+    /// A flush succeeded after `failures` consecutive failed flushes.
+    FlushRecovered { failures: usize },
+    /// A budget that only warns was exhausted when checked before a call.
+    BudgetWarning {
+        scope: BudgetScope,
+        spent_usd: f64,
+        limit_usd: f64,
+    },
+}
+
+/// Broadcasts [`SdkEvent`]s to any number of subscribers.
+///
+/// Events are dropped when nobody is subscribed. A subscriber that falls
+/// more than the bus capacity behind receives `RecvError::Lagged` and then
+/// continues from the oldest retained event.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<SdkEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl EventBus {
+    /// Create a bus retaining up to `capacity` events per subscriber.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self { sender }
+    }
+
+    /// Subscribe to events emitted after this call.
+    pub fn subscribe(&self) -> broadcast::Receiver<SdkEvent> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: SdkEvent) {
+        // Sending only fails when there are no subscribers
+        let _ = self.sender.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_subscribers_receive_events() {
+        let bus = EventBus::default();
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+
+        bus.emit(SdkEvent::FlushStarted { count: 2 });
+
+        assert_eq!(
+            first.recv().await.unwrap(),
+            SdkEvent::FlushStarted { count: 2 }
+        );
+        assert_eq!(
+            second.recv().await.unwrap(),
+            SdkEvent::FlushStarted { count: 2 }
+        );
+    }
+
+    #[test]
+    fn test_emit_without_subscribers() {
+        EventBus::new(1).emit(SdkEvent::CallDropped { count: 1 });
+    }
+}
@@ -7,35 +7,47 @@
 //!
 //! ```rust,no_run
 //! use diagnyx::feedback::{FeedbackClient, FeedbackOptions};
+//! use diagnyx::TraceId;
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     let client = FeedbackClient::new("dx_api_key", "org-123");
+//!     let trace_id: TraceId = "trace_123".parse()?;
 //!
 //!     // Submit thumbs up
-//!     client.thumbs_up("trace_123", None).await?;
+//!     client.thumbs_up(&trace_id, None).await?;
 //!
 //!     // Submit rating
-//!     client.rating("trace_123", 4, None).await?;
+//!     client.rating(&trace_id, 4, None).await?;
 //!
 //!     // Submit with options
 //!     let options = FeedbackOptions::builder()
 //!         .tags(vec!["accurate", "helpful"])
 //!         .user_id("user_123")
 //!         .build();
-//!     client.text("trace_123", "Great response!", Some(options)).await?;
+//!     client.text(&trace_id, "Great response!", Some(options)).await?;
 //!
 //!     Ok(())
 //! }
 //! ```
 
 use chrono::{DateTime, Utc};
-use reqwest::Client;
+use reqwest::{Client, Method};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use crate::error::DiagnyxError;
+#[cfg(feature = "guardrails")]
+use crate::guardrails::GuardrailSession;
+use crate::http::{HttpConfig, REQUEST_TIMEOUT};
+use crate::ids::{SessionId, TraceId};
+use crate::ratelimit::RateLimiter;
+use crate::redact::ContentRedactor;
+use crate::retry::{send_with_retry, RetryPolicy};
+use crate::types::{default_base_url, disabled_by_env, Masked};
 
 /// Types of feedback that can be submitted.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -63,7 +75,7 @@ pub enum FeedbackSentiment {
 #[serde(rename_all = "camelCase")]
 pub struct Feedback {
     pub id: String,
-    pub trace_id: String,
+    pub trace_id: TraceId,
     pub feedback_type: FeedbackType,
     pub sentiment: FeedbackSentiment,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -79,9 +91,11 @@ pub struct Feedback {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub session_id: Option<String>,
+    pub session_id: Option<SessionId>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub span_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guardrail_session_id: Option<SessionId>,
     pub created_at: DateTime<Utc>,
 }
 
@@ -99,6 +113,36 @@ pub struct FeedbackSummary {
     pub feedback_by_tag: HashMap<String, i32>,
 }
 
+/// Outcome of the guardrail session that checked the response.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuardrailContext {
+    /// Whether the session was terminated, i.e. the response was blocked.
+    pub terminated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub termination_reason: Option<String>,
+    /// IDs of the policies the response violated.
+    #[serde(default)]
+    pub violated_policies: Vec<String>,
+}
+
+#[cfg(feature = "guardrails")]
+impl From<&GuardrailSession> for GuardrailContext {
+    fn from(session: &GuardrailSession) -> Self {
+        let mut violated_policies: Vec<String> = Vec::new();
+        for violation in &session.violations {
+            if !violated_policies.contains(&violation.policy_id) {
+                violated_policies.push(violation.policy_id.clone());
+            }
+        }
+        Self {
+            terminated: session.terminated,
+            termination_reason: session.termination_reason.clone(),
+            violated_policies,
+        }
+    }
+}
+
 /// Options for feedback submission.
 #[derive(Debug, Clone, Default)]
 pub struct FeedbackOptions {
@@ -107,7 +151,13 @@ pub struct FeedbackOptions {
     pub tags: Option<Vec<String>>,
     pub metadata: Option<HashMap<String, serde_json::Value>>,
     pub user_id: Option<String>,
-    pub session_id: Option<String>,
+    pub session_id: Option<SessionId>,
+    /// Guardrail session that checked the response the feedback is about.
+    pub guardrail_session_id: Option<SessionId>,
+    pub guardrail_context: Option<GuardrailContext>,
+    /// The user consented to their comment or correction being kept as
+    /// written, so the client's redactor is not applied to it.
+    pub pii_consent: bool,
 }
 
 impl FeedbackOptions {
@@ -124,7 +174,10 @@ pub struct FeedbackOptionsBuilder {
     tags: Option<Vec<String>>,
     metadata: Option<HashMap<String, serde_json::Value>>,
     user_id: Option<String>,
-    session_id: Option<String>,
+    session_id: Option<SessionId>,
+    guardrail_session_id: Option<SessionId>,
+    guardrail_context: Option<GuardrailContext>,
+    pii_consent: bool,
 }
 
 impl FeedbackOptionsBuilder {
@@ -153,8 +206,27 @@ impl FeedbackOptionsBuilder {
         self
     }
 
-    pub fn session_id(mut self, session_id: impl Into<String>) -> Self {
-        self.session_id = Some(session_id.into());
+    pub fn session_id(mut self, session_id: SessionId) -> Self {
+        self.session_id = Some(session_id);
+        self
+    }
+
+    pub fn guardrail_session_id(mut self, session_id: SessionId) -> Self {
+        self.guardrail_session_id = Some(session_id);
+        self
+    }
+
+    /// Link the feedback to a guardrail session, including whether it
+    /// blocked the response and which policies were violated.
+    #[cfg(feature = "guardrails")]
+    pub fn guardrail_session(mut self, session: &GuardrailSession) -> Self {
+        self.guardrail_session_id = Some(session.session_id.clone());
+        self.guardrail_context = Some(GuardrailContext::from(session));
+        self
+    }
+
+    pub fn pii_consent(mut self, consent: bool) -> Self {
+        self.pii_consent = consent;
         self
     }
 
@@ -166,6 +238,9 @@ impl FeedbackOptionsBuilder {
             metadata: self.metadata,
             user_id: self.user_id,
             session_id: self.session_id,
+            guardrail_session_id: self.guardrail_session_id,
+            guardrail_context: self.guardrail_context,
+            pii_consent: self.pii_consent,
         }
     }
 }
@@ -191,24 +266,80 @@ pub struct FeedbackListResult {
     pub offset: i32,
 }
 
+/// Limit on the feedback submitted by one user, or one session when no user
+/// is given, e.g. to absorb repeated clicks on a feedback button.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeedbackRateLimit {
+    pub max_submissions: u32,
+    /// Sliding window the submissions are counted over.
+    pub per: Duration,
+}
+
 /// Configuration for FeedbackClient.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct FeedbackClientConfig {
     pub api_key: String,
     pub organization_id: String,
     pub base_url: String,
+    /// Attempts per request as last set with the setters; requests are sent
+    /// with [`retry_policy`](Self::retry_policy) alone.
+    #[deprecated(note = "not read; set `retry_policy.max_attempts` instead")]
     pub max_retries: usize,
+    pub retry_policy: RetryPolicy,
+    /// Spaces out requests to the API, retries included. Default: None (no
+    /// limit)
+    pub rate_limiter: Option<RateLimiter>,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
     pub debug: bool,
+    /// Make submitting feedback a no-op returning the feedback as it would
+    /// have been recorded. Default: whether `DIAGNYX_DISABLED` is `1` or
+    /// `true`
+    pub disabled: bool,
+    /// Masks personal data in submitted comments and corrections, unless
+    /// the user consented to keeping it. Default: no redaction
+    pub redactor: ContentRedactor,
+    /// Submissions beyond it fail with `DiagnyxError::TooManyRequests`
+    /// without a request. Feedback without a user or session ID is not
+    /// limited. Default: None (no limit)
+    pub submission_limit: Option<FeedbackRateLimit>,
+}
+
+impl fmt::Debug for FeedbackClientConfig {
+    #[allow(deprecated)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FeedbackClientConfig")
+            .field("api_key", &Masked(&self.api_key))
+            .field("organization_id", &self.organization_id)
+            .field("base_url", &self.base_url)
+            .field("max_retries", &self.max_retries)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .field("disabled", &self.disabled)
+            .field("redactor", &self.redactor)
+            .field("submission_limit", &self.submission_limit)
+            .finish()
+    }
 }
 
 impl FeedbackClientConfig {
+    #[allow(deprecated)]
     pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        let api_key = api_key.into();
         Self {
-            api_key: api_key.into(),
+            base_url: default_base_url(&api_key),
+            api_key,
             organization_id: organization_id.into(),
-            base_url: "https://api.diagnyx.io".to_string(),
             max_retries: 3,
+            retry_policy: RetryPolicy::new(3),
+            rate_limiter: None,
+            http: HttpConfig::default(),
             debug: false,
+            disabled: disabled_by_env(),
+            redactor: ContentRedactor::new(),
+            submission_limit: None,
         }
     }
 
@@ -217,8 +348,30 @@ impl FeedbackClientConfig {
         self
     }
 
+    #[allow(deprecated)]
     pub fn max_retries(mut self, retries: usize) -> Self {
         self.max_retries = retries;
+        self.retry_policy.max_attempts = retries as u32;
+        self
+    }
+
+    #[allow(deprecated)]
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.max_retries = policy.max_attempts as usize;
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Space out requests with `limiter`, e.g. the one of the tracking
+    /// client's [`DiagnyxConfig`](crate::DiagnyxConfig), so that the clients
+    /// sharing it stay within one rate together.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
         self
     }
 
@@ -226,35 +379,135 @@ impl FeedbackClientConfig {
         self.debug = debug;
         self
     }
+
+    /// Turn feedback submission into a no-op, e.g. in test suites and
+    /// local development.
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = disabled;
+        self
+    }
+
+    pub fn redactor(mut self, redactor: ContentRedactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    /// Allow each user, or session, at most `max_submissions` within any
+    /// `per` window.
+    pub fn submission_limit(mut self, max_submissions: u32, per: Duration) -> Self {
+        self.submission_limit = Some(FeedbackRateLimit {
+            max_submissions,
+            per,
+        });
+        self
+    }
+}
+
+/// Feedback as it would have been recorded, for disabled clients.
+fn canned_feedback(
+    trace_id: &TraceId,
+    feedback_type: FeedbackType,
+    rating: Option<i32>,
+    comment: Option<String>,
+    correction: Option<String>,
+    options: FeedbackOptions,
+) -> Feedback {
+    let sentiment = match (feedback_type, rating) {
+        (FeedbackType::ThumbsUp, _) | (_, Some(4..)) => FeedbackSentiment::Positive,
+        (FeedbackType::ThumbsDown | FeedbackType::Flag, _) | (_, Some(..=2)) => {
+            FeedbackSentiment::Negative
+        }
+        _ => FeedbackSentiment::Neutral,
+    };
+    Feedback {
+        id: "disabled".to_string(),
+        trace_id: trace_id.clone(),
+        feedback_type,
+        sentiment,
+        rating,
+        comment: comment.or(options.comment),
+        correction,
+        tags: options.tags.unwrap_or_default(),
+        metadata: options.metadata.unwrap_or_default(),
+        user_id: options.user_id,
+        session_id: options.session_id,
+        span_id: options.span_id,
+        guardrail_session_id: options.guardrail_session_id,
+        created_at: Utc::now(),
+    }
 }
 
 /// Client for submitting and managing user feedback.
 pub struct FeedbackClient {
     config: FeedbackClientConfig,
     http_client: Client,
+    submissions: Mutex<Submissions>,
+}
+
+/// Keys of the submission limit below which stale keys are not swept.
+const MIN_SWEEP_KEYS: usize = 64;
+
+/// Recent submission times per user or session, for the submission limit.
+#[derive(Default)]
+struct Submissions {
+    times: HashMap<String, VecDeque<Instant>>,
+    /// Number of keys above which keys with no recent submission are
+    /// removed.
+    sweep_at: usize,
 }
 
 impl FeedbackClient {
     /// Create a new FeedbackClient with default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
     pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
         Self::with_config(FeedbackClientConfig::new(api_key, organization_id))
     }
 
     /// Create a new FeedbackClient with custom configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_with_config`](Self::try_with_config) to handle the error instead.
     pub fn with_config(config: FeedbackClientConfig) -> Self {
+        Self::try_with_config(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new FeedbackClient with default settings, returning an error if
+    /// the HTTP client cannot be created.
+    pub fn try_new(
+        api_key: impl Into<String>,
+        organization_id: impl Into<String>,
+    ) -> Result<Self, DiagnyxError> {
+        Self::try_with_config(FeedbackClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new FeedbackClient with custom configuration, returning an error
+    /// if the HTTP client cannot be created.
+    pub fn try_with_config(config: FeedbackClientConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config.http.client(REQUEST_TIMEOUT)?;
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Create a new FeedbackClient sending requests with `http_client`.
+    ///
+    /// The HTTP settings of `config` are not applied to it.
+    pub fn with_http_client(config: FeedbackClientConfig, http_client: Client) -> Self {
         Self {
             config,
-            http_client: Client::builder()
-                .timeout(Duration::from_secs(30))
-                .build()
-                .expect("Failed to create HTTP client"),
+            http_client,
+            submissions: Mutex::default(),
         }
     }
 
     /// Submit positive thumbs up feedback.
     pub async fn thumbs_up(
         &self,
-        trace_id: &str,
+        trace_id: &TraceId,
         options: Option<FeedbackOptions>,
     ) -> Result<Feedback, DiagnyxError> {
         self.submit(trace_id, FeedbackType::ThumbsUp, None, None, None, options)
@@ -264,33 +517,65 @@ impl FeedbackClient {
     /// Submit negative thumbs down feedback.
     pub async fn thumbs_down(
         &self,
-        trace_id: &str,
+        trace_id: &TraceId,
         options: Option<FeedbackOptions>,
     ) -> Result<Feedback, DiagnyxError> {
-        self.submit(trace_id, FeedbackType::ThumbsDown, None, None, None, options)
-            .await
+        self.submit(
+            trace_id,
+            FeedbackType::ThumbsDown,
+            None,
+            None,
+            None,
+            options,
+        )
+        .await
+    }
+
+    /// Submit thumbs down feedback on a response checked by a guardrail
+    /// session, e.g. one that was blocked or filtered, so that complaints
+    /// can be traced back to the policies involved.
+    #[cfg(feature = "guardrails")]
+    pub async fn guardrail_thumbs_down(
+        &self,
+        trace_id: &TraceId,
+        session: &GuardrailSession,
+        options: Option<FeedbackOptions>,
+    ) -> Result<Feedback, DiagnyxError> {
+        let options = FeedbackOptions {
+            guardrail_session_id: Some(session.session_id.clone()),
+            guardrail_context: Some(GuardrailContext::from(session)),
+            ..options.unwrap_or_default()
+        };
+        self.thumbs_down(trace_id, Some(options)).await
     }
 
     /// Submit a numeric rating (1-5).
     pub async fn rating(
         &self,
-        trace_id: &str,
+        trace_id: &TraceId,
         value: i32,
         options: Option<FeedbackOptions>,
     ) -> Result<Feedback, DiagnyxError> {
-        if value < 1 || value > 5 {
+        if !(1..=5).contains(&value) {
             return Err(DiagnyxError::ConfigError(
                 "Rating value must be between 1 and 5".to_string(),
             ));
         }
-        self.submit(trace_id, FeedbackType::Rating, Some(value), None, None, options)
-            .await
+        self.submit(
+            trace_id,
+            FeedbackType::Rating,
+            Some(value),
+            None,
+            None,
+            options,
+        )
+        .await
     }
 
     /// Submit text feedback.
     pub async fn text(
         &self,
-        trace_id: &str,
+        trace_id: &TraceId,
         comment: &str,
         options: Option<FeedbackOptions>,
     ) -> Result<Feedback, DiagnyxError> {
@@ -308,7 +593,7 @@ impl FeedbackClient {
     /// Submit a correction for fine-tuning.
     pub async fn correction(
         &self,
-        trace_id: &str,
+        trace_id: &TraceId,
         correction: &str,
         options: Option<FeedbackOptions>,
     ) -> Result<Feedback, DiagnyxError> {
@@ -326,7 +611,7 @@ impl FeedbackClient {
     /// Flag a response for review.
     pub async fn flag(
         &self,
-        trace_id: &str,
+        trace_id: &TraceId,
         reason: Option<&str>,
         options: Option<FeedbackOptions>,
     ) -> Result<Feedback, DiagnyxError> {
@@ -343,7 +628,7 @@ impl FeedbackClient {
 
     async fn submit(
         &self,
-        trace_id: &str,
+        trace_id: &TraceId,
         feedback_type: FeedbackType,
         rating: Option<i32>,
         comment: Option<String>,
@@ -351,6 +636,51 @@ impl FeedbackClient {
         options: Option<FeedbackOptions>,
     ) -> Result<Feedback, DiagnyxError> {
         let options = options.unwrap_or_default();
+        let reservation = self.admit(&options)?;
+        let result = self
+            .send(
+                trace_id,
+                feedback_type,
+                rating,
+                comment,
+                correction,
+                options,
+            )
+            .await;
+        if let (Err(_), Some(reservation)) = (&result, reservation) {
+            self.release(reservation);
+        }
+        result
+    }
+
+    async fn send(
+        &self,
+        trace_id: &TraceId,
+        feedback_type: FeedbackType,
+        rating: Option<i32>,
+        comment: Option<String>,
+        correction: Option<String>,
+        mut options: FeedbackOptions,
+    ) -> Result<Feedback, DiagnyxError> {
+        let (mut comment, mut correction) = (comment, correction);
+        if !options.pii_consent && !self.config.redactor.is_empty() {
+            for text in [&mut comment, &mut options.comment, &mut correction]
+                .into_iter()
+                .flatten()
+            {
+                *text = self.config.redactor.redact(text);
+            }
+        }
+        if self.config.disabled {
+            return Ok(canned_feedback(
+                trace_id,
+                feedback_type,
+                rating,
+                comment,
+                correction,
+                options,
+            ));
+        }
 
         let mut payload = serde_json::json!({
             "traceId": trace_id,
@@ -379,13 +709,70 @@ impl FeedbackClient {
             payload["userId"] = serde_json::Value::String(user_id.clone());
         }
         if let Some(session_id) = &options.session_id {
-            payload["sessionId"] = serde_json::Value::String(session_id.clone());
+            payload["sessionId"] = serde_json::json!(session_id);
+        }
+        if let Some(session_id) = &options.guardrail_session_id {
+            payload["guardrailSessionId"] = serde_json::json!(session_id);
+        }
+        if let Some(context) = &options.guardrail_context {
+            payload["guardrailContext"] = serde_json::json!(context);
         }
 
-        let response: Feedback = self.request("POST", "/api/v1/feedback", Some(payload)).await?;
+        let response: Feedback = self
+            .request("POST", "/api/v1/feedback", Some(payload))
+            .await?;
         Ok(response)
     }
 
+    /// Reserve a submission by the user or session of `options`, failing
+    /// if it would exceed the submission limit.
+    ///
+    /// Returns the reservation to [`release`](Self::release) if the
+    /// submission fails.
+    fn admit(&self, options: &FeedbackOptions) -> Result<Option<(String, Instant)>, DiagnyxError> {
+        let Some(limit) = self.config.submission_limit else {
+            return Ok(None);
+        };
+        let key = match (&options.user_id, &options.session_id) {
+            (Some(user_id), _) => format!("user:{}", user_id),
+            (None, Some(session_id)) => format!("session:{}", session_id),
+            (None, None) => return Ok(None),
+        };
+
+        let now = Instant::now();
+        let mut submissions = self.submissions.lock().unwrap_or_else(|e| e.into_inner());
+        let times = submissions.times.entry(key.clone()).or_default();
+        while times.front().is_some_and(|&t| now - t >= limit.per) {
+            times.pop_front();
+        }
+        if times.len() >= limit.max_submissions as usize {
+            let retry_after = times.front().map_or(limit.per, |&t| limit.per - (now - t));
+            return Err(DiagnyxError::TooManyRequests { retry_after });
+        }
+        times.push_back(now);
+
+        // Forget users and sessions that stopped submitting, once the map
+        // has doubled since it was last swept
+        if submissions.times.len() > submissions.sweep_at {
+            submissions
+                .times
+                .retain(|_, times| times.back().is_some_and(|&t| now - t < limit.per));
+            submissions.sweep_at = (submissions.times.len() * 2).max(MIN_SWEEP_KEYS);
+        }
+        Ok(Some((key, now)))
+    }
+
+    /// Give back a reservation from [`admit`](Self::admit) for a submission
+    /// that failed, so it does not count against the limit.
+    fn release(&self, (key, at): (String, Instant)) {
+        let mut submissions = self.submissions.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(times) = submissions.times.get_mut(&key) {
+            if let Some(position) = times.iter().position(|&t| t == at) {
+                times.remove(position);
+            }
+        }
+    }
+
     /// List feedback with filters.
     pub async fn list(
         &self,
@@ -455,7 +842,7 @@ impl FeedbackClient {
     }
 
     /// Get feedback for a specific trace.
-    pub async fn get_for_trace(&self, trace_id: &str) -> Result<Vec<Feedback>, DiagnyxError> {
+    pub async fn get_for_trace(&self, trace_id: &TraceId) -> Result<Vec<Feedback>, DiagnyxError> {
         let path = format!(
             "/api/v1/organizations/{}/feedback/trace/{}",
             self.config.organization_id, trace_id
@@ -470,52 +857,285 @@ impl FeedbackClient {
         body: Option<serde_json::Value>,
     ) -> Result<T, DiagnyxError> {
         let url = format!("{}{}", self.config.base_url, path);
-        let mut last_error = None;
+        let method = match method {
+            "POST" => Method::POST,
+            "GET" => Method::GET,
+            _ => {
+                return Err(DiagnyxError::ConfigError(format!(
+                    "Unknown method: {}",
+                    method
+                )))
+            }
+        };
 
-        for attempt in 0..self.config.max_retries {
-            let mut request = match method {
-                "POST" => self.http_client.post(&url),
-                "GET" => self.http_client.get(&url),
-                _ => return Err(DiagnyxError::ConfigError(format!("Unknown method: {}", method))),
-            };
+        let response = send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            method,
+            |method| {
+                let mut request = self
+                    .http_client
+                    .request(method, &url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", self.config.api_key));
+                if let Some(ref b) = body {
+                    request = request.json(b);
+                }
+                request
+            },
+        )
+        .await?;
 
-            request = request
-                .header("Content-Type", "application/json")
-                .header("Authorization", format!("Bearer {}", self.config.api_key));
+        response
+            .json()
+            .await
+            .map_err(|e| DiagnyxError::ConfigError(format!("Failed to parse response: {}", e)))
+    }
+}
 
-            if let Some(ref b) = body {
-                request = request.json(b);
-            }
+#[cfg(all(test, feature = "guardrails"))]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
 
-            match request.send().await {
-                Ok(response) => {
-                    let status = response.status();
-                    if status.is_success() {
-                        return response.json().await.map_err(|e| {
-                            DiagnyxError::ConfigError(format!("Failed to parse response: {}", e))
-                        });
-                    }
-
-                    let message = response.text().await.unwrap_or_default();
-                    last_error = Some(DiagnyxError::ApiError {
-                        status_code: status.as_u16(),
-                        message,
-                    });
-
-                    if status.is_client_error() {
-                        break;
-                    }
-                }
-                Err(e) => {
-                    last_error = Some(DiagnyxError::HttpError(e));
+    #[tokio::test]
+    async fn test_guardrail_thumbs_down_carries_session_context() {
+        use crate::guardrails::{EnforcementLevel, GuardrailViolation};
+        use crate::ProjectId;
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/feedback"))
+            .and(body_partial_json(serde_json::json!({
+                "feedbackType": "thumbs_down",
+                "userId": "user-1",
+                "guardrailSessionId": "sess-1",
+                "guardrailContext": {
+                    "terminated": true,
+                    "terminationReason": "PII detected",
+                    "violatedPolicies": ["pii"]
                 }
-            }
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "fb-1",
+                "traceId": "trace-1",
+                "feedbackType": "thumbs_down",
+                "sentiment": "negative",
+                "guardrailSessionId": "sess-1",
+                "createdAt": "2024-05-01T00:00:00Z"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
 
-            if attempt < self.config.max_retries - 1 {
-                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt as u32))).await;
-            }
-        }
+        let violation = GuardrailViolation {
+            policy_id: "pii".to_string(),
+            policy_type: "pii".to_string(),
+            message: "PII found".to_string(),
+            severity: EnforcementLevel::Blocking,
+            details: None,
+            start_offset: None,
+            end_offset: None,
+        };
+        let session = GuardrailSession {
+            session_id: SessionId::from_static("sess-1"),
+            organization_id: "org-1".to_string(),
+            project_id: ProjectId::from_static("proj-1"),
+            active_policies: vec!["pii".to_string()],
+            tokens_processed: 12,
+            violations: vec![violation.clone(), violation],
+            terminated: true,
+            termination_reason: Some("PII detected".to_string()),
+            allowed: false,
+        };
+
+        let client = FeedbackClient::with_config(
+            FeedbackClientConfig::new("test-api-key", "org-1").base_url(server.uri()),
+        );
+        let options = FeedbackOptions::builder().user_id("user-1").build();
+        let feedback = client
+            .guardrail_thumbs_down(&TraceId::from_static("trace-1"), &session, Some(options))
+            .await
+            .unwrap();
+        assert_eq!(feedback.guardrail_session_id.unwrap(), "sess-1");
+    }
+
+    #[tokio::test]
+    async fn test_client_sends_through_configured_proxy() {
+        let proxy = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/feedback"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "fb-1",
+                "traceId": "trace-1",
+                "feedbackType": "thumbs_up",
+                "sentiment": "positive",
+                "createdAt": "2024-05-01T00:00:00Z"
+            })))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        let client = FeedbackClient::with_config(
+            FeedbackClientConfig::new("test-api-key", "org-1")
+                .base_url("http://api.diagnyx.invalid")
+                .http(HttpConfig::new().proxy(proxy.uri())),
+        );
+        client
+            .thumbs_up(&TraceId::from_static("trace-1"), None)
+            .await
+            .unwrap();
+
+        let requests = proxy.received_requests().await.unwrap();
+        assert_eq!(requests[0].url.host_str(), Some("api.diagnyx.invalid"));
+    }
+
+    #[tokio::test]
+    async fn test_disabled_client_returns_canned_feedback() {
+        let client = FeedbackClient::with_config(
+            FeedbackClientConfig::new("", "org-1")
+                .base_url("http://127.0.0.1:9")
+                .disabled(true),
+        );
+        let trace_id = TraceId::from_static("trace-1");
+
+        let feedback = client.rating(&trace_id, 5, None).await.unwrap();
+        assert_eq!(feedback.trace_id, trace_id);
+        assert_eq!(feedback.rating, Some(5));
+        assert_eq!(feedback.sentiment, FeedbackSentiment::Positive);
+
+        let options = FeedbackOptions::builder().comment("wrong answer").build();
+        let feedback = client.thumbs_down(&trace_id, Some(options)).await.unwrap();
+        assert_eq!(feedback.sentiment, FeedbackSentiment::Negative);
+        assert_eq!(feedback.comment.as_deref(), Some("wrong answer"));
+    }
+
+    #[tokio::test]
+    async fn test_redactor_masks_feedback_text_without_consent() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/feedback"))
+            .and(body_partial_json(serde_json::json!({
+                "correction": "Reach me at [EMAIL] or [PHONE]"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "fb-1",
+                "traceId": "trace-1",
+                "feedbackType": "correction",
+                "sentiment": "neutral",
+                "createdAt": "2024-05-01T00:00:00Z"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let config = FeedbackClientConfig::new("test-key", "org-1")
+            .base_url(server.uri())
+            .redactor(ContentRedactor::pii());
+        let trace_id = TraceId::from_static("trace-1");
+
+        FeedbackClient::with_config(config.clone())
+            .correction(
+                &trace_id,
+                "Reach me at bob@example.com or 555-123-4567",
+                None,
+            )
+            .await
+            .unwrap();
+
+        let disabled = FeedbackClient::with_config(config.disabled(true));
+        let options = FeedbackOptions::builder()
+            .comment("bob@example.com")
+            .build();
+        let feedback = disabled.flag(&trace_id, None, Some(options)).await.unwrap();
+        assert_eq!(feedback.comment.as_deref(), Some("[EMAIL]"));
+        let options = FeedbackOptions::builder().pii_consent(true).build();
+        let feedback = disabled
+            .text(&trace_id, "bob@example.com", Some(options))
+            .await
+            .unwrap();
+        assert_eq!(feedback.comment.as_deref(), Some("bob@example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_submission_limit_applies_per_user() {
+        let client = FeedbackClient::with_config(
+            FeedbackClientConfig::new("", "org-1")
+                .disabled(true)
+                .submission_limit(2, Duration::from_secs(60)),
+        );
+        let trace_id = TraceId::from_static("trace-1");
+        let by = |user_id: &str| Some(FeedbackOptions::builder().user_id(user_id).build());
+
+        client.thumbs_up(&trace_id, by("alice")).await.unwrap();
+        client.thumbs_down(&trace_id, by("alice")).await.unwrap();
+        let error = client.thumbs_up(&trace_id, by("alice")).await.unwrap_err();
+        assert!(matches!(
+            error,
+            DiagnyxError::TooManyRequests { retry_after } if retry_after <= Duration::from_secs(60)
+        ));
+        client.thumbs_up(&trace_id, by("bob")).await.unwrap();
+        client.thumbs_up(&trace_id, None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_submissions_share_the_limit() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/feedback"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({
+                        "id": "fb-1",
+                        "traceId": "trace-1",
+                        "feedbackType": "thumbs_up",
+                        "sentiment": "positive",
+                        "createdAt": "2024-05-01T00:00:00Z"
+                    }))
+                    .set_delay(Duration::from_millis(100)),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = FeedbackClient::with_config(
+            FeedbackClientConfig::new("test-api-key", "org-1")
+                .base_url(server.uri())
+                .submission_limit(1, Duration::from_secs(60)),
+        );
+        let trace_id = TraceId::from_static("trace-1");
+        let options = || Some(FeedbackOptions::builder().user_id("alice").build());
+
+        let (first, second) = tokio::join!(
+            client.thumbs_up(&trace_id, options()),
+            client.thumbs_up(&trace_id, options()),
+        );
+        assert!(first.is_ok());
+        assert!(matches!(second, Err(DiagnyxError::TooManyRequests { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_failed_submissions_do_not_count_against_the_limit() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/feedback"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = FeedbackClient::with_config(
+            FeedbackClientConfig::new("test-api-key", "org-1")
+                .base_url(server.uri())
+                .max_retries(0)
+                .submission_limit(1, Duration::from_secs(60)),
+        );
+        let trace_id = TraceId::from_static("trace-1");
+        let options = || Some(FeedbackOptions::builder().user_id("alice").build());
 
-        Err(last_error.unwrap_or(DiagnyxError::MaxRetriesExceeded))
+        let error = client.thumbs_up(&trace_id, options()).await.unwrap_err();
+        assert!(!matches!(error, DiagnyxError::TooManyRequests { .. }));
+        let error = client.thumbs_up(&trace_id, options()).await.unwrap_err();
+        assert!(!matches!(error, DiagnyxError::TooManyRequests { .. }));
     }
 }
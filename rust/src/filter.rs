@@ -0,0 +1,222 @@
+//! Ingest-time filtering of tracked calls.
+//!
+//! A [`CallFilter`] sees every call before it is buffered and can modify it
+//! or drop it, for example to skip health-check prompts or to anonymize
+//! internal test users. Filters run in the order they were added to the
+//! [`DiagnyxConfig`](crate::DiagnyxConfig), after any enrichers, and a
+//! dropped call is not passed to later filters.
+//!
+//! A filter that panics is treated as keeping the call unchanged and an
+//! [`SdkEvent::FilterFailed`] is emitted. One that panics several times in
+//! a row is disabled and an [`SdkEvent::HookDisabled`] is emitted.
+//!
+//! Closures taking `&mut LLMCall` and returning a [`FilterAction`] can be
+//! used as filters.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::filter::FilterAction;
+//! use diagnyx::{DiagnyxClient, DiagnyxConfig, LLMCall};
+//!
+//! let config = DiagnyxConfig::new("dx_live_your_api_key")
+//!     .filter(|call: &mut LLMCall| match call.endpoint.as_deref() {
+//!         Some("/healthz") => FilterAction::Drop,
+//!         _ => FilterAction::Keep,
+//!     })
+//!     .filter(|call: &mut LLMCall| {
+//!         if call.user_identifier.as_deref().is_some_and(|u| u.ends_with("@test.internal")) {
+//!             call.user_identifier = Some("internal-test-user".to_string());
+//!         }
+//!         FilterAction::Keep
+//!     });
+//!
+//! let client = DiagnyxClient::with_config(config);
+//! ```
+
+use std::fmt;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Arc;
+
+use crate::events::{EventBus, SdkEvent};
+use crate::health::{panic_message, HookHealth, DEFAULT_MAX_FAILURES};
+use crate::types::LLMCall;
+
+/// What to do with a call after filtering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterAction {
+    /// Track the call, including any changes made by the filter.
+    Keep,
+    /// Discard the call.
+    Drop,
+}
+
+/// Drops or modifies calls before they are buffered.
+pub trait CallFilter: Send + Sync {
+    /// Inspect `call`, modifying it in place if needed.
+    fn filter(&self, call: &mut LLMCall) -> FilterAction;
+}
+
+impl<F> CallFilter for F
+where
+    F: Fn(&mut LLMCall) -> FilterAction + Send + Sync,
+{
+    fn filter(&self, call: &mut LLMCall) -> FilterAction {
+        self(call)
+    }
+}
+
+/// The filters of a client, run in order.
+#[derive(Clone)]
+pub struct FilterChain {
+    filters: Vec<Arc<Slot>>,
+    max_failures: u32,
+}
+
+/// A filter and its failure count.
+struct Slot {
+    filter: Box<dyn CallFilter>,
+    health: HookHealth,
+}
+
+impl Default for FilterChain {
+    fn default() -> Self {
+        Self {
+            filters: Vec::new(),
+            max_failures: DEFAULT_MAX_FAILURES,
+        }
+    }
+}
+
+impl fmt::Debug for FilterChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterChain")
+            .field("filters", &self.filters.len())
+            .field("max_failures", &self.max_failures)
+            .finish()
+    }
+}
+
+impl FilterChain {
+    /// Append a filter, which runs after those already added.
+    pub fn push(&mut self, filter: impl CallFilter + 'static) {
+        self.filters.push(Arc::new(Slot {
+            filter: Box::new(filter),
+            health: HookHealth::default(),
+        }));
+    }
+
+    /// Set how many consecutive panics disable a filter. 0 never disables
+    /// it. Default: 5.
+    pub fn set_max_failures(&mut self, max_failures: u32) {
+        self.max_failures = max_failures;
+    }
+
+    /// Number of filters in the chain.
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Whether the chain has no filters.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Run the enabled filters on `call`, returning it unless one of them
+    /// drops it.
+    pub(crate) fn run(&self, mut call: LLMCall, events: &EventBus) -> Option<LLMCall> {
+        for (index, slot) in self.filters.iter().enumerate() {
+            if slot.health.is_disabled() {
+                continue;
+            }
+            let mut filtered = call.clone();
+            match catch_unwind(AssertUnwindSafe(|| slot.filter.filter(&mut filtered))) {
+                Ok(FilterAction::Drop) => {
+                    slot.health.succeeded();
+                    return None;
+                }
+                Ok(FilterAction::Keep) => {
+                    slot.health.succeeded();
+                    call = filtered;
+                }
+                Err(payload) => {
+                    events.emit(SdkEvent::FilterFailed {
+                        filter: index,
+                        error: panic_message(payload.as_ref()),
+                    });
+                    if let Some(failures) = slot.health.failed(self.max_failures) {
+                        events.emit(SdkEvent::HookDisabled {
+                            hook: format!("filter #{}", index),
+                            failures,
+                        });
+                    }
+                }
+            }
+        }
+        Some(call)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Provider;
+
+    fn call(model: &str) -> LLMCall {
+        LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model(model)
+            .build()
+    }
+
+    #[test]
+    fn test_filters_modify_and_drop_in_order() {
+        let mut chain = FilterChain::default();
+        chain.push(|call: &mut LLMCall| {
+            call.model = call.model.to_uppercase();
+            FilterAction::Keep
+        });
+        chain.push(|call: &mut LLMCall| {
+            if call.model == "HEALTH" {
+                FilterAction::Drop
+            } else {
+                FilterAction::Keep
+            }
+        });
+
+        let events = EventBus::default();
+        assert_eq!(chain.run(call("gpt-4"), &events).unwrap().model, "GPT-4");
+        assert!(chain.run(call("health"), &events).is_none());
+    }
+
+    #[test]
+    fn test_panicking_filter_keeps_call_and_is_disabled() {
+        let mut chain = FilterChain::default();
+        chain.set_max_failures(1);
+        chain.push(|call: &mut LLMCall| {
+            call.model = "changed".to_string();
+            panic!("bad filter")
+        });
+
+        let events = EventBus::default();
+        let mut receiver = events.subscribe();
+        assert_eq!(chain.run(call("gpt-4"), &events).unwrap().model, "gpt-4");
+        assert_eq!(chain.run(call("gpt-4"), &events).unwrap().model, "gpt-4");
+
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            SdkEvent::FilterFailed {
+                filter: 0,
+                error: "panicked: bad filter".to_string(),
+            }
+        );
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            SdkEvent::HookDisabled {
+                hook: "filter #0".to_string(),
+                failures: 1,
+            }
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+}
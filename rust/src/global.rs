@@ -0,0 +1,104 @@
+//! A process-wide client.
+//!
+//! [`init`] installs a [`DiagnyxClient`] that any code can reach through
+//! [`global`] without an `Arc<DiagnyxClient>` being passed down to it, and
+//! [`shutdown`] flushes and removes it.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::{DiagnyxConfig, LLMCall, Provider};
+//!
+//! # async fn run() -> Result<(), diagnyx::DiagnyxError> {
+//! diagnyx::init(DiagnyxConfig::new("dx_live_your_api_key"))?;
+//!
+//! // Anywhere else in the process
+//! if let Some(client) = diagnyx::global() {
+//!     let call = LLMCall::builder()
+//!         .provider(Provider::OpenAI)
+//!         .model("gpt-4o")
+//!         .build();
+//!     client.track(call).await;
+//! }
+//!
+//! diagnyx::shutdown().await.into_result()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::{DiagnyxClient, ShutdownReport};
+use crate::error::DiagnyxError;
+use crate::types::DiagnyxConfig;
+use std::sync::{Arc, RwLock};
+
+static GLOBAL: RwLock<Option<Arc<DiagnyxClient>>> = RwLock::new(None);
+
+/// Create the process-wide client from `config`.
+///
+/// Must be called from within a Tokio runtime. Returns
+/// [`DiagnyxError::ConfigError`] if a client is already installed; call
+/// [`shutdown`] first to replace it.
+pub fn init(config: DiagnyxConfig) -> Result<(), DiagnyxError> {
+    let mut global = GLOBAL.write().unwrap_or_else(|e| e.into_inner());
+    if global.is_some() {
+        return Err(DiagnyxError::ConfigError(
+            "global client already initialized".to_string(),
+        ));
+    }
+    *global = Some(Arc::new(DiagnyxClient::try_with_config(config)?));
+    Ok(())
+}
+
+/// The process-wide client, if [`init`] has been called.
+pub fn global() -> Option<Arc<DiagnyxClient>> {
+    GLOBAL.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Shut down and remove the process-wide client, flushing its buffered
+/// calls. Reports nothing sent if there is none.
+///
+/// Clones returned by [`global`] earlier stay usable, but their calls are
+/// no longer flushed in the background.
+pub async fn shutdown() -> ShutdownReport {
+    let client = GLOBAL.write().unwrap_or_else(|e| e.into_inner()).take();
+    match client {
+        Some(client) => client.shutdown().await,
+        None => ShutdownReport::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LLMCall, Provider};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_init_global_shutdown() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let config = || {
+            DiagnyxConfig::new("test-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+        };
+
+        init(config()).unwrap();
+        assert!(init(config()).is_err());
+        let call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .build();
+        global().unwrap().track(call).await;
+
+        assert_eq!(shutdown().await.flushed, 1);
+        assert!(global().is_none());
+        assert_eq!(shutdown().await.flushed, 0);
+    }
+}
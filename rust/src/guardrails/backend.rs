@@ -0,0 +1,522 @@
+//! Pluggable guardrail backends.
+//!
+//! A [`GuardrailBackend`] decides whether streamed tokens may be released.
+//! [`StreamingGuardrail`](super::StreamingGuardrail) drives a backend and
+//! keeps the session state, so the same facade works whether verdicts come
+//! from the Diagnyx API ([`RemoteBackend`](super::remote::RemoteBackend)),
+//! from policies evaluated in-process
+//! ([`LocalBackend`](super::local::LocalBackend)), or from both through a
+//! [`LayeredBackend`].
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::guardrails::backend::LayeredBackend;
+//! use diagnyx::guardrails::local::{BlockedTerms, LocalBackend};
+//! use diagnyx::guardrails::remote::RemoteBackend;
+//! use diagnyx::guardrails::{StreamingGuardrail, StreamingGuardrailConfig};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let config = StreamingGuardrailConfig::new("dx_...", "org_123", "proj_456");
+//!
+//!     // Check every token locally, and the remote policies every 20 tokens
+//!     let local = LocalBackend::new().policy(BlockedTerms::new("secrets", ["BEGIN PRIVATE KEY"]));
+//!     let remote = RemoteBackend::try_new(config.clone())?;
+//!     let backend = LayeredBackend::new(local, remote).remote_every_n_tokens(20);
+//!
+//!     let guardrail = StreamingGuardrail::with_backend(config, backend);
+//!     guardrail.start_session(None).await?;
+//!     guardrail.evaluate("Hello", true).await?;
+//!     guardrail.complete_session().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use super::streaming::{SourceDocument, StreamingGuardrailSession, Violation};
+use crate::error::DiagnyxError;
+use crate::ids::SessionId;
+
+/// A session started by a backend.
+#[derive(Debug, Clone)]
+pub struct BackendSession {
+    pub session_id: SessionId,
+    pub active_policies: Vec<String>,
+}
+
+/// A blocking violation that ends the session.
+#[derive(Debug, Clone)]
+pub struct Termination {
+    pub reason: Option<String>,
+    pub violation: Violation,
+}
+
+/// Final outcome of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    pub allowed: bool,
+    pub total_tokens: i32,
+}
+
+/// A backend's decision about one token.
+#[derive(Debug, Clone, Default)]
+pub struct Verdict {
+    /// The token may be released.
+    pub allowed: bool,
+    /// Violations that did not end the session.
+    pub violations: Vec<Violation>,
+    /// Set when a blocking violation ends the session.
+    pub termination: Option<Termination>,
+    /// Set when the backend completed the session while evaluating the last
+    /// token.
+    pub completion: Option<Completion>,
+}
+
+impl Verdict {
+    /// A verdict releasing the token with no violations.
+    pub fn allow() -> Self {
+        Self {
+            allowed: true,
+            ..Default::default()
+        }
+    }
+
+    /// Combine with a verdict from a later stage.
+    ///
+    /// The token is allowed only if both allow it; violations are
+    /// concatenated and the first termination wins.
+    pub(crate) fn merge(mut self, other: Verdict) -> Self {
+        self.allowed = self.allowed && other.allowed;
+        self.violations.extend(other.violations);
+        self.termination = self.termination.or(other.termination);
+        self.completion = self.completion.or(other.completion);
+        self
+    }
+}
+
+/// Evaluates streamed tokens against guardrail policies.
+///
+/// A backend tracks at most one session at a time. Tokens are passed in the
+/// order they were produced, each with its index in the stream.
+#[async_trait]
+pub trait GuardrailBackend: Send + Sync {
+    /// Start a session, optionally with the user input that prompted it.
+    async fn start(&self, input: Option<&str>) -> Result<BackendSession, DiagnyxError>;
+
+    /// Evaluate the next token of the session.
+    async fn evaluate(
+        &self,
+        token: &str,
+        index: i32,
+        is_last: bool,
+    ) -> Result<Verdict, DiagnyxError>;
+
+    /// Complete the session.
+    ///
+    /// Text still pending is evaluated first; the returned verdict carries
+    /// its violations and termination, and the session's outcome as its
+    /// `completion` if the backend reports one.
+    async fn complete(&self) -> Result<Verdict, DiagnyxError>;
+
+    /// Cancel the session. Returns whether a session was cancelled.
+    async fn cancel(&self) -> Result<bool, DiagnyxError>;
+
+    /// Enable or disable debug logging for the current session only.
+    ///
+    /// Backends that do not log ignore it.
+    fn set_session_debug(&self, _debug: bool) {}
+
+    /// Add input context, such as retrieved documents or tool output, to
+    /// the session for contextual checks.
+    ///
+    /// Backends without contextual policies ignore it.
+    async fn add_context(&self, _context: &str) -> Result<(), DiagnyxError> {
+        Ok(())
+    }
+
+    /// Add source documents that the output should be grounded in.
+    ///
+    /// By default each document's content is added as input context.
+    async fn add_sources(&self, sources: &[SourceDocument]) -> Result<(), DiagnyxError> {
+        for source in sources {
+            self.add_context(&source.content).await?;
+        }
+        Ok(())
+    }
+
+    /// Evaluation requests sent to an API since the backend was created,
+    /// reported as [`GuardrailUsage::evaluations`](super::GuardrailUsage).
+    ///
+    /// Backends evaluating in-process send none.
+    fn evaluation_requests(&self) -> u64 {
+        0
+    }
+
+    /// State of the current session that is not in
+    /// [`StreamingGuardrailSession`], such as tokens not evaluated yet,
+    /// stored with the session so that it can be resumed.
+    async fn session_state(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Continue `session`, possibly started by another instance of the
+    /// backend, with the `state` its [`session_state`](Self::session_state)
+    /// returned.
+    ///
+    /// By default sessions cannot be resumed.
+    async fn resume(
+        &self,
+        _session: &StreamingGuardrailSession,
+        _state: Option<&serde_json::Value>,
+    ) -> Result<(), DiagnyxError> {
+        Err(DiagnyxError::ConfigError(
+            "This guardrail backend cannot resume sessions".to_string(),
+        ))
+    }
+}
+
+/// Tokens batched for a single evaluation.
+#[derive(Default, Serialize, Deserialize)]
+pub(super) struct PendingText {
+    text: String,
+    tokens: usize,
+    next_index: i32,
+    /// When the first token of the batch arrived.
+    #[serde(skip)]
+    since: Option<Instant>,
+}
+
+impl PendingText {
+    /// Add a token to the batch.
+    pub(super) fn push(&mut self, token: &str) {
+        self.text.push_str(token);
+        self.tokens += 1;
+        self.since.get_or_insert_with(Instant::now);
+    }
+
+    /// Number of tokens in the batch.
+    pub(super) fn tokens(&self) -> usize {
+        self.tokens
+    }
+
+    /// How long the oldest token of the batch has waited.
+    pub(super) fn age(&self) -> Duration {
+        self.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// The batched text.
+    pub(super) fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The batch stored in `state`, restarting its wait, or an empty one.
+    pub(super) fn restore(state: Option<&serde_json::Value>) -> Self {
+        let mut pending: Self = state
+            .and_then(|state| serde_json::from_value(state.clone()).ok())
+            .unwrap_or_default();
+        if pending.tokens > 0 {
+            pending.since = Some(Instant::now());
+        }
+        pending
+    }
+
+    pub(super) fn to_state(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    /// Take the batched text and the index of the batch.
+    pub(super) fn take(&mut self) -> (String, i32) {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.tokens = 0;
+        self.since = None;
+        (std::mem::take(&mut self.text), index)
+    }
+}
+
+/// Runs a fast local backend on every token and a remote backend every N
+/// tokens.
+///
+/// Tokens between remote evaluations are batched and sent to the remote
+/// backend as a single chunk, so a token may be released before the remote
+/// policies have seen it. A termination from either backend ends the
+/// session.
+///
+/// A [`RemoteBackend`](super::remote::RemoteBackend) batches tokens on its
+/// own as well; configure it with `evaluate_every_n_tokens(1)` so that each
+/// chunk is sent as soon as it is due.
+pub struct LayeredBackend {
+    local: Box<dyn GuardrailBackend>,
+    remote: Box<dyn GuardrailBackend>,
+    remote_every_n_tokens: usize,
+    pending: Mutex<PendingText>,
+}
+
+impl LayeredBackend {
+    /// Layer `local` in front of `remote`. Tokens are sent to the remote
+    /// backend in batches of 10 by default.
+    pub fn new(
+        local: impl GuardrailBackend + 'static,
+        remote: impl GuardrailBackend + 'static,
+    ) -> Self {
+        Self {
+            local: Box::new(local),
+            remote: Box::new(remote),
+            remote_every_n_tokens: 10,
+            pending: Mutex::new(PendingText::default()),
+        }
+    }
+
+    /// Set how many tokens are batched before the remote backend is called.
+    pub fn remote_every_n_tokens(mut self, n: usize) -> Self {
+        self.remote_every_n_tokens = n.max(1);
+        self
+    }
+
+    /// Take the batched text if it is due for remote evaluation.
+    async fn take_pending(&self, token: &str, force: bool) -> Option<(String, i32)> {
+        let mut pending = self.pending.lock().await;
+        pending.push(token);
+
+        if !force && pending.tokens() < self.remote_every_n_tokens {
+            return None;
+        }
+        Some(pending.take())
+    }
+}
+
+#[async_trait]
+impl GuardrailBackend for LayeredBackend {
+    async fn start(&self, input: Option<&str>) -> Result<BackendSession, DiagnyxError> {
+        *self.pending.lock().await = PendingText::default();
+
+        let local = self.local.start(input).await?;
+        let mut remote = match self.remote.start(input).await {
+            Ok(session) => session,
+            Err(e) => {
+                let _ = self.local.cancel().await;
+                return Err(e);
+            }
+        };
+        remote.active_policies.extend(local.active_policies);
+        Ok(remote)
+    }
+
+    async fn evaluate(
+        &self,
+        token: &str,
+        index: i32,
+        is_last: bool,
+    ) -> Result<Verdict, DiagnyxError> {
+        let verdict = self.local.evaluate(token, index, is_last).await?;
+        if verdict.termination.is_some() {
+            return Ok(verdict);
+        }
+
+        match self.take_pending(token, is_last).await {
+            Some((chunk, chunk_index)) => {
+                let remote = self.remote.evaluate(&chunk, chunk_index, is_last).await?;
+                Ok(verdict.merge(remote))
+            }
+            None => Ok(verdict),
+        }
+    }
+
+    async fn complete(&self) -> Result<Verdict, DiagnyxError> {
+        let (chunk, index) = self.pending.lock().await.take();
+        let last = if chunk.is_empty() {
+            Verdict::allow()
+        } else {
+            self.remote.evaluate(&chunk, index, true).await?
+        };
+
+        let local = self.local.complete().await?;
+        let remote = self.remote.complete().await?;
+        let completion = match (local.completion, remote.completion.or(last.completion)) {
+            (Some(local), Some(remote)) => Some(Completion {
+                allowed: local.allowed && remote.allowed,
+                total_tokens: local.total_tokens,
+            }),
+            (local, remote) => local.or(remote),
+        };
+        Ok(Verdict {
+            completion,
+            ..local.merge(last).merge(remote)
+        })
+    }
+
+    async fn cancel(&self) -> Result<bool, DiagnyxError> {
+        *self.pending.lock().await = PendingText::default();
+        let local = self.local.cancel().await?;
+        let remote = self.remote.cancel().await?;
+        Ok(local || remote)
+    }
+
+    fn set_session_debug(&self, debug: bool) {
+        self.local.set_session_debug(debug);
+        self.remote.set_session_debug(debug);
+    }
+
+    fn evaluation_requests(&self) -> u64 {
+        self.local.evaluation_requests() + self.remote.evaluation_requests()
+    }
+
+    async fn add_context(&self, context: &str) -> Result<(), DiagnyxError> {
+        self.local.add_context(context).await?;
+        self.remote.add_context(context).await
+    }
+
+    async fn add_sources(&self, sources: &[SourceDocument]) -> Result<(), DiagnyxError> {
+        self.local.add_sources(sources).await?;
+        self.remote.add_sources(sources).await
+    }
+
+    async fn session_state(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "pending": self.pending.lock().await.to_state(),
+            "local": self.local.session_state().await,
+            "remote": self.remote.session_state().await,
+        }))
+    }
+
+    async fn resume(
+        &self,
+        session: &StreamingGuardrailSession,
+        state: Option<&serde_json::Value>,
+    ) -> Result<(), DiagnyxError> {
+        let part = |key| {
+            state
+                .and_then(|state| state.get(key))
+                .filter(|v| !v.is_null())
+        };
+        self.local.resume(session, part("local")).await?;
+        self.remote.resume(session, part("remote")).await?;
+        *self.pending.lock().await = PendingText::restore(part("pending"));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guardrails::local::{BlockedTerms, LocalBackend};
+    use crate::guardrails::streaming::EnforcementLevel;
+    use std::sync::Arc;
+
+    /// Records the chunks it is asked to evaluate.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        chunks: Arc<Mutex<Vec<(String, i32, bool)>>>,
+        /// Report a blocking violation on the last chunk.
+        block_last: bool,
+    }
+
+    #[async_trait]
+    impl GuardrailBackend for Recorder {
+        async fn start(&self, _input: Option<&str>) -> Result<BackendSession, DiagnyxError> {
+            Ok(BackendSession {
+                session_id: SessionId::from_static("remote-1"),
+                active_policies: vec!["remote".to_string()],
+            })
+        }
+
+        async fn evaluate(
+            &self,
+            token: &str,
+            index: i32,
+            is_last: bool,
+        ) -> Result<Verdict, DiagnyxError> {
+            self.chunks
+                .lock()
+                .await
+                .push((token.to_string(), index, is_last));
+
+            let mut verdict = Verdict::allow();
+            if self.block_last && is_last {
+                verdict.violations.push(Violation {
+                    policy_id: "remote".to_string(),
+                    policy_name: "Remote".to_string(),
+                    policy_type: "content_filter".to_string(),
+                    violation_type: "blocked_term".to_string(),
+                    message: "Blocked".to_string(),
+                    severity: "high".to_string(),
+                    enforcement_level: EnforcementLevel::Blocking,
+                    details: None,
+                    start_offset: None,
+                    end_offset: None,
+                });
+            }
+            Ok(verdict)
+        }
+
+        async fn complete(&self) -> Result<Verdict, DiagnyxError> {
+            Ok(Verdict::allow())
+        }
+
+        async fn cancel(&self) -> Result<bool, DiagnyxError> {
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_layered_batches_remote_evaluations() {
+        let remote = Recorder::default();
+        let backend =
+            LayeredBackend::new(LocalBackend::new(), remote.clone()).remote_every_n_tokens(2);
+
+        let session = backend.start(None).await.unwrap();
+        assert_eq!(session.session_id, "remote-1");
+
+        for (i, token) in ["a", "b", "c"].iter().enumerate() {
+            let verdict = backend.evaluate(token, i as i32, false).await.unwrap();
+            assert!(verdict.allowed);
+        }
+        backend.complete().await.unwrap();
+
+        let chunks = remote.chunks.lock().await.clone();
+        assert_eq!(
+            chunks,
+            vec![("ab".to_string(), 0, false), ("c".to_string(), 1, true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_layered_completion_keeps_last_remote_verdict() {
+        let remote = Recorder {
+            block_last: true,
+            ..Default::default()
+        };
+        let backend =
+            LayeredBackend::new(LocalBackend::new(), remote.clone()).remote_every_n_tokens(4);
+
+        backend.start(None).await.unwrap();
+        backend.evaluate("a", 0, false).await.unwrap();
+        let verdict = backend.complete().await.unwrap();
+
+        assert_eq!(verdict.violations.len(), 1);
+        assert_eq!(verdict.violations[0].policy_id, "remote");
+        assert_eq!(verdict.completion.unwrap().total_tokens, 1);
+        assert_eq!(
+            remote.chunks.lock().await.clone(),
+            vec![("a".to_string(), 0, true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_layered_local_termination_skips_remote() {
+        let remote = Recorder::default();
+        let local = LocalBackend::new().policy(BlockedTerms::new("secrets", ["password"]));
+        let backend = LayeredBackend::new(local, remote.clone()).remote_every_n_tokens(1);
+
+        backend.start(None).await.unwrap();
+        let verdict = backend.evaluate("my password", 0, false).await.unwrap();
+
+        assert!(verdict.termination.is_some());
+        assert!(remote.chunks.lock().await.is_empty());
+    }
+}
@@ -1,12 +1,17 @@
 //! Streaming guardrails client for real-time token validation.
 
 use crate::error::DiagnyxError;
+use crate::events::{EventBus, SdkEvent};
 use crate::guardrails::types::{
     CancelSessionRequest, CompleteSessionRequest, EvaluateTokenRequest, GuardrailSession,
     GuardrailViolation, SessionStartedData, StartSessionRequest, StreamingEvent,
-    StreamingGuardrailsConfig,
+    StreamingGuardrailsConfig, TokenAllowedData,
 };
-use reqwest::Client;
+use crate::ids::{ProjectId, SessionId};
+use crate::logger::Logger;
+use crate::retry::{send_with_retry, with_timeout};
+use crate::sse;
+use reqwest::{Client, Method};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::mpsc;
@@ -32,23 +37,60 @@ pub struct StreamingGuardrails {
     config: StreamingGuardrailsConfig,
     http_client: Client,
     session: Arc<Mutex<Option<GuardrailSession>>>,
+    events: EventBus,
+    /// Logger of the current session, or of the client between sessions.
+    logger: std::sync::Mutex<Logger>,
 }
 
 impl StreamingGuardrails {
     /// Create a new streaming guardrails client.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
     pub fn new(config: StreamingGuardrailsConfig) -> Self {
+        Self::from_config(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new streaming guardrails client, returning an error if the
+    /// configuration is invalid or the HTTP client cannot be created.
+    pub fn try_new(config: StreamingGuardrailsConfig) -> Result<Self, DiagnyxError> {
+        config.validate()?;
+        Self::from_config(config)
+    }
+
+    fn from_config(config: StreamingGuardrailsConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config
+            .http
+            .client(Duration::from_secs(config.timeout_secs))?;
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Create a new streaming guardrails client sending requests with
+    /// `http_client`.
+    ///
+    /// The HTTP settings and timeout of `config` are not applied to it.
+    pub fn with_http_client(config: StreamingGuardrailsConfig, http_client: Client) -> Self {
         Self {
-            http_client: Client::builder()
-                .timeout(Duration::from_secs(config.timeout_secs))
-                .build()
-                .expect("Failed to create HTTP client"),
-            config,
+            http_client,
             session: Arc::new(Mutex::new(None)),
+            events: EventBus::default(),
+            logger: std::sync::Mutex::new(Logger::new(LOG_COMPONENT, config.debug)),
+            config,
         }
     }
 
+    /// Subscribe to session events emitted by this client.
+    pub fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<SdkEvent> {
+        self.events.subscribe()
+    }
+
     /// Start a new streaming evaluation session.
-    pub async fn start_session(&self, input: Option<&str>) -> Result<GuardrailSession, DiagnyxError> {
+    pub async fn start_session(
+        &self,
+        input: Option<&str>,
+    ) -> Result<GuardrailSession, DiagnyxError> {
         let url = format!("{}/api/v1/guardrails/streaming/start", self.config.base_url);
 
         let request = StartSessionRequest {
@@ -59,28 +101,33 @@ impl StreamingGuardrails {
             enable_early_termination: self.config.enable_early_termination,
         };
 
-        self.log(&format!("Starting session at {}", url));
-
-        let response = self
-            .http_client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .json(&request)
-            .send()
+        let data = if self.config.disabled {
+            SessionStartedData {
+                session_id: SessionId::generate(),
+                organization_id: request.organization_id,
+                project_id: ProjectId::new(request.project_id)?,
+                active_policies: Vec::new(),
+            }
+        } else {
+            self.log(&format!("Starting session at {}", url));
+            let response = send_with_retry(
+                &self.config.retry_policy,
+                self.config.rate_limiter.as_ref(),
+                Method::POST,
+                |method| {
+                    self.http_client
+                        .request(method, &url)
+                        .header("Content-Type", "application/json")
+                        .header("Authorization", format!("Bearer {}", self.config.api_key))
+                        .json(&request)
+                },
+            )
             .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let message = response.text().await.unwrap_or_default();
-            return Err(DiagnyxError::ApiError {
-                status_code: status.as_u16(),
-                message,
-            });
-        }
-
-        let data: SessionStartedData = response.json().await?;
-        self.log(&format!("Session started: {}", data.session_id));
+            response.json().await?
+        };
+        *self.logger.lock().unwrap() =
+            Logger::new(LOG_COMPONENT, self.config.debug).child("session", &data.session_id);
+        self.log("Session started");
 
         let session = GuardrailSession::new(data);
         *self.session.lock().await = Some(session.clone());
@@ -89,16 +136,41 @@ impl StreamingGuardrails {
     }
 
     /// Evaluate a single token.
+    ///
+    /// Bounded by `evaluate_timeout` from the configuration, if set.
     pub async fn evaluate_token(&self, token: &str) -> Result<StreamingEvent, DiagnyxError> {
-        let session_id = {
+        with_timeout(self.config.evaluate_timeout, self.request_token(token)).await
+    }
+
+    /// Evaluate a single token, failing with `DiagnyxError::Timeout` if the
+    /// evaluation takes longer than `timeout`.
+    pub async fn evaluate_token_with_timeout(
+        &self,
+        token: &str,
+        timeout: Duration,
+    ) -> Result<StreamingEvent, DiagnyxError> {
+        with_timeout(Some(timeout), self.request_token(token)).await
+    }
+
+    async fn request_token(&self, token: &str) -> Result<StreamingEvent, DiagnyxError> {
+        let (session_id, tokens_processed) = {
             let session = self.session.lock().await;
-            session
+            let session = session
                 .as_ref()
-                .ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))?
-                .session_id
-                .clone()
+                .ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))?;
+            (session.session_id.clone(), session.tokens_processed)
         };
 
+        if self.config.disabled {
+            let event = StreamingEvent::TokenAllowed(TokenAllowedData {
+                session_id,
+                token: token.to_string(),
+                tokens_processed: tokens_processed + 1,
+            });
+            self.apply(&event).await;
+            return Ok(event);
+        }
+
         let url = format!(
             "{}/api/v1/guardrails/streaming/evaluate",
             self.config.base_url
@@ -109,23 +181,19 @@ impl StreamingGuardrails {
             token: token.to_string(),
         };
 
-        let response = self
-            .http_client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .json(&request)
-            .send()
-            .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let message = response.text().await.unwrap_or_default();
-            return Err(DiagnyxError::ApiError {
-                status_code: status.as_u16(),
-                message,
-            });
-        }
+        let response = send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            Method::POST,
+            |method| {
+                self.http_client
+                    .request(method, &url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+                    .json(&request)
+            },
+        )
+        .await?;
 
         // Parse SSE response
         let text = response.text().await?;
@@ -138,11 +206,16 @@ impl StreamingGuardrails {
                 s.update(&event);
             }
         }
+        emit_termination(&self.events, &event);
 
         Ok(event)
     }
 
     /// Complete the streaming session.
+    ///
+    /// The final evaluation is read as it streams in, updating the session
+    /// with each event, until the session is complete. Bounded by
+    /// `complete_timeout` from the configuration, if set.
     pub async fn complete_session(&self) -> Result<GuardrailSession, DiagnyxError> {
         let session_id = {
             let session = self.session.lock().await;
@@ -162,39 +235,66 @@ impl StreamingGuardrails {
             session_id: session_id.clone(),
         };
 
-        self.log(&format!("Completing session: {}", session_id));
+        self.log("Completing session");
 
-        let response = self
-            .http_client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .json(&request)
-            .send()
+        let timeout = self.config.complete_timeout;
+        let disabled = self.config.disabled;
+        with_timeout(timeout, async {
+            if disabled {
+                return Ok(());
+            }
+            let response = send_with_retry(
+                &self.config.retry_policy,
+                self.config.rate_limiter.as_ref(),
+                Method::POST,
+                |method| {
+                    self.http_client
+                        .request(method, &url)
+                        .header("Content-Type", "application/json")
+                        .header("Authorization", format!("Bearer {}", self.config.api_key))
+                        .json(&request)
+                },
+            )
             .await?;
+            self.read_completion(response).await
+        })
+        .await?;
 
-        let status = response.status();
-        if !status.is_success() {
-            let message = response.text().await.unwrap_or_default();
-            return Err(DiagnyxError::ApiError {
-                status_code: status.as_u16(),
-                message,
-            });
-        }
-
-        // Parse SSE response and update session
-        let text = response.text().await?;
-        let event = self.parse_sse_response(&text)?;
+        let session = self.session.lock().await.take();
+        self.end_session_logging();
+        session.ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))
+    }
 
-        let session = {
-            let mut session_lock = self.session.lock().await;
-            if let Some(ref mut s) = *session_lock {
-                s.update(&event);
+    /// Apply the events of a completion response as they arrive, stopping
+    /// at the session's completion.
+    async fn read_completion(&self, response: reqwest::Response) -> Result<(), DiagnyxError> {
+        let mut reader = sse::EventReader::new(response);
+        while let Some(event) = reader.next().await? {
+            // Heartbeats and unnamed events carry no evaluation
+            let Some(event_type) = event.event else {
+                continue;
+            };
+            let event = StreamingEvent::from_sse(&event_type, &event.data)
+                .map_err(DiagnyxError::SerializationError)?;
+            self.apply(&event).await;
+            if matches!(event, StreamingEvent::SessionComplete(_)) {
+                return Ok(());
             }
-            session_lock.take()
-        };
+        }
 
-        session.ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))
+        // Not an event stream; parse the body as a whole
+        if let Some(body) = reader.into_body() {
+            let event = self.parse_sse_response(&body)?;
+            self.apply(&event).await;
+        }
+        Ok(())
+    }
+
+    async fn apply(&self, event: &StreamingEvent) {
+        if let Some(ref mut s) = *self.session.lock().await {
+            s.update(event);
+        }
+        emit_termination(&self.events, event);
     }
 
     /// Cancel the streaming session.
@@ -217,28 +317,27 @@ impl StreamingGuardrails {
             reason: reason.map(|s| s.to_string()),
         };
 
-        self.log(&format!("Cancelling session: {}", session_id));
-
-        let response = self
-            .http_client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .json(&request)
-            .send()
+        self.log("Cancelling session");
+
+        if !self.config.disabled {
+            send_with_retry(
+                &self.config.retry_policy,
+                self.config.rate_limiter.as_ref(),
+                Method::POST,
+                |method| {
+                    self.http_client
+                        .request(method, &url)
+                        .header("Content-Type", "application/json")
+                        .header("Authorization", format!("Bearer {}", self.config.api_key))
+                        .json(&request)
+                },
+            )
             .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let message = response.text().await.unwrap_or_default();
-            return Err(DiagnyxError::ApiError {
-                status_code: status.as_u16(),
-                message,
-            });
         }
 
         // Clear session
         *self.session.lock().await = None;
+        self.end_session_logging();
 
         Ok(())
     }
@@ -248,6 +347,14 @@ impl StreamingGuardrails {
         self.session.lock().await.clone()
     }
 
+    /// Enable or disable debug logging for the current session only,
+    /// overriding `debug` from the configuration until the session ends.
+    ///
+    /// Log lines of a session are prefixed with its ID.
+    pub fn set_session_debug(&self, debug: bool) {
+        self.logger.lock().unwrap().set_debug(debug);
+    }
+
     /// Stream tokens with guardrail evaluation.
     ///
     /// Returns a receiver that yields streaming events. Each token is evaluated
@@ -270,6 +377,7 @@ impl StreamingGuardrails {
         let client = self.http_client.clone();
         let config = self.config.clone();
         let session = Arc::clone(&self.session);
+        let events = self.events.clone();
 
         tokio::spawn(async move {
             let mut stream = Box::pin(token_stream);
@@ -281,83 +389,69 @@ impl StreamingGuardrails {
                         Some(s) => s.session_id.clone(),
                         None => {
                             let _ = tx
-                                .send(Err(DiagnyxError::ConfigError(
-                                    "Session ended".to_string(),
-                                )))
+                                .send(Err(DiagnyxError::ConfigError("Session ended".to_string())))
                                 .await;
                             return;
                         }
                     }
                 };
 
-                let url = format!(
-                    "{}/api/v1/guardrails/streaming/evaluate",
-                    config.base_url
-                );
+                let url = format!("{}/api/v1/guardrails/streaming/evaluate", config.base_url);
 
                 let request = EvaluateTokenRequest {
                     session_id: session_id.clone(),
                     token: token.clone(),
                 };
 
-                let result = client
-                    .post(&url)
-                    .header("Content-Type", "application/json")
-                    .header("Authorization", format!("Bearer {}", config.api_key))
-                    .json(&request)
-                    .send()
-                    .await;
+                let result = with_timeout(config.evaluate_timeout, async {
+                    let response = send_with_retry(
+                        &config.retry_policy,
+                        config.rate_limiter.as_ref(),
+                        Method::POST,
+                        |method| {
+                            client
+                                .request(method, &url)
+                                .header("Content-Type", "application/json")
+                                .header("Authorization", format!("Bearer {}", config.api_key))
+                                .json(&request)
+                        },
+                    )
+                    .await?;
+                    Ok(response.text().await?)
+                })
+                .await;
 
                 match result {
-                    Ok(response) => {
-                        let status = response.status();
-                        if !status.is_success() {
-                            let message = response.text().await.unwrap_or_default();
-                            let _ = tx
-                                .send(Err(DiagnyxError::ApiError {
-                                    status_code: status.as_u16(),
-                                    message,
-                                }))
-                                .await;
-                            return;
-                        }
-
-                        match response.text().await {
-                            Ok(text) => {
-                                match parse_sse_response_static(&text) {
-                                    Ok(event) => {
-                                        // Update session state
-                                        {
-                                            let mut session_lock = session.lock().await;
-                                            if let Some(ref mut s) = *session_lock {
-                                                s.update(&event);
-                                            }
-                                        }
-
-                                        // Check for early termination
-                                        let is_termination =
-                                            matches!(event, StreamingEvent::EarlyTermination(_));
-
-                                        let _ = tx.send(Ok(event)).await;
-
-                                        if is_termination {
-                                            return;
-                                        }
-                                    }
-                                    Err(e) => {
-                                        let _ = tx.send(Err(e)).await;
-                                        return;
+                    Ok(text) => {
+                        match parse_sse_response_static(&text) {
+                            Ok(event) => {
+                                // Update session state
+                                {
+                                    let mut session_lock = session.lock().await;
+                                    if let Some(ref mut s) = *session_lock {
+                                        s.update(&event);
                                     }
                                 }
+                                emit_termination(&events, &event);
+
+                                // Check for early termination
+                                let is_termination =
+                                    matches!(event, StreamingEvent::EarlyTermination(_));
+
+                                let _ = tx.send(Ok(event)).await;
+
+                                if is_termination {
+                                    return;
+                                }
                             }
                             Err(e) => {
-                                let _ = tx.send(Err(DiagnyxError::HttpError(e))).await;
+                                let _ = tx.send(Err(e)).await;
                                 return;
                             }
                         }
                     }
                     Err(e) => {
-                        let _ = tx.send(Err(DiagnyxError::HttpError(e))).await;
+                        let _ = tx.send(Err(e)).await;
                         return;
                     }
                 }
@@ -370,20 +464,23 @@ impl StreamingGuardrails {
             };
 
             if let Some(session_id) = session_id {
-                let url = format!(
-                    "{}/api/v1/guardrails/streaming/complete",
-                    config.base_url
-                );
+                let url = format!("{}/api/v1/guardrails/streaming/complete", config.base_url);
 
                 let request = CompleteSessionRequest { session_id };
 
-                let result = client
-                    .post(&url)
-                    .header("Content-Type", "application/json")
-                    .header("Authorization", format!("Bearer {}", config.api_key))
-                    .json(&request)
-                    .send()
-                    .await;
+                let result = send_with_retry(
+                    &config.retry_policy,
+                    config.rate_limiter.as_ref(),
+                    Method::POST,
+                    |method| {
+                        client
+                            .request(method, &url)
+                            .header("Content-Type", "application/json")
+                            .header("Authorization", format!("Bearer {}", config.api_key))
+                            .json(&request)
+                    },
+                )
+                .await;
 
                 if let Ok(response) = result {
                     if let Ok(text) = response.text().await {
@@ -403,32 +500,30 @@ impl StreamingGuardrails {
     }
 
     fn log(&self, message: &str) {
-        if self.config.debug {
-            println!("[Diagnyx Guardrails] {}", message);
-        }
+        self.logger.lock().unwrap().log(message);
+    }
+
+    fn end_session_logging(&self) {
+        *self.logger.lock().unwrap() = Logger::new(LOG_COMPONENT, self.config.debug);
     }
 }
 
+const LOG_COMPONENT: &str = "Diagnyx Guardrails";
+
 fn parse_sse_response_static(text: &str) -> Result<StreamingEvent, DiagnyxError> {
-    let mut event_type = String::new();
-    let mut data = String::new();
-
-    for line in text.lines() {
-        if line.starts_with("event: ") {
-            event_type = line[7..].to_string();
-        } else if line.starts_with("data: ") {
-            data = line[6..].to_string();
-        }
-    }
+    // The last named event; heartbeat comments carry no event
+    let (mut event_type, mut data) = sse::parse(text)
+        .events
+        .into_iter()
+        .rev()
+        .find_map(|event| Some((event.event?, event.data)))
+        .unwrap_or_default();
 
     if event_type.is_empty() || data.is_empty() {
         // Try parsing as raw JSON
         if let Ok(event) = serde_json::from_str::<serde_json::Value>(text) {
             if let Some(event_type_val) = event.get("event_type") {
-                event_type = event_type_val
-                    .as_str()
-                    .unwrap_or("error")
-                    .to_string();
+                event_type = event_type_val.as_str().unwrap_or("error").to_string();
                 data = text.to_string();
             }
         }
@@ -440,7 +535,16 @@ fn parse_sse_response_static(text: &str) -> Result<StreamingEvent, DiagnyxError>
         }
     }
 
-    StreamingEvent::from_sse(&event_type, &data).map_err(|e| DiagnyxError::SerializationError(e))
+    StreamingEvent::from_sse(&event_type, &data).map_err(DiagnyxError::SerializationError)
+}
+
+fn emit_termination(events: &EventBus, event: &StreamingEvent) {
+    if let StreamingEvent::EarlyTermination(data) = event {
+        events.emit(SdkEvent::SessionTerminated {
+            session_id: data.session_id.clone(),
+            reason: data.reason.clone(),
+        });
+    }
 }
 
 /// Wrap an async token stream with guardrail evaluation.
@@ -455,6 +559,8 @@ pub async fn stream_with_guardrails<S>(
 where
     S: futures::Stream<Item = String> + Send + 'static,
 {
+    let organization_id = config.organization_id.clone();
+    let project_id = ProjectId::new(config.project_id.clone())?;
     let client = StreamingGuardrails::new(config);
     let mut events_rx = client.stream_with_guardrails(token_stream, input).await?;
     let session = client.get_session().await;
@@ -483,8 +589,8 @@ where
                                 GuardrailSession::new(
                                     crate::guardrails::types::SessionStartedData {
                                         session_id: data.session_id.clone(),
-                                        organization_id: String::new(),
-                                        project_id: String::new(),
+                                        organization_id: organization_id.clone(),
+                                        project_id: project_id.clone(),
                                         active_policies: vec![],
                                     },
                                 )
@@ -521,6 +627,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ids::SessionId;
 
     #[test]
     fn test_streaming_guardrails_new() {
@@ -528,6 +635,30 @@ mod tests {
         let _ = StreamingGuardrails::new(config);
     }
 
+    #[test]
+    fn test_streaming_guardrails_try_new_validates_config() {
+        let config = StreamingGuardrailsConfig::new("api-key", "org-1", "proj-1");
+        assert!(StreamingGuardrails::try_new(config.clone()).is_ok());
+        assert!(StreamingGuardrails::try_new(config.evaluate_every_n_tokens(0)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_disabled_client_allows_tokens_without_requests() {
+        let guardrails = StreamingGuardrails::new(
+            StreamingGuardrailsConfig::new("", "org-1", "proj-1")
+                .base_url("http://127.0.0.1:9")
+                .disabled(true),
+        );
+        guardrails.start_session(Some("Hi")).await.unwrap();
+        for token in ["Hello", " world"] {
+            let event = guardrails.evaluate_token(token).await.unwrap();
+            assert!(matches!(event, StreamingEvent::TokenAllowed(_)));
+        }
+        let session = guardrails.complete_session().await.unwrap();
+        assert_eq!(session.tokens_processed, 2);
+        assert!(session.allowed);
+    }
+
     #[test]
     fn test_guardrail_violation_error_display() {
         let violation = GuardrailViolation {
@@ -536,12 +667,14 @@ mod tests {
             message: "PII detected in output".to_string(),
             severity: crate::guardrails::types::EnforcementLevel::Blocking,
             details: None,
+            start_offset: None,
+            end_offset: None,
         };
 
         let session = GuardrailSession::new(crate::guardrails::types::SessionStartedData {
-            session_id: "sess-123".to_string(),
+            session_id: SessionId::from_static("sess-123"),
             organization_id: "org-1".to_string(),
-            project_id: "proj-1".to_string(),
+            project_id: ProjectId::from_static("proj-1"),
             active_policies: vec![],
         });
 
@@ -567,6 +700,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_sse_response_skips_heartbeats() {
+        let text = ": keep-alive\n\nretry: 3000\nevent: token_allowed\ndata:{\"session_id\":\"sess-123\",\"token\":\"hi\",\"tokens_processed\":2}\n\n: keep-alive\n\n";
+        let event = parse_sse_response_static(text).unwrap();
+
+        assert!(matches!(event, StreamingEvent::TokenAllowed(data) if data.token == "hi"));
+        assert!(parse_sse_response_static(": keep-alive\n\n").is_err());
+    }
+
     #[test]
     fn test_parse_sse_response_violation() {
         let text = "event: violation_detected\ndata: {\"session_id\":\"sess-123\",\"violation\":{\"policy_id\":\"pol-1\",\"policy_type\":\"pii\",\"message\":\"PII found\",\"severity\":\"warning\",\"details\":null},\"tokens_processed\":5}\n\n";
@@ -584,4 +726,79 @@ mod tests {
             _ => panic!("Expected ViolationDetected event"),
         }
     }
+
+    async fn started(
+        server: &wiremock::MockServer,
+        config: StreamingGuardrailsConfig,
+    ) -> StreamingGuardrails {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, ResponseTemplate};
+
+        Mock::given(method("POST"))
+            .and(path("/api/v1/guardrails/streaming/start"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "session_id": "sess-123",
+                "organization_id": "org-1",
+                "project_id": "proj-1",
+                "active_policies": []
+            })))
+            .mount(server)
+            .await;
+
+        let client = StreamingGuardrails::new(config.base_url(server.uri()));
+        client.start_session(None).await.unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn test_complete_session_stops_at_completion() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = started(
+            &server,
+            StreamingGuardrailsConfig::new("api-key", "org-1", "proj-1"),
+        )
+        .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/guardrails/streaming/complete"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(concat!(
+                ": keep-alive\n\n",
+                "event: session_complete\n",
+                "data: {\"session_id\":\"sess-123\",\"total_tokens\":12,\"violations\":[],\"allowed\":false}\n\n",
+                "event: token_allowed\n",
+                "data: {\"session_id\":\"sess-123\",\"token\":\"x\",\"tokens_processed\":99}\n\n",
+            )))
+            .mount(&server)
+            .await;
+
+        let session = client.complete_session().await.unwrap();
+        assert_eq!(session.tokens_processed, 12);
+        assert!(!session.allowed);
+        assert!(client.get_session().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_complete_session_timeout() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let client = started(
+            &server,
+            StreamingGuardrailsConfig::new("api-key", "org-1", "proj-1")
+                .complete_timeout(Duration::from_millis(50)),
+        )
+        .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/guardrails/streaming/complete"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+            .mount(&server)
+            .await;
+
+        let result = client.complete_session().await;
+        assert!(matches!(result, Err(DiagnyxError::Timeout(_))));
+        assert!(client.get_session().await.is_some());
+    }
 }
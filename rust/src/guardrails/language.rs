@@ -0,0 +1,253 @@
+//! Lightweight language detection for guardrail output.
+//!
+//! Detection runs locally on the start of the accumulated output so that
+//! evaluation requests can carry a language code and the API can apply the
+//! policies configured for that language. Text in a distinctive script
+//! (Cyrillic, Arabic, CJK, ...) is identified by script; Latin-script text
+//! is scored against common function words of the supported languages.
+//!
+//! # Example
+//!
+//! ```rust
+//! use diagnyx::guardrails::language::detect_language;
+//!
+//! assert_eq!(detect_language("The weather is nice and the sky is blue"), Some("en"));
+//! assert_eq!(detect_language("Il fait beau et le ciel est bleu"), Some("fr"));
+//! assert_eq!(detect_language("ok"), None);
+//! ```
+
+/// Only this many bytes of the text are inspected, so detection cost stays
+/// constant as the output grows.
+pub(crate) const SAMPLE_BYTES: usize = 2048;
+
+/// Minimum number of letters before a language is reported.
+const MIN_LETTERS: usize = 12;
+
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "of", "to", "in", "that", "it", "with", "for", "was",
+            "this", "you", "not", "be", "have",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "la", "los", "las", "de", "que", "y", "en", "es", "por", "con", "para", "una",
+            "del", "no", "se", "está",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "de", "et", "est", "que", "un", "une", "des", "du", "pour", "pas",
+            "dans", "je", "vous", "avec", "ce",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ein", "eine", "mit", "zu", "den", "ich",
+            "sie", "es", "auf", "für", "von",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "lo", "la", "di", "che", "e", "è", "un", "una", "per", "non", "sono", "con",
+            "del", "della", "gli",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "a", "os", "as", "de", "que", "e", "em", "um", "uma", "não", "para", "com", "do",
+            "da", "é", "são",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "is", "van", "dat", "niet", "ik", "je", "op", "met", "voor",
+            "zijn",
+        ],
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Devanagari,
+    Thai,
+    Hangul,
+    Kana,
+    Han,
+}
+
+impl Script {
+    const ALL: [Script; 10] = [
+        Script::Latin,
+        Script::Cyrillic,
+        Script::Greek,
+        Script::Arabic,
+        Script::Hebrew,
+        Script::Devanagari,
+        Script::Thai,
+        Script::Hangul,
+        Script::Kana,
+        Script::Han,
+    ];
+
+    fn of(c: char) -> Option<Script> {
+        let script = match c as u32 {
+            0x0041..=0x005A | 0x0061..=0x007A | 0x00C0..=0x024F => Script::Latin,
+            0x0370..=0x03FF => Script::Greek,
+            0x0400..=0x04FF => Script::Cyrillic,
+            0x0590..=0x05FF => Script::Hebrew,
+            0x0600..=0x06FF => Script::Arabic,
+            0x0900..=0x097F => Script::Devanagari,
+            0x0E00..=0x0E7F => Script::Thai,
+            0x1100..=0x11FF | 0xAC00..=0xD7AF => Script::Hangul,
+            0x3040..=0x30FF => Script::Kana,
+            0x4E00..=0x9FFF => Script::Han,
+            _ => return None,
+        };
+        // Latin-1 punctuation such as '×' and '÷' is not a letter
+        c.is_alphabetic().then_some(script)
+    }
+}
+
+/// Detect the language of `text`, returning its ISO 639-1 code.
+///
+/// Returns `None` when the text is too short or no supported language is
+/// clearly dominant. Only the first 2 KB of the text are inspected.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let mut end = text.len().min(SAMPLE_BYTES);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let sample = &text[..end];
+
+    let mut counts = [0usize; Script::ALL.len()];
+    for script in sample.chars().filter_map(Script::of) {
+        counts[script as usize] += 1;
+    }
+    if counts.iter().sum::<usize>() < MIN_LETTERS {
+        return None;
+    }
+
+    let (dominant, _) = Script::ALL
+        .iter()
+        .zip(counts)
+        .max_by_key(|(_, count)| *count)?;
+
+    match dominant {
+        Script::Latin => detect_latin(sample),
+        Script::Cyrillic => Some("ru"),
+        Script::Greek => Some("el"),
+        Script::Arabic => Some("ar"),
+        Script::Hebrew => Some("he"),
+        Script::Devanagari => Some("hi"),
+        Script::Thai => Some("th"),
+        Script::Hangul => Some("ko"),
+        // Japanese mixes kana with Han characters
+        Script::Kana => Some("ja"),
+        Script::Han if counts[Script::Kana as usize] > 0 => Some("ja"),
+        Script::Han => Some("zh"),
+    }
+}
+
+/// Detect the language of the end of `text`, the last 2 KB of the output
+/// streamed so far. Both the session's language and the language sent
+/// with each evaluation come from here, so they agree.
+pub(crate) fn detect_recent_language(text: &str) -> Option<&'static str> {
+    let mut start = text.len().saturating_sub(SAMPLE_BYTES);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    detect_language(&text[start..])
+}
+
+/// Pick the Latin-script language whose function words occur most often.
+fn detect_latin(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    let mut scores: Vec<(&'static str, usize)> = STOPWORDS
+        .iter()
+        .map(|(code, stopwords)| {
+            let score = words
+                .iter()
+                .filter(|w| stopwords.contains(&w.as_str()))
+                .count();
+            (*code, score)
+        })
+        .collect();
+    scores.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+
+    match scores.as_slice() {
+        [(code, best), (_, second), ..] if *best > *second => Some(code),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_latin_languages() {
+        let cases = [
+            ("en", "This is the answer that you were looking for."),
+            ("es", "El perro está en la casa con los niños."),
+            ("fr", "Je pense que vous avez raison dans ce cas."),
+            ("de", "Ich glaube, dass die Antwort nicht richtig ist."),
+            ("it", "Il gatto è sul tavolo della cucina con gli amici."),
+            ("pt", "O livro não está na mesa, são os meus amigos."),
+            ("nl", "Het is niet zo dat ik voor een nieuwe fiets ben."),
+        ];
+        for (code, text) in cases {
+            assert_eq!(detect_language(text), Some(code), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_detects_scripts() {
+        assert_eq!(
+            detect_language("Привет, как у тебя дела сегодня?"),
+            Some("ru")
+        );
+        assert_eq!(detect_language("今日はとても良い天気ですね。"), Some("ja"));
+        assert_eq!(detect_language("今天天气很好我们去公园散步吧"), Some("zh"));
+        assert_eq!(
+            detect_language("오늘은 날씨가 정말 좋네요 그렇죠"),
+            Some("ko")
+        );
+    }
+
+    #[test]
+    fn test_short_or_ambiguous_text() {
+        assert_eq!(detect_language("Hello"), None);
+        assert_eq!(detect_language("12345 67890 !!!"), None);
+        assert_eq!(detect_language("Lorem ipsum dolor sit amet"), None);
+    }
+
+    #[test]
+    fn test_recent_language_follows_the_output() {
+        let text = format!(
+            "{}{}",
+            "The weather is nice and the sky is blue. ".repeat(60),
+            "Il fait beau et le ciel est bleu. ".repeat(70)
+        );
+        assert_eq!(detect_language(&text), Some("en"));
+        assert_eq!(detect_recent_language(&text), Some("fr"));
+    }
+}
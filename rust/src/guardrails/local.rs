@@ -0,0 +1,682 @@
+//! In-process guardrail policies.
+//!
+//! [`LocalBackend`] evaluates keyword ([`BlockedTerms`]), regular expression
+//! ([`RegexPolicy`]) and personal data ([`PiiPolicy`]) policies against the
+//! accumulated output without a network round trip. It can be used on its own or in front of the remote
+//! backend with a [`LayeredBackend`](super::backend::LayeredBackend).
+
+use async_trait::async_trait;
+use regex::Regex;
+use std::collections::HashSet;
+use tokio::sync::Mutex;
+
+use super::backend::{BackendSession, Completion, GuardrailBackend, Termination, Verdict};
+use super::streaming::{EnforcementLevel, StreamingGuardrailSession, Violation};
+use super::types::{PiiDetails, RegexDetails, TextSpan, ViolationDetails};
+use crate::error::DiagnyxError;
+use crate::ids::SessionId;
+use crate::redact::{find_card_number, find_email, find_ssn};
+
+/// A policy evaluated in-process.
+pub trait LocalPolicy: Send + Sync {
+    /// Name reported in `active_policies`.
+    fn name(&self) -> &str;
+
+    /// Check the text accumulated so far, returning a violation if the
+    /// policy is breached.
+    fn check(&self, text: &str) -> Option<Violation>;
+
+    /// Check the accumulated text against the input context added to the
+    /// session. Policies that do not use context only implement
+    /// [`check`](Self::check).
+    fn check_in_context(&self, text: &str, _context: &[String]) -> Option<Violation> {
+        self.check(text)
+    }
+
+    /// Longest match, in bytes, the policy can report. When set, only the
+    /// new output and this many bytes before it are checked as tokens
+    /// arrive. Default: unbounded, so the whole accumulated text is checked.
+    fn max_match_len(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Longest email address accepted by SMTP (RFC 5321).
+const MAX_EMAIL_LEN: usize = 254;
+
+/// Blocks output containing any of a list of terms, ignoring case.
+#[derive(Debug, Clone)]
+pub struct BlockedTerms {
+    name: String,
+    terms: Vec<String>,
+    enforcement_level: EnforcementLevel,
+}
+
+impl BlockedTerms {
+    /// Create a blocking policy for the given terms.
+    pub fn new<I, S>(name: impl Into<String>, terms: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            terms: terms
+                .into_iter()
+                .map(|t| t.into().to_lowercase())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            enforcement_level: EnforcementLevel::Blocking,
+        }
+    }
+
+    /// Set the enforcement level of violations. Default: blocking.
+    pub fn enforcement_level(mut self, level: EnforcementLevel) -> Self {
+        self.enforcement_level = level;
+        self
+    }
+}
+
+impl LocalPolicy for BlockedTerms {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self, text: &str) -> Option<Violation> {
+        let folded = Folded::new(text);
+        let (term, (start, end)) = self
+            .terms
+            .iter()
+            .find_map(|t| folded.find(t).map(|span| (t, span)))?;
+
+        Some(Violation {
+            policy_id: format!("local:{}", self.name),
+            policy_name: self.name.clone(),
+            policy_type: "blocked_terms".to_string(),
+            violation_type: "blocked_term".to_string(),
+            message: format!("Output contains blocked term \"{}\"", term),
+            severity: severity(self.enforcement_level).to_string(),
+            enforcement_level: self.enforcement_level,
+            details: None,
+            start_offset: Some(start),
+            end_offset: Some(end),
+        })
+    }
+
+    fn max_match_len(&self) -> Option<usize> {
+        // A lowercase character may fold from a character of up to 4 bytes
+        self.terms.iter().map(|t| t.chars().count() * 4).max()
+    }
+}
+
+/// Blocks output matching a regular expression.
+#[derive(Debug, Clone)]
+pub struct RegexPolicy {
+    name: String,
+    pattern: Regex,
+    max_len: Option<usize>,
+    enforcement_level: EnforcementLevel,
+}
+
+impl RegexPolicy {
+    /// Create a blocking policy for `pattern`, in the syntax of the `regex`
+    /// crate.
+    pub fn new(name: impl Into<String>, pattern: &str) -> Result<Self, DiagnyxError> {
+        let pattern = Regex::new(pattern)
+            .map_err(|e| DiagnyxError::ConfigError(format!("invalid policy pattern: {}", e)))?;
+        let max_len = regex_syntax::parse(pattern.as_str())
+            .ok()
+            .and_then(|hir| hir.properties().maximum_len());
+        Ok(Self {
+            name: name.into(),
+            pattern,
+            max_len,
+            enforcement_level: EnforcementLevel::Blocking,
+        })
+    }
+
+    /// Set the enforcement level of violations. Default: blocking.
+    pub fn enforcement_level(mut self, level: EnforcementLevel) -> Self {
+        self.enforcement_level = level;
+        self
+    }
+}
+
+impl LocalPolicy for RegexPolicy {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self, text: &str) -> Option<Violation> {
+        let found = self.pattern.find(text)?;
+
+        Some(Violation {
+            policy_id: format!("local:{}", self.name),
+            policy_name: self.name.clone(),
+            policy_type: "regex".to_string(),
+            violation_type: "pattern_match".to_string(),
+            message: format!("Output matches pattern /{}/", self.pattern.as_str()),
+            severity: severity(self.enforcement_level).to_string(),
+            enforcement_level: self.enforcement_level,
+            details: Some(ViolationDetails::Regex(RegexDetails {
+                pattern: self.pattern.as_str().to_string(),
+            })),
+            start_offset: Some(found.start()),
+            end_offset: Some(found.end()),
+        })
+    }
+
+    fn max_match_len(&self) -> Option<usize> {
+        self.max_len
+    }
+}
+
+/// Create a blocking policy that detects personal data.
+///
+/// See [`PiiPolicy`] for what is detected.
+pub fn pii() -> PiiPolicy {
+    PiiPolicy::default()
+}
+
+/// Detects email addresses, US social security numbers and payment card
+/// numbers.
+///
+/// The kind of data found and where are reported in the violation's
+/// `details` as [`PiiDetails`](super::PiiDetails).
+#[derive(Debug, Clone)]
+pub struct PiiPolicy {
+    enforcement_level: EnforcementLevel,
+}
+
+impl Default for PiiPolicy {
+    fn default() -> Self {
+        Self {
+            enforcement_level: EnforcementLevel::Blocking,
+        }
+    }
+}
+
+impl PiiPolicy {
+    /// Set the enforcement level of violations. Default: blocking.
+    pub fn enforcement_level(mut self, level: EnforcementLevel) -> Self {
+        self.enforcement_level = level;
+        self
+    }
+}
+
+impl LocalPolicy for PiiPolicy {
+    fn name(&self) -> &str {
+        "pii"
+    }
+
+    fn check(&self, text: &str) -> Option<Violation> {
+        let (kind, (start, end)) = [
+            ("email", find_email(text)),
+            ("ssn", find_ssn(text)),
+            ("card_number", find_card_number(text)),
+        ]
+        .into_iter()
+        .find_map(|(kind, span)| span.map(|span| (kind, span)))?;
+
+        let details = ViolationDetails::Pii(PiiDetails {
+            entity_type: kind.to_string(),
+            span: Some(TextSpan { start, end }),
+        });
+
+        Some(Violation {
+            policy_id: "local:pii".to_string(),
+            policy_name: "pii".to_string(),
+            policy_type: "pii".to_string(),
+            violation_type: "pii_detected".to_string(),
+            message: format!("Output contains personal data ({})", kind),
+            severity: severity(self.enforcement_level).to_string(),
+            enforcement_level: self.enforcement_level,
+            details: Some(details),
+            start_offset: Some(start),
+            end_offset: Some(end),
+        })
+    }
+
+    fn max_match_len(&self) -> Option<usize> {
+        // Card numbers and SSNs are shorter than the longest email address
+        Some(MAX_EMAIL_LEN)
+    }
+}
+
+/// Text lowercased once to search for several terms ignoring case.
+struct Folded {
+    lower: String,
+    // (offset in `lower`, offset in the original text) for each character
+    offsets: Vec<(usize, usize)>,
+    len: usize,
+}
+
+impl Folded {
+    fn new(text: &str) -> Self {
+        // Lowercasing can change byte lengths, so map offsets back to `text`
+        let mut lower = String::with_capacity(text.len());
+        let mut offsets = Vec::with_capacity(text.len());
+        for (i, c) in text.char_indices() {
+            for lc in c.to_lowercase() {
+                offsets.push((lower.len(), i));
+                lower.push(lc);
+            }
+        }
+        Self {
+            lower,
+            offsets,
+            len: text.len(),
+        }
+    }
+
+    /// Find `term`, which must be lowercase. Returns the byte span of the
+    /// match in the original text.
+    fn find(&self, term: &str) -> Option<(usize, usize)> {
+        let found = self.lower.find(term)?;
+        let to_text = |pos: usize| {
+            let at = self
+                .offsets
+                .partition_point(|(lower_pos, _)| *lower_pos < pos);
+            self.offsets.get(at).map_or(self.len, |(_, i)| *i)
+        };
+        Some((to_text(found), to_text(found + term.len())))
+    }
+}
+
+/// Start of the text a policy needs to see to find matches ending after
+/// `checked`: `max_len` bytes before it, or the whole text if unbounded.
+fn window_start(text: &str, checked: usize, max_len: Option<usize>) -> usize {
+    let Some(max_len) = max_len else {
+        return 0;
+    };
+    let mut start = checked.saturating_sub(max_len);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    start
+}
+
+/// Move the offsets of a violation found in `text[by..]` to `text`.
+fn shift(violation: &mut Violation, by: usize) {
+    for offset in [&mut violation.start_offset, &mut violation.end_offset]
+        .into_iter()
+        .flatten()
+    {
+        *offset += by;
+    }
+    if let Some(ViolationDetails::Pii(PiiDetails {
+        span: Some(span), ..
+    })) = &mut violation.details
+    {
+        span.start += by;
+        span.end += by;
+    }
+}
+
+fn severity(level: EnforcementLevel) -> &'static str {
+    match level {
+        EnforcementLevel::Blocking => "high",
+        EnforcementLevel::Warning => "medium",
+        EnforcementLevel::Advisory => "low",
+    }
+}
+
+#[derive(Default)]
+struct LocalSession {
+    text: String,
+    context: Vec<String>,
+    // Length of `text` every unreported policy has been checked against
+    checked: usize,
+    tokens: i32,
+    reported: HashSet<usize>,
+    allowed: bool,
+}
+
+/// Evaluates [`LocalPolicy`]s against the accumulated output.
+///
+/// Each policy reports at most one violation per session. A blocking
+/// violation ends the session.
+#[derive(Default)]
+pub struct LocalBackend {
+    policies: Vec<Box<dyn LocalPolicy>>,
+    session: Mutex<Option<LocalSession>>,
+}
+
+impl LocalBackend {
+    /// Create a backend with no policies.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a policy.
+    pub fn policy(mut self, policy: impl LocalPolicy + 'static) -> Self {
+        self.policies.push(Box::new(policy));
+        self
+    }
+}
+
+/// Check the text a policy has not seen, plus enough of what it has seen to
+/// find matches spanning both.
+fn check_new_text(policy: &dyn LocalPolicy, session: &LocalSession) -> Option<Violation> {
+    let mut start = window_start(&session.text, session.checked, policy.max_match_len());
+    loop {
+        let mut violation = policy.check_in_context(&session.text[start..], &session.context)?;
+        shift(&mut violation, start);
+        match violation.end_offset {
+            // Cutting the window can expose a match inside text already
+            // checked, such as the tail of a longer number
+            Some(end) if start > 0 && end <= session.checked => {
+                start = if end > start {
+                    end
+                } else {
+                    // Step over an empty match
+                    start + session.text[start..].chars().next()?.len_utf8()
+                };
+            }
+            _ => return Some(violation),
+        }
+    }
+}
+
+#[async_trait]
+impl GuardrailBackend for LocalBackend {
+    async fn start(&self, _input: Option<&str>) -> Result<BackendSession, DiagnyxError> {
+        *self.session.lock().await = Some(LocalSession {
+            allowed: true,
+            ..Default::default()
+        });
+
+        Ok(BackendSession {
+            session_id: SessionId::generate(),
+            active_policies: self.policies.iter().map(|p| p.name().to_string()).collect(),
+        })
+    }
+
+    async fn evaluate(
+        &self,
+        token: &str,
+        _index: i32,
+        _is_last: bool,
+    ) -> Result<Verdict, DiagnyxError> {
+        let mut guard = self.session.lock().await;
+        let session = guard
+            .as_mut()
+            .ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))?;
+        session.text.push_str(token);
+        session.tokens += 1;
+
+        let mut verdict = Verdict::allow();
+        for (i, policy) in self.policies.iter().enumerate() {
+            if session.reported.contains(&i) {
+                continue;
+            }
+            let Some(violation) = check_new_text(policy.as_ref(), session) else {
+                continue;
+            };
+            session.reported.insert(i);
+
+            if violation.enforcement_level == EnforcementLevel::Blocking {
+                session.allowed = false;
+                verdict.allowed = false;
+                verdict.termination = Some(Termination {
+                    reason: Some(violation.message.clone()),
+                    violation,
+                });
+                break;
+            }
+            verdict.violations.push(violation);
+        }
+        session.checked = session.text.len();
+
+        Ok(verdict)
+    }
+
+    async fn complete(&self) -> Result<Verdict, DiagnyxError> {
+        let session = self
+            .session
+            .lock()
+            .await
+            .take()
+            .ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))?;
+
+        Ok(Verdict {
+            completion: Some(Completion {
+                allowed: session.allowed,
+                total_tokens: session.tokens,
+            }),
+            ..Verdict::allow()
+        })
+    }
+
+    async fn cancel(&self) -> Result<bool, DiagnyxError> {
+        Ok(self.session.lock().await.take().is_some())
+    }
+
+    async fn add_context(&self, context: &str) -> Result<(), DiagnyxError> {
+        let mut guard = self.session.lock().await;
+        let session = guard
+            .as_mut()
+            .ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))?;
+        session.context.push(context.to_string());
+        // Text already checked may quote the new context
+        session.checked = 0;
+        Ok(())
+    }
+
+    async fn resume(
+        &self,
+        session: &StreamingGuardrailSession,
+        _state: Option<&serde_json::Value>,
+    ) -> Result<(), DiagnyxError> {
+        // Policies report at most once per session
+        let reported = self
+            .policies
+            .iter()
+            .enumerate()
+            .filter(|(_, policy)| {
+                session
+                    .violations
+                    .iter()
+                    .any(|violation| violation.policy_name == policy.name())
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let context = session
+            .input_context
+            .iter()
+            .cloned()
+            .chain(session.sources.iter().map(|source| source.content.clone()))
+            .collect();
+        *self.session.lock().await = Some(LocalSession {
+            text: session.accumulated_text.clone(),
+            context,
+            checked: 0,
+            tokens: session.next_token_index,
+            reported,
+            allowed: session.allowed,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_blocked_term_spanning_tokens_terminates() {
+        let backend = LocalBackend::new().policy(BlockedTerms::new("secrets", ["Password"]));
+        backend.start(None).await.unwrap();
+
+        assert!(backend.evaluate("my pass", 0, false).await.unwrap().allowed);
+        let verdict = backend.evaluate("word is", 1, false).await.unwrap();
+
+        assert!(!verdict.allowed);
+        let termination = verdict.termination.unwrap();
+        assert_eq!(termination.violation.policy_id, "local:secrets");
+        assert_eq!(termination.violation.start_offset, Some(3));
+        assert_eq!(termination.violation.end_offset, Some(11));
+
+        let completion = backend.complete().await.unwrap().completion.unwrap();
+        assert!(!completion.allowed);
+        assert_eq!(completion.total_tokens, 2);
+    }
+
+    #[tokio::test]
+    async fn test_regex_policy_terminates_on_match() {
+        let policy = RegexPolicy::new("api-keys", r"sk-[A-Za-z0-9]{8,}").unwrap();
+        let backend = LocalBackend::new().policy(policy);
+        backend.start(None).await.unwrap();
+
+        assert!(
+            backend
+                .evaluate("key: sk-abc", 0, false)
+                .await
+                .unwrap()
+                .allowed
+        );
+        let verdict = backend.evaluate("def12345", 1, false).await.unwrap();
+
+        let violation = verdict.termination.unwrap().violation;
+        assert_eq!(violation.policy_type, "regex");
+        assert_eq!(violation.start_offset, Some(5));
+        assert_eq!(violation.end_offset, Some(19));
+        assert!(RegexPolicy::new("broken", "(").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_non_blocking_violation_reported_once() {
+        let policy =
+            BlockedTerms::new("tone", ["darn"]).enforcement_level(EnforcementLevel::Warning);
+        let backend = LocalBackend::new().policy(policy);
+        backend.start(None).await.unwrap();
+
+        let first = backend.evaluate("darn", 0, false).await.unwrap();
+        let second = backend.evaluate(" it", 1, false).await.unwrap();
+
+        assert!(first.allowed);
+        assert_eq!(first.violations.len(), 1);
+        assert!(second.violations.is_empty());
+    }
+
+    #[test]
+    fn test_pii_detection() {
+        let policy = pii();
+        let kind = |text: &str| {
+            policy.check(text).and_then(|v| v.details).map(|d| match d {
+                ViolationDetails::Pii(details) => details.entity_type,
+                other => panic!("Expected PII details, got {:?}", other),
+            })
+        };
+
+        assert_eq!(
+            kind("mail me at jane.doe@example.com").as_deref(),
+            Some("email")
+        );
+        assert_eq!(kind("SSN 123-45-6789.").as_deref(), Some("ssn"));
+        assert_eq!(
+            kind("card 4111 1111 1111 1111").as_deref(),
+            Some("card_number")
+        );
+        assert_eq!(kind("card 4111-1111-1111-1112"), None);
+        assert_eq!(kind("@ the office, call 1234-56-78901"), None);
+        assert_eq!(kind("user@localhost"), None);
+
+        let text = "Contact: jane.doe@example.com.";
+        let violation = policy.check(text).unwrap();
+        assert_eq!(violation.span(text), Some("jane.doe@example.com"));
+    }
+
+    #[test]
+    fn test_blocked_term_offsets_survive_case_folding() {
+        // 'İ' lowercases to two characters, shifting lowercase offsets
+        let text = "İİ Secret";
+        let violation = BlockedTerms::new("s", ["secret"]).check(text).unwrap();
+        assert_eq!(violation.span(text), Some("Secret"));
+    }
+
+    struct QuotesContext;
+
+    impl LocalPolicy for QuotesContext {
+        fn name(&self) -> &str {
+            "quotes"
+        }
+
+        fn check(&self, _text: &str) -> Option<Violation> {
+            None
+        }
+
+        fn check_in_context(&self, text: &str, context: &[String]) -> Option<Violation> {
+            context
+                .iter()
+                .any(|c| text.contains(c.as_str()))
+                .then(|| Violation {
+                    policy_id: "quotes".to_string(),
+                    policy_name: "quotes".to_string(),
+                    policy_type: "context".to_string(),
+                    violation_type: "verbatim_context".to_string(),
+                    message: "Output quotes context".to_string(),
+                    severity: "low".to_string(),
+                    enforcement_level: EnforcementLevel::Advisory,
+                    details: None,
+                    start_offset: None,
+                    end_offset: None,
+                })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_policies_see_added_context() {
+        let backend = LocalBackend::new().policy(QuotesContext);
+        backend.start(None).await.unwrap();
+
+        let before = backend.evaluate("secret doc", 0, false).await.unwrap();
+        assert!(before.violations.is_empty());
+
+        backend.add_context("secret doc").await.unwrap();
+        let after = backend.evaluate(" again", 1, false).await.unwrap();
+        assert_eq!(after.violations.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_only_text_near_new_tokens_is_checked() {
+        let backend = LocalBackend::new().policy(pii());
+        backend.start(None).await.unwrap();
+
+        // 18 digits fail the Luhn check, but the window of the next check
+        // starts at the valid card number "4111 1111 1111 1111"
+        let checked = format!("12 4111 1111 1111 1111 {}", "x".repeat(MAX_EMAIL_LEN - 20));
+        assert_eq!(checked.len() - MAX_EMAIL_LEN, 3);
+        let verdict = backend.evaluate(&checked, 0, false).await.unwrap();
+        assert!(verdict.allowed);
+
+        let verdict = backend
+            .evaluate(" card 5555 5555 5555 4444", 1, false)
+            .await
+            .unwrap();
+        let text = format!("{} card 5555 5555 5555 4444", checked);
+        let violation = verdict.termination.unwrap().violation;
+        assert_eq!(violation.span(&text), Some("5555 5555 5555 4444"));
+        match violation.details {
+            Some(ViolationDetails::Pii(details)) => {
+                let span = details.span.unwrap();
+                assert_eq!(&text[span.start..span.end], "5555 5555 5555 4444");
+            }
+            other => panic!("Expected PII details, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_regex_policy_bounds_matches() {
+        let bounded = RegexPolicy::new("ids", r"\bID-[0-9]{4}\b").unwrap();
+        assert_eq!(bounded.max_match_len(), Some(7));
+        let unbounded = RegexPolicy::new("quotes", r#""[^"]*""#).unwrap();
+        assert_eq!(unbounded.max_match_len(), None);
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_without_session_fails() {
+        let backend = LocalBackend::new();
+        assert!(backend.evaluate("hi", 0, false).await.is_err());
+    }
+}
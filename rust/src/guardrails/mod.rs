@@ -46,23 +46,34 @@
 //! }
 //! ```
 
+pub mod backend;
 mod client;
+pub mod language;
+pub mod local;
+pub mod pipeline;
+pub mod pool;
+pub mod remote;
 pub mod streaming;
 mod types;
 
 pub use client::{stream_with_guardrails, GuardrailViolationError, StreamingGuardrails};
 pub use types::{
     EarlyTerminationData, EnforcementLevel, ErrorData, GuardrailSession, GuardrailViolation,
-    SessionCompleteData, SessionStartedData, StreamingEvent, StreamingEventType,
-    StreamingGuardrailsConfig, TokenAllowedData, ViolationDetectedData,
+    PiiDetails, RegexDetails, SessionCompleteData, SessionStartedData, StreamingEvent,
+    StreamingEventType, StreamingGuardrailsConfig, TextSpan, TokenAllowedData, ToxicityDetails,
+    ViolationDetails, ViolationDetectedData,
 };
 
+pub use backend::{GuardrailBackend, LayeredBackend, Verdict};
+pub use language::detect_language;
+pub use local::{pii, BlockedTerms, LocalBackend, LocalPolicy, PiiPolicy, RegexPolicy};
+pub use pipeline::Pipeline;
+pub use pool::GuardrailSessionPool;
+pub use remote::RemoteBackend;
+
 // New streaming guardrail (token-by-token)
 pub use streaming::{
-    StreamingGuardrail,
-    StreamingGuardrailConfig,
-    StreamingGuardrailSession,
-    Violation,
-    ViolationError,
-    stream_with_guardrails as stream_with_guardrail,
+    stream_with_guardrails as stream_with_guardrail, GuardrailUsage, SourceDocument,
+    StreamingGuardrail, StreamingGuardrailConfig, StreamingGuardrailSession, UngroundedClaim,
+    Violation, ViolationError,
 };
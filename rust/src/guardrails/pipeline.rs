@@ -0,0 +1,258 @@
+//! Composite guardrail pipelines.
+//!
+//! A [`Pipeline`] runs several guardrail backends on each token in order and
+//! reports the violations of every stage together. A stage can be marked to
+//! short-circuit, skipping the remaining stages for a token it flags; a
+//! blocking violation always ends the session.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::guardrails::local::pii;
+//! use diagnyx::guardrails::pipeline::Pipeline;
+//! use diagnyx::guardrails::{StreamingGuardrail, StreamingGuardrailConfig};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let config = StreamingGuardrailConfig::new("dx_...", "org_123", "proj_456");
+//!
+//!     let pipeline = Pipeline::new()
+//!         .local(pii())
+//!         .short_circuit()
+//!         .remote(config.clone());
+//!
+//!     let guardrail = StreamingGuardrail::with_backend(config, pipeline);
+//!     guardrail.start_session(None).await?;
+//!     guardrail.evaluate("Hello", true).await?;
+//!     guardrail.complete_session().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use async_trait::async_trait;
+
+use super::backend::{BackendSession, Completion, GuardrailBackend, Verdict};
+use super::local::{LocalBackend, LocalPolicy};
+use super::remote::RemoteBackend;
+use super::streaming::{SourceDocument, StreamingGuardrailConfig};
+use crate::error::DiagnyxError;
+use crate::ids::SessionId;
+
+struct Stage {
+    backend: Box<dyn GuardrailBackend>,
+    short_circuit: bool,
+}
+
+/// Runs guardrail backends in sequence on every token.
+///
+/// A token is released only if every stage that evaluated it allows it.
+/// Violations from all stages are combined in the returned [`Verdict`].
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    /// Create an empty pipeline, which allows every token.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a stage evaluating a local policy.
+    pub fn local(self, policy: impl LocalPolicy + 'static) -> Self {
+        self.backend(LocalBackend::new().policy(policy))
+    }
+
+    /// Add a stage evaluating the remote policies of a Diagnyx project.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`backend`](Self::backend) with
+    /// [`RemoteBackend::try_new`] to handle the error instead.
+    pub fn remote(self, config: StreamingGuardrailConfig) -> Self {
+        self.backend(RemoteBackend::new(config))
+    }
+
+    /// Add a stage evaluating a custom backend.
+    pub fn backend(mut self, backend: impl GuardrailBackend + 'static) -> Self {
+        self.stages.push(Stage {
+            backend: Box::new(backend),
+            short_circuit: false,
+        });
+        self
+    }
+
+    /// Skip the remaining stages for any token on which the most recently
+    /// added stage reports a violation.
+    pub fn short_circuit(mut self) -> Self {
+        if let Some(stage) = self.stages.last_mut() {
+            stage.short_circuit = true;
+        }
+        self
+    }
+
+    /// Number of stages in the pipeline.
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Whether the pipeline has no stages.
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+}
+
+#[async_trait]
+impl GuardrailBackend for Pipeline {
+    /// Start a session on every stage.
+    ///
+    /// The session ID of the last stage is returned, so a remote stage added
+    /// last keeps its server-side ID. Active policies of all stages are
+    /// combined.
+    async fn start(&self, input: Option<&str>) -> Result<BackendSession, DiagnyxError> {
+        let mut combined = BackendSession {
+            session_id: SessionId::generate(),
+            active_policies: Vec::new(),
+        };
+
+        for (i, stage) in self.stages.iter().enumerate() {
+            match stage.backend.start(input).await {
+                Ok(session) => {
+                    combined.session_id = session.session_id;
+                    combined.active_policies.extend(session.active_policies);
+                }
+                Err(e) => {
+                    for started in &self.stages[..i] {
+                        let _ = started.backend.cancel().await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(combined)
+    }
+
+    async fn evaluate(
+        &self,
+        token: &str,
+        index: i32,
+        is_last: bool,
+    ) -> Result<Verdict, DiagnyxError> {
+        let mut combined = Verdict::allow();
+
+        for stage in &self.stages {
+            let verdict = stage.backend.evaluate(token, index, is_last).await?;
+            let flagged = !verdict.violations.is_empty() || verdict.termination.is_some();
+
+            combined = combined.merge(verdict);
+            if combined.termination.is_some() || (stage.short_circuit && flagged) {
+                break;
+            }
+        }
+
+        Ok(combined)
+    }
+
+    async fn complete(&self) -> Result<Verdict, DiagnyxError> {
+        let mut combined = Verdict::allow();
+
+        for stage in &self.stages {
+            let verdict = stage.backend.complete().await?;
+            let completion = match (combined.completion, verdict.completion) {
+                (Some(c), Some(completion)) => Some(Completion {
+                    allowed: c.allowed && completion.allowed,
+                    total_tokens: c.total_tokens.max(completion.total_tokens),
+                }),
+                (c, completion) => c.or(completion),
+            };
+            combined = Verdict {
+                completion,
+                ..combined.merge(verdict)
+            };
+        }
+
+        Ok(combined)
+    }
+
+    async fn cancel(&self) -> Result<bool, DiagnyxError> {
+        let mut cancelled = false;
+        for stage in &self.stages {
+            cancelled |= stage.backend.cancel().await?;
+        }
+        Ok(cancelled)
+    }
+
+    async fn add_context(&self, context: &str) -> Result<(), DiagnyxError> {
+        for stage in &self.stages {
+            stage.backend.add_context(context).await?;
+        }
+        Ok(())
+    }
+
+    async fn add_sources(&self, sources: &[SourceDocument]) -> Result<(), DiagnyxError> {
+        for stage in &self.stages {
+            stage.backend.add_sources(sources).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guardrails::local::BlockedTerms;
+    use crate::guardrails::streaming::EnforcementLevel;
+
+    fn warn(name: &str, term: &str) -> BlockedTerms {
+        BlockedTerms::new(name, [term]).enforcement_level(EnforcementLevel::Warning)
+    }
+
+    #[tokio::test]
+    async fn test_combines_violations_from_all_stages() {
+        let pipeline = Pipeline::new()
+            .local(warn("first", "alpha"))
+            .local(warn("second", "beta"));
+
+        let session = pipeline.start(None).await.unwrap();
+        assert_eq!(session.active_policies, vec!["first", "second"]);
+
+        let verdict = pipeline.evaluate("alpha beta", 0, false).await.unwrap();
+        assert!(verdict.allowed);
+        let names: Vec<_> = verdict
+            .violations
+            .iter()
+            .map(|v| v.policy_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn test_short_circuit_skips_later_stages() {
+        let pipeline = Pipeline::new()
+            .local(warn("first", "alpha"))
+            .short_circuit()
+            .local(warn("second", "beta"));
+        pipeline.start(None).await.unwrap();
+
+        let verdict = pipeline.evaluate("alpha beta", 0, false).await.unwrap();
+        assert_eq!(verdict.violations.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_blocking_violation_stops_pipeline() {
+        let pipeline = Pipeline::new()
+            .local(BlockedTerms::new("block", ["alpha"]))
+            .local(warn("second", "beta"));
+        pipeline.start(None).await.unwrap();
+
+        let verdict = pipeline.evaluate("alpha beta", 0, false).await.unwrap();
+        assert!(!verdict.allowed);
+        assert!(verdict.termination.is_some());
+        assert!(verdict.violations.is_empty());
+
+        let completion = pipeline.complete().await.unwrap().completion.unwrap();
+        assert!(!completion.allowed);
+    }
+}
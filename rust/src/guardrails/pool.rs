@@ -0,0 +1,487 @@
+//! Pool of pre-started streaming guardrail sessions.
+//!
+//! Starting a session costs a round trip before the first token can be
+//! evaluated. A `GuardrailSessionPool` keeps sessions started ahead of time so
+//! that acquiring one for a request is immediate.
+//!
+//! Sessions are never handed out once they have been started for longer than
+//! the idle timeout. Once the pool is warmed or first used, a background task
+//! recycles expired sessions and starts replacements.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::guardrails::pool::GuardrailSessionPool;
+//! use diagnyx::guardrails::StreamingGuardrailConfig;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let config = StreamingGuardrailConfig::new("dx_...", "org_123", "proj_456");
+//!     let pool = GuardrailSessionPool::new(config, 4);
+//!     pool.warm().await?;
+//!
+//!     // When the user hits "send"
+//!     let guardrail = pool.acquire().await?;
+//!     for token in vec!["Hello", " ", "world"] {
+//!         guardrail.evaluate(token, false).await?;
+//!     }
+//!     guardrail.complete_session().await?;
+//!
+//!     pool.shutdown().await;
+//!     Ok(())
+//! }
+//! ```
+
+use reqwest::Client;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use super::streaming::{StreamingGuardrail, StreamingGuardrailConfig};
+use crate::error::DiagnyxError;
+use crate::ids::SessionId;
+use crate::logger::Logger;
+
+/// How long an unused session is kept before it is recycled.
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Shortest interval between background eviction passes.
+const MIN_EVICTION_INTERVAL: Duration = Duration::from_secs(1);
+
+struct PooledSession {
+    guardrail: StreamingGuardrail,
+    started_at: Instant,
+}
+
+struct PoolInner {
+    config: StreamingGuardrailConfig,
+    http_client: Client,
+    size: usize,
+    idle_timeout: Duration,
+    sessions: Mutex<VecDeque<PooledSession>>,
+    /// When each session handed out by the pool was started, so that a
+    /// released session keeps its age.
+    issued: Mutex<HashMap<SessionId, Instant>>,
+    evictor: std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl PoolInner {
+    fn log(&self, message: &str) {
+        Logger::new("DiagnyxGuardrails", self.config.debug).log(message);
+    }
+
+    async fn start(&self) -> Result<StreamingGuardrail, DiagnyxError> {
+        let guardrail =
+            StreamingGuardrail::with_http_client(self.config.clone(), self.http_client.clone());
+        guardrail.start_session(None).await?;
+        Ok(guardrail)
+    }
+
+    fn expired(&self, started_at: Instant) -> bool {
+        started_at.elapsed() >= self.idle_timeout
+    }
+
+    async fn evict_idle(&self) -> usize {
+        let expired: Vec<PooledSession> = {
+            let mut sessions = self.sessions.lock().await;
+            let (expired, fresh): (Vec<_>, Vec<_>) =
+                sessions.drain(..).partition(|s| self.expired(s.started_at));
+            *sessions = fresh.into();
+            expired
+        };
+        self.issued
+            .lock()
+            .await
+            .retain(|_, started_at| !self.expired(*started_at));
+
+        let count = expired.len();
+        for pooled in expired {
+            let _ = pooled.guardrail.cancel_session().await;
+        }
+        if count > 0 {
+            self.log(&format!("Recycled {} idle guardrail sessions", count));
+        }
+        count
+    }
+
+    /// Start the background task that recycles idle sessions, if it is not
+    /// running yet and a runtime is available.
+    fn start_evictor(self: &Arc<Self>) {
+        let mut evictor = self.evictor.lock().unwrap_or_else(|e| e.into_inner());
+        if evictor.is_some() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+
+        let period = (self.idle_timeout / 2).max(MIN_EVICTION_INTERVAL);
+        let inner: Weak<Self> = Arc::downgrade(self);
+        *evictor = Some(runtime.spawn(async move {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                if inner.evict_idle().await > 0 {
+                    if let Err(e) = inner.refill().await {
+                        inner.log(&format!("Failed to refill session pool: {}", e));
+                    }
+                }
+            }
+        }));
+    }
+
+    async fn refill(&self) -> Result<usize, DiagnyxError> {
+        let missing = self.size.saturating_sub(self.sessions.lock().await.len());
+        let started = futures::future::join_all((0..missing).map(|_| self.start())).await;
+
+        let mut count = 0;
+        let mut last_error = None;
+        let mut sessions = self.sessions.lock().await;
+        for result in started {
+            match result {
+                Ok(guardrail) if sessions.len() < self.size => {
+                    sessions.push_back(PooledSession {
+                        guardrail,
+                        started_at: Instant::now(),
+                    });
+                    count += 1;
+                }
+                Ok(guardrail) => {
+                    let _ = guardrail.cancel_session().await;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        match last_error {
+            Some(e) if count == 0 && missing > 0 => Err(e),
+            _ => Ok(count),
+        }
+    }
+}
+
+/// A pool of pre-started guardrail sessions.
+///
+/// Cloning the pool is cheap; clones share the same sessions.
+#[derive(Clone)]
+pub struct GuardrailSessionPool {
+    inner: Arc<PoolInner>,
+}
+
+impl GuardrailSessionPool {
+    /// Create a pool that keeps up to `size` sessions started.
+    ///
+    /// No sessions are started until [`warm`](Self::warm) or
+    /// [`acquire`](Self::acquire) is called.
+    pub fn new(config: StreamingGuardrailConfig, size: usize) -> Self {
+        Self::with_idle_timeout(config, size, DEFAULT_IDLE_TIMEOUT)
+    }
+
+    /// Create a pool whose unused sessions are recycled after `idle_timeout`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_with_idle_timeout`](Self::try_with_idle_timeout) to handle the
+    /// error instead.
+    pub fn with_idle_timeout(
+        config: StreamingGuardrailConfig,
+        size: usize,
+        idle_timeout: Duration,
+    ) -> Self {
+        let http_client = config
+            .http
+            .client(Duration::from_secs(config.timeout_secs))
+            .expect("Failed to create HTTP client");
+
+        Self::with_http_client(config, http_client, size, idle_timeout)
+    }
+
+    /// Create a pool, returning an error if the configuration is invalid or
+    /// the HTTP client cannot be created.
+    pub fn try_new(config: StreamingGuardrailConfig, size: usize) -> Result<Self, DiagnyxError> {
+        Self::try_with_idle_timeout(config, size, DEFAULT_IDLE_TIMEOUT)
+    }
+
+    /// Create a pool with an idle timeout, returning an error if the
+    /// configuration is invalid or the HTTP client cannot be created.
+    pub fn try_with_idle_timeout(
+        config: StreamingGuardrailConfig,
+        size: usize,
+        idle_timeout: Duration,
+    ) -> Result<Self, DiagnyxError> {
+        config.validate()?;
+        let http_client = config
+            .http
+            .client(Duration::from_secs(config.timeout_secs))?;
+
+        Ok(Self::with_http_client(
+            config,
+            http_client,
+            size,
+            idle_timeout,
+        ))
+    }
+
+    /// Create a pool whose sessions send requests with `http_client`.
+    ///
+    /// The HTTP settings and timeout of `config` are not applied to it.
+    pub fn with_http_client(
+        config: StreamingGuardrailConfig,
+        http_client: Client,
+        size: usize,
+        idle_timeout: Duration,
+    ) -> Self {
+        Self {
+            inner: Arc::new(PoolInner {
+                config,
+                http_client,
+                size,
+                idle_timeout,
+                sessions: Mutex::new(VecDeque::new()),
+                issued: Mutex::new(HashMap::new()),
+                evictor: std::sync::Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Start sessions until the pool is full.
+    ///
+    /// Returns the number of sessions started.
+    pub async fn warm(&self) -> Result<usize, DiagnyxError> {
+        self.inner.start_evictor();
+        self.inner.refill().await
+    }
+
+    /// Number of pre-started sessions currently available.
+    pub async fn available(&self) -> usize {
+        self.inner.sessions.lock().await.len()
+    }
+
+    /// Take a guardrail with a started session.
+    ///
+    /// Uses a pre-started session when one is available and has not been
+    /// idle longer than the idle timeout, otherwise starts a new one. The
+    /// pool is refilled in the background.
+    pub async fn acquire(&self) -> Result<StreamingGuardrail, DiagnyxError> {
+        self.inner.start_evictor();
+        let mut expired = Vec::new();
+        let pooled = {
+            let mut sessions = self.inner.sessions.lock().await;
+            loop {
+                match sessions.pop_front() {
+                    Some(s) if self.inner.expired(s.started_at) => expired.push(s),
+                    other => break other,
+                }
+            }
+        };
+        for stale in expired {
+            let _ = stale.guardrail.cancel_session().await;
+        }
+
+        let inner = Arc::clone(&self.inner);
+        tokio::spawn(async move {
+            if let Err(e) = inner.refill().await {
+                inner.log(&format!("Failed to refill session pool: {}", e));
+            }
+        });
+
+        let (guardrail, started_at) = match pooled {
+            Some(pooled) => (pooled.guardrail, pooled.started_at),
+            None => (self.inner.start().await?, Instant::now()),
+        };
+        if let Some(session) = guardrail.get_session().await {
+            self.inner
+                .issued
+                .lock()
+                .await
+                .insert(session.session_id, started_at);
+        }
+        Ok(guardrail)
+    }
+
+    /// Return a guardrail to the pool.
+    ///
+    /// The session is reused if it was acquired from this pool, no tokens
+    /// were evaluated on it, it has not outlived the idle timeout and the
+    /// pool has room; otherwise it is cancelled if still active. A reused
+    /// session keeps the time it was started at.
+    pub async fn release(&self, guardrail: StreamingGuardrail) {
+        let started_at = match guardrail.get_session().await {
+            Some(s) if !s.terminated && s.accumulated_text.is_empty() => {
+                self.inner.issued.lock().await.remove(&s.session_id)
+            }
+            _ => None,
+        };
+
+        if let Some(started_at) = started_at.filter(|t| !self.inner.expired(*t)) {
+            let mut sessions = self.inner.sessions.lock().await;
+            if sessions.len() < self.inner.size {
+                sessions.push_back(PooledSession {
+                    guardrail,
+                    started_at,
+                });
+                return;
+            }
+        }
+
+        let _ = guardrail.cancel_session().await;
+    }
+
+    /// Cancel sessions that have been idle longer than the idle timeout.
+    ///
+    /// This also runs in the background once the pool has been warmed or
+    /// used. Returns the number of sessions recycled. Call
+    /// [`warm`](Self::warm) afterwards to replace them.
+    pub async fn evict_idle(&self) -> usize {
+        self.inner.evict_idle().await
+    }
+
+    /// Stop background eviction and cancel all pooled sessions.
+    pub async fn shutdown(&self) {
+        let evictor = self
+            .inner
+            .evictor
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(evictor) = evictor {
+            evictor.abort();
+        }
+        let sessions: Vec<PooledSession> = self.inner.sessions.lock().await.drain(..).collect();
+        for pooled in sessions {
+            let _ = pooled.guardrail.cancel_session().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path, path_regex};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn mock_server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream/start",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "type": "session_started",
+                "sessionId": "sess-1",
+                "activePolicies": []
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path_regex(
+                "^/api/v1/organizations/org-1/guardrails/evaluate/stream/.+$",
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"cancelled": true})),
+            )
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn config(server: &MockServer) -> StreamingGuardrailConfig {
+        StreamingGuardrailConfig::new("test-api-key", "org-1", "proj-1").base_url(server.uri())
+    }
+
+    #[tokio::test]
+    async fn test_warm_and_acquire() {
+        let server = mock_server().await;
+        let pool = GuardrailSessionPool::new(config(&server), 2);
+
+        assert_eq!(pool.warm().await.unwrap(), 2);
+        assert_eq!(pool.available().await, 2);
+
+        let guardrail = pool.acquire().await.unwrap();
+        assert!(guardrail.is_active().await);
+
+        pool.release(guardrail).await;
+        assert_eq!(pool.available().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_evict_idle() {
+        let server = mock_server().await;
+        let pool = GuardrailSessionPool::with_idle_timeout(config(&server), 2, Duration::ZERO);
+        pool.warm().await.unwrap();
+
+        assert_eq!(pool.evict_idle().await, 2);
+        assert_eq!(pool.available().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_skips_expired_sessions() {
+        let server = mock_server().await;
+        let pool =
+            GuardrailSessionPool::with_idle_timeout(config(&server), 1, Duration::from_millis(50));
+        pool.warm().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        let guardrail = pool.acquire().await.unwrap();
+        assert!(guardrail.is_active().await);
+        let cancels = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|r| r.method == wiremock::http::Method::Delete)
+            .count();
+        assert_eq!(cancels, 1);
+    }
+
+    #[tokio::test]
+    async fn test_release_keeps_session_age() {
+        let server = mock_server().await;
+        let pool =
+            GuardrailSessionPool::with_idle_timeout(config(&server), 1, Duration::from_millis(50));
+        pool.warm().await.unwrap();
+
+        let guardrail = pool.acquire().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        pool.evict_idle().await;
+        pool.release(guardrail).await;
+
+        assert_eq!(pool.available().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_are_evicted_in_the_background() {
+        let server = mock_server().await;
+        let pool =
+            GuardrailSessionPool::with_idle_timeout(config(&server), 1, Duration::from_millis(100));
+        pool.warm().await.unwrap();
+        tokio::time::sleep(MIN_EVICTION_INTERVAL + Duration::from_millis(200)).await;
+
+        let cancels = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|r| r.method == wiremock::http::Method::Delete)
+            .count();
+        assert!(cancels >= 1);
+        assert_eq!(pool.available().await, 1);
+        pool.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_warm_fails_when_sessions_cannot_start() {
+        let server = MockServer::start().await;
+        let pool = GuardrailSessionPool::new(config(&server), 1);
+
+        assert!(pool.warm().await.is_err());
+        assert!(pool.acquire().await.is_err());
+    }
+}
@@ -0,0 +1,610 @@
+//! Guardrail backend backed by the Diagnyx API.
+
+use async_trait::async_trait;
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use super::backend::{
+    BackendSession, Completion, GuardrailBackend, PendingText, Termination, Verdict,
+};
+use super::language::{detect_recent_language, SAMPLE_BYTES};
+use super::streaming::{
+    EnforcementLevel, SourceDocument, StreamingGuardrailConfig, StreamingGuardrailSession,
+    Violation,
+};
+use super::types::ViolationDetails;
+use crate::error::DiagnyxError;
+use crate::ids::SessionId;
+use crate::logger::Logger;
+use crate::retry::{send_with_retry, with_timeout};
+use crate::sse;
+
+#[derive(Debug, Deserialize)]
+struct StartSessionResponse {
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(rename = "sessionId")]
+    session_id: Option<SessionId>,
+    #[serde(rename = "activePolicies")]
+    active_policies: Option<Vec<String>>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EvaluateResponse {
+    #[serde(rename = "type")]
+    event_type: String,
+    #[serde(rename = "totalTokens")]
+    total_tokens: Option<i32>,
+    allowed: Option<bool>,
+    reason: Option<String>,
+    #[serde(rename = "blockingViolation")]
+    blocking_violation: Option<ViolationData>,
+    #[serde(flatten)]
+    violation: ViolationData,
+    error: Option<String>,
+}
+
+impl EvaluateResponse {
+    fn completion(&self) -> Completion {
+        Completion {
+            allowed: self.allowed.unwrap_or(true),
+            total_tokens: self.total_tokens.unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ViolationData {
+    #[serde(rename = "policyId")]
+    policy_id: Option<String>,
+    #[serde(rename = "policyName")]
+    policy_name: Option<String>,
+    #[serde(rename = "policyType")]
+    policy_type: Option<String>,
+    #[serde(rename = "violationType")]
+    violation_type: Option<String>,
+    message: Option<String>,
+    severity: Option<String>,
+    #[serde(rename = "enforcementLevel")]
+    enforcement_level: Option<String>,
+    details: Option<ViolationDetails>,
+    #[serde(rename = "startOffset")]
+    start_offset: Option<usize>,
+    #[serde(rename = "endOffset")]
+    end_offset: Option<usize>,
+}
+
+impl ViolationData {
+    fn to_violation(&self) -> Violation {
+        let level = self
+            .enforcement_level
+            .as_ref()
+            .map(|s| match s.as_str() {
+                "blocking" => EnforcementLevel::Blocking,
+                "warning" => EnforcementLevel::Warning,
+                _ => EnforcementLevel::Advisory,
+            })
+            .unwrap_or(EnforcementLevel::Advisory);
+
+        Violation {
+            policy_id: self.policy_id.clone().unwrap_or_default(),
+            policy_name: self.policy_name.clone().unwrap_or_default(),
+            policy_type: self.policy_type.clone().unwrap_or_default(),
+            violation_type: self.violation_type.clone().unwrap_or_default(),
+            message: self.message.clone().unwrap_or_default(),
+            severity: self.severity.clone().unwrap_or_default(),
+            enforcement_level: level,
+            details: self
+                .details
+                .clone()
+                .map(|details| details.for_policy(self.policy_type.as_deref().unwrap_or_default())),
+            start_offset: self.start_offset,
+            end_offset: self.end_offset,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StartSessionRequest {
+    #[serde(rename = "projectId")]
+    project_id: String,
+    #[serde(rename = "evaluateEveryNTokens")]
+    evaluate_every_n_tokens: i32,
+    #[serde(rename = "enableEarlyTermination")]
+    enable_early_termination: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct EvaluateTokenRequest {
+    #[serde(rename = "sessionId")]
+    session_id: SessionId,
+    token: String,
+    #[serde(rename = "tokenIndex")]
+    token_index: i32,
+    #[serde(rename = "isLast")]
+    is_last: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+struct AddContextRequest<'a> {
+    #[serde(rename = "sessionId")]
+    session_id: SessionId,
+    context: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct AddSourcesRequest<'a> {
+    #[serde(rename = "sessionId")]
+    session_id: SessionId,
+    sources: &'a [SourceDocument],
+}
+
+const LOG_COMPONENT: &str = "DiagnyxGuardrails";
+
+/// Drop the start of `text` so at most `max` bytes remain, cutting on a
+/// char boundary.
+fn keep_tail(text: &mut String, max: usize) {
+    if text.len() <= max {
+        return;
+    }
+    let mut start = text.len() - max;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    text.drain(..start);
+}
+
+/// Parse the data of each event in a server-sent events body.
+fn parse_events(text: &str) -> impl Iterator<Item = Result<EvaluateResponse, serde_json::Error>> {
+    sse::parse(text)
+        .events
+        .into_iter()
+        .map(|event| serde_json::from_str::<EvaluateResponse>(&event.data))
+}
+
+/// Evaluates tokens with the Diagnyx streaming guardrails API.
+///
+/// Tokens are batched as configured by
+/// [`evaluate_every_n_tokens`](StreamingGuardrailConfig::evaluate_every_n_tokens)
+/// and [`evaluate_interval`](StreamingGuardrailConfig::evaluate_interval),
+/// and each batch is sent as a single evaluation request. A batch still
+/// pending when the session completes is sent before completing it.
+pub struct RemoteBackend {
+    config: StreamingGuardrailConfig,
+    http_client: Client,
+    session_id: Mutex<Option<SessionId>>,
+    /// The most recent evaluated text, used to detect the output language.
+    output: Mutex<String>,
+    pending: Mutex<PendingText>,
+    /// Evaluation requests sent, retries not included.
+    evaluations: AtomicU64,
+    /// Logger of the current session, or of the backend between sessions.
+    logger: std::sync::Mutex<Logger>,
+}
+
+impl RemoteBackend {
+    /// Create a remote backend.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
+    pub fn new(config: StreamingGuardrailConfig) -> Self {
+        let http_client = config
+            .http
+            .client(Duration::from_secs(config.timeout_secs))
+            .expect("Failed to create HTTP client");
+
+        Self::with_http_client(config, http_client)
+    }
+
+    /// Create a remote backend, returning an error if the configuration is
+    /// invalid or the HTTP client cannot be created.
+    pub fn try_new(config: StreamingGuardrailConfig) -> Result<Self, DiagnyxError> {
+        config.validate()?;
+        let http_client = config
+            .http
+            .client(Duration::from_secs(config.timeout_secs))?;
+
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Create a backend sending requests with `http_client`.
+    ///
+    /// The HTTP settings and timeout of `config` are not applied to it.
+    pub fn with_http_client(config: StreamingGuardrailConfig, http_client: Client) -> Self {
+        Self {
+            http_client,
+            session_id: Mutex::new(None),
+            output: Mutex::new(String::new()),
+            pending: Mutex::new(PendingText::default()),
+            evaluations: AtomicU64::new(0),
+            logger: std::sync::Mutex::new(Logger::new(LOG_COMPONENT, config.debug)),
+            config,
+        }
+    }
+
+    fn log(&self, message: &str) {
+        self.logger.lock().unwrap().log(message);
+    }
+
+    fn set_logger(&self, session_id: Option<&SessionId>) {
+        let logger = Logger::new(LOG_COMPONENT, self.config.debug);
+        *self.logger.lock().unwrap() = match session_id {
+            Some(id) => logger.child("session", id),
+            None => logger,
+        };
+    }
+
+    fn get_base_endpoint(&self) -> String {
+        format!(
+            "{}/api/v1/organizations/{}/guardrails",
+            self.config.base_url.trim_end_matches('/'),
+            self.config.organization_id
+        )
+    }
+
+    async fn current_session(&self) -> Result<SessionId, DiagnyxError> {
+        self.session_id
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))
+    }
+
+    /// Add `token` to the pending batch and take the batch if it is due.
+    async fn take_batch(&self, token: &str, is_last: bool) -> Option<(String, i32)> {
+        let mut pending = self.pending.lock().await;
+        pending.push(token);
+
+        let full = pending.tokens() >= self.config.evaluate_every_n_tokens.max(1) as usize;
+        let expired = self
+            .config
+            .evaluate_interval
+            .is_some_and(|interval| pending.age() >= interval);
+        (is_last || full || expired).then(|| pending.take())
+    }
+
+    async fn send_batch(
+        &self,
+        chunk: String,
+        index: i32,
+        is_last: bool,
+    ) -> Result<Verdict, DiagnyxError> {
+        let url = format!("{}/evaluate/stream", self.get_base_endpoint());
+
+        let session_id = self.current_session().await?;
+        let language = {
+            let mut output = self.output.lock().await;
+            output.push_str(&chunk);
+            keep_tail(&mut output, SAMPLE_BYTES);
+            detect_recent_language(&output)
+        };
+
+        let request = EvaluateTokenRequest {
+            session_id,
+            token: chunk,
+            token_index: index,
+            is_last,
+            language,
+        };
+        self.evaluations.fetch_add(1, Ordering::Relaxed);
+
+        let response = send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            Method::POST,
+            |method| {
+                self.http_client
+                    .request(method, &url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+                    .header("Accept", "text/event-stream")
+                    .json(&request)
+            },
+        )
+        .await?;
+
+        let text = response.text().await?;
+        let mut verdict = Verdict::default();
+
+        for event in parse_events(&text) {
+            match event {
+                Ok(data) => match data.event_type.as_str() {
+                    "token_allowed" => verdict.allowed = true,
+                    "violation_detected" => verdict.violations.push(data.violation.to_violation()),
+                    "early_termination" => {
+                        let violation = data
+                            .blocking_violation
+                            .as_ref()
+                            .unwrap_or(&data.violation)
+                            .to_violation();
+                        verdict.allowed = false;
+                        verdict.termination = Some(Termination {
+                            reason: data.reason,
+                            violation,
+                        });
+                        return Ok(verdict);
+                    }
+                    "session_complete" => verdict.completion = Some(data.completion()),
+                    "error" => {
+                        self.log(&format!("Error: {}", data.error.unwrap_or_default()));
+                    }
+                    _ => {}
+                },
+                Err(e) => {
+                    self.log(&format!("Failed to parse event: {}", e));
+                }
+            }
+        }
+
+        Ok(verdict)
+    }
+}
+
+#[async_trait]
+impl GuardrailBackend for RemoteBackend {
+    async fn start(&self, input: Option<&str>) -> Result<BackendSession, DiagnyxError> {
+        let url = format!("{}/evaluate/stream/start", self.get_base_endpoint());
+
+        let request = StartSessionRequest {
+            project_id: self.config.project_id.clone(),
+            // Tokens are batched before they are sent, so every request is
+            // evaluated
+            evaluate_every_n_tokens: 1,
+            enable_early_termination: self.config.enable_early_termination,
+            input: input.map(|s| s.to_string()),
+        };
+
+        self.log(&format!("Starting session at {}", url));
+
+        let response = send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            Method::POST,
+            |method| {
+                self.http_client
+                    .request(method, &url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+                    .json(&request)
+            },
+        )
+        .await?;
+
+        let data: StartSessionResponse = response.json().await?;
+
+        match data.event_type.as_str() {
+            "session_started" => {
+                let session_id = data.session_id.ok_or_else(|| {
+                    DiagnyxError::ConfigError("Missing session_id in response".to_string())
+                })?;
+                *self.session_id.lock().await = Some(session_id.clone());
+                self.output.lock().await.clear();
+                *self.pending.lock().await = PendingText::default();
+
+                self.set_logger(Some(&session_id));
+                self.log("Session started");
+                Ok(BackendSession {
+                    session_id,
+                    active_policies: data.active_policies.unwrap_or_default(),
+                })
+            }
+            "error" => Err(DiagnyxError::ApiError {
+                status_code: 400,
+                message: data.error.unwrap_or("Unknown error".to_string()),
+                request_id: None,
+            }),
+            other => Err(DiagnyxError::ConfigError(format!(
+                "Unexpected response type: {}",
+                other
+            ))),
+        }
+    }
+
+    async fn evaluate(
+        &self,
+        token: &str,
+        _index: i32,
+        is_last: bool,
+    ) -> Result<Verdict, DiagnyxError> {
+        self.current_session().await?;
+        match self.take_batch(token, is_last).await {
+            Some((chunk, index)) => self.send_batch(chunk, index, is_last).await,
+            None => Ok(Verdict::allow()),
+        }
+    }
+
+    async fn complete(&self) -> Result<Verdict, DiagnyxError> {
+        let (chunk, index) = self.pending.lock().await.take();
+        let mut last = if chunk.is_empty() {
+            Verdict::allow()
+        } else {
+            self.send_batch(chunk, index, true).await?
+        };
+
+        let session_id = self.current_session().await?;
+        let url = format!(
+            "{}/evaluate/stream/{}/complete",
+            self.get_base_endpoint(),
+            session_id
+        );
+
+        self.log("Completing session");
+
+        // The final evaluation may take a while; read its events as they
+        // arrive and stop at the completion
+        let timeout = self.config.complete_timeout;
+        let completion = with_timeout(timeout, async {
+            let response = send_with_retry(
+                &self.config.retry_policy,
+                self.config.rate_limiter.as_ref(),
+                Method::POST,
+                |method| {
+                    self.http_client
+                        .request(method, &url)
+                        .header("Authorization", format!("Bearer {}", self.config.api_key))
+                        .header("Accept", "text/event-stream")
+                },
+            )
+            .await?;
+
+            let mut reader = sse::EventReader::new(response);
+            while let Some(event) = reader.next().await? {
+                match serde_json::from_str::<EvaluateResponse>(&event.data) {
+                    Ok(data) if data.event_type == "session_complete" => {
+                        return Ok(Some(data.completion()));
+                    }
+                    Ok(_) => {}
+                    Err(e) => self.log(&format!("Failed to parse event: {}", e)),
+                }
+            }
+            Ok(None)
+        })
+        .await?;
+
+        *self.session_id.lock().await = None;
+        self.set_logger(None);
+        last.completion = completion.or(last.completion);
+        Ok(last)
+    }
+
+    async fn cancel(&self) -> Result<bool, DiagnyxError> {
+        let Some(session_id) = self.session_id.lock().await.clone() else {
+            return Ok(false);
+        };
+
+        let url = format!(
+            "{}/evaluate/stream/{}",
+            self.get_base_endpoint(),
+            session_id
+        );
+
+        self.log("Cancelling session");
+
+        let response = send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            Method::DELETE,
+            |method| {
+                self.http_client
+                    .request(method, &url)
+                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+            },
+        )
+        .await?;
+
+        #[derive(Deserialize)]
+        struct CancelResponse {
+            cancelled: Option<bool>,
+        }
+
+        let data: CancelResponse = response.json().await?;
+        *self.session_id.lock().await = None;
+        *self.pending.lock().await = PendingText::default();
+        self.set_logger(None);
+
+        Ok(data.cancelled.unwrap_or(false))
+    }
+
+    fn set_session_debug(&self, debug: bool) {
+        self.logger.lock().unwrap().set_debug(debug);
+    }
+
+    fn evaluation_requests(&self) -> u64 {
+        self.evaluations.load(Ordering::Relaxed)
+    }
+
+    async fn add_context(&self, context: &str) -> Result<(), DiagnyxError> {
+        let session_id = self.current_session().await?;
+        let url = format!(
+            "{}/evaluate/stream/{}/context",
+            self.get_base_endpoint(),
+            session_id
+        );
+
+        let request = AddContextRequest {
+            session_id,
+            context,
+        };
+
+        send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            Method::POST,
+            |method| {
+                self.http_client
+                    .request(method, &url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+                    .json(&request)
+            },
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn session_state(&self) -> Option<serde_json::Value> {
+        Some(self.pending.lock().await.to_state())
+    }
+
+    async fn resume(
+        &self,
+        session: &StreamingGuardrailSession,
+        state: Option<&serde_json::Value>,
+    ) -> Result<(), DiagnyxError> {
+        let pending = PendingText::restore(state);
+        // Text still pending was added to the session but not evaluated
+        let mut output = session
+            .accumulated_text
+            .strip_suffix(pending.text())
+            .unwrap_or(&session.accumulated_text)
+            .to_string();
+        keep_tail(&mut output, SAMPLE_BYTES);
+        *self.output.lock().await = output;
+        *self.pending.lock().await = pending;
+        *self.session_id.lock().await = Some(session.session_id.clone());
+        self.set_logger(Some(&session.session_id));
+        self.log("Session resumed");
+        Ok(())
+    }
+
+    async fn add_sources(&self, sources: &[SourceDocument]) -> Result<(), DiagnyxError> {
+        let session_id = self.current_session().await?;
+        let url = format!(
+            "{}/evaluate/stream/{}/sources",
+            self.get_base_endpoint(),
+            session_id
+        );
+
+        let request = AddSourcesRequest {
+            session_id,
+            sources,
+        };
+
+        send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            Method::POST,
+            |method| {
+                self.http_client
+                    .request(method, &url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", self.config.api_key))
+                    .json(&request)
+            },
+        )
+        .await?;
+
+        Ok(())
+    }
+}
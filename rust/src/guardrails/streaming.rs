@@ -38,30 +38,40 @@
 //! ```
 
 use crate::error::DiagnyxError;
+use crate::events::{EventBus, SdkEvent};
+use crate::guardrails::backend::{GuardrailBackend, Verdict};
+use crate::guardrails::language::detect_recent_language;
+use crate::guardrails::local::LocalBackend;
+use crate::guardrails::remote::RemoteBackend;
+use crate::guardrails::types::{validate_settings, ViolationDetails};
+use crate::http::HttpConfig;
+use crate::ids::{ProjectId, SessionId};
+use crate::ratelimit::RateLimiter;
+use crate::retry::{with_timeout, RetryPolicy};
+use crate::types::{default_base_url, disabled_by_env, CallType, LLMCall, Masked, Provider};
+use crate::DiagnyxClient;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Mutex;
 
 /// Enforcement level for guardrail policies.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum EnforcementLevel {
+    #[default]
     Advisory,
     Warning,
     Blocking,
 }
 
-impl Default for EnforcementLevel {
-    fn default() -> Self {
-        EnforcementLevel::Advisory
-    }
-}
-
 /// Details of a guardrail policy violation.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+// Deserialized through the impl below, which types the details
+#[serde(remote = "Self")]
 pub struct Violation {
     pub policy_id: String,
     pub policy_name: String,
@@ -71,7 +81,93 @@ pub struct Violation {
     pub severity: String,
     pub enforcement_level: EnforcementLevel,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub details: Option<HashMap<String, serde_json::Value>>,
+    pub details: Option<ViolationDetails>,
+    /// Byte offset into the accumulated text where the offending span starts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_offset: Option<usize>,
+    /// Byte offset into the accumulated text just past the offending span.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_offset: Option<usize>,
+}
+
+impl Violation {
+    /// The part of `text` that triggered the violation, if the policy
+    /// reported offsets that fall within it.
+    ///
+    /// `text` is normally the session's accumulated text.
+    pub fn span<'a>(&self, text: &'a str) -> Option<&'a str> {
+        text.get(self.start_offset?..self.end_offset?)
+    }
+
+    /// Violation type reported by citation-checking policies.
+    pub const UNGROUNDED_CLAIM: &'static str = "ungrounded_claim";
+
+    /// The unsupported claim, if this is an `ungrounded_claim` violation
+    /// with well-formed details.
+    pub fn ungrounded_claim(&self) -> Option<UngroundedClaim> {
+        if self.violation_type != Self::UNGROUNDED_CLAIM {
+            return None;
+        }
+        match &self.details {
+            Some(ViolationDetails::Unknown(value)) => serde_json::from_value(value.clone()).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for Violation {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Violation::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Violation {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut violation = Violation::deserialize(deserializer)?;
+        violation.details = violation
+            .details
+            .map(|details| details.for_policy(&violation.policy_type));
+        Ok(violation)
+    }
+}
+
+/// A claim in the output that is not supported by the session's sources.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UngroundedClaim {
+    /// The text of the claim.
+    pub claim: String,
+    /// Byte offset of the claim's start in the accumulated text.
+    pub start_offset: usize,
+    /// Byte offset just past the claim's end in the accumulated text.
+    pub end_offset: usize,
+    /// The source closest to supporting the claim, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nearest_source_id: Option<String>,
+}
+
+/// A document the output should be grounded in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceDocument {
+    pub id: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl SourceDocument {
+    pub fn new(id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            content: content.into(),
+            title: None,
+        }
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
 }
 
 /// Error type for guardrail violations that require termination.
@@ -90,7 +186,7 @@ impl std::fmt::Display for ViolationError {
 impl std::error::Error for ViolationError {}
 
 /// Configuration for the streaming guardrail.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct StreamingGuardrailConfig {
     pub api_key: String,
     pub organization_id: String,
@@ -98,8 +194,47 @@ pub struct StreamingGuardrailConfig {
     pub base_url: String,
     pub timeout_secs: u64,
     pub evaluate_every_n_tokens: i32,
+    pub evaluate_interval: Option<Duration>,
     pub enable_early_termination: bool,
+    pub retry_policy: RetryPolicy,
+    /// Spaces out requests to the API, retries included. Default: None (no
+    /// limit)
+    pub rate_limiter: Option<RateLimiter>,
+    pub chunk_concurrency: usize,
+    pub max_reorder_window: usize,
+    pub evaluate_timeout: Option<Duration>,
+    pub complete_timeout: Option<Duration>,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
     pub debug: bool,
+    /// Evaluate tokens locally with no policies, allowing everything
+    /// without a request. Default: whether `DIAGNYX_DISABLED` is `1` or
+    /// `true`
+    pub disabled: bool,
+}
+
+impl fmt::Debug for StreamingGuardrailConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamingGuardrailConfig")
+            .field("api_key", &Masked(&self.api_key))
+            .field("organization_id", &self.organization_id)
+            .field("project_id", &self.project_id)
+            .field("base_url", &self.base_url)
+            .field("timeout_secs", &self.timeout_secs)
+            .field("evaluate_every_n_tokens", &self.evaluate_every_n_tokens)
+            .field("evaluate_interval", &self.evaluate_interval)
+            .field("enable_early_termination", &self.enable_early_termination)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("chunk_concurrency", &self.chunk_concurrency)
+            .field("max_reorder_window", &self.max_reorder_window)
+            .field("evaluate_timeout", &self.evaluate_timeout)
+            .field("complete_timeout", &self.complete_timeout)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .field("disabled", &self.disabled)
+            .finish()
+    }
 }
 
 impl StreamingGuardrailConfig {
@@ -109,15 +244,25 @@ impl StreamingGuardrailConfig {
         organization_id: impl Into<String>,
         project_id: impl Into<String>,
     ) -> Self {
+        let api_key = api_key.into();
         Self {
-            api_key: api_key.into(),
+            base_url: default_base_url(&api_key),
+            api_key,
             organization_id: organization_id.into(),
             project_id: project_id.into(),
-            base_url: "https://api.diagnyx.io".to_string(),
             timeout_secs: 30,
             evaluate_every_n_tokens: 10,
+            evaluate_interval: None,
             enable_early_termination: true,
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: None,
+            chunk_concurrency: 4,
+            max_reorder_window: 1,
+            evaluate_timeout: None,
+            complete_timeout: None,
+            http: HttpConfig::default(),
             debug: false,
+            disabled: disabled_by_env(),
         }
     }
 
@@ -134,30 +279,122 @@ impl StreamingGuardrailConfig {
     }
 
     /// Set how often to evaluate (every N tokens).
+    ///
+    /// Tokens are batched locally and sent in a single evaluation request
+    /// once N have arrived, or with the token marked as the last one. Tokens
+    /// waiting for their batch are released before they are evaluated.
     pub fn evaluate_every_n_tokens(mut self, n: i32) -> Self {
         self.evaluate_every_n_tokens = n;
         self
     }
 
+    /// Also send a batch when its oldest token has waited longer than
+    /// `interval`, so a slow stream is not left unevaluated.
+    ///
+    /// The interval is checked as tokens arrive; a batch is not sent while
+    /// the stream is idle.
+    pub fn evaluate_interval(mut self, interval: Duration) -> Self {
+        self.evaluate_interval = Some(interval);
+        self
+    }
+
     /// Enable or disable early termination.
     pub fn enable_early_termination(mut self, enable: bool) -> Self {
         self.enable_early_termination = enable;
         self
     }
 
+    /// Set the retry policy. Only idempotent requests, such as cancelling a
+    /// session, are retried unless the policy opts in.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Space out requests with `limiter`, e.g. the one of the tracking
+    /// client's [`DiagnyxConfig`](crate::DiagnyxConfig), so that the clients
+    /// sharing it stay within one rate together.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Set how many chunk evaluations `evaluate_chunks` runs concurrently.
+    pub fn chunk_concurrency(mut self, n: usize) -> Self {
+        self.chunk_concurrency = n;
+        self
+    }
+
+    /// Set how many tokens `stream_with_guardrails` may evaluate ahead of
+    /// the next token to be released. Tokens are always released in their
+    /// original order; `1` evaluates tokens one at a time.
+    pub fn max_reorder_window(mut self, window: usize) -> Self {
+        self.max_reorder_window = window;
+        self
+    }
+
+    /// Set a time limit for each token evaluation, overriding the HTTP
+    /// client's timeout for interactive use. Evaluations that take longer
+    /// fail with `DiagnyxError::Timeout`.
+    pub fn evaluate_timeout(mut self, timeout: Duration) -> Self {
+        self.evaluate_timeout = Some(timeout);
+        self
+    }
+
+    /// Set a time limit for completing the session, including reading the
+    /// final evaluation as it streams in. Overrides the HTTP client's
+    /// timeout, so long final evaluations are not cut short; completions
+    /// that take longer fail with `DiagnyxError::Timeout`.
+    pub fn complete_timeout(mut self, timeout: Duration) -> Self {
+        self.complete_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the proxy and TLS settings of the HTTP client.
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
     /// Enable or disable debug logging.
     pub fn debug(mut self, debug: bool) -> Self {
         self.debug = debug;
         self
     }
+
+    /// Turn evaluations into no-ops allowing every token, e.g. in test
+    /// suites and local development.
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = disabled;
+        self
+    }
+
+    /// Validate the configuration.
+    pub fn validate(&self) -> Result<(), DiagnyxError> {
+        validate_settings(
+            &self.organization_id,
+            &self.project_id,
+            &self.base_url,
+            self.evaluate_every_n_tokens,
+        )
+    }
+
+    /// Validate and return the configuration.
+    pub fn build(self) -> Result<Self, DiagnyxError> {
+        self.validate()?;
+        Ok(self)
+    }
 }
 
 /// Session state for streaming guardrail.
-#[derive(Debug, Clone)]
+///
+/// Like [`GuardrailSession`](super::GuardrailSession), it serializes to
+/// JSON with [`to_json`](Self::to_json) for storage between requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StreamingGuardrailSession {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub organization_id: String,
-    pub project_id: String,
+    pub project_id: ProjectId,
     pub active_policies: Vec<String>,
     pub tokens_processed: i32,
     pub violations: Vec<Violation>,
@@ -165,10 +402,44 @@ pub struct StreamingGuardrailSession {
     pub termination_reason: Option<String>,
     pub allowed: bool,
     pub accumulated_text: String,
+    /// Input context added with
+    /// [`add_input_context`](StreamingGuardrail::add_input_context).
+    pub input_context: Vec<String>,
+    /// Source documents the output is checked against.
+    pub sources: Vec<SourceDocument>,
+    /// ISO 639-1 code of the language detected in the last 2 KB of the
+    /// accumulated text, as sent with each evaluation.
+    ///
+    /// `None` until enough text has been seen to tell. See
+    /// [`detect_language`](super::language::detect_language).
+    pub language: Option<String>,
+    /// Index given to the next token evaluated without an explicit index.
+    #[serde(default)]
+    pub next_token_index: i32,
+    /// State of the backend, set by
+    /// [`get_session`](StreamingGuardrail::get_session) so that the session
+    /// can be [resumed](StreamingGuardrail::resume_session).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend_state: Option<serde_json::Value>,
 }
 
 impl StreamingGuardrailSession {
-    fn new(session_id: String, organization_id: String, project_id: String, active_policies: Vec<String>) -> Self {
+    /// Serialize the session to JSON.
+    pub fn to_json(&self) -> Result<String, DiagnyxError> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Deserialize a session serialized with [`to_json`](Self::to_json).
+    pub fn from_json(json: &str) -> Result<Self, DiagnyxError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    fn new(
+        session_id: SessionId,
+        organization_id: String,
+        project_id: ProjectId,
+        active_policies: Vec<String>,
+    ) -> Self {
         Self {
             session_id,
             organization_id,
@@ -180,111 +451,72 @@ impl StreamingGuardrailSession {
             termination_reason: None,
             allowed: true,
             accumulated_text: String::new(),
+            input_context: Vec::new(),
+            sources: Vec::new(),
+            language: None,
+            next_token_index: 0,
+            backend_state: None,
         }
     }
 }
 
-/// Internal response structures
-#[derive(Debug, Deserialize)]
-struct StartSessionResponse {
-    #[serde(rename = "type")]
-    event_type: String,
-    #[serde(rename = "sessionId")]
-    session_id: Option<String>,
-    #[serde(rename = "activePolicies")]
-    active_policies: Option<Vec<String>>,
-    error: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct EvaluateResponse {
-    #[serde(rename = "type")]
-    event_type: String,
-    #[serde(rename = "tokenIndex")]
-    token_index: Option<i32>,
-    #[serde(rename = "totalTokens")]
-    total_tokens: Option<i32>,
-    allowed: Option<bool>,
-    reason: Option<String>,
-    #[serde(rename = "blockingViolation")]
-    blocking_violation: Option<ViolationData>,
-    #[serde(rename = "policyId")]
-    policy_id: Option<String>,
-    #[serde(rename = "policyName")]
-    policy_name: Option<String>,
-    #[serde(rename = "policyType")]
-    policy_type: Option<String>,
-    #[serde(rename = "violationType")]
-    violation_type: Option<String>,
-    message: Option<String>,
-    severity: Option<String>,
-    #[serde(rename = "enforcementLevel")]
-    enforcement_level: Option<String>,
-    details: Option<HashMap<String, serde_json::Value>>,
-    error: Option<String>,
+/// Model name of the calls reporting guardrail usage.
+///
+/// It has no built-in price. The cost of the evaluated text, reported as
+/// the input tokens of the call, is estimated by the tracking client with
+/// a custom price for this model, e.g.
+/// `CostCalculator::new().custom_price(GUARDRAIL_USAGE_MODEL, price)`.
+pub const GUARDRAIL_USAGE_MODEL: &str = "diagnyx-guardrails";
+
+/// Usage of guardrails, which has a cost of its own when every token of
+/// heavy traffic is evaluated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuardrailUsage {
+    pub sessions: u64,
+    /// Evaluation requests sent to the API; tokens are batched into one
+    /// request, and in-process backends send none.
+    pub evaluations: u64,
+    /// Bytes of text evaluated.
+    pub bytes: u64,
 }
 
-#[derive(Debug, Deserialize)]
-struct ViolationData {
-    #[serde(rename = "policyId")]
-    policy_id: Option<String>,
-    #[serde(rename = "policyName")]
-    policy_name: Option<String>,
-    #[serde(rename = "policyType")]
-    policy_type: Option<String>,
-    #[serde(rename = "violationType")]
-    violation_type: Option<String>,
-    message: Option<String>,
-    severity: Option<String>,
-    #[serde(rename = "enforcementLevel")]
-    enforcement_level: Option<String>,
-    details: Option<HashMap<String, serde_json::Value>>,
-}
+impl GuardrailUsage {
+    pub fn is_empty(&self) -> bool {
+        self.sessions == 0 && self.evaluations == 0
+    }
 
-impl ViolationData {
-    fn to_violation(&self) -> Violation {
-        let level = self.enforcement_level.as_ref()
-            .map(|s| match s.as_str() {
-                "blocking" => EnforcementLevel::Blocking,
-                "warning" => EnforcementLevel::Warning,
-                _ => EnforcementLevel::Advisory,
-            })
-            .unwrap_or(EnforcementLevel::Advisory);
-
-        Violation {
-            policy_id: self.policy_id.clone().unwrap_or_default(),
-            policy_name: self.policy_name.clone().unwrap_or_default(),
-            policy_type: self.policy_type.clone().unwrap_or_default(),
-            violation_type: self.violation_type.clone().unwrap_or_default(),
-            message: self.message.clone().unwrap_or_default(),
-            severity: self.severity.clone().unwrap_or_default(),
-            enforcement_level: level,
-            details: self.details.clone(),
+    /// A call reporting this usage, of type [`CallType::Guardrail`], with
+    /// the counts in its metadata and the evaluated text, at four bytes per
+    /// token, as its input tokens.
+    pub fn to_call(&self, project_id: &ProjectId) -> LLMCall {
+        let metadata = [
+            ("guardrail_sessions", self.sessions),
+            ("guardrail_evaluations", self.evaluations),
+            ("guardrail_bytes", self.bytes),
+        ]
+        .into_iter()
+        .map(|(key, count)| (key.to_string(), count.into()))
+        .collect();
+        LLMCall {
+            call_type: CallType::Guardrail,
+            ..LLMCall::builder()
+                .provider(Provider::Custom)
+                .model(GUARDRAIL_USAGE_MODEL)
+                .input_tokens(i32::try_from(self.bytes.div_ceil(4)).unwrap_or(i32::MAX))
+                .project_id(project_id.clone())
+                .metadata(metadata)
+                .build()
         }
     }
 }
 
-#[derive(Debug, Serialize)]
-struct StartSessionRequest {
-    #[serde(rename = "projectId")]
-    project_id: String,
-    #[serde(rename = "evaluateEveryNTokens")]
-    evaluate_every_n_tokens: i32,
-    #[serde(rename = "enableEarlyTermination")]
-    enable_early_termination: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    input: Option<String>,
-}
-
-#[derive(Debug, Serialize)]
-struct EvaluateTokenRequest {
-    #[serde(rename = "sessionId")]
-    session_id: String,
-    token: String,
-    #[serde(rename = "tokenIndex")]
-    token_index: i32,
-    #[serde(rename = "isLast")]
-    is_last: bool,
+/// Usage counted by a guardrail since it was last taken.
+#[derive(Default)]
+struct UsageCounter {
+    /// Sessions and bytes; evaluations are counted by the backend.
+    usage: GuardrailUsage,
+    /// Evaluation requests of the backend included in earlier takes.
+    evaluations_taken: u64,
 }
 
 /// Token-by-token streaming guardrail for LLM output validation.
@@ -293,108 +525,102 @@ struct EvaluateTokenRequest {
 /// guardrail policies with support for early termination on blocking violations.
 pub struct StreamingGuardrail {
     config: StreamingGuardrailConfig,
-    http_client: Client,
+    backend: Arc<dyn GuardrailBackend>,
     session: Arc<Mutex<Option<StreamingGuardrailSession>>>,
     token_index: Arc<Mutex<i32>>,
+    usage: Arc<std::sync::Mutex<UsageCounter>>,
+    events: EventBus,
 }
 
 impl StreamingGuardrail {
     /// Create a new streaming guardrail client.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
     pub fn new(config: StreamingGuardrailConfig) -> Self {
-        let http_client = Client::builder()
-            .timeout(Duration::from_secs(config.timeout_secs))
-            .build()
-            .expect("Failed to create HTTP client");
+        if config.disabled {
+            return Self::with_backend(config, LocalBackend::new());
+        }
+        let backend = RemoteBackend::new(config.clone());
+        Self::with_backend(config, backend)
+    }
 
+    /// Create a new streaming guardrail client, returning an error if the
+    /// configuration is invalid or the HTTP client cannot be created.
+    pub fn try_new(config: StreamingGuardrailConfig) -> Result<Self, DiagnyxError> {
+        if config.disabled {
+            return Ok(Self::with_backend(config, LocalBackend::new()));
+        }
+        let backend = RemoteBackend::try_new(config.clone())?;
+        Ok(Self::with_backend(config, backend))
+    }
+
+    /// Create a guardrail that evaluates tokens with a custom backend.
+    ///
+    /// The configuration still controls concurrency, timeouts and logging.
+    pub fn with_backend(
+        config: StreamingGuardrailConfig,
+        backend: impl GuardrailBackend + 'static,
+    ) -> Self {
         Self {
             config,
-            http_client,
+            backend: Arc::new(backend),
             session: Arc::new(Mutex::new(None)),
             token_index: Arc::new(Mutex::new(0)),
+            usage: Arc::default(),
+            events: EventBus::default(),
         }
     }
 
-    fn log(&self, message: &str) {
-        if self.config.debug {
-            println!("[DiagnyxGuardrails] {}", message);
+    /// Create a guardrail sending requests with `http_client`.
+    ///
+    /// The HTTP settings and timeout of `config` are not applied to it.
+    pub fn with_http_client(config: StreamingGuardrailConfig, http_client: Client) -> Self {
+        if config.disabled {
+            return Self::with_backend(config, LocalBackend::new());
         }
+        let backend = RemoteBackend::with_http_client(config.clone(), http_client);
+        Self::with_backend(config, backend)
     }
 
-    fn get_base_endpoint(&self) -> String {
-        format!(
-            "{}/api/v1/organizations/{}/guardrails",
-            self.config.base_url.trim_end_matches('/'),
-            self.config.organization_id
-        )
+    /// Subscribe to session events emitted by this guardrail.
+    pub fn subscribe_events(&self) -> tokio::sync::broadcast::Receiver<SdkEvent> {
+        self.events.subscribe()
     }
 
     /// Start a new streaming guardrail session.
-    pub async fn start_session(&self, input: Option<&str>) -> Result<StreamingGuardrailSession, DiagnyxError> {
-        let url = format!("{}/evaluate/stream/start", self.get_base_endpoint());
-
-        let request = StartSessionRequest {
-            project_id: self.config.project_id.clone(),
-            evaluate_every_n_tokens: self.config.evaluate_every_n_tokens,
-            enable_early_termination: self.config.enable_early_termination,
-            input: input.map(|s| s.to_string()),
-        };
-
-        self.log(&format!("Starting session at {}", url));
-
-        let response = self.http_client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .json(&request)
-            .send()
-            .await?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let message = response.text().await.unwrap_or_default();
-            return Err(DiagnyxError::ApiError {
-                status_code: status.as_u16(),
-                message,
-            });
-        }
-
-        let data: StartSessionResponse = response.json().await?;
-
-        if data.event_type == "session_started" {
-            let session_id = data.session_id.ok_or_else(|| {
-                DiagnyxError::ConfigError("Missing session_id in response".to_string())
-            })?;
+    pub async fn start_session(
+        &self,
+        input: Option<&str>,
+    ) -> Result<StreamingGuardrailSession, DiagnyxError> {
+        let project_id = ProjectId::new(self.config.project_id.clone())?;
+        let started = self.backend.start(input).await?;
 
-            let session = StreamingGuardrailSession::new(
-                session_id.clone(),
-                self.config.organization_id.clone(),
-                self.config.project_id.clone(),
-                data.active_policies.unwrap_or_default(),
-            );
+        let session = StreamingGuardrailSession::new(
+            started.session_id,
+            self.config.organization_id.clone(),
+            project_id,
+            started.active_policies,
+        );
 
-            *self.session.lock().await = Some(session.clone());
-            *self.token_index.lock().await = 0;
+        *self.session.lock().await = Some(session.clone());
+        *self.token_index.lock().await = 0;
+        self.usage.lock().unwrap().usage.sessions += 1;
 
-            self.log(&format!("Session started: {}", session_id));
-            Ok(session)
-        } else if data.event_type == "error" {
-            Err(DiagnyxError::ApiError {
-                status_code: 400,
-                message: data.error.unwrap_or("Unknown error".to_string()),
-            })
-        } else {
-            Err(DiagnyxError::ConfigError(format!(
-                "Unexpected response type: {}",
-                data.event_type
-            )))
-        }
+        Ok(session)
     }
 
     /// Evaluate a token against guardrail policies.
     ///
     /// Returns `Ok(Some(token))` if the token is allowed, `Ok(None)` if blocked without error,
     /// or `Err(ViolationError)` if a blocking violation occurred.
-    pub async fn evaluate(&self, token: &str, is_last: bool) -> Result<Option<String>, DiagnyxError> {
+    pub async fn evaluate(
+        &self,
+        token: &str,
+        is_last: bool,
+    ) -> Result<Option<String>, DiagnyxError> {
         self.evaluate_with_index(token, None, is_last).await
     }
 
@@ -405,6 +631,35 @@ impl StreamingGuardrail {
         token_idx: Option<i32>,
         is_last: bool,
     ) -> Result<Option<String>, DiagnyxError> {
+        let (session_id, index) = self.prepare_evaluation(token, token_idx).await?;
+        self.send_evaluation(session_id, token, index, is_last)
+            .await
+    }
+
+    /// Evaluate a token, failing with `DiagnyxError::Timeout` if the
+    /// evaluation takes longer than `timeout`.
+    ///
+    /// Overrides `evaluate_timeout` from the configuration for this call.
+    pub async fn evaluate_with_timeout(
+        &self,
+        token: &str,
+        is_last: bool,
+        timeout: Duration,
+    ) -> Result<Option<String>, DiagnyxError> {
+        let (session_id, index) = self.prepare_evaluation(token, None).await?;
+        with_timeout(
+            Some(timeout),
+            self.request_evaluation(session_id, token, index, is_last),
+        )
+        .await
+    }
+
+    /// Assign an index to a token and append it to the accumulated text.
+    async fn prepare_evaluation(
+        &self,
+        token: &str,
+        token_idx: Option<i32>,
+    ) -> Result<(SessionId, i32), DiagnyxError> {
         let session_id = {
             let session = self.session.lock().await;
             session
@@ -428,204 +683,270 @@ impl StreamingGuardrail {
         {
             let mut session = self.session.lock().await;
             if let Some(ref mut s) = *session {
+                s.next_token_index = s.next_token_index.max(index + 1);
                 s.accumulated_text.push_str(token);
+                s.language = detect_recent_language(&s.accumulated_text).map(String::from);
             }
         }
 
-        let url = format!("{}/evaluate/stream", self.get_base_endpoint());
-
-        let request = EvaluateTokenRequest {
-            session_id: session_id.clone(),
-            token: token.to_string(),
-            token_index: index,
-            is_last,
-        };
+        Ok((session_id, index))
+    }
 
-        let response = self.http_client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .header("Accept", "text/event-stream")
-            .json(&request)
-            .send()
-            .await?;
+    /// Evaluate a sequence of chunks with bounded concurrency.
+    ///
+    /// Up to `chunk_concurrency` evaluation requests are in flight at once.
+    /// Results are returned in the order of `chunks`; the last chunk is sent
+    /// with `is_last` set. A blocking violation on any chunk fails the call.
+    pub async fn evaluate_chunks(
+        &self,
+        chunks: Vec<String>,
+    ) -> Result<Vec<Option<String>>, DiagnyxError> {
+        use futures::{StreamExt, TryStreamExt};
 
-        let status = response.status();
-        if !status.is_success() {
-            let message = response.text().await.unwrap_or_default();
-            return Err(DiagnyxError::ApiError {
-                status_code: status.as_u16(),
-                message,
-            });
+        let mut prepared = Vec::with_capacity(chunks.len());
+        for chunk in &chunks {
+            prepared.push(self.prepare_evaluation(chunk, None).await?);
         }
 
-        let text = response.text().await?;
-        let mut result: Option<String> = None;
+        let count = chunks.len();
+        futures::stream::iter(chunks.into_iter().zip(prepared).enumerate())
+            .map(|(i, (chunk, (session_id, index)))| async move {
+                self.send_evaluation(session_id, &chunk, index, i + 1 == count)
+                    .await
+            })
+            .buffered(self.config.chunk_concurrency.max(1))
+            .try_collect()
+            .await
+    }
 
-        for line in text.lines() {
-            if !line.starts_with("data: ") {
-                continue;
-            }
+    /// Send an evaluation, bounded by the configured `evaluate_timeout`.
+    async fn send_evaluation(
+        &self,
+        session_id: SessionId,
+        token: &str,
+        index: i32,
+        is_last: bool,
+    ) -> Result<Option<String>, DiagnyxError> {
+        with_timeout(
+            self.config.evaluate_timeout,
+            self.request_evaluation(session_id, token, index, is_last),
+        )
+        .await
+    }
 
-            let json_str = &line[6..];
-            match serde_json::from_str::<EvaluateResponse>(json_str) {
-                Ok(data) => {
-                    match data.event_type.as_str() {
-                        "token_allowed" => {
-                            let mut session = self.session.lock().await;
-                            if let Some(ref mut s) = *session {
-                                s.tokens_processed = data.token_index.unwrap_or(0) + 1;
-                            }
-                            result = Some(token.to_string());
-                        }
-                        "violation_detected" => {
-                            let violation = self.parse_violation_from_response(&data);
-                            let mut session = self.session.lock().await;
-                            if let Some(ref mut s) = *session {
-                                s.violations.push(violation.clone());
-                                if violation.enforcement_level == EnforcementLevel::Blocking {
-                                    s.allowed = false;
-                                }
-                            }
-                        }
-                        "early_termination" => {
-                            let violation = data.blocking_violation
-                                .map(|v| v.to_violation())
-                                .unwrap_or_else(|| self.parse_violation_from_response(&data));
-
-                            let session = {
-                                let mut session_guard = self.session.lock().await;
-                                if let Some(ref mut s) = *session_guard {
-                                    s.terminated = true;
-                                    s.termination_reason = data.reason.clone();
-                                    s.allowed = false;
-                                }
-                                session_guard.clone()
-                            };
+    async fn request_evaluation(
+        &self,
+        session_id: SessionId,
+        token: &str,
+        index: i32,
+        is_last: bool,
+    ) -> Result<Option<String>, DiagnyxError> {
+        self.usage.lock().unwrap().usage.bytes += token.len() as u64;
+        let verdict = self.backend.evaluate(token, index, is_last).await?;
 
-                            return Err(DiagnyxError::ViolationError(Box::new(ViolationError {
-                                violation,
-                                session: session.unwrap(),
-                            })));
-                        }
-                        "session_complete" => {
-                            let mut session = self.session.lock().await;
-                            if let Some(ref mut s) = *session {
-                                s.tokens_processed = data.total_tokens.unwrap_or(0);
-                                s.allowed = data.allowed.unwrap_or(true);
-                            }
-                        }
-                        "error" => {
-                            self.log(&format!("Error: {}", data.error.unwrap_or_default()));
-                        }
-                        _ => {}
-                    }
+        let mut session = self.session.lock().await;
+        let allowed = self.apply_verdict(&mut session, session_id, verdict, Some(index))?;
+        Ok(allowed.then(|| token.to_string()))
+    }
+
+    /// Apply a backend's verdict to `session`, returning whether the
+    /// evaluated text may be released.
+    ///
+    /// `index` is the index of the evaluated token, if the verdict is for
+    /// one. Fails with a [`DiagnyxError::ViolationError`] if the verdict
+    /// ends the session.
+    fn apply_verdict(
+        &self,
+        session: &mut Option<StreamingGuardrailSession>,
+        session_id: SessionId,
+        verdict: Verdict,
+        index: Option<i32>,
+    ) -> Result<bool, DiagnyxError> {
+        if let Some(ref mut s) = *session {
+            match (verdict.completion, index) {
+                (Some(completion), _) => {
+                    s.tokens_processed = completion.total_tokens;
+                    s.allowed = completion.allowed;
                 }
-                Err(e) => {
-                    self.log(&format!("Failed to parse event: {}", e));
+                (None, Some(index)) if verdict.allowed => s.tokens_processed = index + 1,
+                _ => {}
+            }
+            for violation in &verdict.violations {
+                if violation.enforcement_level == EnforcementLevel::Blocking {
+                    s.allowed = false;
                 }
             }
+            s.violations.extend(verdict.violations);
         }
 
-        Ok(result)
+        if let Some(termination) = verdict.termination {
+            if let Some(ref mut s) = *session {
+                s.terminated = true;
+                s.termination_reason = termination.reason.clone();
+                s.allowed = false;
+            }
+            let session = session.clone();
+            self.events.emit(SdkEvent::SessionTerminated {
+                session_id,
+                reason: termination.reason.unwrap_or_default(),
+            });
+
+            return Err(DiagnyxError::ViolationError(Box::new(ViolationError {
+                violation: termination.violation,
+                session: session.unwrap(),
+            })));
+        }
+
+        Ok(verdict.allowed)
     }
 
     /// Complete the current session.
+    ///
+    /// Text still pending in the backend is evaluated first. Its violations
+    /// are added to the session, and a blocking violation that ends the
+    /// session is returned as a [`DiagnyxError::ViolationError`] holding the
+    /// completed session.
     pub async fn complete_session(&self) -> Result<StreamingGuardrailSession, DiagnyxError> {
-        let session_id = {
-            let session = self.session.lock().await;
-            session
-                .as_ref()
-                .ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))?
-                .session_id
-                .clone()
+        let Some(session_id) = self
+            .session
+            .lock()
+            .await
+            .as_ref()
+            .map(|s| s.session_id.clone())
+        else {
+            return Err(DiagnyxError::ConfigError("No active session".to_string()));
         };
 
-        let url = format!("{}/evaluate/stream/{}/complete", self.get_base_endpoint(), session_id);
-
-        self.log(&format!("Completing session: {}", session_id));
-
-        let response = self.http_client
-            .post(&url)
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .header("Accept", "text/event-stream")
-            .send()
-            .await?;
+        let verdict = self.backend.complete().await?;
+        let mut session = self.session.lock().await;
+        let applied = self.apply_verdict(&mut session, session_id, verdict, None);
+        let session = session.take();
+        applied?;
+        session.ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))
+    }
 
-        let status = response.status();
-        if !status.is_success() {
-            let message = response.text().await.unwrap_or_default();
-            return Err(DiagnyxError::ApiError {
-                status_code: status.as_u16(),
-                message,
-            });
+    /// Cancel the current session.
+    pub async fn cancel_session(&self) -> Result<bool, DiagnyxError> {
+        if self.session.lock().await.is_none() {
+            return Ok(false);
         }
 
-        let text = response.text().await?;
+        let cancelled = self.backend.cancel().await?;
+        *self.session.lock().await = None;
 
-        for line in text.lines() {
-            if !line.starts_with("data: ") {
-                continue;
-            }
+        Ok(cancelled)
+    }
 
-            if let Ok(data) = serde_json::from_str::<EvaluateResponse>(&line[6..]) {
-                if data.event_type == "session_complete" {
-                    let mut session = self.session.lock().await;
-                    if let Some(ref mut s) = *session {
-                        s.tokens_processed = data.total_tokens.unwrap_or(0);
-                        s.allowed = data.allowed.unwrap_or(true);
-                    }
-                }
-            }
+    /// Start a session whose output is checked against source documents.
+    ///
+    /// Citation-checking policies report claims the sources do not support
+    /// as `ungrounded_claim` violations; see [`Violation::ungrounded_claim`].
+    pub async fn start_session_with_sources(
+        &self,
+        input: Option<&str>,
+        sources: Vec<SourceDocument>,
+    ) -> Result<StreamingGuardrailSession, DiagnyxError> {
+        self.start_session(input).await?;
+        if let Err(e) = self.backend.add_sources(&sources).await {
+            let _ = self.cancel_session().await;
+            return Err(e);
         }
 
-        let session = self.session.lock().await.take();
-        session.ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))
+        let mut session = self.session.lock().await;
+        let s = session
+            .as_mut()
+            .ok_or_else(|| DiagnyxError::ConfigError("No active session".to_string()))?;
+        s.sources = sources;
+        Ok(s.clone())
     }
 
-    /// Cancel the current session.
-    pub async fn cancel_session(&self) -> Result<bool, DiagnyxError> {
-        let session_id = {
-            let session = self.session.lock().await;
-            match session.as_ref() {
-                Some(s) => s.session_id.clone(),
-                None => return Ok(false),
-            }
-        };
+    /// Add input context to the current session.
+    ///
+    /// Retrieved documents or tool output added mid-conversation are
+    /// considered by contextual policies, such as groundedness checks, for
+    /// the tokens evaluated afterwards.
+    pub async fn add_input_context(&self, text: &str) -> Result<(), DiagnyxError> {
+        if self.session.lock().await.is_none() {
+            return Err(DiagnyxError::ConfigError("No active session".to_string()));
+        }
 
-        let url = format!("{}/evaluate/stream/{}", self.get_base_endpoint(), session_id);
+        self.backend.add_context(text).await?;
 
-        self.log(&format!("Cancelling session: {}", session_id));
+        if let Some(ref mut s) = *self.session.lock().await {
+            s.input_context.push(text.to_string());
+        }
+        Ok(())
+    }
 
-        let response = self.http_client
-            .delete(&url)
-            .header("Authorization", format!("Bearer {}", self.config.api_key))
-            .send()
+    /// Get the current session, with the state of the backend needed to
+    /// [resume](Self::resume_session) it.
+    pub async fn get_session(&self) -> Option<StreamingGuardrailSession> {
+        let mut session = self.session.lock().await.clone()?;
+        session.backend_state = self.backend.session_state().await;
+        Some(session)
+    }
+
+    /// Continue a session saved with [`get_session`](Self::get_session),
+    /// for example by another process handling the next request of a
+    /// conversation. It replaces the current session, if any.
+    ///
+    /// Fails with a `ConfigError` if the backend cannot resume sessions.
+    pub async fn resume_session(
+        &self,
+        mut session: StreamingGuardrailSession,
+    ) -> Result<(), DiagnyxError> {
+        self.backend
+            .resume(&session, session.backend_state.as_ref())
             .await?;
+        session.backend_state = None;
+        *self.token_index.lock().await = session.next_token_index;
+        *self.session.lock().await = Some(session);
+        Ok(())
+    }
 
-        let status = response.status();
-        if !status.is_success() {
-            let message = response.text().await.unwrap_or_default();
-            return Err(DiagnyxError::ApiError {
-                status_code: status.as_u16(),
-                message,
-            });
-        }
+    /// Enable or disable debug logging for the current session only,
+    /// overriding `debug` from the configuration until the session ends.
+    ///
+    /// Log lines of a session are prefixed with its ID.
+    pub fn set_session_debug(&self, debug: bool) {
+        self.backend.set_session_debug(debug);
+    }
 
-        #[derive(Deserialize)]
-        struct CancelResponse {
-            cancelled: Option<bool>,
+    /// Usage of this guardrail since it was created or usage was last
+    /// taken.
+    pub fn usage(&self) -> GuardrailUsage {
+        let counter = self.usage.lock().unwrap();
+        GuardrailUsage {
+            evaluations: self
+                .backend
+                .evaluation_requests()
+                .saturating_sub(counter.evaluations_taken),
+            ..counter.usage
         }
+    }
 
-        let data: CancelResponse = response.json().await?;
-        *self.session.lock().await = None;
-
-        Ok(data.cancelled.unwrap_or(false))
+    /// Return the usage and reset it, e.g. to report it periodically.
+    pub fn take_usage(&self) -> GuardrailUsage {
+        let mut counter = self.usage.lock().unwrap();
+        let evaluations = self.backend.evaluation_requests();
+        let usage = GuardrailUsage {
+            evaluations: evaluations.saturating_sub(counter.evaluations_taken),
+            ..std::mem::take(&mut counter.usage)
+        };
+        counter.evaluations_taken = evaluations;
+        usage
     }
 
-    /// Get the current session.
-    pub async fn get_session(&self) -> Option<StreamingGuardrailSession> {
-        self.session.lock().await.clone()
+    /// Track the usage since it was last taken as a call with `client`, so
+    /// the cost of guardrails shows up in reports. Does nothing if there
+    /// was no usage.
+    pub async fn report_usage(&self, client: &DiagnyxClient) -> Result<(), DiagnyxError> {
+        let project_id = ProjectId::new(self.config.project_id.clone())?;
+        let usage = self.take_usage();
+        if !usage.is_empty() {
+            client.track(usage.to_call(&project_id)).await;
+        }
+        Ok(())
     }
 
     /// Check if there's an active session.
@@ -633,33 +954,50 @@ impl StreamingGuardrail {
         let session = self.session.lock().await;
         session.as_ref().map(|s| !s.terminated).unwrap_or(false)
     }
+}
 
-    fn parse_violation_from_response(&self, data: &EvaluateResponse) -> Violation {
-        let level = data.enforcement_level.as_ref()
-            .map(|s| match s.as_str() {
-                "blocking" => EnforcementLevel::Blocking,
-                "warning" => EnforcementLevel::Warning,
-                _ => EnforcementLevel::Advisory,
-            })
-            .unwrap_or(EnforcementLevel::Advisory);
-
-        Violation {
-            policy_id: data.policy_id.clone().unwrap_or_default(),
-            policy_name: data.policy_name.clone().unwrap_or_default(),
-            policy_type: data.policy_type.clone().unwrap_or_default(),
-            violation_type: data.violation_type.clone().unwrap_or_default(),
-            message: data.message.clone().unwrap_or_default(),
-            severity: data.severity.clone().unwrap_or_default(),
-            enforcement_level: level,
-            details: data.details.clone(),
+/// Releases items in sequence order as they complete out of order.
+struct ReorderBuffer<T> {
+    next: usize,
+    pending: BTreeMap<usize, T>,
+}
+
+impl<T> ReorderBuffer<T> {
+    fn new() -> Self {
+        Self {
+            next: 0,
+            pending: BTreeMap::new(),
         }
     }
+
+    /// Number of items held back waiting for an earlier item.
+    fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Insert the item with sequence number `seq` and return every item that
+    /// is now ready, in order.
+    fn push(&mut self, seq: usize, item: T) -> Vec<T> {
+        self.pending.insert(seq, item);
+
+        let mut ready = Vec::new();
+        while let Some(item) = self.pending.remove(&self.next) {
+            ready.push(item);
+            self.next += 1;
+        }
+        ready
+    }
 }
 
 /// Wrap an async token stream with guardrail protection.
 ///
 /// Returns a stream that yields filtered tokens. If a blocking violation
-/// is detected, the stream will end with an error.
+/// is detected, the stream will end with an error, including a violation
+/// found in text the backend was still batching when the input ended.
+///
+/// Up to `max_reorder_window` tokens are evaluated concurrently. Tokens are
+/// yielded in their original order regardless of which evaluation completes
+/// first, and no token after a blocking violation is yielded.
 pub async fn stream_with_guardrails<S>(
     config: StreamingGuardrailConfig,
     mut token_stream: S,
@@ -668,9 +1006,12 @@ pub async fn stream_with_guardrails<S>(
 where
     S: futures::Stream<Item = String> + Send + Unpin + 'static,
 {
+    use futures::future::Either;
+    use futures::stream::FuturesUnordered;
     use futures::StreamExt;
     use tokio::sync::mpsc;
 
+    let window = config.max_reorder_window.max(1);
     let guardrail = StreamingGuardrail::new(config);
     guardrail.start_session(input).await?;
 
@@ -679,25 +1020,89 @@ where
     let guardrail_clone = Arc::clone(&guardrail);
 
     tokio::spawn(async move {
-        while let Some(token) = token_stream.next().await {
-            match guardrail_clone.evaluate(&token, false).await {
-                Ok(Some(filtered)) => {
-                    if tx.send(Ok(filtered)).await.is_err() {
-                        break;
+        let mut in_flight = FuturesUnordered::new();
+        let mut reorder = ReorderBuffer::new();
+        let mut next_seq = 0;
+        let mut input_done = false;
+        // Set when the stream ended with an error or was dropped
+        let mut stopped = false;
+
+        'outer: loop {
+            let has_room = in_flight.len() + reorder.len() < window;
+
+            let completed = if !input_done && has_room {
+                let next_token = if in_flight.is_empty() {
+                    Either::Left(token_stream.next().await)
+                } else {
+                    match futures::future::select(token_stream.next(), in_flight.next()).await {
+                        Either::Left((token, _)) => Either::Left(token),
+                        Either::Right((done, _)) => Either::Right(done),
                     }
+                };
+
+                match next_token {
+                    Either::Left(Some(token)) => {
+                        let (session_id, index) =
+                            match guardrail_clone.prepare_evaluation(&token, None).await {
+                                Ok(prepared) => prepared,
+                                Err(e) => {
+                                    let _ = tx.send(Err(e)).await;
+                                    stopped = true;
+                                    break;
+                                }
+                            };
+                        let seq = next_seq;
+                        next_seq += 1;
+                        let guardrail = Arc::clone(&guardrail_clone);
+                        in_flight.push(async move {
+                            let result = guardrail
+                                .send_evaluation(session_id, &token, index, false)
+                                .await;
+                            (seq, result)
+                        });
+                        continue;
+                    }
+                    Either::Left(None) => {
+                        input_done = true;
+                        continue;
+                    }
+                    Either::Right(done) => done,
                 }
-                Ok(None) => {
-                    // Token blocked but not a terminating violation
-                }
-                Err(e) => {
-                    let _ = tx.send(Err(e)).await;
-                    break;
+            } else {
+                in_flight.next().await
+            };
+
+            let Some((seq, result)) = completed else {
+                break;
+            };
+
+            for result in reorder.push(seq, result) {
+                match result {
+                    Ok(Some(filtered)) => {
+                        if tx.send(Ok(filtered)).await.is_err() {
+                            stopped = true;
+                            break 'outer;
+                        }
+                    }
+                    Ok(None) => {
+                        // Token blocked but not a terminating violation
+                    }
+                    Err(e) => {
+                        let _ = tx.send(Err(e)).await;
+                        stopped = true;
+                        break 'outer;
+                    }
                 }
             }
         }
 
+        // The backend may still hold the end of the stream; a violation in
+        // it is the stream's last item
         if guardrail_clone.is_active().await {
-            let _ = guardrail_clone.complete_session().await;
+            let completed = guardrail_clone.complete_session().await;
+            if let (Err(e), false) = (completed, stopped) {
+                let _ = tx.send(Err(e)).await;
+            }
         }
     });
 
@@ -707,6 +1112,50 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn mock_guardrail(server: &MockServer) -> StreamingGuardrail {
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream/start",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "type": "session_started",
+                "sessionId": "sess-1",
+                "activePolicies": []
+            })))
+            .mount(server)
+            .await;
+
+        let guardrail = StreamingGuardrail::new(
+            StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+                .base_url(server.uri())
+                .evaluate_every_n_tokens(1),
+        );
+        guardrail.start_session(None).await.unwrap();
+        guardrail
+    }
+
+    #[tokio::test]
+    async fn test_disabled_guardrail_allows_without_requests() {
+        let guardrail = StreamingGuardrail::new(
+            StreamingGuardrailConfig::new("", "org-1", "proj-1")
+                .base_url("http://127.0.0.1:9")
+                .disabled(true),
+        );
+        guardrail.start_session(None).await.unwrap();
+        assert_eq!(
+            guardrail.evaluate("Hello", true).await.unwrap().as_deref(),
+            Some("Hello")
+        );
+        assert!(guardrail.complete_session().await.unwrap().allowed);
+    }
+
+    fn token_allowed() -> ResponseTemplate {
+        ResponseTemplate::new(200)
+            .set_body_string("data: {\"type\":\"token_allowed\",\"tokenIndex\":0}\n\n")
+    }
 
     #[test]
     fn test_config_builder() {
@@ -715,6 +1164,8 @@ mod tests {
             .timeout_secs(60)
             .evaluate_every_n_tokens(5)
             .enable_early_termination(false)
+            .chunk_concurrency(8)
+            .max_reorder_window(16)
             .debug(true);
 
         assert_eq!(config.api_key, "api-key");
@@ -724,6 +1175,8 @@ mod tests {
         assert_eq!(config.timeout_secs, 60);
         assert_eq!(config.evaluate_every_n_tokens, 5);
         assert!(!config.enable_early_termination);
+        assert_eq!(config.chunk_concurrency, 8);
+        assert_eq!(config.max_reorder_window, 16);
         assert!(config.debug);
     }
 
@@ -735,9 +1188,26 @@ mod tests {
         assert_eq!(config.timeout_secs, 30);
         assert_eq!(config.evaluate_every_n_tokens, 10);
         assert!(config.enable_early_termination);
+        assert_eq!(config.chunk_concurrency, 4);
+        assert_eq!(config.max_reorder_window, 1);
         assert!(!config.debug);
     }
 
+    #[test]
+    fn test_config_build_validates() {
+        assert!(StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+            .build()
+            .is_ok());
+        assert!(StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+            .evaluate_every_n_tokens(-1)
+            .build()
+            .is_err());
+        assert!(StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+            .base_url("not a url")
+            .build()
+            .is_err());
+    }
+
     #[test]
     fn test_enforcement_level_default() {
         assert_eq!(EnforcementLevel::default(), EnforcementLevel::Advisory);
@@ -754,12 +1224,14 @@ mod tests {
             severity: "high".to_string(),
             enforcement_level: EnforcementLevel::Blocking,
             details: None,
+            start_offset: None,
+            end_offset: None,
         };
 
         let session = StreamingGuardrailSession::new(
-            "sess-123".to_string(),
+            SessionId::from_static("sess-123"),
             "org-1".to_string(),
-            "proj-1".to_string(),
+            ProjectId::from_static("proj-1"),
             vec![],
         );
 
@@ -773,9 +1245,9 @@ mod tests {
     #[test]
     fn test_session_new() {
         let session = StreamingGuardrailSession::new(
-            "sess-123".to_string(),
+            SessionId::from_static("sess-123"),
             "org-1".to_string(),
-            "proj-1".to_string(),
+            ProjectId::from_static("proj-1"),
             vec!["policy-1".to_string()],
         );
 
@@ -789,4 +1261,514 @@ mod tests {
         assert!(session.allowed);
         assert!(session.accumulated_text.is_empty());
     }
+
+    #[tokio::test]
+    async fn test_early_termination_emits_event() {
+        let server = MockServer::start().await;
+        let guardrail = mock_guardrail(&server).await;
+        let mut events = guardrail.subscribe_events();
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(
+                    "data: {\"type\":\"early_termination\",\"reason\":\"pii\"}\n\n",
+                ),
+            )
+            .mount(&server)
+            .await;
+
+        assert!(guardrail.evaluate("secret", false).await.is_err());
+        assert_eq!(
+            events.recv().await.unwrap(),
+            SdkEvent::SessionTerminated {
+                session_id: SessionId::from_static("sess-1"),
+                reason: "pii".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_input_context() {
+        let server = MockServer::start().await;
+        let guardrail = mock_guardrail(&server).await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream/sess-1/context",
+            ))
+            .and(body_partial_json(
+                serde_json::json!({"sessionId": "sess-1", "context": "Doc A"}),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        guardrail.add_input_context("Doc A").await.unwrap();
+
+        let session = guardrail.get_session().await.unwrap();
+        assert_eq!(session.input_context, vec!["Doc A"]);
+    }
+
+    #[tokio::test]
+    async fn test_ungrounded_claim_from_sources() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream/start",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "type": "session_started",
+                "sessionId": "sess-1",
+                "activePolicies": ["citations"]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream/sess-1/sources",
+            ))
+            .and(body_partial_json(serde_json::json!({
+                "sources": [{"id": "doc-1", "content": "The sky is blue."}]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                "data: {}\n\n",
+                serde_json::json!({
+                    "type": "violation_detected",
+                    "violationType": "ungrounded_claim",
+                    "enforcementLevel": "warning",
+                    "startOffset": 11,
+                    "endOffset": 16,
+                    "details": {
+                        "claim": "The sky is green.",
+                        "startOffset": 0,
+                        "endOffset": 17,
+                        "nearestSourceId": "doc-1"
+                    }
+                })
+            )))
+            .mount(&server)
+            .await;
+
+        let guardrail = StreamingGuardrail::new(
+            StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+                .base_url(server.uri())
+                .evaluate_every_n_tokens(1),
+        );
+        let session = guardrail
+            .start_session_with_sources(
+                None,
+                vec![SourceDocument::new("doc-1", "The sky is blue.")],
+            )
+            .await
+            .unwrap();
+        assert_eq!(session.sources.len(), 1);
+
+        guardrail
+            .evaluate("The sky is green.", false)
+            .await
+            .unwrap();
+
+        let session = guardrail.get_session().await.unwrap();
+        assert_eq!(
+            session.violations[0].span(&session.accumulated_text),
+            Some("green")
+        );
+        let claim = session.violations[0].ungrounded_claim().unwrap();
+        assert_eq!(
+            claim,
+            UngroundedClaim {
+                claim: "The sky is green.".to_string(),
+                start_offset: 0,
+                end_offset: 17,
+                nearest_source_id: Some("doc-1".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_with_local_backend() {
+        use crate::guardrails::local::{BlockedTerms, LocalBackend};
+
+        let backend = LocalBackend::new().policy(BlockedTerms::new("secrets", ["password"]));
+        let guardrail = StreamingGuardrail::with_backend(
+            StreamingGuardrailConfig::new("api-key", "org-1", "proj-1"),
+            backend,
+        );
+        guardrail.start_session(None).await.unwrap();
+
+        assert_eq!(
+            guardrail.evaluate("the ", false).await.unwrap(),
+            Some("the ".to_string())
+        );
+        assert!(guardrail.evaluate("password", false).await.is_err());
+
+        let session = guardrail.get_session().await.unwrap();
+        assert!(session.terminated);
+        assert!(!session.allowed);
+
+        let usage = guardrail.take_usage();
+        assert_eq!(
+            usage,
+            GuardrailUsage {
+                sessions: 1,
+                evaluations: 0,
+                bytes: 12,
+            }
+        );
+        assert!(guardrail.usage().is_empty());
+        let call = usage.to_call(&ProjectId::from_static("proj-1"));
+        assert_eq!(call.call_type, CallType::Guardrail);
+        assert_eq!(call.model, GUARDRAIL_USAGE_MODEL);
+        assert_eq!(call.input_tokens, 3);
+        assert_eq!(call.metadata.unwrap()["guardrail_bytes"], 12);
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_with_timeout() {
+        let server = MockServer::start().await;
+        let guardrail = mock_guardrail(&server).await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .respond_with(token_allowed().set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+
+        let result = guardrail
+            .evaluate_with_timeout("Hello", false, Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(DiagnyxError::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_chunks_preserves_order() {
+        let server = MockServer::start().await;
+        let guardrail = mock_guardrail(&server).await;
+
+        // The first chunk responds last
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .and(body_partial_json(serde_json::json!({"token": "one "})))
+            .respond_with(token_allowed().set_delay(Duration::from_millis(100)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .and(body_partial_json(
+                serde_json::json!({"isLast": true, "tokenIndex": 2}),
+            ))
+            .respond_with(token_allowed())
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .respond_with(token_allowed())
+            .mount(&server)
+            .await;
+
+        let chunks = vec!["one ".to_string(), "two ".to_string(), "three".to_string()];
+        let results = guardrail.evaluate_chunks(chunks).await.unwrap();
+
+        assert_eq!(
+            results,
+            vec![
+                Some("one ".to_string()),
+                Some("two ".to_string()),
+                Some("three".to_string())
+            ]
+        );
+        let session = guardrail.get_session().await.unwrap();
+        assert_eq!(session.accumulated_text, "one two three");
+    }
+
+    #[tokio::test]
+    async fn test_evaluation_includes_detected_language() {
+        let server = MockServer::start().await;
+        let guardrail = mock_guardrail(&server).await;
+
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .and(body_partial_json(serde_json::json!({"language": "fr"})))
+            .respond_with(token_allowed())
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .respond_with(token_allowed())
+            .mount(&server)
+            .await;
+
+        guardrail.evaluate("Je pense ", false).await.unwrap();
+        assert_eq!(guardrail.get_session().await.unwrap().language, None);
+
+        guardrail
+            .evaluate("que vous avez raison.", false)
+            .await
+            .unwrap();
+        let session = guardrail.get_session().await.unwrap();
+        assert_eq!(session.language.as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn test_reorder_buffer_releases_in_order() {
+        let mut buffer = ReorderBuffer::new();
+
+        assert!(buffer.push(1, "b").is_empty());
+        assert!(buffer.push(2, "c").is_empty());
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.push(0, "a"), vec!["a", "b", "c"]);
+        assert_eq!(buffer.len(), 0);
+        assert_eq!(buffer.push(3, "d"), vec!["d"]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_with_guardrails_preserves_order() {
+        use futures::StreamExt;
+
+        let server = MockServer::start().await;
+        let _ = mock_guardrail(&server).await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .and(body_partial_json(serde_json::json!({"tokenIndex": 0})))
+            .respond_with(token_allowed().set_delay(Duration::from_millis(100)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .respond_with(token_allowed())
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream/sess-1/complete",
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let config = StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+            .base_url(server.uri())
+            .evaluate_every_n_tokens(1)
+            .max_reorder_window(3);
+        let tokens = futures::stream::iter(vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+        let stream = stream_with_guardrails(config, tokens, None).await.unwrap();
+
+        let output: Vec<String> = stream.map(|t| t.unwrap()).collect().await;
+        assert_eq!(output, vec!["a", "b", "c"]);
+    }
+
+    /// Mock a session whose final batch, marked `isLast`, gets `event`,
+    /// and whose completion reports nothing.
+    async fn mock_final_batch(server: &MockServer, event: serde_json::Value) {
+        let _ = mock_guardrail(server).await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .and(body_partial_json(serde_json::json!({"isLast": true})))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(format!("data: {}\n\n", event)),
+            )
+            .expect(1)
+            .mount(server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream/sess-1/complete",
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_complete_session_applies_violations_in_the_last_batch() {
+        let server = MockServer::start().await;
+        mock_final_batch(
+            &server,
+            serde_json::json!({
+                "type": "violation_detected",
+                "policyId": "pii",
+                "enforcementLevel": "blocking",
+            }),
+        )
+        .await;
+
+        let guardrail = StreamingGuardrail::new(
+            StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+                .base_url(server.uri())
+                .evaluate_every_n_tokens(10),
+        );
+        guardrail.start_session(None).await.unwrap();
+        guardrail.evaluate("my ssn", false).await.unwrap();
+
+        let session = guardrail.complete_session().await.unwrap();
+        assert!(!session.allowed);
+        assert_eq!(session.violations.len(), 1);
+        assert_eq!(session.violations[0].policy_id, "pii");
+    }
+
+    #[tokio::test]
+    async fn test_stream_with_guardrails_ends_with_termination_in_the_last_batch() {
+        use futures::StreamExt;
+
+        let server = MockServer::start().await;
+        mock_final_batch(
+            &server,
+            serde_json::json!({"type": "early_termination", "reason": "pii"}),
+        )
+        .await;
+
+        let config = StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+            .base_url(server.uri())
+            .evaluate_every_n_tokens(10);
+        let tokens = futures::stream::iter(vec!["my ".to_string(), "ssn".to_string()]);
+        let stream = stream_with_guardrails(config, tokens, None).await.unwrap();
+
+        let output: Vec<_> = stream.collect().await;
+        assert_eq!(output.len(), 3);
+        let Err(DiagnyxError::ViolationError(error)) = &output[2] else {
+            panic!("expected a violation, got {:?}", output[2]);
+        };
+        let error = error.downcast_ref::<ViolationError>().unwrap();
+        assert!(error.session.terminated);
+        assert_eq!(error.session.termination_reason.as_deref(), Some("pii"));
+    }
+
+    #[tokio::test]
+    async fn test_batches_tokens_per_evaluation() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream/start",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "type": "session_started",
+                "sessionId": "sess-1",
+                "activePolicies": []
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .respond_with(token_allowed())
+            .mount(&server)
+            .await;
+
+        let guardrail = StreamingGuardrail::new(
+            StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+                .base_url(server.uri())
+                .evaluate_every_n_tokens(3),
+        );
+        guardrail.start_session(None).await.unwrap();
+        for (token, is_last) in [
+            ("a", false),
+            ("b", false),
+            ("c", false),
+            ("d", false),
+            ("e", true),
+        ] {
+            assert_eq!(
+                guardrail.evaluate(token, is_last).await.unwrap().as_deref(),
+                Some(token)
+            );
+        }
+
+        let requests = server.received_requests().await.unwrap();
+        let batches: Vec<serde_json::Value> = requests
+            .iter()
+            .filter(|request| request.url.path().ends_with("/evaluate/stream"))
+            .map(|request| serde_json::from_slice(&request.body).unwrap())
+            .collect();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0]["token"], "abc");
+        assert_eq!(batches[0]["tokenIndex"], 0);
+        assert_eq!(batches[1]["token"], "de");
+        assert_eq!(batches[1]["isLast"], true);
+
+        let usage = guardrail.take_usage();
+        assert_eq!(usage.evaluations, 2);
+        assert_eq!(usage.bytes, 5);
+        assert_eq!(guardrail.usage().evaluations, 0);
+    }
+
+    #[tokio::test]
+    async fn test_resume_session_continues_pending_batch() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream/start",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "type": "session_started",
+                "sessionId": "sess-1",
+                "activePolicies": []
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/guardrails/evaluate/stream",
+            ))
+            .respond_with(token_allowed())
+            .expect(1)
+            .mount(&server)
+            .await;
+        let config = StreamingGuardrailConfig::new("api-key", "org-1", "proj-1")
+            .base_url(server.uri())
+            .evaluate_every_n_tokens(3);
+
+        let first = StreamingGuardrail::new(config.clone());
+        first.start_session(None).await.unwrap();
+        first.evaluate("a", false).await.unwrap();
+        first.evaluate("b", false).await.unwrap();
+        let saved = first.get_session().await.unwrap().to_json().unwrap();
+
+        let second = StreamingGuardrail::new(config);
+        second
+            .resume_session(StreamingGuardrailSession::from_json(&saved).unwrap())
+            .await
+            .unwrap();
+        second.evaluate("c", false).await.unwrap();
+        let session = second.get_session().await.unwrap();
+        assert_eq!(session.session_id, "sess-1");
+        assert_eq!(session.accumulated_text, "abc");
+        assert_eq!(session.next_token_index, 3);
+
+        let requests = server.received_requests().await.unwrap();
+        let batch: serde_json::Value = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(batch["sessionId"], "sess-1");
+        assert_eq!(batch["token"], "abc");
+        assert_eq!(batch["tokenIndex"], 0);
+    }
 }
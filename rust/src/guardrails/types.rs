@@ -1,7 +1,15 @@
 //! Type definitions for streaming guardrails.
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use crate::error::DiagnyxError;
+use crate::http::HttpConfig;
+use crate::ids::{ProjectId, SessionId};
+use crate::ratelimit::RateLimiter;
+use crate::retry::RetryPolicy;
+use crate::types::{default_base_url, disabled_by_env, Masked};
 
 /// Event types for streaming guardrail evaluation.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -16,43 +24,147 @@ pub enum StreamingEventType {
 }
 
 /// Enforcement level for guardrail policies.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum EnforcementLevel {
+    #[default]
     Advisory,
     Warning,
     Blocking,
 }
 
-impl Default for EnforcementLevel {
-    fn default() -> Self {
-        EnforcementLevel::Advisory
+/// Policy-specific details of a violation.
+///
+/// The details of a violation are typed by its `policy_type`. Details of
+/// other policies, or that do not match their policy's shape, are kept as
+/// [`Unknown`](Self::Unknown). Details deserialized on their own are matched
+/// to a variant by their fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ViolationDetails {
+    Pii(PiiDetails),
+    Regex(RegexDetails),
+    Toxicity(ToxicityDetails),
+    Unknown(serde_json::Value),
+}
+
+impl ViolationDetails {
+    /// Type the details as those of a policy of `policy_type`.
+    pub fn for_policy(self, policy_type: &str) -> Self {
+        fn parse<T: serde::de::DeserializeOwned>(
+            value: serde_json::Value,
+            variant: fn(T) -> ViolationDetails,
+        ) -> ViolationDetails {
+            match serde_json::from_value(value.clone()) {
+                Ok(details) => variant(details),
+                Err(_) => ViolationDetails::Unknown(value),
+            }
+        }
+
+        let value = match self {
+            Self::Unknown(value) => value,
+            typed => serde_json::to_value(&typed).unwrap_or_default(),
+        };
+        let policy_type = policy_type.to_ascii_lowercase();
+        if policy_type.contains("pii") {
+            parse(value, Self::Pii)
+        } else if policy_type.contains("regex") {
+            parse(value, Self::Regex)
+        } else if policy_type.contains("toxic") {
+            parse(value, Self::Toxicity)
+        } else {
+            Self::Unknown(value)
+        }
     }
 }
 
+/// Personal data found by a PII policy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PiiDetails {
+    /// Kind of data, e.g. `email` or `ssn`.
+    #[serde(alias = "entityType", alias = "pii_type")]
+    pub entity_type: String,
+    /// Where the data was found.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<TextSpan>,
+}
+
+/// Byte range in the evaluated text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextSpan {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Pattern matched by a regular expression policy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegexDetails {
+    pub pattern: String,
+}
+
+/// Score of a toxicity policy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToxicityDetails {
+    /// Toxicity from 0.0 to 1.0.
+    #[serde(alias = "toxicity_score", alias = "toxicityScore")]
+    pub score: f64,
+}
+
 /// Represents a guardrail violation.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+// Deserialized through the impl below, which types the details
+#[serde(remote = "Self")]
 pub struct GuardrailViolation {
     pub policy_id: String,
     pub policy_type: String,
     pub message: String,
     pub severity: EnforcementLevel,
-    pub details: Option<HashMap<String, serde_json::Value>>,
+    pub details: Option<ViolationDetails>,
+    /// Byte offset into the accumulated text where the offending span starts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_offset: Option<usize>,
+    /// Byte offset into the accumulated text just past the offending span.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_offset: Option<usize>,
+}
+
+impl GuardrailViolation {
+    /// The part of `text` that triggered the violation, if the policy
+    /// reported offsets that fall within it.
+    pub fn span<'a>(&self, text: &'a str) -> Option<&'a str> {
+        text.get(self.start_offset?..self.end_offset?)
+    }
+}
+
+impl Serialize for GuardrailViolation {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        GuardrailViolation::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for GuardrailViolation {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut violation = GuardrailViolation::deserialize(deserializer)?;
+        violation.details = violation
+            .details
+            .map(|details| details.for_policy(&violation.policy_type));
+        Ok(violation)
+    }
 }
 
 /// Session started event data.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionStartedData {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub organization_id: String,
-    pub project_id: String,
+    pub project_id: ProjectId,
     pub active_policies: Vec<String>,
 }
 
 /// Token allowed event data.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TokenAllowedData {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub token: String,
     pub tokens_processed: i32,
 }
@@ -60,7 +172,7 @@ pub struct TokenAllowedData {
 /// Violation detected event data.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ViolationDetectedData {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub violation: GuardrailViolation,
     pub tokens_processed: i32,
 }
@@ -68,7 +180,7 @@ pub struct ViolationDetectedData {
 /// Early termination event data.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EarlyTerminationData {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub reason: String,
     pub violation: GuardrailViolation,
     pub tokens_processed: i32,
@@ -77,7 +189,7 @@ pub struct EarlyTerminationData {
 /// Session complete event data.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionCompleteData {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub total_tokens: i32,
     pub violations: Vec<GuardrailViolation>,
     pub allowed: bool,
@@ -86,7 +198,7 @@ pub struct SessionCompleteData {
 /// Error event data.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ErrorData {
-    pub session_id: Option<String>,
+    pub session_id: Option<SessionId>,
     pub error: String,
     pub code: Option<String>,
 }
@@ -116,14 +228,14 @@ impl StreamingEvent {
     }
 
     /// Get the session ID if available.
-    pub fn session_id(&self) -> Option<&str> {
+    pub fn session_id(&self) -> Option<&SessionId> {
         match self {
             StreamingEvent::SessionStarted(data) => Some(&data.session_id),
             StreamingEvent::TokenAllowed(data) => Some(&data.session_id),
             StreamingEvent::ViolationDetected(data) => Some(&data.session_id),
             StreamingEvent::EarlyTermination(data) => Some(&data.session_id),
             StreamingEvent::SessionComplete(data) => Some(&data.session_id),
-            StreamingEvent::Error(data) => data.session_id.as_deref(),
+            StreamingEvent::Error(data) => data.session_id.as_ref(),
         }
     }
 
@@ -167,11 +279,14 @@ impl StreamingEvent {
 }
 
 /// Guardrail session state.
-#[derive(Debug, Clone)]
+///
+/// Sessions serialize to JSON with [`to_json`](Self::to_json), so stateless
+/// services can keep them in a store such as Redis between requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GuardrailSession {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub organization_id: String,
-    pub project_id: String,
+    pub project_id: ProjectId,
     pub active_policies: Vec<String>,
     pub tokens_processed: i32,
     pub violations: Vec<GuardrailViolation>,
@@ -196,6 +311,16 @@ impl GuardrailSession {
         }
     }
 
+    /// Serialize the session to JSON.
+    pub fn to_json(&self) -> Result<String, DiagnyxError> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Deserialize a session serialized with [`to_json`](Self::to_json).
+    pub fn from_json(json: &str) -> Result<Self, DiagnyxError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
     /// Update session state from a streaming event.
     pub fn update(&mut self, event: &StreamingEvent) {
         match event {
@@ -224,7 +349,7 @@ impl GuardrailSession {
 }
 
 /// Configuration for the streaming guardrails client.
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct StreamingGuardrailsConfig {
     pub api_key: String,
     pub organization_id: String,
@@ -233,7 +358,39 @@ pub struct StreamingGuardrailsConfig {
     pub timeout_secs: u64,
     pub evaluate_every_n_tokens: i32,
     pub enable_early_termination: bool,
+    pub retry_policy: RetryPolicy,
+    /// Spaces out requests to the API, retries included. Default: None (no
+    /// limit)
+    pub rate_limiter: Option<RateLimiter>,
+    pub evaluate_timeout: Option<Duration>,
+    pub complete_timeout: Option<Duration>,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
     pub debug: bool,
+    /// Allow every token without a request. Default: whether
+    /// `DIAGNYX_DISABLED` is `1` or `true`
+    pub disabled: bool,
+}
+
+impl fmt::Debug for StreamingGuardrailsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamingGuardrailsConfig")
+            .field("api_key", &Masked(&self.api_key))
+            .field("organization_id", &self.organization_id)
+            .field("project_id", &self.project_id)
+            .field("base_url", &self.base_url)
+            .field("timeout_secs", &self.timeout_secs)
+            .field("evaluate_every_n_tokens", &self.evaluate_every_n_tokens)
+            .field("enable_early_termination", &self.enable_early_termination)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("evaluate_timeout", &self.evaluate_timeout)
+            .field("complete_timeout", &self.complete_timeout)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .field("disabled", &self.disabled)
+            .finish()
+    }
 }
 
 impl StreamingGuardrailsConfig {
@@ -243,15 +400,22 @@ impl StreamingGuardrailsConfig {
         organization_id: impl Into<String>,
         project_id: impl Into<String>,
     ) -> Self {
+        let api_key = api_key.into();
         Self {
-            api_key: api_key.into(),
+            base_url: default_base_url(&api_key),
+            api_key,
             organization_id: organization_id.into(),
             project_id: project_id.into(),
-            base_url: "https://api.diagnyx.io".to_string(),
             timeout_secs: 30,
             evaluate_every_n_tokens: 10,
             enable_early_termination: true,
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: None,
+            evaluate_timeout: None,
+            complete_timeout: None,
+            http: HttpConfig::default(),
             debug: false,
+            disabled: disabled_by_env(),
         }
     }
 
@@ -279,11 +443,107 @@ impl StreamingGuardrailsConfig {
         self
     }
 
+    /// Set the retry policy. Only idempotent requests, such as cancelling a
+    /// session, are retried unless the policy opts in.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Space out requests with `limiter`, e.g. the one of the tracking
+    /// client's [`DiagnyxConfig`](crate::DiagnyxConfig), so that the clients
+    /// sharing it stay within one rate together.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Set a time limit for each token evaluation, overriding the HTTP
+    /// client's timeout for interactive use. Evaluations that take longer
+    /// fail with `DiagnyxError::Timeout`.
+    pub fn evaluate_timeout(mut self, timeout: Duration) -> Self {
+        self.evaluate_timeout = Some(timeout);
+        self
+    }
+
+    /// Set a time limit for completing the session, including reading the
+    /// final evaluation as it streams in. Overrides the HTTP client's
+    /// timeout, so long final evaluations are not cut short; completions
+    /// that take longer fail with `DiagnyxError::Timeout`.
+    pub fn complete_timeout(mut self, timeout: Duration) -> Self {
+        self.complete_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the proxy and TLS settings of the HTTP client.
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
     /// Enable or disable debug logging.
     pub fn debug(mut self, debug: bool) -> Self {
         self.debug = debug;
         self
     }
+
+    /// Turn evaluations into no-ops allowing every token, e.g. in test
+    /// suites and local development.
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = disabled;
+        self
+    }
+
+    /// Validate the configuration.
+    pub fn validate(&self) -> Result<(), DiagnyxError> {
+        validate_settings(
+            &self.organization_id,
+            &self.project_id,
+            &self.base_url,
+            self.evaluate_every_n_tokens,
+        )
+    }
+
+    /// Validate and return the configuration.
+    pub fn build(self) -> Result<Self, DiagnyxError> {
+        self.validate()?;
+        Ok(self)
+    }
+}
+
+/// Checks shared by the guardrail configurations.
+pub(crate) fn validate_settings(
+    organization_id: &str,
+    project_id: &str,
+    base_url: &str,
+    evaluate_every_n_tokens: i32,
+) -> Result<(), DiagnyxError> {
+    if organization_id.trim().is_empty() {
+        return Err(DiagnyxError::ConfigError(
+            "organization_id must not be empty".to_string(),
+        ));
+    }
+
+    ProjectId::new(project_id)?;
+
+    if evaluate_every_n_tokens <= 0 {
+        return Err(DiagnyxError::ConfigError(format!(
+            "evaluate_every_n_tokens must be greater than 0, got {}",
+            evaluate_every_n_tokens
+        )));
+    }
+
+    let url = reqwest::Url::parse(base_url).map_err(|e| {
+        DiagnyxError::ConfigError(format!("Invalid base_url {:?}: {}", base_url, e))
+    })?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(DiagnyxError::ConfigError(format!(
+            "base_url must be an http(s) URL with a host, got {:?}",
+            base_url
+        )));
+    }
+
+    Ok(())
 }
 
 /// Request body for starting a streaming session.
@@ -299,20 +559,20 @@ pub(crate) struct StartSessionRequest {
 /// Request body for evaluating a token.
 #[derive(Debug, Serialize)]
 pub(crate) struct EvaluateTokenRequest {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub token: String,
 }
 
 /// Request body for completing a session.
 #[derive(Debug, Serialize)]
 pub(crate) struct CompleteSessionRequest {
-    pub session_id: String,
+    pub session_id: SessionId,
 }
 
 /// Request body for cancelling a session.
 #[derive(Debug, Serialize)]
 pub(crate) struct CancelSessionRequest {
-    pub session_id: String,
+    pub session_id: SessionId,
     pub reason: Option<String>,
 }
 
@@ -320,6 +580,36 @@ pub(crate) struct CancelSessionRequest {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_session_json_round_trip() {
+        let session = GuardrailSession {
+            session_id: SessionId::from_static("sess-1"),
+            organization_id: "org-1".to_string(),
+            project_id: ProjectId::from_static("proj-1"),
+            active_policies: vec!["pii".to_string()],
+            tokens_processed: 42,
+            violations: vec![GuardrailViolation {
+                policy_id: "pii".to_string(),
+                policy_type: "pii".to_string(),
+                message: "PII found".to_string(),
+                severity: EnforcementLevel::Blocking,
+                details: None,
+                start_offset: Some(3),
+                end_offset: Some(9),
+            }],
+            terminated: true,
+            termination_reason: Some("PII detected".to_string()),
+            allowed: false,
+        };
+
+        let restored = GuardrailSession::from_json(&session.to_json().unwrap()).unwrap();
+        assert_eq!(restored.session_id, session.session_id);
+        assert_eq!(restored.tokens_processed, 42);
+        assert_eq!(restored.violations[0].end_offset, Some(9));
+        assert_eq!(restored.termination_reason, session.termination_reason);
+        assert!(GuardrailSession::from_json("{}").is_err());
+    }
+
     #[test]
     fn test_streaming_event_type_serialization() {
         let event_type = StreamingEventType::SessionStarted;
@@ -390,12 +680,77 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_violation_details_are_typed() {
+        let details =
+            |json: serde_json::Value| -> ViolationDetails { serde_json::from_value(json).unwrap() };
+
+        assert_eq!(
+            details(serde_json::json!({"entity_type": "email", "span": {"start": 3, "end": 9}})),
+            ViolationDetails::Pii(PiiDetails {
+                entity_type: "email".to_string(),
+                span: Some(TextSpan { start: 3, end: 9 }),
+            })
+        );
+        assert_eq!(
+            details(serde_json::json!({"pattern": "sk-[a-z]+"})),
+            ViolationDetails::Regex(RegexDetails {
+                pattern: "sk-[a-z]+".to_string()
+            })
+        );
+        assert_eq!(
+            details(serde_json::json!({"score": 0.92})),
+            ViolationDetails::Toxicity(ToxicityDetails { score: 0.92 })
+        );
+        assert_eq!(
+            details(serde_json::json!({"claim": "The sky is green."})),
+            ViolationDetails::Unknown(serde_json::json!({"claim": "The sky is green."}))
+        );
+    }
+
+    #[test]
+    fn test_violation_details_are_typed_by_policy_type() {
+        let violation = |policy_type: &str, details: serde_json::Value| -> GuardrailViolation {
+            serde_json::from_value(serde_json::json!({
+                "policy_id": "pol-1",
+                "policy_type": policy_type,
+                "message": "Violation",
+                "severity": "warning",
+                "details": details,
+            }))
+            .unwrap()
+        };
+
+        // A custom policy's score is not a toxicity score
+        let custom = violation("sentiment", serde_json::json!({"score": 0.1}));
+        assert_eq!(
+            custom.details,
+            Some(ViolationDetails::Unknown(serde_json::json!({"score": 0.1})))
+        );
+        let toxicity = violation("toxicity", serde_json::json!({"score": 0.92}));
+        assert_eq!(
+            toxicity.details,
+            Some(ViolationDetails::Toxicity(ToxicityDetails { score: 0.92 }))
+        );
+        // Malformed details of a known policy are kept as they are
+        let pii = violation("pii_detection", serde_json::json!({"pattern": "x"}));
+        assert_eq!(
+            pii.details,
+            Some(ViolationDetails::Unknown(
+                serde_json::json!({"pattern": "x"})
+            ))
+        );
+        let round_trip: GuardrailViolation =
+            serde_json::from_value(serde_json::to_value(&toxicity).unwrap()).unwrap();
+        assert_eq!(round_trip.details, toxicity.details);
+    }
+
     #[test]
     fn test_streaming_event_event_type() {
         let event = StreamingEvent::SessionStarted(SessionStartedData {
-            session_id: "sess-123".to_string(),
+            session_id: SessionId::from_static("sess-123"),
             organization_id: "org-1".to_string(),
-            project_id: "proj-1".to_string(),
+            project_id: ProjectId::from_static("proj-1"),
             active_policies: vec![],
         });
         assert_eq!(event.event_type(), StreamingEventType::SessionStarted);
@@ -404,19 +759,19 @@ mod tests {
     #[test]
     fn test_streaming_event_session_id() {
         let event = StreamingEvent::TokenAllowed(TokenAllowedData {
-            session_id: "sess-123".to_string(),
+            session_id: SessionId::from_static("sess-123"),
             token: "test".to_string(),
             tokens_processed: 1,
         });
-        assert_eq!(event.session_id(), Some("sess-123"));
+        assert_eq!(event.session_id().map(SessionId::as_str), Some("sess-123"));
     }
 
     #[test]
     fn test_guardrail_session_new() {
         let data = SessionStartedData {
-            session_id: "sess-123".to_string(),
+            session_id: SessionId::from_static("sess-123"),
             organization_id: "org-1".to_string(),
-            project_id: "proj-1".to_string(),
+            project_id: ProjectId::from_static("proj-1"),
             active_policies: vec!["policy-1".to_string()],
         };
         let session = GuardrailSession::new(data);
@@ -431,15 +786,15 @@ mod tests {
     #[test]
     fn test_guardrail_session_update() {
         let data = SessionStartedData {
-            session_id: "sess-123".to_string(),
+            session_id: SessionId::from_static("sess-123"),
             organization_id: "org-1".to_string(),
-            project_id: "proj-1".to_string(),
+            project_id: ProjectId::from_static("proj-1"),
             active_policies: vec![],
         };
         let mut session = GuardrailSession::new(data);
 
         let event = StreamingEvent::TokenAllowed(TokenAllowedData {
-            session_id: "sess-123".to_string(),
+            session_id: SessionId::from_static("sess-123"),
             token: "hello".to_string(),
             tokens_processed: 5,
         });
@@ -452,9 +807,11 @@ mod tests {
             message: "PII detected".to_string(),
             severity: EnforcementLevel::Warning,
             details: None,
+            start_offset: None,
+            end_offset: None,
         };
         let event = StreamingEvent::ViolationDetected(ViolationDetectedData {
-            session_id: "sess-123".to_string(),
+            session_id: SessionId::from_static("sess-123"),
             violation,
             tokens_processed: 10,
         });
@@ -463,6 +820,24 @@ mod tests {
         assert_eq!(session.violations.len(), 1);
     }
 
+    #[test]
+    fn test_violation_span() {
+        let violation = GuardrailViolation {
+            policy_id: "pol-1".to_string(),
+            policy_type: "pii_detection".to_string(),
+            message: "PII detected".to_string(),
+            severity: EnforcementLevel::Warning,
+            details: None,
+            start_offset: Some(6),
+            end_offset: Some(22),
+        };
+        assert_eq!(
+            violation.span("Email jane@example.com now"),
+            Some("jane@example.com")
+        );
+        assert_eq!(violation.span("Email"), None);
+    }
+
     #[test]
     fn test_streaming_guardrails_config_defaults() {
         let config = StreamingGuardrailsConfig::new("api-key", "org-1", "proj-1");
@@ -492,4 +867,27 @@ mod tests {
         assert!(!config.enable_early_termination);
         assert!(config.debug);
     }
+
+    #[test]
+    fn test_streaming_guardrails_config_build() {
+        assert!(StreamingGuardrailsConfig::new("api-key", "org-1", "proj-1")
+            .build()
+            .is_ok());
+
+        let invalid = [
+            StreamingGuardrailsConfig::new("api-key", "", "proj-1"),
+            StreamingGuardrailsConfig::new("api-key", "org-1", ""),
+            StreamingGuardrailsConfig::new("api-key", "org-1", "proj-1").evaluate_every_n_tokens(0),
+            StreamingGuardrailsConfig::new("api-key", "org-1", "proj-1").base_url("api.diagnyx.io"),
+            StreamingGuardrailsConfig::new("api-key", "org-1", "proj-1")
+                .base_url("ftp://api.diagnyx.io"),
+        ];
+        for config in invalid {
+            assert!(
+                matches!(config.clone().build(), Err(DiagnyxError::ConfigError(_))),
+                "expected {:?} to be rejected",
+                config
+            );
+        }
+    }
 }
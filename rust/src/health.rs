@@ -0,0 +1,70 @@
+//! Failure tracking for user-provided hooks.
+//!
+//! Enrichers and filters are user code running on the ingestion path. Each
+//! one gets a [`HookHealth`] counting its consecutive failures, and once the
+//! count reaches the chain's limit the hook is skipped for the rest of the
+//! client's life.
+
+use std::any::Any;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Consecutive failures after which a hook is disabled by default.
+pub(crate) const DEFAULT_MAX_FAILURES: u32 = 5;
+
+/// Consecutive failures of one hook, shared by clones of its chain.
+#[derive(Debug, Default)]
+pub(crate) struct HookHealth {
+    failures: AtomicU32,
+    disabled: AtomicBool,
+}
+
+impl HookHealth {
+    /// Whether the hook has been disabled.
+    pub(crate) fn is_disabled(&self) -> bool {
+        self.disabled.load(Ordering::Relaxed)
+    }
+
+    /// Record a successful run, resetting the failure count.
+    pub(crate) fn succeeded(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Record a failed run, returning the failure count if it disabled the
+    /// hook. A limit of 0 never disables it.
+    pub(crate) fn failed(&self, max_failures: u32) -> Option<u32> {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if max_failures == 0 || failures < max_failures {
+            return None;
+        }
+        (!self.disabled.swap(true, Ordering::Relaxed)).then_some(failures)
+    }
+}
+
+/// Message of a caught panic payload.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        format!("panicked: {}", message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        format!("panicked: {}", message)
+    } else {
+        "panicked".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_disabled_after_consecutive_failures() {
+        let health = HookHealth::default();
+        assert_eq!(health.failed(3), None);
+        health.succeeded();
+        assert_eq!(health.failed(3), None);
+        assert_eq!(health.failed(3), None);
+        assert!(!health.is_disabled());
+        assert_eq!(health.failed(3), Some(3));
+        assert!(health.is_disabled());
+        assert_eq!(health.failed(3), None);
+    }
+}
@@ -0,0 +1,67 @@
+//! Periodic registration of the SDK instance with the API.
+//!
+//! Heartbeats tell the API which services are emitting calls, from which
+//! hosts and with which SDK version, so a service that stops reporting can
+//! be noticed even when it has no calls to send.
+
+use reqwest::{Client, Method};
+use serde::Serialize;
+
+use crate::client::random_bits;
+use crate::error::DiagnyxError;
+use crate::retry::send_with_retry;
+use crate::types::DiagnyxConfig;
+
+/// Body of a heartbeat; the same for every heartbeat of a client.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Heartbeat {
+    instance_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service_name: Option<String>,
+    sdk: &'static str,
+    sdk_version: &'static str,
+    host: String,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(config: &DiagnyxConfig) -> Self {
+        Self {
+            instance_id: format!("{:016x}", random_bits()),
+            service_name: config.service_name.clone(),
+            sdk: "rust",
+            sdk_version: env!("CARGO_PKG_VERSION"),
+            host: host_name(),
+        }
+    }
+
+    pub(crate) async fn send(
+        &self,
+        http_client: &Client,
+        config: &DiagnyxConfig,
+    ) -> Result<(), DiagnyxError> {
+        let url = format!("{}/api/v1/sdk/heartbeat", config.base_url);
+        send_with_retry(
+            &config.retry_policy,
+            config.rate_limiter.as_ref(),
+            Method::POST,
+            |method| {
+                http_client
+                    .request(method, &url)
+                    .header("Authorization", format!("Bearer {}", config.api_key))
+                    .json(self)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+fn host_name() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,209 @@
+//! Host resource context.
+//!
+//! With [`capture_host_context`](crate::DiagnyxConfig::capture_host_context)
+//! enabled, tracked calls carry the resources of the host or container they
+//! were made from, so their cost can be compared across capacity: the CPUs
+//! available to the process, its memory limit, and the cloud instance type
+//! when an AWS, GCP or Azure instance metadata service answers. The host is
+//! inspected once when the client is created; the instance type is looked up
+//! in the background, so calls tracked in the first moments may lack it.
+
+use reqwest::Client;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::types::LLMCall;
+
+/// Metadata key of the number of CPUs available to the process.
+pub const CPU_COUNT_KEY: &str = "host.cpu_count";
+/// Metadata key of the memory limit of the container, or host, in bytes.
+pub const MEMORY_LIMIT_KEY: &str = "host.memory_limit_bytes";
+/// Metadata key of the cloud instance type, e.g. `m5.large`.
+pub const INSTANCE_TYPE_KEY: &str = "host.instance_type";
+
+/// Address of the AWS and Azure instance metadata services.
+const IMDS_URL: &str = "http://169.254.169.254";
+/// Address of the GCP metadata server.
+const GCP_METADATA_URL: &str = "http://metadata.google.internal";
+/// Time allowed for each metadata request; off-cloud hosts do not answer.
+const METADATA_TIMEOUT: Duration = Duration::from_millis(500);
+/// cgroup v1 reports no memory limit as a value near `i64::MAX`.
+const UNLIMITED_MEMORY: u64 = 1 << 60;
+
+/// Resources of the host the client runs on.
+#[derive(Debug, Default)]
+pub(crate) struct HostContext {
+    cpu_count: Option<usize>,
+    memory_limit_bytes: Option<u64>,
+    instance_type: OnceLock<String>,
+}
+
+impl HostContext {
+    /// Inspect the current host, except for its instance type.
+    pub(crate) fn detect() -> Self {
+        Self {
+            cpu_count: std::thread::available_parallelism().ok().map(|n| n.get()),
+            memory_limit_bytes: memory_limit(),
+            instance_type: OnceLock::new(),
+        }
+    }
+
+    /// Add the host resources to `call`, keeping values it already has.
+    pub(crate) fn apply(&self, call: &mut LLMCall) {
+        let metadata = call.metadata.get_or_insert_with(Default::default);
+        let mut insert = |key: &str, value: serde_json::Value| {
+            metadata.entry(key.to_string()).or_insert(value);
+        };
+        if let Some(cpus) = self.cpu_count {
+            insert(CPU_COUNT_KEY, cpus.into());
+        }
+        if let Some(bytes) = self.memory_limit_bytes {
+            insert(MEMORY_LIMIT_KEY, bytes.into());
+        }
+        if let Some(instance_type) = self.instance_type.get() {
+            insert(INSTANCE_TYPE_KEY, instance_type.as_str().into());
+        }
+    }
+
+    /// Ask the cloud metadata services for the instance type.
+    ///
+    /// The services are link-local, so they are asked directly rather than
+    /// through the proxy of the client's HTTP settings or environment.
+    pub(crate) async fn lookup_instance_type(&self) {
+        let Ok(http_client) = Client::builder().no_proxy().build() else {
+            return;
+        };
+        self.lookup_instance_type_at(&http_client, IMDS_URL, GCP_METADATA_URL)
+            .await;
+    }
+
+    async fn lookup_instance_type_at(&self, http_client: &Client, imds: &str, gcp: &str) {
+        let (aws, gcp, azure) = tokio::join!(
+            aws_instance_type(http_client, imds),
+            gcp_machine_type(http_client, gcp),
+            azure_vm_size(http_client, imds),
+        );
+        if let Some(instance_type) = aws.or(gcp).or(azure) {
+            let _ = self.instance_type.set(instance_type);
+        }
+    }
+}
+
+/// The memory limit of the process's cgroup, or else the host's memory.
+fn memory_limit() -> Option<u64> {
+    [
+        "/sys/fs/cgroup/memory.max",
+        "/sys/fs/cgroup/memory/memory.limit_in_bytes",
+    ]
+    .iter()
+    .find_map(|path| parse_memory_limit(&std::fs::read_to_string(path).ok()?))
+    .or_else(|| parse_mem_total(&std::fs::read_to_string("/proc/meminfo").ok()?))
+}
+
+/// A cgroup memory limit, `None` if unlimited.
+fn parse_memory_limit(contents: &str) -> Option<u64> {
+    let limit: u64 = contents.trim().parse().ok()?;
+    (limit < UNLIMITED_MEMORY).then_some(limit)
+}
+
+/// `MemTotal` of `/proc/meminfo`, in bytes.
+fn parse_mem_total(meminfo: &str) -> Option<u64> {
+    let line = meminfo.lines().find(|line| line.starts_with("MemTotal:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// EC2 instance type, through IMDSv2.
+async fn aws_instance_type(http_client: &Client, base: &str) -> Option<String> {
+    let token = metadata_text(
+        http_client
+            .put(format!("{}/latest/api/token", base))
+            .header("X-aws-ec2-metadata-token-ttl-seconds", "60"),
+    )
+    .await?;
+    metadata_text(
+        http_client
+            .get(format!("{}/latest/meta-data/instance-type", base))
+            .header("X-aws-ec2-metadata-token", token),
+    )
+    .await
+}
+
+/// GCE machine type, reported as `projects/<id>/machineTypes/<type>`.
+async fn gcp_machine_type(http_client: &Client, base: &str) -> Option<String> {
+    let machine_type = metadata_text(
+        http_client
+            .get(format!("{}/computeMetadata/v1/instance/machine-type", base))
+            .header("Metadata-Flavor", "Google"),
+    )
+    .await?;
+    machine_type.rsplit('/').next().map(str::to_string)
+}
+
+/// Azure VM size.
+async fn azure_vm_size(http_client: &Client, base: &str) -> Option<String> {
+    metadata_text(
+        http_client
+            .get(format!("{}/metadata/instance/compute/vmSize", base))
+            .query(&[("api-version", "2021-02-01"), ("format", "text")])
+            .header("Metadata", "true"),
+    )
+    .await
+}
+
+/// The body of a successful metadata response, if not empty.
+async fn metadata_text(request: reqwest::RequestBuilder) -> Option<String> {
+    let response = request.timeout(METADATA_TIMEOUT).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let text = response.text().await.ok()?;
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Provider;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_parse_memory_limits() {
+        assert_eq!(parse_memory_limit("536870912\n"), Some(536_870_912));
+        assert_eq!(parse_memory_limit("max\n"), None);
+        assert_eq!(parse_memory_limit("9223372036854771712"), None);
+        assert_eq!(
+            parse_mem_total("MemTotal:       16318412 kB\nMemFree: 1 kB\n"),
+            Some(16_318_412 * 1024)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_host_context_applies_instance_type() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/computeMetadata/v1/instance/machine-type"))
+            .and(header("Metadata-Flavor", "Google"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string("projects/42/machineTypes/e2-medium"),
+            )
+            .mount(&server)
+            .await;
+
+        let host = HostContext::detect();
+        host.lookup_instance_type_at(&Client::new(), &server.uri(), &server.uri())
+            .await;
+        let mut call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .build();
+        call.metadata = Some([(CPU_COUNT_KEY.to_string(), 64.into())].into());
+        host.apply(&mut call);
+
+        let metadata = call.metadata.unwrap();
+        assert_eq!(metadata[INSTANCE_TYPE_KEY], "e2-medium");
+        assert_eq!(metadata[CPU_COUNT_KEY], 64);
+    }
+}
@@ -0,0 +1,261 @@
+//! HTTP client settings.
+//!
+//! Networks that reach the internet only through a proxy, or that inspect
+//! TLS with their own certificate authority, need the SDK's HTTP clients set
+//! up for them. [`HttpConfig`] covers the common cases: a proxy, extra root
+//! certificates and a client certificate for mutual TLS, all read from PEM
+//! files when a client is created. Every client's configuration has one,
+//! under `http`. Anything else can be set on a `reqwest::Client` built by
+//! hand and passed to the `with_http_client` constructor of each client,
+//! such as [`DiagnyxClient::with_http_client`](crate::DiagnyxClient::with_http_client).
+//!
+//! Without a proxy set, the `HTTPS_PROXY` and `HTTP_PROXY` environment
+//! variables are used.
+//!
+//! # Example
+//!
+//! ```rust
+//! use diagnyx::{DiagnyxConfig, HttpConfig};
+//!
+//! let http = HttpConfig::new()
+//!     .proxy("http://proxy.internal:3128")
+//!     .root_certificate("/etc/ssl/corp-ca.pem");
+//! let config = DiagnyxConfig::new("dx_live_your_api_key").http(http.clone());
+//! # #[cfg(feature = "feedback")]
+//! let feedback = diagnyx::FeedbackClientConfig::new("dx_live_your_api_key", "org_123").http(http);
+//! ```
+
+use reqwest::{Certificate, Client, Identity, Proxy};
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::DiagnyxError;
+use crate::types::Masked;
+
+/// Timeout of requests made by the SDK's own HTTP clients.
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A client certificate and its private key, as PEM files, presented to the
+/// server for mutual TLS.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ClientCertificate {
+    pub certificate_path: PathBuf,
+    /// PKCS #8 private key.
+    pub private_key_path: PathBuf,
+}
+
+/// Proxy and TLS settings of a client's HTTP client.
+#[derive(Clone, Default, PartialEq)]
+pub struct HttpConfig {
+    /// URL of the HTTP proxy requests are sent through, e.g.
+    /// `http://proxy.internal:3128`. Default: None (the `HTTPS_PROXY` and
+    /// `HTTP_PROXY` environment variables)
+    pub proxy: Option<String>,
+    /// PEM files of certificate authorities trusted in addition to the
+    /// system's. Default: none
+    pub root_certificates: Vec<PathBuf>,
+    /// Certificate presented to the API for mutual TLS. Default: None
+    pub client_certificate: Option<ClientCertificate>,
+}
+
+impl fmt::Debug for HttpConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Proxy URLs can carry credentials
+        f.debug_struct("HttpConfig")
+            .field("proxy", &self.proxy.as_deref().map(Masked))
+            .field("root_certificates", &self.root_certificates)
+            .field("client_certificate", &self.client_certificate)
+            .finish()
+    }
+}
+
+impl HttpConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// Trust the certificate authorities in the PEM file at `path`, e.g.
+    /// the one of a TLS-inspecting proxy.
+    pub fn root_certificate(mut self, path: impl Into<PathBuf>) -> Self {
+        self.root_certificates.push(path.into());
+        self
+    }
+
+    /// Present the certificate and PKCS #8 private key in the PEM files at
+    /// `certificate_path` and `private_key_path` for mutual TLS.
+    pub fn client_certificate(
+        mut self,
+        certificate_path: impl Into<PathBuf>,
+        private_key_path: impl Into<PathBuf>,
+    ) -> Self {
+        self.client_certificate = Some(ClientCertificate {
+            certificate_path: certificate_path.into(),
+            private_key_path: private_key_path.into(),
+        });
+        self
+    }
+
+    /// Build an HTTP client with these settings, timing out requests after
+    /// `timeout`.
+    pub(crate) fn client(&self, timeout: Duration) -> Result<Client, DiagnyxError> {
+        HttpSettings::load(self)?.client(timeout)
+    }
+
+    /// Build a blocking HTTP client with these settings, timing out
+    /// requests after `timeout`.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn blocking_client(
+        &self,
+        timeout: Duration,
+    ) -> Result<reqwest::blocking::Client, DiagnyxError> {
+        HttpSettings::load(self)?.blocking_client(timeout)
+    }
+}
+
+/// The settings of an [`HttpConfig`], loaded from their files.
+struct HttpSettings {
+    proxy: Option<Proxy>,
+    root_certificates: Vec<Certificate>,
+    identity: Option<Identity>,
+}
+
+impl HttpSettings {
+    fn load(config: &HttpConfig) -> Result<Self, DiagnyxError> {
+        let proxy = config
+            .proxy
+            .as_deref()
+            .map(|url| {
+                Proxy::all(url).map_err(|e| {
+                    DiagnyxError::ConfigError(format!("Invalid proxy URL {}: {}", url, e))
+                })
+            })
+            .transpose()?;
+        let mut root_certificates = Vec::new();
+        for path in &config.root_certificates {
+            let certificates = Certificate::from_pem_bundle(&read(path)?)
+                .map_err(|e| invalid_pem("root certificates", path, e))?;
+            root_certificates.extend(certificates);
+        }
+        let identity = config
+            .client_certificate
+            .as_ref()
+            .map(|cert| {
+                Identity::from_pkcs8_pem(
+                    &read(&cert.certificate_path)?,
+                    &read(&cert.private_key_path)?,
+                )
+                .map_err(|e| invalid_pem("client certificate", &cert.certificate_path, e))
+            })
+            .transpose()?;
+        Ok(Self {
+            proxy,
+            root_certificates,
+            identity,
+        })
+    }
+
+    fn client(self, timeout: Duration) -> Result<Client, DiagnyxError> {
+        let mut builder = Client::builder().timeout(timeout);
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(proxy);
+        }
+        for certificate in self.root_certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some(identity) = self.identity {
+            builder = builder.identity(identity);
+        }
+        Ok(builder.build()?)
+    }
+
+    #[cfg(feature = "blocking")]
+    fn blocking_client(self, timeout: Duration) -> Result<reqwest::blocking::Client, DiagnyxError> {
+        let mut builder = reqwest::blocking::Client::builder().timeout(timeout);
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(proxy);
+        }
+        for certificate in self.root_certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+        if let Some(identity) = self.identity {
+            builder = builder.identity(identity);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, DiagnyxError> {
+    std::fs::read(path)
+        .map_err(|e| DiagnyxError::ConfigError(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+fn invalid_pem(what: &str, path: &Path, error: reqwest::Error) -> DiagnyxError {
+    DiagnyxError::ConfigError(format!("Invalid {} in {}: {}", what, path.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::DiagnyxClient;
+    use crate::types::{DiagnyxConfig, LLMCall, Provider};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_client_sends_through_configured_proxy() {
+        let proxy = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key")
+                .base_url("http://ingest.diagnyx.invalid")
+                .proxy(proxy.uri())
+                .flush_interval_ms(60000),
+        );
+
+        client
+            .track(
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build(),
+            )
+            .await;
+        client.flush().await.unwrap();
+
+        let requests = proxy.received_requests().await.unwrap();
+        assert_eq!(requests[0].url.host_str(), Some("ingest.diagnyx.invalid"));
+    }
+
+    #[test]
+    fn test_load_reports_unreadable_and_invalid_files() {
+        let missing = HttpConfig::new().root_certificate("/nonexistent/ca.pem");
+        let error = HttpSettings::load(&missing).err().unwrap();
+        assert!(
+            error.to_string().contains("/nonexistent/ca.pem"),
+            "{}",
+            error
+        );
+
+        let path = std::env::temp_dir().join(format!("diagnyx-ca-{}.pem", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "-----BEGIN CERTIFICATE-----\nnot base64\n").unwrap();
+        let invalid = HttpConfig::new().root_certificate(&path);
+        let error = HttpSettings::load(&invalid).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(error, DiagnyxError::ConfigError(_)), "{}", error);
+
+        let proxy = HttpConfig::new().proxy("not a url");
+        assert!(HttpSettings::load(&proxy).is_err());
+    }
+}
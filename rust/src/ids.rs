@@ -0,0 +1,284 @@
+//! Typed identifiers.
+//!
+//! Sessions, traces and projects are all identified by strings on the wire.
+//! Wrapping each in its own type keeps a trace ID from being passed where a
+//! session ID is expected.
+//!
+//! Generated identifiers are UUID v4 strings by default. An [`IdGenerator`]
+//! installed with [`set_id_generator`] can produce them instead, e.g. ULIDs
+//! or IDs in the format of an existing tracing system.
+//!
+//! # Example
+//!
+//! ```rust
+//! use diagnyx::{ProjectId, TraceId};
+//!
+//! let project: ProjectId = "proj-123".parse().unwrap();
+//!
+//! assert_eq!(project, "proj-123");
+//! assert!("has whitespace".parse::<TraceId>().is_err());
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+#[cfg(feature = "uuid")]
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::error::DiagnyxError;
+
+/// Maximum length of an identifier, in bytes.
+pub const MAX_ID_LENGTH: usize = 256;
+
+fn validate(kind: &str, value: &str) -> Result<(), DiagnyxError> {
+    if value.is_empty() {
+        return Err(DiagnyxError::ConfigError(format!(
+            "{} must not be empty",
+            kind
+        )));
+    }
+    if value.len() > MAX_ID_LENGTH {
+        return Err(DiagnyxError::ConfigError(format!(
+            "{} must be at most {} bytes",
+            kind, MAX_ID_LENGTH
+        )));
+    }
+    if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(DiagnyxError::ConfigError(format!(
+            "{} must not contain whitespace or control characters: {:?}",
+            kind, value
+        )));
+    }
+    Ok(())
+}
+
+/// Produces new identifiers for traces, sessions and callback runs.
+///
+/// Closures returning a `String` can be used as generators.
+#[cfg(feature = "uuid")]
+pub trait IdGenerator: Send + Sync {
+    /// Return a new identifier.
+    ///
+    /// Values that are not valid identifiers are replaced with a UUID v4.
+    fn next_id(&self) -> String;
+}
+
+#[cfg(feature = "uuid")]
+impl<F> IdGenerator for F
+where
+    F: Fn() -> String + Send + Sync,
+{
+    fn next_id(&self) -> String {
+        self()
+    }
+}
+
+#[cfg(feature = "uuid")]
+fn generator() -> &'static RwLock<Option<Arc<dyn IdGenerator>>> {
+    static GENERATOR: OnceLock<RwLock<Option<Arc<dyn IdGenerator>>>> = OnceLock::new();
+    GENERATOR.get_or_init(|| RwLock::new(None))
+}
+
+/// Generate identifiers with `generator` instead of UUID v4, process-wide.
+///
+/// Requires the `uuid` feature.
+#[cfg(feature = "uuid")]
+pub fn set_id_generator(generator: impl IdGenerator + 'static) {
+    *self::generator().write().unwrap() = Some(Arc::new(generator));
+}
+
+/// A new identifier from `generator`, or from the process-wide generator if
+/// `None`, falling back to a UUID v4 if it is invalid.
+#[cfg(feature = "uuid")]
+pub(crate) fn next_id(kind: &str, generator: Option<&dyn IdGenerator>) -> String {
+    let id = match generator {
+        Some(generator) => Some(generator.next_id()),
+        None => self::generator()
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|generator| generator.next_id()),
+    };
+    id.filter(|id| validate(kind, id).is_ok())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+macro_rules! define_id {
+    ($(#[$meta:meta])* $name:ident, $kind:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            /// Create a validated identifier.
+            ///
+            /// Identifiers must be non-empty, at most [`MAX_ID_LENGTH`] bytes,
+            /// and free of whitespace and control characters.
+            pub fn new(value: impl Into<String>) -> Result<Self, DiagnyxError> {
+                let value = value.into();
+                validate($kind, &value)?;
+                Ok(Self(value))
+            }
+
+            /// Create an identifier from a string literal.
+            ///
+            /// # Panics
+            ///
+            /// Panics if the value is not a valid identifier.
+            pub fn from_static(value: &'static str) -> Self {
+                match Self::new(value) {
+                    Ok(id) => id,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+
+            /// Generate a new identifier with the process-wide
+            /// [`IdGenerator`], a UUID v4 by default.
+            ///
+            /// Requires the `uuid` feature.
+            #[cfg(feature = "uuid")]
+            pub fn generate() -> Self {
+                Self(next_id($kind, None))
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            pub fn into_inner(self) -> String {
+                self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = DiagnyxError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Self::new(s)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = DiagnyxError;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                Self::new(value)
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = DiagnyxError;
+
+            fn try_from(value: &str) -> Result<Self, Self::Error> {
+                Self::new(value)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+    };
+}
+
+define_id!(
+    /// Identifier of a guardrail or feedback session.
+    SessionId,
+    "session ID"
+);
+
+define_id!(
+    /// Identifier of a trace, shared by every call made for one request.
+    TraceId,
+    "trace ID"
+);
+
+define_id!(
+    /// Identifier of a Diagnyx project.
+    ProjectId,
+    "project ID"
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_ids() {
+        let id: TraceId = "trace-123".parse().unwrap();
+        assert_eq!(id, "trace-123");
+        assert_eq!(id.to_string(), "trace-123");
+        assert_eq!(id.as_str(), "trace-123");
+    }
+
+    #[test]
+    fn test_invalid_ids() {
+        assert!(SessionId::new("").is_err());
+        assert!(SessionId::new("sess 1").is_err());
+        assert!(SessionId::new("sess\n1").is_err());
+        assert!(SessionId::new("x".repeat(MAX_ID_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "project ID must not be empty")]
+    fn test_from_static_panics_on_invalid() {
+        ProjectId::from_static("");
+    }
+
+    #[test]
+    fn test_serde_roundtrip_validates() {
+        let id = ProjectId::from_static("proj-1");
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, "\"proj-1\"");
+
+        let parsed: ProjectId = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, id);
+        assert!(serde_json::from_str::<ProjectId>("\"\"").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "uuid")]
+    fn test_generate_is_unique() {
+        assert_ne!(TraceId::generate(), TraceId::generate());
+    }
+
+    #[test]
+    #[cfg(feature = "uuid")]
+    fn test_next_id_uses_generator() {
+        let custom = || "01ARZ3NDEKTSV4RRFFQ69G5FAV".to_string();
+        assert_eq!(
+            next_id("trace ID", Some(&custom)),
+            "01ARZ3NDEKTSV4RRFFQ69G5FAV"
+        );
+
+        // Invalid IDs fall back to UUIDs
+        let invalid = || "not valid".to_string();
+        assert_eq!(next_id("trace ID", Some(&invalid)).len(), 36);
+    }
+}
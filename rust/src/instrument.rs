@@ -0,0 +1,175 @@
+//! Tracking of calls from their return values.
+//!
+//! [`TrackOutput`] reads the token usage and error of an LLM call from what
+//! the function making it returns, so the call can be tracked without
+//! building an [`LLMCall`] by hand. With the `macros` feature,
+//! `#[diagnyx::track]` does this for every call of an async function:
+//!
+//! ```rust,ignore
+//! use diagnyx::TrackOutput;
+//!
+//! struct Answer {
+//!     text: String,
+//!     prompt_tokens: u32,
+//!     completion_tokens: u32,
+//! }
+//!
+//! impl TrackOutput for Answer {
+//!     fn input_tokens(&self) -> i32 {
+//!         self.prompt_tokens as i32
+//!     }
+//!
+//!     fn output_tokens(&self) -> i32 {
+//!         self.completion_tokens as i32
+//!     }
+//! }
+//!
+//! #[diagnyx::track(provider = "openai", model_from = "request.model")]
+//! async fn ask(request: Request) -> Result<Answer, Error> {
+//!     // ...
+//! }
+//! ```
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::client::DiagnyxClient;
+use crate::types::{CallStatus, LLMCall, Provider};
+
+/// The outcome of an LLM call, as returned by the function making it.
+///
+/// A `Result` is tracked as an error with the error's message, or as its
+/// success value.
+pub trait TrackOutput {
+    /// Tokens sent to the model. Default: 0
+    fn input_tokens(&self) -> i32 {
+        0
+    }
+
+    /// Tokens generated by the model. Default: 0
+    fn output_tokens(&self) -> i32 {
+        0
+    }
+
+    /// Why the call failed, if it did. Default: `None`
+    fn error_message(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<T: TrackOutput, E: fmt::Display> TrackOutput for Result<T, E> {
+    fn input_tokens(&self) -> i32 {
+        self.as_ref().map_or(0, T::input_tokens)
+    }
+
+    fn output_tokens(&self) -> i32 {
+        self.as_ref().map_or(0, T::output_tokens)
+    }
+
+    fn error_message(&self) -> Option<String> {
+        match self {
+            Ok(value) => value.error_message(),
+            Err(e) => Some(e.to_string()),
+        }
+    }
+}
+
+impl TrackOutput for () {}
+
+impl TrackOutput for String {}
+
+#[cfg(feature = "openai")]
+impl TrackOutput for async_openai::types::CreateChatCompletionResponse {
+    fn input_tokens(&self) -> i32 {
+        self.usage.as_ref().map_or(0, |u| u.prompt_tokens as i32)
+    }
+
+    fn output_tokens(&self) -> i32 {
+        self.usage
+            .as_ref()
+            .map_or(0, |u| u.completion_tokens as i32)
+    }
+}
+
+/// Track a call to `model` that took `latency` and returned `output`.
+pub async fn track_output<O: TrackOutput + ?Sized>(
+    client: &DiagnyxClient,
+    provider: Provider,
+    model: impl Into<String>,
+    latency: Duration,
+    output: &O,
+) {
+    let mut call = LLMCall::builder()
+        .provider(provider)
+        .model(model)
+        .input_tokens(output.input_tokens())
+        .output_tokens(output.output_tokens())
+        .latency_ms(latency.as_millis() as i64);
+    if let Some(message) = output.error_message() {
+        call = call.status(CallStatus::Error).error_message(message);
+    }
+    client.track(call.build()).await;
+}
+
+#[cfg(all(test, feature = "macros"))]
+mod tests {
+    use super::*;
+    use crate::DiagnyxConfig;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    struct Answer {
+        prompt_tokens: i32,
+        completion_tokens: i32,
+    }
+
+    impl TrackOutput for Answer {
+        fn input_tokens(&self) -> i32 {
+            self.prompt_tokens
+        }
+
+        fn output_tokens(&self) -> i32 {
+            self.completion_tokens
+        }
+    }
+
+    #[crate::track(provider = "anthropic", model_from = "model", client = "client")]
+    async fn ask(client: &DiagnyxClient, model: &str, fail: bool) -> Result<Answer, String> {
+        if fail {
+            return Err("overloaded".to_string());
+        }
+        Ok(Answer {
+            prompt_tokens: 12,
+            completion_tokens: 4,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_track_attribute_records_calls() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let client = DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000),
+        );
+
+        assert!(ask(&client, "claude-3-5-haiku", false).await.is_ok());
+        assert!(ask(&client, "claude-3-5-haiku", true).await.is_err());
+        client.flush().await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let calls = body["calls"].as_array().unwrap();
+        assert_eq!(calls[0]["provider"], "anthropic");
+        assert_eq!(calls[0]["model"], "claude-3-5-haiku");
+        assert_eq!(calls[0]["input_tokens"], 12);
+        assert_eq!(calls[0]["output_tokens"], 4);
+        assert_eq!(calls[1]["status"], "error");
+        assert_eq!(calls[1]["error_message"], "overloaded");
+    }
+}
@@ -0,0 +1,14 @@
+//! Instrumented wrappers for LLM provider clients.
+//!
+//! Each integration wraps a provider's client and tracks every call it makes
+//! with a [`DiagnyxClient`](crate::DiagnyxClient), filling in the model,
+//! token usage and latency from the provider's response. The `tower`
+//! layer does the same for calls proxied through an HTTP gateway, and the
+//! `tracing` layer for spans of calls made by other instrumented libraries.
+
+#[cfg(feature = "openai")]
+pub mod openai;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "tracing-layer")]
+pub mod tracing_layer;
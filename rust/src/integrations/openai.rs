@@ -0,0 +1,524 @@
+//! Instrumented wrapper for the `async-openai` client.
+//!
+//! [`InstrumentedClient`] forwards chat, completion and embedding requests to
+//! an [`async_openai::Client`] and tracks each one: the model and token
+//! usage come from the response, latency is measured around the request,
+//! and for streamed chat completions the time to first token is recorded.
+//! Failed requests are tracked with an error status.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use async_openai::types::{ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs};
+//! use diagnyx::integrations::openai::InstrumentedClient;
+//! use diagnyx::DiagnyxClient;
+//! use std::sync::Arc;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let diagnyx = Arc::new(DiagnyxClient::new("dx_live_your_api_key"));
+//!     let openai = InstrumentedClient::new(async_openai::Client::new(), diagnyx.clone());
+//!
+//!     let request = CreateChatCompletionRequestArgs::default()
+//!         .model("gpt-4o-mini")
+//!         .messages([ChatCompletionRequestUserMessageArgs::default()
+//!             .content("Hello!")
+//!             .build()?
+//!             .into()])
+//!         .build()?;
+//!     let response = openai.chat(request).await?;
+//!     println!("{:?}", response.choices[0].message.content);
+//!
+//!     diagnyx.flush().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use async_openai::config::Config;
+use async_openai::error::OpenAIError;
+use async_openai::types::{
+    ChatCompletionStreamOptions, CompletionUsage, CreateChatCompletionRequest,
+    CreateChatCompletionResponse, CreateChatCompletionStreamResponse, CreateCompletionRequest,
+    CreateCompletionResponse, CreateEmbeddingRequest, CreateEmbeddingResponse,
+};
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use crate::client::DiagnyxClient;
+use crate::ids::ProjectId;
+use crate::types::{CallStatus, LLMCall, LLMCallBuilder, Provider};
+
+/// An `async-openai` client that tracks every call with Diagnyx.
+pub struct InstrumentedClient<C: Config> {
+    inner: async_openai::Client<C>,
+    diagnyx: Arc<DiagnyxClient>,
+    provider: Provider,
+    project_id: Option<ProjectId>,
+    environment: Option<String>,
+}
+
+impl<C: Config> InstrumentedClient<C> {
+    /// Wrap `inner`, tracking its calls with `diagnyx`.
+    pub fn new(inner: async_openai::Client<C>, diagnyx: Arc<DiagnyxClient>) -> Self {
+        Self {
+            inner,
+            diagnyx,
+            provider: Provider::OpenAI,
+            project_id: None,
+            environment: None,
+        }
+    }
+
+    /// Set the provider recorded on calls, e.g. [`Provider::Azure`] for an
+    /// Azure OpenAI client. Default: [`Provider::OpenAI`].
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// Set the project recorded on calls.
+    pub fn project_id(mut self, id: ProjectId) -> Self {
+        self.project_id = Some(id);
+        self
+    }
+
+    /// Set the environment recorded on calls.
+    pub fn environment(mut self, env: impl Into<String>) -> Self {
+        self.environment = Some(env.into());
+        self
+    }
+
+    /// The wrapped client, for requests that are not tracked.
+    pub fn inner(&self) -> &async_openai::Client<C> {
+        &self.inner
+    }
+
+    /// Create a chat completion.
+    pub async fn chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse, OpenAIError> {
+        let model = request.model.clone();
+        let start = Instant::now();
+        let result = self.inner.chat().create(request).await;
+
+        let call = match &result {
+            Ok(response) => self
+                .call(&response.model, "/chat/completions", start)
+                .usage(response.usage.as_ref()),
+            Err(e) => self.call(&model, "/chat/completions", start).error(e),
+        };
+        self.diagnyx.track(call.build()).await;
+        result
+    }
+
+    /// Create a streamed chat completion.
+    ///
+    /// Token usage is requested from the API and recorded from the final
+    /// chunk. The call is tracked when the stream ends or is dropped.
+    pub async fn chat_stream(
+        &self,
+        mut request: CreateChatCompletionRequest,
+    ) -> Result<InstrumentedStream, OpenAIError> {
+        request.stream_options = Some(ChatCompletionStreamOptions {
+            include_usage: true,
+        });
+        let model = request.model.clone();
+        let start = Instant::now();
+
+        match self.inner.chat().create_stream(request).await {
+            Ok(inner) => Ok(InstrumentedStream {
+                inner,
+                diagnyx: Arc::clone(&self.diagnyx),
+                call: Some(self.call(&model, "/chat/completions", start)),
+            }),
+            Err(e) => {
+                let call = self.call(&model, "/chat/completions", start).error(&e);
+                self.diagnyx.track(call.build()).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Create a legacy text completion.
+    pub async fn completion(
+        &self,
+        request: CreateCompletionRequest,
+    ) -> Result<CreateCompletionResponse, OpenAIError> {
+        let model = request.model.clone();
+        let start = Instant::now();
+        let result = self.inner.completions().create(request).await;
+
+        let call = match &result {
+            Ok(response) => self
+                .call(&response.model, "/completions", start)
+                .usage(response.usage.as_ref()),
+            Err(e) => self.call(&model, "/completions", start).error(e),
+        };
+        self.diagnyx.track(call.build()).await;
+        result
+    }
+
+    /// Create embeddings.
+    pub async fn embedding(
+        &self,
+        request: CreateEmbeddingRequest,
+    ) -> Result<CreateEmbeddingResponse, OpenAIError> {
+        let model = request.model.clone();
+        let start = Instant::now();
+        let result = self.inner.embeddings().create(request).await;
+
+        let call = match &result {
+            Ok(response) => self
+                .call(&response.model, "/embeddings", start)
+                .input_tokens(response.usage.prompt_tokens as i32),
+            Err(e) => self.call(&model, "/embeddings", start).error(e),
+        };
+        self.diagnyx.track(call.build()).await;
+        result
+    }
+
+    fn call(&self, model: &str, endpoint: &str, start: Instant) -> CallRecord {
+        let mut builder = LLMCall::builder()
+            .provider(self.provider.clone())
+            .model(model)
+            .endpoint(endpoint);
+        if let Some(id) = &self.project_id {
+            builder = builder.project_id(id.clone());
+        }
+        if let Some(env) = &self.environment {
+            builder = builder.environment(env);
+        }
+        CallRecord {
+            builder,
+            start,
+            ttft_ms: None,
+        }
+    }
+}
+
+/// A call being recorded, timed from `start`.
+struct CallRecord {
+    builder: LLMCallBuilder,
+    start: Instant,
+    ttft_ms: Option<i64>,
+}
+
+impl CallRecord {
+    fn usage(mut self, usage: Option<&CompletionUsage>) -> Self {
+        if let Some(usage) = usage {
+            self.builder = self
+                .builder
+                .input_tokens(usage.prompt_tokens as i32)
+                .output_tokens(usage.completion_tokens as i32);
+        }
+        self
+    }
+
+    fn input_tokens(mut self, tokens: i32) -> Self {
+        self.builder = self.builder.input_tokens(tokens);
+        self
+    }
+
+    fn error(mut self, error: &OpenAIError) -> Self {
+        let status = match error {
+            OpenAIError::ApiError(e) if e.code.as_deref() == Some("rate_limit_exceeded") => {
+                CallStatus::RateLimited
+            }
+            _ => CallStatus::Error,
+        };
+        if let OpenAIError::ApiError(e) = error {
+            if let Some(code) = &e.code {
+                self.builder = self.builder.error_code(code);
+            }
+        }
+        self.builder = self.builder.status(status).error_message(error.to_string());
+        self
+    }
+
+    fn build(self) -> LLMCall {
+        let mut builder = self.builder;
+        if let Some(ttft) = self.ttft_ms {
+            builder = builder.ttft_ms(ttft);
+        }
+        builder
+            .latency_ms(self.start.elapsed().as_millis() as i64)
+            .build()
+    }
+}
+
+/// A streamed chat completion that tracks the call when it finishes.
+///
+/// Yields the same chunks as the wrapped stream.
+pub struct InstrumentedStream {
+    inner:
+        Pin<Box<dyn Stream<Item = Result<CreateChatCompletionStreamResponse, OpenAIError>> + Send>>,
+    diagnyx: Arc<DiagnyxClient>,
+    call: Option<CallRecord>,
+}
+
+impl InstrumentedStream {
+    fn track(&mut self) {
+        let Some(call) = self.call.take() else {
+            return;
+        };
+        let call = call.build();
+        let diagnyx = Arc::clone(&self.diagnyx);
+        // The stream may be dropped outside a runtime, where the call is lost
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move { diagnyx.track(call).await });
+        }
+    }
+}
+
+impl Stream for InstrumentedStream {
+    type Item = Result<CreateChatCompletionStreamResponse, OpenAIError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let poll = this.inner.as_mut().poll_next(cx);
+
+        if let Some(mut call) = this.call.take() {
+            match &poll {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let has_content = chunk.choices.iter().any(|c| c.delta.content.is_some());
+                    if has_content && call.ttft_ms.is_none() {
+                        call.ttft_ms = Some(call.start.elapsed().as_millis() as i64);
+                    }
+                    call.builder = call.builder.model(&chunk.model);
+                    call = call.usage(chunk.usage.as_ref());
+                }
+                Poll::Ready(Some(Err(e))) => call = call.error(e),
+                _ => {}
+            }
+            this.call = Some(call);
+        }
+
+        if let Poll::Ready(None) = poll {
+            this.track();
+        }
+        poll
+    }
+}
+
+impl Drop for InstrumentedStream {
+    fn drop(&mut self) {
+        self.track();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DiagnyxConfig;
+    use async_openai::config::OpenAIConfig;
+    use async_openai::types::{
+        ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
+        CreateEmbeddingRequestArgs,
+    };
+    use futures::StreamExt;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn clients(
+        server: &MockServer,
+    ) -> (InstrumentedClient<OpenAIConfig>, Arc<DiagnyxClient>) {
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 1,
+                "total_cost": 0.0,
+                "total_tokens": 0,
+                "ids": []
+            })))
+            .mount(server)
+            .await;
+
+        let diagnyx = Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000),
+        ));
+        let openai = async_openai::Client::with_config(
+            OpenAIConfig::new()
+                .with_api_key("sk-test")
+                .with_api_base(format!("{}/v1", server.uri())),
+        );
+        (InstrumentedClient::new(openai, diagnyx.clone()), diagnyx)
+    }
+
+    /// Flush and return the calls sent to the ingest endpoint.
+    async fn tracked(server: &MockServer, diagnyx: &DiagnyxClient) -> Vec<serde_json::Value> {
+        diagnyx.flush().await.unwrap();
+        let mut calls = Vec::new();
+        for request in server.received_requests().await.unwrap() {
+            if request.url.path() == "/api/v1/ingest/llm/batch" {
+                let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                calls.extend(body["calls"].as_array().unwrap().iter().cloned());
+            }
+        }
+        calls
+    }
+
+    fn chat_request() -> CreateChatCompletionRequest {
+        CreateChatCompletionRequestArgs::default()
+            .model("gpt-4o-mini")
+            .messages([ChatCompletionRequestUserMessageArgs::default()
+                .content("Hello")
+                .build()
+                .unwrap()
+                .into()])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chat_tracks_usage() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1700000000,
+                "model": "gpt-4o-mini-2024-07-18",
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": "Hi!"},
+                    "finish_reason": "stop"
+                }],
+                "usage": {"prompt_tokens": 9, "completion_tokens": 3, "total_tokens": 12}
+            })))
+            .mount(&server)
+            .await;
+        let (openai, diagnyx) = clients(&server).await;
+
+        openai.chat(chat_request()).await.unwrap();
+
+        let calls = tracked(&server, &diagnyx).await;
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0]["provider"], "openai");
+        assert_eq!(calls[0]["model"], "gpt-4o-mini-2024-07-18");
+        assert_eq!(calls[0]["endpoint"], "/chat/completions");
+        assert_eq!(calls[0]["input_tokens"], 9);
+        assert_eq!(calls[0]["output_tokens"], 3);
+        assert_eq!(calls[0]["status"], "success");
+    }
+
+    #[tokio::test]
+    async fn test_chat_tracks_api_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(serde_json::json!({
+                "error": {
+                    "message": "Invalid model",
+                    "type": "invalid_request_error",
+                    "param": null,
+                    "code": "model_not_found"
+                }
+            })))
+            .mount(&server)
+            .await;
+        let (openai, diagnyx) = clients(&server).await;
+
+        assert!(openai.chat(chat_request()).await.is_err());
+
+        let calls = tracked(&server, &diagnyx).await;
+        assert_eq!(calls[0]["model"], "gpt-4o-mini");
+        assert_eq!(calls[0]["status"], "error");
+        assert_eq!(calls[0]["error_code"], "model_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_chat_stream_tracks_ttft_and_usage() {
+        let server = MockServer::start().await;
+        let chunk = |delta: serde_json::Value, usage: serde_json::Value| {
+            let choices = if delta.is_null() {
+                serde_json::json!([])
+            } else {
+                serde_json::json!([{"index": 0, "delta": delta, "finish_reason": null}])
+            };
+            format!(
+                "data: {}\n\n",
+                serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion.chunk",
+                    "created": 1700000000,
+                    "model": "gpt-4o-mini-2024-07-18",
+                    "choices": choices,
+                    "usage": usage
+                })
+            )
+        };
+        let body = [
+            chunk(
+                serde_json::json!({"role": "assistant", "content": "Hi"}),
+                serde_json::Value::Null,
+            ),
+            chunk(serde_json::json!({"content": "!"}), serde_json::Value::Null),
+            chunk(
+                serde_json::Value::Null,
+                serde_json::json!({"prompt_tokens": 9, "completion_tokens": 2, "total_tokens": 11}),
+            ),
+            "data: [DONE]\n\n".to_string(),
+        ]
+        .concat();
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+        let (openai, diagnyx) = clients(&server).await;
+
+        let mut stream = openai.chat_stream(chat_request()).await.unwrap();
+        let mut content = String::new();
+        while let Some(chunk) = stream.next().await {
+            for choice in chunk.unwrap().choices {
+                content.push_str(choice.delta.content.as_deref().unwrap_or_default());
+            }
+        }
+        drop(stream);
+        assert_eq!(content, "Hi!");
+        // The call is tracked from a spawned task
+        tokio::task::yield_now().await;
+
+        let calls = tracked(&server, &diagnyx).await;
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0]["model"], "gpt-4o-mini-2024-07-18");
+        assert_eq!(calls[0]["input_tokens"], 9);
+        assert_eq!(calls[0]["output_tokens"], 2);
+        assert!(calls[0]["ttft_ms"].is_number());
+    }
+
+    #[tokio::test]
+    async fn test_embedding_tracks_input_tokens() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/embeddings"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "object": "list",
+                "data": [{"object": "embedding", "index": 0, "embedding": [0.1, 0.2]}],
+                "model": "text-embedding-3-small",
+                "usage": {"prompt_tokens": 5, "total_tokens": 5}
+            })))
+            .mount(&server)
+            .await;
+        let (openai, diagnyx) = clients(&server).await;
+
+        let request = CreateEmbeddingRequestArgs::default()
+            .model("text-embedding-3-small")
+            .input("Hello world")
+            .build()
+            .unwrap();
+        openai.embedding(request).await.unwrap();
+
+        let calls = tracked(&server, &diagnyx).await;
+        assert_eq!(calls[0]["endpoint"], "/embeddings");
+        assert_eq!(calls[0]["input_tokens"], 5);
+        assert_eq!(calls[0]["output_tokens"], 0);
+    }
+}
@@ -0,0 +1,625 @@
+//! Tower middleware for LLM gateways.
+//!
+//! [`DiagnyxLayer`] wraps an HTTP service that proxies OpenAI-compatible
+//! requests and tracks every call passing through it: the model comes from
+//! the request or response body, token usage from the response's `usage`
+//! object, and latency is measured from the request to the end of the
+//! response. Responses with an error status are tracked with an error
+//! status.
+//!
+//! Bodies are not buffered: the request and response bodies are passed on
+//! as [`TrackedBody`]s, which keep a copy of the data flowing through them
+//! and track the call when the response ends. Streamed responses
+//! (`text/event-stream`) are tracked too, with the usage reported by their
+//! events, such as OpenAI's final chunk with `stream_options.include_usage`
+//! or Anthropic's `message_start` and `message_delta` events.
+//!
+//! Only JSON request bodies naming a `model` are tracked. Calls whose
+//! request or response body is larger than
+//! [`max_body_bytes`](DiagnyxLayer::max_body_bytes), or whose response is
+//! dropped before its end, are forwarded without being tracked.
+//!
+//! # Example
+//!
+//! The wrapped service receives requests with a [`TrackedBody`] around the
+//! original body, e.g. hyper's `Incoming`:
+//!
+//! ```rust,ignore
+//! use diagnyx::integrations::tower::DiagnyxLayer;
+//! use diagnyx::DiagnyxClient;
+//! use hyper_util::service::TowerToHyperService;
+//! use std::sync::Arc;
+//! use tower_layer::Layer;
+//!
+//! let diagnyx = Arc::new(DiagnyxClient::new("dx_live_your_api_key"));
+//! let gateway = DiagnyxLayer::new(diagnyx)
+//!     .environment("production")
+//!     .layer(forward_to_upstream);
+//! let service = TowerToHyperService::new(gateway);
+//! ```
+
+use bytes::Bytes;
+use http::{header, Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use serde::Deserialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::client::DiagnyxClient;
+use crate::ids::ProjectId;
+use crate::sse;
+use crate::types::{CallStatus, LLMCall, LLMCallBuilder, Provider};
+
+/// Largest request or response body copied by default.
+const DEFAULT_MAX_BODY_BYTES: u64 = 4 * 1024 * 1024;
+
+/// A layer that tracks the LLM calls proxied by the wrapped service.
+#[derive(Clone)]
+pub struct DiagnyxLayer {
+    diagnyx: Arc<DiagnyxClient>,
+    provider: Provider,
+    project_id: Option<ProjectId>,
+    environment: Option<String>,
+    max_body_bytes: u64,
+}
+
+impl DiagnyxLayer {
+    /// Track proxied calls with `diagnyx`.
+    pub fn new(diagnyx: Arc<DiagnyxClient>) -> Self {
+        Self {
+            diagnyx,
+            provider: Provider::OpenAI,
+            project_id: None,
+            environment: None,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+        }
+    }
+
+    /// Set the provider recorded on calls. Default: [`Provider::OpenAI`].
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    /// Set the project recorded on calls.
+    pub fn project_id(mut self, id: ProjectId) -> Self {
+        self.project_id = Some(id);
+        self
+    }
+
+    /// Set the environment recorded on calls.
+    pub fn environment(mut self, env: impl Into<String>) -> Self {
+        self.environment = Some(env.into());
+        self
+    }
+
+    /// Set the largest request or response body that is copied to be
+    /// inspected. Calls with larger bodies are forwarded untracked.
+    /// Default: 4 MiB.
+    pub fn max_body_bytes(mut self, bytes: u64) -> Self {
+        self.max_body_bytes = bytes;
+        self
+    }
+
+    fn call(&self, model: &str, endpoint: &str) -> LLMCallBuilder {
+        let mut builder = LLMCall::builder()
+            .provider(self.provider.clone())
+            .model(model)
+            .endpoint(endpoint);
+        if let Some(id) = &self.project_id {
+            builder = builder.project_id(id.clone());
+        }
+        if let Some(env) = &self.environment {
+            builder = builder.environment(env);
+        }
+        builder
+    }
+
+    /// Track `call` in the background; bodies end outside of async code.
+    fn track(&self, call: LLMCall) {
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let diagnyx = Arc::clone(&self.diagnyx);
+            runtime.spawn(async move { diagnyx.track(call).await });
+        }
+    }
+}
+
+impl<S> Layer<S> for DiagnyxLayer {
+    type Service = DiagnyxService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DiagnyxService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// Service produced by [`DiagnyxLayer`].
+#[derive(Clone)]
+pub struct DiagnyxService<S> {
+    inner: S,
+    layer: DiagnyxLayer,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for DiagnyxService<S>
+where
+    S: Service<Request<TrackedBody<ReqBody>>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    ReqBody: Body<Data = Bytes> + Send + 'static,
+    ResBody: Body<Data = Bytes> + Send + 'static,
+{
+    type Response = Response<TrackedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        // The readied service handles this request; a clone takes its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+        let limit = layer.max_body_bytes;
+        let endpoint = request.uri().path().to_string();
+
+        let request_body = Arc::new(Mutex::new(None));
+        let sink = Arc::clone(&request_body);
+        let request = request.map(|body| {
+            TrackedBody::new(body, limit, move |capture| {
+                if let Capture::Complete(Some(body)) = capture {
+                    *sink.lock().unwrap_or_else(|e| e.into_inner()) = Some(body);
+                }
+            })
+        });
+
+        Box::pin(async move {
+            let start = Instant::now();
+            let response = inner.call(request).await?;
+            let status = response.status();
+            let event_stream = is_event_stream(&response);
+            Ok(response.map(|body| {
+                TrackedBody::new(body, limit, move |capture| {
+                    let request_body = request_body
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .take();
+                    let Some(proxied) = request_body
+                        .and_then(|body| serde_json::from_slice::<ProxiedRequest>(&body).ok())
+                    else {
+                        return;
+                    };
+                    let builder = layer.call(&proxied.model, &endpoint);
+                    let builder = match capture {
+                        Capture::Complete(Some(body)) if event_stream && status.is_success() => {
+                            record_stream(builder, &body)
+                        }
+                        Capture::Complete(Some(body)) => record_response(builder, status, &body),
+                        Capture::Failed => builder
+                            .status(CallStatus::Error)
+                            .error_message("failed to read the upstream response"),
+                        Capture::Complete(None) | Capture::Abandoned => return,
+                    };
+                    layer.track(
+                        builder
+                            .latency_ms(start.elapsed().as_millis() as i64)
+                            .build(),
+                    );
+                })
+            }))
+        })
+    }
+}
+
+/// What a [`TrackedBody`] saw of the body it wraps.
+enum Capture {
+    /// The body ended; its data, unless larger than the limit.
+    Complete(Option<Vec<u8>>),
+    /// Reading the body failed.
+    Failed,
+    /// The body was dropped before its end.
+    Abandoned,
+}
+
+/// A body passed on by [`DiagnyxService`] that keeps a copy of the data
+/// flowing through it, up to the layer's
+/// [`max_body_bytes`](DiagnyxLayer::max_body_bytes), to track the call.
+pub struct TrackedBody<B> {
+    inner: Pin<Box<B>>,
+    /// Data seen so far; `None` once it exceeded `limit`.
+    copy: Option<Vec<u8>>,
+    limit: u64,
+    on_end: Option<Box<dyn FnOnce(Capture) + Send>>,
+}
+
+impl<B> TrackedBody<B> {
+    fn new(inner: B, limit: u64, on_end: impl FnOnce(Capture) + Send + 'static) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            copy: Some(Vec::new()),
+            limit,
+            on_end: Some(Box::new(on_end)),
+        }
+    }
+
+    fn keep(&mut self, data: &[u8]) {
+        if let Some(copy) = &mut self.copy {
+            if (copy.len() + data.len()) as u64 > self.limit {
+                self.copy = None;
+            } else {
+                copy.extend_from_slice(data);
+            }
+        }
+    }
+
+    fn end(&mut self, capture: impl FnOnce(Option<Vec<u8>>) -> Capture) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(capture(self.copy.take()));
+        }
+    }
+}
+
+impl<B: Body<Data = Bytes>> Body for TrackedBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+        let this = self.get_mut();
+        let frame = ready!(this.inner.as_mut().poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.keep(data);
+                }
+                // Callers may stop polling once the body says it has ended
+                if this.inner.is_end_stream() {
+                    this.end(Capture::Complete);
+                }
+            }
+            Some(Err(_)) => this.end(|_| Capture::Failed),
+            None => this.end(Capture::Complete),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> Drop for TrackedBody<B> {
+    fn drop(&mut self) {
+        self.end(|_| Capture::Abandoned);
+    }
+}
+
+/// The fields of a proxied request body used for tracking.
+#[derive(Deserialize)]
+struct ProxiedRequest {
+    model: String,
+}
+
+/// The fields of a proxied response body used for tracking.
+#[derive(Deserialize)]
+struct ProxiedResponse {
+    model: Option<String>,
+    usage: Option<Usage>,
+    error: Option<ErrorBody>,
+}
+
+/// Token usage, as reported by OpenAI-compatible or Anthropic APIs.
+#[derive(Deserialize)]
+struct Usage {
+    #[serde(alias = "input_tokens", default)]
+    prompt_tokens: i32,
+    #[serde(alias = "output_tokens", default)]
+    completion_tokens: i32,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: Option<String>,
+    code: Option<serde_json::Value>,
+}
+
+/// Add the model, usage and status of a buffered response to `builder`.
+fn record_response(mut builder: LLMCallBuilder, status: StatusCode, body: &[u8]) -> LLMCallBuilder {
+    let parsed = serde_json::from_slice::<ProxiedResponse>(body).ok();
+    let (model, usage, error) = match parsed {
+        Some(r) => (r.model, r.usage, r.error),
+        None => (None, None, None),
+    };
+    if let Some(model) = model {
+        builder = builder.model(model);
+    }
+    if let Some(usage) = usage {
+        builder = builder
+            .input_tokens(usage.prompt_tokens)
+            .output_tokens(usage.completion_tokens);
+    }
+    if status.is_success() {
+        return builder.status(CallStatus::Success);
+    }
+
+    let call_status = match status {
+        StatusCode::TOO_MANY_REQUESTS => CallStatus::RateLimited,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => CallStatus::Timeout,
+        _ => CallStatus::Error,
+    };
+    let code = error
+        .as_ref()
+        .and_then(|e| e.code.as_ref())
+        .map(|code| match code {
+            serde_json::Value::String(code) => code.clone(),
+            other => other.to_string(),
+        })
+        .unwrap_or_else(|| status.as_u16().to_string());
+    let message = error
+        .and_then(|e| e.message)
+        .unwrap_or_else(|| format!("HTTP {}", status.as_u16()));
+    builder
+        .status(call_status)
+        .error_code(code)
+        .error_message(message)
+}
+
+/// The fields of a streamed response event used for tracking.
+#[derive(Deserialize)]
+struct StreamEvent {
+    model: Option<String>,
+    usage: Option<StreamUsage>,
+    /// The message started by Anthropic's `message_start` event.
+    message: Option<Box<StreamEvent>>,
+}
+
+/// Token usage reported by a streamed event; each event may report only
+/// one of the counts.
+#[derive(Deserialize)]
+struct StreamUsage {
+    #[serde(alias = "input_tokens")]
+    prompt_tokens: Option<i32>,
+    #[serde(alias = "output_tokens")]
+    completion_tokens: Option<i32>,
+}
+
+/// Add the model and usage reported by the events of a successful streamed
+/// response to `builder`. Later events take precedence.
+fn record_stream(mut builder: LLMCallBuilder, body: &[u8]) -> LLMCallBuilder {
+    let text = String::from_utf8_lossy(body);
+    for event in sse::parse(&text).events {
+        let Ok(event) = serde_json::from_str::<StreamEvent>(&event.data) else {
+            continue;
+        };
+        for event in std::iter::once(&event).chain(event.message.as_deref()) {
+            if let Some(model) = &event.model {
+                builder = builder.model(model);
+            }
+            if let Some(usage) = &event.usage {
+                if let Some(tokens) = usage.prompt_tokens {
+                    builder = builder.input_tokens(tokens);
+                }
+                if let Some(tokens) = usage.completion_tokens {
+                    builder = builder.output_tokens(tokens);
+                }
+            }
+        }
+    }
+    builder.status(CallStatus::Success)
+}
+
+fn is_event_stream<B>(response: &Response<B>) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DiagnyxConfig;
+    use http_body_util::BodyExt;
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A body sent in chunks without a known size, like a chunked transfer.
+    struct Chunked(VecDeque<&'static str>);
+
+    impl Body for Chunked {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+            Poll::Ready(
+                self.0
+                    .pop_front()
+                    .map(|chunk| Ok(Frame::data(Bytes::from(chunk)))),
+            )
+        }
+    }
+
+    /// An upstream that reads the request, then answers with a fixed status,
+    /// content type and body chunks.
+    #[derive(Clone)]
+    struct Upstream {
+        status: StatusCode,
+        content_type: &'static str,
+        chunks: &'static [&'static str],
+    }
+
+    impl Upstream {
+        fn json(status: StatusCode, body: &'static [&'static str]) -> Self {
+            Self {
+                status,
+                content_type: "application/json",
+                chunks: body,
+            }
+        }
+    }
+
+    impl<B: Body<Data = Bytes> + Send + 'static> Service<Request<B>> for Upstream {
+        type Response = Response<Chunked>;
+        type Error = Infallible;
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<B>) -> Self::Future {
+            let upstream = self.clone();
+            Box::pin(async move {
+                let _ = request.into_body().collect().await;
+                let mut response =
+                    Response::new(Chunked(upstream.chunks.iter().copied().collect()));
+                *response.status_mut() = upstream.status;
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_TYPE, upstream.content_type.parse().unwrap());
+                Ok(response)
+            })
+        }
+    }
+
+    async fn tracked(
+        layer: fn(DiagnyxLayer) -> DiagnyxLayer,
+        upstream: Upstream,
+        body: &'static [&'static str],
+    ) -> serde_json::Value {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let diagnyx = Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key").base_url(server.uri()),
+        ));
+
+        let mut service = layer(DiagnyxLayer::new(Arc::clone(&diagnyx))).layer(upstream.clone());
+        let request = Request::post("/v1/chat/completions")
+            .body(Chunked(body.iter().copied().collect()))
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        let returned = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(returned, upstream.chunks.concat().as_bytes());
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        diagnyx.flush().await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        let batch: serde_json::Value = match requests.first() {
+            Some(request) => serde_json::from_slice(&request.body).unwrap(),
+            None => serde_json::json!({ "calls": [] }),
+        };
+        batch["calls"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_tracks_model_and_usage_of_chunked_bodies() {
+        let upstream = Upstream::json(
+            StatusCode::OK,
+            &[
+                r#"{"model":"gpt-4o-2024-08-06","#,
+                r#""usage":{"prompt_tokens":12,"completion_tokens":5}}"#,
+            ],
+        );
+        let calls = tracked(
+            |l| l,
+            upstream,
+            &[r#"{"model":"gpt-4o","#, r#""messages":[]}"#],
+        )
+        .await;
+
+        assert_eq!(calls[0]["model"], "gpt-4o-2024-08-06");
+        assert_eq!(calls[0]["input_tokens"], 12);
+        assert_eq!(calls[0]["output_tokens"], 5);
+        assert_eq!(calls[0]["endpoint"], "/v1/chat/completions");
+    }
+
+    #[tokio::test]
+    async fn test_tracks_error_responses() {
+        let upstream = Upstream::json(
+            StatusCode::TOO_MANY_REQUESTS,
+            &[r#"{"error":{"message":"slow down","code":"rate_limit_exceeded"}}"#],
+        );
+        let calls = tracked(|l| l, upstream, &[r#"{"model":"gpt-4o","messages":[]}"#]).await;
+
+        assert_eq!(calls[0]["model"], "gpt-4o");
+        assert_eq!(calls[0]["status"], "rate_limited");
+        assert_eq!(calls[0]["error_code"], "rate_limit_exceeded");
+        assert_eq!(calls[0]["error_message"], "slow down");
+    }
+
+    #[tokio::test]
+    async fn test_tracks_usage_of_streamed_responses() {
+        let upstream = Upstream {
+            status: StatusCode::OK,
+            content_type: "text/event-stream",
+            chunks: &[
+                "data: {\"model\":\"gpt-4o-2024-08-06\",\"choices\":[]}\n\n",
+                "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":7,",
+                "\"completion_tokens\":3}}\n\ndata: [DONE]\n\n",
+            ],
+        };
+        let calls = tracked(|l| l, upstream, &[r#"{"model":"gpt-4o","stream":true}"#]).await;
+        assert_eq!(calls[0]["model"], "gpt-4o-2024-08-06");
+        assert_eq!(calls[0]["input_tokens"], 7);
+        assert_eq!(calls[0]["output_tokens"], 3);
+
+        let upstream = Upstream {
+            status: StatusCode::OK,
+            content_type: "text/event-stream",
+            chunks: &[
+                "event: message_start\ndata: {\"type\":\"message_start\",\"message\":",
+                "{\"model\":\"claude-3-5-sonnet\",\"usage\":{\"input_tokens\":20,\"output_tokens\":1}}}\n\n",
+                "event: message_delta\ndata: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":9}}\n\n",
+            ],
+        };
+        let calls = tracked(
+            |l| l,
+            upstream,
+            &[r#"{"model":"claude-3-5-sonnet","stream":true}"#],
+        )
+        .await;
+        assert_eq!(calls[0]["input_tokens"], 20);
+        assert_eq!(calls[0]["output_tokens"], 9);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_and_oversized_requests_are_not_tracked() {
+        let upstream = Upstream::json(StatusCode::OK, &["{}"]);
+        let calls = tracked(|l| l, upstream.clone(), &["not json"]).await;
+        assert_eq!(calls.as_array().unwrap().len(), 0);
+
+        let calls = tracked(
+            |l| l.max_body_bytes(16),
+            upstream,
+            &[r#"{"model":"gpt-4o","#, r#""messages":[]}"#],
+        )
+        .await;
+        assert_eq!(calls.as_array().unwrap().len(), 0);
+    }
+}
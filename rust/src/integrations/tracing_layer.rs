@@ -0,0 +1,313 @@
+//! A `tracing-subscriber` layer tracking LLM calls from spans.
+//!
+//! Libraries instrumented with `tracing` often describe each LLM request as
+//! a span carrying the OpenTelemetry GenAI fields (`gen_ai.request.model`,
+//! `gen_ai.usage.input_tokens`, ...) or the older `llm.*` fields.
+//! [`TracingLayer`] watches for such spans and tracks a call when one
+//! closes: the model, provider and token usage come from the span's fields,
+//! whether set when it was created or recorded later, and the latency is the
+//! span's lifetime. Spans with an `error.type` field are tracked with an
+//! error status. Spans without a model are ignored.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::integrations::tracing_layer::TracingLayer;
+//! use diagnyx::DiagnyxClient;
+//! use std::sync::Arc;
+//! use tracing_subscriber::layer::SubscriberExt;
+//! use tracing_subscriber::util::SubscriberInitExt;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let diagnyx = Arc::new(DiagnyxClient::new("dx_live_your_api_key"));
+//! tracing_subscriber::registry()
+//!     .with(TracingLayer::new(diagnyx))
+//!     .init();
+//!
+//! let span = tracing::info_span!(
+//!     "chat",
+//!     gen_ai.system = "openai",
+//!     gen_ai.request.model = "gpt-4o",
+//!     gen_ai.usage.input_tokens = tracing::field::Empty,
+//! );
+//! span.record("gen_ai.usage.input_tokens", 120);
+//! # }
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::runtime::Handle;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::client::DiagnyxClient;
+use crate::types::{CallStatus, LLMCall, Provider};
+
+/// A layer tracking the spans of LLM calls with a [`DiagnyxClient`].
+#[derive(Clone)]
+pub struct TracingLayer {
+    diagnyx: Arc<DiagnyxClient>,
+    runtime: Handle,
+}
+
+impl TracingLayer {
+    /// Track LLM call spans with `diagnyx`.
+    ///
+    /// Must be called from within a Tokio runtime, on which calls are then
+    /// tracked.
+    pub fn new(diagnyx: Arc<DiagnyxClient>) -> Self {
+        Self {
+            diagnyx,
+            runtime: Handle::current(),
+        }
+    }
+}
+
+impl fmt::Debug for TracingLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TracingLayer").finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for TracingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !attrs.fields().iter().any(|f| is_llm_field(f.name())) {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = LlmFields::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(LlmSpan {
+            start: Instant::now(),
+            fields,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(llm) = extensions.get_mut::<LlmSpan>() {
+            values.record(&mut llm.fields);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(llm) = span.extensions_mut().remove::<LlmSpan>() else {
+            return;
+        };
+        let Some(call) = llm.into_call() else {
+            return;
+        };
+        let diagnyx = Arc::clone(&self.diagnyx);
+        self.runtime.spawn(async move { diagnyx.track(call).await });
+    }
+}
+
+/// Whether `name` is a field describing an LLM call.
+fn is_llm_field(name: &str) -> bool {
+    name.starts_with("gen_ai.") || name.starts_with("llm.")
+}
+
+/// An open span of an LLM call.
+struct LlmSpan {
+    start: Instant,
+    fields: LlmFields,
+}
+
+impl LlmSpan {
+    fn into_call(self) -> Option<LLMCall> {
+        let fields = self.fields;
+        let model = fields.response_model.or(fields.request_model)?;
+        let mut call = LLMCall::builder()
+            .provider(fields.system.as_deref().map_or(Provider::Custom, provider))
+            .model(model)
+            .input_tokens(fields.input_tokens.unwrap_or(0) as i32)
+            .output_tokens(fields.output_tokens.unwrap_or(0) as i32)
+            .latency_ms(self.start.elapsed().as_millis() as i64);
+        if let Some(error) = fields.error {
+            call = call.status(CallStatus::Error).error_code(error);
+        }
+        Some(call.build())
+    }
+}
+
+/// The provider named by a `gen_ai.system` value.
+fn provider(system: &str) -> Provider {
+    let system = system.to_ascii_lowercase();
+    if system.starts_with("openai") {
+        Provider::OpenAI
+    } else if system.starts_with("anthropic") {
+        Provider::Anthropic
+    } else if system.starts_with("az") {
+        Provider::Azure
+    } else if system.starts_with("aws") || system.contains("bedrock") {
+        Provider::Aws
+    } else if ["gcp", "google", "gemini", "vertex"]
+        .iter()
+        .any(|prefix| system.starts_with(prefix))
+    {
+        Provider::Google
+    } else {
+        Provider::Custom
+    }
+}
+
+/// LLM call fields recorded on a span.
+#[derive(Debug, Default)]
+struct LlmFields {
+    system: Option<String>,
+    request_model: Option<String>,
+    response_model: Option<String>,
+    input_tokens: Option<i64>,
+    output_tokens: Option<i64>,
+    error: Option<String>,
+}
+
+impl LlmFields {
+    fn set_str(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "gen_ai.system" | "gen_ai.provider.name" | "llm.system" | "llm.vendor" => {
+                self.system = Some(value)
+            }
+            "gen_ai.request.model" | "llm.request.model" => self.request_model = Some(value),
+            "gen_ai.response.model" | "llm.response.model" => self.response_model = Some(value),
+            "error.type" => self.error = Some(value),
+            name => {
+                if let Ok(tokens) = value.parse() {
+                    self.set_tokens(name, tokens);
+                }
+            }
+        }
+    }
+
+    fn set_tokens(&mut self, name: &str, tokens: i64) {
+        match name {
+            "gen_ai.usage.input_tokens"
+            | "gen_ai.usage.prompt_tokens"
+            | "llm.usage.prompt_tokens" => self.input_tokens = Some(tokens),
+            "gen_ai.usage.output_tokens"
+            | "gen_ai.usage.completion_tokens"
+            | "llm.usage.completion_tokens" => self.output_tokens = Some(tokens),
+            _ => {}
+        }
+    }
+}
+
+impl Visit for LlmFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set_str(field, value.to_string());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set_tokens(field.name(), value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set_tokens(field.name(), value.min(i64::MAX as u64) as i64);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if is_llm_field(field.name()) || field.name() == "error.type" {
+            self.set_str(field, format!("{:?}", value).trim_matches('"').to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DiagnyxConfig;
+    use std::time::Duration;
+    use tracing_subscriber::layer::SubscriberExt;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_layer_tracks_llm_spans() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let diagnyx = Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000),
+        ));
+        let subscriber =
+            tracing_subscriber::registry().with(TracingLayer::new(Arc::clone(&diagnyx)));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "chat",
+                gen_ai.system = "anthropic",
+                gen_ai.request.model = "claude-3-5-sonnet",
+                gen_ai.usage.input_tokens = 120u64,
+                gen_ai.usage.output_tokens = tracing::field::Empty,
+                error.type = tracing::field::Empty,
+            );
+            span.record("gen_ai.usage.output_tokens", 30);
+            drop(span);
+
+            let failed = tracing::info_span!(
+                "completion",
+                llm.system = "openai",
+                llm.request.model = "gpt-4o",
+                error.type = tracing::field::Empty,
+            );
+            failed.record("error.type", "rate_limit");
+            drop(failed);
+
+            drop(tracing::info_span!("unrelated", user = "alice"));
+            drop(tracing::info_span!("no_model", gen_ai.system = "openai"));
+        });
+
+        for _ in 0..100 {
+            if diagnyx.buffer_size().await == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        diagnyx.flush().await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let mut calls = body["calls"].as_array().unwrap().clone();
+        calls.sort_by_key(|call| call["model"].as_str().unwrap().to_string());
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0]["provider"], "anthropic");
+        assert_eq!(calls[0]["model"], "claude-3-5-sonnet");
+        assert_eq!(calls[0]["input_tokens"], 120);
+        assert_eq!(calls[0]["output_tokens"], 30);
+        assert_eq!(calls[0]["status"], "success");
+        assert_eq!(calls[1]["provider"], "openai");
+        assert_eq!(calls[1]["status"], "error");
+        assert_eq!(calls[1]["error_code"], "rate_limit");
+    }
+
+    #[test]
+    fn test_provider_from_system() {
+        assert_eq!(provider("openai"), Provider::OpenAI);
+        assert_eq!(provider("az.ai.openai"), Provider::Azure);
+        assert_eq!(provider("aws.bedrock"), Provider::Aws);
+        assert_eq!(provider("gcp.vertex_ai"), Provider::Google);
+        assert_eq!(provider("mistral_ai"), Provider::Custom);
+    }
+}
@@ -24,54 +24,159 @@
 //! }
 //! ```
 //!
-//! # Streaming Guardrails
+//! # Features
 //!
-//! ```rust,no_run
-//! use diagnyx::guardrails::{StreamingGuardrails, StreamingGuardrailsConfig, StreamingEvent};
-//!
-//! #[tokio::main]
-//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
-//!     let config = StreamingGuardrailsConfig::new(
-//!         "dx_live_your_api_key",
-//!         "org-123",
-//!         "proj-456",
-//!     );
-//!
-//!     let client = StreamingGuardrails::new(config);
-//!
-//!     // Start a session and evaluate tokens
-//!     let session = client.start_session(Some("What is 2+2?")).await?;
+//! The client and its types are always available. Everything else is opt-in
+//! so that applications only compile the dependencies they use:
 //!
-//!     for token in vec!["The", "answer", "is", "4"] {
-//!         match client.evaluate_token(token).await? {
-//!             StreamingEvent::TokenAllowed(data) => println!("OK: {}", data.token),
-//!             StreamingEvent::EarlyTermination(data) => {
-//!                 println!("Blocked: {}", data.reason);
-//!                 break;
-//!             }
-//!             _ => {}
-//!         }
-//!     }
+//! | Feature         | Enables                                          |
+//! |-----------------|--------------------------------------------------|
+//! | `alerts`        | [`alerts`] alert rules and notification channels |
+//! | `analytics`     | [`analytics`] spend and cost queries             |
+//! | `blocking`      | [`blocking`] client for non-async applications   |
+//! | `callbacks`     | [`callbacks`] handler for LLM framework hooks    |
+//! | `cassette`      | [`cassette`] request recording and replay        |
+//! | `ci`            | [`ci`] budget and evaluation gates               |
+//! | `cli`           | The `diagnyx` command-line tool                  |
+//! | `compression`   | [`compression`] of batch ingest payloads         |
+//! | `datasets`      | [`datasets`] examples for evals and fine-tuning  |
+//! | `evaluations`   | [`evaluations`] evaluation runs                  |
+//! | `feedback`      | [`feedback`] user feedback                       |
+//! | `genai`         | [`callbacks::genai`] adapter for genai clients   |
+//! | `guardrails`    | [`guardrails`] streaming guardrails              |
+//! | `integrations`  | Provider integrations (`openai`, `anthropic`)    |
+//! | `llm-chain`     | [`callbacks::llm_chain`] executor wrapper        |
+//! | `macros`        | `#[diagnyx::track]` and `#[derive(PromptVars)]`  |
+//! | `otel`          | [`otel`] span export of tracked calls            |
+//! | `prompts`       | [`prompts`] prompt template registry             |
+//! | `rig`           | [`callbacks::rig`] hook for rig agents           |
+//! | `signals`       | Flushing calls on SIGINT and SIGTERM             |
+//! | `streaming`     | [`streaming`] tracking of streamed responses     |
+//! | `tokenizers`    | [`tokens`] local token counting                  |
+//! | `tower`         | [`integrations::tower`] layer for LLM gateways   |
+//! | `tracing`       | Debug logs as `tracing` events and spans         |
+//! | `tracing-layer` | [`integrations::tracing_layer`] for LLM spans    |
+//! | `triage`        | [`triage`] of negative feedback for review       |
+//! | `uuid`          | Random identifiers, e.g. [`TraceId::generate`]   |
+//! | `full`          | All of the above                                 |
 //!
-//!     let result = client.complete_session().await?;
-//!     println!("Allowed: {}", result.allowed);
-//!     Ok(())
-//! }
+//! ```toml
+//! [dependencies]
+//! diagnyx = { version = "0.1", features = ["guardrails", "feedback"] }
 //! ```
 
+// Lets `#[track]` expand to `::diagnyx` paths in this crate's own tests.
+#[cfg(all(test, feature = "macros"))]
+extern crate self as diagnyx;
+
+#[cfg(feature = "alerts")]
+pub mod alerts;
+#[cfg(feature = "analytics")]
+pub mod analytics;
+pub mod audit;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod budgets;
+mod buffer;
+#[cfg(feature = "callbacks")]
+pub mod callbacks;
+#[cfg(feature = "cassette")]
+pub mod cassette;
+pub mod chunker;
+#[cfg(feature = "ci")]
+pub mod ci;
 mod client;
-mod types;
+mod compact;
+#[cfg(feature = "compression")]
+pub mod compression;
+#[cfg(feature = "datasets")]
+pub mod datasets;
+pub mod enrich;
 mod error;
-pub mod callbacks;
-pub mod guardrails;
+#[cfg(feature = "evaluations")]
+pub mod evaluations;
+pub mod events;
+#[cfg(feature = "feedback")]
 pub mod feedback;
+pub mod filter;
+mod global;
+#[cfg(feature = "guardrails")]
+pub mod guardrails;
+mod health;
+mod heartbeat;
+mod host;
+mod http;
+mod ids;
+pub mod instrument;
+#[cfg(any(
+    feature = "openai",
+    feature = "anthropic",
+    feature = "tower",
+    feature = "tracing-layer"
+))]
+pub mod integrations;
+mod limit;
+mod logger;
+#[cfg(feature = "otel")]
+pub mod otel;
+mod parked;
+pub mod pricing;
+#[cfg(feature = "prompts")]
+pub mod prompts;
+mod ratelimit;
+pub mod redact;
+mod report;
+pub mod retry;
+pub mod sampling;
+mod schedule;
+mod spend;
+mod sse;
+#[cfg(feature = "streaming")]
+pub mod streaming;
+#[cfg(feature = "tokenizers")]
+pub mod tokens;
+pub mod trace;
+#[cfg(feature = "triage")]
+pub mod triage;
+mod types;
+pub mod upload;
+mod watermark;
 
-pub use client::DiagnyxClient;
-pub use types::*;
+#[cfg(feature = "analytics")]
+pub use analytics::{
+    AnalyticsClient, AnalyticsClientConfig, CostComparison, CostDelta, CostGroup, GroupBy,
+    LatencyPercentiles, LeaderboardDimension, LeaderboardEntry, MetadataSpend, ModelCost, Page,
+    PageOptions, ProjectUsage, RankBy, SpendPeriod, UserSpend,
+};
+#[cfg(feature = "callbacks")]
+pub use callbacks::{CallbackOptions, DiagnyxCallbackHandler};
+pub use client::{
+    track_call, track_call_with_content, DiagnyxClient, FlushReport, HttpSink, NdjsonFileSink,
+    ShutdownReport, Sink, StdoutSink,
+};
+#[cfg(feature = "macros")]
+pub use diagnyx_macros::track;
+pub use enrich::Enricher;
 pub use error::DiagnyxError;
-pub use callbacks::{DiagnyxCallbackHandler, CallbackOptions};
+pub use events::{EventBus, SdkEvent};
+#[cfg(feature = "feedback")]
 pub use feedback::{
-    FeedbackClient, FeedbackClientConfig, Feedback, FeedbackType, FeedbackSentiment,
-    FeedbackOptions, FeedbackOptionsBuilder, FeedbackSummary, ListFeedbackOptions,
-    FeedbackListResult,
+    Feedback, FeedbackClient, FeedbackClientConfig, FeedbackListResult, FeedbackOptions,
+    FeedbackOptionsBuilder, FeedbackRateLimit, FeedbackSentiment, FeedbackSummary, FeedbackType,
+    GuardrailContext, ListFeedbackOptions,
 };
+pub use filter::{CallFilter, FilterAction};
+pub use global::{global, init, shutdown};
+pub use host::{CPU_COUNT_KEY, INSTANCE_TYPE_KEY, MEMORY_LIMIT_KEY};
+pub use http::{ClientCertificate, HttpConfig};
+#[cfg(feature = "uuid")]
+pub use ids::{set_id_generator, IdGenerator};
+pub use ids::{ProjectId, SessionId, TraceId, MAX_ID_LENGTH};
+pub use instrument::{track_output, TrackOutput};
+pub use limit::TRIMMED_KEY;
+pub use ratelimit::RateLimiter;
+pub use redact::ContentRedactor;
+pub use retry::RetryPolicy;
+pub use types::*;
+pub use upload::{UploadHandle, UploadProgress};
+pub use watermark::BufferWatermarks;
@@ -0,0 +1,138 @@
+//! Per-call size limit.
+//!
+//! The ingest API rejects a whole batch if one of its calls is too large. With
+//! `max_call_bytes` set, a call serializing to more than that is trimmed when
+//! tracked: its captured response and then prompt are truncated, then its
+//! metadata entries are dropped, largest first. The trimmed fields are listed
+//! in the call's [`TRIMMED_KEY`] metadata entry so the loss stays visible.
+
+use serde_json::Value;
+
+use crate::types::LLMCall;
+
+/// Metadata key listing the fields trimmed from a call.
+pub const TRIMMED_KEY: &str = "diagnyx.trimmed";
+
+/// Trim `call` so it serializes to at most `max_bytes`, returning the
+/// trimmed fields.
+///
+/// Metrics, identifiers and other fields are never trimmed, so a call can
+/// still exceed `max_bytes` if they alone do.
+pub(crate) fn limit_call_size(call: &mut LLMCall, max_bytes: usize) -> Vec<String> {
+    let mut trimmed = Vec::new();
+    if serialized_len(call) <= max_bytes {
+        return trimmed;
+    }
+
+    for field in ["full_response", "full_prompt"] {
+        loop {
+            let excess = serialized_len(call).saturating_sub(max_bytes);
+            let content = match field {
+                "full_response" => &mut call.full_response,
+                _ => &mut call.full_prompt,
+            };
+            let Some(text) = content else {
+                break;
+            };
+            if excess == 0 {
+                return trimmed;
+            }
+            if !trimmed.iter().any(|f| f == field) {
+                trimmed.push(field.to_string());
+                record(call, &trimmed);
+                continue;
+            }
+            if excess >= text.len() {
+                *content = None;
+            } else {
+                let mut len = text.len() - excess;
+                while !text.is_char_boundary(len) {
+                    len -= 1;
+                }
+                text.truncate(len);
+            }
+        }
+    }
+
+    // Largest entries first, by key among equal sizes, so the same call is
+    // always trimmed the same way
+    let mut entries: Vec<(usize, String)> = call
+        .metadata
+        .iter()
+        .flatten()
+        .filter(|(key, _)| *key != TRIMMED_KEY)
+        .map(|(key, value)| (key.len() + value_len(value), key.clone()))
+        .collect();
+    entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    for (_, key) in entries {
+        if serialized_len(call) <= max_bytes {
+            break;
+        }
+        if let Some(metadata) = &mut call.metadata {
+            metadata.remove(&key);
+        }
+        trimmed.push(format!("metadata.{}", key));
+        record(call, &trimmed);
+    }
+    trimmed
+}
+
+/// List `trimmed` in the call's metadata.
+fn record(call: &mut LLMCall, trimmed: &[String]) {
+    call.metadata
+        .get_or_insert_with(Default::default)
+        .insert(TRIMMED_KEY.to_string(), trimmed.into());
+}
+
+fn serialized_len(call: &LLMCall) -> usize {
+    serde_json::to_vec(call).map_or(0, |bytes| bytes.len())
+}
+
+fn value_len(value: &Value) -> usize {
+    serde_json::to_vec(value).map_or(0, |bytes| bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Provider;
+    use std::collections::HashMap;
+
+    fn call() -> LLMCall {
+        LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .full_prompt("p".repeat(2000))
+            .full_response("é".repeat(1000))
+            .metadata(HashMap::from([
+                ("small".to_string(), "x".into()),
+                ("large".to_string(), "y".repeat(500).into()),
+            ]))
+            .build()
+    }
+
+    #[test]
+    fn test_limit_call_size_trims_content_then_metadata() {
+        let mut untouched = call();
+        assert!(limit_call_size(&mut untouched, 10_000).is_empty());
+        assert!(!untouched.metadata.unwrap().contains_key(TRIMMED_KEY));
+
+        let mut truncated = call();
+        let max = serialized_len(&truncated) - 500;
+        assert_eq!(limit_call_size(&mut truncated, max), ["full_response"]);
+        assert!(serialized_len(&truncated) <= max);
+        assert_eq!(truncated.full_prompt.as_deref().map(str::len), Some(2000));
+        assert_eq!(
+            truncated.metadata.as_ref().unwrap()[TRIMMED_KEY],
+            serde_json::json!(["full_response"])
+        );
+
+        let mut stripped = call();
+        let trimmed = limit_call_size(&mut stripped, 400);
+        assert_eq!(trimmed, ["full_response", "full_prompt", "metadata.large"]);
+        assert!(serialized_len(&stripped) <= 400);
+        assert!(stripped.full_response.is_none() && stripped.full_prompt.is_none());
+        assert!(stripped.metadata.as_ref().unwrap().contains_key("small"));
+        assert_eq!(stripped.model, "gpt-4");
+    }
+}
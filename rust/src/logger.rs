@@ -0,0 +1,99 @@
+//! Debug logging scoped to sessions.
+//!
+//! Each line is prefixed with the component logging it and, for a child
+//! logger, with the ID of the session it belongs to, e.g.
+//! `[Diagnyx Guardrails] [session=sess-1] Completing session`, so the logs
+//! of concurrent sessions can be told apart. A child logger can be made
+//! more or less verbose than its parent for the lifetime of its session.
+//!
+//! With the `tracing` feature, lines are emitted as `tracing` events with
+//! the `diagnyx` target instead of being printed, and each child logger
+//! opens a span carrying its scope, so they can be filtered and captured by
+//! the application's subscriber. The `debug` settings still apply.
+
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub(crate) struct Logger {
+    prefix: String,
+    debug: bool,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Logger {
+    pub(crate) fn new(component: &str, debug: bool) -> Self {
+        Self {
+            prefix: format!("[{}]", component),
+            debug,
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!(target: "diagnyx", "diagnyx", component),
+        }
+    }
+
+    /// A logger also prefixing lines with `key=value`.
+    #[cfg_attr(not(feature = "guardrails"), allow(dead_code))]
+    pub(crate) fn child(&self, key: &str, value: impl fmt::Display) -> Self {
+        Self {
+            prefix: format!("{} [{}={}]", self.prefix, key, value),
+            debug: self.debug,
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!(
+                target: "diagnyx",
+                parent: &self.span,
+                "scope",
+                key,
+                value = %value
+            ),
+        }
+    }
+
+    #[cfg_attr(not(feature = "guardrails"), allow(dead_code))]
+    pub(crate) fn set_debug(&mut self, debug: bool) {
+        self.debug = debug;
+    }
+
+    pub(crate) fn log(&self, message: &str) {
+        if !self.debug {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        self.span
+            .in_scope(|| tracing::debug!(target: "diagnyx", "{}", message));
+        #[cfg(not(feature = "tracing"))]
+        println!("{}", self.line(message));
+    }
+
+    /// Log a failure, on stderr without the `tracing` feature.
+    pub(crate) fn warn(&self, message: &str) {
+        if !self.debug {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        self.span
+            .in_scope(|| tracing::warn!(target: "diagnyx", "{}", message));
+        #[cfg(not(feature = "tracing"))]
+        eprintln!("{}", self.line(message));
+    }
+
+    #[cfg_attr(feature = "tracing", allow(dead_code))]
+    fn line(&self, message: &str) -> String {
+        format!("{} {}", self.prefix, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_child_prefixes_scope() {
+        let logger = Logger::new("Diagnyx Guardrails", true);
+        let session = logger.child("session", "sess-1");
+        assert_eq!(
+            session.line("Completing session"),
+            "[Diagnyx Guardrails] [session=sess-1] Completing session"
+        );
+        assert_eq!(logger.line("Starting"), "[Diagnyx Guardrails] Starting");
+    }
+}
@@ -0,0 +1,317 @@
+//! OpenTelemetry export of tracked calls.
+//!
+//! When a [`TracerProvider`] is configured, every tracked call is also
+//! recorded as a span following the OpenTelemetry GenAI semantic
+//! conventions (`gen_ai.request.model`, `gen_ai.usage.input_tokens`, ...).
+//!
+//! The span joins the trace of the call's `trace_id` and `span_id` when
+//! they are valid W3C identifiers, or otherwise the span active when the
+//! call is tracked. Its IDs fill in the call's `trace_id` and `span_id`
+//! when the call has none, so Diagnyx data and existing OTLP traces share
+//! the same IDs, and are always recorded as `otel.trace_id` and
+//! `otel.span_id` metadata. IDs the call already has are kept.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::{DiagnyxClient, DiagnyxConfig};
+//!
+//! # fn provider() -> opentelemetry::trace::noop::NoopTracerProvider {
+//! #     opentelemetry::trace::noop::NoopTracerProvider::new()
+//! # }
+//! // The application's provider, e.g. an OTLP-exporting SdkTracerProvider
+//! let provider = provider();
+//! let client = DiagnyxClient::with_config(
+//!     DiagnyxConfig::new("dx_live_your_api_key").tracer_provider(&provider),
+//! );
+//! ```
+
+use opentelemetry::global::BoxedTracer;
+use opentelemetry::trace::{
+    Span, SpanContext, SpanId, SpanKind, Status, TraceContextExt, TraceFlags, TraceId, TraceState,
+    Tracer, TracerProvider,
+};
+use opentelemetry::{Context, InstrumentationScope, KeyValue};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::ids;
+use crate::types::{CallStatus, LLMCall, Provider};
+
+/// Records tracked calls as OpenTelemetry spans.
+#[derive(Clone)]
+pub struct OtelExporter {
+    tracer: Arc<BoxedTracer>,
+}
+
+impl fmt::Debug for OtelExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OtelExporter")
+    }
+}
+
+impl OtelExporter {
+    /// Create an exporter using a tracer from `provider`.
+    pub fn new<P, T, S>(provider: &P) -> Self
+    where
+        P: TracerProvider<Tracer = T>,
+        T: Tracer<Span = S> + Send + Sync + 'static,
+        S: Span + Send + Sync + 'static,
+    {
+        let scope = InstrumentationScope::builder("diagnyx")
+            .with_version(env!("CARGO_PKG_VERSION"))
+            .build();
+        let tracer = provider.tracer_with_scope(scope);
+        Self {
+            tracer: Arc::new(BoxedTracer::new(Box::new(tracer))),
+        }
+    }
+
+    /// Record `call` as a span, filling in its missing trace and span IDs
+    /// with the span's.
+    pub(crate) fn export(&self, call: &mut LLMCall) {
+        let operation = operation_name(call.endpoint.as_deref());
+        let end = SystemTime::from(call.timestamp);
+        let start = end
+            .checked_sub(Duration::from_millis(call.latency_ms.max(0) as u64))
+            .unwrap_or(end);
+
+        let mut attributes = vec![
+            KeyValue::new("gen_ai.operation.name", operation),
+            KeyValue::new("gen_ai.system", system_name(&call.provider)),
+            KeyValue::new("gen_ai.request.model", call.model.clone()),
+            KeyValue::new("gen_ai.usage.input_tokens", i64::from(call.input_tokens)),
+            KeyValue::new("gen_ai.usage.output_tokens", i64::from(call.output_tokens)),
+        ];
+        if let Some(cost) = call.estimated_cost_usd {
+            attributes.push(KeyValue::new("diagnyx.cost_usd", cost));
+        }
+        if let Some(ttft) = call.ttft_ms {
+            attributes.push(KeyValue::new("diagnyx.ttft_ms", ttft));
+        }
+        if let Some(project) = &call.project_id {
+            attributes.push(KeyValue::new("diagnyx.project_id", project.to_string()));
+        }
+        if let Some(env) = &call.environment {
+            attributes.push(KeyValue::new("deployment.environment", env.clone()));
+        }
+        if call.status != CallStatus::Success {
+            let error_type = call
+                .error_code
+                .clone()
+                .unwrap_or_else(|| status_name(&call.status).to_string());
+            attributes.push(KeyValue::new("error.type", error_type));
+        }
+
+        let trace_id = call
+            .trace_id
+            .as_ref()
+            .and_then(|id| TraceId::from_hex(id.as_str()).ok());
+        let span_id = call
+            .span_id
+            .as_deref()
+            .and_then(|id| SpanId::from_hex(id).ok());
+        let parent = match (trace_id, span_id) {
+            (Some(trace_id), Some(span_id)) => {
+                Context::new().with_remote_span_context(SpanContext::new(
+                    trace_id,
+                    span_id,
+                    TraceFlags::SAMPLED,
+                    true,
+                    TraceState::default(),
+                ))
+            }
+            _ => Context::current(),
+        };
+
+        let mut builder = self
+            .tracer
+            .span_builder(format!("{} {}", operation, call.model))
+            .with_kind(SpanKind::Client)
+            .with_start_time(start)
+            .with_attributes(attributes);
+        if !parent.has_active_span() {
+            builder.trace_id = trace_id;
+        }
+        let mut span = self.tracer.build_with_context(builder, &parent);
+
+        if call.status != CallStatus::Success {
+            let message = call.error_message.clone().unwrap_or_default();
+            span.set_status(Status::error(message));
+        }
+
+        let context = span.span_context();
+        if context.is_valid() {
+            let trace_id = context.trace_id().to_string();
+            let span_id = context.span_id().to_string();
+            if call.trace_id.is_none() {
+                call.trace_id = ids::TraceId::new(trace_id.clone()).ok();
+            }
+            if call.span_id.is_none() {
+                call.span_id = Some(span_id.clone());
+            }
+            let metadata = call.metadata.get_or_insert_with(Default::default);
+            metadata.insert("otel.trace_id".to_string(), trace_id.into());
+            metadata.insert("otel.span_id".to_string(), span_id.into());
+        }
+        span.end_with_timestamp(end);
+    }
+}
+
+/// GenAI operation name for a provider endpoint.
+fn operation_name(endpoint: Option<&str>) -> &'static str {
+    match endpoint {
+        Some(endpoint) if endpoint.ends_with("/embeddings") => "embeddings",
+        Some(endpoint) if endpoint.ends_with("/completions") && !endpoint.contains("chat") => {
+            "text_completion"
+        }
+        _ => "chat",
+    }
+}
+
+/// GenAI system name for a provider.
+fn system_name(provider: &Provider) -> &'static str {
+    match provider {
+        Provider::OpenAI => "openai",
+        Provider::Anthropic => "anthropic",
+        Provider::Google => "gcp.gemini",
+        Provider::Azure => "azure.ai.openai",
+        Provider::Aws => "aws.bedrock",
+        Provider::Custom => "_OTHER",
+    }
+}
+
+fn status_name(status: &CallStatus) -> &'static str {
+    match status {
+        CallStatus::Success => "success",
+        CallStatus::Error => "error",
+        CallStatus::Timeout => "timeout",
+        CallStatus::RateLimited => "rate_limited",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::Value;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+    fn provider() -> (SdkTracerProvider, InMemorySpanExporter) {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        (provider, exporter)
+    }
+
+    fn call() -> LLMCall {
+        let mut call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4o")
+            .endpoint("/chat/completions")
+            .input_tokens(100)
+            .output_tokens(20)
+            .latency_ms(1500)
+            .build();
+        call.timestamp = chrono::Utc::now();
+        call
+    }
+
+    fn attribute(span: &opentelemetry_sdk::trace::SpanData, key: &str) -> Option<Value> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    #[test]
+    fn test_exports_genai_span_and_links_ids() {
+        let (provider, exporter) = provider();
+        let otel = OtelExporter::new(&provider);
+
+        let mut call = call();
+        otel.export(&mut call);
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span.name, "chat gpt-4o");
+        assert_eq!(span.span_kind, SpanKind::Client);
+        assert_eq!(
+            attribute(span, "gen_ai.request.model"),
+            Some(Value::from("gpt-4o"))
+        );
+        assert_eq!(
+            attribute(span, "gen_ai.usage.input_tokens"),
+            Some(Value::I64(100))
+        );
+        assert_eq!(
+            span.end_time.duration_since(span.start_time).unwrap(),
+            Duration::from_millis(1500)
+        );
+
+        assert_eq!(
+            call.trace_id.unwrap().as_str(),
+            span.span_context.trace_id().to_string()
+        );
+        assert_eq!(
+            call.span_id.unwrap(),
+            span.span_context.span_id().to_string()
+        );
+    }
+
+    #[test]
+    fn test_joins_trace_of_call_ids() {
+        let (provider, exporter) = provider();
+        let otel = OtelExporter::new(&provider);
+
+        let mut call = call();
+        call.trace_id = Some(ids::TraceId::from_static(
+            "4bf92f3577b34da6a3ce929d0e0e4736",
+        ));
+        call.span_id = Some("00f067aa0ba902b7".to_string());
+        call.status = CallStatus::Error;
+        call.error_code = Some("rate_limit_exceeded".to_string());
+        otel.export(&mut call);
+
+        let span = &exporter.get_finished_spans().unwrap()[0];
+        assert_eq!(
+            span.span_context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_eq!(span.parent_span_id.to_string(), "00f067aa0ba902b7");
+        assert!(matches!(span.status, Status::Error { .. }));
+        assert_eq!(
+            attribute(span, "error.type"),
+            Some(Value::from("rate_limit_exceeded"))
+        );
+        assert_eq!(
+            call.trace_id.unwrap().as_str(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        // The call keeps its own span ID; the exported span's is in metadata
+        assert_eq!(call.span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert_eq!(
+            call.metadata.unwrap()["otel.span_id"],
+            span.span_context.span_id().to_string()
+        );
+    }
+
+    #[test]
+    fn test_joins_active_span() {
+        let (provider, exporter) = provider();
+        let otel = OtelExporter::new(&provider);
+        let app_tracer = provider.tracer("app");
+
+        let mut call = call();
+        let request = app_tracer.start("handle_request");
+        let request_context = request.span_context().clone();
+        let _guard = Context::current_with_span(request).attach();
+        otel.export(&mut call);
+
+        let span = &exporter.get_finished_spans().unwrap()[0];
+        assert_eq!(span.span_context.trace_id(), request_context.trace_id());
+        assert_eq!(span.parent_span_id, request_context.span_id());
+    }
+}
@@ -0,0 +1,174 @@
+//! Calls parked on disk.
+//!
+//! Several processes, such as the workers of a pre-forked server, can share
+//! one `parked_buffer_path`. Writes to it are serialized with an advisory
+//! lock on a `.lock` file next to it, and each process appends the calls
+//! it parks, so workers parking at the same time do not overwrite each
+//! other. A process removes the calls it appended from its buffer, so that
+//! no call is appended twice. The file is replaced atomically, so a crash
+//! while writing leaves the previous calls intact. The first process to find parked calls takes the
+//! lock on the `.drain` file next to it and is then the only one loading
+//! them to send them, until it exits or is parked itself.
+//!
+//! Locks belong to the open file, so clients should be created after
+//! forking: a child would otherwise share its parent's drain lock.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::error::DiagnyxError;
+use crate::types::LLMCall;
+
+/// The parked buffer file of one client.
+#[derive(Debug)]
+pub(crate) struct ParkedQueue {
+    path: PathBuf,
+    /// The drain lock, while this client holds it.
+    drain: Mutex<Option<File>>,
+}
+
+impl ParkedQueue {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            drain: Mutex::new(None),
+        }
+    }
+
+    /// Take the calls parked by any process, removing them from the file.
+    ///
+    /// Yields no calls unless this client holds the drain lock, or can
+    /// acquire it because there are calls to take. A missing or unreadable
+    /// file yields no calls.
+    pub(crate) fn take(&self) -> Vec<LLMCall> {
+        if !self.acquire_drain() {
+            return Vec::new();
+        }
+        self.locked(|| {
+            let calls = read_parked(&self.path);
+            if !calls.is_empty() {
+                std::fs::remove_file(&self.path)?;
+            }
+            Ok(calls)
+        })
+        .unwrap_or_default()
+    }
+
+    /// Append the calls of `buffer`, removing them from it once they are
+    /// saved.
+    pub(crate) fn append(&self, buffer: &mut Vec<LLMCall>) -> Result<(), DiagnyxError> {
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        self.locked(|| {
+            let mut calls = read_parked(&self.path);
+            calls.extend_from_slice(buffer);
+            // Only the holder of the write lock uses the temporary file
+            let temp = sibling(&self.path, "tmp");
+            std::fs::write(&temp, serde_json::to_string(&calls)?)?;
+            std::fs::rename(&temp, &self.path)?;
+            Ok(())
+        })?;
+        buffer.clear();
+        Ok(())
+    }
+
+    /// Let another process drain the queue.
+    pub(crate) fn release_drain(&self) {
+        self.drain.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    fn acquire_drain(&self) -> bool {
+        let mut drain = self.drain.lock().unwrap_or_else(|e| e.into_inner());
+        if drain.is_none() && self.path.exists() {
+            *drain = open_lock(&self.path, "drain")
+                .ok()
+                .filter(|file| file.try_lock().is_ok());
+        }
+        drain.is_some()
+    }
+
+    /// Run `f` holding the write lock.
+    fn locked<T>(&self, f: impl FnOnce() -> Result<T, DiagnyxError>) -> Result<T, DiagnyxError> {
+        let lock = open_lock(&self.path, "lock")?;
+        lock.lock()?;
+        // Released when `lock` is closed
+        f()
+    }
+}
+
+/// Open the lock file with `extension` next to `path`, creating it and its
+/// directory if needed.
+fn open_lock(path: &Path, extension: &str) -> Result<File, DiagnyxError> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    Ok(OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(sibling(path, extension))?)
+}
+
+/// The path of the file with `extension` next to `path`.
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut sibling = OsString::from(path);
+    sibling.push(".");
+    sibling.push(extension);
+    sibling.into()
+}
+
+fn read_parked(path: &Path) -> Vec<LLMCall> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Provider;
+
+    fn call(model: &str) -> LLMCall {
+        LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model(model)
+            .build()
+    }
+
+    #[test]
+    fn test_one_queue_drains_while_others_append() {
+        let path =
+            std::env::temp_dir().join(format!("diagnyx-queue-{}.json", uuid::Uuid::new_v4()));
+        let drainer = ParkedQueue::new(path.clone());
+        let worker = ParkedQueue::new(path.clone());
+        let other = ParkedQueue::new(path.clone());
+        assert!(drainer.take().is_empty());
+
+        let mut parked = vec![call("gpt-4")];
+        worker.append(&mut parked).unwrap();
+        assert!(parked.is_empty());
+        assert_eq!(drainer.take().len(), 1);
+
+        parked.push(call("gpt-4o"));
+        worker.append(&mut parked).unwrap();
+        other.append(&mut vec![call("gpt-4o-mini")]).unwrap();
+        assert!(worker.take().is_empty());
+        let models: Vec<_> = drainer.take().into_iter().map(|c| c.model).collect();
+        assert_eq!(models, ["gpt-4o", "gpt-4o-mini"]);
+        assert!(!path.exists());
+
+        drainer.release_drain();
+        worker
+            .append(&mut vec![call("gpt-4"), call("gpt-4o")])
+            .unwrap();
+        worker.append(&mut vec![call("o1")]).unwrap();
+        assert_eq!(worker.take().len(), 3);
+        assert!(!sibling(&path, "tmp").exists());
+    }
+}
@@ -0,0 +1,503 @@
+//! Client-side cost estimates.
+//!
+//! Prices are looked up from built-in tables of list prices in USD per
+//! million tokens for OpenAI, Anthropic, Google, AWS Bedrock and Azure
+//! OpenAI models, so a request can be costed before it is sent, for example
+//! to show "this request may cost up to $0.12" or to refuse calls above a
+//! cap.
+//!
+//! Image generation and transcription models are priced per image and per
+//! minute of audio instead, from the [`MultimodalUsage`] of a call.
+//!
+//! A [`CostCalculator`] adds custom prices, such as those of self-hosted
+//! models loaded from a JSON price sheet, on top of the built-in tables.
+//! The client uses one to set [`LLMCall::estimated_cost_usd`] on every
+//! tracked call.
+//!
+//! # Example
+//!
+//! ```rust
+//! use diagnyx::pricing::{self, CostCalculator};
+//! use diagnyx::Provider;
+//!
+//! let estimate = pricing::estimate("gpt-4o", "Summarize this article: ...", 1000).unwrap();
+//! println!("This request may cost up to ${:.4}", estimate.max_cost_usd);
+//! assert!(estimate.max_cost_usd <= 0.02);
+//!
+//! let calculator = CostCalculator::new().price_sheet_json(
+//!     r#"{"models": [{"model": "llama-3-70b", "input_per_million": 0.6, "output_per_million": 0.8}]}"#,
+//! )?;
+//! let cost = calculator.estimate(&Provider::Custom, "llama-3-70b", 1000, 1000).unwrap();
+//! assert!((cost - 0.0014).abs() < 1e-9);
+//! # Ok::<(), diagnyx::DiagnyxError>(())
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::DiagnyxError;
+use crate::types::{LLMCall, MultimodalUsage, Provider};
+
+/// Characters per token used to estimate the token count of plain text.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// List price of a model, in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelPrice {
+    /// Cost in USD of the given token counts.
+    pub fn cost(&self, input_tokens: i32, output_tokens: i32) -> f64 {
+        (input_tokens.max(0) as f64 * self.input_per_million
+            + output_tokens.max(0) as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// A price table: model name prefixes and their input and output prices.
+/// More specific prefixes come first, as the first match wins.
+type PriceTable = &'static [(&'static str, f64, f64)];
+
+const OPENAI: PriceTable = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("o1-mini", 3.00, 12.00),
+    ("o1", 15.00, 60.00),
+    ("o3-mini", 1.10, 4.40),
+    ("text-embedding-3-small", 0.02, 0.0),
+    ("text-embedding-3-large", 0.13, 0.0),
+];
+
+/// Azure OpenAI deployment names that differ from OpenAI's model names.
+/// Other Azure models are priced like their OpenAI counterparts.
+const AZURE: PriceTable = &[("gpt-35-turbo", 0.50, 1.50)];
+
+const ANTHROPIC: PriceTable = &[
+    ("claude-opus-4", 15.00, 75.00),
+    ("claude-sonnet-4", 3.00, 15.00),
+    ("claude-3-7-sonnet", 3.00, 15.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-3-opus", 15.00, 75.00),
+    ("claude-3-sonnet", 3.00, 15.00),
+    ("claude-3-haiku", 0.25, 1.25),
+];
+
+const GOOGLE: PriceTable = &[
+    ("gemini-2.0-flash", 0.10, 0.40),
+    ("gemini-1.5-pro", 1.25, 5.00),
+    ("gemini-1.5-flash", 0.075, 0.30),
+    ("gemini-1.0-pro", 0.50, 1.50),
+];
+
+/// AWS Bedrock model IDs. Cross-region inference profiles such as
+/// `us.anthropic.claude-3-5-sonnet-...` match after their region prefix.
+const BEDROCK: PriceTable = &[
+    ("anthropic.claude-3-5-sonnet", 3.00, 15.00),
+    ("anthropic.claude-3-5-haiku", 0.80, 4.00),
+    ("anthropic.claude-3-opus", 15.00, 75.00),
+    ("anthropic.claude-3-sonnet", 3.00, 15.00),
+    ("anthropic.claude-3-haiku", 0.25, 1.25),
+    ("meta.llama3-1-70b", 0.99, 0.99),
+    ("meta.llama3-1-8b", 0.22, 0.22),
+    ("meta.llama3-70b", 2.65, 3.50),
+    ("meta.llama3-8b", 0.30, 0.60),
+    ("amazon.titan-text-express", 0.20, 0.60),
+    ("amazon.titan-text-lite", 0.15, 0.20),
+    ("mistral.mistral-large", 4.00, 12.00),
+];
+
+/// Bedrock cross-region inference profile prefixes.
+const BEDROCK_REGIONS: &[&str] = &["us.", "eu.", "apac."];
+
+/// Tables searched for a provider, in order.
+fn tables(provider: &Provider) -> &'static [PriceTable] {
+    match provider {
+        Provider::OpenAI => &[OPENAI],
+        Provider::Azure => &[AZURE, OPENAI],
+        Provider::Anthropic => &[ANTHROPIC],
+        Provider::Google => &[GOOGLE],
+        Provider::Aws => &[BEDROCK],
+        Provider::Custom => &[],
+    }
+}
+
+fn lookup(tables: &[PriceTable], model: &str) -> Option<ModelPrice> {
+    let model = model.to_lowercase();
+    let model = BEDROCK_REGIONS
+        .iter()
+        .find_map(|region| model.strip_prefix(region))
+        .unwrap_or(&model);
+
+    tables
+        .iter()
+        .flat_map(|table| table.iter())
+        .find(|(prefix, _, _)| model.starts_with(prefix))
+        .map(|&(_, input, output)| ModelPrice {
+            input_per_million: input,
+            output_per_million: output,
+        })
+}
+
+/// Look up the list price of `model` from any provider's table.
+///
+/// Dated snapshots such as `gpt-4o-2024-08-06` match their base model.
+/// Returns `None` for unknown models.
+pub fn price(model: &str) -> Option<ModelPrice> {
+    lookup(&[OPENAI, AZURE, ANTHROPIC, GOOGLE, BEDROCK], model)
+}
+
+/// Look up the list price of `model` as served by `provider`.
+pub fn price_for(provider: &Provider, model: &str) -> Option<ModelPrice> {
+    lookup(tables(provider), model)
+}
+
+/// Image prices: model name prefix, resolution (`None` matching any other
+/// resolution) and USD per image. The first match wins.
+const IMAGE_PRICES: &[(&str, Option<&str>, f64)] = &[
+    ("dall-e-3", Some("1024x1024"), 0.040),
+    ("dall-e-3", None, 0.080),
+    ("dall-e-2", Some("256x256"), 0.016),
+    ("dall-e-2", Some("512x512"), 0.018),
+    ("dall-e-2", None, 0.020),
+    ("gpt-image-1", Some("1024x1024"), 0.042),
+    ("gpt-image-1", None, 0.063),
+    ("imagen-3", None, 0.030),
+];
+
+/// Audio prices: model name prefix and USD per minute.
+const AUDIO_PRICES: &[(&str, f64)] = &[
+    ("whisper", 0.006),
+    ("gpt-4o-mini-transcribe", 0.003),
+    ("gpt-4o-transcribe", 0.006),
+];
+
+/// Cost in USD of the images and audio of a call to `model`, or `None` if
+/// the model has no known media price.
+///
+/// Video, and images given to vision models as input, are priced in
+/// tokens and have no media cost.
+pub fn media_cost(model: &str, usage: &MultimodalUsage) -> Option<f64> {
+    let model = model.to_lowercase();
+    let images = usage.image_count.and_then(|count| {
+        let resolution = usage.resolution.as_deref();
+        IMAGE_PRICES
+            .iter()
+            .find(|(prefix, res, _)| {
+                model.starts_with(prefix) && res.is_none_or(|res| Some(res) == resolution)
+            })
+            .map(|&(_, _, per_image)| count as f64 * per_image)
+    });
+    let audio = usage.audio_seconds.and_then(|seconds| {
+        AUDIO_PRICES
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix))
+            .map(|&(_, per_minute)| seconds / 60.0 * per_minute)
+    });
+    match (images, audio) {
+        (None, None) => None,
+        (images, audio) => Some(images.unwrap_or(0.0) + audio.unwrap_or(0.0)),
+    }
+}
+
+/// The prompt of a request to be estimated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prompt<'a> {
+    /// Prompt text, whose token count is approximated from its length.
+    Text(&'a str),
+    /// An exact prompt token count.
+    Tokens(i32),
+}
+
+impl Prompt<'_> {
+    /// Number of prompt tokens, estimated for text.
+    pub fn tokens(&self) -> i32 {
+        match self {
+            Prompt::Text(text) => {
+                let tokens = text.chars().count().div_ceil(CHARS_PER_TOKEN);
+                i32::try_from(tokens).unwrap_or(i32::MAX)
+            }
+            Prompt::Tokens(tokens) => *tokens,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Prompt<'a> {
+    fn from(text: &'a str) -> Self {
+        Prompt::Text(text)
+    }
+}
+
+impl<'a> From<&'a String> for Prompt<'a> {
+    fn from(text: &'a String) -> Self {
+        Prompt::Text(text)
+    }
+}
+
+impl From<i32> for Prompt<'_> {
+    fn from(tokens: i32) -> Self {
+        Prompt::Tokens(tokens)
+    }
+}
+
+/// Estimated cost range of a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostEstimate {
+    /// Prompt tokens, estimated when the prompt was given as text.
+    pub input_tokens: i32,
+    /// Upper bound on output tokens.
+    pub max_output_tokens: i32,
+    /// Cost if the model produces no output.
+    pub min_cost_usd: f64,
+    /// Cost if the model produces `max_output_tokens` tokens.
+    pub max_cost_usd: f64,
+}
+
+/// Estimate the cost range of a request to `model`.
+///
+/// The prompt may be given as text or as a token count. The range spans
+/// from no output to `max_output_tokens` output tokens. Returns `None` if
+/// the model has no known price.
+pub fn estimate<'a>(
+    model: &str,
+    prompt: impl Into<Prompt<'a>>,
+    max_output_tokens: i32,
+) -> Option<CostEstimate> {
+    let price = price(model)?;
+    let input_tokens = prompt.into().tokens();
+
+    Some(CostEstimate {
+        input_tokens,
+        max_output_tokens,
+        min_cost_usd: price.cost(input_tokens, 0),
+        max_cost_usd: price.cost(input_tokens, max_output_tokens),
+    })
+}
+
+/// A price that overrides or extends the built-in tables.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomPrice {
+    /// Model name, or a prefix of it.
+    pub model: String,
+    /// Provider the price applies to. Applies to all providers when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
+    #[serde(flatten)]
+    pub price: ModelPrice,
+}
+
+#[derive(Debug, Deserialize)]
+struct PriceSheet {
+    models: Vec<CustomPrice>,
+}
+
+/// Computes the cost of calls from custom prices and the built-in tables.
+///
+/// Custom prices are checked first, in the order they were added.
+#[derive(Debug, Clone, Default)]
+pub struct CostCalculator {
+    custom: Vec<CustomPrice>,
+}
+
+impl CostCalculator {
+    /// Create a calculator using only the built-in tables.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a price for `model` from any provider.
+    pub fn custom_price(mut self, model: impl Into<String>, price: ModelPrice) -> Self {
+        self.custom.push(CustomPrice {
+            model: model.into(),
+            provider: None,
+            price,
+        });
+        self
+    }
+
+    /// Add the prices of a JSON price sheet.
+    ///
+    /// The sheet has the form
+    /// `{"models": [{"model": "...", "provider": "custom", "input_per_million": 0.5, "output_per_million": 1.0}]}`,
+    /// where `provider` is optional.
+    pub fn price_sheet_json(mut self, json: &str) -> Result<Self, DiagnyxError> {
+        let sheet: PriceSheet = serde_json::from_str(json)?;
+        self.custom.extend(sheet.models);
+        Ok(self)
+    }
+
+    /// Add the prices of a JSON price sheet file. See
+    /// [`price_sheet_json`](Self::price_sheet_json) for the format.
+    pub fn price_sheet_file(self, path: impl AsRef<Path>) -> Result<Self, DiagnyxError> {
+        let contents = std::fs::read_to_string(path)?;
+        self.price_sheet_json(&contents)
+    }
+
+    /// Price of `model` as served by `provider`.
+    pub fn price(&self, provider: &Provider, model: &str) -> Option<ModelPrice> {
+        let lower = model.to_lowercase();
+        self.custom
+            .iter()
+            .find(|custom| {
+                custom.provider.as_ref().is_none_or(|p| p == provider)
+                    && lower.starts_with(&custom.model.to_lowercase())
+            })
+            .map(|custom| custom.price)
+            .or_else(|| price_for(provider, model))
+    }
+
+    /// Cost in USD of a call, or `None` if the model has no known price.
+    pub fn estimate(
+        &self,
+        provider: &Provider,
+        model: &str,
+        input_tokens: i32,
+        output_tokens: i32,
+    ) -> Option<f64> {
+        self.price(provider, model)
+            .map(|price| price.cost(input_tokens, output_tokens))
+    }
+
+    /// Set `estimated_cost_usd` on `call` unless it is already set, adding
+    /// the cost of its media to that of its tokens.
+    pub fn apply(&self, call: &mut LLMCall) {
+        if call.estimated_cost_usd.is_some() {
+            return;
+        }
+        let tokens = self.estimate(
+            &call.provider,
+            &call.model,
+            call.input_tokens,
+            call.output_tokens,
+        );
+        let media = call
+            .multimodal
+            .as_ref()
+            .and_then(|usage| media_cost(&call.model, usage));
+        call.estimated_cost_usd = match (tokens, media) {
+            (None, None) => None,
+            (tokens, media) => Some(tokens.unwrap_or(0.0) + media.unwrap_or(0.0)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_price_matches_most_specific_prefix() {
+        assert_eq!(
+            price("gpt-4o-mini-2024-07-18").unwrap().input_per_million,
+            0.15
+        );
+        assert_eq!(price("GPT-4o-2024-08-06").unwrap().input_per_million, 2.50);
+        assert_eq!(price("gpt-4-0613").unwrap().input_per_million, 30.00);
+        assert!(price("my-local-model").is_none());
+    }
+
+    #[test]
+    fn test_estimate_from_tokens() {
+        let estimate = estimate("claude-3-5-sonnet-20241022", 1_000_000, 100_000).unwrap();
+
+        assert_eq!(estimate.input_tokens, 1_000_000);
+        assert!((estimate.min_cost_usd - 3.0).abs() < 1e-9);
+        assert!((estimate.max_cost_usd - 4.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_estimate_from_text() {
+        let prompt = "a".repeat(4001);
+        let estimate = estimate("gpt-4", &prompt, 0).unwrap();
+
+        assert_eq!(estimate.input_tokens, 1001);
+        assert_eq!(estimate.min_cost_usd, estimate.max_cost_usd);
+        assert!(super::estimate("unknown", "hi", 10).is_none());
+    }
+
+    #[test]
+    fn test_provider_tables() {
+        let input =
+            |provider, model| price_for(&provider, model).map(|p: ModelPrice| p.input_per_million);
+
+        assert_eq!(input(Provider::Azure, "gpt-35-turbo-0125"), Some(0.50));
+        assert_eq!(input(Provider::Azure, "gpt-4o"), Some(2.50));
+        assert_eq!(
+            input(
+                Provider::Aws,
+                "us.anthropic.claude-3-5-sonnet-20241022-v2:0"
+            ),
+            Some(3.00)
+        );
+        assert_eq!(
+            input(Provider::Aws, "meta.llama3-1-8b-instruct-v1:0"),
+            Some(0.22)
+        );
+        assert_eq!(input(Provider::Anthropic, "gpt-4o"), None);
+        assert_eq!(input(Provider::Custom, "gpt-4o"), None);
+    }
+
+    #[test]
+    fn test_calculator_prefers_custom_prices() {
+        let calculator = CostCalculator::new()
+            .price_sheet_json(
+                r#"{"models": [
+                    {"model": "gpt-4o", "provider": "azure", "input_per_million": 2.0, "output_per_million": 8.0},
+                    {"model": "my-llama", "input_per_million": 1.0, "output_per_million": 1.0}
+                ]}"#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            calculator.estimate(&Provider::Azure, "gpt-4o", 1_000_000, 0),
+            Some(2.0)
+        );
+        assert_eq!(
+            calculator.estimate(&Provider::OpenAI, "gpt-4o", 1_000_000, 0),
+            Some(2.5)
+        );
+        assert_eq!(
+            calculator.estimate(&Provider::Custom, "my-llama-70b", 0, 1_000_000),
+            Some(1.0)
+        );
+
+        let mut call = LLMCall::builder()
+            .provider(Provider::Custom)
+            .model("my-llama")
+            .input_tokens(500_000)
+            .output_tokens(500_000)
+            .build();
+        calculator.apply(&mut call);
+        assert_eq!(call.estimated_cost_usd, Some(1.0));
+
+        assert!(CostCalculator::new().price_sheet_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_media_cost() {
+        let hd = MultimodalUsage::images(2).resolution("1792x1024");
+        assert_eq!(media_cost("dall-e-3", &hd), Some(0.16));
+        let square = MultimodalUsage::images(1).resolution("1024x1024");
+        assert_eq!(media_cost("dall-e-3", &square), Some(0.04));
+        let audio = MultimodalUsage::audio(90.0);
+        assert!((media_cost("whisper-1", &audio).unwrap() - 0.009).abs() < 1e-12);
+        assert_eq!(media_cost("gpt-4o", &square), None);
+
+        let mut call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("whisper-1")
+            .multimodal(MultimodalUsage::audio(60.0))
+            .build();
+        CostCalculator::new().apply(&mut call);
+        assert_eq!(call.estimated_cost_usd, Some(0.006));
+    }
+}
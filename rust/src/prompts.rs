@@ -0,0 +1,1299 @@
+//! Prompts Module for Diagnyx Rust SDK
+//!
+//! Fetches prompt templates stored in Diagnyx and renders them locally.
+//! Templates are cached: a pinned version never changes and is kept for the
+//! life of the client, while the latest version of a prompt is refetched
+//! once `cache_ttl` has passed. Refetches are conditional on the ETag of
+//! the cached version, so an unchanged prompt costs a `304 Not Modified`.
+//!
+//! To pick up a newly published version within seconds, either set a
+//! `poll_interval`, at which cached latest versions are revalidated in the
+//! background, or call [`PromptClient::invalidate`] from the handler of a
+//! Diagnyx prompt webhook.
+//!
+//! A new version can be rolled out gradually:
+//! [`PromptClient::get_with_rollout`] serves the canary version of a prompt
+//! to a percentage of users, chosen deterministically from their ID so each
+//! user keeps seeing the same version, and calls made with it are tagged
+//! with the arm they were served. Calls built with a rendered prompt record
+//! its `prompt_id` and `prompt_version`, so cost and quality can be compared
+//! across prompt versions.
+//!
+//! # Templates
+//!
+//! Templates use a Handlebars-like syntax:
+//!
+//! - `{{name}}` and `{{customer.name}}` insert a variable, or a field of one
+//! - `{{#if name}}...{{else}}...{{/if}}` renders a block if a variable is
+//!   set and not `false`, empty or zero
+//! - `{{#each items}}...{{/each}}` renders a block for each element of a
+//!   list, in which `{{this}}`, `{{@index}}` and the element's fields can
+//!   be used
+//! - `{{> name}}` inserts a partial template given as [`Partials`]
+//!
+//! Variables can be given as name and value pairs to
+//! [`PromptTemplate::render`], or as any `Serialize` struct to
+//! [`PromptTemplate::render_with`], which keeps the variables a prompt takes
+//! in one typed place. Templates fetched at runtime cannot be checked
+//! against the struct: a template using a variable that is not given fails
+//! to render with a [`TemplateError`](crate::DiagnyxError::TemplateError).
+//! A template kept in the code can be, by deriving [`PromptVars`] for the
+//! struct with the `macros` feature: a template using a variable that is
+//! not a field of it fails to compile.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::prompts::{PromptClient, Version};
+//! use diagnyx::{LLMCall, Provider};
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//!     let prompts = PromptClient::new("dx_api_key", "org-123");
+//!
+//!     let template = prompts.get("checkout-assistant", Version::Latest).await?;
+//!     let prompt = template.render([("customer", "Ada"), ("items", "3")])?;
+//!
+//!     // Send prompt.text to the model, then track the call
+//!     let call = LLMCall::builder()
+//!         .provider(Provider::OpenAI)
+//!         .model("gpt-4o")
+//!         .prompt(&prompt)
+//!         .build();
+//!
+//!     Ok(())
+//! }
+//! ```
+
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use crate::error::DiagnyxError;
+use crate::http::{HttpConfig, REQUEST_TIMEOUT};
+use crate::logger::Logger;
+use crate::retry::{send_with_retry, RetryPolicy};
+use crate::types::{default_base_url, LLMCall, Masked};
+
+/// Version of a prompt to fetch.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Version {
+    /// The most recently published version.
+    Latest,
+    /// A specific version number.
+    Number(u32),
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Latest => write!(f, "latest"),
+            Version::Number(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+/// A prompt template stored in Diagnyx.
+///
+/// Variables are written `{{name}}` in the template.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptTemplate {
+    pub name: String,
+    pub version: u32,
+    pub template: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Arm of a rollout the template was served for, if any.
+    #[serde(skip)]
+    pub rollout_arm: Option<RolloutArm>,
+}
+
+impl PromptTemplate {
+    /// Substitute `variables` into the template.
+    ///
+    /// Returns [`DiagnyxError::TemplateError`] if the template uses a
+    /// variable that is not given or is malformed.
+    pub fn render<I, K, V>(&self, variables: I) -> Result<RenderedPrompt, DiagnyxError>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: fmt::Display,
+    {
+        let variables: Map<String, Value> = variables
+            .into_iter()
+            .map(|(name, value)| (name.into(), Value::String(value.to_string())))
+            .collect();
+        self.render_value(&Value::Object(variables), &Partials::new())
+    }
+
+    /// Substitute the fields of `variables` into the template.
+    ///
+    /// ```rust
+    /// use diagnyx::prompts::{Partials, PromptTemplate};
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Checkout {
+    ///     customer: String,
+    ///     items: Vec<String>,
+    /// }
+    ///
+    /// let template = PromptTemplate {
+    ///     name: "checkout-assistant".to_string(),
+    ///     version: 1,
+    ///     template: "{{> greeting}} Your cart: {{#each items}}{{this}} {{/each}}".to_string(),
+    ///     model: None,
+    ///     created_at: chrono::Utc::now(),
+    ///     rollout_arm: None,
+    /// };
+    /// let partials = Partials::new().partial("greeting", "Hi {{customer}}.");
+    /// let variables = Checkout {
+    ///     customer: "Ada".to_string(),
+    ///     items: vec!["tea".to_string(), "scones".to_string()],
+    /// };
+    ///
+    /// let prompt = template.render_with_partials(&variables, &partials)?;
+    /// assert_eq!(prompt.text, "Hi Ada. Your cart: tea scones ");
+    /// # Ok::<(), diagnyx::DiagnyxError>(())
+    /// ```
+    pub fn render_with<T: Serialize>(&self, variables: &T) -> Result<RenderedPrompt, DiagnyxError> {
+        self.render_with_partials(variables, &Partials::new())
+    }
+
+    /// Substitute the fields of `variables` into the template, with
+    /// `partials` available to `{{> name}}`.
+    pub fn render_with_partials<T: Serialize>(
+        &self,
+        variables: &T,
+        partials: &Partials,
+    ) -> Result<RenderedPrompt, DiagnyxError> {
+        let variables = serde_json::to_value(variables).map_err(|e| {
+            DiagnyxError::TemplateError(format!(
+                "invalid variables for prompt {}: {}",
+                self.name, e
+            ))
+        })?;
+        self.render_value(&variables, partials)
+    }
+
+    fn render_value(
+        &self,
+        variables: &Value,
+        partials: &Partials,
+    ) -> Result<RenderedPrompt, DiagnyxError> {
+        let nodes = parse(&self.template).map_err(|e| self.error(e))?;
+        let mut text = String::with_capacity(self.template.len());
+        Renderer { partials, depth: 0 }
+            .render(&nodes, &mut vec![Scope::new(variables)], &mut text)
+            .map_err(|e| self.error(e))?;
+
+        Ok(RenderedPrompt {
+            text,
+            name: self.name.clone(),
+            version: self.version,
+            rollout_arm: self.rollout_arm,
+        })
+    }
+
+    fn error(&self, message: String) -> DiagnyxError {
+        DiagnyxError::TemplateError(format!("{} in prompt {}", message, self.name))
+    }
+}
+
+/// Partial templates, inserted into prompts with `{{> name}}`.
+#[derive(Debug, Clone, Default)]
+pub struct Partials {
+    templates: HashMap<String, String>,
+}
+
+impl Partials {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn partial(mut self, name: impl Into<String>, template: impl Into<String>) -> Self {
+        self.templates.insert(name.into(), template.into());
+        self
+    }
+}
+
+/// Variables of a prompt whose template is kept in the code.
+///
+/// Derive it with the `macros` feature, giving the template in a
+/// `#[prompt(template = "...")]` attribute and optionally the prompt's
+/// `name`, by default the struct's name. The variables the template uses
+/// outside of `#each` blocks are checked against the struct's fields when it
+/// compiles; variables inside `#each` blocks and partials are only checked
+/// when rendering. Rendered prompts have version 0, as their template is
+/// not stored in Diagnyx.
+///
+/// ```rust,ignore
+/// use diagnyx::prompts::PromptVars;
+/// use serde::Serialize;
+///
+/// #[derive(Serialize, PromptVars)]
+/// #[prompt(name = "checkout-assistant", template = "Hi {{customer}}.")]
+/// struct Checkout {
+///     customer: String,
+/// }
+///
+/// let prompt = Checkout { customer: "Ada".to_string() }.render()?;
+/// assert_eq!(prompt.text, "Hi Ada.");
+/// ```
+pub trait PromptVars: Serialize + Sized {
+    /// Name of the prompt.
+    const NAME: &'static str;
+    /// The template, checked against the struct's fields.
+    const TEMPLATE: &'static str;
+
+    /// The template as a [`PromptTemplate`].
+    fn template() -> PromptTemplate {
+        PromptTemplate {
+            name: Self::NAME.to_string(),
+            version: 0,
+            template: Self::TEMPLATE.to_string(),
+            model: None,
+            created_at: Utc::now(),
+            rollout_arm: None,
+        }
+    }
+
+    /// Substitute these variables into the template.
+    fn render(&self) -> Result<RenderedPrompt, DiagnyxError> {
+        self.render_with_partials(&Partials::new())
+    }
+
+    /// Substitute these variables into the template, with `partials`
+    /// available to `{{> name}}`.
+    fn render_with_partials(&self, partials: &Partials) -> Result<RenderedPrompt, DiagnyxError> {
+        Self::template().render_with_partials(self, partials)
+    }
+}
+
+#[cfg(feature = "macros")]
+pub use diagnyx_macros::PromptVars;
+
+/// Partials may include other partials up to this depth, which stops
+/// partials that include themselves.
+const MAX_PARTIAL_DEPTH: usize = 16;
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Variable(String),
+    If {
+        path: String,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        path: String,
+        body: Vec<Node>,
+    },
+    Partial(String),
+}
+
+/// A block being parsed.
+struct Block {
+    each: bool,
+    path: String,
+    then: Vec<Node>,
+    otherwise: Option<Vec<Node>>,
+}
+
+impl Block {
+    fn nodes(&mut self) -> &mut Vec<Node> {
+        self.otherwise.as_mut().unwrap_or(&mut self.then)
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Node>, String> {
+    let mut root = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut rest = source;
+
+    loop {
+        let start = rest.find("{{").unwrap_or(rest.len());
+        if start > 0 {
+            let text = Node::Text(rest[..start].to_string());
+            blocks.last_mut().map_or(&mut root, Block::nodes).push(text);
+        }
+        if start == rest.len() {
+            break;
+        }
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| "unclosed variable".to_string())?;
+        let tag = after[..end].trim();
+        rest = &after[end + 2..];
+
+        let node = if let Some(open) = tag.strip_prefix('#') {
+            let (kind, path) = open.split_once(char::is_whitespace).unwrap_or((open, ""));
+            if !matches!(kind, "if" | "each") || path.trim().is_empty() {
+                return Err(format!("invalid block {{{{{}}}}}", tag));
+            }
+            blocks.push(Block {
+                each: kind == "each",
+                path: path.trim().to_string(),
+                then: Vec::new(),
+                otherwise: None,
+            });
+            continue;
+        } else if let Some(close) = tag.strip_prefix('/') {
+            let block = blocks
+                .pop()
+                .ok_or_else(|| format!("unopened block {{{{{}}}}}", tag))?;
+            if close.trim() != if block.each { "each" } else { "if" } {
+                return Err(format!("mismatched block {{{{{}}}}}", tag));
+            }
+            if block.each {
+                Node::Each {
+                    path: block.path,
+                    body: block.then,
+                }
+            } else {
+                Node::If {
+                    path: block.path,
+                    then: block.then,
+                    otherwise: block.otherwise.unwrap_or_default(),
+                }
+            }
+        } else if tag == "else" {
+            match blocks.last_mut() {
+                Some(block) if !block.each && block.otherwise.is_none() => {
+                    block.otherwise = Some(Vec::new());
+                    continue;
+                }
+                _ => return Err("{{else}} outside of an if block".to_string()),
+            }
+        } else if let Some(name) = tag.strip_prefix('>') {
+            Node::Partial(name.trim().to_string())
+        } else {
+            Node::Variable(tag.to_string())
+        };
+        blocks.last_mut().map_or(&mut root, Block::nodes).push(node);
+    }
+
+    match blocks.last() {
+        Some(block) => Err(format!("unclosed block {}", block.path)),
+        None => Ok(root),
+    }
+}
+
+/// Variables in scope: the prompt's, or an element of an `each` list.
+struct Scope<'v> {
+    value: &'v Value,
+    index: Option<usize>,
+}
+
+impl<'v> Scope<'v> {
+    fn new(value: &'v Value) -> Self {
+        Self { value, index: None }
+    }
+}
+
+struct Renderer<'p> {
+    partials: &'p Partials,
+    depth: usize,
+}
+
+impl Renderer<'_> {
+    fn render<'v>(
+        &mut self,
+        nodes: &[Node],
+        scopes: &mut Vec<Scope<'v>>,
+        out: &mut String,
+    ) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Variable(path) => {
+                    if path == "@index" {
+                        match scopes.last().and_then(|scope| scope.index) {
+                            Some(index) => out.push_str(&index.to_string()),
+                            None => return Err("@index outside of an each block".to_string()),
+                        }
+                        continue;
+                    }
+                    match lookup(scopes, path) {
+                        None | Some(Value::Null) => {
+                            return Err(format!("missing variable {}", path))
+                        }
+                        Some(Value::String(value)) => out.push_str(value),
+                        Some(value) => out.push_str(&value.to_string()),
+                    }
+                }
+                Node::If {
+                    path,
+                    then,
+                    otherwise,
+                } => {
+                    let branch = if lookup(scopes, path).is_some_and(truthy) {
+                        then
+                    } else {
+                        otherwise
+                    };
+                    self.render(branch, scopes, out)?;
+                }
+                Node::Each { path, body } => match lookup(scopes, path) {
+                    None | Some(Value::Null) => {}
+                    Some(Value::Array(items)) => {
+                        for (index, item) in items.iter().enumerate() {
+                            scopes.push(Scope {
+                                value: item,
+                                index: Some(index),
+                            });
+                            let rendered = self.render(body, scopes, out);
+                            scopes.pop();
+                            rendered?;
+                        }
+                    }
+                    Some(_) => return Err(format!("variable {} is not a list", path)),
+                },
+                Node::Partial(name) => {
+                    let source = self
+                        .partials
+                        .templates
+                        .get(name)
+                        .ok_or_else(|| format!("missing partial {}", name))?;
+                    if self.depth == MAX_PARTIAL_DEPTH {
+                        return Err(format!("partial {} nested too deeply", name));
+                    }
+                    let nodes = parse(source).map_err(|e| format!("{} in partial {}", e, name))?;
+                    self.depth += 1;
+                    let rendered = self.render(&nodes, scopes, out);
+                    self.depth -= 1;
+                    rendered?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resolve a dotted path, from the innermost scope defining its first
+/// segment outwards.
+fn lookup<'v>(scopes: &[Scope<'v>], path: &str) -> Option<&'v Value> {
+    let mut segments = path.split('.');
+    let first = segments.next()?;
+    let mut value = if first == "this" {
+        scopes.last()?.value
+    } else {
+        scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.value.get(first))?
+    };
+    for segment in segments {
+        value = value.get(segment)?;
+    }
+    Some(value)
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(value) => !value.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+/// A prompt rendered from a template, with the version it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedPrompt {
+    pub text: String,
+    pub name: String,
+    pub version: u32,
+    pub rollout_arm: Option<RolloutArm>,
+}
+
+impl RenderedPrompt {
+    /// Link `call` to the prompt template version, and tag it with the
+    /// rollout arm, unless it is already linked to a prompt.
+    pub fn apply(&self, call: &mut LLMCall) {
+        if call.prompt_id.is_some() {
+            return;
+        }
+        call.prompt_id = Some(self.name.clone());
+        call.prompt_version = Some(self.version);
+        if let Some(arm) = self.rollout_arm {
+            call.metadata
+                .get_or_insert_with(HashMap::new)
+                .insert(ROLLOUT_ARM_KEY.to_string(), arm.as_str().into());
+        }
+    }
+}
+
+/// Metadata key of the rollout arm a call's prompt was served for.
+pub const ROLLOUT_ARM_KEY: &str = "prompt_rollout";
+
+/// Arm of a prompt rollout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RolloutArm {
+    Stable,
+    Canary,
+}
+
+impl RolloutArm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RolloutArm::Stable => "stable",
+            RolloutArm::Canary => "canary",
+        }
+    }
+}
+
+/// Gradual rollout of a new version of a prompt.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rollout {
+    pub name: String,
+    pub stable_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary_version: Option<u32>,
+    /// Percentage of users served the canary version, from 0 to 100.
+    #[serde(default)]
+    pub canary_percentage: f64,
+}
+
+impl Rollout {
+    /// The version and arm served to `user_id`.
+    ///
+    /// Users are bucketed by a hash of the prompt name and their ID, so a
+    /// user keeps the same arm while the percentage grows, and is in the
+    /// canary of one prompt independently of others.
+    pub fn select(&self, user_id: &str) -> (u32, RolloutArm) {
+        match self.canary_version {
+            Some(canary) if bucket(&self.name, user_id) < self.canary_percentage => {
+                (canary, RolloutArm::Canary)
+            }
+            _ => (self.stable_version, RolloutArm::Stable),
+        }
+    }
+}
+
+/// Bucket of a user in `[0, 100)`, from the FNV-1a hash of the prompt name
+/// and user ID, which is stable across processes and releases.
+fn bucket(name: &str, user_id: &str) -> f64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes().chain([0]).chain(user_id.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % 10_000) as f64 / 100.0
+}
+
+/// Configuration for PromptClient.
+#[derive(Clone)]
+pub struct PromptClientConfig {
+    pub api_key: String,
+    pub organization_id: String,
+    pub base_url: String,
+    pub retry_policy: RetryPolicy,
+    /// How long the latest version of a prompt is cached. Default: 60s
+    pub cache_ttl: Duration,
+    /// Interval at which cached latest versions are revalidated in the
+    /// background. Default: None
+    pub poll_interval: Option<Duration>,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
+    pub debug: bool,
+}
+
+impl fmt::Debug for PromptClientConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PromptClientConfig")
+            .field("api_key", &Masked(&self.api_key))
+            .field("organization_id", &self.organization_id)
+            .field("base_url", &self.base_url)
+            .field("retry_policy", &self.retry_policy)
+            .field("cache_ttl", &self.cache_ttl)
+            .field("poll_interval", &self.poll_interval)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .finish()
+    }
+}
+
+impl PromptClientConfig {
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        let api_key = api_key.into();
+        Self {
+            base_url: default_base_url(&api_key),
+            api_key,
+            organization_id: organization_id.into(),
+            retry_policy: RetryPolicy::new(3),
+            cache_ttl: Duration::from_secs(60),
+            poll_interval: None,
+            http: HttpConfig::default(),
+            debug: false,
+        }
+    }
+
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into();
+        self
+    }
+
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.retry_policy.max_attempts = retries as u32;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+}
+
+struct CachedTemplate {
+    template: PromptTemplate,
+    etag: Option<String>,
+    fetched_at: Instant,
+}
+
+type Cache = Mutex<HashMap<(String, Version), CachedTemplate>>;
+
+/// Client for fetching prompt templates.
+pub struct PromptClient {
+    config: PromptClientConfig,
+    http_client: Client,
+    cache: Arc<Cache>,
+    rollouts: Mutex<HashMap<String, (Rollout, Instant)>>,
+}
+
+impl PromptClient {
+    /// Create a new PromptClient with default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
+    pub fn new(api_key: impl Into<String>, organization_id: impl Into<String>) -> Self {
+        Self::with_config(PromptClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new PromptClient with custom configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created, or a `poll_interval` is
+    /// set outside of a Tokio runtime. Use
+    /// [`try_with_config`](Self::try_with_config) to handle the error instead.
+    pub fn with_config(config: PromptClientConfig) -> Self {
+        Self::try_with_config(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new PromptClient with default settings, returning an error if
+    /// the HTTP client cannot be created.
+    pub fn try_new(
+        api_key: impl Into<String>,
+        organization_id: impl Into<String>,
+    ) -> Result<Self, DiagnyxError> {
+        Self::try_with_config(PromptClientConfig::new(api_key, organization_id))
+    }
+
+    /// Create a new PromptClient with custom configuration, returning an error
+    /// if the HTTP client cannot be created.
+    ///
+    /// With a `poll_interval`, this returns a `ConfigError` outside of a
+    /// Tokio runtime, which runs the polling.
+    pub fn try_with_config(config: PromptClientConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config.http.client(REQUEST_TIMEOUT)?;
+        Self::with_http_client(config, http_client)
+    }
+
+    /// Create a new PromptClient sending requests with `http_client`.
+    ///
+    /// The HTTP settings of `config` are not applied to it. With a
+    /// `poll_interval`, this returns a `ConfigError` outside of a Tokio
+    /// runtime.
+    pub fn with_http_client(
+        config: PromptClientConfig,
+        http_client: Client,
+    ) -> Result<Self, DiagnyxError> {
+        let client = Self {
+            config,
+            http_client,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            rollouts: Mutex::new(HashMap::new()),
+        };
+        if let Some(interval) = client.config.poll_interval {
+            client.start_poll_task(interval)?;
+        }
+        Ok(client)
+    }
+
+    /// Get a version of a prompt template, from the cache if fresh.
+    pub async fn get(&self, name: &str, version: Version) -> Result<PromptTemplate, DiagnyxError> {
+        let key = (name.to_string(), version);
+        let etag = match self.cache.lock().unwrap().get(&key) {
+            Some(cached)
+                if key.1 != Version::Latest
+                    || cached.fetched_at.elapsed() < self.config.cache_ttl =>
+            {
+                return Ok(cached.template.clone());
+            }
+            Some(cached) => cached.etag.clone(),
+            None => None,
+        };
+
+        revalidate(
+            &self.http_client,
+            &self.config,
+            &self.cache,
+            &key,
+            etag.as_deref(),
+        )
+        .await
+    }
+
+    /// Get the version of a prompt template that its rollout serves to
+    /// `user_id`, tagged with the rollout arm.
+    ///
+    /// Rollouts are cached for `cache_ttl`, like latest versions.
+    pub async fn get_with_rollout(
+        &self,
+        name: &str,
+        user_id: &str,
+    ) -> Result<PromptTemplate, DiagnyxError> {
+        let (version, arm) = self.rollout(name).await?.select(user_id);
+        let mut template = self.get(name, Version::Number(version)).await?;
+        template.rollout_arm = Some(arm);
+        Ok(template)
+    }
+
+    /// Get the rollout of a prompt, from the cache if fresh.
+    pub async fn rollout(&self, name: &str) -> Result<Rollout, DiagnyxError> {
+        if let Some((rollout, fetched_at)) = self.rollouts.lock().unwrap().get(name) {
+            if fetched_at.elapsed() < self.config.cache_ttl {
+                return Ok(rollout.clone());
+            }
+        }
+
+        let url = format!(
+            "{}/api/v1/organizations/{}/prompts/{}/rollout",
+            self.config.base_url, self.config.organization_id, name
+        );
+        let response = send_with_retry(&self.config.retry_policy, None, Method::GET, |method| {
+            self.http_client
+                .request(method, &url)
+                .header("Authorization", format!("Bearer {}", self.config.api_key))
+        })
+        .await?;
+        let rollout: Rollout = response
+            .json()
+            .await
+            .map_err(|e| DiagnyxError::ConfigError(format!("Failed to parse response: {}", e)))?;
+
+        self.rollouts
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (rollout.clone(), Instant::now()));
+        Ok(rollout)
+    }
+
+    /// Drop the cached latest version of a prompt, so the next
+    /// [`get`](Self::get) fetches it, and its rollout. Call this when
+    /// notified that a new version was published.
+    pub fn invalidate(&self, name: &str) {
+        self.cache
+            .lock()
+            .unwrap()
+            .remove(&(name.to_string(), Version::Latest));
+        self.rollouts.lock().unwrap().remove(name);
+    }
+
+    /// Drop all cached templates and rollouts.
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().clear();
+        self.rollouts.lock().unwrap().clear();
+    }
+
+    /// Revalidate cached latest versions every `interval`, until the client
+    /// is dropped.
+    fn start_poll_task(&self, interval: Duration) -> Result<(), DiagnyxError> {
+        let runtime = tokio::runtime::Handle::try_current().map_err(|_| {
+            DiagnyxError::ConfigError(
+                "poll_interval requires the client to be created within a Tokio runtime"
+                    .to_string(),
+            )
+        })?;
+        let cache = Arc::downgrade(&self.cache);
+        let config = self.config.clone();
+        let http_client = self.http_client.clone();
+        let logger = logger(&config);
+
+        runtime.spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+
+                let Some(cache) = Weak::upgrade(&cache) else {
+                    break;
+                };
+                let stale: Vec<_> = cache
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .filter(|(key, _)| key.1 == Version::Latest)
+                    .map(|(key, cached)| (key.clone(), cached.etag.clone()))
+                    .collect();
+                for (key, etag) in stale {
+                    if let Err(e) =
+                        revalidate(&http_client, &config, &cache, &key, etag.as_deref()).await
+                    {
+                        logger.warn(&format!("Failed to poll prompt {}: {}", key.0, e));
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+fn logger(config: &PromptClientConfig) -> Logger {
+    Logger::new("Diagnyx Prompts", config.debug)
+}
+
+/// Fetch a prompt version into the cache, conditionally on `etag`, and
+/// return it.
+async fn revalidate(
+    http_client: &Client,
+    config: &PromptClientConfig,
+    cache: &Cache,
+    key: &(String, Version),
+    etag: Option<&str>,
+) -> Result<PromptTemplate, DiagnyxError> {
+    let url = format!(
+        "{}/api/v1/organizations/{}/prompts/{}/versions/{}",
+        config.base_url, config.organization_id, key.0, key.1
+    );
+    let response = send_with_retry(&config.retry_policy, None, Method::GET, |method| {
+        let mut request = http_client
+            .request(method, &url)
+            .header("Authorization", format!("Bearer {}", config.api_key));
+        if let Some(etag) = etag {
+            request = request.header("If-None-Match", etag);
+        }
+        request
+    })
+    .await;
+
+    let response = match response {
+        Err(DiagnyxError::ApiError {
+            status_code: 304, ..
+        }) => {
+            if let Some(cached) = cache.lock().unwrap().get_mut(key) {
+                cached.fetched_at = Instant::now();
+                return Ok(cached.template.clone());
+            }
+            return Err(DiagnyxError::ConfigError(format!(
+                "Prompt {} not modified but not cached",
+                key.0
+            )));
+        }
+        response => response?,
+    };
+    let etag = response
+        .headers()
+        .get("ETag")
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    let template: PromptTemplate = response
+        .json()
+        .await
+        .map_err(|e| DiagnyxError::ConfigError(format!("Failed to parse response: {}", e)))?;
+
+    logger(config).log(&format!(
+        "Fetched {} version {}",
+        template.name, template.version
+    ));
+    cache.lock().unwrap().insert(
+        key.clone(),
+        CachedTemplate {
+            template: template.clone(),
+            etag,
+            fetched_at: Instant::now(),
+        },
+    );
+    Ok(template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn template(body: &str) -> PromptTemplate {
+        PromptTemplate {
+            name: "checkout-assistant".to_string(),
+            version: 3,
+            template: body.to_string(),
+            model: None,
+            created_at: Utc::now(),
+            rollout_arm: None,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let prompt = template("Hi {{ customer }}, you have {{items}} items")
+            .render([("customer", "Ada"), ("items", "3")])
+            .unwrap();
+
+        assert_eq!(prompt.text, "Hi Ada, you have 3 items");
+        let mut call = LLMCall::builder()
+            .provider(crate::Provider::OpenAI)
+            .model("gpt-4o")
+            .build();
+        prompt.apply(&mut call);
+        assert_eq!(call.prompt_id.as_deref(), Some("checkout-assistant"));
+        assert_eq!(call.prompt_version, Some(3));
+
+        let missing = template("Hi {{customer}}").render(Vec::<(&str, &str)>::new());
+        assert!(matches!(missing, Err(DiagnyxError::TemplateError(_))));
+        let unclosed = template("Hi {{customer").render([("customer", "Ada")]);
+        assert!(matches!(unclosed, Err(DiagnyxError::TemplateError(_))));
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn test_derived_prompt_vars() {
+        #[derive(Serialize, PromptVars)]
+        #[prompt(
+            name = "checkout-assistant",
+            template = "Hi {{name}}: {{#each items}}{{this}} {{/each}}{{#if vip}}VIP{{/if}}"
+        )]
+        struct Checkout {
+            #[serde(rename = "name")]
+            customer: &'static str,
+            items: Vec<&'static str>,
+            vip: bool,
+            #[serde(skip)]
+            #[allow(dead_code)]
+            session: u64,
+        }
+
+        let prompt = Checkout {
+            customer: "Ada",
+            items: vec!["tea", "scones"],
+            vip: true,
+            session: 7,
+        }
+        .render()
+        .unwrap();
+        assert_eq!(prompt.text, "Hi Ada: tea scones VIP");
+        assert_eq!(prompt.name, "checkout-assistant");
+        assert_eq!(prompt.version, 0);
+    }
+
+    #[test]
+    fn test_render_with_blocks_and_partials() {
+        #[derive(Serialize)]
+        struct Item {
+            name: &'static str,
+            quantity: u32,
+        }
+        #[derive(Serialize)]
+        struct Variables {
+            customer: &'static str,
+            vip: bool,
+            items: Vec<Item>,
+        }
+
+        let variables = Variables {
+            customer: "Ada",
+            vip: false,
+            items: vec![
+                Item {
+                    name: "tea",
+                    quantity: 2,
+                },
+                Item {
+                    name: "scones",
+                    quantity: 6,
+                },
+            ],
+        };
+        let partials = Partials::new().partial(
+            "line",
+            "{{@index}}. {{quantity}} {{name}} for {{customer}}\n",
+        );
+        let prompt = template(
+            "{{#if vip}}Dear{{else}}Hi{{/if}} {{customer}}\n{{#each items}}{{> line}}{{/each}}",
+        )
+        .render_with_partials(&variables, &partials)
+        .unwrap();
+        assert_eq!(
+            prompt.text,
+            "Hi Ada\n0. 2 tea for Ada\n1. 6 scones for Ada\n"
+        );
+
+        for invalid in [
+            "{{#if vip}}",
+            "{{/if}}",
+            "{{#each items}}{{/if}}",
+            "{{> missing}}",
+        ] {
+            let rendered = template(invalid).render_with_partials(&variables, &partials);
+            assert!(
+                matches!(rendered, Err(DiagnyxError::TemplateError(_))),
+                "{}",
+                invalid
+            );
+        }
+        let recursive = Partials::new().partial("loop", "{{> loop}}");
+        assert!(template("{{> loop}}")
+            .render_with_partials(&variables, &recursive)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_caches_latest_for_ttl() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/api/v1/organizations/org-1/prompts/checkout-assistant/versions/latest",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "name": "checkout-assistant",
+                "version": 3,
+                "template": "Hi {{customer}}",
+                "createdAt": "2024-03-01T00:00:00Z"
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = PromptClient::with_config(
+            PromptClientConfig::new("test-api-key", "org-1")
+                .base_url(server.uri())
+                .cache_ttl(Duration::from_millis(100)),
+        );
+        for _ in 0..2 {
+            let template = client
+                .get("checkout-assistant", Version::Latest)
+                .await
+                .unwrap();
+            assert_eq!(template.version, 3);
+        }
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        client
+            .get("checkout-assistant", Version::Latest)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_rollout_select_is_deterministic() {
+        let rollout = Rollout {
+            name: "checkout-assistant".to_string(),
+            stable_version: 3,
+            canary_version: Some(4),
+            canary_percentage: 25.0,
+        };
+        let users: Vec<String> = (0..1000).map(|i| format!("user-{}", i)).collect();
+        let canary = users
+            .iter()
+            .filter(|user| rollout.select(user) == (4, RolloutArm::Canary))
+            .count();
+        assert!((200..300).contains(&canary), "{}", canary);
+        for user in &users {
+            assert_eq!(rollout.select(user), rollout.select(user));
+        }
+
+        let everyone = Rollout {
+            canary_percentage: 100.0,
+            ..rollout.clone()
+        };
+        assert!(users
+            .iter()
+            .all(|user| everyone.select(user).1 == RolloutArm::Canary));
+        let finished = Rollout {
+            canary_version: None,
+            ..everyone
+        };
+        assert_eq!(finished.select("user-1"), (3, RolloutArm::Stable));
+    }
+
+    #[test]
+    fn test_polling_outside_a_runtime_is_a_config_error() {
+        let config = PromptClientConfig::new("key", "org-1").poll_interval(Duration::from_secs(60));
+        let error = PromptClient::try_with_config(config).err().unwrap();
+        assert!(matches!(error, DiagnyxError::ConfigError(_)), "{}", error);
+
+        assert!(PromptClient::try_with_config(PromptClientConfig::new("key", "org-1")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_get_with_rollout_tags_calls() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/api/v1/organizations/org-1/prompts/checkout-assistant/rollout",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "name": "checkout-assistant",
+                "stableVersion": 3,
+                "canaryVersion": 4,
+                "canaryPercentage": 100.0
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/api/v1/organizations/org-1/prompts/checkout-assistant/versions/4",
+            ))
+            .respond_with(latest(4))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = PromptClient::with_config(
+            PromptClientConfig::new("test-api-key", "org-1").base_url(server.uri()),
+        );
+        for _ in 0..2 {
+            let template = client
+                .get_with_rollout("checkout-assistant", "user-1")
+                .await
+                .unwrap();
+            assert_eq!(template.rollout_arm, Some(RolloutArm::Canary));
+        }
+        let prompt = client
+            .get_with_rollout("checkout-assistant", "user-1")
+            .await
+            .unwrap()
+            .render([("customer", "Ada")])
+            .unwrap();
+        let call = LLMCall::builder()
+            .provider(crate::Provider::OpenAI)
+            .model("gpt-4o")
+            .prompt(&prompt)
+            .build();
+        assert_eq!(call.prompt_version, Some(4));
+        assert_eq!(call.metadata.unwrap()[ROLLOUT_ARM_KEY], "canary");
+    }
+
+    const LATEST_PATH: &str =
+        "/api/v1/organizations/org-1/prompts/checkout-assistant/versions/latest";
+
+    fn latest(version: u32) -> ResponseTemplate {
+        ResponseTemplate::new(200)
+            .insert_header("ETag", format!("\"v{}\"", version).as_str())
+            .set_body_json(serde_json::json!({
+                "name": "checkout-assistant",
+                "version": version,
+                "template": "Hi {{customer}}",
+                "createdAt": "2024-03-01T00:00:00Z"
+            }))
+    }
+
+    #[tokio::test]
+    async fn test_get_revalidates_with_etag() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(LATEST_PATH))
+            .and(header("If-None-Match", "\"v3\""))
+            .respond_with(ResponseTemplate::new(304))
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(LATEST_PATH))
+            .respond_with(latest(3))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = PromptClient::with_config(
+            PromptClientConfig::new("test-api-key", "org-1")
+                .base_url(server.uri())
+                .cache_ttl(Duration::ZERO),
+        );
+        for _ in 0..2 {
+            let template = client
+                .get("checkout-assistant", Version::Latest)
+                .await
+                .unwrap();
+            assert_eq!(template.version, 3);
+        }
+        client.invalidate("checkout-assistant");
+        client
+            .get("checkout-assistant", Version::Latest)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_poll_refreshes_latest() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(LATEST_PATH))
+            .respond_with(latest(3))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(LATEST_PATH))
+            .and(header("If-None-Match", "\"v3\""))
+            .respond_with(latest(4))
+            .mount(&server)
+            .await;
+
+        let client = PromptClient::with_config(
+            PromptClientConfig::new("test-api-key", "org-1")
+                .base_url(server.uri())
+                .cache_ttl(Duration::from_secs(3600))
+                .poll_interval(Duration::from_millis(50)),
+        );
+        let template = client
+            .get("checkout-assistant", Version::Latest)
+            .await
+            .unwrap();
+        assert_eq!(template.version, 3);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let template = client
+            .get("checkout-assistant", Version::Latest)
+            .await
+            .unwrap();
+        assert_eq!(template.version, 4);
+    }
+}
@@ -0,0 +1,135 @@
+//! Client-side rate limiting of requests to Diagnyx.
+//!
+//! A burst of traffic can push the SDK past the API's own rate limits, and
+//! the rejected requests are then retried on top of new ones. A
+//! [`RateLimiter`] spaces requests out before they are sent instead. It is a
+//! token bucket holding up to one second of requests: requests within the
+//! bucket go out at once, and later ones wait for their turn, in order.
+//!
+//! Clones share their bucket, so one limiter set on the configurations of
+//! the tracking, guardrail and feedback clients bounds their requests
+//! together. Every attempt, including retries, takes a token.
+//!
+//! # Example
+//!
+//! ```rust
+//! use diagnyx::{DiagnyxConfig, RateLimiter};
+//!
+//! let limiter = RateLimiter::new(20.0);
+//! let config = DiagnyxConfig::new("dx_live_your_api_key").rate_limiter(limiter.clone());
+//! # #[cfg(feature = "feedback")]
+//! let feedback = diagnyx::FeedbackClientConfig::new("dx_live_your_api_key", "org_123")
+//!     .rate_limiter(limiter);
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A token bucket shared by the clones of a limiter.
+#[derive(Clone)]
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Tokens available; negative when requests are waiting for tokens.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("requests_per_second", &self.inner.per_second)
+            .finish()
+    }
+}
+
+impl RateLimiter {
+    /// A limiter allowing `requests_per_second` requests on average.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `requests_per_second` is not a positive number.
+    pub fn new(requests_per_second: f64) -> Self {
+        assert!(
+            requests_per_second > 0.0 && requests_per_second.is_finite(),
+            "requests_per_second must be a positive number"
+        );
+        Self {
+            inner: Arc::new(Inner {
+                per_second: requests_per_second,
+                bucket: Mutex::new(Bucket {
+                    tokens: burst(requests_per_second),
+                    updated: Instant::now(),
+                }),
+            }),
+        }
+    }
+
+    /// The average number of requests allowed per second.
+    pub fn requests_per_second(&self) -> f64 {
+        self.inner.per_second
+    }
+
+    /// Wait until a request may be sent.
+    pub async fn acquire(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Block the thread until a request may be sent.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn acquire_blocking(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    /// Take a token, returning how long to wait for it.
+    fn reserve(&self) -> Duration {
+        let per_second = self.inner.per_second;
+        let mut bucket = self.inner.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(burst(per_second)) - 1.0;
+        bucket.updated = now;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / per_second)
+        }
+    }
+}
+
+/// Size of the bucket: one second of requests, and at least one.
+fn burst(per_second: f64) -> f64 {
+    per_second.max(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limiter_allows_a_burst_then_spaces_requests() {
+        let limiter = RateLimiter::new(10.0);
+        let shared = limiter.clone();
+        for _ in 0..10 {
+            assert_eq!(limiter.reserve(), Duration::ZERO);
+        }
+        let first = shared.reserve();
+        let second = limiter.reserve();
+        assert!(first > Duration::from_millis(50) && first <= Duration::from_millis(100));
+        assert!(second > first + Duration::from_millis(50));
+    }
+}
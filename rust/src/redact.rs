@@ -0,0 +1,337 @@
+//! Masking of personal data in captured content.
+//!
+//! With `capture_full_content` enabled, prompts and responses are sent to
+//! Diagnyx. A [`ContentRedactor`] set with
+//! [`DiagnyxConfig::content_redactor`](crate::DiagnyxConfig::content_redactor)
+//! masks personal data in them before they leave the process: the built-in
+//! detectors replace email addresses, US social security numbers, payment
+//! card numbers and phone numbers with placeholders, and custom redactions
+//! handle anything else.
+//!
+//! Every call's `full_prompt` and `full_response` are redacted when the call
+//! is tracked, whichever integration captured them, and then truncated to
+//! `content_max_length`. The
+//! [`DiagnyxCallbackHandler`](crate::DiagnyxCallbackHandler) first cuts
+//! content to its own maximum length, so give it a limit no lower than the
+//! client's.
+//! Feedback clients take a redactor of their own for the comments and
+//! corrections users submit.
+//!
+//! # Example
+//!
+//! ```rust
+//! use diagnyx::redact::ContentRedactor;
+//! use diagnyx::DiagnyxConfig;
+//!
+//! let redactor = ContentRedactor::pii()
+//!     .custom(|text: &str| text.replace("Project Falcon", "[CODENAME]"));
+//! assert_eq!(
+//!     redactor.redact("Mail alice@example.com about Project Falcon"),
+//!     "Mail [EMAIL] about [CODENAME]",
+//! );
+//!
+//! let config = DiagnyxConfig::new("dx_live_your_api_key")
+//!     .capture_full_content(true)
+//!     .content_redactor(redactor);
+//! ```
+//!
+//! [`track_call_with_content`]: crate::track_call_with_content
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Kind of personal data found by the built-in detectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PiiKind {
+    Email,
+    /// US social security number, as `ddd-dd-dddd`.
+    Ssn,
+    /// Payment card number passing the Luhn check.
+    CardNumber,
+    /// Phone number of 10 to 15 digits, with a `+` prefix or spaces, dots,
+    /// dashes or parentheses between groups. Bare runs of digits, such as
+    /// Unix timestamps and order numbers, are not matched.
+    Phone,
+}
+
+impl PiiKind {
+    /// Placeholder replacing matches, such as `[EMAIL]`.
+    pub fn placeholder(self) -> &'static str {
+        match self {
+            PiiKind::Email => "[EMAIL]",
+            PiiKind::Ssn => "[SSN]",
+            PiiKind::CardNumber => "[CARD_NUMBER]",
+            PiiKind::Phone => "[PHONE]",
+        }
+    }
+
+    fn find(self, text: &str) -> Option<(usize, usize)> {
+        match self {
+            PiiKind::Email => find_email(text),
+            PiiKind::Ssn => find_ssn(text),
+            PiiKind::CardNumber => find_card_number(text),
+            PiiKind::Phone => find_phone(text),
+        }
+    }
+}
+
+type CustomRedaction = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Masks personal data in captured prompts and responses.
+///
+/// The built-in detectors run first, then custom redactions in the order
+/// they were added.
+#[derive(Clone, Default)]
+pub struct ContentRedactor {
+    detectors: Vec<PiiKind>,
+    custom: Vec<CustomRedaction>,
+}
+
+impl fmt::Debug for ContentRedactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContentRedactor")
+            .field("detectors", &self.detectors)
+            .field("custom", &self.custom.len())
+            .finish()
+    }
+}
+
+impl ContentRedactor {
+    /// A redactor without detectors, which leaves content unchanged.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A redactor with every built-in detector.
+    pub fn pii() -> Self {
+        Self::new()
+            .detect(PiiKind::Email)
+            .detect(PiiKind::Ssn)
+            .detect(PiiKind::CardNumber)
+            .detect(PiiKind::Phone)
+    }
+
+    /// Add a built-in detector.
+    pub fn detect(mut self, kind: PiiKind) -> Self {
+        if !self.detectors.contains(&kind) {
+            self.detectors.push(kind);
+        }
+        self
+    }
+
+    /// Add a custom redaction, which returns its input with anything
+    /// sensitive masked.
+    pub fn custom(mut self, redaction: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.custom.push(Arc::new(redaction));
+        self
+    }
+
+    /// Whether the redactor leaves content unchanged.
+    pub fn is_empty(&self) -> bool {
+        self.detectors.is_empty() && self.custom.is_empty()
+    }
+
+    /// Mask personal data in `text`.
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for &kind in &self.detectors {
+            text = mask(&text, kind);
+        }
+        for redaction in &self.custom {
+            text = redaction(&text);
+        }
+        text
+    }
+}
+
+/// Replace every match of `kind` in `text` with its placeholder.
+fn mask(text: &str, kind: PiiKind) -> String {
+    let mut masked = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((start, end)) = kind.find(rest) {
+        masked.push_str(&rest[..start]);
+        masked.push_str(kind.placeholder());
+        rest = &rest[end..];
+    }
+    masked.push_str(rest);
+    masked
+}
+
+pub(crate) fn find_email(text: &str) -> Option<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let is_local = |b: u8| b.is_ascii_alphanumeric() || b"._%+-".contains(&b);
+    let is_domain = |b: u8| b.is_ascii_alphanumeric() || b".-".contains(&b);
+
+    bytes.iter().enumerate().find_map(|(at, &b)| {
+        if b != b'@' || at == 0 || !is_local(bytes[at - 1]) {
+            return None;
+        }
+        let local_len = bytes[..at]
+            .iter()
+            .rev()
+            .take_while(|&&b| is_local(b))
+            .count();
+        let domain_len = bytes[at + 1..]
+            .iter()
+            .take_while(|&&b| is_domain(b))
+            .count();
+        let domain = text[at + 1..at + 1 + domain_len].trim_end_matches('.');
+        let (host, tld) = domain.rsplit_once('.')?;
+        let valid =
+            !host.is_empty() && tld.len() >= 2 && tld.bytes().all(|b| b.is_ascii_alphabetic());
+        valid.then(|| (at - local_len, at + 1 + domain.len()))
+    })
+}
+
+pub(crate) fn find_ssn(text: &str) -> Option<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let shape = b"ddd-dd-dddd";
+    if bytes.len() < shape.len() {
+        return None;
+    }
+
+    (0..=bytes.len() - shape.len()).find_map(|start| {
+        let matches = shape.iter().zip(&bytes[start..]).all(|(&s, &b)| match s {
+            b'd' => b.is_ascii_digit(),
+            _ => b == s,
+        });
+        let before = start == 0 || !bytes[start - 1].is_ascii_digit();
+        let end = start + shape.len();
+        let after = end == bytes.len() || !bytes[end].is_ascii_digit();
+        (matches && before && after).then_some((start, end))
+    })
+}
+
+pub(crate) fn find_card_number(text: &str) -> Option<(usize, usize)> {
+    let mut digits: Vec<u32> = Vec::new();
+    let mut start = 0;
+    let mut end = 0;
+    let mut separators = 0;
+
+    for (i, c) in text
+        .char_indices()
+        .chain(std::iter::once((text.len(), '\0')))
+    {
+        if let Some(d) = c.to_digit(10) {
+            if digits.is_empty() {
+                start = i;
+            }
+            digits.push(d);
+            end = i + c.len_utf8();
+            separators = 0;
+            continue;
+        }
+        // Allow single spaces or dashes between digit groups
+        if (c == ' ' || c == '-') && !digits.is_empty() && separators == 0 {
+            separators += 1;
+            continue;
+        }
+        if (13..=19).contains(&digits.len()) && luhn_valid(&digits) {
+            return Some((start, end));
+        }
+        digits.clear();
+        separators = 0;
+    }
+    None
+}
+
+pub(crate) fn find_phone(text: &str) -> Option<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut at = 0;
+    while at < bytes.len() {
+        let starts = bytes[at].is_ascii_digit() || b"+(".contains(&bytes[at]);
+        if !starts || (at > 0 && bytes[at - 1].is_ascii_alphanumeric()) {
+            at += 1;
+            continue;
+        }
+
+        let mut i = at + usize::from(bytes[at] == b'+');
+        let mut formatted = b"+(".contains(&bytes[at]);
+        let (mut digits, mut group, mut separators, mut end) = (0, 0, 0, at);
+        while i < bytes.len() {
+            let b = bytes[i];
+            if b.is_ascii_digit() {
+                if separators > 0 {
+                    formatted |= digits > 0;
+                    group = 0;
+                }
+                digits += 1;
+                group += 1;
+                separators = 0;
+                end = i + 1;
+            } else if b" .-()".contains(&b) && separators < 2 {
+                separators += 1;
+            } else {
+                break;
+            }
+            i += 1;
+        }
+        // Requiring a last group of 3 or more digits keeps dates and times
+        // such as `2024-05-01 12` out
+        let word_after = end < bytes.len() && bytes[end].is_ascii_alphanumeric();
+        if formatted && (10..=15).contains(&digits) && group >= 3 && !word_after {
+            return Some((at, end));
+        }
+        at = end.max(at + 1);
+    }
+    None
+}
+
+fn luhn_valid(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match i % 2 {
+            1 if d * 2 > 9 => d * 2 - 9,
+            1 => d * 2,
+            _ => d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pii_redactor_masks_every_match() {
+        let redactor = ContentRedactor::pii();
+        assert_eq!(
+            redactor.redact(
+                "alice@example.com, bob@example.org, SSN 123-45-6789, card 4111 1111 1111 1111"
+            ),
+            "[EMAIL], [EMAIL], SSN [SSN], card [CARD_NUMBER]"
+        );
+        assert_eq!(redactor.redact("order 1234-5678"), "order 1234-5678");
+    }
+
+    #[test]
+    fn test_phone_detector_masks_numbers_not_dates() {
+        let redactor = ContentRedactor::new().detect(PiiKind::Phone);
+        assert_eq!(
+            redactor.redact("call +1 (555) 123-4567, (555)1234567 or 020.7946.0958"),
+            "call [PHONE], [PHONE] or [PHONE]"
+        );
+        assert_eq!(redactor.redact("text +15551234567"), "text [PHONE]");
+        assert_eq!(
+            redactor.redact("on 2024-05-01 12:30, order A5551234567"),
+            "on 2024-05-01 12:30, order A5551234567"
+        );
+        assert_eq!(
+            redactor.redact("at 1714567890123, order 5551234567"),
+            "at 1714567890123, order 5551234567"
+        );
+    }
+
+    #[test]
+    fn test_custom_redactions_run_after_detectors() {
+        let redactor = ContentRedactor::new()
+            .detect(PiiKind::Email)
+            .custom(|text: &str| text.replace("[EMAIL]", "<hidden>"));
+        assert_eq!(redactor.redact("ping ops@acme.io"), "ping <hidden>");
+        assert!(ContentRedactor::new().is_empty());
+    }
+}
@@ -0,0 +1,117 @@
+//! Deduplicated reporting of repeated flush failures.
+//!
+//! While the API is unreachable every flush fails with the same error. The
+//! first occurrence of an error is reported immediately; repeats are counted
+//! and summarized at most once per window, and a recovery is reported once
+//! the next flush succeeds.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often repeated failures are summarized.
+const SUMMARY_WINDOW: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Default)]
+struct State {
+    last_error: Option<String>,
+    last_reported: Option<Instant>,
+    /// Failures since the last report.
+    unreported: usize,
+    /// Failures since the last success.
+    consecutive: usize,
+}
+
+/// Decides which flush failures are worth logging.
+#[derive(Debug)]
+pub(crate) struct FailureReporter {
+    window: Duration,
+    state: Mutex<State>,
+}
+
+impl Default for FailureReporter {
+    fn default() -> Self {
+        Self {
+            window: SUMMARY_WINDOW,
+            state: Mutex::new(State::default()),
+        }
+    }
+}
+
+impl FailureReporter {
+    /// Record a failed flush, returning the message to log, if any.
+    pub(crate) fn failure(&self, error: &str) -> Option<String> {
+        self.failure_at(error, Instant::now())
+    }
+
+    fn failure_at(&self, error: &str, now: Instant) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive += 1;
+
+        let repeated = state.last_error.as_deref() == Some(error);
+        let window_elapsed = state
+            .last_reported
+            .is_none_or(|reported| now.duration_since(reported) >= self.window);
+
+        if !repeated {
+            state.last_error = Some(error.to_string());
+            state.last_reported = Some(now);
+            state.unreported = 0;
+            return Some(format!("Flush failed: {}", error));
+        }
+        state.unreported += 1;
+        if !window_elapsed {
+            return None;
+        }
+
+        let count = state.unreported;
+        state.last_reported = Some(now);
+        state.unreported = 0;
+        Some(format!(
+            "Flush failed {} times in the last {}m: {}",
+            count,
+            self.window.as_secs() / 60,
+            error
+        ))
+    }
+
+    /// Record a successful flush, returning the number of consecutive
+    /// failures it recovered from, if any.
+    pub(crate) fn success(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        let failures = std::mem::take(&mut *state).consecutive;
+        (failures > 0).then_some(failures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_failures_are_summarized() {
+        let reporter = FailureReporter::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            reporter.failure_at("timeout", start).as_deref(),
+            Some("Flush failed: timeout")
+        );
+        for minute in 1..5 {
+            let now = start + Duration::from_secs(60 * minute);
+            assert_eq!(reporter.failure_at("timeout", now), None);
+        }
+        assert_eq!(
+            reporter
+                .failure_at("timeout", start + Duration::from_secs(300))
+                .as_deref(),
+            Some("Flush failed 5 times in the last 5m: timeout")
+        );
+
+        // A different error is reported at once
+        assert!(reporter
+            .failure_at("HTTP 500", start + Duration::from_secs(301))
+            .is_some());
+        assert_eq!(reporter.success(), Some(7));
+        assert_eq!(reporter.success(), None);
+    }
+}
@@ -0,0 +1,660 @@
+//! Retry and backoff shared by all sub-clients.
+//!
+//! Requests are retried on network errors, rate limiting (HTTP 429) and
+//! server errors, with exponential backoff and full jitter between attempts:
+//! each delay is drawn uniformly between zero and the backoff. Other client
+//! errors are returned immediately. A policy can override which statuses
+//! are retried and bound the total time spent retrying.
+//!
+//! Only idempotent requests (GET, HEAD, PUT, DELETE, OPTIONS) are retried by
+//! default, since repeating a POST may apply it twice. A policy can opt in to
+//! retrying non-idempotent requests for endpoints that tolerate it.
+//!
+//! When a 429 or 503 response has a `Retry-After` header, or an error
+//! response is an event stream with a `retry:` field, the delay the server
+//! suggests is used instead of the backoff, still capped by the policy's
+//! maximum delay.
+//!
+//! Each request is sent with an `X-Request-Id` header, the same on every
+//! attempt, which is also set on the [`DiagnyxError::ApiError`] returned if
+//! it fails so that SDK failures can be matched with server logs.
+//!
+//! Operations with their own latency budget can be bounded with
+//! [`with_timeout`]. Requests sent within it are given the time left as
+//! their timeout, in place of the HTTP client's, so a budget longer than
+//! the client's timeout is not cut short.
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::client::{random_bits, random_unit};
+use crate::error::DiagnyxError;
+use crate::ratelimit::RateLimiter;
+use crate::sse;
+
+tokio::task_local! {
+    /// Deadline of the [`with_timeout`] operation the current task runs.
+    static DEADLINE: tokio::time::Instant;
+}
+
+/// Header carrying the client-side ID of a request.
+pub(crate) const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// A new random request ID.
+pub(crate) fn new_request_id() -> String {
+    format!("{:016x}{:016x}", random_bits(), random_bits())
+}
+
+/// How failed requests are retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first. Values below 1 are treated as 1.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for each further retry.
+    pub base_delay: Duration,
+    /// Upper bound on the delay between attempts.
+    pub max_delay: Duration,
+    /// Retry requests that are not idempotent, such as POST.
+    pub retry_non_idempotent: bool,
+    /// Draw each backoff delay uniformly between zero and the backoff.
+    /// Delays suggested by the server are not jittered.
+    pub jitter: bool,
+    /// Stop retrying once waiting for the next attempt would exceed this
+    /// time since the first attempt.
+    pub max_elapsed: Option<Duration>,
+    /// Statuses retried, or not, regardless of the default rules.
+    pub status_rules: BTreeMap<u16, bool>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+impl RetryPolicy {
+    /// Create a policy making up to `max_attempts` attempts.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            retry_non_idempotent: false,
+            jitter: true,
+            max_elapsed: None,
+            status_rules: BTreeMap::new(),
+        }
+    }
+
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts;
+        self
+    }
+
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    pub fn retry_non_idempotent(mut self, retry: bool) -> Self {
+        self.retry_non_idempotent = retry;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn max_elapsed(mut self, elapsed: Duration) -> Self {
+        self.max_elapsed = Some(elapsed);
+        self
+    }
+
+    /// Retry responses with `status`, or never retry them, overriding the
+    /// default of retrying 429 and server errors.
+    pub fn retry_status(mut self, status: u16, retry: bool) -> Self {
+        self.status_rules.insert(status, retry);
+        self
+    }
+
+    /// Whether a response with `status` is retried.
+    pub fn retries_status(&self, status: StatusCode) -> bool {
+        self.status_rules
+            .get(&status.as_u16())
+            .copied()
+            .unwrap_or_else(|| is_retryable_status(status))
+    }
+
+    /// Backoff after the given zero-based attempt fails, before jitter.
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    /// Delay to wait after the given zero-based attempt fails, using the
+    /// server's suggested delay, capped by `max_delay`, if there is one.
+    pub fn delay_with_hint(&self, attempt: u32, suggested: Option<Duration>) -> Duration {
+        suggested.map_or_else(
+            || self.delay_for(attempt),
+            |delay| delay.min(self.max_delay),
+        )
+    }
+
+    /// Delay to wait after the given zero-based attempt fails, with jitter
+    /// applied to the backoff, or `None` if waiting would exceed
+    /// `max_elapsed` given the time `elapsed` since the first attempt.
+    fn next_delay(
+        &self,
+        attempt: u32,
+        suggested: Option<Duration>,
+        elapsed: Duration,
+    ) -> Option<Duration> {
+        let mut delay = self.delay_with_hint(attempt, suggested);
+        if self.jitter && suggested.is_none() {
+            delay = delay.mul_f64(random_unit());
+        }
+        match self.max_elapsed {
+            Some(max) if elapsed + delay > max => None,
+            _ => Some(delay),
+        }
+    }
+
+    /// Number of attempts to make for a request with the given method.
+    pub fn attempts_for(&self, method: &Method) -> u32 {
+        if self.retry_non_idempotent || is_idempotent(method) {
+            self.max_attempts.max(1)
+        } else {
+            1
+        }
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Delay requested by the `Retry-After` header of a 429 or 503 response,
+/// given either in seconds or as an HTTP date.
+fn retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Send a request, retrying according to `policy`.
+///
+/// `build` is called once per attempt to construct the request. Returns the
+/// first successful response; unsuccessful responses are converted to
+/// `DiagnyxError::ApiError`. With a `limiter`, each attempt waits for it
+/// first.
+pub(crate) async fn send_with_retry<F>(
+    policy: &RetryPolicy,
+    limiter: Option<&RateLimiter>,
+    method: Method,
+    build: F,
+) -> Result<Response, DiagnyxError>
+where
+    F: Fn(Method) -> RequestBuilder,
+{
+    let attempts = policy.attempts_for(&method);
+    let started = Instant::now();
+    let request_id = new_request_id();
+    let mut last_error = None;
+
+    for attempt in 0..attempts {
+        let mut suggested = None;
+        let mut request = build(method.clone()).header(REQUEST_ID_HEADER, &request_id);
+        if let Ok(deadline) = DEADLINE.try_with(|deadline| *deadline) {
+            request =
+                request.timeout(deadline.saturating_duration_since(tokio::time::Instant::now()));
+        }
+        if let Some(limiter) = limiter {
+            limiter.acquire().await;
+        }
+        match request.send().await {
+            Ok(response) => {
+                let status = response.status();
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "diagnyx", status = status.as_u16(), attempt, request_id = %request_id, "Response received");
+                if status.is_success() {
+                    return Ok(response);
+                }
+
+                let header_delay = retry_after(status, response.headers());
+                let message = response.text().await.unwrap_or_default();
+                suggested = header_delay.or(sse::parse(&message).retry);
+                last_error = Some(DiagnyxError::ApiError {
+                    status_code: status.as_u16(),
+                    message,
+                    request_id: Some(request_id.clone()),
+                });
+
+                if !policy.retries_status(status) {
+                    break;
+                }
+            }
+            Err(e) => {
+                last_error = Some(DiagnyxError::HttpError(e));
+            }
+        }
+
+        if attempt + 1 < attempts {
+            match policy.next_delay(attempt, suggested, started.elapsed()) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => break,
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or(DiagnyxError::MaxRetriesExceeded))
+}
+
+/// Blocking version of [`send_with_retry`], sleeping the current thread
+/// between attempts.
+#[cfg(feature = "blocking")]
+pub(crate) fn send_with_retry_blocking<F>(
+    policy: &RetryPolicy,
+    limiter: Option<&RateLimiter>,
+    method: Method,
+    build: F,
+) -> Result<reqwest::blocking::Response, DiagnyxError>
+where
+    F: Fn(Method) -> reqwest::blocking::RequestBuilder,
+{
+    let attempts = policy.attempts_for(&method);
+    let started = Instant::now();
+    let request_id = new_request_id();
+    let mut last_error = None;
+
+    for attempt in 0..attempts {
+        let mut suggested = None;
+        let request = build(method.clone()).header(REQUEST_ID_HEADER, &request_id);
+        if let Some(limiter) = limiter {
+            limiter.acquire_blocking();
+        }
+        match request.send() {
+            Ok(response) => {
+                let status = response.status();
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "diagnyx", status = status.as_u16(), attempt, request_id = %request_id, "Response received");
+                if status.is_success() {
+                    return Ok(response);
+                }
+
+                let header_delay = retry_after(status, response.headers());
+                let message = response.text().unwrap_or_default();
+                suggested = header_delay.or(sse::parse(&message).retry);
+                last_error = Some(DiagnyxError::ApiError {
+                    status_code: status.as_u16(),
+                    message,
+                    request_id: Some(request_id.clone()),
+                });
+
+                if !policy.retries_status(status) {
+                    break;
+                }
+            }
+            Err(e) => {
+                last_error = Some(DiagnyxError::HttpError(e));
+            }
+        }
+
+        if attempt + 1 < attempts {
+            match policy.next_delay(attempt, suggested, started.elapsed()) {
+                Some(delay) => std::thread::sleep(delay),
+                None => break,
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or(DiagnyxError::MaxRetriesExceeded))
+}
+
+/// Run `operation`, failing with `DiagnyxError::Timeout` if it does not
+/// finish within `timeout`. `None` means no limit.
+///
+/// Requests sent by [`send_with_retry`] within `operation` time out at the
+/// same deadline, overriding the HTTP client's timeout.
+pub(crate) async fn with_timeout<T, F>(
+    timeout: Option<Duration>,
+    operation: F,
+) -> Result<T, DiagnyxError>
+where
+    F: Future<Output = Result<T, DiagnyxError>>,
+{
+    let Some(limit) = timeout else {
+        return operation.await;
+    };
+    let deadline = tokio::time::Instant::now() + limit;
+    DEADLINE
+        .scope(deadline, tokio::time::timeout_at(deadline, operation))
+        .await
+        .unwrap_or(Err(DiagnyxError::Timeout(limit)))
+        .map_err(|e| match e {
+            DiagnyxError::HttpError(e) if e.is_timeout() => DiagnyxError::Timeout(limit),
+            e => e,
+        })
+}
+
+/// Run `operation` within the deadline of the current task's
+/// [`with_timeout`] operation, if any, e.g. in a task it spawns.
+pub(crate) fn within_deadline<F: Future>(operation: F) -> impl Future<Output = F::Output> {
+    let deadline = DEADLINE.try_with(|deadline| *deadline).ok();
+    async move {
+        match deadline {
+            Some(deadline) => DEADLINE.scope(deadline, operation).await,
+            None => operation.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn fast_policy(attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(attempts).base_delay(Duration::from_millis(1))
+    }
+
+    #[test]
+    fn test_delay_for_is_exponential_and_capped() {
+        let policy = RetryPolicy::new(5).max_delay(Duration::from_secs(3));
+        assert_eq!(policy.delay_for(0), Duration::from_secs(1));
+        assert_eq!(policy.delay_for(1), Duration::from_secs(2));
+        assert_eq!(policy.delay_for(2), Duration::from_secs(3));
+        assert_eq!(policy.delay_for(40), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_delay_with_hint_uses_capped_suggestion() {
+        let policy = RetryPolicy::new(3).max_delay(Duration::from_secs(3));
+        let suggested = |ms| Some(Duration::from_millis(ms));
+        assert_eq!(policy.delay_with_hint(0, None), Duration::from_secs(1));
+        assert_eq!(
+            policy.delay_with_hint(0, suggested(2500)),
+            Duration::from_millis(2500)
+        );
+        assert_eq!(
+            policy.delay_with_hint(0, suggested(60_000)),
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn test_next_delay_jitters_backoff_within_elapsed_budget() {
+        let policy = RetryPolicy::new(3).max_elapsed(Duration::from_secs(5));
+        for _ in 0..20 {
+            let delay = policy.next_delay(1, None, Duration::ZERO).unwrap();
+            assert!(delay <= Duration::from_secs(2));
+        }
+        let suggested = Some(Duration::from_secs(3));
+        assert_eq!(
+            policy.next_delay(0, suggested, Duration::ZERO),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            policy.next_delay(0, suggested, Duration::from_secs(3)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_retry_after_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "7".parse().unwrap());
+        assert_eq!(
+            retry_after(StatusCode::TOO_MANY_REQUESTS, &headers),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(retry_after(StatusCode::BAD_GATEWAY, &headers), None);
+
+        let date = (Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        headers.insert(RETRY_AFTER, date.parse().unwrap());
+        let delay = retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(
+            retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn test_status_rules_override_defaults() {
+        let policy = RetryPolicy::new(3)
+            .retry_status(409, true)
+            .retry_status(503, false);
+        assert!(policy.retries_status(StatusCode::CONFLICT));
+        assert!(!policy.retries_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(policy.retries_status(StatusCode::BAD_GATEWAY));
+        assert!(!policy.retries_status(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn test_attempts_for_respects_idempotency() {
+        let policy = RetryPolicy::new(3);
+        assert_eq!(policy.attempts_for(&Method::GET), 3);
+        assert_eq!(policy.attempts_for(&Method::DELETE), 3);
+        assert_eq!(policy.attempts_for(&Method::POST), 1);
+        assert_eq!(
+            policy
+                .retry_non_idempotent(true)
+                .attempts_for(&Method::POST),
+            3
+        );
+        assert_eq!(RetryPolicy::new(0).attempts_for(&Method::GET), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors_for_get() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/flaky"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/flaky"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let http = reqwest::Client::new();
+        let url = format!("{}/flaky", server.uri());
+        let response = send_with_retry(&fast_policy(3), None, Method::GET, |m| {
+            http.request(m, &url)
+        })
+        .await
+        .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_sent_and_reported() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/down"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let http = reqwest::Client::new();
+        let url = format!("{}/down", server.uri());
+        let error = send_with_retry(&fast_policy(2), None, Method::GET, |m| {
+            http.request(m, &url)
+        })
+        .await
+        .unwrap_err();
+
+        let requests = server.received_requests().await.unwrap();
+        let sent: Vec<_> = requests
+            .iter()
+            .flat_map(|r| &r.headers)
+            .filter(|(name, _)| name.as_str().eq_ignore_ascii_case(REQUEST_ID_HEADER))
+            .map(|(_, values)| values.last().as_str())
+            .collect();
+        assert_eq!(sent[0].len(), 32);
+        assert_eq!(sent[0], sent[1]);
+        assert_eq!(error.request_id(), Some(sent[0]));
+        assert!(error
+            .with_request_id("Flush failed".to_string())
+            .ends_with(&format!("(request ID {})", sent[0])));
+    }
+
+    #[tokio::test]
+    async fn test_honors_retry_after_on_rate_limit() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/limited"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/limited"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // The header's delay replaces the 10 second backoff
+        let policy = RetryPolicy::new(2).base_delay(Duration::from_secs(10));
+        let http = reqwest::Client::new();
+        let url = format!("{}/limited", server.uri());
+        let started = Instant::now();
+        let response = send_with_retry(&policy, None, Method::GET, |m| http.request(m, &url))
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_post_by_default() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/create"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let http = reqwest::Client::new();
+        let url = format!("{}/create", server.uri());
+        let result = send_with_retry(&fast_policy(3), None, Method::POST, |m| {
+            http.request(m, &url)
+        })
+        .await;
+        assert!(matches!(
+            result,
+            Err(DiagnyxError::ApiError {
+                status_code: 503,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_client_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let http = reqwest::Client::new();
+        let url = format!("{}/missing", server.uri());
+        let result = send_with_retry(&fast_policy(3), None, Method::GET, |m| {
+            http.request(m, &url)
+        })
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_with_timeout() {
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        };
+        let result = with_timeout(Some(Duration::from_millis(10)), slow).await;
+        assert!(matches!(result, Err(DiagnyxError::Timeout(_))));
+
+        let fast = async { Ok(1) };
+        assert_eq!(with_timeout(None, fast).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_with_timeout_overrides_the_client_timeout() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(150)))
+            .mount(&server)
+            .await;
+
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let url = format!("{}/slow", server.uri());
+        let policy = fast_policy(1);
+        let send = || send_with_retry(&policy, None, Method::GET, |m| http.request(m, &url));
+
+        assert!(send().await.is_err());
+        assert!(with_timeout(Some(Duration::from_secs(5)), send())
+            .await
+            .is_ok());
+        assert!(matches!(
+            with_timeout(Some(Duration::from_millis(50)), send()).await,
+            Err(DiagnyxError::Timeout(_))
+        ));
+    }
+}
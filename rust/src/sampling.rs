@@ -0,0 +1,385 @@
+//! Sampling of tracked calls.
+//!
+//! A [`SamplingPolicy`] combines a sample rate with rules keeping the calls
+//! that matter: failed calls, expensive calls and projects sampled at their
+//! own rate. Sampling by trace ID keeps or drops all calls of a trace
+//! together, consistently across services, so that sampled traces stay
+//! complete.
+//!
+//! ```rust,no_run
+//! use diagnyx::sampling::SamplingPolicy;
+//! use diagnyx::{DiagnyxClient, DiagnyxConfig, ProjectId};
+//!
+//! let client = DiagnyxClient::with_config(
+//!     DiagnyxConfig::new("dx_live_your_api_key").sampling(
+//!         SamplingPolicy::new(0.05)
+//!             .always_sample_errors()
+//!             .project_rate(ProjectId::from_static("checkout"), 0.5)
+//!             .by_trace_id(),
+//!     ),
+//! );
+//! ```
+//!
+//! # Volume cap
+//!
+//! A fixed sample rate has to be tuned for peak traffic, which throws away
+//! most calls when traffic is low. The adaptive sampler instead keeps the
+//! volume sent to Diagnyx under a cap of calls or bytes per minute. At the
+//! start of each minute it sets the rate to the fraction of the previous
+//! minute's volume that fit under the cap, and within a minute it drops
+//! every call once the cap is reached.
+//!
+//! The effective sample rate applied to each kept call, including the
+//! configured [`sample_rate`](crate::DiagnyxConfig::sample_rate), is sent
+//! as the call's `sample_rate` so that totals can be extrapolated.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::sampling::VolumeCap;
+//! use diagnyx::{DiagnyxClient, DiagnyxConfig};
+//!
+//! let client = DiagnyxClient::with_config(
+//!     DiagnyxConfig::new("dx_live_your_api_key").volume_cap(VolumeCap::CallsPerMinute(600)),
+//! );
+//! ```
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::client::random_unit;
+use crate::ids::ProjectId;
+use crate::types::{CallStatus, CostSampling, LLMCall};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Lowest rate the sampler adapts to, so some calls are always sampled.
+const MIN_RATE: f64 = 0.001;
+
+/// Which calls are tracked.
+///
+/// Calls kept at a rate below 1.0 have it recorded as their `sample_rate`,
+/// so that totals can be extrapolated.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplingPolicy {
+    /// Fraction of calls that are tracked, from 0.0 to 1.0. Default: 1.0
+    pub rate: f64,
+    /// Keep every failed call. Default: false
+    pub always_sample_errors: bool,
+    /// Rates used instead of `rate` for the calls of a project.
+    pub project_rates: HashMap<ProjectId, f64>,
+    /// Decide from a hash of the trace ID instead of at random, for calls
+    /// that have one. Default: false
+    pub by_trace_id: bool,
+    /// Apply the rate only to cheap successful calls. Default: None
+    pub cost: Option<CostSampling>,
+    /// Adapt the rate to keep the volume sent under a cap. Default: None
+    pub volume_cap: Option<VolumeCap>,
+}
+
+impl Default for SamplingPolicy {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl SamplingPolicy {
+    /// Track a fraction `rate` of calls.
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            always_sample_errors: false,
+            project_rates: HashMap::new(),
+            by_trace_id: false,
+            cost: None,
+            volume_cap: None,
+        }
+    }
+
+    /// Keep every failed call regardless of the rate.
+    pub fn always_sample_errors(mut self) -> Self {
+        self.always_sample_errors = true;
+        self
+    }
+
+    /// Sample the calls of `project` at `rate` instead.
+    pub fn project_rate(mut self, project: ProjectId, rate: f64) -> Self {
+        self.project_rates.insert(project, rate);
+        self
+    }
+
+    /// Keep or drop calls by a hash of their trace ID, so that every
+    /// service sampling at the same rate keeps the same traces.
+    pub fn by_trace_id(mut self) -> Self {
+        self.by_trace_id = true;
+        self
+    }
+
+    /// Always keep expensive and failed calls.
+    pub fn cost(mut self, sampling: CostSampling) -> Self {
+        self.cost = Some(sampling);
+        self
+    }
+
+    /// Lower the rate as needed to keep the volume sent under `cap`.
+    pub fn volume_cap(mut self, cap: VolumeCap) -> Self {
+        self.volume_cap = Some(cap);
+        self
+    }
+
+    /// Whether every call is sampled at the same rate, so calls can be
+    /// dropped before enrichers and filters run.
+    pub(crate) fn is_uniform(&self) -> bool {
+        !self.always_sample_errors
+            && self.project_rates.is_empty()
+            && !self.by_trace_id
+            && self.cost.is_none()
+    }
+
+    /// Sample a prepared call with `rate` as the default rate, returning
+    /// the rate it was kept at, or `None` if it is dropped.
+    pub(crate) fn sample(&self, call: &LLMCall, rate: f64) -> Option<f64> {
+        let exempt = (self.always_sample_errors && call.status != CallStatus::Success)
+            || self.cost.is_some_and(|cost| cost.keeps(call));
+        if exempt {
+            return Some(1.0);
+        }
+        let rate = call
+            .project_id
+            .as_ref()
+            .and_then(|project| self.project_rates.get(project))
+            .copied()
+            .unwrap_or(rate);
+        if rate >= 1.0 {
+            return Some(rate);
+        }
+        let draw = match &call.trace_id {
+            Some(trace_id) if self.by_trace_id => hash_unit(trace_id.as_str()),
+            _ => random_unit(),
+        };
+        (draw < rate).then_some(rate)
+    }
+}
+
+/// Uniform value in `[0, 1)` from the FNV-1a hash of `key`, stable across
+/// processes and platforms.
+fn hash_unit(key: &str) -> f64 {
+    let mut hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    // FNV leaves the high bits poorly mixed for keys differing at the end
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Maximum volume of calls sent per minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VolumeCap {
+    CallsPerMinute(u64),
+    /// Size of the calls serialized as JSON.
+    BytesPerMinute(u64),
+}
+
+impl VolumeCap {
+    fn limit(&self) -> f64 {
+        match self {
+            VolumeCap::CallsPerMinute(calls) => *calls as f64,
+            VolumeCap::BytesPerMinute(bytes) => *bytes as f64,
+        }
+    }
+
+    fn size(&self, call: &LLMCall) -> f64 {
+        match self {
+            VolumeCap::CallsPerMinute(_) => 1.0,
+            VolumeCap::BytesPerMinute(_) => {
+                serde_json::to_vec(call).map_or(0, |json| json.len()) as f64
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    rate: f64,
+    /// Volume of calls offered to the sampler in this window.
+    offered: f64,
+    /// Volume of calls drawn at `rate` in this window, kept or not.
+    drawn: f64,
+    /// Volume of calls kept in this window.
+    kept: f64,
+}
+
+/// Sampler adjusting its rate to keep volume under a [`VolumeCap`].
+#[derive(Debug)]
+pub(crate) struct AdaptiveSampler {
+    cap: VolumeCap,
+    window: Mutex<Window>,
+}
+
+impl AdaptiveSampler {
+    pub(crate) fn new(cap: VolumeCap) -> Self {
+        Self {
+            cap,
+            window: Mutex::new(Window {
+                start: Instant::now(),
+                rate: 1.0,
+                offered: 0.0,
+                drawn: 0.0,
+                kept: 0.0,
+            }),
+        }
+    }
+
+    /// Current rate of the sampler.
+    pub(crate) fn rate(&self) -> f64 {
+        self.window.lock().unwrap().rate
+    }
+
+    /// Sample `call`, returning the rate it was kept at, or `None` if it is
+    /// dropped.
+    ///
+    /// Calls drawn at the window's rate are still dropped once the cap is
+    /// reached, so the rate returned is the window's rate scaled by the
+    /// share of drawn volume the cap let through.
+    pub(crate) fn sample(&self, call: &LLMCall) -> Option<f64> {
+        self.sample_at(self.cap.size(call), Instant::now(), random_unit())
+    }
+
+    fn sample_at(&self, size: f64, now: Instant, draw: f64) -> Option<f64> {
+        let limit = self.cap.limit();
+        let mut window = self.window.lock().unwrap();
+
+        let elapsed = now.duration_since(window.start);
+        if elapsed >= WINDOW {
+            // Volume offered over the last minute; an idle gap of more than
+            // a window resets the rate
+            window.rate = if elapsed >= 2 * WINDOW || window.offered <= limit {
+                1.0
+            } else {
+                (limit / window.offered).max(MIN_RATE)
+            };
+            window.start = now;
+            window.offered = 0.0;
+            window.drawn = 0.0;
+            window.kept = 0.0;
+        }
+
+        window.offered += size;
+        if draw >= window.rate {
+            return None;
+        }
+        window.drawn += size;
+        if window.kept + size > limit {
+            return None;
+        }
+        window.kept += size;
+        if window.kept < window.drawn {
+            Some(window.rate * window.kept / window.drawn)
+        } else {
+            Some(window.rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Provider;
+
+    #[test]
+    fn test_rate_adapts_to_offered_volume() {
+        let sampler = AdaptiveSampler::new(VolumeCap::CallsPerMinute(100));
+        let start = Instant::now();
+
+        // 400 calls in the first minute: the first 100 fit under the cap
+        let kept = (0..400)
+            .filter(|_| sampler.sample_at(1.0, start, 0.0).is_some())
+            .count();
+        assert_eq!(kept, 100);
+
+        let next = start + WINDOW;
+        assert_eq!(sampler.sample_at(1.0, next, 0.1), Some(0.25));
+        assert_eq!(sampler.rate(), 0.25);
+        assert_eq!(sampler.sample_at(1.0, next, 0.3), None);
+
+        // Traffic dropped under the cap
+        assert_eq!(sampler.sample_at(1.0, next + WINDOW, 0.9), Some(1.0));
+    }
+
+    #[test]
+    fn test_policy_keeps_errors_and_uses_project_rates() {
+        let policy = SamplingPolicy::new(0.0)
+            .always_sample_errors()
+            .project_rate(ProjectId::from_static("checkout"), 1.0);
+        let call = |project: &'static str, status: CallStatus| {
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model("gpt-4")
+                .project_id(ProjectId::from_static(project))
+                .status(status)
+                .build()
+        };
+
+        assert_eq!(
+            policy.sample(&call("search", CallStatus::Success), 0.0),
+            None
+        );
+        assert_eq!(
+            policy.sample(&call("search", CallStatus::Error), 0.0),
+            Some(1.0)
+        );
+        assert_eq!(
+            policy.sample(&call("checkout", CallStatus::Success), 0.0),
+            Some(1.0)
+        );
+        assert!(!policy.is_uniform());
+        assert!(SamplingPolicy::new(0.5).is_uniform());
+    }
+
+    #[test]
+    fn test_policy_samples_traces_consistently() {
+        let policy = SamplingPolicy::new(0.5).by_trace_id();
+        let kept = (0..200)
+            .filter(|i| {
+                let trace_id = crate::TraceId::new(format!("trace-{}", i)).unwrap();
+                let call = LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .trace_id(trace_id)
+                    .build();
+                let kept = policy.sample(&call, 0.5);
+                assert!((0..5).all(|_| policy.sample(&call, 0.5) == kept));
+                kept.is_some()
+            })
+            .count();
+        assert!((60..140).contains(&kept), "kept {} of 200 traces", kept);
+    }
+
+    #[test]
+    fn test_rate_counts_calls_dropped_at_the_cap() {
+        let sampler = AdaptiveSampler::new(VolumeCap::BytesPerMinute(1000));
+        let start = Instant::now();
+
+        assert_eq!(sampler.sample_at(600.0, start, 0.0), Some(1.0));
+        assert_eq!(sampler.sample_at(600.0, start, 0.0), None);
+        assert_eq!(sampler.sample_at(300.0, start, 0.0), Some(0.6));
+    }
+
+    #[test]
+    fn test_idle_gap_resets_rate() {
+        let sampler = AdaptiveSampler::new(VolumeCap::BytesPerMinute(1000));
+        let start = Instant::now();
+        for _ in 0..10 {
+            sampler.sample_at(500.0, start, 0.0);
+        }
+        assert_eq!(sampler.sample_at(500.0, start + WINDOW, 0.0), Some(0.2));
+        assert_eq!(sampler.sample_at(500.0, start + WINDOW * 4, 0.9), Some(1.0));
+    }
+}
@@ -0,0 +1,166 @@
+//! Flush scheduling.
+//!
+//! The ingest API can ask a client to send fewer batches with an
+//! `X-Ingest-Rate` header giving the batches per second it accepts from it.
+//! Background flushes are then spaced at least that far apart, and flushes
+//! triggered by a full batch wait for the next background flush instead of
+//! exceeding the rate. A hint never spaces batches more than a minute apart,
+//! however low its rate. Clients also start their flush interval at a random
+//! phase so that replicas started together do not flush in step.
+
+use reqwest::header::HeaderMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::client::random_unit;
+
+/// Response header carrying the batches per second the API accepts.
+pub(crate) const INGEST_RATE_HEADER: &str = "X-Ingest-Rate";
+
+/// Longest spacing between batches a rate hint can ask for, so that a tiny
+/// rate does not stall flushes while the buffer fills.
+const MAX_SPACING: Duration = Duration::from_secs(60);
+
+/// Flush pacing of one client.
+#[derive(Debug, Default)]
+pub(crate) struct FlushSchedule {
+    /// Minimum time between batches requested by the API, in milliseconds;
+    /// 0 if none.
+    min_spacing_ms: AtomicU64,
+    last_sent: Mutex<Option<Instant>>,
+}
+
+impl FlushSchedule {
+    /// Record a batch accepted with a response carrying `headers`.
+    ///
+    /// A response without a rate hint lifts any earlier one.
+    pub(crate) fn sent(&self, headers: &HeaderMap) {
+        let spacing = ingest_rate(headers).map_or(0, |rate| {
+            (1000.0 / rate).ceil().min(MAX_SPACING.as_millis() as f64) as u64
+        });
+        self.min_spacing_ms.store(spacing, Ordering::Relaxed);
+        let mut last_sent = self.last_sent.lock().unwrap_or_else(|e| e.into_inner());
+        // Keep the slot of a batch waiting in `pace`
+        let now = Instant::now();
+        *last_sent = Some(last_sent.map_or(now, |last| last.max(now)));
+    }
+
+    /// Wait until a batch may be sent within the requested rate, reserving
+    /// its slot so that batches sent concurrently are spaced too.
+    pub(crate) async fn pace(&self) {
+        let spacing = self.min_spacing();
+        if spacing.is_zero() {
+            return;
+        }
+        let slot = {
+            let mut last_sent = self.last_sent.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let slot = last_sent
+                .and_then(|last| last.checked_add(spacing))
+                .map_or(now, |next| next.max(now));
+            *last_sent = Some(slot);
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    fn min_spacing(&self) -> Duration {
+        Duration::from_millis(self.min_spacing_ms.load(Ordering::Relaxed))
+    }
+
+    /// Time until the next background flush, at least `configured`.
+    pub(crate) fn interval(&self, configured: Duration) -> Duration {
+        configured.max(self.min_spacing())
+    }
+
+    /// Whether a batch sent now stays within the requested rate.
+    pub(crate) fn ready(&self) -> bool {
+        let spacing = self.min_spacing();
+        spacing.is_zero()
+            || self
+                .last_sent
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .is_none_or(|last| last.elapsed() >= spacing)
+    }
+}
+
+/// A random delay within one `interval`, before the first background flush.
+pub(crate) fn phase_offset(interval: Duration) -> Duration {
+    interval.mul_f64(random_unit())
+}
+
+/// Batches per second requested by the `X-Ingest-Rate` header.
+fn ingest_rate(headers: &HeaderMap) -> Option<f64> {
+    let rate: f64 = headers
+        .get(INGEST_RATE_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    (rate.is_finite() && rate > 0.0).then_some(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(rate: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(INGEST_RATE_HEADER, rate.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_rate_hint_spaces_flushes() {
+        let schedule = FlushSchedule::default();
+        let configured = Duration::from_secs(5);
+        assert!(schedule.ready());
+
+        schedule.sent(&headers("0.1"));
+        assert_eq!(schedule.interval(configured), Duration::from_secs(10));
+        assert!(!schedule.ready());
+
+        schedule.sent(&headers("bogus"));
+        assert_eq!(schedule.interval(configured), configured);
+        assert!(schedule.ready());
+
+        schedule.sent(&headers("-1"));
+        assert!(schedule.ready());
+    }
+
+    #[test]
+    fn test_tiny_rates_are_capped() {
+        let schedule = FlushSchedule::default();
+        let configured = Duration::from_secs(5);
+
+        schedule.sent(&headers("0.001"));
+        assert_eq!(schedule.interval(configured), MAX_SPACING);
+
+        schedule.sent(&headers("1e-20"));
+        assert_eq!(schedule.interval(configured), MAX_SPACING);
+        assert!(!schedule.ready());
+    }
+
+    #[tokio::test]
+    async fn test_pace_waits_at_most_the_capped_spacing() {
+        let schedule = FlushSchedule::default();
+        schedule.sent(&headers("1e-20"));
+        let paced = tokio::time::timeout(Duration::from_millis(50), schedule.pace()).await;
+        // Still waiting for the capped slot, without overflowing
+        assert!(paced.is_err());
+        let slot = schedule.last_sent.lock().unwrap().unwrap();
+        assert!(slot <= Instant::now() + MAX_SPACING);
+    }
+
+    #[tokio::test]
+    async fn test_pace_spaces_concurrent_batches() {
+        let schedule = FlushSchedule::default();
+        schedule.sent(&headers("20"));
+        let start = Instant::now();
+        tokio::join!(schedule.pace(), schedule.pace());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
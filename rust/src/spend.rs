@@ -0,0 +1,197 @@
+//! Month-to-date spend per project, kept locally.
+//!
+//! The cache is updated with the estimated cost of every tracked call and
+//! periodically reconciled with the totals reported by the API. When a path
+//! is configured it is persisted as JSON, so the running total survives
+//! restarts and can be displayed without an API call.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::error::DiagnyxError;
+use crate::pricing;
+use crate::types::LLMCall;
+
+/// Month-to-date spend of a project as reported by the API.
+#[derive(Debug, Deserialize)]
+pub(crate) struct MonthToDateResponse {
+    pub month: String,
+    pub total_cost: f64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SpendFile {
+    /// Calendar month of the totals, as `YYYY-MM` in UTC.
+    month: String,
+    /// Spend in USD by project ID.
+    projects: HashMap<String, f64>,
+}
+
+/// Running month-to-date spend per project.
+#[derive(Debug)]
+pub(crate) struct SpendCache {
+    path: Option<PathBuf>,
+    state: Mutex<SpendFile>,
+}
+
+impl SpendCache {
+    /// Create a cache, loading previous totals from `path` if it exists.
+    ///
+    /// An unreadable file is ignored; the totals are rebuilt from tracked
+    /// calls and the next reconciliation.
+    pub(crate) fn open(path: Option<PathBuf>) -> Self {
+        let state = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Add the estimated cost of a tracked call.
+    ///
+    /// Calls without a project, or from a month before the current totals,
+    /// are ignored.
+    pub(crate) fn record(&self, call: &LLMCall) {
+        let Some(project) = &call.project_id else {
+            return;
+        };
+
+        let month = month_of(call.timestamp);
+        let mut state = self.state.lock().unwrap();
+        if month < state.month {
+            return;
+        }
+        if month > state.month {
+            state.month = month;
+            state.projects.clear();
+        }
+        *state.projects.entry(project.to_string()).or_default() += call_cost(call);
+    }
+
+    /// Month-to-date spend of `project` in USD.
+    pub(crate) fn month_to_date(&self, project: &str) -> f64 {
+        let state = self.state.lock().unwrap();
+        if state.month != month_of(Utc::now()) {
+            return 0.0;
+        }
+        state.projects.get(project).copied().unwrap_or(0.0)
+    }
+
+    /// Projects with spend in the current month.
+    pub(crate) fn projects(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        if state.month != month_of(Utc::now()) {
+            return Vec::new();
+        }
+        state.projects.keys().cloned().collect()
+    }
+
+    /// Replace the total of `project` for `month` with an authoritative one.
+    pub(crate) fn reconcile(&self, project: &str, month: &str, total: f64) {
+        let mut state = self.state.lock().unwrap();
+        if month < state.month.as_str() {
+            return;
+        }
+        if month > state.month.as_str() {
+            state.month = month.to_string();
+            state.projects.clear();
+        }
+        state.projects.insert(project.to_string(), total);
+    }
+
+    /// Write the totals to the configured path, if any.
+    pub(crate) fn save(&self) -> Result<(), DiagnyxError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let contents = serde_json::to_string_pretty(&*self.state.lock().unwrap())?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+}
+
+/// Calendar month of `timestamp`, as `YYYY-MM`.
+pub(crate) fn month_of(timestamp: DateTime<Utc>) -> String {
+    timestamp.format("%Y-%m").to_string()
+}
+
+/// Estimated cost of `call` in USD, or zero for models without a known price.
+pub(crate) fn call_cost(call: &LLMCall) -> f64 {
+    call.estimated_cost_usd.unwrap_or_else(|| {
+        pricing::price_for(&call.provider, &call.model).map_or(0.0, |price| {
+            price.cost(call.input_tokens, call.output_tokens)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProjectId, Provider};
+
+    fn call(project: &'static str, timestamp: DateTime<Utc>) -> LLMCall {
+        let mut call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4")
+            .input_tokens(1000)
+            .output_tokens(500)
+            .project_id(ProjectId::from_static(project))
+            .build();
+        call.timestamp = timestamp;
+        call
+    }
+
+    #[test]
+    fn test_records_and_persists_spend() {
+        let path =
+            std::env::temp_dir().join(format!("diagnyx-spend-{}.json", uuid::Uuid::new_v4()));
+        let cache = SpendCache::open(Some(path.clone()));
+
+        cache.record(&call("proj-1", Utc::now()));
+        cache.record(&call("proj-1", Utc::now()));
+        assert!((cache.month_to_date("proj-1") - 0.12).abs() < 1e-9);
+        assert_eq!(cache.month_to_date("proj-2"), 0.0);
+        cache.save().unwrap();
+
+        let reopened = SpendCache::open(Some(path.clone()));
+        assert!((reopened.month_to_date("proj-1") - 0.12).abs() < 1e-9);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_new_month_resets_totals() {
+        let cache = SpendCache::open(None);
+        let last_month = Utc::now() - chrono::Duration::days(40);
+
+        cache.record(&call("proj-1", last_month));
+        cache.record(&call("proj-2", Utc::now()));
+        cache.record(&call("proj-1", last_month));
+
+        assert_eq!(cache.month_to_date("proj-1"), 0.0);
+        assert_eq!(cache.projects(), vec!["proj-2".to_string()]);
+    }
+
+    #[test]
+    fn test_reconcile_replaces_total() {
+        let cache = SpendCache::open(None);
+        cache.record(&call("proj-1", Utc::now()));
+
+        cache.reconcile("proj-1", &month_of(Utc::now()), 5.0);
+        assert_eq!(cache.month_to_date("proj-1"), 5.0);
+    }
+}
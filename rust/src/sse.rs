@@ -0,0 +1,224 @@
+//! Parsing of server-sent event bodies.
+//!
+//! Follows the event stream format: lines starting with `:` are comments,
+//! such as `: keep-alive` heartbeats, and are skipped; `data:` lines of one
+//! event are joined with newlines; and a `retry:` field carries the delay
+//! the server suggests before reconnecting or retrying.
+//!
+//! Bodies can be parsed whole with [`parse`], or as they arrive with an
+//! `EventReader`, which hands over each event as soon as it is complete.
+
+use std::time::Duration;
+
+/// An event dispatched by a blank line, or by the end of the body.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SseEvent {
+    /// Value of the `event:` field, if any.
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Events of a body and the last retry delay it suggested.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SseBody {
+    pub events: Vec<SseEvent>,
+    pub retry: Option<Duration>,
+}
+
+/// Parse an event stream body.
+///
+/// Events without data, e.g. those made only of comments, are not
+/// dispatched.
+pub(crate) fn parse(text: &str) -> SseBody {
+    let mut decoder = Decoder::default();
+    let mut events = decoder.feed(text.as_bytes());
+    let body = decoder.finish();
+    events.extend(body.events);
+    SseBody {
+        events,
+        retry: body.retry,
+    }
+}
+
+/// Reads the events of a response as its body arrives.
+#[cfg(feature = "guardrails")]
+pub(crate) struct EventReader {
+    response: reqwest::Response,
+    decoder: Decoder,
+    ready: std::collections::VecDeque<SseEvent>,
+    /// The body read so far, kept until an event is seen.
+    raw: Vec<u8>,
+    seen: bool,
+    done: bool,
+}
+
+#[cfg(feature = "guardrails")]
+impl EventReader {
+    pub(crate) fn new(response: reqwest::Response) -> Self {
+        Self {
+            response,
+            decoder: Decoder::default(),
+            ready: Default::default(),
+            raw: Vec::new(),
+            seen: false,
+            done: false,
+        }
+    }
+
+    /// The next event, waiting for more of the body if needed, or `None`
+    /// at the end of the body.
+    pub(crate) async fn next(&mut self) -> Result<Option<SseEvent>, crate::DiagnyxError> {
+        loop {
+            if let Some(event) = self.ready.pop_front() {
+                self.seen = true;
+                self.raw.clear();
+                return Ok(Some(event));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => {
+                    if !self.seen {
+                        self.raw.extend_from_slice(&chunk);
+                    }
+                    self.ready.extend(self.decoder.feed(&chunk));
+                }
+                None => {
+                    self.done = true;
+                    self.ready
+                        .extend(std::mem::take(&mut self.decoder).finish().events);
+                }
+            }
+        }
+    }
+
+    /// The body, if it held no events, e.g. a plain JSON response.
+    pub(crate) fn into_body(self) -> Option<String> {
+        (!self.seen).then(|| String::from_utf8_lossy(&self.raw).into_owned())
+    }
+}
+
+/// Incremental parser fed with chunks of a body.
+#[derive(Debug, Default)]
+struct Decoder {
+    /// Bytes of a line whose end has not arrived yet.
+    partial: Vec<u8>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Decoder {
+    /// Parse the complete lines of `chunk`, returning the events they
+    /// dispatch.
+    fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        self.partial.extend_from_slice(chunk);
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]);
+            self.line(line.strip_suffix('\r').unwrap_or(&line), &mut events);
+        }
+        events
+    }
+
+    /// Parse the last line, if unterminated, and dispatch the last event.
+    fn finish(mut self) -> SseBody {
+        let mut events = Vec::new();
+        if !self.partial.is_empty() {
+            let line = String::from_utf8_lossy(&std::mem::take(&mut self.partial)).into_owned();
+            self.line(line.strip_suffix('\r').unwrap_or(&line), &mut events);
+        }
+        self.dispatch(&mut events);
+        SseBody {
+            events,
+            retry: self.retry,
+        }
+    }
+
+    fn line(&mut self, line: &str, events: &mut Vec<SseEvent>) {
+        if line.is_empty() {
+            self.dispatch(events);
+            return;
+        }
+        if line.starts_with(':') {
+            return;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "retry" => {
+                if let Ok(ms) = value.parse::<u64>() {
+                    self.retry = Some(Duration::from_millis(ms));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn dispatch(&mut self, events: &mut Vec<SseEvent>) {
+        let event = self.event.take();
+        if let Some(data) = self.data.take() {
+            events.push(SseEvent { event, data });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skips_comments_and_joins_data() {
+        let body = parse(
+            ": keep-alive\n\nevent: token_allowed\ndata:{\"a\":1,\ndata: \"b\":2}\n\n: keep-alive\n\n",
+        );
+        assert_eq!(
+            body.events,
+            vec![SseEvent {
+                event: Some("token_allowed".to_string()),
+                data: "{\"a\":1,\n\"b\":2}".to_string(),
+            }]
+        );
+        assert_eq!(body.retry, None);
+    }
+
+    #[test]
+    fn test_reads_retry_and_unterminated_event() {
+        let body = parse("retry: 2500\nretry: soon\n\ndata: last");
+        assert_eq!(body.retry, Some(Duration::from_millis(2500)));
+        assert_eq!(body.events.len(), 1);
+        assert_eq!(body.events[0].event, None);
+        assert_eq!(body.events[0].data, "last");
+    }
+
+    #[test]
+    fn test_decoder_handles_split_lines() {
+        let mut decoder = Decoder::default();
+        assert!(decoder.feed(b"event: sess").is_empty());
+        assert!(decoder
+            .feed(b"ion_complete\r\ndata: {\"allowed\"")
+            .is_empty());
+        let events = decoder.feed(b":true}\r\n\r\nevent: tail\ndata: x");
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                event: Some("session_complete".to_string()),
+                data: "{\"allowed\":true}".to_string(),
+            }]
+        );
+        assert_eq!(decoder.finish().events[0].data, "x");
+    }
+}
@@ -0,0 +1,362 @@
+//! Tracking of streamed LLM responses.
+//!
+//! [`StreamTracker`] wraps a stream of text chunks from any provider SDK
+//! and passes them through unchanged. It measures the time to the first
+//! non-empty chunk and the total latency, accumulates the output, and
+//! tracks a single [`LLMCall`] when the stream ends, fails or is dropped.
+//!
+//! Token counts are estimated from the prompt and output text unless given
+//! explicitly. With the `tokenizers` feature they are counted with the
+//! model's BPE encoding.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::streaming::StreamTracker;
+//! use diagnyx::{DiagnyxClient, Provider};
+//! use futures::StreamExt;
+//! use std::sync::Arc;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let diagnyx = Arc::new(DiagnyxClient::new("dx_live_your_api_key"));
+//!
+//!     // Chunks from the provider's streaming API
+//!     let chunks = futures::stream::iter(vec!["Hello".to_string(), ", world".to_string()]);
+//!     let mut stream = StreamTracker::new(chunks, diagnyx.clone(), Provider::Anthropic, "claude-3-5-sonnet")
+//!         .prompt("Say hello");
+//!
+//!     while let Some(chunk) = stream.next().await {
+//!         print!("{}", chunk);
+//!     }
+//! }
+//! ```
+
+use futures::Stream;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use crate::client::DiagnyxClient;
+use crate::ids::{ProjectId, TraceId};
+use crate::types::{CallStatus, LLMCall, LLMCallBuilder, Provider};
+
+/// A chunk of a streamed response.
+///
+/// Implemented for text chunks and for results of text chunks, whose errors
+/// end the tracked call with an error status.
+pub trait StreamChunk {
+    /// The chunk's text, or a description of the error.
+    fn text(&self) -> Result<&str, String>;
+}
+
+impl StreamChunk for String {
+    fn text(&self) -> Result<&str, String> {
+        Ok(self)
+    }
+}
+
+impl StreamChunk for &str {
+    fn text(&self) -> Result<&str, String> {
+        Ok(self)
+    }
+}
+
+impl<E: fmt::Display> StreamChunk for Result<String, E> {
+    fn text(&self) -> Result<&str, String> {
+        self.as_deref().map_err(|e| e.to_string())
+    }
+}
+
+/// The call being recorded while the stream is consumed.
+struct Recording {
+    builder: LLMCallBuilder,
+    model: String,
+    start: Instant,
+    ttft_ms: Option<i64>,
+    prompt: Option<String>,
+    input_tokens: Option<i32>,
+    output: String,
+}
+
+/// A stream that tracks the response it yields as a single call.
+pub struct StreamTracker<S> {
+    inner: Pin<Box<S>>,
+    diagnyx: Arc<DiagnyxClient>,
+    recording: Option<Recording>,
+}
+
+impl<S> StreamTracker<S>
+where
+    S: Stream,
+    S::Item: StreamChunk,
+{
+    /// Wrap `inner`, tracking it as a call to `model` with `diagnyx`.
+    ///
+    /// Latency is measured from the creation of the tracker; use
+    /// [`started_at`](Self::started_at) if the request was sent earlier.
+    pub fn new(
+        inner: S,
+        diagnyx: Arc<DiagnyxClient>,
+        provider: Provider,
+        model: impl Into<String>,
+    ) -> Self {
+        let model = model.into();
+        Self {
+            inner: Box::pin(inner),
+            diagnyx,
+            recording: Some(Recording {
+                builder: LLMCall::builder().provider(provider).model(&model),
+                model,
+                start: Instant::now(),
+                ttft_ms: None,
+                prompt: None,
+                input_tokens: None,
+                output: String::new(),
+            }),
+        }
+    }
+
+    /// Measure latency from `start`, e.g. when the request was sent.
+    pub fn started_at(mut self, start: Instant) -> Self {
+        self.update(|recording| recording.start = start);
+        self
+    }
+
+    /// The prompt, used to count input tokens and captured when full
+    /// content capture is enabled.
+    pub fn prompt(mut self, prompt: impl Into<String>) -> Self {
+        let prompt = prompt.into();
+        self.update(|recording| recording.prompt = Some(prompt));
+        self
+    }
+
+    /// Input token count reported by the provider, used instead of counting
+    /// the prompt.
+    pub fn input_tokens(mut self, tokens: i32) -> Self {
+        self.update(|recording| recording.input_tokens = Some(tokens));
+        self
+    }
+
+    pub fn endpoint(self, endpoint: impl Into<String>) -> Self {
+        self.with_builder(|builder| builder.endpoint(endpoint))
+    }
+
+    pub fn project_id(self, id: ProjectId) -> Self {
+        self.with_builder(|builder| builder.project_id(id))
+    }
+
+    pub fn environment(self, env: impl Into<String>) -> Self {
+        self.with_builder(|builder| builder.environment(env))
+    }
+
+    pub fn user_identifier(self, id: impl Into<String>) -> Self {
+        self.with_builder(|builder| builder.user_identifier(id))
+    }
+
+    pub fn trace_id(self, id: TraceId) -> Self {
+        self.with_builder(|builder| builder.trace_id(id))
+    }
+
+    /// Output accumulated so far, or empty once the call is tracked.
+    pub fn output(&self) -> &str {
+        self.recording
+            .as_ref()
+            .map_or("", |recording| recording.output.as_str())
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut Recording)) {
+        if let Some(recording) = &mut self.recording {
+            f(recording);
+        }
+    }
+
+    fn with_builder(mut self, f: impl FnOnce(LLMCallBuilder) -> LLMCallBuilder) -> Self {
+        if let Some(mut recording) = self.recording.take() {
+            recording.builder = f(recording.builder);
+            self.recording = Some(recording);
+        }
+        self
+    }
+}
+
+impl<S> StreamTracker<S> {
+    /// Track the recorded call, if not already tracked, ending with
+    /// `status` and `error`.
+    fn track(&mut self, status: CallStatus, error: Option<String>) {
+        let Some(recording) = self.recording.take() else {
+            return;
+        };
+        let output_tokens = count_tokens(&recording.model, &recording.output);
+        let input_tokens = recording.input_tokens.unwrap_or_else(|| {
+            recording
+                .prompt
+                .as_deref()
+                .map_or(0, |prompt| count_tokens(&recording.model, prompt))
+        });
+
+        let mut builder = recording
+            .builder
+            .input_tokens(input_tokens)
+            .output_tokens(output_tokens)
+            .latency_ms(recording.start.elapsed().as_millis() as i64)
+            .status(status);
+        if let Some(ttft) = recording.ttft_ms {
+            builder = builder.ttft_ms(ttft);
+        }
+        if let Some(error) = error {
+            builder = builder.error_message(error);
+        }
+        if self.diagnyx.capture_full_content() {
+            if let Some(prompt) = recording.prompt {
+                builder = builder.full_prompt(prompt);
+            }
+            builder = builder.full_response(recording.output);
+        }
+
+        let call = builder.build();
+        let diagnyx = Arc::clone(&self.diagnyx);
+        // The stream may be dropped outside a runtime, where the call is lost
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move { diagnyx.track(call).await });
+        }
+    }
+}
+
+impl<S> Stream for StreamTracker<S>
+where
+    S: Stream,
+    S::Item: StreamChunk,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let poll = this.inner.as_mut().poll_next(cx);
+
+        match &poll {
+            Poll::Ready(Some(chunk)) => match chunk.text() {
+                Ok(text) => {
+                    if let Some(recording) = &mut this.recording {
+                        if !text.is_empty() && recording.ttft_ms.is_none() {
+                            recording.ttft_ms = Some(recording.start.elapsed().as_millis() as i64);
+                        }
+                        recording.output.push_str(text);
+                    }
+                }
+                Err(e) => this.track(CallStatus::Error, Some(e)),
+            },
+            Poll::Ready(None) => this.track(CallStatus::Success, None),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl<S> Drop for StreamTracker<S> {
+    fn drop(&mut self) {
+        self.track(
+            CallStatus::Error,
+            Some("stream dropped before completion".to_string()),
+        );
+    }
+}
+
+fn count_tokens(model: &str, text: &str) -> i32 {
+    #[cfg(feature = "tokenizers")]
+    return crate::tokens::count(model, text);
+    #[cfg(not(feature = "tokenizers"))]
+    {
+        let _ = model;
+        crate::pricing::Prompt::Text(text).tokens()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DiagnyxConfig;
+    use futures::StreamExt;
+    use std::time::Duration;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn client(server: &MockServer) -> Arc<DiagnyxClient> {
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "tracked": 1,
+                "total_cost": 0.0,
+                "total_tokens": 0,
+                "ids": []
+            })))
+            .mount(server)
+            .await;
+        Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000),
+        ))
+    }
+
+    /// Wait for the tracked call, flush and return the calls sent.
+    async fn tracked(server: &MockServer, diagnyx: &DiagnyxClient) -> Vec<serde_json::Value> {
+        while diagnyx.buffer_size().await == 0 {
+            tokio::task::yield_now().await;
+        }
+        diagnyx.flush().await.unwrap();
+        let request = &server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        body["calls"].as_array().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_tracks_completed_stream() {
+        let server = MockServer::start().await;
+        let diagnyx = client(&server).await;
+
+        let chunks = futures::stream::iter(["", "Hello", ", world!"]).then(|chunk| async move {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            chunk
+        });
+        let mut stream = StreamTracker::new(chunks, diagnyx.clone(), Provider::OpenAI, "gpt-4")
+            .prompt("Say hello to the world")
+            .endpoint("/chat/completions");
+        let mut output = String::new();
+        while let Some(chunk) = stream.next().await {
+            output.push_str(chunk);
+        }
+        assert_eq!(output, "Hello, world!");
+
+        let calls = tracked(&server, &diagnyx).await;
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0]["status"], "success");
+        assert!(calls[0]["input_tokens"].as_i64().unwrap() > 0);
+        assert!(calls[0]["output_tokens"].as_i64().unwrap() > 0);
+        let ttft = calls[0]["ttft_ms"].as_i64().unwrap();
+        assert!(ttft >= 10 && ttft <= calls[0]["latency_ms"].as_i64().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_tracks_stream_error_once() {
+        let server = MockServer::start().await;
+        let diagnyx = client(&server).await;
+
+        let chunks = futures::stream::iter(vec![
+            Ok("partial".to_string()),
+            Err("connection reset"),
+            Ok("ignored".to_string()),
+        ]);
+        let stream =
+            StreamTracker::new(chunks, diagnyx.clone(), Provider::OpenAI, "gpt-4").input_tokens(12);
+        assert_eq!(stream.collect::<Vec<_>>().await.len(), 3);
+
+        let calls = tracked(&server, &diagnyx).await;
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0]["status"], "error");
+        assert_eq!(calls[0]["error_message"], "connection reset");
+        assert_eq!(calls[0]["input_tokens"], 12);
+    }
+}
@@ -0,0 +1,278 @@
+//! Local token counting with tiktoken-compatible BPE files.
+//!
+//! Many providers do not report token usage for streamed responses. This
+//! module counts tokens locally using the byte-pair encodings published for
+//! OpenAI models (`cl100k_base.tiktoken`, `o200k_base.tiktoken`, ...).
+//!
+//! The BPE files are not bundled with the crate. They are loaded on first
+//! use from the directory set with [`set_bpe_dir`], or the
+//! `DIAGNYX_TIKTOKEN_DIR` environment variable, or can be registered
+//! directly with [`register`]. When no file is available for a model's
+//! encoding, [`count`] falls back to estimating four characters per token.
+//!
+//! Models without a published encoding, such as Claude, are counted with
+//! `cl100k_base`, which gives a close approximation.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::tokens;
+//!
+//! tokens::set_bpe_dir("/opt/tiktoken");
+//! let count = tokens::count("gpt-4o", "How many tokens is this?");
+//! println!("{} tokens", count);
+//! ```
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use fancy_regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::error::DiagnyxError;
+use crate::pricing::Prompt;
+
+const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+const O200K_PATTERN: &str = concat!(
+    r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+    r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|\s+(?!\S)|\s+",
+);
+
+const P50K_PATTERN: &str =
+    r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+
+/// A byte-pair encoding loaded from a `.tiktoken` file.
+#[derive(Debug)]
+pub struct Encoding {
+    ranks: HashMap<Vec<u8>, u32>,
+    pattern: Regex,
+}
+
+impl Encoding {
+    /// Parse the contents of a `.tiktoken` file, splitting text into pieces
+    /// with the regex `pattern` before merging.
+    ///
+    /// Each line holds a base64-encoded token and its rank.
+    pub fn from_tiktoken(contents: &str, pattern: &str) -> Result<Self, DiagnyxError> {
+        let mut ranks = HashMap::new();
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid =
+                || DiagnyxError::ConfigError(format!("invalid BPE entry on line {}", number + 1));
+            let (token, rank) = line.split_once(' ').ok_or_else(invalid)?;
+            let token = STANDARD.decode(token).map_err(|_| invalid())?;
+            let rank = rank.trim().parse().map_err(|_| invalid())?;
+            ranks.insert(token, rank);
+        }
+        let pattern = Regex::new(pattern)
+            .map_err(|e| DiagnyxError::ConfigError(format!("invalid token pattern: {}", e)))?;
+        Ok(Self { ranks, pattern })
+    }
+
+    /// Load a `.tiktoken` file for the encoding `name`, e.g. `cl100k_base`.
+    pub fn from_file(name: &str, path: impl AsRef<Path>) -> Result<Self, DiagnyxError> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::from_tiktoken(&contents, pattern_for(name))
+    }
+
+    /// Number of tokens in `text`.
+    pub fn count(&self, text: &str) -> usize {
+        self.pattern
+            .find_iter(text)
+            .filter_map(Result::ok)
+            .map(|piece| self.count_piece(piece.as_str().as_bytes()))
+            .sum()
+    }
+
+    fn count_piece(&self, piece: &[u8]) -> usize {
+        if self.ranks.contains_key(piece) {
+            return 1;
+        }
+
+        // Start from single bytes and repeatedly merge the adjacent pair
+        // with the lowest rank
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let best = (0..bounds.len().saturating_sub(2))
+                .filter_map(|i| {
+                    let pair = &piece[bounds[i]..bounds[i + 2]];
+                    self.ranks.get(pair).map(|rank| (*rank, i))
+                })
+                .min();
+            let Some((_, i)) = best else {
+                return bounds.len() - 1;
+            };
+            bounds.remove(i + 1);
+        }
+    }
+}
+
+/// Name of the encoding used by `model`.
+pub fn encoding_for(model: &str) -> &'static str {
+    const O200K: &[&str] = &[
+        "gpt-4o",
+        "gpt-4.1",
+        "gpt-4.5",
+        "gpt-5",
+        "o1",
+        "o3",
+        "o4",
+        "chatgpt-4o",
+    ];
+    const P50K: &[&str] = &["text-davinci-002", "text-davinci-003", "code-"];
+    const R50K: &[&str] = &["davinci", "curie", "babbage", "ada", "text-davinci-001"];
+
+    let model = model.rsplit('/').next().unwrap_or(model);
+    if O200K.iter().any(|prefix| model.starts_with(prefix)) {
+        "o200k_base"
+    } else if P50K.iter().any(|prefix| model.starts_with(prefix)) {
+        "p50k_base"
+    } else if R50K.iter().any(|prefix| model.starts_with(prefix)) {
+        "r50k_base"
+    } else {
+        "cl100k_base"
+    }
+}
+
+/// Pre-tokenization pattern of the encoding `name`.
+fn pattern_for(name: &str) -> &'static str {
+    match name {
+        "o200k_base" => O200K_PATTERN,
+        "p50k_base" | "r50k_base" => P50K_PATTERN,
+        _ => CL100K_PATTERN,
+    }
+}
+
+struct Registry {
+    dir: Option<PathBuf>,
+    /// Loaded encodings, or `None` if loading failed.
+    encodings: HashMap<String, Option<Arc<Encoding>>>,
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        Mutex::new(Registry {
+            dir: std::env::var_os("DIAGNYX_TIKTOKEN_DIR").map(PathBuf::from),
+            encodings: HashMap::new(),
+        })
+    })
+}
+
+/// Set the directory containing `<encoding>.tiktoken` files.
+///
+/// Overrides `DIAGNYX_TIKTOKEN_DIR`. Encodings that failed to load are
+/// retried from the new directory.
+pub fn set_bpe_dir(dir: impl Into<PathBuf>) {
+    let mut registry = registry().lock().unwrap();
+    registry.dir = Some(dir.into());
+    registry.encodings.retain(|_, encoding| encoding.is_some());
+}
+
+/// Use `encoding` for models with the encoding `name`.
+pub fn register(name: impl Into<String>, encoding: Encoding) {
+    registry()
+        .lock()
+        .unwrap()
+        .encodings
+        .insert(name.into(), Some(Arc::new(encoding)));
+}
+
+/// The encoding `name`, loading it from the BPE directory on first use.
+pub fn encoding(name: &str) -> Option<Arc<Encoding>> {
+    let mut registry = registry().lock().unwrap();
+    if let Some(encoding) = registry.encodings.get(name) {
+        return encoding.clone();
+    }
+
+    let encoding = registry
+        .dir
+        .as_ref()
+        .and_then(|dir| Encoding::from_file(name, dir.join(format!("{}.tiktoken", name))).ok())
+        .map(Arc::new);
+    registry
+        .encodings
+        .insert(name.to_string(), encoding.clone());
+    encoding
+}
+
+/// Number of tokens in `text` for `model`.
+///
+/// Estimated from the text length if the model's encoding is unavailable.
+pub fn count(model: &str, text: &str) -> i32 {
+    match encoding(encoding_for(model)) {
+        Some(encoding) => i32::try_from(encoding.count(text)).unwrap_or(i32::MAX),
+        None => Prompt::Text(text).tokens(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An encoding with every byte plus merges for "he", "ll", "hell",
+    /// "hello" and " world".
+    fn test_encoding() -> String {
+        let mut tokens: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).collect();
+        for merge in [
+            "he", "ll", "hell", "hello", " w", "or", " wor", "ld", " world",
+        ] {
+            tokens.push(merge.as_bytes().to_vec());
+        }
+        tokens
+            .iter()
+            .enumerate()
+            .map(|(rank, token)| format!("{} {}\n", STANDARD.encode(token), rank))
+            .collect()
+    }
+
+    #[test]
+    fn test_counts_merged_pieces() {
+        let encoding = Encoding::from_tiktoken(&test_encoding(), CL100K_PATTERN).unwrap();
+        assert_eq!(encoding.count("hello world"), 2);
+        assert_eq!(encoding.count("hellx"), 2);
+        assert_eq!(encoding.count(""), 0);
+    }
+
+    #[test]
+    fn test_loads_encoding_from_bpe_dir() {
+        let dir = std::env::temp_dir().join(format!("diagnyx-bpe-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("p50k_base.tiktoken"), test_encoding()).unwrap();
+
+        /// Put the registry back as other tests expect it, even on failure.
+        struct Restore(Option<PathBuf>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+                registry.dir = self.0.take();
+                registry.encodings.remove("p50k_base");
+            }
+        }
+        let _restore = Restore(registry().lock().unwrap().dir.clone());
+
+        set_bpe_dir(&dir);
+        assert_eq!(count("text-davinci-003", "hello world"), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_encoding_for_models() {
+        assert_eq!(encoding_for("gpt-4o-mini"), "o200k_base");
+        assert_eq!(encoding_for("gpt-4-turbo"), "cl100k_base");
+        assert_eq!(encoding_for("text-embedding-ada-002"), "cl100k_base");
+        assert_eq!(encoding_for("davinci"), "r50k_base");
+        assert_eq!(encoding_for("claude-3-5-sonnet"), "cl100k_base");
+    }
+
+    #[test]
+    fn test_invalid_file_is_rejected() {
+        assert!(Encoding::from_tiktoken("not-base64!! 1", CL100K_PATTERN).is_err());
+        assert!(Encoding::from_tiktoken("aGk= x", CL100K_PATTERN).is_err());
+    }
+}
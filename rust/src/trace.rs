@@ -0,0 +1,255 @@
+//! Traces of multi-step workflows.
+//!
+//! A workflow such as a RAG pipeline or an agent loop makes several LLM
+//! calls between other steps. [`DiagnyxClient::start_trace`] opens the root
+//! [`Span`] of a trace; child spans mark the steps, and calls tracked
+//! through a span carry its trace ID, span ID and parent span ID, so the
+//! API can rebuild the workflow.
+//!
+//! Each span is reported as a call of type [`CallType::Span`], named after
+//! the span, with its duration as the latency, when it is
+//! [ended](Span::end) or dropped. A span dropped outside a Tokio runtime is
+//! not reported.
+//!
+//! Trace and span IDs are random hex strings of 32 and 16 characters, the
+//! sizes used by W3C trace context, so they can also be exported with the
+//! `otel` feature.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::{DiagnyxClient, LLMCall, Provider};
+//! use std::sync::Arc;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let client = Arc::new(DiagnyxClient::new("dx_live_your_api_key"));
+//!
+//!     let trace = client.start_trace("rag-pipeline");
+//!     let retrieval = trace.child("retrieval");
+//!     // ...
+//!     retrieval.end().await;
+//!     let answer = trace.child("answer");
+//!     answer
+//!         .track(
+//!             LLMCall::builder()
+//!                 .provider(Provider::OpenAI)
+//!                 .model("gpt-4o")
+//!                 .build(),
+//!         )
+//!         .await;
+//!     answer.end().await;
+//!     trace.end().await;
+//! }
+//! ```
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::client::{random_bits, DiagnyxClient};
+use crate::ids::TraceId;
+use crate::types::{CallType, LLMCall, Provider};
+
+/// Metadata key of the name of the span a call was tracked in.
+pub const SPAN_NAME_KEY: &str = "span_name";
+
+/// A step of a traced workflow.
+pub struct Span {
+    client: Arc<DiagnyxClient>,
+    name: String,
+    trace_id: TraceId,
+    span_id: String,
+    parent_span_id: Option<String>,
+    started_at: DateTime<Utc>,
+    started: Instant,
+    /// Set once the span has been reported.
+    ended: bool,
+}
+
+impl Span {
+    pub(crate) fn root(client: Arc<DiagnyxClient>, name: impl Into<String>) -> Self {
+        let trace_id = format!("{:016x}{:016x}", random_bits(), random_bits());
+        Self::start(
+            client,
+            name.into(),
+            TraceId::new(trace_id).expect("hex is a valid trace ID"),
+            None,
+        )
+    }
+
+    fn start(
+        client: Arc<DiagnyxClient>,
+        name: String,
+        trace_id: TraceId,
+        parent_span_id: Option<String>,
+    ) -> Self {
+        Self {
+            client,
+            name,
+            trace_id,
+            span_id: new_span_id(),
+            parent_span_id,
+            started_at: Utc::now(),
+            started: Instant::now(),
+            ended: false,
+        }
+    }
+
+    /// Start a span nested in this one.
+    pub fn child(&self, name: impl Into<String>) -> Span {
+        Self::start(
+            Arc::clone(&self.client),
+            name.into(),
+            self.trace_id.clone(),
+            Some(self.span_id.clone()),
+        )
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn trace_id(&self) -> &TraceId {
+        &self.trace_id
+    }
+
+    pub fn span_id(&self) -> &str {
+        &self.span_id
+    }
+
+    pub fn parent_span_id(&self) -> Option<&str> {
+        self.parent_span_id.as_deref()
+    }
+
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    /// Set the trace fields of `call` to this span, and its name in the
+    /// call's metadata. Trace fields the call already has are kept.
+    pub fn apply(&self, call: &mut LLMCall) {
+        if call.trace_id.is_some() {
+            return;
+        }
+        call.trace_id = Some(self.trace_id.clone());
+        call.span_id = Some(self.span_id.clone());
+        call.parent_span_id = self.parent_span_id.clone();
+        call.metadata
+            .get_or_insert_with(HashMap::new)
+            .entry(SPAN_NAME_KEY.to_string())
+            .or_insert_with(|| self.name.clone().into());
+    }
+
+    /// Track a call made in this span.
+    pub async fn track(&self, mut call: LLMCall) {
+        self.apply(&mut call);
+        self.client.track(call).await;
+    }
+
+    /// End the span and report it.
+    pub async fn end(mut self) {
+        if let Some(call) = self.finish() {
+            self.client.track(call).await;
+        }
+    }
+
+    /// The call reporting the span, unless it was already reported.
+    fn finish(&mut self) -> Option<LLMCall> {
+        if std::mem::replace(&mut self.ended, true) {
+            return None;
+        }
+        let mut call = LLMCall {
+            call_type: CallType::Span,
+            ..LLMCall::builder()
+                .provider(Provider::Custom)
+                .model(self.name.clone())
+                .latency_ms(self.started.elapsed().as_millis() as i64)
+                .build()
+        };
+        self.apply(&mut call);
+        Some(call)
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(call) = self.finish() else {
+            return;
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let client = Arc::clone(&self.client);
+            runtime.spawn(async move { client.track(call).await });
+        }
+    }
+}
+
+fn new_span_id() -> String {
+    format!("{:016x}", random_bits())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DiagnyxConfig;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_child_spans_link_calls() {
+        let client = Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key").flush_interval_ms(60000),
+        ));
+        let trace = client.start_trace("rag-pipeline");
+        let answer = trace.child("answer");
+
+        let mut call = LLMCall::builder()
+            .provider(Provider::OpenAI)
+            .model("gpt-4o")
+            .build();
+        answer.apply(&mut call);
+
+        assert_eq!(trace.trace_id().as_str().len(), 32);
+        assert_eq!(call.trace_id.as_ref(), Some(trace.trace_id()));
+        assert_eq!(call.span_id.as_deref(), Some(answer.span_id()));
+        assert_eq!(call.parent_span_id.as_deref(), Some(trace.span_id()));
+        assert_eq!(call.metadata.unwrap()[SPAN_NAME_KEY], "answer");
+        assert_ne!(answer.span_id(), trace.span_id());
+    }
+
+    #[tokio::test]
+    async fn test_spans_are_reported_when_ended_or_dropped() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let client = Arc::new(DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-api-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000),
+        ));
+        let trace = client.start_trace("agent");
+        let step = trace.child("tool");
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        step.end().await;
+        drop(trace);
+        // The dropped span is reported by a spawned task
+        while client.buffer_size().await < 2 {
+            tokio::task::yield_now().await;
+        }
+        client.flush().await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+        let calls: Vec<LLMCall> = serde_json::from_value(body["calls"].clone()).unwrap();
+        assert!(calls.iter().all(|call| call.call_type == CallType::Span));
+        let tool = calls.iter().find(|call| call.model == "tool").unwrap();
+        let agent = calls.iter().find(|call| call.model == "agent").unwrap();
+        assert!(tool.latency_ms >= 20);
+        assert_eq!(tool.parent_span_id, agent.span_id);
+        assert_eq!(tool.trace_id, agent.trace_id);
+    }
+}
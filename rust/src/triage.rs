@@ -0,0 +1,533 @@
+//! Routing of negative feedback into evaluation runs and annotation queues.
+//!
+//! [`FeedbackTriage`] periodically pulls recent negative feedback, fetches
+//! the trace each piece of feedback is about, and enqueues the prompt and
+//! response into an evaluation run or an annotation queue, so responses
+//! users disliked are reviewed without separate tooling. A correction
+//! given as feedback becomes the expected output.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::triage::{Destination, FeedbackTriage, FeedbackTriageConfig};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let triage = FeedbackTriage::new(
+//!         FeedbackTriageConfig::new(
+//!             "dx_api_key",
+//!             "org-123",
+//!             Destination::AnnotationQueue("disliked-answers".to_string()),
+//!         )
+//!         .poll_interval(Duration::from_secs(600)),
+//!     );
+//!     let task = Arc::new(triage).start();
+//!     // ...
+//!     task.abort();
+//! }
+//! ```
+
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::error::DiagnyxError;
+use crate::evaluations::{EvaluationClient, EvaluationClientConfig, EvaluationResult};
+use crate::feedback::{
+    Feedback, FeedbackClient, FeedbackClientConfig, FeedbackSentiment, ListFeedbackOptions,
+};
+use crate::http::{HttpConfig, REQUEST_TIMEOUT};
+use crate::ids::TraceId;
+use crate::logger::Logger;
+use crate::ratelimit::RateLimiter;
+use crate::retry::{send_with_retry, RetryPolicy};
+use crate::types::{default_base_url, Masked};
+
+/// Number of feedback records fetched per page.
+const PAGE_SIZE: i32 = 100;
+
+/// Metadata key of the ID of the feedback an item was enqueued for.
+pub const FEEDBACK_ID_KEY: &str = "feedback_id";
+
+/// Where flagged traces are enqueued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// An evaluation run, by ID. Results are submitted with a score of 0.
+    EvaluationRun(String),
+    /// An annotation queue, by ID.
+    AnnotationQueue(String),
+}
+
+/// The prompt and response of a trace.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceRecord {
+    pub trace_id: TraceId,
+    #[serde(default)]
+    pub input: String,
+    #[serde(default)]
+    pub output: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// An item of an annotation queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotationItem {
+    pub trace_id: TraceId,
+    pub feedback_id: String,
+    pub input: String,
+    pub output: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correction: Option<String>,
+}
+
+/// Configuration for FeedbackTriage.
+#[derive(Clone)]
+pub struct FeedbackTriageConfig {
+    pub api_key: String,
+    pub organization_id: String,
+    pub base_url: String,
+    pub destination: Destination,
+    /// Interval between pulls of new feedback. Default: 5 minutes
+    pub poll_interval: Duration,
+    /// How far back the first pull looks. Default: 1 hour
+    pub lookback: Duration,
+    pub retry_policy: RetryPolicy,
+    /// Spaces out requests to the API, retries included. Default: None (no
+    /// limit)
+    pub rate_limiter: Option<RateLimiter>,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
+    pub debug: bool,
+}
+
+impl fmt::Debug for FeedbackTriageConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FeedbackTriageConfig")
+            .field("api_key", &Masked(&self.api_key))
+            .field("organization_id", &self.organization_id)
+            .field("base_url", &self.base_url)
+            .field("destination", &self.destination)
+            .field("poll_interval", &self.poll_interval)
+            .field("lookback", &self.lookback)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .finish()
+    }
+}
+
+impl FeedbackTriageConfig {
+    pub fn new(
+        api_key: impl Into<String>,
+        organization_id: impl Into<String>,
+        destination: Destination,
+    ) -> Self {
+        let api_key = api_key.into();
+        Self {
+            base_url: default_base_url(&api_key),
+            api_key,
+            organization_id: organization_id.into(),
+            destination,
+            poll_interval: Duration::from_secs(300),
+            lookback: Duration::from_secs(3600),
+            retry_policy: RetryPolicy::new(3),
+            rate_limiter: None,
+            http: HttpConfig::default(),
+            debug: false,
+        }
+    }
+
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = url.into();
+        self
+    }
+
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    pub fn lookback(mut self, lookback: Duration) -> Self {
+        self.lookback = lookback;
+        self
+    }
+
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.retry_policy.max_attempts = retries as u32;
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Space out requests with `limiter`, e.g. the one shared by the other
+    /// clients, so that they stay within one rate together.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+}
+
+/// Feedback pulled so far.
+struct Cursor {
+    /// Creation time of the newest feedback pulled.
+    since: DateTime<Utc>,
+    /// IDs of the feedback pulled at `since`, which the next pull returns
+    /// again as its start date is inclusive.
+    seen: HashSet<String>,
+}
+
+/// Enqueues traces with negative feedback for review.
+pub struct FeedbackTriage {
+    config: FeedbackTriageConfig,
+    http_client: Client,
+    feedback: FeedbackClient,
+    evaluations: EvaluationClient,
+    cursor: Mutex<Cursor>,
+    logger: Logger,
+}
+
+impl FeedbackTriage {
+    /// Create a new FeedbackTriage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the HTTP client cannot be created. Use
+    /// [`try_new`](Self::try_new) to handle the error instead.
+    pub fn new(config: FeedbackTriageConfig) -> Self {
+        Self::try_new(config).expect("Failed to create HTTP client")
+    }
+
+    /// Create a new FeedbackTriage, returning an error if the HTTP client
+    /// cannot be created.
+    pub fn try_new(config: FeedbackTriageConfig) -> Result<Self, DiagnyxError> {
+        let http_client = config.http.client(REQUEST_TIMEOUT)?;
+        Ok(Self::with_http_client(config, http_client))
+    }
+
+    /// Create a new FeedbackTriage sending requests, including those of its
+    /// feedback and evaluation clients, with `http_client`.
+    ///
+    /// The HTTP settings of `config` are not applied to it.
+    pub fn with_http_client(config: FeedbackTriageConfig, http_client: Client) -> Self {
+        // The clients share the triage's limiter, so their requests count
+        // against one rate
+        let feedback = FeedbackClient::with_http_client(
+            FeedbackClientConfig {
+                rate_limiter: config.rate_limiter.clone(),
+                ..FeedbackClientConfig::new(&config.api_key, &config.organization_id)
+                    .base_url(&config.base_url)
+                    .retry_policy(config.retry_policy.clone())
+                    .http(config.http.clone())
+                    .debug(config.debug)
+            },
+            http_client.clone(),
+        );
+        let evaluations = EvaluationClient::with_http_client(
+            EvaluationClientConfig {
+                rate_limiter: config.rate_limiter.clone(),
+                ..EvaluationClientConfig::new(&config.api_key, &config.organization_id)
+                    .base_url(&config.base_url)
+                    .retry_policy(config.retry_policy.clone())
+                    .http(config.http.clone())
+                    .debug(config.debug)
+            },
+            http_client.clone(),
+        );
+        let since = Utc::now()
+            - chrono::Duration::from_std(config.lookback).unwrap_or(chrono::Duration::zero());
+        Self {
+            logger: Logger::new("Diagnyx Triage", config.debug),
+            http_client,
+            feedback,
+            evaluations,
+            cursor: Mutex::new(Cursor {
+                since,
+                seen: HashSet::new(),
+            }),
+            config,
+        }
+    }
+
+    /// Pull negative feedback given since the last pull and enqueue the
+    /// traces it is about. Returns the number of traces enqueued.
+    ///
+    /// If enqueueing fails, the same feedback is pulled again next time.
+    /// Feedback whose trace cannot be fetched is logged and skipped.
+    pub async fn run_once(&self) -> Result<usize, DiagnyxError> {
+        let mut cursor = self.cursor.lock().await;
+
+        let mut flagged: Vec<Feedback> = Vec::new();
+        let mut offset = 0;
+        loop {
+            let page = self
+                .feedback
+                .list(Some(ListFeedbackOptions {
+                    limit: Some(PAGE_SIZE),
+                    offset: Some(offset),
+                    sentiment: Some(FeedbackSentiment::Negative),
+                    start_date: Some(cursor.since),
+                    ..Default::default()
+                }))
+                .await?;
+            offset += page.data.len() as i32;
+            let done = page.data.is_empty() || offset >= page.total;
+            flagged.extend(
+                page.data
+                    .into_iter()
+                    .filter(|feedback| !cursor.seen.contains(&feedback.id)),
+            );
+            if done {
+                break;
+            }
+        }
+        if flagged.is_empty() {
+            return Ok(0);
+        }
+
+        let mut items = Vec::with_capacity(flagged.len());
+        for feedback in &flagged {
+            let trace = match self.get_trace(&feedback.trace_id).await {
+                Ok(trace) => trace,
+                Err(e) => {
+                    self.logger.warn(&format!(
+                        "Skipping feedback {}: failed to get trace {}: {}",
+                        feedback.id, feedback.trace_id, e
+                    ));
+                    continue;
+                }
+            };
+            items.push(AnnotationItem {
+                trace_id: trace.trace_id,
+                feedback_id: feedback.id.clone(),
+                input: trace.input,
+                output: trace.output,
+                comment: feedback.comment.clone(),
+                correction: feedback.correction.clone(),
+            });
+        }
+        if !items.is_empty() {
+            self.enqueue(&items).await?;
+        }
+
+        let newest = flagged
+            .iter()
+            .map(|feedback| feedback.created_at)
+            .max()
+            .unwrap_or(cursor.since);
+        if newest > cursor.since {
+            cursor.since = newest;
+            cursor.seen.clear();
+        }
+        cursor.seen.extend(
+            flagged
+                .iter()
+                .filter(|feedback| feedback.created_at == newest)
+                .map(|feedback| feedback.id.clone()),
+        );
+        self.logger
+            .log(&format!("Enqueued {} flagged traces", items.len()));
+        Ok(items.len())
+    }
+
+    /// Call [`run_once`](Self::run_once) every `poll_interval` until the
+    /// returned task is aborted.
+    pub fn start(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.config.poll_interval);
+
+            loop {
+                ticker.tick().await;
+
+                if let Err(e) = self.run_once().await {
+                    self.logger.warn(&format!("Triage error: {}", e));
+                }
+            }
+        })
+    }
+
+    /// Get the prompt and response of a trace.
+    pub async fn get_trace(&self, trace_id: &TraceId) -> Result<TraceRecord, DiagnyxError> {
+        let path = format!(
+            "/api/v1/organizations/{}/traces/{}",
+            self.config.organization_id, trace_id
+        );
+        self.request(Method::GET, &path, None).await
+    }
+
+    async fn enqueue(&self, items: &[AnnotationItem]) -> Result<(), DiagnyxError> {
+        match &self.config.destination {
+            Destination::EvaluationRun(run_id) => {
+                let results = items
+                    .iter()
+                    .map(|item| {
+                        let mut result = EvaluationResult::unscored(&item.input, &item.output)
+                            .trace_id(item.trace_id.clone())
+                            .metadata(HashMap::from([(
+                                FEEDBACK_ID_KEY.to_string(),
+                                item.feedback_id.clone().into(),
+                            )]));
+                        if let Some(correction) = &item.correction {
+                            result = result.expected_output(correction);
+                        }
+                        result
+                    })
+                    .collect();
+                self.evaluations.submit_results(run_id, results).await
+            }
+            Destination::AnnotationQueue(queue_id) => {
+                let path = format!(
+                    "/api/v1/organizations/{}/annotation-queues/{}/items",
+                    self.config.organization_id, queue_id
+                );
+                let payload = serde_json::json!({ "items": items });
+                let _: serde_json::Value = self.request(Method::POST, &path, Some(payload)).await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<T, DiagnyxError> {
+        let url = format!("{}{}", self.config.base_url, path);
+        let response = send_with_retry(
+            &self.config.retry_policy,
+            self.config.rate_limiter.as_ref(),
+            method,
+            |method| {
+                let mut request = self
+                    .http_client
+                    .request(method, &url)
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", self.config.api_key));
+                if let Some(ref b) = body {
+                    request = request.json(b);
+                }
+                request
+            },
+        )
+        .await?;
+
+        response
+            .json()
+            .await
+            .map_err(|e| DiagnyxError::ConfigError(format!("Failed to parse response: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_run_once_enqueues_new_negative_feedback() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/organizations/org-1/feedback"))
+            .and(query_param("sentiment", "negative"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": [{
+                    "id": "fb-1",
+                    "traceId": "trace-1",
+                    "feedbackType": "correction",
+                    "sentiment": "negative",
+                    "correction": "Paris",
+                    "createdAt": "2030-01-01T00:00:00Z"
+                }, {
+                    "id": "fb-2",
+                    "traceId": "trace-deleted",
+                    "feedbackType": "thumbs_down",
+                    "sentiment": "negative",
+                    "createdAt": "2030-01-01T00:00:00Z"
+                }],
+                "total": 2,
+                "limit": 100,
+                "offset": 0
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/organizations/org-1/traces/trace-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "traceId": "trace-1",
+                "input": "Capital of France?",
+                "output": "Lyon"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(
+                "/api/v1/organizations/org-1/evaluations/runs/run-1/results",
+            ))
+            .and(body_partial_json(serde_json::json!({
+                "results": [{
+                    "input": "Capital of France?",
+                    "output": "Lyon",
+                    "expectedOutput": "Paris",
+                    "traceId": "trace-1",
+                    "metadata": {"feedback_id": "fb-1"}
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let triage = FeedbackTriage::new(
+            FeedbackTriageConfig::new(
+                "test-api-key",
+                "org-1",
+                Destination::EvaluationRun("run-1".to_string()),
+            )
+            .base_url(server.uri())
+            .max_retries(1),
+        );
+
+        // The feedback whose trace is gone is skipped
+        assert_eq!(triage.run_once().await.unwrap(), 1);
+        // The same feedback is returned again and skipped
+        assert_eq!(triage.run_once().await.unwrap(), 0);
+
+        let requests = server.received_requests().await.unwrap();
+        let submitted = requests
+            .iter()
+            .find(|r| r.method == wiremock::http::Method::Post)
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&submitted.body).unwrap();
+        assert!(body["results"][0].get("score").is_none());
+    }
+}
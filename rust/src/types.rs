@@ -1,6 +1,62 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::budgets::BudgetConfig;
+use crate::client::Sink;
+use crate::compact;
+#[cfg(feature = "compression")]
+use crate::compression::Compression;
+use crate::enrich::{Enricher, EnricherChain};
+use crate::error::DiagnyxError;
+use crate::filter::{CallFilter, FilterChain};
+use crate::http::{ClientCertificate, HttpConfig};
+use crate::ids::{ProjectId, TraceId};
+use crate::logger::Logger;
+#[cfg(feature = "otel")]
+use crate::otel::OtelExporter;
+use crate::pricing::CostCalculator;
+use crate::ratelimit::RateLimiter;
+use crate::redact::ContentRedactor;
+use crate::retry::RetryPolicy;
+use crate::sampling::{SamplingPolicy, VolumeCap};
+use crate::spend;
+use crate::watermark::BufferWatermarks;
+
+/// Prefix of test-mode API keys.
+const TEST_KEY_PREFIX: &str = "dx_test_";
+
+/// Environment set on calls tracked with a test-mode API key.
+pub(crate) const TEST_ENVIRONMENT: &str = "test";
+
+/// Whether `api_key` is a test-mode key, whose traffic goes to the sandbox.
+pub(crate) fn is_test_key(api_key: &str) -> bool {
+    api_key.starts_with(TEST_KEY_PREFIX)
+}
+
+/// Default base URL for `api_key`: the sandbox for test-mode keys, so that
+/// staging traffic never reaches production analytics.
+pub(crate) fn default_base_url(api_key: &str) -> String {
+    if is_test_key(api_key) {
+        "https://sandbox.api.diagnyx.io".to_string()
+    } else {
+        "https://api.diagnyx.io".to_string()
+    }
+}
+
+/// Environment variable disabling the clients when set to `1` or `true`.
+const DISABLED_ENV: &str = "DIAGNYX_DISABLED";
+
+/// Whether the clients are disabled by the `DIAGNYX_DISABLED` variable.
+pub(crate) fn disabled_by_env() -> bool {
+    std::env::var(DISABLED_ENV)
+        .is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+}
 
 /// Supported LLM providers.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -15,47 +71,292 @@ pub enum Provider {
 }
 
 /// Status of an LLM call.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum CallStatus {
+    #[default]
     Success,
     Error,
     Timeout,
     RateLimited,
 }
 
-impl Default for CallStatus {
-    fn default() -> Self {
-        CallStatus::Success
-    }
-}
-
 /// Configuration for the Diagnyx client.
-#[derive(Debug, Clone)]
+///
+/// The config can also be deserialized, e.g. from a JSON or YAML file
+/// written by an orchestration tool. Only `api_key` is required; omitted
+/// settings keep the defaults of [`DiagnyxConfig::new`]. In string
+/// settings, `${NAME}` is replaced with the environment variable `NAME`,
+/// which must be set. Settings holding code, such as enrichers, filters and
+/// callbacks, are added with their setters after loading.
+///
+/// ```rust
+/// use diagnyx::DiagnyxConfig;
+///
+/// std::env::set_var("DIAGNYX_API_KEY", "dx_live_abc123");
+/// let config: DiagnyxConfig = serde_json::from_str(
+///     r#"{"api_key": "${DIAGNYX_API_KEY}", "batch_size": 50, "flush_mode": "ndjson"}"#,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(config.api_key, "dx_live_abc123");
+/// assert_eq!(config.batch_size, 50);
+/// assert_eq!(format!("{:?}", config).matches("abc123").count(), 0);
+/// ```
+#[derive(Clone)]
 pub struct DiagnyxConfig {
     pub api_key: String,
     pub base_url: String,
     pub batch_size: usize,
+    /// Maximum number of batches sent at once when a flush holds more than
+    /// `batch_size` calls. A failed batch is kept for the next flush without
+    /// holding back the others. Default: 4
+    pub max_concurrent_flushes: usize,
     pub flush_interval_ms: u64,
+    /// Delay the first background flush by a random part of the flush
+    /// interval, so replicas started together do not flush in step.
+    /// Default: true
+    pub flush_jitter: bool,
+    /// Time [`shutdown`](crate::DiagnyxClient::shutdown) gives the final
+    /// flush, including retries. Default: 10 seconds
+    pub shutdown_timeout: Option<Duration>,
+    /// Maximum number of calls kept in the buffer. When a failed flush
+    /// leaves more than this, the oldest calls are dropped. Default: 10000
+    pub max_buffer_size: usize,
+    /// Maximum total bytes of captured `full_prompt`/`full_response` kept in
+    /// the buffer. When exceeded, the content of the oldest calls is dropped
+    /// while their metrics are kept. Default: None (no cap)
+    pub max_content_bytes: Option<usize>,
+    /// Maximum serialized size of a call in bytes. Larger calls have their
+    /// captured content truncated and then metadata dropped when tracked,
+    /// instead of the API rejecting their batch. Default: None (no limit)
+    pub max_call_bytes: Option<usize>,
+    /// Buffer fill levels reported as [`SdkEvent::BufferHigh`] and
+    /// [`SdkEvent::BufferDrained`]. Default: 80% and 50% of
+    /// `max_buffer_size`
+    ///
+    /// [`SdkEvent::BufferHigh`]: crate::SdkEvent::BufferHigh
+    /// [`SdkEvent::BufferDrained`]: crate::SdkEvent::BufferDrained
+    pub buffer_watermarks: Option<BufferWatermarks>,
+    /// Attempts per batch as last set with the setters; batches are sent
+    /// with [`retry_policy`](Self::retry_policy) alone.
+    #[deprecated(note = "not read; set `retry_policy.max_attempts` instead")]
     pub max_retries: u32,
+    pub retry_policy: RetryPolicy,
+    /// Spaces out requests to the API, retries included. Share it with the
+    /// guardrail and feedback clients to bound their requests together.
+    /// Default: None (no limit)
+    pub rate_limiter: Option<RateLimiter>,
+    /// Proxy and TLS settings of the HTTP client. Default: none
+    pub http: HttpConfig,
     pub debug: bool,
+    /// Make tracking a no-op: calls are dropped without being buffered and
+    /// nothing is sent. Default: whether `DIAGNYX_DISABLED` is `1` or `true`
+    pub disabled: bool,
     /// Enable capturing full prompt/response content. Default: false (privacy-first)
     pub capture_full_content: bool,
     /// Maximum length for captured content before truncation. Default: 10000
     pub content_max_length: usize,
+    /// Masks personal data in captured content. Default: no redaction
+    pub content_redactor: ContentRedactor,
+    /// Attach the CPU count, memory limit and cloud instance type of the
+    /// host to calls as `host.*` metadata. Default: false
+    pub capture_host_context: bool,
+    /// Which calls are tracked. Default: all of them
+    pub sampling: SamplingPolicy,
+    /// File where month-to-date spend per project is persisted. Default: None (memory only)
+    pub spend_cache_path: Option<PathBuf>,
+    /// Interval for reconciling month-to-date spend with the API. Default: None (disabled)
+    pub spend_reconcile_interval_ms: Option<u64>,
+    /// Spend limits checked by `DiagnyxClient::check_budget`.
+    pub budgets: Vec<BudgetConfig>,
+    /// Interval for syncing budget totals with the API. Default: None (disabled)
+    pub budget_sync_interval_ms: Option<u64>,
+    /// Name of the service the client runs in, reported with heartbeats.
+    /// Default: None
+    pub service_name: Option<String>,
+    /// Interval for registering the client with the API, so it shows up
+    /// in fleet dashboards even when idle. Default: None (disabled)
+    pub heartbeat_interval_ms: Option<u64>,
+    /// Enrichers run on every call before it is buffered, in order.
+    pub enrichers: EnricherChain,
+    /// Filters that can drop or modify calls before they are buffered.
+    pub filters: FilterChain,
+    /// Sets `estimated_cost_usd` on tracked calls. Default: built-in price tables
+    pub cost_calculator: CostCalculator,
+    /// Consecutive 401/403 flush failures after which the client stops
+    /// sending and parks its buffer. Default: 3
+    pub auth_failure_threshold: u32,
+    /// Called once when the client parks its buffer after auth failures.
+    pub on_auth_failure: Option<AuthFailureCallback>,
+    /// File where parked calls are persisted, and loaded from on startup.
+    /// Processes sharing it append to it, and one of them at a time loads
+    /// and sends the calls. Default: None (memory only)
+    pub parked_buffer_path: Option<PathBuf>,
+    /// Records tracked calls as OpenTelemetry spans. Default: None
+    #[cfg(feature = "otel")]
+    pub otel: Option<OtelExporter>,
+    /// Compresses batch bodies sent for ingestion. Default: None
+    #[cfg(feature = "compression")]
+    pub compression: Option<Compression>,
+    /// Size in bytes below which batch bodies are not compressed. Default:
+    /// 1024
+    #[cfg(feature = "compression")]
+    pub compression_threshold: usize,
+    /// How batches are sent for ingestion. Default: [`FlushMode::Batch`]
+    pub flush_mode: FlushMode,
+    /// Receives flushed calls instead of the API. Default: None (an
+    /// [`HttpSink`](crate::HttpSink) sending them to the API)
+    pub sink: Option<Arc<dyn Sink>>,
+}
+
+/// How buffered calls are sent for ingestion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlushMode {
+    /// A JSON object with a `calls` array, posted to the batch endpoint.
+    #[default]
+    Batch,
+    /// One JSON call per line, streamed to the streaming endpoint, which
+    /// ingests each line on its own instead of parsing the batch as one
+    /// document.
+    ///
+    /// The lines of each flush are written to one long-lived chunked
+    /// request, ended after a minute, after 4 MiB of lines, or at shutdown,
+    /// so frequent flushes do not each pay for a request. Streamed lines
+    /// are not compressed. The blocking client sends each batch as one
+    /// NDJSON request instead.
+    Ndjson,
+    /// Calls with their shared fields, such as the model and project,
+    /// dictionary-encoded, posted to the batch endpoint. Falls back to
+    /// [`FlushMode::Batch`] if the server does not accept the format.
+    Compact,
+}
+
+impl FlushMode {
+    pub(crate) fn path(&self) -> &'static str {
+        match self {
+            FlushMode::Batch | FlushMode::Compact => "/api/v1/ingest/llm/batch",
+            FlushMode::Ndjson => "/api/v1/ingest/llm/stream",
+        }
+    }
+
+    pub(crate) fn content_type(&self) -> &'static str {
+        match self {
+            FlushMode::Batch => "application/json",
+            FlushMode::Ndjson => "application/x-ndjson",
+            FlushMode::Compact => compact::CONTENT_TYPE,
+        }
+    }
+}
+
+/// Thresholds above which calls are exempt from sampling.
+///
+/// Failed calls are always kept. With cost sampling, calls are sampled after
+/// enrichers, filters and the cost calculator have run, so the thresholds
+/// apply to the estimated cost of the call as it would be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct CostSampling {
+    /// Calls estimated to cost at least this much in USD are always kept.
+    pub keep_above_usd: f64,
+    /// Calls with at least this many input and output tokens are always
+    /// kept. Default: None
+    #[serde(default)]
+    pub keep_above_tokens: Option<i32>,
+}
+
+impl CostSampling {
+    pub fn new(keep_above_usd: f64) -> Self {
+        Self {
+            keep_above_usd,
+            keep_above_tokens: None,
+        }
+    }
+
+    pub fn keep_above_tokens(mut self, tokens: i32) -> Self {
+        self.keep_above_tokens = Some(tokens);
+        self
+    }
+
+    /// Whether `call` is kept regardless of the sample rate.
+    pub fn keeps(&self, call: &LLMCall) -> bool {
+        call.status != CallStatus::Success
+            || spend::call_cost(call) >= self.keep_above_usd
+            || self.keep_above_tokens.is_some_and(|tokens| {
+                call.input_tokens.saturating_add(call.output_tokens) >= tokens
+            })
+    }
+}
+
+/// Callback invoked when the API keeps rejecting the client's API key.
+#[derive(Clone)]
+pub struct AuthFailureCallback(Arc<dyn Fn(&DiagnyxError) + Send + Sync>);
+
+impl AuthFailureCallback {
+    pub(crate) fn call(&self, error: &DiagnyxError) {
+        (self.0)(error)
+    }
+}
+
+impl fmt::Debug for AuthFailureCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AuthFailureCallback")
+    }
 }
 
 impl DiagnyxConfig {
+    /// Create a configuration for `api_key`.
+    ///
+    /// Test-mode keys, starting with `dx_test_`, send calls to the sandbox
+    /// API unless a base URL is set. See
+    /// [`is_test_mode`](Self::is_test_mode).
+    #[allow(deprecated)]
     pub fn new(api_key: impl Into<String>) -> Self {
+        let api_key = api_key.into();
         Self {
-            api_key: api_key.into(),
-            base_url: "https://api.diagnyx.io".to_string(),
+            base_url: default_base_url(&api_key),
+            api_key,
             batch_size: 100,
+            max_concurrent_flushes: 4,
             flush_interval_ms: 5000,
+            flush_jitter: true,
+            shutdown_timeout: Some(Duration::from_secs(10)),
+            max_buffer_size: 10000,
+            max_content_bytes: None,
+            max_call_bytes: None,
+            buffer_watermarks: Some(BufferWatermarks::default()),
             max_retries: 3,
+            // Batch ingestion is retried even though it is a POST
+            retry_policy: RetryPolicy::new(3).retry_non_idempotent(true),
+            rate_limiter: None,
+            http: HttpConfig::default(),
             debug: false,
+            disabled: disabled_by_env(),
             capture_full_content: false,
+            capture_host_context: false,
             content_max_length: 10000,
+            content_redactor: ContentRedactor::new(),
+            sampling: SamplingPolicy::default(),
+            spend_cache_path: None,
+            spend_reconcile_interval_ms: None,
+            budgets: Vec::new(),
+            budget_sync_interval_ms: None,
+            service_name: None,
+            heartbeat_interval_ms: None,
+            enrichers: EnricherChain::default(),
+            filters: FilterChain::default(),
+            cost_calculator: CostCalculator::default(),
+            auth_failure_threshold: 3,
+            on_auth_failure: None,
+            parked_buffer_path: None,
+            #[cfg(feature = "otel")]
+            otel: None,
+            #[cfg(feature = "compression")]
+            compression: None,
+            #[cfg(feature = "compression")]
+            compression_threshold: 1024,
+            flush_mode: FlushMode::Batch,
+            sink: None,
         }
     }
 
@@ -64,18 +365,116 @@ impl DiagnyxConfig {
         self
     }
 
+    /// Whether the API key is a test-mode key.
+    ///
+    /// Calls tracked in test mode have their environment set to `test`,
+    /// and budgets are neither checked nor updated, so no budget warnings
+    /// are emitted.
+    pub fn is_test_mode(&self) -> bool {
+        is_test_key(&self.api_key)
+    }
+
     pub fn batch_size(mut self, size: usize) -> Self {
         self.batch_size = size;
         self
     }
 
+    pub fn max_concurrent_flushes(mut self, count: usize) -> Self {
+        self.max_concurrent_flushes = count;
+        self
+    }
+
     pub fn flush_interval_ms(mut self, interval: u64) -> Self {
         self.flush_interval_ms = interval;
         self
     }
 
+    pub fn flush_jitter(mut self, jitter: bool) -> Self {
+        self.flush_jitter = jitter;
+        self
+    }
+
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    pub fn max_buffer_size(mut self, size: usize) -> Self {
+        self.max_buffer_size = size;
+        self
+    }
+
+    pub fn max_content_bytes(mut self, bytes: usize) -> Self {
+        self.max_content_bytes = Some(bytes);
+        self
+    }
+
+    pub fn max_call_bytes(mut self, bytes: usize) -> Self {
+        self.max_call_bytes = Some(bytes);
+        self
+    }
+
+    pub fn buffer_watermarks(mut self, watermarks: BufferWatermarks) -> Self {
+        self.buffer_watermarks = Some(watermarks);
+        self
+    }
+
+    #[allow(deprecated)]
     pub fn max_retries(mut self, retries: u32) -> Self {
         self.max_retries = retries;
+        self.retry_policy.max_attempts = retries;
+        self
+    }
+
+    #[allow(deprecated)]
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.max_retries = policy.max_attempts;
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Limit requests to the API to `requests_per_second` on average, with
+    /// a new [`RateLimiter`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `requests_per_second` is not a positive number.
+    pub fn max_requests_per_second(mut self, requests_per_second: f64) -> Self {
+        self.rate_limiter = Some(RateLimiter::new(requests_per_second));
+        self
+    }
+
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Shorthand for setting [`HttpConfig::proxy`].
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.http = self.http.proxy(url);
+        self
+    }
+
+    /// Shorthand for [`HttpConfig::root_certificate`].
+    pub fn root_certificate(mut self, path: impl Into<PathBuf>) -> Self {
+        self.http = self.http.root_certificate(path);
+        self
+    }
+
+    /// Shorthand for [`HttpConfig::client_certificate`].
+    pub fn client_certificate(
+        mut self,
+        certificate_path: impl Into<PathBuf>,
+        private_key_path: impl Into<PathBuf>,
+    ) -> Self {
+        self.http = self
+            .http
+            .client_certificate(certificate_path, private_key_path);
         self
     }
 
@@ -84,26 +483,465 @@ impl DiagnyxConfig {
         self
     }
 
+    /// Turn tracking into a no-op, e.g. in test suites and local
+    /// development.
+    pub fn disabled(mut self, disabled: bool) -> Self {
+        self.disabled = disabled;
+        self
+    }
+
     pub fn capture_full_content(mut self, capture: bool) -> Self {
         self.capture_full_content = capture;
         self
     }
 
+    pub fn capture_host_context(mut self, capture: bool) -> Self {
+        self.capture_host_context = capture;
+        self
+    }
+
     pub fn content_max_length(mut self, length: usize) -> Self {
         self.content_max_length = length;
         self
     }
+
+    /// Mask personal data in captured content before it is truncated.
+    pub fn content_redactor(mut self, redactor: ContentRedactor) -> Self {
+        self.content_redactor = redactor;
+        self
+    }
+
+    /// Replace the sampling policy, including the settings below.
+    pub fn sampling(mut self, policy: SamplingPolicy) -> Self {
+        self.sampling = policy;
+        self
+    }
+
+    /// Shorthand for the rate of the sampling policy.
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.sampling.rate = rate;
+        self
+    }
+
+    /// Always keep expensive and failed calls, sampling only the rest at
+    /// the sample rate.
+    pub fn cost_sampling(mut self, sampling: CostSampling) -> Self {
+        self.sampling.cost = Some(sampling);
+        self
+    }
+
+    /// Lower the sample rate as needed to keep the volume sent under `cap`.
+    pub fn volume_cap(mut self, cap: VolumeCap) -> Self {
+        self.sampling.volume_cap = Some(cap);
+        self
+    }
+
+    pub fn spend_cache_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.spend_cache_path = Some(path.into());
+        self
+    }
+
+    pub fn spend_reconcile_interval_ms(mut self, interval: u64) -> Self {
+        self.spend_reconcile_interval_ms = Some(interval);
+        self
+    }
+
+    /// Add a budget, checked along with those already added.
+    pub fn budget(mut self, budget: BudgetConfig) -> Self {
+        self.budgets.push(budget);
+        self
+    }
+
+    pub fn budget_sync_interval_ms(mut self, interval: u64) -> Self {
+        self.budget_sync_interval_ms = Some(interval);
+        self
+    }
+
+    pub fn service_name(mut self, name: impl Into<String>) -> Self {
+        self.service_name = Some(name.into());
+        self
+    }
+
+    pub fn heartbeat_interval_ms(mut self, interval: u64) -> Self {
+        self.heartbeat_interval_ms = Some(interval);
+        self
+    }
+
+    /// Add an enricher, run after those already added.
+    pub fn enricher(mut self, enricher: impl Enricher + 'static) -> Self {
+        self.enrichers.push(enricher);
+        self
+    }
+
+    /// Time allowed for each enricher. Default: 100ms
+    pub fn enricher_timeout(mut self, timeout: Duration) -> Self {
+        self.enrichers.set_timeout(timeout);
+        self
+    }
+
+    /// Consecutive failures after which an enricher or filter is disabled;
+    /// 0 never disables them. Default: 5
+    pub fn max_hook_failures(mut self, max_failures: u32) -> Self {
+        self.enrichers.set_max_failures(max_failures);
+        self.filters.set_max_failures(max_failures);
+        self
+    }
+
+    /// Add a filter, run after those already added.
+    pub fn filter(mut self, filter: impl CallFilter + 'static) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn cost_calculator(mut self, calculator: CostCalculator) -> Self {
+        self.cost_calculator = calculator;
+        self
+    }
+
+    pub fn auth_failure_threshold(mut self, failures: u32) -> Self {
+        self.auth_failure_threshold = failures;
+        self
+    }
+
+    /// Register a callback for when the API key is rejected, e.g. to alert
+    /// on a revoked or rotated key.
+    pub fn on_auth_failure(
+        mut self,
+        callback: impl Fn(&DiagnyxError) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_auth_failure = Some(AuthFailureCallback(Arc::new(callback)));
+        self
+    }
+
+    pub fn parked_buffer_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.parked_buffer_path = Some(path.into());
+        self
+    }
+
+    /// Record every tracked call as a span with a tracer from `provider`.
+    #[cfg(feature = "otel")]
+    pub fn tracer_provider<P, T, S>(mut self, provider: &P) -> Self
+    where
+        P: opentelemetry::trace::TracerProvider<Tracer = T>,
+        T: opentelemetry::trace::Tracer<Span = S> + Send + Sync + 'static,
+        S: opentelemetry::trace::Span + Send + Sync + 'static,
+    {
+        self.otel = Some(OtelExporter::new(provider));
+        self
+    }
+
+    /// Compress batch bodies of at least `compression_threshold` bytes.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    #[cfg(feature = "compression")]
+    pub fn compression_threshold(mut self, bytes: usize) -> Self {
+        self.compression_threshold = bytes;
+        self
+    }
+
+    pub fn flush_mode(mut self, mode: FlushMode) -> Self {
+        self.flush_mode = mode;
+        self
+    }
+
+    /// Write flushed calls to `sink` instead of sending them to the API.
+    pub fn sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sink = Some(Arc::new(sink));
+        self
+    }
+
+    pub(crate) fn logger(&self) -> Logger {
+        Logger::new("Diagnyx", self.debug)
+    }
+}
+
+/// Secret formatted with all but its `dx_live_`-style prefix masked.
+pub(crate) struct Masked<'a>(pub &'a str);
+
+impl fmt::Debug for Masked<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = if self.0.starts_with("dx_") {
+            self.0
+                .match_indices('_')
+                .nth(1)
+                .map_or("", |(i, _)| &self.0[..=i])
+        } else {
+            ""
+        };
+        write!(f, "\"{}****\"", prefix)
+    }
+}
+
+impl fmt::Debug for DiagnyxConfig {
+    #[allow(deprecated)]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("DiagnyxConfig");
+        s.field("api_key", &Masked(&self.api_key))
+            .field("base_url", &self.base_url)
+            .field("batch_size", &self.batch_size)
+            .field("max_concurrent_flushes", &self.max_concurrent_flushes)
+            .field("flush_interval_ms", &self.flush_interval_ms)
+            .field("flush_jitter", &self.flush_jitter)
+            .field("shutdown_timeout", &self.shutdown_timeout)
+            .field("max_buffer_size", &self.max_buffer_size)
+            .field("max_content_bytes", &self.max_content_bytes)
+            .field("max_call_bytes", &self.max_call_bytes)
+            .field("buffer_watermarks", &self.buffer_watermarks)
+            .field("max_retries", &self.max_retries)
+            .field("retry_policy", &self.retry_policy)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("http", &self.http)
+            .field("debug", &self.debug)
+            .field("disabled", &self.disabled)
+            .field("capture_full_content", &self.capture_full_content)
+            .field("content_max_length", &self.content_max_length)
+            .field("content_redactor", &self.content_redactor)
+            .field("capture_host_context", &self.capture_host_context)
+            .field("sampling", &self.sampling)
+            .field("spend_cache_path", &self.spend_cache_path)
+            .field(
+                "spend_reconcile_interval_ms",
+                &self.spend_reconcile_interval_ms,
+            )
+            .field("budgets", &self.budgets)
+            .field("budget_sync_interval_ms", &self.budget_sync_interval_ms)
+            .field("service_name", &self.service_name)
+            .field("heartbeat_interval_ms", &self.heartbeat_interval_ms)
+            .field("enrichers", &self.enrichers)
+            .field("filters", &self.filters)
+            .field("cost_calculator", &self.cost_calculator)
+            .field("auth_failure_threshold", &self.auth_failure_threshold)
+            .field("on_auth_failure", &self.on_auth_failure)
+            .field("parked_buffer_path", &self.parked_buffer_path);
+        #[cfg(feature = "otel")]
+        s.field("otel", &self.otel);
+        #[cfg(feature = "compression")]
+        s.field("compression", &self.compression)
+            .field("compression_threshold", &self.compression_threshold);
+        s.field("flush_mode", &self.flush_mode)
+            .field("sink", &self.sink)
+            .finish()
+    }
+}
+
+/// Settings of a [`DiagnyxConfig`] that can be deserialized.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    api_key: String,
+    base_url: Option<String>,
+    batch_size: Option<usize>,
+    max_concurrent_flushes: Option<usize>,
+    flush_interval_ms: Option<u64>,
+    flush_jitter: Option<bool>,
+    max_buffer_size: Option<usize>,
+    max_content_bytes: Option<usize>,
+    max_call_bytes: Option<usize>,
+    buffer_watermarks: Option<BufferWatermarks>,
+    max_retries: Option<u32>,
+    max_requests_per_second: Option<f64>,
+    proxy: Option<String>,
+    #[serde(default)]
+    root_certificates: Vec<String>,
+    client_certificate: Option<ClientCertificate>,
+    debug: Option<bool>,
+    disabled: Option<bool>,
+    capture_full_content: Option<bool>,
+    content_max_length: Option<usize>,
+    capture_host_context: Option<bool>,
+    sampling: Option<SamplingPolicy>,
+    sample_rate: Option<f64>,
+    cost_sampling: Option<CostSampling>,
+    volume_cap: Option<VolumeCap>,
+    spend_cache_path: Option<String>,
+    spend_reconcile_interval_ms: Option<u64>,
+    #[serde(default)]
+    budgets: Vec<BudgetConfig>,
+    budget_sync_interval_ms: Option<u64>,
+    service_name: Option<String>,
+    heartbeat_interval_ms: Option<u64>,
+    enricher_timeout_ms: Option<u64>,
+    shutdown_timeout_ms: Option<u64>,
+    max_hook_failures: Option<u32>,
+    auth_failure_threshold: Option<u32>,
+    parked_buffer_path: Option<String>,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+    #[cfg(feature = "compression")]
+    compression_threshold: Option<usize>,
+    flush_mode: Option<FlushMode>,
+}
+
+impl ConfigFile {
+    fn into_config(self) -> Result<DiagnyxConfig, String> {
+        let mut config = DiagnyxConfig::new(expand_env(&self.api_key)?);
+        // Setters keep derived settings, such as the retry policy, in sync
+        macro_rules! apply {
+            ($($field:ident),* $(,)?) => {
+                $(if let Some(value) = self.$field {
+                    config = config.$field(value);
+                })*
+            };
+        }
+        apply!(
+            batch_size,
+            max_concurrent_flushes,
+            flush_interval_ms,
+            flush_jitter,
+            max_buffer_size,
+            max_content_bytes,
+            max_call_bytes,
+            buffer_watermarks,
+            max_retries,
+            debug,
+            disabled,
+            capture_full_content,
+            content_max_length,
+            capture_host_context,
+            cost_sampling,
+            volume_cap,
+            spend_reconcile_interval_ms,
+            budget_sync_interval_ms,
+            heartbeat_interval_ms,
+            max_hook_failures,
+            auth_failure_threshold,
+            flush_mode,
+        );
+        #[cfg(feature = "compression")]
+        apply!(compression, compression_threshold);
+
+        // Rates are checked before they are stored, as the client checks
+        // them when they are changed at runtime
+        let check_rate = |name: &str, rate: f64| {
+            if (0.0..=1.0).contains(&rate) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} must be between 0.0 and 1.0, got {}",
+                    name, rate
+                ))
+            }
+        };
+        if let Some(sampling) = self.sampling {
+            check_rate("sampling.rate", sampling.rate)?;
+            for rate in sampling.project_rates.values() {
+                check_rate("sampling.project_rates", *rate)?;
+            }
+            config = config.sampling(sampling);
+        }
+        if let Some(rate) = self.sample_rate {
+            check_rate("sample_rate", rate)?;
+            config = config.sample_rate(rate);
+        }
+
+        if let Some(rate) = self.max_requests_per_second {
+            if !(rate > 0.0 && rate.is_finite()) {
+                return Err(format!(
+                    "max_requests_per_second must be a positive number, got {}",
+                    rate
+                ));
+            }
+            config = config.max_requests_per_second(rate);
+        }
+        if let Some(url) = self.base_url {
+            config = config.base_url(expand_env(&url)?);
+        }
+        if let Some(path) = self.spend_cache_path {
+            config = config.spend_cache_path(expand_env(&path)?);
+        }
+        if let Some(name) = self.service_name {
+            config = config.service_name(expand_env(&name)?);
+        }
+        if let Some(path) = self.parked_buffer_path {
+            config = config.parked_buffer_path(expand_env(&path)?);
+        }
+        if let Some(url) = self.proxy {
+            config = config.proxy(expand_env(&url)?);
+        }
+        for path in self.root_certificates {
+            config = config.root_certificate(expand_env(&path)?);
+        }
+        config.http.client_certificate = self.client_certificate;
+        if let Some(timeout) = self.enricher_timeout_ms {
+            config = config.enricher_timeout(Duration::from_millis(timeout));
+        }
+        if let Some(timeout) = self.shutdown_timeout_ms {
+            config = config.shutdown_timeout(Duration::from_millis(timeout));
+        }
+        config.budgets = self.budgets;
+        Ok(config)
+    }
+}
+
+impl<'de> Deserialize<'de> for DiagnyxConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        ConfigFile::deserialize(deserializer)?
+            .into_config()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Replace each `${NAME}` in `value` with the environment variable `NAME`.
+fn expand_env(value: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            return Err("unterminated `${` in config value".to_string());
+        };
+        let name = &rest[start + 2..start + len];
+        let var =
+            std::env::var(name).map_err(|_| format!("environment variable {} is not set", name))?;
+        expanded.push_str(&var);
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Kind of model call.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CallType {
+    #[default]
+    Completion,
+    Embedding,
+    /// Usage of Diagnyx guardrails, reported as a call of its own.
+    Guardrail,
+    /// A step of a traced workflow, reported when its
+    /// [`Span`](crate::trace::Span) ends.
+    Span,
+}
+
+impl CallType {
+    fn is_completion(&self) -> bool {
+        *self == CallType::Completion
+    }
 }
 
 /// Represents a single LLM API call.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMCall {
+    #[serde(default, skip_serializing_if = "CallType::is_completion")]
+    pub call_type: CallType,
     pub provider: Provider,
     pub model: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub endpoint: Option<String>,
     pub input_tokens: i32,
     pub output_tokens: i32,
+    /// Cost estimated by the client from its price tables, in USD.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost_usd: Option<f64>,
+    /// Fraction of similar calls that were tracked, set by the client when
+    /// the call was sampled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<f64>,
     pub latency_ms: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ttft_ms: Option<i64>,
@@ -113,17 +951,26 @@ pub struct LLMCall {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_message: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub project_id: Option<String>,
+    pub project_id: Option<ProjectId>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub environment: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user_identifier: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub trace_id: Option<String>,
+    pub trace_id: Option<TraceId>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub span_id: Option<String>,
+    /// Parent of the span the call was made in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_span_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<HashMap<String, serde_json::Value>>,
+    /// Name of the prompt template the call's prompt was rendered from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_id: Option<String>,
+    /// Version of the prompt template the call's prompt was rendered from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_version: Option<u32>,
     pub timestamp: DateTime<Utc>,
     /// Full prompt content (only captured if capture_full_content=true)
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -131,12 +978,131 @@ pub struct LLMCall {
     /// Full response content (only captured if capture_full_content=true)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub full_response: Option<String>,
+    /// Tools run by the application for the call.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCallRecord>,
+    /// Dimensions of the vectors returned by an embedding call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+    /// Number of inputs embedded by an embedding call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_size: Option<u32>,
+    /// Images, audio or video generated or transcribed by the call.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multimodal: Option<MultimodalUsage>,
+}
+
+/// Kind of media a multimodal call generates or consumes.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaType {
+    Image,
+    Audio,
+    Video,
+}
+
+/// Media usage of an image generation, transcription or vision call, which
+/// is priced per image or per minute rather than per token.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultimodalUsage {
+    pub media_type: MediaType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_count: Option<u32>,
+    /// Image or video resolution, e.g. `1024x1024`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_seconds: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_seconds: Option<f64>,
+}
+
+impl MultimodalUsage {
+    pub fn new(media_type: MediaType) -> Self {
+        Self {
+            media_type,
+            image_count: None,
+            resolution: None,
+            audio_seconds: None,
+            video_seconds: None,
+        }
+    }
+
+    /// Usage of `count` images.
+    pub fn images(count: u32) -> Self {
+        Self::new(MediaType::Image).image_count(count)
+    }
+
+    /// Usage of `seconds` of audio.
+    pub fn audio(seconds: f64) -> Self {
+        Self::new(MediaType::Audio).audio_seconds(seconds)
+    }
+
+    /// Usage of `seconds` of video.
+    pub fn video(seconds: f64) -> Self {
+        Self::new(MediaType::Video).video_seconds(seconds)
+    }
+
+    pub fn image_count(mut self, count: u32) -> Self {
+        self.image_count = Some(count);
+        self
+    }
+
+    pub fn resolution(mut self, resolution: impl Into<String>) -> Self {
+        self.resolution = Some(resolution.into());
+        self
+    }
+
+    pub fn audio_seconds(mut self, seconds: f64) -> Self {
+        self.audio_seconds = Some(seconds);
+        self
+    }
+
+    pub fn video_seconds(mut self, seconds: f64) -> Self {
+        self.video_seconds = Some(seconds);
+        self
+    }
+}
+
+/// A tool or function call made by an agent, attributed to an LLM call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallRecord {
+    pub name: String,
+    /// Size of the arguments passed to the tool, in bytes.
+    pub arguments_bytes: usize,
+    pub duration_ms: i64,
+    pub status: CallStatus,
+}
+
+impl ToolCallRecord {
+    pub fn new(name: impl Into<String>, arguments_bytes: usize, duration_ms: i64) -> Self {
+        Self {
+            name: name.into(),
+            arguments_bytes,
+            duration_ms,
+            status: CallStatus::Success,
+        }
+    }
+
+    pub fn status(mut self, status: CallStatus) -> Self {
+        self.status = status;
+        self
+    }
 }
 
 impl LLMCall {
     pub fn builder() -> LLMCallBuilder {
         LLMCallBuilder::default()
     }
+
+    /// Start a [`TypedCallBuilder`], which only builds once the provider
+    /// and model are set.
+    pub fn typed_builder() -> TypedCallBuilder<Unset, Unset> {
+        TypedCallBuilder {
+            inner: LLMCallBuilder::default(),
+            state: PhantomData,
+        }
+    }
 }
 
 /// Builder for LLMCall.
@@ -147,19 +1113,25 @@ pub struct LLMCallBuilder {
     endpoint: Option<String>,
     input_tokens: i32,
     output_tokens: i32,
+    estimated_cost_usd: Option<f64>,
     latency_ms: i64,
     ttft_ms: Option<i64>,
     status: CallStatus,
     error_code: Option<String>,
     error_message: Option<String>,
-    project_id: Option<String>,
+    project_id: Option<ProjectId>,
     environment: Option<String>,
     user_identifier: Option<String>,
-    trace_id: Option<String>,
+    trace_id: Option<TraceId>,
     span_id: Option<String>,
+    parent_span_id: Option<String>,
     metadata: Option<HashMap<String, serde_json::Value>>,
+    prompt_id: Option<String>,
+    prompt_version: Option<u32>,
     full_prompt: Option<String>,
     full_response: Option<String>,
+    tool_calls: Vec<ToolCallRecord>,
+    multimodal: Option<MultimodalUsage>,
 }
 
 impl LLMCallBuilder {
@@ -188,6 +1160,11 @@ impl LLMCallBuilder {
         self
     }
 
+    pub fn estimated_cost_usd(mut self, cost: f64) -> Self {
+        self.estimated_cost_usd = Some(cost);
+        self
+    }
+
     pub fn latency_ms(mut self, latency: i64) -> Self {
         self.latency_ms = latency;
         self
@@ -213,8 +1190,8 @@ impl LLMCallBuilder {
         self
     }
 
-    pub fn project_id(mut self, id: impl Into<String>) -> Self {
-        self.project_id = Some(id.into());
+    pub fn project_id(mut self, id: ProjectId) -> Self {
+        self.project_id = Some(id);
         self
     }
 
@@ -228,8 +1205,8 @@ impl LLMCallBuilder {
         self
     }
 
-    pub fn trace_id(mut self, id: impl Into<String>) -> Self {
-        self.trace_id = Some(id.into());
+    pub fn trace_id(mut self, id: TraceId) -> Self {
+        self.trace_id = Some(id);
         self
     }
 
@@ -238,11 +1215,38 @@ impl LLMCallBuilder {
         self
     }
 
+    pub fn parent_span_id(mut self, id: impl Into<String>) -> Self {
+        self.parent_span_id = Some(id.into());
+        self
+    }
+
     pub fn metadata(mut self, metadata: HashMap<String, serde_json::Value>) -> Self {
         self.metadata = Some(metadata);
         self
     }
 
+    pub fn prompt_id(mut self, id: impl Into<String>) -> Self {
+        self.prompt_id = Some(id.into());
+        self
+    }
+
+    pub fn prompt_version(mut self, version: u32) -> Self {
+        self.prompt_version = Some(version);
+        self
+    }
+
+    /// Link the call to the prompt template version it was rendered from.
+    #[cfg(feature = "prompts")]
+    pub fn prompt(mut self, prompt: &crate::prompts::RenderedPrompt) -> Self {
+        if let Some(arm) = prompt.rollout_arm {
+            self.metadata.get_or_insert_with(HashMap::new).insert(
+                crate::prompts::ROLLOUT_ARM_KEY.to_string(),
+                arm.as_str().into(),
+            );
+        }
+        self.prompt_id(&prompt.name).prompt_version(prompt.version)
+    }
+
     pub fn full_prompt(mut self, prompt: impl Into<String>) -> Self {
         self.full_prompt = Some(prompt.into());
         self
@@ -253,13 +1257,27 @@ impl LLMCallBuilder {
         self
     }
 
+    pub fn multimodal(mut self, usage: MultimodalUsage) -> Self {
+        self.multimodal = Some(usage);
+        self
+    }
+
+    /// Add a tool call, after those already added.
+    pub fn tool_call(mut self, record: ToolCallRecord) -> Self {
+        self.tool_calls.push(record);
+        self
+    }
+
     pub fn build(self) -> LLMCall {
         LLMCall {
+            call_type: CallType::Completion,
             provider: self.provider.expect("provider is required"),
             model: self.model.expect("model is required"),
             endpoint: self.endpoint,
             input_tokens: self.input_tokens,
             output_tokens: self.output_tokens,
+            estimated_cost_usd: self.estimated_cost_usd,
+            sample_rate: None,
             latency_ms: self.latency_ms,
             ttft_ms: self.ttft_ms,
             status: self.status,
@@ -270,14 +1288,126 @@ impl LLMCallBuilder {
             user_identifier: self.user_identifier,
             trace_id: self.trace_id,
             span_id: self.span_id,
+            parent_span_id: self.parent_span_id,
             metadata: self.metadata,
+            prompt_id: self.prompt_id,
+            prompt_version: self.prompt_version,
             timestamp: Utc::now(),
             full_prompt: self.full_prompt,
             full_response: self.full_response,
+            tool_calls: self.tool_calls,
+            dimensions: None,
+            batch_size: None,
+            multimodal: self.multimodal,
         }
     }
 }
 
+/// A required field of a [`TypedCallBuilder`] that has not been set.
+#[derive(Debug, Clone, Copy)]
+pub struct Unset;
+
+/// A required field of a [`TypedCallBuilder`] that has been set.
+#[derive(Debug, Clone, Copy)]
+pub struct Set;
+
+/// Builder for LLMCall that checks the required fields at compile time.
+///
+/// The type parameters record whether the provider and model have been set,
+/// and `build` is only available once both are, so a missing field is a
+/// compile error instead of a panic in [`LLMCallBuilder::build`].
+///
+/// ```rust
+/// use diagnyx::{LLMCall, Provider};
+///
+/// let call = LLMCall::typed_builder()
+///     .provider(Provider::OpenAI)
+///     .model("gpt-4o")
+///     .input_tokens(100)
+///     .build();
+/// ```
+///
+/// ```rust,compile_fail
+/// use diagnyx::{LLMCall, Provider};
+///
+/// let call = LLMCall::typed_builder().provider(Provider::OpenAI).build();
+/// ```
+pub struct TypedCallBuilder<P, M> {
+    inner: LLMCallBuilder,
+    state: PhantomData<(P, M)>,
+}
+
+macro_rules! forward_setters {
+    ($($(#[$meta:meta])* $name:ident($arg:ident: $ty:ty);)*) => {
+        $(
+            $(#[$meta])*
+            pub fn $name(self, $arg: $ty) -> Self {
+                Self {
+                    inner: self.inner.$name($arg),
+                    state: PhantomData,
+                }
+            }
+        )*
+    };
+}
+
+impl<P, M> TypedCallBuilder<P, M> {
+    pub fn provider(self, provider: Provider) -> TypedCallBuilder<Set, M> {
+        TypedCallBuilder {
+            inner: self.inner.provider(provider),
+            state: PhantomData,
+        }
+    }
+
+    pub fn model(self, model: impl Into<String>) -> TypedCallBuilder<P, Set> {
+        TypedCallBuilder {
+            inner: self.inner.model(model),
+            state: PhantomData,
+        }
+    }
+
+    forward_setters! {
+        endpoint(endpoint: impl Into<String>);
+        input_tokens(tokens: i32);
+        output_tokens(tokens: i32);
+        estimated_cost_usd(cost: f64);
+        latency_ms(latency: i64);
+        ttft_ms(ttft: i64);
+        status(status: CallStatus);
+        error_code(code: impl Into<String>);
+        error_message(message: impl Into<String>);
+        project_id(id: ProjectId);
+        environment(env: impl Into<String>);
+        user_identifier(id: impl Into<String>);
+        trace_id(id: TraceId);
+        span_id(id: impl Into<String>);
+        parent_span_id(id: impl Into<String>);
+        metadata(metadata: HashMap<String, serde_json::Value>);
+        prompt_id(id: impl Into<String>);
+        prompt_version(version: u32);
+        /// Link the call to the prompt template version it was rendered from.
+        #[cfg(feature = "prompts")]
+        prompt(prompt: &crate::prompts::RenderedPrompt);
+        full_prompt(prompt: impl Into<String>);
+        full_response(response: impl Into<String>);
+        multimodal(usage: MultimodalUsage);
+        /// Add a tool call, after those already added.
+        tool_call(record: ToolCallRecord);
+    }
+}
+
+impl TypedCallBuilder<Set, Set> {
+    pub fn build(self) -> LLMCall {
+        self.inner.build()
+    }
+}
+
+impl From<TypedCallBuilder<Set, Set>> for LLMCallBuilder {
+    fn from(builder: TypedCallBuilder<Set, Set>) -> Self {
+        builder.inner
+    }
+}
+
 /// Request body for batch ingestion.
 #[derive(Debug, Serialize)]
 pub(crate) struct BatchRequest {
@@ -285,7 +1415,7 @@ pub(crate) struct BatchRequest {
 }
 
 /// Response from batch ingestion.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
 pub struct BatchResponse {
     pub tracked: i32,
     pub total_cost: f64,
@@ -293,13 +1423,172 @@ pub struct BatchResponse {
     pub ids: Vec<String>,
 }
 
+/// Represents a single embedding API call.
+///
+/// Embeddings are priced on input tokens only and have no output. They are
+/// tracked as an [`LLMCall`] of type [`CallType::Embedding`].
+#[derive(Debug, Clone)]
+pub struct EmbeddingCall {
+    pub provider: Provider,
+    pub model: String,
+    pub input_tokens: i32,
+    /// Dimensions of the returned vectors.
+    pub dimensions: Option<u32>,
+    /// Number of inputs embedded in the request.
+    pub batch_size: u32,
+    pub latency_ms: i64,
+    pub status: CallStatus,
+    pub error_message: Option<String>,
+    pub project_id: Option<ProjectId>,
+    pub environment: Option<String>,
+    pub trace_id: Option<TraceId>,
+    pub metadata: Option<HashMap<String, serde_json::Value>>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl EmbeddingCall {
+    pub fn builder() -> EmbeddingCallBuilder {
+        EmbeddingCallBuilder::default()
+    }
+}
+
+impl From<EmbeddingCall> for LLMCall {
+    fn from(call: EmbeddingCall) -> Self {
+        let mut builder = LLMCall::builder()
+            .provider(call.provider)
+            .model(call.model)
+            .input_tokens(call.input_tokens)
+            .latency_ms(call.latency_ms)
+            .status(call.status);
+        if let Some(message) = call.error_message {
+            builder = builder.error_message(message);
+        }
+        if let Some(id) = call.project_id {
+            builder = builder.project_id(id);
+        }
+        if let Some(environment) = call.environment {
+            builder = builder.environment(environment);
+        }
+        if let Some(id) = call.trace_id {
+            builder = builder.trace_id(id);
+        }
+        if let Some(metadata) = call.metadata {
+            builder = builder.metadata(metadata);
+        }
+        LLMCall {
+            call_type: CallType::Embedding,
+            dimensions: call.dimensions,
+            batch_size: Some(call.batch_size),
+            timestamp: call.timestamp,
+            ..builder.build()
+        }
+    }
+}
+
+/// Builder for EmbeddingCall.
+#[derive(Default)]
+pub struct EmbeddingCallBuilder {
+    provider: Option<Provider>,
+    model: Option<String>,
+    input_tokens: i32,
+    dimensions: Option<u32>,
+    batch_size: Option<u32>,
+    latency_ms: i64,
+    status: CallStatus,
+    error_message: Option<String>,
+    project_id: Option<ProjectId>,
+    environment: Option<String>,
+    trace_id: Option<TraceId>,
+    metadata: Option<HashMap<String, serde_json::Value>>,
+}
+
+impl EmbeddingCallBuilder {
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn input_tokens(mut self, tokens: i32) -> Self {
+        self.input_tokens = tokens;
+        self
+    }
+
+    pub fn dimensions(mut self, dimensions: u32) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    pub fn batch_size(mut self, size: u32) -> Self {
+        self.batch_size = Some(size);
+        self
+    }
+
+    pub fn latency_ms(mut self, latency: i64) -> Self {
+        self.latency_ms = latency;
+        self
+    }
+
+    pub fn status(mut self, status: CallStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn error_message(mut self, message: impl Into<String>) -> Self {
+        self.error_message = Some(message.into());
+        self
+    }
+
+    pub fn project_id(mut self, id: ProjectId) -> Self {
+        self.project_id = Some(id);
+        self
+    }
+
+    pub fn environment(mut self, env: impl Into<String>) -> Self {
+        self.environment = Some(env.into());
+        self
+    }
+
+    pub fn trace_id(mut self, id: TraceId) -> Self {
+        self.trace_id = Some(id);
+        self
+    }
+
+    pub fn metadata(mut self, metadata: HashMap<String, serde_json::Value>) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn build(self) -> EmbeddingCall {
+        EmbeddingCall {
+            provider: self.provider.expect("provider is required"),
+            model: self.model.expect("model is required"),
+            input_tokens: self.input_tokens,
+            dimensions: self.dimensions,
+            batch_size: self.batch_size.unwrap_or(1),
+            latency_ms: self.latency_ms,
+            status: self.status,
+            error_message: self.error_message,
+            project_id: self.project_id,
+            environment: self.environment,
+            trace_id: self.trace_id,
+            metadata: self.metadata,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
 /// Options for tracking calls.
 #[derive(Debug, Clone, Default)]
 pub struct TrackOptions {
-    pub project_id: Option<String>,
+    pub project_id: Option<ProjectId>,
     pub environment: Option<String>,
     pub user_identifier: Option<String>,
-    pub trace_id: Option<String>,
+    pub trace_id: Option<TraceId>,
     pub span_id: Option<String>,
     pub metadata: Option<HashMap<String, serde_json::Value>>,
 }
@@ -309,8 +1598,8 @@ impl TrackOptions {
         Self::default()
     }
 
-    pub fn project_id(mut self, id: impl Into<String>) -> Self {
-        self.project_id = Some(id.into());
+    pub fn project_id(mut self, id: ProjectId) -> Self {
+        self.project_id = Some(id);
         self
     }
 
@@ -324,8 +1613,8 @@ impl TrackOptions {
         self
     }
 
-    pub fn trace_id(mut self, id: impl Into<String>) -> Self {
-        self.trace_id = Some(id.into());
+    pub fn trace_id(mut self, id: TraceId) -> Self {
+        self.trace_id = Some(id);
         self
     }
 
@@ -339,6 +1628,25 @@ impl TrackOptions {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_cost_sampling_keeps_expensive_and_failed_calls() {
+        let sampling = CostSampling::new(0.01).keep_above_tokens(10_000);
+        let call = |cost: f64, tokens: i32, status: CallStatus| {
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model("gpt-4")
+                .input_tokens(tokens)
+                .estimated_cost_usd(cost)
+                .status(status)
+                .build()
+        };
+
+        assert!(sampling.keeps(&call(0.05, 100, CallStatus::Success)));
+        assert!(sampling.keeps(&call(0.001, 20_000, CallStatus::Success)));
+        assert!(sampling.keeps(&call(0.0, 0, CallStatus::RateLimited)));
+        assert!(!sampling.keeps(&call(0.001, 100, CallStatus::Success)));
+    }
+
     #[test]
     fn test_provider_serialization() {
         let provider = Provider::OpenAI;
@@ -376,24 +1684,38 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_diagnyx_config_default_values() {
         let config = DiagnyxConfig::new("test-api-key");
         assert_eq!(config.api_key, "test-api-key");
         assert_eq!(config.base_url, "https://api.diagnyx.io");
         assert_eq!(config.batch_size, 100);
         assert_eq!(config.flush_interval_ms, 5000);
+        assert_eq!(config.max_buffer_size, 10000);
         assert_eq!(config.max_retries, 3);
         assert!(!config.debug);
         assert!(!config.capture_full_content);
         assert_eq!(config.content_max_length, 10000);
+        assert!(config.spend_cache_path.is_none());
+        assert!(config.spend_reconcile_interval_ms.is_none());
     }
 
     #[test]
+    fn test_test_mode_key_uses_sandbox() {
+        let config = DiagnyxConfig::new("dx_test_abc");
+        assert!(config.is_test_mode());
+        assert_eq!(config.base_url, "https://sandbox.api.diagnyx.io");
+        assert!(!DiagnyxConfig::new("dx_live_abc").is_test_mode());
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn test_diagnyx_config_builder_pattern() {
         let config = DiagnyxConfig::new("my-key")
             .base_url("https://custom.api.com")
             .batch_size(50)
             .flush_interval_ms(10000)
+            .max_buffer_size(500)
             .max_retries(5)
             .debug(true)
             .capture_full_content(true)
@@ -403,7 +1725,9 @@ mod tests {
         assert_eq!(config.base_url, "https://custom.api.com");
         assert_eq!(config.batch_size, 50);
         assert_eq!(config.flush_interval_ms, 10000);
+        assert_eq!(config.max_buffer_size, 500);
         assert_eq!(config.max_retries, 5);
+        assert_eq!(config.retry_policy.max_attempts, 5);
         assert!(config.debug);
         assert!(config.capture_full_content);
         assert_eq!(config.content_max_length, 5000);
@@ -428,6 +1752,21 @@ mod tests {
         assert_eq!(call.status, CallStatus::Success);
     }
 
+    #[test]
+    fn test_typed_call_builder() {
+        let call = LLMCall::typed_builder()
+            .input_tokens(100)
+            .model("claude-3-haiku")
+            .environment("staging")
+            .provider(Provider::Anthropic)
+            .build();
+
+        assert_eq!(call.provider, Provider::Anthropic);
+        assert_eq!(call.model, "claude-3-haiku");
+        assert_eq!(call.input_tokens, 100);
+        assert_eq!(call.environment.as_deref(), Some("staging"));
+    }
+
     #[test]
     fn test_llm_call_with_optional_fields() {
         let mut metadata = HashMap::new();
@@ -442,10 +1781,10 @@ mod tests {
             .latency_ms(750)
             .ttft_ms(50)
             .status(CallStatus::Success)
-            .project_id("proj-123")
+            .project_id(ProjectId::from_static("proj-123"))
             .environment("production")
             .user_identifier("user-456")
-            .trace_id("trace-789")
+            .trace_id(TraceId::from_static("trace-789"))
             .span_id("span-abc")
             .metadata(metadata)
             .full_prompt("Hello, Claude!")
@@ -454,9 +1793,9 @@ mod tests {
 
         assert_eq!(call.endpoint, Some("/v1/messages".to_string()));
         assert_eq!(call.ttft_ms, Some(50));
-        assert_eq!(call.project_id, Some("proj-123".to_string()));
+        assert_eq!(call.project_id, Some(ProjectId::from_static("proj-123")));
         assert_eq!(call.environment, Some("production".to_string()));
-        assert_eq!(call.trace_id, Some("trace-789".to_string()));
+        assert_eq!(call.trace_id, Some(TraceId::from_static("trace-789")));
         assert_eq!(call.full_prompt, Some("Hello, Claude!".to_string()));
     }
 
@@ -472,7 +1811,10 @@ mod tests {
 
         assert_eq!(call.status, CallStatus::Error);
         assert_eq!(call.error_code, Some("rate_limit_exceeded".to_string()));
-        assert_eq!(call.error_message, Some("You exceeded your quota".to_string()));
+        assert_eq!(
+            call.error_message,
+            Some("You exceeded your quota".to_string())
+        );
     }
 
     #[test]
@@ -494,6 +1836,33 @@ mod tests {
         assert!(json.contains("\"status\":\"success\""));
     }
 
+    #[test]
+    fn test_embedding_call_serialization() {
+        let call: LLMCall = EmbeddingCall::builder()
+            .provider(Provider::OpenAI)
+            .model("text-embedding-3-small")
+            .input_tokens(800)
+            .dimensions(1536)
+            .batch_size(16)
+            .build()
+            .into();
+
+        let json = serde_json::to_value(&call).unwrap();
+        assert_eq!(json["call_type"], "embedding");
+        assert_eq!(json["dimensions"], 1536);
+        assert_eq!(json["batch_size"], 16);
+        assert_eq!(json["output_tokens"], 0);
+
+        let completion = serde_json::to_value(
+            LLMCall::builder()
+                .provider(Provider::OpenAI)
+                .model("gpt-4")
+                .build(),
+        )
+        .unwrap();
+        assert!(completion.get("call_type").is_none());
+    }
+
     #[test]
     fn test_llm_call_omits_null_optional_fields() {
         let call = LLMCall::builder()
@@ -512,16 +1881,90 @@ mod tests {
     #[test]
     fn test_track_options_builder() {
         let opts = TrackOptions::new()
-            .project_id("proj-123")
+            .project_id(ProjectId::from_static("proj-123"))
             .environment("production")
             .user_identifier("user-456")
-            .trace_id("trace-789")
+            .trace_id(TraceId::from_static("trace-789"))
             .span_id("span-abc");
 
-        assert_eq!(opts.project_id, Some("proj-123".to_string()));
+        assert_eq!(opts.project_id, Some(ProjectId::from_static("proj-123")));
         assert_eq!(opts.environment, Some("production".to_string()));
         assert_eq!(opts.user_identifier, Some("user-456".to_string()));
-        assert_eq!(opts.trace_id, Some("trace-789".to_string()));
+        assert_eq!(opts.trace_id, Some(TraceId::from_static("trace-789")));
         assert_eq!(opts.span_id, Some("span-abc".to_string()));
     }
+
+    #[test]
+    fn test_config_deserializes_with_env_substitution() {
+        std::env::set_var("DIAGNYX_TEST_CONFIG_KEY", "dx_test_abc123");
+        std::env::set_var("DIAGNYX_TEST_CONFIG_SERVICE", "checkout");
+        let config: DiagnyxConfig = serde_json::from_value(serde_json::json!({
+            "api_key": "${DIAGNYX_TEST_CONFIG_KEY}",
+            "service_name": "${DIAGNYX_TEST_CONFIG_SERVICE}-api",
+            "max_retries": 5,
+            "max_requests_per_second": 20.0,
+            "cost_sampling": {"keep_above_usd": 0.5},
+            "volume_cap": {"calls_per_minute": 600},
+            "budgets": [{"scope": {"project": "proj-1"}, "limit_usd": 50.0, "window": "daily"}],
+        }))
+        .unwrap();
+
+        assert_eq!(config.api_key, "dx_test_abc123");
+        assert_eq!(config.base_url, "https://sandbox.api.diagnyx.io");
+        assert_eq!(config.service_name.as_deref(), Some("checkout-api"));
+        assert_eq!(config.retry_policy.max_attempts, 5);
+        assert_eq!(
+            config.rate_limiter.map(|l| l.requests_per_second()),
+            Some(20.0)
+        );
+        assert_eq!(config.batch_size, 100);
+        assert_eq!(config.sampling.cost, Some(CostSampling::new(0.5)));
+        assert_eq!(
+            config.sampling.volume_cap,
+            Some(VolumeCap::CallsPerMinute(600))
+        );
+        assert_eq!(
+            config.budgets,
+            vec![BudgetConfig::project(
+                ProjectId::from_static("proj-1"),
+                50.0,
+                crate::budgets::BudgetWindow::Daily
+            )]
+        );
+    }
+
+    #[test]
+    fn test_config_deserialize_errors() {
+        let error =
+            serde_json::from_str::<DiagnyxConfig>(r#"{"api_key": "${DIAGNYX_TEST_CONFIG_UNSET}"}"#)
+                .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("environment variable DIAGNYX_TEST_CONFIG_UNSET is not set"));
+
+        assert!(
+            serde_json::from_str::<DiagnyxConfig>(r#"{"api_key": "k", "batch_sise": 5}"#).is_err()
+        );
+
+        let error = serde_json::from_str::<DiagnyxConfig>(
+            r#"{"api_key": "k", "max_requests_per_second": 0}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("max_requests_per_second"));
+
+        let error =
+            serde_json::from_str::<DiagnyxConfig>(r#"{"api_key": "k", "sample_rate": 1.5}"#)
+                .unwrap_err();
+        assert!(error.to_string().contains("sample_rate must be between"));
+    }
+
+    #[test]
+    fn test_config_debug_masks_api_key() {
+        let debug = format!("{:?}", DiagnyxConfig::new("dx_live_abc123"));
+        assert!(debug.contains("api_key: \"dx_live_****\""));
+        assert!(!debug.contains("abc123"));
+
+        let debug = format!("{:?}", DiagnyxConfig::new("secret"));
+        assert!(debug.contains("api_key: \"****\""));
+    }
 }
@@ -0,0 +1,226 @@
+//! Background upload of large call sets.
+//!
+//! [`DiagnyxClient::upload`](crate::DiagnyxClient::upload) sends calls
+//! recorded elsewhere (an import file, another system's logs) straight to the
+//! API in batches, reporting [`UploadProgress`] after each one. The returned
+//! [`UploadHandle`] can cancel the upload between batches.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use diagnyx::{DiagnyxClient, LLMCall};
+//!
+//! # async fn run(calls: Vec<LLMCall>) -> Result<(), diagnyx::DiagnyxError> {
+//! let client = DiagnyxClient::new("dx_live_your_api_key");
+//! let upload = client.upload(calls, |progress| {
+//!     eprintln!(
+//!         "{}/{} batches, {} bytes, eta {:?}",
+//!         progress.batches_sent, progress.total_batches, progress.bytes_sent, progress.eta
+//!     );
+//! });
+//! let progress = upload.wait().await?;
+//! println!("uploaded {} calls", progress.calls_sent);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::DiagnyxError;
+use crate::types::LLMCall;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// How far an upload has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct UploadProgress {
+    /// Batches accepted so far.
+    pub batches_sent: usize,
+    /// Batches in the whole upload.
+    pub total_batches: usize,
+    /// Calls accepted so far.
+    pub calls_sent: usize,
+    /// Size of the accepted calls as uncompressed JSON.
+    pub bytes_sent: u64,
+    /// Estimated time to send the remaining batches, from the average so far.
+    pub eta: Option<Duration>,
+}
+
+impl UploadProgress {
+    /// Whether every batch has been sent.
+    pub fn is_complete(&self) -> bool {
+        self.batches_sent == self.total_batches
+    }
+}
+
+/// A running upload.
+///
+/// Dropping the handle does not stop the upload; call
+/// [`cancel`](Self::cancel) for that.
+#[derive(Debug)]
+pub struct UploadHandle {
+    cancelled: Arc<AtomicBool>,
+    task: JoinHandle<Result<UploadProgress, DiagnyxError>>,
+}
+
+impl UploadHandle {
+    /// Stop the upload before its next batch. A batch already in flight is
+    /// still sent.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the upload has stopped, by finishing, failing or cancellation.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Wait for the upload to stop.
+    ///
+    /// Returns the final progress, which is not
+    /// [complete](UploadProgress::is_complete) if the upload was cancelled,
+    /// or the error of the first batch that failed after retries.
+    pub async fn wait(self) -> Result<UploadProgress, DiagnyxError> {
+        match self.task.await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+/// Spawn a task sending `calls` in batches of `batch_size` with `send`.
+pub(crate) fn spawn<S, F, P>(
+    calls: Vec<LLMCall>,
+    batch_size: usize,
+    send: S,
+    on_progress: P,
+) -> UploadHandle
+where
+    S: Fn(Vec<LLMCall>) -> F + Send + 'static,
+    F: Future<Output = Result<(), DiagnyxError>> + Send,
+    P: Fn(&UploadProgress) + Send + 'static,
+{
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&cancelled);
+    let task = tokio::spawn(async move {
+        let batch_size = batch_size.max(1);
+        let mut progress = UploadProgress {
+            total_batches: calls.len().div_ceil(batch_size),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let mut calls = calls.into_iter().peekable();
+        while calls.peek().is_some() {
+            if flag.load(Ordering::Relaxed) {
+                break;
+            }
+            let batch: Vec<LLMCall> = calls.by_ref().take(batch_size).collect();
+            let bytes = serde_json::to_vec(&batch).map_or(0, |body| body.len() as u64);
+            let count = batch.len();
+            send(batch).await?;
+
+            progress.batches_sent += 1;
+            progress.calls_sent += count;
+            progress.bytes_sent += bytes;
+            let remaining = (progress.total_batches - progress.batches_sent) as u32;
+            progress.eta = Some(started.elapsed() / progress.batches_sent as u32 * remaining);
+            on_progress(&progress);
+        }
+        Ok(progress)
+    });
+    UploadHandle { cancelled, task }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DiagnyxClient, DiagnyxConfig, LLMCall, Provider};
+    use std::sync::{Arc, Mutex};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn calls(count: usize) -> Vec<LLMCall> {
+        (0..count)
+            .map(|_| {
+                LLMCall::builder()
+                    .provider(Provider::OpenAI)
+                    .model("gpt-4")
+                    .build()
+            })
+            .collect()
+    }
+
+    fn client(server: &MockServer) -> DiagnyxClient {
+        DiagnyxClient::with_config(
+            DiagnyxConfig::new("test-key")
+                .base_url(server.uri())
+                .flush_interval_ms(60000)
+                .batch_size(2)
+                .max_retries(1),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_upload_reports_progress_per_batch() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(3)
+            .mount(&server)
+            .await;
+        let client = client(&server);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&seen);
+        let upload = client.upload(calls(5), move |progress| {
+            recorded.lock().unwrap().push(*progress);
+        });
+        let progress = upload.wait().await.unwrap();
+
+        assert!(progress.is_complete());
+        assert_eq!(progress.total_batches, 3);
+        assert_eq!(progress.calls_sent, 5);
+        assert_eq!(progress.eta, Some(std::time::Duration::ZERO));
+        assert_eq!(client.buffer_size().await, 0);
+        let seen = seen.lock().unwrap();
+        let sent: Vec<usize> = seen.iter().map(|p| p.calls_sent).collect();
+        assert_eq!(sent, [2, 4, 5]);
+        assert!(seen[0].bytes_sent > 0 && seen[0].bytes_sent < seen[2].bytes_sent);
+        assert_eq!(seen[2].bytes_sent, progress.bytes_sent);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_upload_stops_before_next_batch() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        let client = client(&server);
+
+        let upload = client.upload(calls(4), |_| {});
+        upload.cancel();
+        let progress = upload.wait().await.unwrap();
+        assert!(!progress.is_complete());
+        assert_eq!(progress.batches_sent, 0);
+        assert_eq!(progress.total_batches, 2);
+    }
+
+    #[tokio::test]
+    async fn test_upload_stops_at_failed_batch() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/ingest/llm/batch"))
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let client = client(&server);
+
+        let upload = client.upload(calls(4), |_| {});
+        assert!(upload.wait().await.is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Buffer depth watermarks.
+//!
+//! A client reports once when its buffer fills past the high watermark and
+//! once more when it has drained below the low one, so services can shed load
+//! or alert before calls start being dropped. The gap between the two keeps a
+//! buffer hovering around one watermark from reporting on every call.
+
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::events::{EventBus, SdkEvent};
+use crate::types::DiagnyxConfig;
+
+/// Buffer fill levels, as fractions of `max_buffer_size`, at which
+/// [`SdkEvent::BufferHigh`] and [`SdkEvent::BufferDrained`] are emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BufferWatermarks {
+    /// Fill level that triggers [`SdkEvent::BufferHigh`]. Default: 0.8
+    pub high: f64,
+    /// Fill level at or below which a full buffer counts as drained.
+    /// Default: 0.5
+    pub low: f64,
+}
+
+impl Default for BufferWatermarks {
+    fn default() -> Self {
+        Self {
+            high: 0.8,
+            low: 0.5,
+        }
+    }
+}
+
+impl BufferWatermarks {
+    /// Watermarks at `high` and `low`, each clamped to `0.0..=1.0`, with
+    /// `low` kept at or below `high`.
+    pub fn new(high: f64, low: f64) -> Self {
+        let high = high.clamp(0.0, 1.0);
+        Self {
+            high,
+            low: low.clamp(0.0, high),
+        }
+    }
+}
+
+/// Whether a client's buffer is above its high watermark.
+#[derive(Debug, Default)]
+pub(crate) struct WatermarkState {
+    high: AtomicBool,
+}
+
+impl WatermarkState {
+    /// Report the buffer reaching `depth` if that crosses a watermark.
+    pub(crate) fn update(&self, depth: usize, config: &DiagnyxConfig, events: &EventBus) {
+        let Some(watermarks) = config.buffer_watermarks else {
+            return;
+        };
+        let capacity = config.max_buffer_size;
+        let fill = depth as f64 / capacity.max(1) as f64;
+        if fill >= watermarks.high {
+            if !self.high.swap(true, Ordering::Relaxed) {
+                config.logger().warn(&format!(
+                    "Buffer holds {} of at most {} calls",
+                    depth, capacity
+                ));
+                events.emit(SdkEvent::BufferHigh { depth, capacity });
+            }
+        } else if fill <= watermarks.low && self.high.swap(false, Ordering::Relaxed) {
+            config
+                .logger()
+                .log(&format!("Buffer drained to {} calls", depth));
+            events.emit(SdkEvent::BufferDrained { depth, capacity });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watermarks_report_once_per_crossing() {
+        let config = DiagnyxConfig::new("test-key")
+            .max_buffer_size(10)
+            .buffer_watermarks(BufferWatermarks::new(0.8, 0.2));
+        let events = EventBus::default();
+        let mut received = events.subscribe();
+        let state = WatermarkState::default();
+
+        for depth in [5, 8, 9, 7, 8, 3, 2, 1] {
+            state.update(depth, &config, &events);
+        }
+        assert_eq!(
+            received.try_recv().unwrap(),
+            SdkEvent::BufferHigh {
+                depth: 8,
+                capacity: 10
+            }
+        );
+        assert_eq!(
+            received.try_recv().unwrap(),
+            SdkEvent::BufferDrained {
+                depth: 2,
+                capacity: 10
+            }
+        );
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_new_orders_watermarks() {
+        assert_eq!(
+            BufferWatermarks::new(1.5, 0.9),
+            BufferWatermarks {
+                high: 1.0,
+                low: 0.9
+            }
+        );
+        assert_eq!(BufferWatermarks::new(0.4, 0.6).low, 0.4);
+    }
+}